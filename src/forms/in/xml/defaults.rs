@@ -1,7 +1,9 @@
 /// Default struct(s) for XML(s) got from the Octopus call
+use std::fmt;
+
 use chrono::{DateTime, Utc};
 
-use crate::macros::r#in::O8ModelLowercase;
+use crate::{macros::r#in::O8ModelLowercase, service::mcp::mask_authcode};
 use macro_rules_attribute::apply;
 
 #[apply(O8ModelLowercase)]
@@ -12,7 +14,7 @@ pub struct Hiba {
 }
 
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct CallData {
     pub authcode: String,
     pub url: String,
@@ -23,7 +25,30 @@ pub struct CallData {
     pub to_date: Option<DateTime<Utc>>,
     pub unpaid: Option<i64>,
     pub language: Option<String>,
-    pub data_type: Option<String>
+    pub data_type: Option<String>,
+    /// Whether the caller asked for a page, which routes the upstream call
+    /// through the page cache (see `soap::get_response_paged`)
+    pub paged: bool
+}
+
+/// Written by hand so the authcode is masked: the fetchers log `{:?}` of every
+/// call, and a derived impl would put the credential in each of those lines.
+impl fmt::Debug for CallData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CallData")
+            .field("authcode", &mask_authcode(&self.authcode))
+            .field("url", &self.url)
+            .field("xmlns", &self.xmlns)
+            .field("pid", &self.pid)
+            .field("type_mod", &self.type_mod)
+            .field("from_date", &self.from_date)
+            .field("to_date", &self.to_date)
+            .field("unpaid", &self.unpaid)
+            .field("language", &self.language)
+            .field("data_type", &self.data_type)
+            .field("paged", &self.paged)
+            .finish()
+    }
 }
 
 impl Default for CallData {
@@ -38,7 +63,8 @@ impl Default for CallData {
             to_date: None,
             unpaid: None,
             language: None,
-            data_type: None
+            data_type: None,
+            paged: false
         }
     }
 }
@@ -69,3 +95,17 @@ impl CallData {
         false
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_debug_output_carries_the_mask_not_the_code() {
+        let call_data = CallData { authcode: "FFD3ABCDEF120E37".into(), ..Default::default() };
+        let logged = format!("{:?}", call_data);
+        assert!(!logged.contains("FFD3ABCDEF120E37"));
+        assert!(logged.contains("FFD3…0E37"));
+    }
+}
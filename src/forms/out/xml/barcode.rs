@@ -30,6 +30,9 @@ OutModelDeriveOnly! {
     pub struct Answer {
         pub version: String,
         pub barcodes: Barcodes,
+        /// Present only on a paged response
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub page: Option<p_defaults::Page>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub error: Option<p_defaults::Error>
     }
@@ -89,6 +92,7 @@ impl From<o8_barcode::Valasz> for Answer {
         Self {
             version: v.verzio,
             barcodes: v.vonalkodok.into(),
+            page: None,
             error: v.hiba.map(|x| x.into())
         }
     }
@@ -130,6 +134,7 @@ pub fn error_struct(code: u64, description: &str) -> Envelope {
                         barcodes: Barcodes {
                             barcode: vec![]
                         },
+                        page: None,
                         error: Some(p_defaults::Error::load(code, description))
                     }
                 }
@@ -54,3 +54,19 @@ impl From<&o8_error> for Error {
         }
     }
 }
+
+
+/// Paging metadata of a paged response, mirrored in the `X-Total-Count` and
+/// `X-Next-Cursor` headers. See `service/page`.
+#[apply(OutModelDeriveOnly)]
+#[derive(Clone)]
+pub struct Page {
+    /// Records matching the request, before paging
+    pub total: usize,
+    pub offset: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+    /// Absent on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>
+}
@@ -29,6 +29,9 @@ OutModelDeriveOnly! {
     pub struct Answer {
         pub version: String,
         pub products: Products,
+        /// Present only on a paged response
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub page: Option<p_defaults::Page>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub error: Option<p_defaults::Error>
     }
@@ -96,6 +99,7 @@ impl From<o8_images::Valasz> for Answer {
             products: v.cikk
                         .into_iter()
                         .collect::<Products>(),
+            page: None,
             error: v.hiba.map(|x| x.into())
         }
     }
@@ -157,6 +161,7 @@ pub fn error_struct(code: u64, description: &str) -> Envelope {
                         products: Products {
                             product: vec![]
                         },
+                        page: None,
                         error: Some(p_defaults::Error::load(code, description))
                     }
                 }
@@ -30,6 +30,9 @@ OutModelDeriveOnly! {
     pub struct Answer {
         pub version: String,
         pub attributes: Attributes,
+        /// Present only on a paged response
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub page: Option<p_defaults::Page>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub error: Option<p_defaults::Error>
     }
@@ -97,6 +100,7 @@ impl From<o8_mat::Valasz> for Answer {
         Self {
             version: v.verzio,
             attributes: v.tulajdonsagok.tulajdonsag.into_iter().collect::<Attributes>(),
+            page: None,
             error: v.hiba.map(|e| e.into())
         }
     }
@@ -142,6 +146,7 @@ pub fn error_sturuct(code: u64, description: &str) -> Envelope {
                         attributes: Attributes{
                             attribute: vec![]
                         },
+                        page: None,
                         error: Some(p_defaults::Error::load(code, description))
                     }
                 }
@@ -26,6 +26,9 @@ OutModelDeriveSerializeOnly! {
     pub struct Answer {
         pub version: String,
         pub prices: Prices,
+        /// Present only on a paged response
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub page: Option<p_defaults::Page>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub error: Option<p_defaults::Error>
     }
@@ -92,6 +95,7 @@ impl From<o8_prices::Valasz> for Answer {
         Self {
             version: valasz.verzio,
             prices: valasz.arak.into(),
+            page: None,
             error: valasz.hiba.map(|e| e.into())
         }
     }
@@ -134,6 +138,7 @@ pub fn error_struct(code: u64, description: &str) -> Envelope {
                         prices: Prices {
                             price: vec![]
                         },
+                        page: None,
                         error: Some(p_defaults::Error::load(code, description))
                     }
                 }
@@ -32,6 +32,9 @@ OutModelDeriveSerializeOnly! {
     pub struct Answer {
         pub version: String,
         pub products: Products,
+        /// Present only on a paged response
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub page: Option<p_defaults::Page>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub error: Option<p_defaults::Error>
     }
@@ -108,6 +111,7 @@ impl From<o8_products::Valasz> for Answer {
         Self {
             version: v.verzio,
             products: v.cikk.into_iter().collect::<Products>(),
+            page: None,
             error: v.hiba.map(|e| e.into())
         }
     }
@@ -182,6 +186,7 @@ pub fn error_struct(code: u64, description: &str) -> Envelope {
                         products: Products {
                             product: vec![]
                         },
+                        page: None,
                         error: Some(p_defaults::Error::load(code, description))
                     }
                 }
@@ -29,6 +29,9 @@ OutModelDeriveSerializeOnly! {
     pub struct Answer {
         pub version: String,
         pub products: Products,
+        /// Present only on a paged response
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub page: Option<p_defaults::Page>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub error: Option<p_defaults::Error>
     }
@@ -86,6 +89,7 @@ impl From<o8_stocks::Valasz> for Answer {
         Self {
             version: v.verzio,
             products: v.cikkek.into(),
+            page: None,
             error: v.hiba.map(|x| x.into())
         }
     }
@@ -125,6 +129,7 @@ pub fn error_struct(code: u64, description: &str) -> Envelope {
                         products: Products {
                             product: Vec::new()
                         },
+                        page: None,
                         error: Some(p_defaults::Error::load(code, description))
                     }
                 }
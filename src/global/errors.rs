@@ -57,6 +57,71 @@ pub const GLOBAL_URL_NOT_ALLOWED_ERROR: RustopusError = RustopusError {
     description: "Url not allowed"
 };
 
+/// Returned when `limit`, `offset` or `cursor` cannot describe a page: a zero
+/// limit, an offset next to a cursor, or a cursor this server did not issue.
+pub const GLOBAL_PAGING_ERROR: RustopusError = RustopusError {
+    code: 207,
+    description: "Invalid paging parameters"
+};
+
+/// Returned with `429` when a caller spends its token bucket faster than it
+/// refills. `Retry-After` says when the next call will be admitted.
+pub const GLOBAL_RATE_LIMIT_ERROR: RustopusError = RustopusError {
+    code: 208,
+    description: "Rate limit exceeded"
+};
+
+/// Returned with `429` when a caller has used its daily quota. Separate from
+/// [`GLOBAL_RATE_LIMIT_ERROR`]: slowing down does not help, waiting for UTC
+/// midnight does.
+pub const GLOBAL_QUOTA_ERROR: RustopusError = RustopusError {
+    code: 209,
+    description: "Daily quota exceeded"
+};
+
+/// Returned when an authcode arrives in the query string on an instance that
+/// takes it only from a header (`[server] query_credentials = false`). Separate
+/// from [`GLOBAL_AUTH_ERROR`]: the code is there, it is just in the wrong place.
+pub const GLOBAL_QUERY_AUTH_ERROR: RustopusError = RustopusError {
+    code: 210,
+    description: "Authcode not accepted in the query string, send it in the X-Authcode or Authorization: Bearer header"
+};
+
+/// A presented `rk-` API key is unknown, disabled or past its expiry. Sent as
+/// `401`; the partner needs a new key from whoever issued it.
+pub const GLOBAL_API_KEY_ERROR: RustopusError = RustopusError {
+    code: 211,
+    description: "Invalid or expired API key"
+};
+
+/// The API key is valid but was not issued for this endpoint. Sent as `403`.
+pub const GLOBAL_API_KEY_SCOPE_ERROR: RustopusError = RustopusError {
+    code: 212,
+    description: "API key not valid for this endpoint"
+};
+
+/// Returned when `tenant` (or a `/t/{tenant}/` path prefix) names no profile in
+/// `soap.json`. Like [`GLOBAL_URL_NOT_ALLOWED_ERROR`], it does not list the
+/// profiles that do exist.
+pub const GLOBAL_TENANT_ERROR: RustopusError = RustopusError {
+    code: 213,
+    description: "Unknown tenant"
+};
+
+/// Returned when an attribute filter on the search endpoint cannot be read:
+/// no `code:value` pair, or a comparison on something that is not a number.
+pub const GLOBAL_FILTER_ERROR: RustopusError = RustopusError {
+    code: 214,
+    description: "Invalid attribute filter"
+};
+
+/// A request body that is not the JSON the endpoint takes, or that it refuses:
+/// an empty list, too many lines, a line without an id.
+pub const GLOBAL_BODY_ERROR: RustopusError = RustopusError {
+    code: 215,
+    description: "Invalid request body"
+};
+
 pub const GLOBAL_MISSING_ERROR: RustopusError = RustopusError {
     code: 299,
     description: "Missing value"
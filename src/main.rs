@@ -11,8 +11,8 @@ mod tools;
 mod language;
 
 use crate::{
-    routes::{barcode, bulk, image, index, invoice, mat, order, price, product, stock, test}, service::{
-        blocklist, log::{elogger, logger}, mcp, soap_config::{
+    routes::{availability, barcode, bulk, image, index, invoice, mat, order, price, product, search, stock, test}, service::{
+        apikey, blocklist, ipv4, log::{elogger, logger}, abuse, mcp, ratelimit, sealed, state, tenant, soap_config::{
             SOAP_URL, SoapConfig, check_soap_config, get_soap_path, init_allowlist
         }
     }
@@ -59,18 +59,47 @@ async fn main() -> std::io::Result<()> {
         elogger(format!("Panic: {:?}", info));
     }));
 
+    // One-shot maintenance commands, run instead of the server: `reseal`
+    // migrates the secret-grade files to the current storage key (see
+    // `service/sealed`), `new-storage-key` prints a key to configure,
+    // `migrate-state sqlite|toml` copies the server state between backends
+    // (see `service/state`).
+    match env::args().nth(1).as_deref() {
+        Some("reseal") => return sealed::reseal_command(),
+        Some("migrate-state") => return state::migrate_command(env::args().nth(2).as_deref()),
+        Some("new-storage-key") => {
+            println!("{}", sealed::new_key());
+            return Ok(())
+        }
+        Some(other) => {
+            elogger(format!("Unknown command '{}' — expected `reseal`, `new-storage-key` or `migrate-state`, or none to serve", other));
+            return Err(std::io::Error::other("unknown command"))
+        }
+        None => {}
+    }
+
     let config = service::config::get_settings();
 
-    let soap_url: Option<String> = if check_soap_config() {
-        Some(SoapConfig::load().url
-            .unwrap_or_default())
-            .filter(|s| !s.is_empty())
+    let soap_config = if check_soap_config() {
+        SoapConfig::load()
     } else {
         elogger(format!("'{:#?}' not found. (Do not bother this message, if you are not willing to work with static 'url'.)", get_soap_path()));
-        None
+        SoapConfig::default()
     };
 
-    let _ = SOAP_URL.set(soap_url);
+    // The named Octopus installations, before the default url (which may name
+    // one) and the allowlist (which includes them all) are resolved.
+    tenant::init(soap_config.tenants.clone());
+
+    let _ = SOAP_URL.set(soap_config.default_url());
+
+    // Before anything reads a secret-grade file, so the log says whether they
+    // are sealed and which are still in plain text.
+    sealed::init();
+
+    // Before anything loads state. A backend that cannot be opened stops the
+    // server rather than let it serve on an empty blocklist.
+    state::init()?;
 
     // Which hosts a request's `url` parameter may point at. Resolved here rather
     // than on the first request so the log says what is allowed at startup, and
@@ -130,17 +159,37 @@ async fn main() -> std::io::Result<()> {
         // Download tokens live in memory, so any export file left by a previous
         // run is unreachable — and holds partner prices. Clear them at startup.
         mcp::export::purge_orphans();
+        // Before the first search, so a broken synonym file is reported at
+        // startup rather than on someone's query.
+        mcp::synonyms::init();
         mcp::precache::spawn(mcp_config.precache_interval_secs());
         Some(mcp::tools::build_service())
     } else {
         None
     };
 
+    // Before anything that keys on a caller's address, so the log shows whose
+    // forwarding headers are believed.
+    ipv4::init();
+
     // Access blocklist. Loaded on every instance, MCP or not: an abusive client
     // hits `/get-bulk` and `/mcp` with equal enthusiasm. With no rules on file
     // the middleware below costs one atomic load per request.
     blocklist::init();
 
+    // Partner API keys for the REST endpoints. Loaded on every instance; with
+    // none issued the middleware below only looks for the `rk-` prefix.
+    apikey::init();
+
+    // Rate limits and daily quotas on the REST endpoints. Off unless
+    // `[rate_limit] enabled = true`, in which case the middleware returns on
+    // its first line.
+    ratelimit::init();
+
+    // Automatic, time-limited blocks for repeated 205s, 206s, rejected
+    // authcodes and rate-limit refusals. Off unless `[abuse] enabled = true`.
+    abuse::init();
+
     // Admin dashboard. Registered whenever a token is set — *not* only when MCP
     // is on, because it now also manages the blocklist, which the REST-only
     // instance needs as much as the MCP one. Without a token it is not
@@ -192,11 +241,26 @@ async fn main() -> std::io::Result<()> {
         let well_known_scope = oauth_enabled.then(mcp::oauth::endpoints::well_known_scope);
 
         let app = App::new()
-            // Innermost of the three, so a blocked caller's 403 still carries the
-            // security headers and is still compressed like any other response.
-            // Wrapping the whole app rather than each route is the point: one
-            // rule set covers the REST fetchers, /mcp and /export alike.
+            // Innermost: holds the caller's address for the abuse counters
+            // while the handler runs (see `service/abuse`).
+            .wrap(from_fn(abuse::guard))
+            // Inside the blocklist, so a blocked caller is refused before it
+            // spends a token and its flood does not crowd the usage table.
+            .wrap(from_fn(ratelimit::guard))
+            // Between the two: a blocked address never gets a key lookup, and
+            // the rate limiter sees the authcode a key stands in for and the
+            // key's own limit.
+            .wrap(from_fn(apikey::guard))
+            // Inside Compress and the headers, so a blocked caller's 403 still
+            // carries the security headers and is still compressed like any
+            // other response. Wrapping the whole app rather than each route is
+            // the point: one rule set covers the REST fetchers, /mcp and
+            // /export alike.
             .wrap(from_fn(blocklist::guard))
+            // Outside every guard: `/t/{tenant}/get-product` is rewritten to
+            // `/get-product?tenant=…` before any of them reads the path, so a
+            // prefix never dodges a rule, a key scope or a rate limit.
+            .wrap(from_fn(tenant::route))
             .wrap(Compress::default())
             .wrap(security_headers())
             .default_service(web::to(not_found))
@@ -218,6 +282,15 @@ async fn main() -> std::io::Result<()> {
             .service(order::post).service(order::post_alias)
             .service(test::get_handler);
 
+        // Search and the availability check answer from the MCP snapshot cache,
+        // so they exist only where that cache does.
+        let app = if mcp_enabled {
+            app.service(search::get).service(search::get_alias)
+                .service(availability::post)
+        } else {
+            app
+        };
+
         let app = match admin_scope {
             Some(scope) => app.service(scope),
             None => app
@@ -0,0 +1,163 @@
+use actix_web::{
+    post, HttpRequest, HttpResponse, Responder,
+    web::{self, Query, Bytes}
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    routes::default::{
+        RequestParameters, GetStringResponse, GetI64Response, JsonError, SendError,
+        get_auth, get_url, get_pid
+    },
+    global::errors::{GLOBAL_BODY_ERROR, GLOBAL_GET_DATA_ERROR, UNDEFINED_ERROR},
+    service::{
+        slave::get_uuid,
+        log::{log_with_ip_uuid, elog_with_ip_uuid},
+        ipv4::log_ip,
+        mcp::{
+            availability::{self, CheckItem},
+            cache::cache,
+            export,
+            mask_authcode
+        },
+        soap_gate::{self, Lane}
+    }
+};
+
+/// Name of the current request
+const REQUEST_NAME: &str = "CHECK AVAILABILITY REQUEST";
+
+/// The JSON body: the lines to check and, optionally, a spreadsheet format
+#[derive(Deserialize)]
+struct CheckRequest {
+    items: Vec<CheckItem>,
+    format: Option<String>
+}
+
+/// Handler
+///
+/// Checks a whole list of article numbers, barcodes or manufacturer part
+/// numbers against the partner's catalog snapshot in one call, exactly as
+/// `check_availability` does over MCP. Served only with `[mcp] enabled = true`,
+/// since the snapshot lives there. With a `format`, the same result is also
+/// written to a spreadsheet behind an `/export/{token}` link. Errors are JSON
+/// as well ([`JsonError`]), with the codes the XML routes use.
+async fn handler(req: HttpRequest, params: RequestParameters, body: Bytes) -> impl Responder {
+    // ID with UUID
+    let uuid = get_uuid();
+
+    // IP address of the request
+    let ip_address = log_ip(req.clone()).await.to_string();
+
+    // Trying to get url from parameters
+    let url = match get_url(REQUEST_NAME, &ip_address, &uuid, &params, JsonError) {
+        GetStringResponse::Text(url) => url,
+        GetStringResponse::Response(response) => return response
+    };
+
+    // Getting authentication code from parameters
+    let authcode = match get_auth(REQUEST_NAME, &ip_address, &uuid, req.headers(), &params, JsonError) {
+        GetStringResponse::Text(auth) => auth,
+        GetStringResponse::Response(response) => return response
+    };
+
+    // Prices and stock in a snapshot are the partner's own
+    let pid = match get_pid(REQUEST_NAME, &ip_address, &uuid, &params, JsonError) {
+        GetI64Response::Number(pid) => pid,
+        GetI64Response::Response(response) => return response
+    };
+
+    // The list itself, and the spreadsheet format if one is wanted
+    let request: CheckRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(reason) => {
+            let error = GLOBAL_BODY_ERROR;
+            elog_with_ip_uuid(&ip_address, &uuid, format!("{}: {} -> {} ({})", error.code, error.description, reason, REQUEST_NAME));
+            return JsonError.send(error.code, error.description)
+        }
+    };
+    let format = match request.format.as_deref().map(|format| export::Format::parse(Some(format))) {
+        None => None,
+        Some(Some(format)) => Some(format),
+        Some(None) => {
+            let error = GLOBAL_BODY_ERROR;
+            elog_with_ip_uuid(&ip_address, &uuid, format!("{}: {} -> format ({})", error.code, error.description, REQUEST_NAME));
+            return JsonError.send(error.code, error.description)
+        }
+    };
+
+    // Before log
+    log_with_ip_uuid(&ip_address, &uuid, format!(
+        "Before getting {}, {} pid={} items={}",
+        REQUEST_NAME, mask_authcode(&authcode), pid, request.items.len()
+    ));
+
+    // A caller is waiting, so a cold build takes the interactive lane
+    let snapshot = match soap_gate::in_lane(Lane::Interactive, cache().get_or_build(&authcode, pid, &url)).await {
+        Ok(snapshot) => snapshot,
+        Err(reason) => {
+            let error = GLOBAL_GET_DATA_ERROR;
+            elog_with_ip_uuid(&ip_address, &uuid, format!("{}: {} -> {} ({})", error.code, error.description, reason, REQUEST_NAME));
+            return JsonError.send(error.code, error.description)
+        }
+    };
+
+    let checked = match availability::check(&snapshot, &request.items) {
+        Ok(checked) => checked,
+        Err(reason) => {
+            let error = GLOBAL_BODY_ERROR;
+            elog_with_ip_uuid(&ip_address, &uuid, format!("{}: {} -> {} ({})", error.code, error.description, reason, REQUEST_NAME));
+            return JsonError.send(error.code, error.description)
+        }
+    };
+
+    // After log
+    log_with_ip_uuid(&ip_address, &uuid, format!(
+        "After {} got: lines={} in_stock={} not_found={}",
+        REQUEST_NAME, checked.summary.lines, checked.summary.in_stock, checked.summary.not_found
+    ));
+
+    let mut payload = json!({
+        "catalog_age_seconds": snapshot.age_secs(),
+        "summary": checked.summary,
+        "lines": checked.lines
+    });
+
+    if let Some(format) = format {
+        // Writing a workbook is CPU-bound; keep it off the async workers
+        let rows = availability::rows(&checked);
+        let built = web::block(move || {
+            export::write_table("orink-availability", &availability::COLUMNS, &rows, format)
+        }).await;
+
+        let prepared = match built {
+            Ok(Ok(prepared)) => prepared,
+            Ok(Err(reason)) => {
+                let error = UNDEFINED_ERROR;
+                elog_with_ip_uuid(&ip_address, &uuid, format!("{}: {} -> {} ({})", error.code, error.description, reason, REQUEST_NAME));
+                return JsonError.send(error.code, error.description)
+            }
+            Err(reason) => {
+                let error = UNDEFINED_ERROR;
+                elog_with_ip_uuid(&ip_address, &uuid, format!("{}: {} -> {} ({})", error.code, error.description, reason, REQUEST_NAME));
+                return JsonError.send(error.code, error.description)
+            }
+        };
+
+        if let Some(object) = payload.as_object_mut() {
+            object.insert("file_name".into(), json!(prepared.file_name));
+            object.insert("download_url".into(), json!(prepared.url));
+            object.insert("expires_in_seconds".into(), json!(export::ttl_secs()));
+        }
+    }
+
+    HttpResponse::Ok().json(payload)
+}
+
+
+/// POST handler
+#[post("/check-availability")]
+pub async fn post(req: HttpRequest, query: Query<RequestParameters>, body: Bytes) -> impl Responder {
+    handler(req, query.into_inner(), body).await
+}
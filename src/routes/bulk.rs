@@ -6,8 +6,8 @@ use actix_web::{
 use crate::{
     routes::default::{
         RequestParameters, GetStringResponse, GetI64Response, GetDateResponse,
-        send_xml, send_csv, send_xlsx, return_internal_server_error,
-        get_auth, get_url, get_xmlns, get_pid, get_date
+        send_xml, return_internal_server_error,
+        get_auth, get_url, get_xmlns, get_language, get_pid, get_date
     },
     forms::{
         r#in::xml::defaults::CallData,
@@ -21,6 +21,7 @@ use crate::{
         log::log_with_ip_uuid,
         ipv4::log_ip,
         get_data::{RequestGet, ResponseGet},
+        select::Selection,
         get::bulk::{BulkData, BulkCSV}
     }
 };
@@ -34,7 +35,7 @@ async fn handler(req: HttpRequest, params: RequestParameters) -> impl Responder
     let uuid = get_uuid();
 
     // IP address of the request
-    let ip_address = log_ip(req).await.to_string();
+    let ip_address = log_ip(req.clone()).await.to_string();
 
     // Trying to get url from parameters
     let url = match get_url(REQUEST_NAME, &ip_address, &uuid, &params, error_struct_xml) {
@@ -45,10 +46,16 @@ async fn handler(req: HttpRequest, params: RequestParameters) -> impl Responder
     // Deriving XMLNS from the url; the parameter is only a fallback
     let xmlns = get_xmlns(REQUEST_NAME, &ip_address, &uuid, &params, &url);
 
+    // The caller's language, or the tenant's default
+    let language = get_language(&params, &url);
+
+    // Column projection and filters, applied to the translated records
+    let selection = Selection::from_params(&params);
+
     // Creating call data from parameters
     let call_data = CallData {
         // Getting authentication code from parameters
-        authcode: match get_auth(REQUEST_NAME, &ip_address, &uuid, &params, error_struct_xml) {
+        authcode: match get_auth(REQUEST_NAME, &ip_address, &uuid, req.headers(), &params, error_struct_xml) {
             GetStringResponse::Text(auth) => auth,
             GetStringResponse::Response(response) => return response
         },
@@ -64,7 +71,7 @@ async fn handler(req: HttpRequest, params: RequestParameters) -> impl Responder
         } else {
             None
         },
-        language: params.language,
+        language,
         data_type: params.data_type,
         ..Default::default()
     };
@@ -83,9 +90,15 @@ async fn handler(req: HttpRequest, params: RequestParameters) -> impl Responder
 
     // Handling got data
     match data {
-        ResponseGet::Bulk(BulkData::Xlsx(BulkCSV::En(d))) => send_xlsx(&d.products, "bulk.xlsx", if is_hu { Some(HU_HEADERS) } else { None }),
-        ResponseGet::Bulk(BulkData::Csv(BulkCSV::En(d))) => send_csv(&d.products, "bulk.csv", if is_hu { Some(HU_HEADERS) } else { None }),
-        ResponseGet::Bulk(BulkData::Xml(d)) => send_xml(d.to_xml()),
+        ResponseGet::Bulk(BulkData::Xlsx(BulkCSV::En(mut d))) => {
+            selection.retain(&mut d.products, &ip_address, &uuid);
+            selection.send_xlsx(&d.products, "bulk.xlsx", if is_hu { Some(HU_HEADERS) } else { None })
+        }
+        ResponseGet::Bulk(BulkData::Csv(BulkCSV::En(mut d))) => {
+            selection.retain(&mut d.products, &ip_address, &uuid);
+            selection.send_csv(&d.products, "bulk.csv", if is_hu { Some(HU_HEADERS) } else { None })
+        }
+        ResponseGet::Bulk(BulkData::Xml(d)) => send_xml(d.into_selected_xml(&selection, &ip_address, &uuid)),
         _ => return_internal_server_error()
     }
 }
@@ -1,16 +1,21 @@
 use chrono::{DateTime, Utc};
-use actix_web::HttpResponse;
+use actix_web::{HttpResponse, http::header::HeaderMap};
 use serde::Deserialize;
+use serde_json::json;
 
 use crate::{
     global::errors::{
         GLOBAL_AUTH_ERROR, GLOBAL_AUTH_FORMAT_ERROR, GLOBAL_URL_ERROR, GLOBAL_URL_NOT_ALLOWED_ERROR,
-        GLOBAL_PID_ERROR, GLOBAL_MISSING_ERROR
+        GLOBAL_PID_ERROR, GLOBAL_MISSING_ERROR, GLOBAL_PAGING_ERROR, GLOBAL_QUERY_AUTH_ERROR, GLOBAL_TENANT_ERROR
     },
     service::{
+        abuse::{self, Trigger},
         authcode,
+        config::get_settings,
+        page::Paging,
         log::{log_with_ip_uuid, elog_with_ip_uuid},
-        soap_config::{get_default_url, is_allowed_soap_url}
+        soap_config::{get_default_url, is_allowed_soap_url},
+        tenant
     }
 };
 
@@ -19,6 +24,8 @@ pub struct RequestParameters {
     pub authcode: Option<String>,
     pub auth: Option<String>,
     pub url: Option<String>,
+    /// A named Octopus installation from `soap.json`, in place of `url`
+    pub tenant: Option<String>,
     pub xmlns: Option<String>,
     pub pid: Option<i64>,
     pub type_mod: Option<i64>,
@@ -26,7 +33,57 @@ pub struct RequestParameters {
     pub to_date: Option<DateTime<Utc>>,
     pub unpaid: Option<i64>,
     pub language: Option<String>,
-    pub data_type: Option<String>
+    pub data_type: Option<String>,
+    /// Comma-separated columns to keep (product, price, stock and bulk endpoints)
+    pub fields: Option<String>,
+    /// Comma-separated brands to keep
+    pub brand: Option<String>,
+    /// Comma-separated category or main category codes or names to keep
+    pub category: Option<String>,
+    /// `1` keeps records with stock above zero, `0` the rest
+    pub in_stock: Option<i64>,
+    /// Comma-separated article numbers to keep
+    pub no: Option<String>,
+    /// Keeps records published to the web on or after this day; `from_date` is
+    /// what asks for records changed since
+    pub published_since: Option<DateTime<Utc>>,
+    /// Page size (product, price, stock, image, barcode and mat endpoints)
+    pub limit: Option<usize>,
+    /// Records to skip before the page starts
+    pub offset: Option<usize>,
+    /// `X-Next-Cursor` of the previous page, in place of `offset`
+    pub cursor: Option<String>,
+    /// Words to search for (search endpoint)
+    pub query: Option<String>,
+    /// Attribute filters, `code:value` pairs separated by `;` (search endpoint)
+    pub attributes: Option<String>
+}
+
+
+/// How a route sends an error found while reading its parameters.
+///
+/// Every XML route passes its `error_struct_xml`, whose envelope goes out
+/// through [`send_xml`]; a JSON route passes [`JsonError`].
+pub trait SendError {
+    fn send(&self, code: u64, description: &str) -> HttpResponse;
+}
+
+impl<F: Fn(u64, &str) -> String> SendError for F {
+    fn send(&self, code: u64, description: &str) -> HttpResponse {
+        send_xml(self(code, description))
+    }
+}
+
+/// Errors as `{"error": {"code", "description"}}`, for the routes that answer
+/// in JSON. Sent with `200` like the XML envelope, so a client reads the code
+/// the same way whichever format it asked for.
+#[derive(Clone, Copy)]
+pub struct JsonError;
+
+impl SendError for JsonError {
+    fn send(&self, code: u64, description: &str) -> HttpResponse {
+        HttpResponse::Ok().json(json!({ "error": { "code": code, "description": description } }))
+    }
 }
 
 
@@ -48,6 +105,12 @@ pub enum GetDateResponse {
 }
 
 
+pub enum GetPagingResponse {
+    Paging(Option<Paging>),
+    Response(actix_web::HttpResponse)
+}
+
+
 pub fn send_xml(xml: String) -> HttpResponse {
     HttpResponse::Ok()
         .content_type("application/xml")
@@ -129,7 +192,12 @@ pub fn send_xlsx<T: serde::Serialize>(records: &[T], filename: &str, hu_headers:
 }
 
 
-/// Tries to get authentication from the parameter, sends back error xml on fail
+/// Tries to get authentication from the headers or the parameter, sends back error xml on fail
+///
+/// `X-Authcode` or `Authorization: Bearer` win over the `authcode`/`auth`
+/// parameter: a header stays out of proxy access logs and browser history,
+/// which is why `[server] query_credentials = false` can switch the parameter
+/// off altogether (error `210`).
 ///
 /// The code is checked against [`authcode::is_well_formed`] before it is
 /// accepted. That is the second layer under the escaping in the SOAP builders,
@@ -137,10 +205,20 @@ pub fn send_xlsx<T: serde::Serialize>(records: &[T], filename: &str, hu_headers:
 /// this is what makes it visible in the log instead of being forwarded to
 /// Octopus as an ordinary-looking authentication failure. The code is never
 /// altered here — see the note in `service/authcode`.
-pub fn get_auth(request_name: &str, ip_address: &str, uuid: &str, params: &RequestParameters, send_error_xml_fn: fn(u64, &str) -> String) -> GetStringResponse {
-    let presented = params.authcode.as_ref()
+pub fn get_auth(request_name: &str, ip_address: &str, uuid: &str, headers: &HeaderMap, params: &RequestParameters, send_error: impl SendError) -> GetStringResponse {
+    let from_query = params.authcode.clone()
         .filter(|x| !x.trim().is_empty())
-        .or_else(|| params.auth.as_ref().filter(|x| !x.trim().is_empty()));
+        .or_else(|| params.auth.clone().filter(|x| !x.trim().is_empty()));
+
+    let presented = match authcode::from_headers(headers) {
+        Some(code) => Some(code),
+        None if from_query.is_some() && !get_settings().server.query_credentials() => {
+            let error = GLOBAL_QUERY_AUTH_ERROR;
+            elog_with_ip_uuid(ip_address, uuid, format!("{}: {} ({})", error.code, error.description, request_name));
+            return GetStringResponse::Response(send_error.send(error.code, error.description))
+        }
+        None => from_query
+    };
 
     if let Some(s) = presented {
         let s = s.trim();
@@ -150,14 +228,15 @@ pub fn get_auth(request_name: &str, ip_address: &str, uuid: &str, params: &Reque
             // malformed, and `mask_authcode` on a hostile value would only put a
             // fragment of markup in the log.
             elog_with_ip_uuid(ip_address, uuid, format!("{}: {} ({})", error.code, error.description, request_name));
-            return GetStringResponse::Response(send_xml(send_error_xml_fn(error.code, error.description)))
+            abuse::report(Trigger::MalformedAuthcode, ip_address);
+            return GetStringResponse::Response(send_error.send(error.code, error.description))
         }
         return GetStringResponse::Text(s.to_string())
     }
 
     let error = GLOBAL_AUTH_ERROR;
     elog_with_ip_uuid(ip_address, uuid, format!("{}: {} ({})", error.code, error.description, request_name));
-    GetStringResponse::Response(send_xml(send_error_xml_fn(error.code, error.description)))
+    GetStringResponse::Response(send_error.send(error.code, error.description))
 }
 
 
@@ -168,7 +247,28 @@ pub fn get_auth(request_name: &str, ip_address: &str, uuid: &str, params: &Reque
 /// caller-controlled value decides where the process opens a connection, so it
 /// is the one place the check has to happen: every fetcher and `/post-order`
 /// reaches the SOAP layer through here.
-pub fn get_url(request_name: &str, ip_address: &str, uuid: &str, params: &RequestParameters, send_error_xml_fn: fn(u64, &str) -> String) -> GetStringResponse {
+///
+/// A `tenant` wins over both: its profile's url is used, and a `url` sent
+/// alongside is ignored and logged. An unknown tenant is refused (error `213`)
+/// rather than falling back, which would send the caller's authcode to the
+/// wrong installation.
+pub fn get_url(request_name: &str, ip_address: &str, uuid: &str, params: &RequestParameters, send_error: impl SendError) -> GetStringResponse {
+    if let Some(name) = params.tenant.as_ref().filter(|x| !x.trim().is_empty()) {
+        let Some(profile) = tenant::get(name) else {
+            let error = GLOBAL_TENANT_ERROR;
+            elog_with_ip_uuid(ip_address, uuid, format!("{}: {} -> '{}' ({})", error.code, error.description, name, request_name));
+            return GetStringResponse::Response(send_error.send(error.code, error.description))
+        };
+        if let Some(supplied) = params.url.as_ref().filter(|x| !x.trim().is_empty())
+            && supplied.trim() != profile.url {
+                log_with_ip_uuid(ip_address, uuid, format!(
+                    "Ignoring the supplied url '{}' in favour of tenant '{}' ({})",
+                    supplied, name, request_name
+                ));
+        }
+        log_with_ip_uuid(ip_address, uuid, format!("Using tenant '{}': '{}'", name, profile.url));
+        return GetStringResponse::Text(profile.url.clone())
+    }
     if let Some(s) = params.url.as_ref().filter(|x| !x.trim().is_empty()) {
         if !is_allowed_soap_url(s) {
             let error = GLOBAL_URL_NOT_ALLOWED_ERROR;
@@ -176,7 +276,8 @@ pub fn get_url(request_name: &str, ip_address: &str, uuid: &str, params: &Reques
             // allowlist needs to see what was asked for, and this is also what an
             // SSRF attempt looks like in the log.
             elog_with_ip_uuid(ip_address, uuid, format!("{}: {} -> '{}' ({})", error.code, error.description, s, request_name));
-            return GetStringResponse::Response(send_xml(send_error_xml_fn(error.code, error.description)))
+            abuse::report(Trigger::UrlNotAllowed, ip_address);
+            return GetStringResponse::Response(send_error.send(error.code, error.description))
         }
         return GetStringResponse::Text(s.into())
     }
@@ -186,7 +287,7 @@ pub fn get_url(request_name: &str, ip_address: &str, uuid: &str, params: &Reques
     }
     let error = GLOBAL_URL_ERROR;
     elog_with_ip_uuid(ip_address, uuid, format!("{}: {} ({})", error.code, error.description, request_name));
-    GetStringResponse::Response(send_xml(send_error_xml_fn(error.code, error.description)))
+    GetStringResponse::Response(send_error.send(error.code, error.description))
 }
 
 
@@ -218,7 +319,14 @@ pub fn derive_xmlns(url: &str) -> String {
 /// would break a deployment whose paths are shaped differently. Both the ignored
 /// and the fallback case are logged, so a caller who does pass an `xmlns` shows
 /// up in the log rather than silently changing behaviour.
+///
+/// Ahead of both: the `xmlns` of the tenant profile the url belongs to, which
+/// is configuration like the url itself.
 pub fn get_xmlns(request_name: &str, ip_address: &str, uuid: &str, params: &RequestParameters, url: &str) -> String {
+    if let Some(xmlns) = tenant_xmlns(url) {
+        return xmlns
+    }
+
     let supplied = params.xmlns.as_ref()
         .map(|xmlns| xmlns.trim())
         .filter(|xmlns| !xmlns.is_empty());
@@ -249,19 +357,42 @@ pub fn get_xmlns(request_name: &str, ip_address: &str, uuid: &str, params: &Requ
 }
 
 
+/// The xmlns for a url when there is no request to read a parameter from — the
+/// MCP order tools: the tenant profile's, else derived from the url.
+pub fn xmlns_for_url(url: &str) -> String {
+    tenant_xmlns(url).unwrap_or_else(|| derive_xmlns(url))
+}
+
+
+/// The `xmlns` of the tenant profile the url belongs to, if it sets one.
+fn tenant_xmlns(url: &str) -> Option<String> {
+    tenant::for_url(url)
+        .and_then(|(_, profile)| profile.xmlns.as_ref().map(|xmlns| xmlns.trim().to_string()))
+        .filter(|xmlns| !xmlns.is_empty())
+}
+
+
+/// The caller's `language`, or else the default of the tenant profile the url
+/// belongs to.
+pub fn get_language(params: &RequestParameters, url: &str) -> Option<String> {
+    params.language.clone()
+        .or_else(|| tenant::for_url(url).and_then(|(_, profile)| profile.language.clone()))
+}
+
+
 /// Tries to get pid (Partner ID) from parameter, sends back error xml on fail
-pub fn get_pid(request_name: &str, ip_address: &str, uuid: &str, params: &RequestParameters, send_error_xml_fn: fn(u64, &str) -> String) -> GetI64Response {
+pub fn get_pid(request_name: &str, ip_address: &str, uuid: &str, params: &RequestParameters, send_error: impl SendError) -> GetI64Response {
     if let Some(s) = params.pid {
         return GetI64Response::Number(s)
     }
     let error = GLOBAL_PID_ERROR;
     elog_with_ip_uuid(ip_address, uuid, format!("{}: {} ({})", error.code, error.description, request_name));
-    GetI64Response::Response(send_xml(send_error_xml_fn(error.code, error.description)))
+    GetI64Response::Response(send_error.send(error.code, error.description))
 }
 
 
 /// Tries to get date from parameter, sends back error xml on fail
-pub fn get_date(request_name: &str, ip_address: &str, uuid: &str, param: Option<DateTime<Utc>>, send_error_xml_fn: fn(u64, &str) -> String, param_name: Option<&str>, soft_error: bool) -> GetDateResponse {
+pub fn get_date(request_name: &str, ip_address: &str, uuid: &str, param: Option<DateTime<Utc>>, send_error: impl SendError, param_name: Option<&str>, soft_error: bool) -> GetDateResponse {
     if let Some(s) = param {
         return GetDateResponse::DateTime(s)
     }
@@ -269,18 +400,34 @@ pub fn get_date(request_name: &str, ip_address: &str, uuid: &str, param: Option<
     if !soft_error {
         elog_with_ip_uuid(ip_address, uuid, format!("{}: {} -> {} ({})", error.code, error.description, param_name.unwrap_or("_"), request_name));
     }
-    GetDateResponse::Response(send_xml(send_error_xml_fn(error.code, error.description)))
+    GetDateResponse::Response(send_error.send(error.code, error.description))
 }
 
 
 /// Tries to get i64 from parameter, send back error xml on fail
-pub fn get_i64(request_name: &str, ip_address: &str, uuid: &str, param: Option<i64>, send_error_xml_fn: fn(u64, &str) -> String, param_name: Option<&str>) -> GetI64Response {
+pub fn get_i64(request_name: &str, ip_address: &str, uuid: &str, param: Option<i64>, send_error: impl SendError, param_name: Option<&str>) -> GetI64Response {
     if let Some(s) = param {
         return GetI64Response::Number(s)
     }
     let error = GLOBAL_MISSING_ERROR;
     elog_with_ip_uuid(ip_address, uuid, format!("{}: {} -> {} ({})", error.code, error.description, param_name.unwrap_or("_"), request_name));
-    GetI64Response::Response(send_xml(send_error_xml_fn(error.code, error.description)))
+    GetI64Response::Response(send_error.send(error.code, error.description))
+}
+
+
+/// Tries to get paging from the `limit`, `offset` and `cursor` parameters, sends back error xml on fail
+///
+/// A request with none of the three is not paged, and gets
+/// `GetPagingResponse::Paging(None)`.
+pub fn get_paging(request_name: &str, ip_address: &str, uuid: &str, params: &RequestParameters, send_error: impl SendError) -> GetPagingResponse {
+    match Paging::new(params.offset, params.limit, params.cursor.as_deref()) {
+        Ok(paging) => GetPagingResponse::Paging(paging),
+        Err(reason) => {
+            let error = GLOBAL_PAGING_ERROR;
+            elog_with_ip_uuid(ip_address, uuid, format!("{}: {} -> {} ({})", error.code, error.description, reason, request_name));
+            GetPagingResponse::Response(send_error.send(error.code, error.description))
+        }
+    }
 }
 
 
@@ -7,7 +7,7 @@ use crate::{
     routes::default::{
         GetStringResponse, GetI64Response, GetDateResponse, RequestParameters,
         send_xml, send_csv, send_xlsx, return_internal_server_error,
-        get_auth, get_url, get_xmlns, get_pid, get_i64, get_date
+        get_auth, get_url, get_xmlns, get_language, get_pid, get_i64, get_date
     },
     forms::{
         r#in::xml::defaults::CallData,
@@ -35,7 +35,7 @@ async fn handler(req: HttpRequest, params: RequestParameters) -> impl Responder
     let uuid = get_uuid();
 
     // IP address of the request
-    let ip_address = log_ip(req).await.to_string();
+    let ip_address = log_ip(req.clone()).await.to_string();
 
     // Trying to get url from parameters
     let url = match get_url(REQUEST_NAME, &ip_address, &uuid, &params, error_struct_xml) {
@@ -45,11 +45,14 @@ async fn handler(req: HttpRequest, params: RequestParameters) -> impl Responder
 
     // Deriving XMLNS from the url; the parameter is only a fallback
     let xmlns = get_xmlns(REQUEST_NAME, &ip_address, &uuid, &params, &url);
+
+    // The caller's language, or the tenant's default
+    let language = get_language(&params, &url);
     
     // Creating call data from parameters
     let call_data = CallData {
         // Getting authentication code from parameters
-        authcode: match get_auth(REQUEST_NAME, &ip_address, &uuid, &params, error_struct_xml) {
+        authcode: match get_auth(REQUEST_NAME, &ip_address, &uuid, req.headers(), &params, error_struct_xml) {
             GetStringResponse::Text(auth) => auth,
             GetStringResponse::Response(response) => return response
         },
@@ -87,8 +90,9 @@ async fn handler(req: HttpRequest, params: RequestParameters) -> impl Responder
         } else {
             Some(0)
         },
-        language: params.language,
-        data_type: params.data_type
+        language,
+        data_type: params.data_type,
+        ..Default::default()
     };
 
     // Before log
@@ -5,9 +5,9 @@ use actix_web::{
 
 use crate::{
     routes::default::{
-        RequestParameters, GetStringResponse, GetDateResponse, 
+        RequestParameters, GetStringResponse, GetPagingResponse, GetDateResponse, 
         send_xml, send_csv, send_xlsx, return_internal_server_error,
-        get_auth, get_url, get_xmlns, get_date
+        get_auth, get_url, get_xmlns, get_language, get_date, get_paging
     },
     forms::{
         r#in::xml::defaults::CallData,
@@ -21,6 +21,7 @@ use crate::{
         log::log_with_ip_uuid,
         ipv4::log_ip,
         get_data::{RequestGet, ResponseGet},
+        page::{self, paged},
         get::mat::{MatData, MatCSV}
     }
 };
@@ -34,7 +35,7 @@ async fn handler(req: HttpRequest, params: RequestParameters) -> impl Responder
     let uuid = get_uuid();
 
     // IP address of the request
-    let ip_address = log_ip(req).await.to_string();
+    let ip_address = log_ip(req.clone()).await.to_string();
 
     // Trying to get url from parameters
     let url = match get_url(REQUEST_NAME, &ip_address, &uuid, &params, error_struct_xml) {
@@ -45,10 +46,19 @@ async fn handler(req: HttpRequest, params: RequestParameters) -> impl Responder
     // Deriving XMLNS from the url; the parameter is only a fallback
     let xmlns = get_xmlns(REQUEST_NAME, &ip_address, &uuid, &params, &url);
 
+    // The caller's language, or the tenant's default
+    let language = get_language(&params, &url);
+
+    // Page the caller asked for
+    let paging = match get_paging(REQUEST_NAME, &ip_address, &uuid, &params, error_struct_xml) {
+        GetPagingResponse::Paging(paging) => paging,
+        GetPagingResponse::Response(response) => return response
+    };
+
     // Creating call data from parameters
     let call_data = CallData {
         // Getting authentication code from parameters
-        authcode: match get_auth(REQUEST_NAME, &ip_address, &uuid, &params, error_struct_xml) {
+        authcode: match get_auth(REQUEST_NAME, &ip_address, &uuid, req.headers(), &params, error_struct_xml) {
             GetStringResponse::Text(auth) => auth,
             GetStringResponse::Response(response) => return response
         },
@@ -61,8 +71,9 @@ async fn handler(req: HttpRequest, params: RequestParameters) -> impl Responder
         } else {
             None
         },
-        language: params.language,
+        language,
         data_type: params.data_type,
+        paged: paging.is_some(),
         ..Default::default()
     };
 
@@ -80,9 +91,18 @@ async fn handler(req: HttpRequest, params: RequestParameters) -> impl Responder
 
     // Handling got data
     match data {
-        ResponseGet::Mat(MatData::Xlsx(MatCSV::En(c))) => send_xlsx(&c.concepts, "mat.csv", if is_hu { Some(HU_HEADERS) } else { None }),
-        ResponseGet::Mat(MatData::Csv(MatCSV::En(c))) => send_csv(&c.concepts, "mat.csv", if is_hu { Some(HU_HEADERS) } else { None }),
-        ResponseGet::Mat(MatData::Xml(d)) => send_xml(d.to_xml()),
+        ResponseGet::Mat(MatData::Xlsx(MatCSV::En(mut c))) => {
+            let page = page::cut(paging.as_ref(), &mut c.concepts);
+            paged(send_xlsx(&c.concepts, "mat.xlsx", if is_hu { Some(HU_HEADERS) } else { None }), page.as_ref())
+        }
+        ResponseGet::Mat(MatData::Csv(MatCSV::En(mut c))) => {
+            let page = page::cut(paging.as_ref(), &mut c.concepts);
+            paged(send_csv(&c.concepts, "mat.csv", if is_hu { Some(HU_HEADERS) } else { None }), page.as_ref())
+        }
+        ResponseGet::Mat(MatData::Xml(d)) => {
+            let (xml, page) = d.into_paged_xml(paging.as_ref());
+            paged(send_xml(xml), page.as_ref())
+        }
         _ => return_internal_server_error()
     }
 }
@@ -9,4 +9,6 @@ pub mod test;
 pub mod barcode;
 pub mod invoice;
 pub mod order;
-pub mod mat;
\ No newline at end of file
+pub mod mat;
+pub mod search;
+pub mod availability;
@@ -27,7 +27,9 @@ use crate::{
         log::log_with_ip_uuid,
         ipv4::log_ip,
         get_data::to_xml_string,
-        soap::get_response
+        mcp::mask_authcode,
+        soap::get_response,
+        soap_gate::{self, Lane}
     },
     language::countries::order_country_to_hu
 };
@@ -61,9 +63,9 @@ const REQUEST_NAME: &str = "ORDER SUBMISSION";
 
 async fn handler(req: HttpRequest, params: RequestParameters, body: Bytes) -> impl Responder {
     let uuid = get_uuid();
-    let ip_address = log_ip(req).await.to_string();
+    let ip_address = log_ip(req.clone()).await.to_string();
 
-    let authcode = match get_auth(REQUEST_NAME, &ip_address, &uuid, &params, error_struct_xml) {
+    let authcode = match get_auth(REQUEST_NAME, &ip_address, &uuid, req.headers(), &params, error_struct_xml) {
         GetStringResponse::Text(auth) => auth,
         GetStringResponse::Response(response) => return response
     };
@@ -89,9 +91,7 @@ async fn handler(req: HttpRequest, params: RequestParameters, body: Bytes) -> im
 
     // 2. Parse into `Order`
     let order: Order = match quick_xml::de::from_str(&raw) {
-        Ok(o) => {
-            order_country_to_hu(o)
-        },
+        Ok(o) => o,
         Err(e) => {
             log_with_ip_uuid(&ip_address, &uuid, format!("{REQUEST_NAME}: parse error: {e}"));
             return HttpResponse::BadRequest()
@@ -100,41 +100,66 @@ async fn handler(req: HttpRequest, params: RequestParameters, body: Bytes) -> im
         }
     };
 
-    // 3. Convert `Order` to `Rendeles`
-    let order_hu: Rendeles = order.into();
-    let order_hu_xml_string = to_xml_string(&order_hu);
-    log_with_ip_uuid(&ip_address, &uuid, format!("{REQUEST_NAME}: formatted to: {}", to_single_line(&order_hu_xml_string)));
-
-    // 4. Get the request string
-    let request = get_request_string(&xmlns, &order_hu_xml_string, &authcode);
-    log_with_ip_uuid(&ip_address, &uuid, format!("Request: {}", request));
-
-    // 5. Gets the response string from Octopus
-    let response_str = get_response(&url, request).await;
-    log_with_ip_uuid(&ip_address, &uuid, format!("Response: {}", response_str));
-
-    // 6. Deserialize SOAP response into Envelope
-    let envelope: Envelope = match quick_xml::de::from_str(&response_str) {
-        Ok(e) => e,
+    // 3. Convert, send, and read the answer in English
+    let response_trans = match submit(&url, &xmlns, &authcode, order, |line| log_with_ip_uuid(&ip_address, &uuid, line)).await {
+        Ok(response) => response,
         Err(e) => {
-            log_with_ip_uuid(&ip_address, &uuid, format!("{REQUEST_NAME}: response parse error: {e}"));
             return HttpResponse::InternalServerError()
                 .content_type("application/xml")
                 .body(format!("<error><message>Failed to parse Octopus response: {e}</message></error>"));
         }
     };
 
-    // 7. Convert response to `p_Envelope` and then to raw XML string
-    let response_trans: p_Envelope = envelope.into();
+    // 4. Convert the English response to raw XML string
     let response_xml = to_xml_string(&response_trans);
 
     log_with_ip_uuid(&ip_address, &uuid, format!("{REQUEST_NAME}: converted to English response: {}", to_single_line(&response_xml)));
 
-    // 8. Send back English XML response to client
+    // 5. Send back English XML response to client
     send_xml(response_xml)
 }
 
 
+/// Sends a parsed order to Octopus and returns its answer in English.
+///
+/// The whole of what `/post-order` does once it holds an `Order`: country names
+/// to Hungarian, `Order` to `Rendeles`, the SOAP call in the interactive lane,
+/// and the `RendelesFeladasAuth` response translated. Shared with the MCP order
+/// tools, which build their `Order` from a confirmed draft rather than a body.
+/// Every step is reported to `log`; the authcode only ever as its mask.
+///
+/// An `Err` means Octopus's answer could not be read — whether the order was
+/// recorded is then unknown.
+pub async fn submit(url: &str, xmlns: &str, authcode: &str, order: Order, log: impl Fn(String)) -> Result<p_Envelope, String> {
+    // 1. Convert `Order` to `Rendeles`
+    let order_hu: Rendeles = order_country_to_hu(order).into();
+    let order_hu_xml_string = to_xml_string(&order_hu);
+    log(format!("{REQUEST_NAME}: formatted to: {}", to_single_line(&order_hu_xml_string)));
+
+    // 2. Get the request string
+    let request = get_request_string(xmlns, &order_hu_xml_string, authcode);
+    // The envelope carries the authcode; the log gets its mask.
+    log(format!("Request: {}", request.replace(authcode, &mask_authcode(authcode))));
+
+    // 3. Gets the response string from Octopus
+    // Someone is waiting on an order: it never queues behind a bulk sync.
+    let response_str = soap_gate::in_lane(Lane::Interactive, get_response(url, request)).await;
+    log(format!("Response: {}", response_str));
+
+    // 4. Deserialize SOAP response into Envelope
+    let envelope: Envelope = match quick_xml::de::from_str(&response_str) {
+        Ok(e) => e,
+        Err(e) => {
+            log(format!("{REQUEST_NAME}: response parse error: {e}"));
+            return Err(e.to_string())
+        }
+    };
+
+    // 5. Convert response to `p_Envelope`
+    Ok(envelope.into())
+}
+
+
 /// POST handler
 #[post("/post-order")]
 pub async fn post(req: HttpRequest, query: Query<RequestParameters>, body: Bytes, ) -> impl Responder {
@@ -5,9 +5,9 @@ use actix_web::{
 
 use crate::{
     routes::default::{
-        RequestParameters, GetStringResponse, GetI64Response,
-        send_xml, send_csv, send_xlsx, return_internal_server_error,
-        get_auth, get_url, get_xmlns, get_pid
+        RequestParameters, GetStringResponse, GetPagingResponse, GetI64Response,
+        send_xml, return_internal_server_error,
+        get_auth, get_url, get_xmlns, get_language, get_pid, get_paging
     },
     forms::{
         r#in::xml::defaults::CallData,
@@ -21,6 +21,8 @@ use crate::{
         log::log_with_ip_uuid,
         ipv4::log_ip,
         get_data::{RequestGet, ResponseGet},
+        page::{self, paged},
+        select::Selection,
         get::prices::{PricesData, PricesCSV}
     }
 };
@@ -34,7 +36,7 @@ async fn handler(req: HttpRequest, params: RequestParameters) -> impl Responder
     let uuid = get_uuid();
 
     // IP address of the request
-    let ip_address = log_ip(req).await.to_string();
+    let ip_address = log_ip(req.clone()).await.to_string();
 
     // Trying to get url from parameters
     let url = match get_url(REQUEST_NAME, &ip_address, &uuid, &params, error_struct_xml) {
@@ -44,11 +46,23 @@ async fn handler(req: HttpRequest, params: RequestParameters) -> impl Responder
 
     // Deriving XMLNS from the url; the parameter is only a fallback
     let xmlns = get_xmlns(REQUEST_NAME, &ip_address, &uuid, &params, &url);
+
+    // The caller's language, or the tenant's default
+    let language = get_language(&params, &url);
+
+    // Column projection and filters, applied to the translated records
+    let selection = Selection::from_params(&params);
     
+    // Page the caller asked for, cut after any filters
+    let paging = match get_paging(REQUEST_NAME, &ip_address, &uuid, &params, error_struct_xml) {
+        GetPagingResponse::Paging(paging) => paging,
+        GetPagingResponse::Response(response) => return response
+    };
+
     // Creating call data from parameters
     let call_data = CallData {
         // Getting authentication code from parameters
-        authcode: match get_auth(REQUEST_NAME, &ip_address, &uuid, &params, error_struct_xml) {
+        authcode: match get_auth(REQUEST_NAME, &ip_address, &uuid, req.headers(), &params, error_struct_xml) {
             GetStringResponse::Text(auth) => auth,
             GetStringResponse::Response(response) => return response
         },
@@ -59,8 +73,9 @@ async fn handler(req: HttpRequest, params: RequestParameters) -> impl Responder
             GetI64Response::Number(pid) => Some(pid),
             GetI64Response::Response(response) => return response
         },
-        language: params.language,
+        language,
         data_type: params.data_type,
+        paged: paging.is_some(),
         ..Default::default()
     };
 
@@ -78,9 +93,20 @@ async fn handler(req: HttpRequest, params: RequestParameters) -> impl Responder
 
     // Handling got data
     match data {
-        ResponseGet::Prices(PricesData::Xlsx(PricesCSV::En(d))) => send_xlsx(&d.prices, "prices.xlsx", if is_hu { Some(HU_HEADERS) } else { None }),
-        ResponseGet::Prices(PricesData::Csv(PricesCSV::En(d))) => send_csv(&d.prices, "prices.csv", if is_hu { Some(HU_HEADERS) } else { None }),
-        ResponseGet::Prices(PricesData::Xml(d)) => send_xml(d.to_xml()),
+        ResponseGet::Prices(PricesData::Xlsx(PricesCSV::En(mut d))) => {
+            selection.retain(&mut d.prices, &ip_address, &uuid);
+            let page = page::cut(paging.as_ref(), &mut d.prices);
+            paged(selection.send_xlsx(&d.prices, "prices.xlsx", if is_hu { Some(HU_HEADERS) } else { None }), page.as_ref())
+        }
+        ResponseGet::Prices(PricesData::Csv(PricesCSV::En(mut d))) => {
+            selection.retain(&mut d.prices, &ip_address, &uuid);
+            let page = page::cut(paging.as_ref(), &mut d.prices);
+            paged(selection.send_csv(&d.prices, "prices.csv", if is_hu { Some(HU_HEADERS) } else { None }), page.as_ref())
+        }
+        ResponseGet::Prices(PricesData::Xml(d)) => {
+            let (xml, page) = d.into_selected_xml(&selection, paging.as_ref(), &ip_address, &uuid);
+            paged(send_xml(xml), page.as_ref())
+        }
         _ => return_internal_server_error()
     }
 }
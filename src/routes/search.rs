@@ -0,0 +1,154 @@
+use actix_web::{
+    get, HttpRequest, HttpResponse, Responder,
+    web::Query
+};
+use serde_json::json;
+
+use crate::{
+    routes::default::{
+        RequestParameters, GetStringResponse, GetI64Response, GetPagingResponse, JsonError, SendError,
+        get_auth, get_url, get_pid, get_paging
+    },
+    global::errors::{GLOBAL_FILTER_ERROR, GLOBAL_GET_DATA_ERROR, GLOBAL_MISSING_ERROR},
+    service::{
+        slave::get_uuid,
+        log::{log_with_ip_uuid, elog_with_ip_uuid},
+        ipv4::log_ip,
+        mcp::{
+            cache::cache,
+            index::{SearchFilters, fold},
+            mask_authcode,
+            syntax
+        },
+        page::{Paging, paged},
+        soap_gate::{self, Lane}
+    }
+};
+
+/// Name of the current request
+const REQUEST_NAME: &str = "SEARCH PRODUCTS REQUEST";
+
+/// Results per page when the caller asks for no `limit`
+const DEFAULT_LIMIT: usize = 20;
+
+/// Largest page served, whatever the caller asks for; a search is browsed, not
+/// exported (`get-products` is there for that)
+const MAX_LIMIT: usize = 100;
+
+/// Entries per facet list, and values per attribute
+const FACET_LIMIT: usize = 50;
+
+/// Handler
+///
+/// Answers from the partner's catalog snapshot (`service/mcp`), the same one and
+/// the same ranking `search_products` uses, so the two never disagree. That is
+/// why it is served only with `[mcp] enabled = true`. The result is JSON: rows
+/// carry a nested `highlight`, and the `facets` breakdown of every match is
+/// nested too, neither of which the flat XML and CSV shapes can hold. Errors
+/// are JSON as well ([`JsonError`]), with the codes the XML routes use.
+async fn handler(req: HttpRequest, params: RequestParameters) -> impl Responder {
+    // ID with UUID
+    let uuid = get_uuid();
+
+    // IP address of the request
+    let ip_address = log_ip(req.clone()).await.to_string();
+
+    // The words to search for
+    let Some(query) = params.query.clone().filter(|query| !query.trim().is_empty()) else {
+        let error = GLOBAL_MISSING_ERROR;
+        elog_with_ip_uuid(&ip_address, &uuid, format!("{}: {} -> query ({})", error.code, error.description, REQUEST_NAME));
+        return JsonError.send(error.code, error.description)
+    };
+
+    // Trying to get url from parameters
+    let url = match get_url(REQUEST_NAME, &ip_address, &uuid, &params, JsonError) {
+        GetStringResponse::Text(url) => url,
+        GetStringResponse::Response(response) => return response
+    };
+
+    // Getting authentication code from parameters
+    let authcode = match get_auth(REQUEST_NAME, &ip_address, &uuid, req.headers(), &params, JsonError) {
+        GetStringResponse::Text(auth) => auth,
+        GetStringResponse::Response(response) => return response
+    };
+
+    // Prices and stock in a snapshot are the partner's own
+    let pid = match get_pid(REQUEST_NAME, &ip_address, &uuid, &params, JsonError) {
+        GetI64Response::Number(pid) => pid,
+        GetI64Response::Response(response) => return response
+    };
+
+    // Page the caller asked for, always bounded
+    let paging = match get_paging(REQUEST_NAME, &ip_address, &uuid, &params, JsonError) {
+        GetPagingResponse::Paging(paging) => Paging::bounded(paging, DEFAULT_LIMIT, MAX_LIMIT),
+        GetPagingResponse::Response(response) => return response
+    };
+
+    // Attribute filters, as the facets name them
+    let predicates = match params.attributes.as_deref().map(syntax::attribute_list).transpose() {
+        Ok(predicates) => predicates.unwrap_or_default(),
+        Err(reason) => {
+            let error = GLOBAL_FILTER_ERROR;
+            elog_with_ip_uuid(&ip_address, &uuid, format!("{}: {} -> {} ({})", error.code, error.description, reason, REQUEST_NAME));
+            return JsonError.send(error.code, error.description)
+        }
+    };
+
+    let filters = SearchFilters {
+        brand: params.brand.as_deref().map(fold),
+        category: params.category.as_deref().map(fold),
+        main_category: None,
+        predicates
+    };
+
+    // Before log
+    log_with_ip_uuid(&ip_address, &uuid, format!(
+        "Before getting {}, {} pid={} query='{}' {:?}",
+        REQUEST_NAME, mask_authcode(&authcode), pid, query, paging
+    ));
+
+    // A caller is waiting, so a cold build takes the interactive lane
+    let snapshot = match soap_gate::in_lane(Lane::Interactive, cache().get_or_build(&authcode, pid, &url)).await {
+        Ok(snapshot) => snapshot,
+        Err(reason) => {
+            let error = GLOBAL_GET_DATA_ERROR;
+            elog_with_ip_uuid(&ip_address, &uuid, format!("{}: {} -> {} ({})", error.code, error.description, reason, REQUEST_NAME));
+            return JsonError.send(error.code, error.description)
+        }
+    };
+
+    let outcome = snapshot.search_page(&query, &filters, paging.offset(), paging.limit().unwrap_or(DEFAULT_LIMIT));
+
+    // After log
+    log_with_ip_uuid(&ip_address, &uuid, format!(
+        "After {} got: matched={} returned={}",
+        REQUEST_NAME, outcome.matched, outcome.results.len()
+    ));
+
+    let page = paging.describe(outcome.matched);
+    paged(
+        HttpResponse::Ok().json(json!({
+            "matched": outcome.matched,
+            "returned": outcome.results.len(),
+            "offset": page.offset,
+            "catalog_age_seconds": snapshot.age_secs(),
+            "results": outcome.results,
+            "facets": outcome.facets.top(FACET_LIMIT)
+        })),
+        Some(&page)
+    )
+}
+
+
+/// GET handler
+#[get("/search-product")]
+pub async fn get(req: HttpRequest, query: Query<RequestParameters>) -> impl Responder {
+    handler(req, query.into_inner()).await
+}
+
+
+/// GET handler alias
+#[get("/search-products")]
+pub async fn get_alias(req: HttpRequest, query: Query<RequestParameters>) -> impl Responder {
+    handler(req, query.into_inner()).await
+}
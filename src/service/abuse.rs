@@ -0,0 +1,353 @@
+//! Automatic, time-limited blocks for callers that keep doing what only abuse
+//! looks like.
+//!
+//! Four signals count as a strike against an address:
+//!
+//! - a malformed authcode (error 206) — markup or SQL where a code should be;
+//! - a refused `url` parameter (error 205) — an SSRF probe;
+//! - an authcode Octopus rejects — guessing, whether on a fetcher or on the
+//!   OAuth sign-in form;
+//! - a rate-limit refusal (429, see `service/ratelimit`) — a flood that ignores
+//!   `Retry-After`. A spent daily quota is a 429 too, but not a strike.
+//!
+//! Enough strikes of one kind within `window_secs` and the address gets an IP
+//! rule in the blocklist, marked `auto`, with a reason and an expiry. From then
+//! on it is an ordinary rule: the blocklist enforces it, `/admin` shows it, and
+//! an operator can lift it early or make it permanent. Expired automatic rules
+//! are dropped the next time one is added and at startup.
+//!
+//! ## What is never blocked automatically
+//!
+//! An operator's own rule is never overwritten — a paused manual rule stays
+//! paused — and an address covered by an allow rule is never counted.
+//!
+//! Nor is a loopback address, or one in `[server] trusted_proxies`: that is
+//! the reverse proxy or load balancer in front of this server, and should the
+//! client ever resolve to it, blocking it would block everyone behind it.
+//!
+//! ## How an Octopus rejection finds its caller
+//!
+//! The rejection surfaces in `get::defaults::check_return_type`, deep inside a
+//! fetcher that was handed a [`CallData`](crate::forms::r#in::xml::defaults::CallData)
+//! and nothing about who asked. Rather than thread an address through every
+//! fetcher, [`guard`] scopes the request in a task-local holding the caller's
+//! address and [`report_caller`] reads it back. Anything outside a request —
+//! the precache job, a snapshot build — has no caller and is never counted: an
+//! operator's stale precache code is not an attack.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::Error;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use chrono::Utc;
+use once_cell::sync::Lazy;
+
+use crate::service::{
+    blocklist::{self, BlockRule, BlockScope, Surface, request_addresses},
+    config::{AbuseConfig, get_settings},
+    ipv4::{IpNetwork, normalize, trusted_proxies},
+    log::{elog_with_ip, elogger, logger}
+};
+
+/// The Hungarian text Octopus answers a rejected authcode with, as listed in
+/// `src/errors/errors.json` ("Authentication error").
+const OCTOPUS_AUTH_ERROR: &str = "Authentikációs hiba";
+
+/// Above this many tracked addresses the whole strike table is swept on the
+/// next report, rather than only the entry being written.
+const SWEEP_ABOVE: usize = 1_024;
+
+
+/// What a strike was for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Trigger {
+    MalformedAuthcode,
+    UrlNotAllowed,
+    OctopusAuth,
+    Flood
+}
+
+impl Trigger {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Trigger::MalformedAuthcode => "malformed_authcode",
+            Trigger::UrlNotAllowed => "url_not_allowed",
+            Trigger::OctopusAuth => "octopus_auth",
+            Trigger::Flood => "flood"
+        }
+    }
+
+    /// What the block's reason says was repeated.
+    fn describe(&self) -> &'static str {
+        match self {
+            Trigger::MalformedAuthcode => "malformed authcodes (error 206)",
+            Trigger::UrlNotAllowed => "refused url parameters (error 205)",
+            Trigger::OctopusAuth => "authcodes rejected by Octopus",
+            Trigger::Flood => "rate-limit refusals (429)"
+        }
+    }
+
+    fn threshold(&self, config: &AbuseConfig) -> u32 {
+        match self {
+            Trigger::MalformedAuthcode => config.malformed_authcode(),
+            Trigger::UrlNotAllowed => config.url_not_allowed(),
+            Trigger::OctopusAuth => config.octopus_auth(),
+            Trigger::Flood => config.flood()
+        }
+    }
+}
+
+
+/// The `[abuse]` table, or `None` when it is absent or disabled — in which case
+/// every function here returns on its first line.
+static CONFIG: Lazy<Option<AbuseConfig>> = Lazy::new(|| {
+    get_settings().abuse.filter(AbuseConfig::is_enabled)
+});
+
+/// Recent strikes per trigger and address. In memory only, like the rate-limit
+/// buckets: a restart forgets who was close to a block, not who was blocked.
+static STRIKES: Lazy<Mutex<Strikes>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// When each recent strike landed, per trigger and address.
+type Strikes = HashMap<(Trigger, String), Vec<Instant>>;
+
+tokio::task_local! {
+    /// The address of the request being served. See the module note.
+    static CALLER: String;
+}
+
+
+/// Records a strike, and reports whether it was the one that crossed the
+/// threshold. Crossing clears the address's strikes for that trigger, so a
+/// block that is lifted early starts the count again from zero.
+fn strike(strikes: &mut Strikes, trigger: Trigger, address: &str, threshold: u32, window: Duration, now: Instant) -> bool {
+    if strikes.len() > SWEEP_ABOVE {
+        strikes.retain(|_, times| {
+            times.retain(|at| now.saturating_duration_since(*at) < window);
+            !times.is_empty()
+        });
+    }
+
+    let key = (trigger, address.to_string());
+    let times = strikes.entry(key.clone()).or_default();
+    times.retain(|at| now.saturating_duration_since(*at) < window);
+    times.push(now);
+    if times.len() < threshold as usize {
+        return false
+    }
+    strikes.remove(&key);
+    true
+}
+
+
+/// Counts a strike against an address, blocking it when this one crosses the
+/// trigger's threshold. A no-op when abuse detection is off or the trigger's
+/// threshold is `0`.
+pub fn report(trigger: Trigger, address: &str) {
+    let Some(config) = CONFIG.as_ref() else {
+        return
+    };
+    let threshold = trigger.threshold(config);
+    if threshold == 0 {
+        return
+    }
+    // Only a real address that is not a proxy of ours can be blocked.
+    // "unknown IP address" and the proxies in front of this server fall out here.
+    let Ok(ip) = address.trim().parse::<IpAddr>() else {
+        return
+    };
+    if is_proxy(ip, trusted_proxies()) {
+        return
+    }
+    // An address an operator has allowed is theirs to police, not ours.
+    if [Surface::Rest, Surface::Mcp].into_iter().any(|surface| blocklist::is_allowed(&[ip], None, surface)) {
+        return
+    }
+
+    let crossed = match STRIKES.lock() {
+        Ok(mut strikes) => strike(&mut strikes, trigger, address.trim(), threshold, Duration::from_secs(config.window_secs()), Instant::now()),
+        Err(_) => false
+    };
+    if crossed {
+        block(trigger, &ip.to_string(), threshold, config);
+    }
+}
+
+
+/// Whether an address is loopback or a trusted proxy, which is never blocked.
+fn is_proxy(ip: IpAddr, trusted: &[IpNetwork]) -> bool {
+    let ip = normalize(ip);
+    ip.is_loopback() || trusted.iter().any(|network| network.contains(ip))
+}
+
+
+/// [`report`] against the address of the request being served, when there is
+/// one.
+pub fn report_caller(trigger: Trigger) {
+    if CONFIG.is_none() {
+        return
+    }
+    if let Ok(address) = CALLER.try_with(|address| address.clone()) {
+        report(trigger, &address);
+    }
+}
+
+
+/// Whether an Octopus `<hiba>` description is a rejected authcode.
+pub fn is_octopus_auth_error(description: &str) -> bool {
+    description.trim_start().starts_with(OCTOPUS_AUTH_ERROR)
+}
+
+
+/// Writes the automatic rule for an address that crossed a threshold.
+fn block(trigger: Trigger, address: &str, threshold: u32, config: &AbuseConfig) {
+    let window_minutes = config.window_secs().div_ceil(60);
+    let note = format!("auto: {} {} in {} min", threshold, trigger.describe(), window_minutes);
+    let mut rule = match BlockRule::ip(address, Some(note.clone()), Some(BlockScope::All)) {
+        Ok(rule) => rule,
+        Err(error) => {
+            elogger(format!("Abuse: cannot block '{}': {}", address, error));
+            return
+        }
+    };
+
+    // An operator's decision about this address wins, whatever it was: an
+    // active rule already refuses it, and a paused one was paused on purpose.
+    let now = Utc::now();
+    if let Some(existing) = blocklist::find(&rule.id())
+        && (!existing.is_auto() || existing.is_active(now)) {
+            return
+    }
+
+    rule.auto = Some(true);
+    rule.expires_at = Some(now + chrono::Duration::seconds(config.block_secs() as i64));
+
+    if let Err(error) = blocklist::purge_expired() {
+        elogger(format!("Abuse: cannot drop expired automatic rules: {}", error));
+    }
+    match blocklist::upsert(rule) {
+        Ok(()) => elog_with_ip(address, format!(
+            "ABUSE: blocked for {} s [{}] — {}", config.block_secs(), trigger.as_str(), note
+        )),
+        Err(error) => elogger(format!("Abuse: cannot save the block for '{}': {}", address, error))
+    }
+}
+
+
+/// Reports the thresholds at startup.
+pub fn init() {
+    let Some(config) = CONFIG.as_ref() else {
+        logger("Abuse detection: off (no [abuse] table, or enabled = false)");
+        return
+    };
+    logger(format!(
+        "Abuse detection: per {} s — {} malformed authcodes, {} refused urls, {} Octopus rejections, {} rate-limit refusals; blocks last {} s",
+        config.window_secs(),
+        config.malformed_authcode(),
+        config.url_not_allowed(),
+        config.octopus_auth(),
+        config.flood(),
+        config.block_secs()
+    ));
+}
+
+
+/// The middleware. Holds the caller's address for [`report_caller`] while the
+/// request is served; with detection off it does nothing else either.
+pub async fn guard(
+    request: ServiceRequest,
+    next: Next<impl MessageBody + 'static>
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if CONFIG.is_none() {
+        return next.call(request).await.map(ServiceResponse::map_into_boxed_body)
+    }
+    let (_, display) = request_addresses(&request);
+    let Some(address) = display else {
+        return next.call(request).await.map(ServiceResponse::map_into_boxed_body)
+    };
+    CALLER.scope(address, next.call(request)).await.map(ServiceResponse::map_into_boxed_body)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_threshold_strike_crosses_once_then_the_count_restarts() {
+        let mut strikes = HashMap::new();
+        let window = Duration::from_secs(600);
+        let now = Instant::now();
+
+        assert!(!strike(&mut strikes, Trigger::UrlNotAllowed, "203.0.113.7", 3, window, now));
+        assert!(!strike(&mut strikes, Trigger::UrlNotAllowed, "203.0.113.7", 3, window, now));
+        assert!(strike(&mut strikes, Trigger::UrlNotAllowed, "203.0.113.7", 3, window, now));
+        assert!(!strike(&mut strikes, Trigger::UrlNotAllowed, "203.0.113.7", 3, window, now));
+    }
+
+    #[test]
+    fn loopback_and_trusted_proxies_are_never_blocked() {
+        let trusted: Vec<IpNetwork> = ["10.0.0.0/8", "2001:db8::/32"].iter().filter_map(|entry| IpNetwork::parse(entry)).collect();
+        let ip = |address: &str| address.parse::<IpAddr>().unwrap();
+
+        assert!(is_proxy(ip("127.0.0.1"), &[]));
+        assert!(is_proxy(ip("::1"), &[]));
+        assert!(is_proxy(ip("10.20.30.40"), &trusted));
+        assert!(is_proxy(ip("::ffff:10.20.30.40"), &trusted));
+        assert!(is_proxy(ip("2001:db8::17"), &trusted));
+        assert!(!is_proxy(ip("203.0.113.7"), &trusted));
+        assert!(!is_proxy(ip("10.20.30.40"), &[]));
+    }
+
+    #[test]
+    fn strikes_outside_the_window_do_not_count() {
+        let mut strikes = HashMap::new();
+        let window = Duration::from_secs(60);
+        let start = Instant::now();
+
+        assert!(!strike(&mut strikes, Trigger::Flood, "203.0.113.7", 2, window, start));
+        assert!(!strike(&mut strikes, Trigger::Flood, "203.0.113.7", 2, window, start + Duration::from_secs(61)));
+        assert!(strike(&mut strikes, Trigger::Flood, "203.0.113.7", 2, window, start + Duration::from_secs(62)));
+    }
+
+    #[test]
+    fn triggers_and_addresses_are_counted_apart() {
+        let mut strikes = HashMap::new();
+        let window = Duration::from_secs(600);
+        let now = Instant::now();
+
+        assert!(!strike(&mut strikes, Trigger::OctopusAuth, "203.0.113.7", 2, window, now));
+        assert!(!strike(&mut strikes, Trigger::MalformedAuthcode, "203.0.113.7", 2, window, now));
+        assert!(!strike(&mut strikes, Trigger::OctopusAuth, "203.0.113.8", 2, window, now));
+        assert!(strike(&mut strikes, Trigger::OctopusAuth, "203.0.113.7", 2, window, now));
+    }
+
+    #[test]
+    fn only_the_octopus_authentication_error_counts_as_a_rejected_code() {
+        assert!(is_octopus_auth_error("Authentikációs hiba"));
+        assert!(is_octopus_auth_error(" Authentikációs hiba: lejárt kód"));
+        assert!(!is_octopus_auth_error("Túl sok kérés"));
+    }
+
+    #[test]
+    fn an_omitted_threshold_takes_its_default_and_zero_stays_zero() {
+        let config = AbuseConfig {
+            enabled: Some(true),
+            window_secs: None,
+            block_secs: Some(0),
+            malformed_authcode: None,
+            url_not_allowed: Some(0),
+            octopus_auth: None,
+            flood: None
+        };
+        assert_eq!(Trigger::MalformedAuthcode.threshold(&config), 10);
+        assert_eq!(Trigger::UrlNotAllowed.threshold(&config), 0);
+        // A zero-length block would be no block at all: the default applies.
+        assert_eq!(config.block_secs(), 3_600);
+    }
+}
@@ -0,0 +1,574 @@
+//! Rustopus-issued API keys for the REST endpoints.
+//!
+//! A partner integrating against the fetchers used to need the raw Octopus
+//! authcode, which it cannot rotate and which leaks wherever the integration is
+//! configured. An API key stands in for it: issued in `/admin`, stored hashed,
+//! and mapped here to the authcode (plus, optionally, the pid and url) that
+//! Octopus actually sees. Revoking or expiring a key costs the partner nothing
+//! but a new key; the authcode never leaves this server.
+//!
+//! ## How a key is presented
+//!
+//! Anywhere an authcode is: `X-Authcode`, `Authorization: Bearer`, or the query
+//! parameter while `[server] query_credentials` allows it. A key is told apart
+//! by its [`KEY_PREFIX`], which no Octopus authcode carries. The middleware
+//! below swaps it for the mapped credentials before the request reaches a
+//! route, so the nine fetchers and `/post-order` need no change of their own,
+//! and then records the key on the request for `service/ratelimit`, which
+//! charges the key's own limit on top of the usual ones.
+//!
+//! ## What is secret
+//!
+//! `api_keys.toml` holds live authcodes in plain text, for the same reason
+//! `oauth_sessions.toml` does, and is written `0600` through a temp file and a
+//! rename — sealed, too, when a storage key is configured (`service/sealed.rs`). The key itself is stored as its SHA-256 and shown exactly once, when
+//! it is created — like an OAuth client secret.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Uri;
+use actix_web::http::header::{AUTHORIZATION, HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpMessage, HttpResponse};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    global::errors::{GLOBAL_API_KEY_ERROR, GLOBAL_API_KEY_SCOPE_ERROR, RustopusError},
+    service::{
+        blocklist::{self, Surface, request_addresses, request_authcode},
+        log::{elog_with_ip, elogger, log_with_ip, logger},
+        mcp::oauth::{hash_secret, new_secret},
+        path::get_current_or_root_dir,
+        ratelimit::{ENDPOINTS, endpoint_of},
+        sealed
+    }
+};
+
+/// What every issued key starts with. A hyphen rather than an underscore so a
+/// key passes `authcode::is_well_formed` like the code it stands in for.
+pub const KEY_PREFIX: &str = "rk-";
+
+/// What `api_keys.toml` is sealed as — see `service/sealed`.
+pub const SEAL_CONTEXT: &str = "api_keys.toml";
+
+/// Characters of a key shown on the dashboard after the prefix, enough to tell
+/// a partner's two keys apart and nowhere near enough to use one.
+const SHOWN_CHARS: usize = 6;
+
+
+/// One issued key.
+///
+/// **Secret-grade.** `authcode` is the live Octopus code the key stands in for.
+/// Nothing renders this struct outside the process without going through the
+/// dashboard payload, which leaves it out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    /// The first characters of the key's hash: stable, safe in a URL.
+    pub id: String,
+    pub label: String,
+    /// SHA-256 hex of the key. The key itself was returned once, at creation.
+    pub key_hash: String,
+    /// How the key is shown: `rk-3f9a1c…`.
+    pub hint: String,
+    /// **SECRET** — the Octopus authentication code.
+    pub authcode: String,
+    /// Replaces the caller's `pid` parameter when set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<i64>,
+    /// Replaces the caller's `url` parameter when set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Endpoints the key may call, by their singular name (`get-product`).
+    /// Empty means all of them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+    /// The key's own rate, on top of the authcode and IP limits.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub per_minute: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_quota: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Set `false` to keep a key on file but refuse it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>
+}
+
+impl ApiKey {
+    /// Mints a key for an authcode. Returns the record and the key, which the
+    /// caller shows once and drops.
+    pub fn issue(label: String, authcode: String) -> (Self, String) {
+        let key = format!("{}{}", KEY_PREFIX, new_secret());
+        let key_hash = hash_secret(&key);
+        let shown: String = key.chars().skip(KEY_PREFIX.len()).take(SHOWN_CHARS).collect();
+        let record = Self {
+            id: key_hash.chars().take(12).collect(),
+            label,
+            key_hash,
+            hint: format!("{}{}…", KEY_PREFIX, shown),
+            authcode,
+            pid: None,
+            url: None,
+            scopes: Vec::new(),
+            per_minute: None,
+            daily_quota: None,
+            expires_at: None,
+            enabled: Some(true),
+            created_at: Some(Utc::now())
+        };
+        (record, key)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+
+    /// Whether the key may call `endpoint`.
+    pub fn covers(&self, endpoint: &str) -> bool {
+        self.scopes.is_empty() || self.scopes.iter().any(|scope| scope == endpoint)
+    }
+}
+
+
+/// Checks scope names against the metered endpoints, so a typo is refused at
+/// the dashboard rather than silently locking a key out of everything.
+pub fn parse_scopes(scopes: &[String]) -> Result<Vec<String>, String> {
+    let mut parsed = Vec::new();
+    for scope in scopes.iter().map(|scope| scope.trim().to_lowercase()).filter(|scope| !scope.is_empty()) {
+        // `get-products` names the same route as `get-product`.
+        let Some(endpoint) = endpoint_of(&format!("/{}", scope)) else {
+            return Err(format!("'{}' is not an endpoint — expected one of {}", scope, ENDPOINTS.join(", ")))
+        };
+        if !parsed.iter().any(|known| known == endpoint) {
+            parsed.push(endpoint.to_string());
+        }
+    }
+    Ok(parsed)
+}
+
+
+/// On-disk shape of `api_keys.toml`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ApiKeyConfig {
+    #[serde(default, rename = "key")]
+    pub keys: Vec<ApiKey>
+}
+
+
+static KEYS: Lazy<RwLock<Vec<ApiKey>>> = Lazy::new(|| RwLock::new(load().keys));
+
+/// When each key was last used, for the dashboard. In memory, like the OAuth
+/// grants' timestamps: a timestamp is not worth rewriting a credential file for.
+static LAST_USED: Lazy<Mutex<HashMap<String, DateTime<Utc>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+
+pub fn get_api_keys_path() -> PathBuf {
+    let mut path = get_current_or_root_dir();
+    path.push("api_keys.toml");
+    path
+}
+
+
+/// Reads `api_keys.toml`, or no keys when it is absent or unreadable. A missing
+/// file is the normal case: none has been issued yet.
+pub fn load() -> ApiKeyConfig {
+    let path = get_api_keys_path();
+    if !path.is_file() {
+        return ApiKeyConfig::default()
+    }
+    match sealed::read_to_string(&path, SEAL_CONTEXT) {
+        Ok(content) => match toml::from_str::<ApiKeyConfig>(&content) {
+            Ok(config) => config,
+            Err(error) => {
+                elogger(format!("API keys: cannot parse '{:?}': {}", path, error));
+                ApiKeyConfig::default()
+            }
+        },
+        Err(error) => {
+            elogger(format!("API keys: cannot read '{:?}': {}", path, error));
+            ApiKeyConfig::default()
+        }
+    }
+}
+
+
+/// Writes `api_keys.toml` through a temp file and a rename, owner-only, and
+/// sealed when a storage key is configured.
+fn save(config: &ApiKeyConfig) -> Result<(), String> {
+    let path = get_api_keys_path();
+    let body = toml::to_string_pretty(config).map_err(|error| error.to_string())?;
+    let content = format!(
+        "# Rustopus API keys.\n\
+         #\n\
+         # SECRET FILE: every key maps to a live Octopus authcode, held here in\n\
+         # plain text because the REST endpoints present it to Octopus on the\n\
+         # partner's behalf. The keys themselves are stored as SHA-256 hashes.\n\
+         # Keep it gitignored, keep it 0600, and mount it like oauth_sessions.toml.\n\
+         #\n\
+         # Managed by the /admin dashboard; hand edits are picked up on restart.\n\n{}",
+        body
+    );
+
+    let temporary = path.with_extension("toml.tmp");
+    std::fs::write(&temporary, sealed::seal(content.into_bytes(), SEAL_CONTEXT)?).map_err(|error| error.to_string())?;
+    restrict_permissions(&temporary);
+    std::fs::rename(&temporary, &path).map_err(|error| error.to_string())?;
+    restrict_permissions(&path);
+    Ok(())
+}
+
+
+/// Narrows a file to owner read/write. Logged rather than fatal — the
+/// alternative is refusing to issue a key at all.
+fn restrict_permissions(path: &Path) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Err(error) = std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)) {
+            elogger(format!("API keys: cannot restrict permissions on '{:?}': {}", path, error));
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+    }
+}
+
+
+/// Loads the keys and reports them. Called from `main.rs` at startup so a
+/// broken file shows in the log before the first partner presents a key.
+pub fn init() {
+    let keys = keys();
+    if keys.is_empty() {
+        logger("API keys: none issued");
+        return
+    }
+    let now = Utc::now();
+    let live = keys.iter().filter(|key| key.is_enabled() && !key.is_expired(now)).count();
+    logger(format!(
+        "API keys: {} key{} on file, {} usable",
+        keys.len(),
+        if keys.len() == 1 { "" } else { "s" },
+        live
+    ));
+}
+
+
+/// Every issued key, enabled or not.
+pub fn keys() -> Vec<ApiKey> {
+    KEYS.read().map(|keys| keys.clone()).unwrap_or_default()
+}
+
+
+pub fn find(id: &str) -> Option<ApiKey> {
+    keys().into_iter().find(|key| key.id == id)
+}
+
+
+/// Adds a key, or replaces the one with the same id, and persists the result.
+pub fn upsert(key: ApiKey) -> Result<(), String> {
+    let mut current = keys();
+    match current.iter().position(|existing| existing.id == key.id) {
+        Some(position) => current[position] = key,
+        None => current.push(key)
+    }
+    commit(current)
+}
+
+
+/// Revokes a key. Returns whether it existed.
+pub fn remove(id: &str) -> Result<bool, String> {
+    let mut current = keys();
+    let before = current.len();
+    current.retain(|key| key.id != id);
+    let removed = current.len() != before;
+    commit(current)?;
+    if removed && let Ok(mut used) = LAST_USED.lock() {
+        used.remove(id);
+    }
+    Ok(removed)
+}
+
+
+fn commit(keys: Vec<ApiKey>) -> Result<(), String> {
+    save(&ApiKeyConfig { keys: keys.clone() })?;
+    match KEYS.write() {
+        Ok(mut held) => *held = keys,
+        Err(_) => return Err("api key lock poisoned".into())
+    }
+    Ok(())
+}
+
+
+pub fn last_used() -> HashMap<String, DateTime<Utc>> {
+    LAST_USED.lock().map(|used| used.clone()).unwrap_or_default()
+}
+
+
+fn touch(id: &str) {
+    if let Ok(mut used) = LAST_USED.lock() {
+        used.insert(id.to_string(), Utc::now());
+    }
+}
+
+
+/// Whether a presented credential is shaped like one of our keys rather than
+/// an Octopus authcode.
+pub fn is_key(presented: &str) -> bool {
+    presented.starts_with(KEY_PREFIX)
+}
+
+
+/// Why a presented key was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyRefusal {
+    Unknown,
+    Disabled,
+    Expired,
+    OutOfScope
+}
+
+impl KeyRefusal {
+    fn describe(&self) -> &'static str {
+        match self {
+            KeyRefusal::Unknown => "unknown key",
+            KeyRefusal::Disabled => "key disabled",
+            KeyRefusal::Expired => "key expired",
+            KeyRefusal::OutOfScope => "endpoint outside the key's scopes"
+        }
+    }
+
+    fn error(&self) -> RustopusError {
+        match self {
+            KeyRefusal::OutOfScope => GLOBAL_API_KEY_SCOPE_ERROR,
+            _ => GLOBAL_API_KEY_ERROR
+        }
+    }
+}
+
+
+/// The key behind a presented value, if it may call `endpoint` now.
+fn admit(keys: &[ApiKey], presented: &str, endpoint: &str, now: DateTime<Utc>) -> Result<ApiKey, KeyRefusal> {
+    let hash = hash_secret(presented);
+    let key = keys.iter().find(|key| key.key_hash == hash).ok_or(KeyRefusal::Unknown)?;
+    if !key.is_enabled() {
+        return Err(KeyRefusal::Disabled)
+    }
+    if key.is_expired(now) {
+        return Err(KeyRefusal::Expired)
+    }
+    if !key.covers(endpoint) {
+        return Err(KeyRefusal::OutOfScope)
+    }
+    Ok(key.clone())
+}
+
+
+/// The key a request was admitted with, left on the request for the rate
+/// limiter. Carries nothing secret.
+#[derive(Debug, Clone)]
+pub struct KeyUse {
+    pub id: String,
+    pub label: String,
+    pub per_minute: Option<f64>,
+    pub daily_quota: Option<u64>
+}
+
+
+/// The request's query with the credential parameters dropped and the key's
+/// own `url` and `pid` in place of the caller's. A key with a url also drops
+/// the caller's `tenant`, which would otherwise pick a url of its own.
+fn rewrite_query(query: &str, key: &ApiKey) -> String {
+    let replaced = |name: &str| match name {
+        "authcode" | "auth" => true,
+        "url" | "tenant" => key.url.is_some(),
+        "pid" => key.pid.is_some(),
+        _ => false
+    };
+    let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+    for (name, value) in url::form_urlencoded::parse(query.as_bytes()) {
+        if !replaced(&name) {
+            serializer.append_pair(&name, &value);
+        }
+    }
+    if let Some(url) = &key.url {
+        serializer.append_pair("url", url);
+    }
+    if let Some(pid) = key.pid {
+        serializer.append_pair("pid", &pid.to_string());
+    }
+    serializer.finish()
+}
+
+
+/// Swaps the key for the credentials it stands in for: the authcode goes into
+/// `X-Authcode`, which `get_auth` reads first, and the key leaves the request.
+fn substitute(request: &mut ServiceRequest, key: &ApiKey) -> Result<(), String> {
+    let query = rewrite_query(request.query_string(), key);
+    let target = if query.is_empty() {
+        request.path().to_string()
+    } else {
+        format!("{}?{}", request.path(), query)
+    };
+    let uri: Uri = target.parse().map_err(|error| format!("cannot rebuild the request uri: {}", error))?;
+    let value = HeaderValue::from_str(&key.authcode).map_err(|_| "the mapped authcode is not a valid header value".to_string())?;
+
+    let headers = request.headers_mut();
+    headers.remove(AUTHORIZATION);
+    headers.insert(HeaderName::from_static("x-authcode"), value);
+    request.match_info_mut().get_mut().update(&uri);
+    request.head_mut().uri = uri;
+    Ok(())
+}
+
+
+/// `401`/`403` with the house error shape.
+fn refused(refusal: KeyRefusal) -> HttpResponse {
+    let error = refusal.error();
+    let mut response = match refusal {
+        KeyRefusal::OutOfScope => HttpResponse::Forbidden(),
+        _ => HttpResponse::Unauthorized()
+    };
+    response
+        .content_type("application/xml")
+        .body(format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?><error><code>{}</code><description>{}</description></error>",
+            error.code, error.description
+        ))
+}
+
+
+/// The middleware. Wraps the whole app inside the blocklist, so an address
+/// already blocked never gets as far as a key lookup, and outside the rate
+/// limiter, which needs to know which key to charge.
+pub async fn guard(
+    mut request: ServiceRequest,
+    next: Next<impl MessageBody + 'static>
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let Some(endpoint) = endpoint_of(request.path()) else {
+        return next.call(request).await.map(ServiceResponse::map_into_boxed_body)
+    };
+    let Some(presented) = request_authcode(&request).filter(|presented| is_key(presented)) else {
+        return next.call(request).await.map(ServiceResponse::map_into_boxed_body)
+    };
+
+    let (_, display) = request_addresses(&request);
+    let address = display.unwrap_or_else(|| "unknown IP address".into());
+
+    let verdict = match KEYS.read() {
+        Ok(keys) => admit(&keys, &presented, endpoint, Utc::now()),
+        Err(_) => Err(KeyRefusal::Unknown)
+    };
+    let key = match verdict {
+        Ok(key) => key,
+        Err(refusal) => {
+            let error = refusal.error();
+            elog_with_ip(&address, format!(
+                "{}: {} — {} on {} {}",
+                error.code, error.description, refusal.describe(), request.method(), request.path()
+            ));
+            return Ok(request.into_response(refused(refusal)))
+        }
+    };
+
+    // The authcode rules apply to what the key stands in for, exactly as they
+    // do behind an OAuth bearer token.
+    if let Some(denied) = blocklist::refuse_if_blocked(&key.authcode, Some(&address), Surface::Rest) {
+        return Ok(request.into_response(denied))
+    }
+
+    if let Err(error) = substitute(&mut request, &key) {
+        elog_with_ip(&address, format!("API KEY: '{}' could not be applied: {}", key.label, error));
+        return Ok(request.into_response(HttpResponse::InternalServerError().body("Something went wrong...")))
+    }
+
+    touch(&key.id);
+    log_with_ip(&address, format!("API KEY: '{}' ({}) accepted on {}", key.label, key.hint, endpoint));
+    request.extensions_mut().insert(KeyUse {
+        id: key.id.clone(),
+        label: key.label.clone(),
+        per_minute: key.per_minute,
+        daily_quota: key.daily_quota
+    });
+    next.call(request).await.map(ServiceResponse::map_into_boxed_body)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn issued(label: &str) -> (ApiKey, String) {
+        ApiKey::issue(label.into(), "FFD3ABCDEF120E37".into())
+    }
+
+    #[test]
+    fn an_issued_key_is_stored_as_its_hash_only() {
+        let (record, key) = issued("warehouse sync");
+        assert!(is_key(&key));
+        assert!(crate::service::authcode::is_well_formed(&key));
+        let text = toml::to_string(&ApiKeyConfig { keys: vec![record.clone()] }).expect("serializes");
+        assert!(!text.contains(&key));
+        assert!(key.starts_with(record.hint.trim_end_matches('…')));
+    }
+
+    #[test]
+    fn only_a_live_key_in_scope_is_admitted() {
+        let now = Utc::now();
+        let (mut record, key) = issued("a");
+        record.scopes = vec!["get-price".into()];
+        let keys = vec![record.clone()];
+
+        assert_eq!(admit(&keys, &key, "get-price", now).map(|key| key.id), Ok(record.id.clone()));
+        assert_eq!(admit(&keys, &key, "get-bulk", now).err(), Some(KeyRefusal::OutOfScope));
+        assert_eq!(admit(&keys, "rk-guess", "get-price", now).err(), Some(KeyRefusal::Unknown));
+
+        let mut expired = record.clone();
+        expired.expires_at = Some(now - chrono::Duration::seconds(1));
+        assert_eq!(admit(&[expired], &key, "get-price", now).err(), Some(KeyRefusal::Expired));
+
+        let mut disabled = record;
+        disabled.enabled = Some(false);
+        assert_eq!(admit(&[disabled], &key, "get-price", now).err(), Some(KeyRefusal::Disabled));
+    }
+
+    #[test]
+    fn scopes_are_checked_and_aliases_folded() {
+        let parsed = parse_scopes(&["get-products".into(), "GET-PRODUCT".into(), " post-order ".into()]).expect("valid");
+        assert_eq!(parsed, vec!["get-product".to_string(), "post-order".to_string()]);
+        assert!(parse_scopes(&["get-everything".into()]).is_err());
+    }
+
+    #[test]
+    fn the_query_loses_the_key_and_gains_the_mapping() {
+        let (mut record, _) = issued("a");
+        let query = "authcode=rk-abc&url=http%3A%2F%2Fother.test%2F&pid=1&fields=no%2Cprice";
+
+        // Nothing mapped: the caller's url and pid stand.
+        assert_eq!(rewrite_query(query, &record), "url=http%3A%2F%2Fother.test%2F&pid=1&fields=no%2Cprice");
+
+        record.url = Some("https://orink.hu/services/vision.asmx".into());
+        record.pid = Some(42);
+        assert_eq!(
+            rewrite_query(query, &record),
+            "fields=no%2Cprice&url=https%3A%2F%2Forink.hu%2Fservices%2Fvision.asmx&pid=42"
+        );
+        // A mapped url is not escaped through a tenant either.
+        assert_eq!(
+            rewrite_query("tenant=other&fields=no", &record),
+            "fields=no&url=https%3A%2F%2Forink.hu%2Fservices%2Fvision.asmx&pid=42"
+        );
+    }
+}
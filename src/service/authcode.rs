@@ -1,6 +1,70 @@
 // Authcode validation
 
+use actix_web::http::header::{AUTHORIZATION, HeaderMap};
+
+use crate::service::mcp::AUTHCODE_HEADER;
+
 pub fn is_well_formed(authcode: &str) -> bool {
     !authcode.is_empty()
         && authcode.chars().all(|character| character.is_ascii_alphanumeric() || character == '-')
-}
\ No newline at end of file
+}
+
+
+/// The code in an `X-Authcode` header, the spelling `/mcp` has always taken.
+pub fn from_header(headers: &HeaderMap) -> Option<String> {
+    headers.get(AUTHCODE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+
+/// The code in an `Authorization: Bearer` header. Only the REST endpoints read
+/// it as an authcode: on `/mcp` a bearer is an OAuth access token.
+pub fn from_bearer(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?.trim();
+    let (scheme, token) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Bearer") {
+        return None
+    }
+    Some(token.trim().to_string()).filter(|token| !token.is_empty())
+}
+
+
+/// The authcode a REST request carries in its headers, `X-Authcode` first.
+pub fn from_headers(headers: &HeaderMap) -> Option<String> {
+    from_header(headers).or_else(|| from_bearer(headers))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(HeaderName::from_static(name), HeaderValue::from_static(value));
+        }
+        map
+    }
+
+    #[test]
+    fn either_header_carries_the_code() {
+        assert_eq!(from_headers(&headers(&[("x-authcode", " FFD3-0E37 ")])).as_deref(), Some("FFD3-0E37"));
+        assert_eq!(from_headers(&headers(&[("authorization", "bearer FFD3-0E37")])).as_deref(), Some("FFD3-0E37"));
+        // Both sent: the dedicated header wins.
+        let both = headers(&[("x-authcode", "ONE"), ("authorization", "Bearer TWO")]);
+        assert_eq!(from_headers(&both).as_deref(), Some("ONE"));
+    }
+
+    #[test]
+    fn other_schemes_and_empty_values_carry_nothing() {
+        assert!(from_headers(&headers(&[("authorization", "Basic dXNlcjpwYXNz")])).is_none());
+        assert!(from_headers(&headers(&[("authorization", "Bearer ")])).is_none());
+        assert!(from_headers(&headers(&[("x-authcode", "  ")])).is_none());
+        assert!(from_headers(&HeaderMap::new()).is_none());
+    }
+}
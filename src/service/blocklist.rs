@@ -26,6 +26,14 @@
 //! rule set without a line of per-route plumbing. `/admin` is deliberately
 //! exempt — an administrator who blocks their own address must not lock
 //! themselves out of the page that would undo it.
+//!
+//! ## Allow rules
+//!
+//! A rule may allow instead of block. Any active allow rule matching a request
+//! lets it through whatever block rules also match, so a partner's office can
+//! be let out of a blocked hosting range without splitting the range into the
+//! pieces around it. Abuse detection (`service/abuse`) never blocks an allowed
+//! address either.
 
 use std::collections::HashMap;
 use std::net::IpAddr;
@@ -44,12 +52,16 @@ use serde::{Deserialize, Serialize};
 use crate::{
     global::errors::GLOBAL_BLOCKED_ERROR,
     service::{
+        authcode,
+        config::get_settings,
         log::{elog_with_ip, elogger, logger},
         mcp::{
             cache::{fingerprint, hash_authcode},
             mask_authcode
         },
-        path::get_current_or_root_dir
+        ipv4::{IpNetwork, client_address},
+        path::get_current_or_root_dir,
+        state
     }
 };
 
@@ -82,6 +94,26 @@ impl BlockKind {
 }
 
 
+/// Whether a matching rule refuses a request or lets it through.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    #[default]
+    Block,
+    /// Wins over every block rule that also matches.
+    Allow
+}
+
+impl RuleAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RuleAction::Block => "block",
+            RuleAction::Allow => "allow"
+        }
+    }
+}
+
+
 /// Which surface a rule applies to.
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -135,6 +167,9 @@ fn surface_of(path: &str) -> Surface {
 /// One blocking rule.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockRule {
+    /// Absent in files written before allow rules existed, which is a block.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<RuleAction>,
     pub kind: BlockKind,
     /// For an IP rule: the address or CIDR range, verbatim.
     /// For an authcode rule: the **SHA-256 hex of the code** — never the code.
@@ -150,7 +185,15 @@ pub struct BlockRule {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub enabled: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub created_at: Option<DateTime<Utc>>
+    pub created_at: Option<DateTime<Utc>>,
+    /// When the rule stops matching. `None` is permanent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Set on rules created by abuse detection (`service/abuse`) rather than by
+    /// an operator. Kept when a rule is made permanent, so its origin stays on
+    /// record.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auto: Option<bool>
 }
 
 impl BlockRule {
@@ -161,13 +204,16 @@ impl BlockRule {
             return Err(format!("'{}' is not an IP address or CIDR range", value))
         }
         Ok(Self {
+            action: None,
             kind: BlockKind::Ip,
             label: value.clone(),
             value,
             note,
             scope,
             enabled: Some(true),
-            created_at: Some(Utc::now())
+            created_at: Some(Utc::now()),
+            expires_at: None,
+            auto: None
         })
     }
 
@@ -179,13 +225,16 @@ impl BlockRule {
             return Err("authcode is required".into())
         }
         Ok(Self {
+            action: None,
             kind: BlockKind::Authcode,
             value: hash_hex(code),
             label: mask_authcode(code),
             note,
             scope,
             enabled: Some(true),
-            created_at: Some(Utc::now())
+            created_at: Some(Utc::now()),
+            expires_at: None,
+            auto: None
         })
     }
 
@@ -197,6 +246,23 @@ impl BlockRule {
         self.scope.unwrap_or_default()
     }
 
+    pub fn action(&self) -> RuleAction {
+        self.action.unwrap_or_default()
+    }
+
+    pub fn is_auto(&self) -> bool {
+        self.auto.unwrap_or(false)
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+
+    /// Enabled and not yet expired: the rule is refusing requests right now.
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.is_enabled() && !self.is_expired(now)
+    }
+
     /// Stable identifier derived from what the rule matches on. Safe in a URL,
     /// a log line or a dashboard row: for an authcode rule the seed is already
     /// a hash, so this reveals nothing the label does not.
@@ -209,7 +275,7 @@ impl BlockRule {
 
 /// Full SHA-256 hex of an authcode. Uses the same one-way hash as the MCP cache
 /// key rather than a second construction of its own.
-fn hash_hex(authcode: &str) -> String {
+pub fn hash_hex(authcode: &str) -> String {
     hash_authcode(authcode).iter()
         .map(|byte| format!("{:02x}", byte))
         .collect()
@@ -226,8 +292,7 @@ struct Compiled {
 
 #[derive(Debug, Clone)]
 enum Matcher {
-    Address(IpAddr),
-    Network(IpAddr, u32),
+    Network(IpNetwork),
     AuthHash(String),
     /// A rule that cannot be parsed. Kept visible in the dashboard rather than
     /// dropped silently, but it never matches anything.
@@ -253,74 +318,20 @@ fn compile(rule: &BlockRule) -> Compiled {
 }
 
 
-/// Parses `203.0.113.7` or `203.0.113.0/24` (v6 equally).
 fn compile_ip(value: &str) -> Matcher {
-    let value = value.trim();
-    let Some((address, prefix)) = value.split_once('/') else {
-        return match value.parse::<IpAddr>() {
-            Ok(address) => Matcher::Address(normalize(address)),
-            Err(_) => Matcher::Invalid
-        }
-    };
-
-    let Ok(address) = address.trim().parse::<IpAddr>() else {
-        return Matcher::Invalid
-    };
-    let Ok(prefix) = prefix.trim().parse::<u32>() else {
-        return Matcher::Invalid
-    };
-    let bits = if address.is_ipv4() { 32 } else { 128 };
-    if prefix > bits {
-        return Matcher::Invalid
-    }
-    Matcher::Network(address, prefix)
+    IpNetwork::parse(value).map_or(Matcher::Invalid, Matcher::Network)
 }
 
 
-/// Collapses an IPv4-mapped IPv6 address (`::ffff:203.0.113.7`) to its IPv4 form,
-/// so a rule written the way an operator reads the address in a log still
-/// matches when the socket reports the mapped shape.
-fn normalize(address: IpAddr) -> IpAddr {
-    match address {
-        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(IpAddr::V6(v6)),
-        other => other
-    }
-}
-
-
-/// Whether `candidate` falls inside `network/prefix`.
-fn in_network(candidate: IpAddr, network: IpAddr, prefix: u32) -> bool {
-    let (left, right): (Vec<u8>, Vec<u8>) = match (normalize(candidate), normalize(network)) {
-        (IpAddr::V4(a), IpAddr::V4(b)) => (a.octets().into(), b.octets().into()),
-        (IpAddr::V6(a), IpAddr::V6(b)) => (a.octets().into(), b.octets().into()),
-        // A v4 rule never matches a v6 caller, or the other way round.
-        _ => return false
-    };
-
-    let mut remaining = prefix;
-    for (a, b) in left.iter().zip(right.iter()) {
-        if remaining == 0 {
-            return true
-        }
-        if remaining >= 8 {
-            if a != b {
-                return false
-            }
-            remaining -= 8;
-            continue
-        }
-        let mask = 0xFFu8 << (8 - remaining);
-        return a & mask == b & mask
+impl Compiled {
+    /// Active, and written for the surface the request arrived on.
+    fn applies(&self, surface: Surface, now: DateTime<Utc>) -> bool {
+        self.rule.is_active(now) && self.rule.scope().covers(surface)
     }
-    true
-}
 
-
-impl Compiled {
     fn matches_ip(&self, candidate: IpAddr) -> bool {
         match &self.matcher {
-            Matcher::Address(address) => normalize(candidate) == *address,
-            Matcher::Network(network, prefix) => in_network(candidate, *network, *prefix),
+            Matcher::Network(network) => network.contains(candidate),
             _ => false
         }
     }
@@ -358,6 +369,10 @@ static RULES: Lazy<RwLock<Vec<Compiled>>> = Lazy::new(|| RwLock::new(compile_all
 
 /// How many rules are currently enforced.
 ///
+/// A rule that expires is still counted until the next edit or
+/// [`purge_expired`]; it only costs the slow path, never a wrong answer, since
+/// [`check`] tests the expiry itself.
+///
 /// Read before anything else on every request, so an instance with no rules
 /// pays one relaxed atomic load and never touches the lock, the query string or
 /// the headers. [`init`] primes it at startup; without that call this would read
@@ -367,7 +382,11 @@ static ARMED: AtomicUsize = AtomicUsize::new(0);
 
 fn compile_all(rules: &[BlockRule]) -> Vec<Compiled> {
     let compiled: Vec<Compiled> = rules.iter().map(compile).collect();
-    ARMED.store(compiled.iter().filter(|entry| entry.rule.is_enabled()).count(), Ordering::Relaxed);
+    let now = Utc::now();
+    // Allow rules alone refuse nothing, so they do not arm the middleware.
+    ARMED.store(compiled.iter()
+        .filter(|entry| entry.rule.action() == RuleAction::Block && entry.rule.is_active(now))
+        .count(), Ordering::Relaxed);
     compiled
 }
 
@@ -381,51 +400,67 @@ pub fn get_blocklist_path() -> PathBuf {
 }
 
 
-/// Reads `blocklist.toml`, or an empty rule set when it is absent or unreadable.
-/// A missing file is the normal case: nobody has been blocked yet.
+/// The stored rule set, or an empty one when it cannot be read. From
+/// `blocklist.toml` or the database, whichever `[storage] backend` names — see
+/// `service/state`.
 pub fn load() -> BlocklistConfig {
+    state::store().load_blocklist().unwrap_or_else(|error| {
+        elogger(format!("Blocklist: {}", error));
+        BlocklistConfig::default()
+    })
+}
+
+
+/// Persists the rule set to the configured backend.
+pub fn save(config: &BlocklistConfig) -> Result<(), String> {
+    state::store().save_blocklist(config)
+}
+
+
+/// Reads `blocklist.toml`. A missing file is the normal case — nobody has been
+/// blocked yet — and reads as an empty rule set.
+pub fn read_file() -> Result<BlocklistConfig, String> {
     let path = get_blocklist_path();
     if !path.is_file() {
-        return BlocklistConfig::default()
-    }
-    match std::fs::read_to_string(&path) {
-        Ok(content) => match toml::from_str::<BlocklistConfig>(&content) {
-            Ok(config) => config,
-            Err(error) => {
-                elogger(format!("Blocklist: cannot parse '{:?}': {}", path, error));
-                BlocklistConfig::default()
-            }
-        },
-        Err(error) => {
-            elogger(format!("Blocklist: cannot read '{:?}': {}", path, error));
-            BlocklistConfig::default()
-        }
+        return Ok(BlocklistConfig::default())
     }
+    let content = std::fs::read_to_string(&path)
+        .map_err(|error| format!("cannot read '{:?}': {}", path, error))?;
+    toml::from_str::<BlocklistConfig>(&content)
+        .map_err(|error| format!("cannot parse '{:?}': {}", path, error))
 }
 
 
 /// Writes `blocklist.toml` through a temp file and a rename, owner-only.
-pub fn save(config: &BlocklistConfig) -> Result<(), String> {
+pub fn write_file(config: &BlocklistConfig) -> Result<(), String> {
     let path = get_blocklist_path();
+    let content = render_toml(config)?;
+
+    let temporary = path.with_extension("toml.tmp");
+    std::fs::write(&temporary, content).map_err(|error| error.to_string())?;
+    restrict_permissions(&temporary);
+    std::fs::rename(&temporary, &path).map_err(|error| error.to_string())?;
+    restrict_permissions(&path);
+    Ok(())
+}
+
+
+/// `blocklist.toml` as written to disk, header included. Also what a TOML export
+/// downloads, so an export can be dropped in place of the file as it is.
+fn render_toml(config: &BlocklistConfig) -> Result<String, String> {
     let body = toml::to_string_pretty(config).map_err(|error| error.to_string())?;
-    let content = format!(
+    Ok(format!(
         "# Rustopus access blocklist.\n\
          #\n\
-         # Requests matching an enabled rule are refused before they reach a route.\n\
+         # Requests matching an enabled block rule are refused before they reach a\n\
+         # route, unless an allow rule matches them too.\n\
          # Authcode rules hold the SHA-256 of the code, never the code itself, so\n\
          # this file is not a credential store — but nothing except this server\n\
          # needs to read it, so it is written 0600 all the same.\n\
          #\n\
          # Managed by the /admin dashboard; hand edits are picked up on restart.\n\n{}",
         body
-    );
-
-    let temporary = path.with_extension("toml.tmp");
-    std::fs::write(&temporary, content).map_err(|error| error.to_string())?;
-    restrict_permissions(&temporary);
-    std::fs::rename(&temporary, &path).map_err(|error| error.to_string())?;
-    restrict_permissions(&path);
-    Ok(())
+    ))
 }
 
 
@@ -449,8 +484,12 @@ fn restrict_permissions(path: &Path) {
 /// Loads the rule set and reports it. Called from `main.rs` at startup so the
 /// armed count is primed before the first request arrives.
 pub fn init() {
+    if let Err(error) = purge_expired() {
+        elogger(format!("Blocklist: cannot drop expired automatic rules: {}", error));
+    }
     let rules = rules();
-    let active = rules.iter().filter(|rule| rule.is_enabled()).count();
+    let now = Utc::now();
+    let active = rules.iter().filter(|rule| rule.is_active(now)).count();
     if rules.is_empty() {
         logger("Blocklist: no rules configured");
         return
@@ -517,6 +556,22 @@ pub fn remove(id: &str) -> Result<bool, String> {
 }
 
 
+/// Drops the automatic rules whose expiry has passed. An operator's own rule
+/// with an expiry is kept, shown as expired, until they remove it: it may be
+/// something they mean to renew.
+pub fn purge_expired() -> Result<usize, String> {
+    let now = Utc::now();
+    let mut current = rules();
+    let before = current.len();
+    current.retain(|rule| !(rule.is_auto() && rule.is_expired(now)));
+    let purged = before - current.len();
+    if purged > 0 {
+        commit(current)?;
+    }
+    Ok(purged)
+}
+
+
 /// Persists a new rule set and swaps in its compiled form.
 fn commit(rules: Vec<BlockRule>) -> Result<(), String> {
     save(&BlocklistConfig { rules: rules.clone() })?;
@@ -529,6 +584,234 @@ fn commit(rules: Vec<BlockRule>) -> Result<(), String> {
 }
 
 
+/// The two shapes a rule set travels in through `/admin`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleFormat {
+    /// Semicolon-delimited like every other CSV this server writes; a comma
+    /// header is accepted on import too.
+    Csv,
+    /// The `blocklist.toml` layout.
+    Toml
+}
+
+impl RuleFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "csv" => Some(RuleFormat::Csv),
+            "toml" => Some(RuleFormat::Toml),
+            _ => None
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            RuleFormat::Csv => "text/csv",
+            RuleFormat::Toml => "application/toml"
+        }
+    }
+
+    pub fn filename(&self) -> &'static str {
+        match self {
+            RuleFormat::Csv => "blocklist.csv",
+            RuleFormat::Toml => "blocklist.toml"
+        }
+    }
+}
+
+
+/// One rule as a CSV row. [`BlockRule`] skips its empty fields when serialized,
+/// which a CSV writer cannot take: every row needs every column.
+#[derive(Debug, Serialize, Deserialize)]
+struct RuleRow {
+    #[serde(default)]
+    action: Option<RuleAction>,
+    kind: BlockKind,
+    value: String,
+    #[serde(default)]
+    label: Option<String>,
+    #[serde(default)]
+    note: Option<String>,
+    #[serde(default)]
+    scope: Option<BlockScope>,
+    #[serde(default)]
+    enabled: Option<bool>,
+    #[serde(default)]
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    auto: Option<bool>,
+    #[serde(default)]
+    created_at: Option<DateTime<Utc>>
+}
+
+impl From<BlockRule> for RuleRow {
+    fn from(rule: BlockRule) -> Self {
+        Self {
+            action: Some(rule.action()),
+            scope: Some(rule.scope()),
+            enabled: Some(rule.is_enabled()),
+            kind: rule.kind,
+            value: rule.value,
+            label: Some(rule.label),
+            note: rule.note,
+            expires_at: rule.expires_at,
+            auto: rule.auto,
+            created_at: rule.created_at
+        }
+    }
+}
+
+impl From<RuleRow> for BlockRule {
+    fn from(row: RuleRow) -> Self {
+        Self {
+            action: row.action,
+            kind: row.kind,
+            value: row.value,
+            label: row.label.unwrap_or_default(),
+            note: row.note.filter(|note| !note.trim().is_empty()),
+            scope: row.scope,
+            enabled: row.enabled,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            auto: row.auto
+        }
+    }
+}
+
+
+/// The whole rule set in the given format.
+pub fn export(format: RuleFormat) -> Result<String, String> {
+    let rules = rules();
+    match format {
+        RuleFormat::Toml => render_toml(&BlocklistConfig { rules }),
+        RuleFormat::Csv => render_csv(rules)
+    }
+}
+
+
+fn render_csv(rules: Vec<BlockRule>) -> Result<String, String> {
+    let mut writer = csv::WriterBuilder::new().delimiter(b';').from_writer(vec![]);
+    for rule in rules {
+        writer.serialize(RuleRow::from(rule)).map_err(|error| error.to_string())?;
+    }
+    let data = writer.into_inner().map_err(|error| error.to_string())?;
+    String::from_utf8(data).map_err(|error| error.to_string())
+}
+
+
+/// Checks an imported rule the way the dashboard form would have.
+///
+/// An authcode rule's `value` is taken as the stored hash when it is shaped like
+/// one — which is what an export holds — and otherwise as a code, hashed here
+/// and not retained, exactly as [`BlockRule::authcode`] does.
+fn admit(mut rule: BlockRule) -> Result<BlockRule, String> {
+    match rule.kind {
+        BlockKind::Ip => {
+            rule.value = rule.value.trim().to_string();
+            if matches!(compile_ip(&rule.value), Matcher::Invalid) {
+                return Err(format!("'{}' is not an IP address or CIDR range", rule.value))
+            }
+            if rule.label.trim().is_empty() {
+                rule.label = rule.value.clone();
+            }
+        }
+        BlockKind::Authcode => {
+            let value = rule.value.trim();
+            if value.is_empty() {
+                return Err("authcode is required".into())
+            }
+            if value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit()) {
+                rule.value = value.to_lowercase();
+                if rule.label.trim().is_empty() {
+                    // Nothing to mask: the code never reached this server.
+                    rule.label = "(imported)".into();
+                }
+            } else {
+                rule.label = mask_authcode(value);
+                rule.value = hash_hex(value);
+            }
+        }
+    }
+    rule.created_at = rule.created_at.or(Some(Utc::now()));
+    Ok(rule)
+}
+
+
+/// Parses an import without applying it. All or nothing: one bad row refuses
+/// the file, naming the row, rather than loading the rules around it.
+fn parse_import(text: &str, format: RuleFormat) -> Result<Vec<BlockRule>, String> {
+    let parsed: Vec<BlockRule> = match format {
+        RuleFormat::Toml => toml::from_str::<BlocklistConfig>(text)
+            .map_err(|error| format!("not a blocklist TOML file: {}", error))?
+            .rules,
+        RuleFormat::Csv => {
+            let header = text.lines().next().unwrap_or_default();
+            let delimiter = if header.contains(';') { b';' } else { b',' };
+            let mut reader = csv::ReaderBuilder::new()
+                .delimiter(delimiter)
+                .trim(csv::Trim::All)
+                .from_reader(text.as_bytes());
+            let mut rules = Vec::new();
+            for (index, row) in reader.deserialize::<RuleRow>().enumerate() {
+                // Row 1 is the header.
+                let row = row.map_err(|error| format!("row {}: {}", index + 2, error))?;
+                rules.push(row.into());
+            }
+            rules
+        }
+    };
+
+    parsed.into_iter()
+        .enumerate()
+        .map(|(index, rule)| admit(rule).map_err(|error| format!("rule {}: {}", index + 1, error)))
+        .collect()
+}
+
+
+/// What an import changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize
+}
+
+
+/// Loads rules from an export, or from a hand-written file in the same shape.
+///
+/// Merged by default: a rule matching the same thing as one on file replaces
+/// it, keeping the original creation time like an edit in the dashboard does.
+/// With `replace` the file becomes the whole rule set.
+pub fn import(text: &str, format: RuleFormat, replace: bool) -> Result<ImportSummary, String> {
+    let incoming = parse_import(text, format)?;
+    let mut current = rules();
+    let mut summary = ImportSummary::default();
+
+    if replace {
+        let ids: Vec<String> = incoming.iter().map(BlockRule::id).collect();
+        let before = current.len();
+        current.retain(|rule| ids.contains(&rule.id()));
+        summary.removed = before - current.len();
+    }
+
+    for rule in incoming {
+        let id = rule.id();
+        match current.iter().position(|existing| existing.id() == id) {
+            Some(position) => {
+                let created_at = current[position].created_at.or(rule.created_at);
+                current[position] = BlockRule { created_at, ..rule };
+                summary.updated += 1;
+            }
+            None => {
+                current.push(rule);
+                summary.added += 1;
+            }
+        }
+    }
+    commit(current)?;
+    Ok(summary)
+}
+
+
 /// What matched, for the log line and the counter.
 #[derive(Debug, Clone)]
 pub struct BlockMatch {
@@ -540,31 +823,67 @@ pub struct BlockMatch {
 
 /// Tests one request's identity against the rule set.
 ///
-/// `addresses` holds every address the request can be attributed to — both the
-/// forwarded one and the peer — because a client talking to this server directly
-/// could otherwise send a fabricated `X-Forwarded-For` and walk past its own
-/// block.
+/// `addresses` comes from [`request_addresses`]: a forwarded address counts only
+/// when a trusted proxy reported it, so a client talking to this server directly
+/// cannot walk past its own block with a fabricated `X-Forwarded-For`.
 pub fn check(addresses: &[IpAddr], authcode: Option<&str>, surface: Surface) -> Option<BlockMatch> {
     let rules = RULES.read().ok()?;
     let hash = authcode.map(hash_hex);
+    evaluate(&rules, addresses, hash.as_deref(), surface, Utc::now())
+}
 
-    for entry in rules.iter() {
-        if !entry.rule.is_enabled() || !entry.rule.scope().covers(surface) {
-            continue
-        }
-        let hit = match entry.rule.kind {
+
+/// Whether an allow rule covers this identity, for callers that decide
+/// something other than a refusal — abuse detection, which must not block an
+/// address an operator has let in.
+pub fn is_allowed(addresses: &[IpAddr], authcode: Option<&str>, surface: Surface) -> bool {
+    let Ok(rules) = RULES.read() else {
+        return false
+    };
+    let hash = authcode.map(hash_hex);
+    allowed(&rules, addresses, hash.as_deref(), surface, Utc::now())
+}
+
+
+/// The first active block rule that matches, unless an allow rule does.
+fn evaluate(rules: &[Compiled], addresses: &[IpAddr], hash: Option<&str>, surface: Surface, now: DateTime<Utc>) -> Option<BlockMatch> {
+    let hit = rules.iter()
+        .filter(|entry| entry.rule.action() == RuleAction::Block && entry.applies(surface, now))
+        .find(|entry| match entry.rule.kind {
             BlockKind::Ip => addresses.iter().any(|address| entry.matches_ip(*address)),
-            BlockKind::Authcode => hash.as_deref().is_some_and(|hash| entry.matches_authcode(hash))
-        };
-        if hit {
-            return Some(BlockMatch {
-                id: entry.rule.id(),
-                kind: entry.rule.kind,
-                label: entry.rule.label.clone()
-            })
-        }
+            BlockKind::Authcode => hash.is_some_and(|hash| entry.matches_authcode(hash))
+        })?;
+
+    if allowed(rules, addresses, hash, surface, now) {
+        return None
+    }
+    Some(BlockMatch {
+        id: hit.rule.id(),
+        kind: hit.rule.kind,
+        label: hit.rule.label.clone()
+    })
+}
+
+
+/// Whether an active allow rule covers the request.
+///
+/// A block matches on *any* attributed address; an allow has to cover *every*
+/// one, so an address that is allowed cannot vouch for one that is not.
+fn allowed(rules: &[Compiled], addresses: &[IpAddr], hash: Option<&str>, surface: Surface, now: DateTime<Utc>) -> bool {
+    let allows: Vec<&Compiled> = rules.iter()
+        .filter(|entry| entry.rule.action() == RuleAction::Allow && entry.applies(surface, now))
+        .collect();
+    if allows.is_empty() {
+        return false
     }
-    None
+
+    if let Some(hash) = hash
+        && allows.iter().any(|entry| entry.matches_authcode(hash)) {
+            return true
+    }
+
+    !addresses.is_empty() && addresses.iter()
+        .all(|address| allows.iter().any(|entry| entry.matches_ip(*address)))
 }
 
 
@@ -578,51 +897,35 @@ fn record_hit(id: &str, address: Option<&str>) {
 }
 
 
-/// Addresses a request can be attributed to: the first `X-Forwarded-For` hop
-/// (what the reverse proxy saw) and the socket peer.
-fn request_addresses(request: &ServiceRequest) -> (Vec<IpAddr>, Option<String>) {
-    let mut addresses = Vec::new();
-    let mut display = None;
-
-    if let Some(forwarded) = request.headers()
-        .get("X-Forwarded-For")
-        .and_then(|value| value.to_str().ok())
-        .and_then(|value| value.split(',').next())
-        .map(str::trim)
-        .filter(|value| !value.is_empty()) {
-            display = Some(forwarded.to_string());
-            if let Ok(address) = forwarded.parse::<IpAddr>() {
-                addresses.push(address);
-            }
-    }
-
-    if let Some(peer) = request.peer_addr() {
-        let address = peer.ip();
-        if !addresses.contains(&address) {
-            addresses.push(address);
-        }
-        if display.is_none() {
-            display = Some(address.to_string());
-        }
+/// Addresses a request can be attributed to, resolved through the trusted
+/// proxies (see `service/ipv4`), and the one to show for it.
+///
+/// A slice because [`check`] takes one: a single resolved address today, but a
+/// rule set is tested the same way however many a request carries.
+pub fn request_addresses(request: &ServiceRequest) -> (Vec<IpAddr>, Option<String>) {
+    match client_address(request.peer_addr(), request.headers()) {
+        Some(address) => (vec![address], Some(address.to_string())),
+        None => (Vec::new(), None)
     }
-
-    (addresses, display)
 }
 
 
-/// The authcode a request presents: the MCP header, or either spelling of the
-/// REST query parameter.
-fn request_authcode(request: &ServiceRequest) -> Option<String> {
-    if let Some(header) = request.headers()
-        .get(crate::service::mcp::AUTHCODE_HEADER)
-        .and_then(|value| value.to_str().ok())
-        .map(str::trim)
-        .filter(|value| !value.is_empty()) {
-            return Some(header.to_string())
+/// The authcode a request presents: `X-Authcode`, then — off `/mcp`, where a
+/// bearer is an OAuth token — `Authorization: Bearer`, then either spelling of
+/// the REST query parameter while those are accepted. The same order
+/// [`crate::routes::default::get_auth`] reads them in.
+pub fn request_authcode(request: &ServiceRequest) -> Option<String> {
+    let headers = request.headers();
+    let from_headers = match surface_of(request.path()) {
+        Surface::Mcp => authcode::from_header(headers),
+        Surface::Rest => authcode::from_headers(headers)
+    };
+    if from_headers.is_some() {
+        return from_headers
     }
 
     let query = request.query_string();
-    if query.is_empty() {
+    if query.is_empty() || !get_settings().server.query_credentials() {
         return None
     }
     let parsed = web::Query::<HashMap<String, String>>::from_query(query).ok()?;
@@ -650,9 +953,10 @@ fn forbidden() -> HttpResponse {
 /// Applies the authcode rules to an identity that only became known **after**
 /// the middleware below ran, and returns the refusal to send.
 ///
-/// One caller: the OAuth guard on `/mcp`. A caller presenting a bearer token
-/// sends no `X-Authcode`, so [`request_authcode`] finds nothing and every
-/// authcode rule would silently stop matching on that surface. Hit counting and
+/// Two callers: the OAuth guard on `/mcp` and `service/apikey`. A caller
+/// presenting a bearer token or an API key never shows the authcode behind it,
+/// so [`request_authcode`] finds nothing useful and every authcode rule would
+/// silently stop matching for them. Hit counting and
 /// the log line stay here rather than being reimplemented there, so a rule's
 /// counter means the same thing however the caller was identified.
 pub fn refuse_if_blocked(authcode: &str, address: Option<&str>, surface: Surface) -> Option<HttpResponse> {
@@ -810,6 +1114,20 @@ mod tests {
         assert!(!rule.is_enabled());
     }
 
+    #[test]
+    fn an_expired_rule_is_kept_on_file_but_no_longer_active() {
+        let now = Utc::now();
+        let mut rule = BlockRule::ip("203.0.113.7", None, None).expect("valid");
+        rule.expires_at = Some(now + chrono::Duration::minutes(5));
+        assert!(rule.is_active(now));
+        assert!(!rule.is_active(now + chrono::Duration::minutes(5)));
+        assert!(rule.is_expired(now + chrono::Duration::minutes(6)));
+
+        // Made permanent: no expiry, whatever the clock says.
+        rule.expires_at = None;
+        assert!(rule.is_active(now + chrono::Duration::days(365)));
+    }
+
     #[test]
     fn serialized_rules_round_trip() {
         let config = BlocklistConfig {
@@ -833,4 +1151,96 @@ mod tests {
         let config = toml::from_str::<BlocklistConfig>("").expect("empty parses");
         assert!(config.rules.is_empty());
     }
+
+    fn allow(value: &str) -> BlockRule {
+        BlockRule { action: Some(RuleAction::Allow), ..BlockRule::ip(value, None, None).expect("valid") }
+    }
+
+    // Not `compile_all`, which would arm the live middleware.
+    fn compiled(rules: &[BlockRule]) -> Vec<Compiled> {
+        rules.iter().map(compile).collect()
+    }
+
+    #[test]
+    fn an_allow_rule_wins_over_a_block() {
+        let now = Utc::now();
+        let rules = compiled(&[
+            BlockRule::ip("203.0.113.0/24", None, None).expect("valid"),
+            allow("203.0.113.7")
+        ]);
+        assert!(evaluate(&rules, &[ip("203.0.113.7")], None, Surface::Rest, now).is_none());
+        assert!(evaluate(&rules, &[ip("203.0.113.8")], None, Surface::Rest, now).is_some());
+    }
+
+    #[test]
+    fn an_allowed_address_does_not_vouch_for_a_blocked_one() {
+        let now = Utc::now();
+        let rules = compiled(&[
+            BlockRule::ip("198.51.100.9", None, None).expect("valid"),
+            allow("203.0.113.7")
+        ]);
+        // Two attributed addresses, only one of them allowed.
+        let forged = [ip("203.0.113.7"), ip("198.51.100.9")];
+        assert!(evaluate(&rules, &forged, None, Surface::Rest, now).is_some());
+    }
+
+    #[test]
+    fn an_expired_allow_no_longer_lets_anyone_in() {
+        let now = Utc::now();
+        let mut expired = allow("203.0.113.7");
+        expired.expires_at = Some(now - chrono::Duration::minutes(1));
+        let rules = compiled(&[BlockRule::ip("203.0.113.0/24", None, None).expect("valid"), expired]);
+        assert!(evaluate(&rules, &[ip("203.0.113.7")], None, Surface::Rest, now).is_some());
+    }
+
+    #[test]
+    fn rules_survive_a_csv_round_trip() {
+        let mut timed = BlockRule::ip("203.0.113.0/24", Some("scraper".into()), Some(BlockScope::Rest)).expect("valid");
+        timed.expires_at = Some(Utc::now() + chrono::Duration::days(1));
+        let rules = vec![timed, allow("2001:db8:1::/64"), BlockRule::authcode("FFD3ABCDEF120E37", None, None).expect("valid")];
+
+        let text = render_csv(rules.clone()).expect("renders");
+        assert!(!text.contains("FFD3ABCDEF120E37"));
+
+        let parsed = parse_import(&text, RuleFormat::Csv).expect("parses");
+        assert_eq!(parsed.len(), 3);
+        for (before, after) in rules.iter().zip(&parsed) {
+            assert_eq!(before.id(), after.id());
+            assert_eq!(before.action(), after.action());
+            assert_eq!(before.scope(), after.scope());
+            assert_eq!(before.expires_at, after.expires_at);
+            assert_eq!(before.label, after.label);
+        }
+    }
+
+    #[test]
+    fn rules_survive_a_toml_round_trip() {
+        let rules = vec![allow("203.0.113.7"), BlockRule::authcode("FFD3ABCDEF120E37", None, None).expect("valid")];
+        let text = render_toml(&BlocklistConfig { rules: rules.clone() }).expect("renders");
+        let parsed = parse_import(&text, RuleFormat::Toml).expect("parses");
+        assert_eq!(parsed.iter().map(BlockRule::id).collect::<Vec<_>>(), rules.iter().map(BlockRule::id).collect::<Vec<_>>());
+        assert_eq!(parsed[0].action(), RuleAction::Allow);
+    }
+
+    #[test]
+    fn an_imported_plain_code_is_hashed_and_a_hash_is_kept() {
+        let text = "kind,value\nauthcode,FFD3ABCDEF120E37\n";
+        let parsed = parse_import(text, RuleFormat::Csv).expect("parses");
+        assert_eq!(parsed[0].value, hash_hex("FFD3ABCDEF120E37"));
+        assert_eq!(parsed[0].label, "FFD3…0E37");
+
+        let text = format!("kind,value\nauthcode,{}\n", hash_hex("FFD3ABCDEF120E37"));
+        let parsed = parse_import(&text, RuleFormat::Csv).expect("parses");
+        assert_eq!(parsed[0].value, hash_hex("FFD3ABCDEF120E37"));
+        assert_eq!(parsed[0].label, "(imported)");
+    }
+
+    #[test]
+    fn one_bad_row_refuses_the_whole_file() {
+        let text = "kind;value\nip;203.0.113.7\nip;not-an-address\n";
+        let error = parse_import(text, RuleFormat::Csv).expect_err("refused");
+        assert!(error.contains("rule 2"), "{}", error);
+
+        assert!(parse_import("kind;value\nphone;555\n", RuleFormat::Csv).is_err());
+    }
 }
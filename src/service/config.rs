@@ -1,4 +1,5 @@
 use config::Config;
+use std::collections::HashMap;
 use std::thread::available_parallelism;
 use once_cell::sync::Lazy;
 
@@ -14,7 +15,17 @@ ConfigModelDerive! {
         // Optional for the same reason as `soap_concurrency` below: a
         // `Config.toml` written before the MCP endpoint existed has no `[mcp]`
         // table at all, and a missing required field fails the whole parse.
-        pub mcp: Option<McpConfig>
+        pub mcp: Option<McpConfig>,
+        // Optional like `mcp`: a file without a `[rate_limit]` table still parses,
+        // and no limit is enforced — see `service/ratelimit`.
+        pub rate_limit: Option<RateLimitConfig>,
+        // Optional like the above: without an `[abuse]` table nothing is
+        // blocked automatically — see `service/abuse`.
+        pub abuse: Option<AbuseConfig>,
+        // Optional like the above: without a `[storage]` table no key file is
+        // read, and only `RUSTOPUS_STORAGE_KEY` can switch on encryption at rest
+        // — see `service/sealed`.
+        pub storage: Option<StorageConfig>
     }
 
     #[derive(Clone)]
@@ -30,7 +41,31 @@ ConfigModelDerive! {
         // Hosts a caller-supplied `url` parameter may point at. Optional for the
         // same reason, and when it is absent the host of `soap.json`'s url is the
         // only one allowed — see `service/soap_config::allowed_soap_hosts`.
-        pub allowed_soap_hosts: Option<Vec<String>>
+        pub allowed_soap_hosts: Option<Vec<String>>,
+        // How long, and within what memory budget, a paged request's upstream
+        // response is kept for the pages after it. Optional like the above —
+        // see `service/soap::PAGE_CACHE`.
+        pub page_cache_ttl_secs: Option<u64>,
+        pub page_cache_max_bytes: Option<u64>,
+        // Proxies whose `X-Forwarded-For` / `Forwarded` headers are believed.
+        // Optional like the above; unset means loopback only, an empty list
+        // means nobody — see `service/ipv4`.
+        pub trusted_proxies: Option<Vec<String>>,
+        // Whether the REST endpoints still take an authcode from the query
+        // string. Optional like the above, default on — see `query_credentials`.
+        pub query_credentials: Option<bool>,
+        // How each backend's `soap_concurrency` is split between priority
+        // lanes. Optional like the above — see `service/soap_gate`.
+        pub soap_lanes: Option<SoapLanesConfig>
+    }
+
+    /// `[server.soap_lanes]`: outbound slots reserved for each lane, per
+    /// Octopus backend. What is left over is shared by all three.
+    #[derive(Clone, Default)]
+    pub struct SoapLanesConfig {
+        pub interactive: Option<usize>,
+        pub bulk: Option<usize>,
+        pub background: Option<usize>
     }
 
     /// `[mcp]` table. Every field is `Option` and every default is applied in
@@ -53,7 +88,65 @@ ConfigModelDerive! {
         pub oauth_sessions_path: Option<String>,
         pub oauth_access_ttl_secs: Option<u64>,
         pub oauth_refresh_ttl_secs: Option<u64>,
-        pub oauth_login_rate_limit: Option<u32>
+        pub oauth_login_rate_limit: Option<u32>,
+        pub oauth_tokens_path: Option<String>,
+        pub oauth_registration_enabled: Option<bool>,
+        pub oauth_registration_redirect_uris: Option<Vec<String>>,
+        pub oauth_registration_rate_limit: Option<u32>,
+        pub oauth_registration_ttl_secs: Option<u64>,
+        pub quote_vat_percent: Option<f64>,
+        pub quote_valid_days: Option<u32>,
+        pub quote_ttl_secs: Option<u64>,
+        pub header_orders: Option<bool>
+    }
+
+    /// `[rate_limit]` table. `authcode` and `ip` are the limits every REST
+    /// endpoint gets; `endpoints` overrides them per endpoint, keyed by its
+    /// singular name (`get-bulk`, `post-order`).
+    #[derive(Clone)]
+    pub struct RateLimitConfig {
+        pub enabled: Option<bool>,
+        pub authcode: Option<LimitConfig>,
+        pub ip: Option<LimitConfig>,
+        pub endpoints: Option<HashMap<String, EndpointLimitConfig>>
+    }
+
+    /// One endpoint's overrides. A key left out falls back to the defaults.
+    #[derive(Clone)]
+    pub struct EndpointLimitConfig {
+        pub authcode: Option<LimitConfig>,
+        pub ip: Option<LimitConfig>
+    }
+
+    /// `[abuse]` table: how many strikes of each kind within `window_secs` get
+    /// an address blocked, and for how long. Every field is optional.
+    #[derive(Clone)]
+    pub struct AbuseConfig {
+        pub enabled: Option<bool>,
+        pub window_secs: Option<u64>,
+        pub block_secs: Option<u64>,
+        pub malformed_authcode: Option<u32>,
+        pub url_not_allowed: Option<u32>,
+        pub octopus_auth: Option<u32>,
+        pub flood: Option<u32>
+    }
+
+    /// `[storage]` table: where server state is kept, and where the key that
+    /// seals the secret-grade files is read from when the environment does not
+    /// supply one.
+    #[derive(Clone, Default)]
+    pub struct StorageConfig {
+        pub backend: Option<String>,
+        pub sqlite_path: Option<String>,
+        pub key_file: Option<String>
+    }
+
+    /// One token bucket plus an optional daily quota.
+    #[derive(Clone, Default)]
+    pub struct LimitConfig {
+        pub per_minute: Option<f64>,
+        pub burst: Option<f64>,
+        pub daily_quota: Option<u64>
     }
 
 }
@@ -76,9 +169,9 @@ const DEFAULT_MCP_MAX_BYTES: u64 = 300_000_000;
 /// runtime path in this service.
 const DEFAULT_MCP_DISK_PATH: &str = "mcp_cache";
 
-/// On-**disk** budget when `[mcp] disk_max_bytes` is unset: 5 GB. Stored
-/// snapshots are gzipped, so this holds far more combinations than the number
-/// suggests.
+/// On-**disk** budget when `[mcp] disk_max_bytes` is unset: 5 GB. Partners of
+/// one url share a base file and add only a small overlay each, so this holds
+/// far more combinations than the number suggests.
 const DEFAULT_MCP_DISK_MAX_BYTES: u64 = 5_000_000_000;
 
 /// Directory generated exports are written to when `[mcp] export_path` is unset.
@@ -127,6 +220,60 @@ const DEFAULT_OAUTH_REFRESH_TTL_SECS: u64 = 2_592_000;
 /// an oracle for guessing authcodes against the ERP.
 const DEFAULT_OAUTH_LOGIN_RATE_LIMIT: u32 = 10;
 
+/// Dynamic registrations allowed per IP per hour when
+/// `[mcp] oauth_registration_rate_limit` is unset. A connector registers once;
+/// anything past a handful is somebody filling the client file.
+const DEFAULT_OAUTH_REGISTRATION_RATE_LIMIT: u32 = 5;
+
+/// How long a dynamically registered client may go without a sign-in before it
+/// is dropped, when `[mcp] oauth_registration_ttl_secs` is unset: 1 day.
+const DEFAULT_OAUTH_REGISTRATION_TTL_SECS: u64 = 86_400;
+
+/// VAT added to a quote's net total when `[mcp] quote_vat_percent` is unset:
+/// the Hungarian standard rate.
+const DEFAULT_QUOTE_VAT_PERCENT: f64 = 27.0;
+
+/// How long a finished quote is offered for when `[mcp] quote_valid_days` is
+/// unset and the caller does not say.
+const DEFAULT_QUOTE_VALID_DAYS: u32 = 30;
+
+/// How long a quote nobody touches is kept when `[mcp] quote_ttl_secs` is
+/// unset: 7 days. Quotes live in memory, so this is what bounds them.
+const DEFAULT_QUOTE_TTL_SECS: u64 = 604_800;
+
+impl ServerConfig {
+    /// `?authcode=` / `?auth=` on the REST endpoints. On by default so existing
+    /// integrations keep working; off, only `X-Authcode` and `Authorization:
+    /// Bearer` are accepted, and nothing credential-shaped reaches an access log.
+    pub fn query_credentials(&self) -> bool {
+        self.query_credentials.unwrap_or(true)
+    }
+
+    pub fn soap_lanes(&self) -> SoapLanesConfig {
+        self.soap_lanes.clone().unwrap_or_default()
+    }
+}
+
+
+/// Slots each lane reserves when `[server.soap_lanes]` does not say: one each,
+/// which with the default `soap_concurrency = 4` leaves one shared.
+const DEFAULT_SOAP_LANE_RESERVED: usize = 1;
+
+impl SoapLanesConfig {
+    pub fn interactive(&self) -> usize {
+        self.interactive.unwrap_or(DEFAULT_SOAP_LANE_RESERVED)
+    }
+
+    pub fn bulk(&self) -> usize {
+        self.bulk.unwrap_or(DEFAULT_SOAP_LANE_RESERVED)
+    }
+
+    pub fn background(&self) -> usize {
+        self.background.unwrap_or(DEFAULT_SOAP_LANE_RESERVED)
+    }
+}
+
+
 impl McpConfig {
     pub fn is_enabled(&self) -> bool {
         self.enabled.unwrap_or(false)
@@ -232,6 +379,60 @@ impl McpConfig {
     pub fn oauth_login_rate_limit(&self) -> u32 {
         self.oauth_login_rate_limit.unwrap_or(DEFAULT_OAUTH_LOGIN_RATE_LIMIT)
     }
+
+    /// Where access tokens are persisted, hashed. Unset — the default — keeps
+    /// them in memory only, which is what a single instance wants; set, tokens
+    /// survive a restart and instances sharing the OAuth files share sign-ins.
+    pub fn oauth_tokens_path(&self) -> Option<String> {
+        self.oauth_tokens_path.as_ref()
+            .filter(|path| !path.trim().is_empty())
+            .cloned()
+    }
+
+    /// Whether `/oauth/register` (RFC 7591) is served. Off by default: clients
+    /// are then created by hand in `/admin`, as before.
+    pub fn oauth_registration_enabled(&self) -> bool {
+        self.oauth_registration_enabled.unwrap_or(false)
+    }
+
+    /// Redirect URIs a dynamically registered client may ask for. Empty — the
+    /// default — refuses every registration, so turning the endpoint on without
+    /// an allowlist cannot open it to arbitrary redirect targets.
+    pub fn oauth_registration_redirect_uris(&self) -> Vec<String> {
+        self.oauth_registration_redirect_uris.clone()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|uri| uri.trim().to_string())
+            .filter(|uri| !uri.is_empty())
+            .collect()
+    }
+
+    pub fn oauth_registration_rate_limit(&self) -> u32 {
+        self.oauth_registration_rate_limit.unwrap_or(DEFAULT_OAUTH_REGISTRATION_RATE_LIMIT)
+    }
+
+    pub fn oauth_registration_ttl_secs(&self) -> u64 {
+        self.oauth_registration_ttl_secs.unwrap_or(DEFAULT_OAUTH_REGISTRATION_TTL_SECS)
+    }
+
+    pub fn quote_vat_percent(&self) -> f64 {
+        self.quote_vat_percent.filter(|percent| percent.is_finite() && *percent >= 0.0).unwrap_or(DEFAULT_QUOTE_VAT_PERCENT)
+    }
+
+    pub fn quote_valid_days(&self) -> u32 {
+        self.quote_valid_days.filter(|days| *days > 0).unwrap_or(DEFAULT_QUOTE_VALID_DAYS)
+    }
+
+    pub fn quote_ttl_secs(&self) -> u64 {
+        self.quote_ttl_secs.unwrap_or(DEFAULT_QUOTE_TTL_SECS)
+    }
+
+    /// Whether `X-Authcode` / `X-Pid` callers may use the order tools. Off by
+    /// default: an order should take a grant somebody gave on purpose — a
+    /// sign-in with `orders.write`, or this switch.
+    pub fn header_orders(&self) -> bool {
+        self.header_orders.unwrap_or(false)
+    }
 }
 
 
@@ -254,11 +455,127 @@ pub fn get_mcp_settings() -> McpConfig {
         oauth_sessions_path: None,
         oauth_access_ttl_secs: None,
         oauth_refresh_ttl_secs: None,
-        oauth_login_rate_limit: None
+        oauth_login_rate_limit: None,
+        oauth_tokens_path: None,
+        oauth_registration_enabled: None,
+        oauth_registration_redirect_uris: None,
+        oauth_registration_rate_limit: None,
+        oauth_registration_ttl_secs: None,
+        quote_vat_percent: None,
+        quote_valid_days: None,
+        quote_ttl_secs: None,
+        header_orders: None
     })
 }
 
 
+/// Window strikes are counted in when `[abuse] window_secs` is unset: 10 minutes.
+const DEFAULT_ABUSE_WINDOW_SECS: u64 = 600;
+
+/// Length of an automatic block when `[abuse] block_secs` is unset: 1 hour.
+/// Long enough to stop a script, short enough that a partner who fat-fingered
+/// a config file is back before anyone has to be phoned.
+const DEFAULT_ABUSE_BLOCK_SECS: u64 = 3_600;
+
+/// Malformed authcodes (error 206) per window before a block. Nobody types a
+/// code with markup in it ten times by accident.
+const DEFAULT_ABUSE_MALFORMED_AUTHCODE: u32 = 10;
+
+/// Refused `url` parameters (error 205) per window before a block. Lower than
+/// the others: a legitimate client sends the configured url or none at all.
+const DEFAULT_ABUSE_URL_NOT_ALLOWED: u32 = 5;
+
+/// Authcodes Octopus rejected per window before a block. High enough for a
+/// partner whose code was just rotated to retry a sync a few times.
+const DEFAULT_ABUSE_OCTOPUS_AUTH: u32 = 20;
+
+/// Rate-limit refusals (429) per window before a block. A client that ignores
+/// `Retry-After` this often is not going to start obeying it.
+const DEFAULT_ABUSE_FLOOD: u32 = 50;
+
+impl AbuseConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn window_secs(&self) -> u64 {
+        self.window_secs.filter(|secs| *secs > 0).unwrap_or(DEFAULT_ABUSE_WINDOW_SECS)
+    }
+
+    pub fn block_secs(&self) -> u64 {
+        self.block_secs.filter(|secs| *secs > 0).unwrap_or(DEFAULT_ABUSE_BLOCK_SECS)
+    }
+
+    /// `0` turns a trigger off.
+    pub fn malformed_authcode(&self) -> u32 {
+        self.malformed_authcode.unwrap_or(DEFAULT_ABUSE_MALFORMED_AUTHCODE)
+    }
+
+    pub fn url_not_allowed(&self) -> u32 {
+        self.url_not_allowed.unwrap_or(DEFAULT_ABUSE_URL_NOT_ALLOWED)
+    }
+
+    pub fn octopus_auth(&self) -> u32 {
+        self.octopus_auth.unwrap_or(DEFAULT_ABUSE_OCTOPUS_AUTH)
+    }
+
+    pub fn flood(&self) -> u32 {
+        self.flood.unwrap_or(DEFAULT_ABUSE_FLOOD)
+    }
+}
+
+
+/// Environment variable holding the current storage key. Checked before
+/// `[storage] key_file`, like `RUSTOPUS_ADMIN_TOKEN` before `admin_token`.
+pub const STORAGE_KEY_ENV: &str = "RUSTOPUS_STORAGE_KEY";
+
+/// Environment variable holding retired storage keys, comma-separated. They
+/// still open a file but never seal one — see `service/sealed`.
+pub const STORAGE_OLD_KEYS_ENV: &str = "RUSTOPUS_STORAGE_OLD_KEYS";
+
+/// Where the SQLite backend keeps its database when `[storage] sqlite_path` is
+/// unset. Relative, like every other runtime path in this service.
+const DEFAULT_STORAGE_SQLITE_PATH: &str = "rustopus.db";
+
+impl StorageConfig {
+    /// `"toml"` (the default) or `"sqlite"`, lowercased. Anything else is
+    /// refused by `service/state` at startup.
+    pub fn backend(&self) -> String {
+        self.backend.as_ref()
+            .map(|backend| backend.trim().to_ascii_lowercase())
+            .filter(|backend| !backend.is_empty())
+            .unwrap_or_else(|| "toml".into())
+    }
+
+    pub fn sqlite_path(&self) -> String {
+        self.sqlite_path.as_ref()
+            .map(|path| path.trim().to_string())
+            .filter(|path| !path.is_empty())
+            .unwrap_or_else(|| DEFAULT_STORAGE_SQLITE_PATH.into())
+    }
+
+    /// The key file, when one is configured. Blank is the same as unset.
+    pub fn key_file(&self) -> Option<String> {
+        self.key_file.as_ref()
+            .map(|path| path.trim().to_string())
+            .filter(|path| !path.is_empty())
+    }
+}
+
+
+/// The `[storage]` table, or an empty one when the table is absent.
+pub fn get_storage_settings() -> StorageConfig {
+    get_settings().storage.unwrap_or_default()
+}
+
+
+impl RateLimitConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+}
+
+
 /// `Config.toml` is parsed from disk once; every `get_settings()` call clones
 /// from this cached view instead of re-reading the file.
 static SETTINGS: Lazy<Settings> = Lazy::new(load_settings);
@@ -297,8 +614,16 @@ fn load_settings() -> Settings {
             // No allowlist in the fallback either: `soap_config` then falls back
             // to the host of `soap.json`'s url, which is the one host an
             // instance running on hardcoded defaults ever calls.
-            allowed_soap_hosts: None
+            allowed_soap_hosts: None,
+            page_cache_ttl_secs: None,
+            page_cache_max_bytes: None,
+            trusted_proxies: None,
+            query_credentials: None,
+            soap_lanes: None
         },
-        mcp: None
+        mcp: None,
+        rate_limit: None,
+        abuse: None,
+        storage: None
     }
 }
@@ -8,12 +8,13 @@ use crate::{
             defaults::CallData
         },
         out::{
-            xml::barcode as p_barcode,
+            xml::{barcode as p_barcode, defaults::Page},
             csv::barcodes as csv_barcodes
         }
     },
     service::{
-        soap::get_response_shared,
+        page::{self, Paging},
+        soap::get_response_paged,
         get_data::{
             FIRST_DATE, ErrorType,
             error_logger, to_xml_string
@@ -47,13 +48,28 @@ impl BarcodesXML {
     pub fn to_xml(&self) -> String {
         to_xml_string(self)
     }
+
+    /// Cuts the requested page. Only the English envelope carries the page
+    /// metadata; Hungarian output has it in the headers.
+    pub fn into_paged_xml(mut self, paging: Option<&Paging>) -> (String, Option<Page>) {
+        let page = match &mut self {
+            BarcodesXML::En(envelope) => {
+                let answer = &mut envelope.body.response.result.answer;
+                let page = page::cut(paging, &mut answer.barcodes.barcode);
+                answer.page = page.clone();
+                page
+            }
+            BarcodesXML::Hu(envelope) => page::cut(paging, &mut envelope.body.get_vonalkodok_auth_response.get_vonalkodok_auth_result.valasz.vonalkodok.vonalkod)
+        };
+        (self.to_xml(), page)
+    }
 }
 
 
 /// This function gets english barcodes envelope from the given `CallData`
 pub async fn get_barcode(call_data: CallData) -> BarcodesData {
     let request = o8_barcode::get_request_string(&call_data.xmlns, &call_data.from_date.unwrap_or(*FIRST_DATE), &call_data.authcode);
-    let response = get_response_shared(&call_data.url, request).await;
+    let response = get_response_paged(&call_data.url, request, call_data.paged).await;
     match quick_xml::de::from_str::<o8_barcode::Envelope>(&response) {
         Ok(envelope) => {
             let error = envelope.body.get_vonalkodok_auth_response.get_vonalkodok_auth_result.valasz.hiba.clone();
@@ -10,6 +10,8 @@ use crate::{
     global::errors,
     macros::get::get_models,
     service::{
+        log::log_with_ip_uuid,
+        select::Selection,
         get::{
             products::{ProductsData, ProductsXML},
             prices::{PricesData, PricesXML},
@@ -46,6 +48,25 @@ impl BulkXML {
     pub fn to_xml(&self) -> String {
         to_xml_string(self)
     }
+
+    /// Filters the records and projects the English envelope to the requested
+    /// fields. Hungarian output keeps its Octopus field names, so `fields=` does
+    /// not apply to it.
+    pub fn into_selected_xml(mut self, selection: &Selection, ip_address: &str, uuid: &str) -> String {
+        match &mut self {
+            BulkXML::En(envelope) => {
+                selection.retain(&mut envelope.body.response.result.answer.products.product, ip_address, uuid);
+                selection.to_xml(envelope, &["products", "product"], ip_address, uuid)
+            }
+            BulkXML::Hu(envelope) => {
+                selection.retain(&mut envelope.body.response.result.valasz.cikkek.cikk, ip_address, uuid);
+                if selection.projects() {
+                    log_with_ip_uuid(ip_address, uuid, "Ignoring `fields`: it applies to English output only");
+                }
+                self.to_xml()
+            }
+        }
+    }
 }
 
 
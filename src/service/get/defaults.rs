@@ -1,7 +1,11 @@
 // Defaults
 use crate::{
     forms::r#in::xml::defaults::{CallData, Hiba},
-    service::log::elogger
+    service::{
+        abuse::{self, Trigger},
+        log::elogger,
+        mcp::mask_authcode
+    }
 };
 
 
@@ -26,7 +30,10 @@ pub fn get_return_type(call_data: CallData) -> ReturnType {
 pub fn check_return_type(call_data: CallData, error: Option<Hiba>, name: &str) -> ReturnType {
     let mut return_type = get_return_type(call_data.clone());
     if let Some(hiba) = &error {
-        elogger(format!("{} | {}: Octopus error in {} envelope: '{} - {}'", call_data.pid.unwrap_or_default(), call_data.authcode, name, hiba.kod, hiba.leiras));
+        elogger(format!("{} | {}: Octopus error in {} envelope: '{} - {}'", call_data.pid.unwrap_or_default(), mask_authcode(&call_data.authcode), name, hiba.kod, hiba.leiras));
+        if abuse::is_octopus_auth_error(&hiba.leiras) {
+            abuse::report_caller(Trigger::OctopusAuth);
+        }
         if !matches!(return_type, ReturnType::Xml | ReturnType::XmlHu) {
             return_type = match call_data.is_hu() {
                 true => ReturnType::XmlHu,
@@ -8,12 +8,13 @@ use crate::{
             images as o8_images
         },
         out::{
-            xml::images as p_images,
+            xml::{images as p_images, defaults::Page},
             csv::images as csv_images
         }
     },
     service::{
-        soap::get_response_shared,
+        page::{self, Paging},
+        soap::get_response_paged,
         get_data::{
             FIRST_DATE, ErrorType,
             error_logger, to_xml_string
@@ -47,6 +48,21 @@ impl ImagesXML {
     pub fn to_xml(&self) -> String {
         to_xml_string(self)
     }
+
+    /// Cuts the requested page. Only the English envelope carries the page
+    /// metadata; Hungarian output has it in the headers.
+    pub fn into_paged_xml(mut self, paging: Option<&Paging>) -> (String, Option<Page>) {
+        let page = match &mut self {
+            ImagesXML::En(envelope) => {
+                let answer = &mut envelope.body.response.result.answer;
+                let page = page::cut(paging, &mut answer.products.product);
+                answer.page = page.clone();
+                page
+            }
+            ImagesXML::Hu(envelope) => page::cut(paging, &mut envelope.body.get_cikk_kepek_auth_response.get_cikk_kepek_auth_result.valasz.cikk)
+        };
+        (self.to_xml(), page)
+    }
 }
 
 
@@ -55,7 +71,7 @@ impl ImagesXML {
 /// This function gets english images envelope from the given `CallData`
 pub async fn get_images(call_data: CallData) -> ImagesData {
     let request = o8_images::get_request_string(&call_data.xmlns, &call_data.from_date.unwrap_or(*FIRST_DATE), &call_data.authcode);
-    let response = get_response_shared(&call_data.url, request).await;
+    let response = get_response_paged(&call_data.url, request, call_data.paged).await;
     match quick_xml::de::from_str::<o8_images::Envelope>(&response) {
         Ok(envelope) => {
             let error = envelope.body.get_cikk_kepek_auth_response.get_cikk_kepek_auth_result.valasz.hiba.clone();
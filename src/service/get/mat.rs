@@ -8,12 +8,13 @@ use crate::{
             defaults::CallData
         },
         out::{
-            xml::mat as p_mat,
+            xml::{mat as p_mat, defaults::Page},
             csv::mat as csv_mat
         }
     },
     service::{
-        soap::get_response_shared,
+        page::{self, Paging},
+        soap::get_response_paged,
         get_data::{
             FIRST_DATE, ErrorType,
             error_logger, to_xml_string
@@ -47,12 +48,27 @@ impl MatXML {
     pub fn to_xml(&self) -> String {
         to_xml_string(self)
     }
+
+    /// Cuts the requested page. Only the English envelope carries the page
+    /// metadata; Hungarian output has it in the headers.
+    pub fn into_paged_xml(mut self, paging: Option<&Paging>) -> (String, Option<Page>) {
+        let page = match &mut self {
+            MatXML::En(envelope) => {
+                let answer = &mut envelope.body.response.result.answer;
+                let page = page::cut(paging, &mut answer.attributes.attribute);
+                answer.page = page.clone();
+                page
+            }
+            MatXML::Hu(envelope) => page::cut(paging, &mut envelope.body.get_matmodell_auth_response.get_matmodell_auth_result.valasz.tulajdonsagok.tulajdonsag)
+        };
+        (self.to_xml(), page)
+    }
 }
 
 
 pub async fn get_mat(call_data: CallData) -> MatData {
     let request = o8_mat::get_request_string(&call_data.xmlns, &call_data.from_date.unwrap_or(*FIRST_DATE), &call_data.authcode);
-    let response = get_response_shared(&call_data.url, request.clone()).await;
+    let response = get_response_paged(&call_data.url, request.clone(), call_data.paged).await;
     match quick_xml::de::from_str::<o8_mat::Envelope>(&response) {
         Ok(envelope) => {
             let error = envelope.body.get_matmodell_auth_response.get_matmodell_auth_result.valasz.hiba.clone();
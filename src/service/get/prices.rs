@@ -8,12 +8,15 @@ use crate::{
             defaults::CallData
         },
         out::{
-            xml::prices as p_prices,
+            xml::{prices as p_prices, defaults::Page},
             csv::prices as csv_prices
         }
     },
     service::{
-        soap::get_response_shared,
+        log::log_with_ip_uuid,
+        page::{self, Paging},
+        select::Selection,
+        soap::get_response_paged,
         get_data::{
             ErrorType,
             error_logger, to_xml_string
@@ -47,6 +50,30 @@ impl PricesXML {
     pub fn to_xml(&self) -> String {
         to_xml_string(self)
     }
+
+    /// Filters the records, cuts the requested page and projects the English
+    /// envelope to the requested fields. Hungarian output keeps its Octopus
+    /// field names, so `fields=` does not apply to it, and carries its page
+    /// metadata in the headers only.
+    pub fn into_selected_xml(mut self, selection: &Selection, paging: Option<&Paging>, ip_address: &str, uuid: &str) -> (String, Option<Page>) {
+        match &mut self {
+            PricesXML::En(envelope) => {
+                let answer = &mut envelope.body.response.result.answer;
+                selection.retain(&mut answer.prices.price, ip_address, uuid);
+                let page = page::cut(paging, &mut answer.prices.price);
+                answer.page = page.clone();
+                (selection.to_xml(envelope, &["prices", "price"], ip_address, uuid), page)
+            }
+            PricesXML::Hu(envelope) => {
+                selection.retain(&mut envelope.body.get_arlista_auth_response.get_arlista_auth_result.valasz.arak.ar, ip_address, uuid);
+                let page = page::cut(paging, &mut envelope.body.get_arlista_auth_response.get_arlista_auth_result.valasz.arak.ar);
+                if selection.projects() {
+                    log_with_ip_uuid(ip_address, uuid, "Ignoring `fields`: it applies to English output only");
+                }
+                (self.to_xml(), page)
+            }
+        }
+    }
 }
 
 
@@ -54,7 +81,7 @@ impl PricesXML {
 pub async fn get_prices(call_data: CallData) -> PricesData {
     if let Some(pid) = call_data.pid {
         let request = o8_prices::get_request_string(&call_data.xmlns, &call_data.authcode, &pid);
-        let response = get_response_shared(&call_data.url, request).await;
+        let response = get_response_paged(&call_data.url, request, call_data.paged).await;
         return match quick_xml::de::from_str::<o8_prices::Envelope>(&response) {
             Ok(envelope) => {
                 let error = envelope.body.get_arlista_auth_response.get_arlista_auth_result.valasz.hiba.clone();
@@ -8,12 +8,15 @@ use crate::{
             defaults::CallData
         },
         out::{
-            xml::products as p_products,
+            xml::{products as p_products, defaults::Page},
             csv::products as csv_products
         }
     },
     service::{
-        soap::get_response_shared,
+        log::log_with_ip_uuid,
+        page::{self, Paging},
+        select::Selection,
+        soap::get_response_paged,
         get_data::{
             FIRST_DATE, ErrorType,
             error_logger, to_xml_string
@@ -47,12 +50,36 @@ impl ProductsXML {
     pub fn to_xml(&self) -> String {
         to_xml_string(self)
     }
+
+    /// Filters the records, cuts the requested page and projects the English
+    /// envelope to the requested fields. Hungarian output keeps its Octopus
+    /// field names, so `fields=` does not apply to it, and carries its page
+    /// metadata in the headers only.
+    pub fn into_selected_xml(mut self, selection: &Selection, paging: Option<&Paging>, ip_address: &str, uuid: &str) -> (String, Option<Page>) {
+        match &mut self {
+            ProductsXML::En(envelope) => {
+                let answer = &mut envelope.body.response.result.answer;
+                selection.retain(&mut answer.products.product, ip_address, uuid);
+                let page = page::cut(paging, &mut answer.products.product);
+                answer.page = page.clone();
+                (selection.to_xml(envelope, &["products", "product"], ip_address, uuid), page)
+            }
+            ProductsXML::Hu(envelope) => {
+                selection.retain(&mut envelope.body.get_cikkek_auth_response.get_cikkek_auth_result.valasz.cikk, ip_address, uuid);
+                let page = page::cut(paging, &mut envelope.body.get_cikkek_auth_response.get_cikkek_auth_result.valasz.cikk);
+                if selection.projects() {
+                    log_with_ip_uuid(ip_address, uuid, "Ignoring `fields`: it applies to English output only");
+                }
+                (self.to_xml(), page)
+            }
+        }
+    }
 }
 
 
 pub async fn get_products(call_data: CallData) -> ProductsData {
     let request = o8_products::get_request_string(&call_data.xmlns, &call_data.from_date.unwrap_or(*FIRST_DATE), &call_data.authcode);
-    let response = get_response_shared(&call_data.url, request).await;
+    let response = get_response_paged(&call_data.url, request, call_data.paged).await;
     match quick_xml::de::from_str::<o8_products::Envelope>(&response) {
         Ok(envelope) => {
             let error = envelope.body.get_cikkek_auth_response.get_cikkek_auth_result.valasz.hiba.clone();
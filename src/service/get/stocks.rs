@@ -9,11 +9,14 @@ use crate::{
         }, 
         out::{
             csv::stocks as csv_stocks,
-            xml::stocks as p_stocks
+            xml::{stocks as p_stocks, defaults::Page}
         }
     },
     service::{
-        soap::get_response_shared,
+        log::log_with_ip_uuid,
+        page::{self, Paging},
+        select::Selection,
+        soap::get_response_paged,
         get_data::{
             ErrorType, FIRST_DATE,
             error_logger, to_xml_string
@@ -47,13 +50,37 @@ impl StocksXML {
     pub fn to_xml(&self) -> String {
         to_xml_string(self)
     }
+
+    /// Filters the records, cuts the requested page and projects the English
+    /// envelope to the requested fields. Hungarian output keeps its Octopus
+    /// field names, so `fields=` does not apply to it, and carries its page
+    /// metadata in the headers only.
+    pub fn into_selected_xml(mut self, selection: &Selection, paging: Option<&Paging>, ip_address: &str, uuid: &str) -> (String, Option<Page>) {
+        match &mut self {
+            StocksXML::En(envelope) => {
+                let answer = &mut envelope.body.response.result.answer;
+                selection.retain(&mut answer.products.product, ip_address, uuid);
+                let page = page::cut(paging, &mut answer.products.product);
+                answer.page = page.clone();
+                (selection.to_xml(envelope, &["products", "product"], ip_address, uuid), page)
+            }
+            StocksXML::Hu(envelope) => {
+                selection.retain(&mut envelope.body.get_cikkek_keszlet_valtozas_auth_response.get_cikkek_keszlet_valtozas_auth_result.valasz.cikkek.cikk, ip_address, uuid);
+                let page = page::cut(paging, &mut envelope.body.get_cikkek_keszlet_valtozas_auth_response.get_cikkek_keszlet_valtozas_auth_result.valasz.cikkek.cikk);
+                if selection.projects() {
+                    log_with_ip_uuid(ip_address, uuid, "Ignoring `fields`: it applies to English output only");
+                }
+                (self.to_xml(), page)
+            }
+        }
+    }
 }
 
 
 /// This function gets english stocks envelope from the given `CallData`
 pub async fn get_stocks(call_data: CallData) -> StocksData {
     let request = o8_stocks::get_request_string(&call_data.xmlns, &call_data.from_date.unwrap_or(*FIRST_DATE), &call_data.authcode);
-    let response = get_response_shared(&call_data.url, request).await;
+    let response = get_response_paged(&call_data.url, request, call_data.paged).await;
     // Resolved before the envelope is inspected, because `get_return_type`
     // consumes `call_data` and both branches below need the answer.
     match quick_xml::de::from_str::<o8_stocks::Envelope>(&response) {
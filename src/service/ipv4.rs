@@ -1,7 +1,32 @@
+//! Who a request came from.
+//!
+//! Behind a reverse proxy the socket peer is the proxy, and the caller's address
+//! arrives in `X-Forwarded-For` or RFC 7239 `Forwarded` — headers anyone can
+//! send. A forwarded address is therefore believed only when the hop that
+//! reported it is a **trusted proxy** (`[server] trusted_proxies`, loopback
+//! when unset). The chain is walked from the right, starting at the peer: each
+//! trusted hop vouches for the entry to its left, and the first untrusted
+//! address is the client. Entries a caller prepended to the header sit left of
+//! that and are never read.
+//!
+//! The blocklist, the rate limits, abuse detection, the OAuth sign-in limit and
+//! every log line resolve the address here, so they all agree on who asked.
+
 use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
 use actix_web::HttpRequest;
+use actix_web::http::header::HeaderMap;
+use once_cell::sync::Lazy;
+
+use crate::service::{
+    config::get_settings,
+    log::{elogger, logger}
+};
 
-use crate::service::log::elogger;
+/// Trusted when `[server] trusted_proxies` is unset: a reverse proxy on the
+/// same machine, which is how the repository's deployment runs.
+const DEFAULT_TRUSTED_PROXIES: [&str; 2] = ["127.0.0.0/8", "::1"];
 
 /// `RequestIP` enum
 pub enum RequestIP {
@@ -20,19 +45,291 @@ impl fmt::Display for RequestIP {
 }
 
 
+/// An address or a CIDR range, v4 or v6.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix: u32
+}
+
+impl IpNetwork {
+    /// Parses `203.0.113.7` or `203.0.113.0/24` (v6 equally). A single address
+    /// is a network of one.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let Some((address, prefix)) = value.split_once('/') else {
+            let address = normalize(value.parse::<IpAddr>().ok()?);
+            return Some(Self { address, prefix: bits(address) })
+        };
+
+        let address = address.trim().parse::<IpAddr>().ok()?;
+        let prefix = prefix.trim().parse::<u32>().ok()?;
+        if prefix > bits(address) {
+            return None
+        }
+        Some(Self { address, prefix })
+    }
+
+    /// Whether `candidate` falls inside this network.
+    pub fn contains(&self, candidate: IpAddr) -> bool {
+        let (left, right): (Vec<u8>, Vec<u8>) = match (normalize(candidate), normalize(self.address)) {
+            (IpAddr::V4(a), IpAddr::V4(b)) => (a.octets().into(), b.octets().into()),
+            (IpAddr::V6(a), IpAddr::V6(b)) => (a.octets().into(), b.octets().into()),
+            // A v4 network never contains a v6 caller, or the other way round.
+            _ => return false
+        };
+
+        let mut remaining = self.prefix;
+        for (a, b) in left.iter().zip(right.iter()) {
+            if remaining == 0 {
+                return true
+            }
+            if remaining >= 8 {
+                if a != b {
+                    return false
+                }
+                remaining -= 8;
+                continue
+            }
+            let mask = 0xFFu8 << (8 - remaining);
+            return a & mask == b & mask
+        }
+        true
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.prefix == bits(self.address) {
+            write!(f, "{}", self.address)
+        } else {
+            write!(f, "{}/{}", self.address, self.prefix)
+        }
+    }
+}
+
+
+fn bits(address: IpAddr) -> u32 {
+    if address.is_ipv4() { 32 } else { 128 }
+}
+
+
+/// Collapses an IPv4-mapped IPv6 address (`::ffff:203.0.113.7`) to its IPv4 form,
+/// so a rule written the way an operator reads the address in a log still
+/// matches when the socket reports the mapped shape.
+pub fn normalize(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(IpAddr::V6(v6)),
+        other => other
+    }
+}
+
+
+/// Proxies whose forwarding headers are believed, resolved once.
+static TRUSTED_PROXIES: Lazy<Vec<IpNetwork>> = Lazy::new(|| {
+    let configured: Vec<String> = get_settings().server.trusted_proxies.clone()
+        .unwrap_or_else(|| DEFAULT_TRUSTED_PROXIES.iter().map(|entry| entry.to_string()).collect());
+
+    configured.iter()
+        .filter(|entry| !entry.trim().is_empty())
+        .filter_map(|entry| match IpNetwork::parse(entry) {
+            Some(network) => Some(network),
+            None => {
+                elogger(format!("Trusted proxies: '{}' is not an IP address or CIDR range — ignored", entry));
+                None
+            }
+        })
+        .collect()
+});
+
+
+/// The proxies whose forwarding headers are believed, `[server] trusted_proxies`
+/// or the loopback default.
+pub fn trusted_proxies() -> &'static [IpNetwork] {
+    &TRUSTED_PROXIES
+}
+
+
+/// Resolves the trusted proxy list at startup, so the log says whose forwarding
+/// headers will be believed before the first request arrives.
+pub fn init() {
+    if TRUSTED_PROXIES.is_empty() {
+        logger("Trusted proxies: none — forwarding headers are ignored, the socket peer is the client");
+    } else {
+        logger(format!(
+            "Trusted proxies: {} — their X-Forwarded-For / Forwarded headers name the client",
+            TRUSTED_PROXIES.iter().map(IpNetwork::to_string).collect::<Vec<String>>().join(", ")
+        ));
+    }
+}
+
+
+/// The `for=` addresses of every RFC 7239 `Forwarded` header, in order. `None`
+/// for a hop the proxy chose not to disclose (`unknown`, `_hidden`).
+fn forwarded_chain(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers.get_all("Forwarded")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| element.split(';')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+            .map(|(_, value)| parse_node(value)))
+        .collect()
+}
+
+
+/// A `Forwarded` node: `192.0.2.60`, `"192.0.2.60:4711"`, `"[2001:db8::17]:4711"`.
+fn parse_node(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    if let Some(rest) = value.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok()
+    }
+    value.parse().ok().or_else(|| {
+        // v4 with a port; a bare v6 address has more than one colon and parsed above.
+        let (address, _) = value.split_once(':')?;
+        address.parse().ok()
+    })
+}
+
+
+/// The `X-Forwarded-For` entries, in order, across every copy of the header.
+fn x_forwarded_chain(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers.get_all("X-Forwarded-For")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(parse_node)
+        .collect()
+}
+
+
+/// Walks the forwarding chain from the peer leftwards. See the module note.
+///
+/// `Forwarded` wins when a request carries both: a proxy that speaks RFC 7239
+/// writes it, and an `X-Forwarded-For` beside it may be the caller's own. A hop
+/// that cannot be read ends the walk at the trusted proxy that reported it —
+/// everything left of it is unverifiable.
+fn resolve(trusted: &[IpNetwork], peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    let is_trusted = |address: IpAddr| trusted.iter().any(|network| network.contains(address));
+    if !is_trusted(peer) {
+        return peer
+    }
+
+    let chain = match forwarded_chain(headers) {
+        chain if !chain.is_empty() => chain,
+        _ => x_forwarded_chain(headers)
+    };
+
+    let mut client = peer;
+    for hop in chain.into_iter().rev() {
+        let Some(address) = hop else {
+            break
+        };
+        client = address;
+        if !is_trusted(address) {
+            break
+        }
+    }
+    client
+}
+
+
+/// The client's address: the socket peer, or what the trusted proxies in front
+/// of it say. `None` only when the socket reports no peer.
+pub fn client_address(peer: Option<SocketAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+    peer.map(|peer| resolve(&TRUSTED_PROXIES, peer.ip(), headers))
+}
+
+
 /// This function tries to get ipv4 address from the request
 pub async fn log_ip(req: HttpRequest) -> RequestIP {
-    if let Some(ip) = req
-        .headers()
-        .get("X-Forwarded-For")
-        .and_then(|x| x.to_str().ok())
-        .and_then(|x| x.split(',').next()) {
-            return RequestIP::Ok(ip.into())
-    }
-    if let Some(peer_address) = req.peer_addr() {
-        let ip = peer_address.ip().to_string();
-        return RequestIP::Ok(ip)
+    if let Some(address) = client_address(req.peer_addr(), req.headers()) {
+        return RequestIP::Ok(address.to_string())
     }
     elogger("Can not get IP address");
     RequestIP::Err("unknown IP address".into())
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().expect("test address parses")
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(HeaderName::from_static(name), HeaderValue::from_static(value));
+        }
+        map
+    }
+
+    fn loopback() -> Vec<IpNetwork> {
+        DEFAULT_TRUSTED_PROXIES.iter().filter_map(|entry| IpNetwork::parse(entry)).collect()
+    }
+
+    #[test]
+    fn an_untrusted_peer_cannot_name_another_client() {
+        let spoofed = headers(&[("x-forwarded-for", "198.51.100.1")]);
+        assert_eq!(resolve(&loopback(), ip("203.0.113.9"), &spoofed), ip("203.0.113.9"));
+    }
+
+    #[test]
+    fn a_trusted_proxy_names_the_client() {
+        let forwarded = headers(&[("x-forwarded-for", "203.0.113.9")]);
+        assert_eq!(resolve(&loopback(), ip("127.0.0.1"), &forwarded), ip("203.0.113.9"));
+        // No header: the proxy itself is all there is.
+        assert_eq!(resolve(&loopback(), ip("127.0.0.1"), &HeaderMap::new()), ip("127.0.0.1"));
+    }
+
+    #[test]
+    fn the_chain_is_read_from_the_right() {
+        // The caller prepended a lie; the proxy appended what it saw.
+        let forwarded = headers(&[("x-forwarded-for", "198.51.100.1, 203.0.113.9")]);
+        assert_eq!(resolve(&loopback(), ip("127.0.0.1"), &forwarded), ip("203.0.113.9"));
+
+        // Two trusted hops: the walk passes both.
+        let trusted = vec![IpNetwork::parse("127.0.0.1").unwrap(), IpNetwork::parse("10.0.0.0/8").unwrap()];
+        let forwarded = headers(&[("x-forwarded-for", "198.51.100.1, 203.0.113.9, 10.1.2.3")]);
+        assert_eq!(resolve(&trusted, ip("127.0.0.1"), &forwarded), ip("203.0.113.9"));
+    }
+
+    #[test]
+    fn the_rfc_7239_header_is_understood_and_preferred() {
+        let forwarded = headers(&[
+            ("forwarded", "for=198.51.100.1;proto=https, for=\"[2001:db8:cafe::17]:4711\""),
+            ("x-forwarded-for", "192.0.2.1")
+        ]);
+        assert_eq!(resolve(&loopback(), ip("::1"), &forwarded), ip("2001:db8:cafe::17"));
+
+        let forwarded = headers(&[("forwarded", "For=\"203.0.113.9:8080\";by=127.0.0.1")]);
+        assert_eq!(resolve(&loopback(), ip("127.0.0.1"), &forwarded), ip("203.0.113.9"));
+    }
+
+    #[test]
+    fn an_undisclosed_hop_ends_the_walk() {
+        let forwarded = headers(&[("forwarded", "for=198.51.100.1, for=unknown")]);
+        assert_eq!(resolve(&loopback(), ip("127.0.0.1"), &forwarded), ip("127.0.0.1"));
+    }
+
+    #[test]
+    fn an_empty_list_trusts_no_one() {
+        let forwarded = headers(&[("x-forwarded-for", "203.0.113.9")]);
+        assert_eq!(resolve(&[], ip("127.0.0.1"), &forwarded), ip("127.0.0.1"));
+    }
+
+    #[test]
+    fn networks_parse_and_match() {
+        let network = IpNetwork::parse("198.51.48.0/20").expect("valid");
+        assert!(network.contains(ip("198.51.63.255")));
+        assert!(!network.contains(ip("198.51.64.0")));
+        assert!(IpNetwork::parse("203.0.113.7").expect("valid").contains(ip("::ffff:203.0.113.7")));
+        assert!(IpNetwork::parse("203.0.113.0/40").is_none());
+        assert_eq!(IpNetwork::parse("203.0.113.0/24").expect("valid").to_string(), "203.0.113.0/24");
+    }
+}
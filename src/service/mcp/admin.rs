@@ -26,6 +26,13 @@
 //! On such an instance `AdminState::mcp_enabled` is false and every handler that
 //! would touch the snapshot cache or the precache is skipped — reading them would
 //! construct a cache on a process that is meant to hold none.
+//!
+//! The same goes for partner API keys (`service/apikey.rs`), which stand in for
+//! authcodes on the REST endpoints. A key is returned once, when it is issued;
+//! its authcode only ever leaves here masked.
+//!
+//! Search synonyms (`service/mcp/synonyms`) are edited here too, on MCP
+//! instances only — nothing else searches the catalog snapshots.
 
 use std::path::PathBuf;
 
@@ -33,25 +40,35 @@ use actix_files::NamedFile;
 use actix_web::{
     HttpRequest, HttpResponse, Responder, Scope, web
 };
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
 use serde_json::json;
 
 use crate::service::{
-    blocklist::{self, BlockRule, BlockScope},
+    apikey,
+    authcode::is_well_formed,
+    blocklist::{self, BlockRule, BlockScope, RuleAction, RuleFormat},
     log::{elog_with_ip, log_with_ip},
     ipv4::log_ip,
     mcp::{
         cache::cache,
+        mask_authcode,
         oauth,
         precache::{self, PrecacheEntry},
         secrets_match,
-        store
-    }
+        store,
+        synonyms
+    },
+    ratelimit,
+    soap_config::is_allowed_soap_url
 };
 
 /// Header accepted as an alternative to HTTP Basic, for curl and scripts.
 const ADMIN_TOKEN_HEADER: &str = "X-Admin-Token";
 
+/// How many past runs each precache entry's status tooltip lists.
+const RUN_HISTORY_SHOWN: usize = 10;
+
 
 /// Everything the admin handlers need, shared through actix app data.
 #[derive(Clone)]
@@ -183,11 +200,13 @@ fn require_mcp(state: &AdminState) -> Option<HttpResponse> {
 /// One row per blocking rule, with its in-memory hit counters.
 fn blocks_payload() -> Vec<serde_json::Value> {
     let hits = blocklist::hits();
+    let now = Utc::now();
     blocklist::rules().iter().map(|rule| {
         let id = rule.id();
         let hit = hits.get(&id).cloned().unwrap_or_default();
         json!({
             "id": id,
+            "action": rule.action().as_str(),
             "kind": rule.kind.as_str(),
             // For an authcode rule this is the mask, never the code — the hash
             // behind it is not published either, since it is a working offline
@@ -197,6 +216,9 @@ fn blocks_payload() -> Vec<serde_json::Value> {
             "scope": rule.scope().as_str(),
             "enabled": rule.is_enabled(),
             "created_at": rule.created_at.map(|at| at.to_rfc3339()),
+            "expires_at": rule.expires_at.map(|at| at.to_rfc3339()),
+            "expired": rule.is_expired(now),
+            "auto": rule.is_auto(),
             "hits": hit.count,
             "last_hit": hit.last_at.map(|at| at.to_rfc3339()),
             "last_ip": hit.last_ip
@@ -205,6 +227,29 @@ fn blocks_payload() -> Vec<serde_json::Value> {
 }
 
 
+/// Rate-limit usage per key and endpoint, or `null` when limits are off and no
+/// API key has been metered. An authcode row carries its mask; the hash it is
+/// keyed by stays server-side.
+fn rate_limits_payload() -> serde_json::Value {
+    let usage = ratelimit::usage();
+    if !ratelimit::is_enabled() && usage.is_empty() {
+        return serde_json::Value::Null
+    }
+    let rows: Vec<serde_json::Value> = usage.iter().map(|usage| json!({
+        "kind": usage.kind.as_str(),
+        "label": usage.label,
+        "endpoint": usage.endpoint,
+        "remaining": usage.remaining,
+        "burst": usage.burst,
+        "used_today": usage.used_today,
+        "daily_quota": usage.daily_quota,
+        "refused_today": usage.refused_today,
+        "last_seen": usage.last_seen.to_rfc3339()
+    })).collect();
+    json!({ "enabled": ratelimit::is_enabled(), "usage": rows })
+}
+
+
 /// Registered OAuth clients and the sign-ins they hold, or `null` when OAuth is
 /// off — exactly as `cache` and `disk` are `null` on a REST-only instance.
 ///
@@ -215,12 +260,22 @@ fn oauth_payload() -> serde_json::Value {
         return serde_json::Value::Null
     }
 
+    let held = oauth::store::grants();
+    let registration_ttl = chrono::Duration::seconds(crate::service::config::get_mcp_settings().oauth_registration_ttl_secs().min(i64::MAX as u64) as i64);
     let clients: Vec<serde_json::Value> = oauth::store::clients().iter().map(|client| json!({
         "client_id": client.client_id,
         "name": client.name,
         "redirect_uris": client.redirect_uris,
+        "scopes": client.allowed_scopes().iter().map(|scope| scope.as_str()).collect::<Vec<_>>(),
         "enabled": client.is_enabled(),
-        "created_at": client.created_at.map(|at| at.to_rfc3339())
+        "created_at": client.created_at.map(|at| at.to_rfc3339()),
+        "registered_by": client.registered_by,
+        "needs_review": client.needs_review(),
+        // When an unapproved self-registration nobody has signed in through is
+        // dropped; `null` for every other client.
+        "expires_at": client.created_at
+            .filter(|_| client.needs_review() && !held.iter().any(|grant| grant.client_id == client.client_id))
+            .map(|at| (at + registration_ttl).to_rfc3339())
     })).collect();
 
     let names: std::collections::HashMap<String, String> = oauth::store::clients().into_iter()
@@ -235,6 +290,7 @@ fn oauth_payload() -> serde_json::Value {
         // Masked, always — the same rule the precache rows follow.
         "authcode": grant.label,
         "pid": grant.pid,
+        "scopes": grant.scopes().iter().map(|scope| scope.as_str()).collect::<Vec<_>>(),
         "created_at": grant.created_at.to_rfc3339(),
         "expires_at": grant.expires_at.to_rfc3339(),
         "last_used": used.get(&grant.id).map(|at| at.to_rfc3339()),
@@ -248,6 +304,18 @@ fn oauth_payload() -> serde_json::Value {
         "issuer": oauth::issuer(),
         "resource": oauth::resource_uri(),
         "allow_headers": oauth::allow_headers(),
+        "tokens": {
+            "persisted": oauth::store::is_shared(),
+            "live": oauth::store::access_count()
+        },
+        "registration": {
+            "enabled": oauth::registration_enabled(),
+            "redirect_uris": oauth::registration_redirect_uris()
+        },
+        "scopes": oauth::TokenScope::ALL.iter().map(|scope| json!({
+            "name": scope.as_str(),
+            "description": scope.describe()
+        })).collect::<Vec<_>>(),
         "clients": clients,
         "sessions": sessions
     })
@@ -255,8 +323,9 @@ fn oauth_payload() -> serde_json::Value {
 
 
 /// Cache usage plus one row per configured entry, all authcodes masked, plus the
-/// blocklist. On a non-MCP instance the cache and precache sections are `null`
-/// and only `blocks` is populated.
+/// blocklist, rate-limit usage and API keys. On a non-MCP instance the cache and
+/// precache sections are `null` and only `blocks`, `rate_limits` and `api_keys`
+/// are populated.
 async fn state_handler(request: HttpRequest, state: web::Data<AdminState>) -> impl Responder {
     if let Some(denied) = guard(&request, &state).await {
         return denied
@@ -269,7 +338,9 @@ async fn state_handler(request: HttpRequest, state: web::Data<AdminState>) -> im
             "disk": null,
             "entries": [],
             "oauth": null,
-            "blocks": blocks_payload()
+            "blocks": blocks_payload(),
+            "rate_limits": rate_limits_payload(),
+            "api_keys": api_keys_payload()
         }))
     }
 
@@ -299,6 +370,8 @@ async fn state_handler(request: HttpRequest, state: web::Data<AdminState>) -> im
             "last_full_pull": run.last_full_pull.map(|at| at.to_rfc3339()),
             "last_duration_ms": run.last_duration_ms,
             "last_outcome": run.last_outcome,
+            // Kept only by the SQLite backend; always empty with TOML files.
+            "history": precache::history(&id, RUN_HISTORY_SHOWN),
             "bytes": entry_stats.bytes,
             "products": entry_stats.products,
             "hits": entry_stats.hits,
@@ -315,10 +388,13 @@ async fn state_handler(request: HttpRequest, state: web::Data<AdminState>) -> im
     HttpResponse::Ok().json(json!({
         "mcp_enabled": true,
         "blocks": blocks_payload(),
+        "rate_limits": rate_limits_payload(),
+        "api_keys": api_keys_payload(),
         "cache": {
             "used_bytes": used,
             "budget_bytes": budget,
             "usage_ratio": if budget == 0 { 0.0 } else { used as f64 / budget as f64 },
+            "shared_base_bytes": cache().base_bytes(),
             "entries_held": cache().entry_count()
         },
         "disk": {
@@ -525,7 +601,13 @@ pub struct NewBlock {
     pub kind: String,
     pub value: String,
     pub note: Option<String>,
-    pub scope: Option<String>
+    pub scope: Option<String>,
+    /// `block` (the default) or `allow`.
+    pub action: Option<String>,
+    /// When the rule stops matching. `expires_in_secs` is the same thing
+    /// relative to now, which is what the dashboard form sends.
+    pub expires_at: Option<DateTime<Utc>>,
+    pub expires_in_secs: Option<i64>
 }
 
 /// Body for editing a rule. No `value`: what a rule matches on defines its id,
@@ -534,7 +616,22 @@ pub struct NewBlock {
 pub struct BlockPatch {
     pub note: Option<String>,
     pub scope: Option<String>,
-    pub enabled: Option<bool>
+    pub enabled: Option<bool>,
+    /// `true` drops the expiry, keeping an automatic block for good.
+    pub permanent: Option<bool>
+}
+
+
+/// Parses the action name, defaulting to a block when it is absent.
+fn parse_action(action: &Option<String>) -> Result<Option<RuleAction>, String> {
+    let Some(action) = action.as_ref().map(|action| action.trim().to_lowercase()) else {
+        return Ok(None)
+    };
+    match action.as_str() {
+        "" | "block" => Ok(Some(RuleAction::Block)),
+        "allow" => Ok(Some(RuleAction::Allow)),
+        other => Err(format!("unknown action '{}' — use block or allow", other))
+    }
 }
 
 
@@ -576,15 +673,25 @@ async fn block_create_handler(
         "authcode" => BlockRule::authcode(&body.value, note, scope),
         other => Err(format!("unknown kind '{}' — use ip or authcode", other))
     };
-    let rule = match rule {
+    let mut rule = match rule {
         Ok(rule) => rule,
         Err(error) => return HttpResponse::BadRequest().json(json!({ "error": error }))
     };
+    rule.action = match parse_action(&body.action) {
+        Ok(action) => action,
+        Err(error) => return HttpResponse::BadRequest().json(json!({ "error": error }))
+    };
+    rule.expires_at = body.expires_at.or_else(|| body.expires_in_secs
+        .filter(|secs| *secs > 0)
+        .map(|secs| Utc::now() + chrono::Duration::seconds(secs)));
+    if rule.expires_at.is_some_and(|at| at <= Utc::now()) {
+        return HttpResponse::BadRequest().json(json!({ "error": "expires_at is in the past" }))
+    }
 
     let id = rule.id();
     // The label, not the value: for an authcode rule the value is a hash and
     // for an IP rule the two are the same thing.
-    let described = format!("{} '{}' ({})", rule.kind.as_str(), rule.label, rule.scope().as_str());
+    let described = format!("{} {} '{}' ({})", rule.action().as_str(), rule.kind.as_str(), rule.label, rule.scope().as_str());
 
     match blocklist::upsert(rule) {
         Ok(()) => {
@@ -597,7 +704,8 @@ async fn block_create_handler(
 }
 
 
-/// Edits a rule's note, scope or enabled flag.
+/// Edits a rule's note, scope or enabled flag, or makes a time-limited rule
+/// permanent.
 async fn block_patch_handler(
     request: HttpRequest,
     state: web::Data<AdminState>,
@@ -627,13 +735,20 @@ async fn block_patch_handler(
     if let Some(enabled) = body.enabled {
         rule.enabled = Some(enabled);
     }
+    if body.permanent == Some(true) {
+        rule.expires_at = None;
+    }
 
     let described = format!(
         "{} '{}' ({}, {})",
         rule.kind.as_str(),
         rule.label,
         rule.scope().as_str(),
-        if rule.is_enabled() { "enforced" } else { "paused" }
+        match (rule.is_enabled(), rule.expires_at) {
+            (false, _) => "paused".to_string(),
+            (true, Some(at)) => format!("enforced until {}", at.to_rfc3339()),
+            (true, None) => "enforced".to_string()
+        }
     );
 
     match blocklist::upsert(rule) {
@@ -674,12 +789,312 @@ async fn block_delete_handler(
 }
 
 
+/// Query of the blocklist export and import.
+#[derive(Debug, Deserialize)]
+pub struct TransferQuery {
+    pub format: Option<String>,
+    /// Import only: `true` makes the file the whole rule set instead of merging.
+    pub replace: Option<bool>
+}
+
+
+fn parse_format(query: &TransferQuery) -> Result<RuleFormat, HttpResponse> {
+    let name = query.format.as_deref().unwrap_or("toml");
+    RuleFormat::parse(name).ok_or_else(|| HttpResponse::BadRequest().json(json!({
+        "error": format!("unknown format '{}' — use csv or toml", name)
+    })))
+}
+
+
+/// Downloads the rule set. Authcode rules travel as their hash and mask, so the
+/// file holds no credential — the same reason `blocklist.toml` is not one.
+async fn block_export_handler(
+    request: HttpRequest,
+    state: web::Data<AdminState>,
+    query: web::Query<TransferQuery>
+) -> impl Responder {
+    if let Some(denied) = guard(&request, &state).await {
+        return denied
+    }
+    let format = match parse_format(&query) {
+        Ok(format) => format,
+        Err(response) => return response
+    };
+
+    match blocklist::export(format) {
+        Ok(body) => HttpResponse::Ok()
+            .content_type(format.content_type())
+            .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\"", format.filename())))
+            .body(body),
+        Err(error) => HttpResponse::InternalServerError().json(json!({ "error": error }))
+    }
+}
+
+
+/// Loads rules from an uploaded export. The body is the file itself; nothing is
+/// applied unless every rule in it is valid.
+async fn block_import_handler(
+    request: HttpRequest,
+    state: web::Data<AdminState>,
+    query: web::Query<TransferQuery>,
+    body: String
+) -> impl Responder {
+    if let Some(denied) = guard(&request, &state).await {
+        return denied
+    }
+    let format = match parse_format(&query) {
+        Ok(format) => format,
+        Err(response) => return response
+    };
+    let replace = query.replace.unwrap_or(false);
+
+    match blocklist::import(&body, format, replace) {
+        Ok(summary) => {
+            let ip_address = log_ip(request.clone()).await.to_string();
+            log_with_ip(&ip_address, format!(
+                "ADMIN: block rules imported [{} added, {} updated, {} removed]",
+                summary.added, summary.updated, summary.removed
+            ));
+            HttpResponse::Ok().json(json!({
+                "added": summary.added,
+                "updated": summary.updated,
+                "removed": summary.removed
+            }))
+        }
+        Err(error) => HttpResponse::BadRequest().json(json!({ "error": error }))
+    }
+}
+
+
+/// Body for issuing an API key. The key itself never arrives here — the server
+/// mints it, returns it once and keeps only its hash.
+#[derive(Debug, Deserialize)]
+pub struct NewApiKey {
+    pub label: String,
+    /// **SECRET** — the Octopus authcode the key stands in for.
+    pub authcode: String,
+    pub pid: Option<i64>,
+    pub url: Option<String>,
+    /// Endpoints by name; empty or absent means all of them.
+    pub scopes: Option<Vec<String>>,
+    pub per_minute: Option<f64>,
+    pub daily_quota: Option<u64>,
+    /// When the key stops working. `expires_in_secs` is the same thing
+    /// relative to now, which is what the dashboard form sends.
+    pub expires_at: Option<DateTime<Utc>>,
+    pub expires_in_secs: Option<i64>
+}
+
+/// Body for editing a key. No `authcode`: a key for a different partner is a
+/// different key. `0` clears a limit; an empty `url` clears the mapping.
+#[derive(Debug, Deserialize)]
+pub struct ApiKeyPatch {
+    pub label: Option<String>,
+    pub pid: Option<i64>,
+    pub url: Option<String>,
+    pub scopes: Option<Vec<String>>,
+    pub per_minute: Option<f64>,
+    pub daily_quota: Option<u64>,
+    pub enabled: Option<bool>,
+    /// `true` drops the expiry.
+    pub permanent: Option<bool>
+}
+
+
+/// One row per issued key. The key is shown by its hint and the authcode by its
+/// mask; neither hash leaves the server.
+fn api_keys_payload() -> Vec<serde_json::Value> {
+    let used = apikey::last_used();
+    let now = Utc::now();
+    apikey::keys().iter().map(|key| json!({
+        "id": key.id,
+        "label": key.label,
+        "hint": key.hint,
+        "authcode": mask_authcode(&key.authcode),
+        "pid": key.pid,
+        "url": key.url,
+        "scopes": key.scopes,
+        "per_minute": key.per_minute,
+        "daily_quota": key.daily_quota,
+        "enabled": key.is_enabled(),
+        "created_at": key.created_at.map(|at| at.to_rfc3339()),
+        "expires_at": key.expires_at.map(|at| at.to_rfc3339()),
+        "expired": key.is_expired(now),
+        "last_used": used.get(&key.id).map(|at| at.to_rfc3339())
+    })).collect()
+}
+
+
+/// Checks a mapped url against the SOAP allowlist, so a key is not issued that
+/// every endpoint would answer with a 205.
+fn parse_key_url(url: Option<String>) -> Result<Option<String>, String> {
+    let Some(url) = url.map(|url| url.trim().to_string()).filter(|url| !url.is_empty()) else {
+        return Ok(None)
+    };
+    if !is_allowed_soap_url(&url) {
+        return Err(format!("'{}' is not an allowed SOAP url (see allowed_soap_hosts)", url))
+    }
+    Ok(Some(url))
+}
+
+
+/// Issues an API key and returns it.
+///
+/// Like client registration, the only response that ever carries the key: it is
+/// stored hashed, so it cannot be shown again.
+async fn api_key_create_handler(
+    request: HttpRequest,
+    state: web::Data<AdminState>,
+    body: web::Json<NewApiKey>
+) -> impl Responder {
+    if let Some(denied) = guard(&request, &state).await {
+        return denied
+    }
+
+    let body = body.into_inner();
+    let label = body.label.trim().to_string();
+    if label.is_empty() {
+        return HttpResponse::BadRequest().json(json!({ "error": "label is required" }))
+    }
+    let authcode = body.authcode.trim().to_string();
+    if !is_well_formed(&authcode) || apikey::is_key(&authcode) {
+        return HttpResponse::BadRequest().json(json!({ "error": "authcode is not a well-formed Octopus authcode" }))
+    }
+    let scopes = match apikey::parse_scopes(&body.scopes.unwrap_or_default()) {
+        Ok(scopes) => scopes,
+        Err(error) => return HttpResponse::BadRequest().json(json!({ "error": error }))
+    };
+    let url = match parse_key_url(body.url) {
+        Ok(url) => url,
+        Err(error) => return HttpResponse::BadRequest().json(json!({ "error": error }))
+    };
+
+    let (mut record, key) = apikey::ApiKey::issue(label.clone(), authcode);
+    record.pid = body.pid;
+    record.url = url;
+    record.scopes = scopes;
+    record.per_minute = body.per_minute.filter(|rate| *rate > 0.0);
+    record.daily_quota = body.daily_quota.filter(|quota| *quota > 0);
+    record.expires_at = body.expires_at.or_else(|| body.expires_in_secs
+        .filter(|secs| *secs > 0)
+        .map(|secs| Utc::now() + chrono::Duration::seconds(secs)));
+    if record.expires_at.is_some_and(|at| at <= Utc::now()) {
+        return HttpResponse::BadRequest().json(json!({ "error": "expires_at is in the past" }))
+    }
+    let id = record.id.clone();
+    let described = format!("'{}' {} for {}", label, record.hint, mask_authcode(&record.authcode));
+
+    match apikey::upsert(record) {
+        Ok(()) => {
+            let ip_address = log_ip(request.clone()).await.to_string();
+            log_with_ip(&ip_address, format!("ADMIN: API key issued [{}]", described));
+            HttpResponse::Ok().json(json!({
+                "id": id,
+                "key": key,
+                "note": "This is the only time the key is shown. Hand it to the partner now; they send it in X-Authcode or Authorization: Bearer."
+            }))
+        }
+        Err(error) => HttpResponse::InternalServerError().json(json!({ "error": error }))
+    }
+}
+
+
+/// Relabels a key, edits its mapping, scopes or limits, disables it, or drops
+/// its expiry.
+async fn api_key_patch_handler(
+    request: HttpRequest,
+    state: web::Data<AdminState>,
+    path: web::Path<String>,
+    body: web::Json<ApiKeyPatch>
+) -> impl Responder {
+    if let Some(denied) = guard(&request, &state).await {
+        return denied
+    }
+
+    let id = path.into_inner();
+    let Some(mut key) = apikey::find(&id) else {
+        return HttpResponse::NotFound().json(json!({ "error": "no such key" }))
+    };
+
+    let body = body.into_inner();
+    if let Some(label) = body.label.filter(|label| !label.trim().is_empty()) {
+        key.label = label.trim().to_string();
+    }
+    if body.pid.is_some() {
+        key.pid = body.pid;
+    }
+    if body.url.is_some() {
+        match parse_key_url(body.url) {
+            Ok(url) => key.url = url,
+            Err(error) => return HttpResponse::BadRequest().json(json!({ "error": error }))
+        }
+    }
+    if let Some(scopes) = body.scopes {
+        match apikey::parse_scopes(&scopes) {
+            Ok(scopes) => key.scopes = scopes,
+            Err(error) => return HttpResponse::BadRequest().json(json!({ "error": error }))
+        }
+    }
+    if let Some(rate) = body.per_minute {
+        key.per_minute = (rate > 0.0).then_some(rate);
+    }
+    if let Some(quota) = body.daily_quota {
+        key.daily_quota = (quota > 0).then_some(quota);
+    }
+    if let Some(enabled) = body.enabled {
+        key.enabled = Some(enabled);
+    }
+    if body.permanent == Some(true) {
+        key.expires_at = None;
+    }
+
+    let described = format!("'{}' {} ({})", key.label, key.hint, if key.is_enabled() { "enabled" } else { "disabled" });
+    match apikey::upsert(key) {
+        Ok(()) => {
+            let ip_address = log_ip(request.clone()).await.to_string();
+            log_with_ip(&ip_address, format!("ADMIN: API key updated [{}]", described));
+            HttpResponse::Ok().json(json!({ "id": id }))
+        }
+        Err(error) => HttpResponse::InternalServerError().json(json!({ "error": error }))
+    }
+}
+
+
+/// Revokes a key. The partner's next call with it gets a 211.
+async fn api_key_delete_handler(
+    request: HttpRequest,
+    state: web::Data<AdminState>,
+    path: web::Path<String>
+) -> impl Responder {
+    if let Some(denied) = guard(&request, &state).await {
+        return denied
+    }
+
+    let id = path.into_inner();
+    let Some(key) = apikey::find(&id) else {
+        return HttpResponse::NotFound().json(json!({ "error": "no such key" }))
+    };
+    let described = format!("'{}' {}", key.label, key.hint);
+
+    match apikey::remove(&id) {
+        Ok(_) => {
+            let ip_address = log_ip(request.clone()).await.to_string();
+            log_with_ip(&ip_address, format!("ADMIN: API key revoked [{}]", described));
+            HttpResponse::Ok().json(json!({ "removed": true }))
+        }
+        Err(error) => HttpResponse::InternalServerError().json(json!({ "error": error }))
+    }
+}
+
+
 /// Body for registering a connector. No secret arrives here — the server mints
 /// it, returns it once and keeps only its hash.
 #[derive(Debug, Deserialize)]
 pub struct NewOauthClient {
     pub name: String,
-    pub redirect_uris: Vec<String>
+    pub redirect_uris: Vec<String>,
+    /// What the connector may be granted; absent means `catalog.read` only.
+    pub scopes: Option<Vec<String>>
 }
 
 /// Body for editing a connector. No `client_id`, and no way to read or reset the
@@ -688,7 +1103,22 @@ pub struct NewOauthClient {
 pub struct OauthClientPatch {
     pub name: Option<String>,
     pub redirect_uris: Option<Vec<String>>,
-    pub enabled: Option<bool>
+    pub scopes: Option<Vec<String>>,
+    pub enabled: Option<bool>,
+    /// Approves a self-registered client, which stops it being flagged and
+    /// swept.
+    pub reviewed: Option<bool>
+}
+
+
+/// Checks a connector's scopes and renders them as stored. Empty is refused:
+/// a connector that may be granted nothing can only fail its sign-ins.
+fn parse_client_scopes(scopes: &[String]) -> Result<Vec<String>, String> {
+    let scopes = oauth::parse_scopes(&scopes.join(" "))?;
+    if scopes.is_empty() {
+        return Err("at least one scope is required".into())
+    }
+    Ok(scopes.iter().map(|scope| scope.as_str().to_string()).collect())
 }
 
 
@@ -748,21 +1178,33 @@ async fn oauth_client_create_handler(
         }))
     }
 
+    let scopes = match body.scopes {
+        Some(scopes) => match parse_client_scopes(&scopes) {
+            Ok(scopes) => scopes,
+            Err(error) => return HttpResponse::BadRequest().json(json!({ "error": error }))
+        },
+        None => Vec::new()
+    };
+
     let secret = oauth::new_secret();
     let client = oauth::OauthClient {
         client_id: uuid::Uuid::new_v4().simple().to_string(),
         name: name.clone(),
         secret_hash: oauth::hash_secret(&secret),
         redirect_uris,
+        scopes,
         created_at: Some(chrono::Utc::now()),
-        enabled: Some(true)
+        enabled: Some(true),
+        registered_by: None,
+        reviewed: None
     };
     let client_id = client.client_id.clone();
+    let granted = oauth::format_scopes(&client.allowed_scopes());
 
     match oauth::store::upsert_client(client) {
         Ok(()) => {
             let ip_address = log_ip(request.clone()).await.to_string();
-            log_with_ip(&ip_address, format!("ADMIN: OAuth client registered '{}'", name));
+            log_with_ip(&ip_address, format!("ADMIN: OAuth client registered '{}' ({})", name, granted));
             HttpResponse::Ok().json(json!({
                 "client_id": client_id,
                 "client_secret": secret,
@@ -774,7 +1216,8 @@ async fn oauth_client_create_handler(
 }
 
 
-/// Renames a connector, edits its redirect URIs, or disables it.
+/// Renames a connector, edits its redirect URIs or scopes, disables it, or
+/// approves one that registered itself.
 async fn oauth_client_patch_handler(
     request: HttpRequest,
     state: web::Data<AdminState>,
@@ -807,11 +1250,26 @@ async fn oauth_client_patch_handler(
         }
         client.redirect_uris = uris;
     }
+    if let Some(scopes) = body.scopes {
+        match parse_client_scopes(&scopes) {
+            Ok(scopes) => client.scopes = scopes,
+            Err(error) => return HttpResponse::BadRequest().json(json!({ "error": error }))
+        }
+    }
     if let Some(enabled) = body.enabled {
         client.enabled = Some(enabled);
     }
+    if let Some(reviewed) = body.reviewed.filter(|_| client.is_dynamic()) {
+        client.reviewed = Some(reviewed);
+    }
 
-    let described = format!("{} ({})", client.name, if client.is_enabled() { "enabled" } else { "disabled" });
+    let described = format!(
+        "{} ({}; {}{})",
+        client.name,
+        if client.is_enabled() { "enabled" } else { "disabled" },
+        oauth::format_scopes(&client.allowed_scopes()),
+        if client.needs_review() { "; awaiting review" } else { "" }
+    );
     match oauth::store::upsert_client(client) {
         Ok(()) => {
             let ip_address = log_ip(request.clone()).await.to_string();
@@ -889,6 +1347,87 @@ async fn oauth_session_delete_handler(
 }
 
 
+/// The synonym file in force, verbatim, with what it compiled to.
+fn synonyms_payload() -> Result<serde_json::Value, String> {
+    let (text, source) = synonyms::text()?;
+    Ok(json!({
+        "text": text,
+        "source": source,
+        "path": synonyms::get_synonyms_path().to_string_lossy(),
+        "counts": synonyms::current().counts()
+    }))
+}
+
+
+/// The search synonyms, as the editor on the dashboard shows them.
+async fn synonyms_handler(request: HttpRequest, state: web::Data<AdminState>) -> impl Responder {
+    if let Some(denied) = guard(&request, &state).await {
+        return denied
+    }
+    if let Some(refused) = require_mcp(&state) {
+        return refused
+    }
+    match synonyms_payload() {
+        Ok(payload) => HttpResponse::Ok().json(payload),
+        Err(error) => HttpResponse::InternalServerError().json(json!({ "error": error }))
+    }
+}
+
+
+/// Replaces the synonym file. The body is the file itself; nothing is written
+/// unless all of it compiles, and the next search uses it.
+async fn synonyms_save_handler(
+    request: HttpRequest,
+    state: web::Data<AdminState>,
+    body: String
+) -> impl Responder {
+    if let Some(denied) = guard(&request, &state).await {
+        return denied
+    }
+    if let Some(refused) = require_mcp(&state) {
+        return refused
+    }
+
+    match synonyms::save(&body) {
+        Ok(counts) => {
+            let ip_address = log_ip(request.clone()).await.to_string();
+            log_with_ip(&ip_address, format!(
+                "ADMIN: search synonyms saved [{} groups, {} one-way, {} units]",
+                counts.synonyms, counts.one_way, counts.units
+            ));
+            match synonyms_payload() {
+                Ok(payload) => HttpResponse::Ok().json(payload),
+                Err(error) => HttpResponse::InternalServerError().json(json!({ "error": error }))
+            }
+        }
+        Err(error) => HttpResponse::BadRequest().json(json!({ "error": error }))
+    }
+}
+
+
+/// Deletes the operator's synonym file, putting the starter vocabulary back.
+async fn synonyms_reset_handler(request: HttpRequest, state: web::Data<AdminState>) -> impl Responder {
+    if let Some(denied) = guard(&request, &state).await {
+        return denied
+    }
+    if let Some(refused) = require_mcp(&state) {
+        return refused
+    }
+
+    let ip_address = log_ip(request.clone()).await.to_string();
+    match synonyms::reset().and_then(|_| synonyms_payload()) {
+        Ok(payload) => {
+            log_with_ip(&ip_address, "ADMIN: search synonyms reset to the starter vocabulary");
+            HttpResponse::Ok().json(payload)
+        }
+        Err(error) => {
+            elog_with_ip(&ip_address, format!("ADMIN: cannot reset search synonyms: {}", error));
+            HttpResponse::InternalServerError().json(json!({ "error": error }))
+        }
+    }
+}
+
+
 /// Serves one of the dashboard's own files, behind the same token check as the
 /// API — the page itself is part of the protected surface, not public chrome.
 async fn asset(request: &HttpRequest, state: &AdminState, name: &str) -> HttpResponse {
@@ -936,8 +1475,14 @@ pub fn scope(state: AdminState) -> Scope {
         // Blocklist. Unlike the routes above these work with MCP disabled — the
         // rules they manage protect the REST endpoints too.
         .route("/api/blocks", web::post().to(block_create_handler))
+        .route("/api/blocks/export", web::get().to(block_export_handler))
+        .route("/api/blocks/import", web::post().to(block_import_handler))
         .route("/api/blocks/{id}", web::patch().to(block_patch_handler))
         .route("/api/blocks/{id}", web::delete().to(block_delete_handler))
+        // API keys, likewise: they stand in for authcodes on the REST endpoints.
+        .route("/api/keys", web::post().to(api_key_create_handler))
+        .route("/api/keys/{id}", web::patch().to(api_key_patch_handler))
+        .route("/api/keys/{id}", web::delete().to(api_key_delete_handler))
         // OAuth connectors and the sign-ins they hold. Registered whatever the
         // configuration says and refused with a 400 when OAuth is off, like the
         // precache routes on a REST-only instance.
@@ -945,6 +1490,10 @@ pub fn scope(state: AdminState) -> Scope {
         .route("/api/oauth/clients/{id}", web::patch().to(oauth_client_patch_handler))
         .route("/api/oauth/clients/{id}", web::delete().to(oauth_client_delete_handler))
         .route("/api/oauth/sessions/{id}", web::delete().to(oauth_session_delete_handler))
+        // Search synonyms. The body of a PUT is the TOML file itself.
+        .route("/api/synonyms", web::get().to(synonyms_handler))
+        .route("/api/synonyms", web::put().to(synonyms_save_handler))
+        .route("/api/synonyms", web::delete().to(synonyms_reset_handler))
 }
 
 
@@ -0,0 +1,477 @@
+//! Bulk availability and price check.
+//!
+//! A partner answering a customer's request for quotation holds a list of
+//! 50–200 codes — article numbers, barcodes, manufacturer part numbers, as the
+//! customer wrote them — and wants to know what is available and at what price.
+//! One `get_product` call per line would take a model minutes and most of its
+//! context, so the list is checked here in one pass against the partner's
+//! snapshot: each line resolved the way `get_product` resolves it, priced, and
+//! held against stock.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    macros::mcp::McpToolArgs,
+    service::mcp::{
+        export::Cell,
+        index::{CatalogSnapshot, ProductView}
+    }
+};
+
+/// Lines accepted in one check. Far beyond an RFQ, and well short of a
+/// catalog export in disguise.
+pub const MAX_ITEMS: usize = 500;
+
+/// Near matches offered for a line that resolves to nothing.
+const SUGGESTIONS: usize = 3;
+
+/// Columns of the spreadsheet a check can be delivered as.
+pub const COLUMNS: [&str; 12] = [
+    "Requested", "Article number", "Name", "Quantity", "Unit", "Price", "Currency",
+    "Line total", "Stock", "Available now", "Shortfall", "Status"
+];
+
+
+McpToolArgs! {
+    #[derive(Clone)]
+    pub struct CheckItem {
+        /// Article number, barcode (EAN), manufacturer part number or internal
+        /// record id, as the customer wrote it.
+        pub id: String,
+        /// How many are wanted. 1 when omitted.
+        #[serde(default, deserialize_with = "super::tools::lenient_count")]
+        pub quantity: Option<u32>
+    }
+}
+
+
+/// Where a line stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// Stock covers the whole quantity.
+    InStock,
+    /// Some stock, less than asked for.
+    Partial,
+    OutOfStock,
+    /// The product exists but is not published for sale.
+    NotOffered,
+    NotFound
+}
+
+impl Status {
+    fn describe(self) -> &'static str {
+        match self {
+            Status::InStock => "In stock",
+            Status::Partial => "Partly in stock",
+            Status::OutOfStock => "Out of stock",
+            Status::NotOffered => "Not offered",
+            Status::NotFound => "Not found"
+        }
+    }
+}
+
+
+/// A near match for a line that resolved to nothing.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Suggestion {
+    pub no: String,
+    pub name: String
+}
+
+/// One checked line.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Line {
+    /// The code as it was asked for.
+    pub requested: String,
+    pub quantity: u32,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    /// What this partner pays per unit. Same figure as `search_products`'.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// `price` times `quantity`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_total: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stock: Option<f64>,
+    /// How much of `quantity` stock covers now.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available: Option<f64>,
+    /// How much of `quantity` it does not.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shortfall: Option<f64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub suggestions: Vec<Suggestion>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>
+}
+
+/// The priced lines' sum in one currency.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Total {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// Every priced line at its full quantity.
+    pub amount: f64,
+    /// Only what stock covers now.
+    pub available_amount: f64
+}
+
+/// Counts per status, and the totals.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct Summary {
+    pub lines: usize,
+    pub in_stock: usize,
+    pub partial: usize,
+    pub out_of_stock: usize,
+    pub not_offered: usize,
+    pub not_found: usize,
+    /// Offered lines without a price, left out of the totals.
+    pub unpriced: usize,
+    pub totals: Vec<Total>
+}
+
+/// A whole check.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Check {
+    pub summary: Summary,
+    pub lines: Vec<Line>
+}
+
+
+/// Checks a list against a partner's snapshot, line by line in the order given.
+/// Refuses an empty list, one longer than [`MAX_ITEMS`], a blank code or a
+/// zero quantity, naming the line.
+pub fn check(snapshot: &CatalogSnapshot, items: &[CheckItem]) -> Result<Check, String> {
+    if items.is_empty() {
+        return Err("the list is empty: pass at least one item".into())
+    }
+    if items.len() > MAX_ITEMS {
+        return Err(format!("{} items is more than the {} one check takes; split the list", items.len(), MAX_ITEMS))
+    }
+
+    let mut lines = Vec::with_capacity(items.len());
+    for (index, item) in items.iter().enumerate() {
+        let requested = item.id.trim();
+        if requested.is_empty() {
+            return Err(format!("item {} has no id", index + 1))
+        }
+        let quantity = match item.quantity {
+            Some(0) => return Err(format!("item {} ('{}') asks for a quantity of 0", index + 1, requested)),
+            Some(quantity) => quantity,
+            None => 1
+        };
+        lines.push(match snapshot.get_by_no(requested) {
+            Some(view) => found(requested, quantity, view),
+            None => missing(requested, quantity, snapshot)
+        });
+    }
+
+    Ok(Check { summary: summarize(&lines), lines })
+}
+
+
+/// What a line is being taken into, for the wording of its refusals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Purpose {
+    Quote,
+    Order
+}
+
+impl Purpose {
+    fn noun(self) -> &'static str {
+        match self {
+            Purpose::Quote => "quote",
+            Purpose::Order => "order"
+        }
+    }
+
+    fn verb(self) -> &'static str {
+        match self {
+            Purpose::Quote => "quoted",
+            Purpose::Order => "ordered"
+        }
+    }
+}
+
+/// A line that can be sold to the partner: its product, the quantity wanted
+/// and the partner's price.
+pub struct Orderable<'a> {
+    pub view: ProductView<'a>,
+    pub quantity: u32,
+    pub price: f64
+}
+
+/// Resolves line `index` of a quote or an order the way [`check`] resolves a
+/// line, and refuses it unless the partner can buy it: found, published for
+/// sale, priced, and priced in `currency` — the currency of the lines already
+/// taken, `None` while there are none. The one place these rules live, so a
+/// quote never promises what an order would refuse.
+pub fn orderable<'a>(
+    snapshot: &'a CatalogSnapshot,
+    index: usize,
+    item: &CheckItem,
+    purpose: Purpose,
+    currency: Option<&Option<String>>
+) -> Result<Orderable<'a>, String> {
+    let requested = item.id.trim();
+    if requested.is_empty() {
+        return Err(format!("line {} has no id", index + 1))
+    }
+    let quantity = match item.quantity {
+        Some(0) => return Err(format!("'{}' cannot be {} at a quantity of 0; leave it out instead", requested, purpose.verb())),
+        Some(quantity) => quantity,
+        None => 1
+    };
+    let Some(view) = snapshot.get_by_no(requested) else {
+        let suggestions = snapshot.did_you_mean(requested, SUGGESTIONS);
+        return Err(if suggestions.is_empty() {
+            format!("no product '{}'", requested)
+        } else {
+            format!(
+                "no product '{}' — did you mean {}",
+                requested,
+                suggestions.iter().map(|s| format!("{} ({})", s.no, s.name)).collect::<Vec<_>>().join(", ")
+            )
+        })
+    };
+    let (product, offer) = (&view.product, view.offer);
+    if !offer.available {
+        return Err(format!("'{}' is not currently published for sale, so it cannot be {}", product.no, purpose.verb()))
+    }
+    let Some(price) = offer.price else {
+        return Err(format!("'{}' has no price for this partner, so it cannot be {}", product.no, purpose.verb()))
+    };
+    if let Some(held) = currency
+        && offer.currency != *held {
+            return Err(format!(
+                "'{}' is priced in {}, the {} in {}; one {} takes one currency",
+                product.no,
+                offer.currency.as_deref().unwrap_or("no currency"),
+                purpose.noun(),
+                held.as_deref().unwrap_or("no currency"),
+                purpose.noun()
+            ))
+    }
+    Ok(Orderable { view, quantity, price })
+}
+
+
+/// Money to the cent, as quotes and orders total it.
+pub fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+
+fn found(requested: &str, quantity: u32, ProductView { product, offer }: ProductView<'_>) -> Line {
+    let wanted = f64::from(quantity);
+    let stock = offer.stock.unwrap_or(0.0).max(0.0);
+    let status = if !offer.available {
+        Status::NotOffered
+    } else if stock >= wanted {
+        Status::InStock
+    } else if stock > 0.0 {
+        Status::Partial
+    } else {
+        Status::OutOfStock
+    };
+    let offered = status != Status::NotOffered;
+    let available = offered.then(|| stock.min(wanted));
+
+    // Octopus's `sell_unit` is the pack a product is sold in; a quantity off
+    // the pack will be rounded by whoever takes the order.
+    let note = match product.sell_unit.filter(|unit| *unit > 1.0) {
+        Some(unit) if offered && wanted % unit != 0.0 => Some(format!("Sold in multiples of {}.", unit)),
+        _ if !offered => Some("Exists in the catalog but is not currently published for sale.".to_string()),
+        _ => None
+    };
+
+    Line {
+        requested: requested.to_string(),
+        quantity,
+        status,
+        no: Some(product.no.clone()),
+        name: Some(product.name.clone()),
+        unit: product.unit.clone(),
+        price: offer.price.filter(|_| offered),
+        currency: offer.currency.clone().filter(|_| offered),
+        line_total: offer.price.filter(|_| offered).map(|price| price * wanted),
+        stock: offer.stock.filter(|_| offered),
+        available,
+        shortfall: available.map(|available| wanted - available),
+        suggestions: Vec::new(),
+        note
+    }
+}
+
+
+fn missing(requested: &str, quantity: u32, snapshot: &CatalogSnapshot) -> Line {
+    Line {
+        requested: requested.to_string(),
+        quantity,
+        status: Status::NotFound,
+        no: None,
+        name: None,
+        unit: None,
+        price: None,
+        currency: None,
+        line_total: None,
+        stock: None,
+        available: None,
+        shortfall: None,
+        suggestions: snapshot.did_you_mean(requested, SUGGESTIONS)
+            .into_iter()
+            .map(|summary| Suggestion { no: summary.no, name: summary.name })
+            .collect(),
+        note: None
+    }
+}
+
+
+fn summarize(lines: &[Line]) -> Summary {
+    let mut summary = Summary { lines: lines.len(), ..Summary::default() };
+    let mut totals: BTreeMap<Option<String>, (f64, f64)> = BTreeMap::new();
+
+    for line in lines {
+        match line.status {
+            Status::InStock => summary.in_stock += 1,
+            Status::Partial => summary.partial += 1,
+            Status::OutOfStock => summary.out_of_stock += 1,
+            Status::NotOffered => summary.not_offered += 1,
+            Status::NotFound => summary.not_found += 1
+        }
+        if matches!(line.status, Status::NotOffered | Status::NotFound) {
+            continue
+        }
+        match line.price {
+            Some(price) => {
+                let (amount, available) = totals.entry(line.currency.clone()).or_default();
+                *amount += price * f64::from(line.quantity);
+                *available += price * line.available.unwrap_or(0.0);
+            }
+            None => summary.unpriced += 1
+        }
+    }
+
+    summary.totals = totals.into_iter()
+        .map(|(currency, (amount, available_amount))| Total { currency, amount, available_amount })
+        .collect();
+    summary
+}
+
+
+/// The check as spreadsheet rows under [`COLUMNS`].
+pub fn rows(check: &Check) -> Vec<Vec<Cell>> {
+    let text = |value: &Option<String>| value.clone().map_or(Cell::Empty, Cell::Text);
+    let number = |value: Option<f64>| value.map_or(Cell::Empty, Cell::Number);
+    check.lines.iter()
+        .map(|line| vec![
+            Cell::Text(line.requested.clone()),
+            text(&line.no),
+            text(&line.name),
+            Cell::Number(f64::from(line.quantity)),
+            text(&line.unit),
+            number(line.price),
+            text(&line.currency),
+            number(line.line_total),
+            number(line.stock),
+            number(line.available),
+            number(line.shortfall),
+            Cell::Text(line.status.describe().to_string())
+        ])
+        .collect()
+}
+
+
+/// A line asking for `quantity` of `id`, for the tools' tests.
+#[cfg(test)]
+pub fn test_item(id: &str, quantity: impl Into<Option<u32>>) -> CheckItem {
+    CheckItem { id: id.into(), quantity: quantity.into() }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::mcp::index::{Offer, test_product, test_snapshot_of};
+
+    fn snapshot() -> CatalogSnapshot {
+        let mut boxed = test_product("B-10", "Füzet A4", "Pax", "");
+        boxed.sell_unit = Some(10.0);
+        let mut coded = test_product("T-1", "Toll", "Pax", "MFG-77");
+        coded.barcodes = vec!["5999000000011".into()];
+        let offer = |price: f64, stock: f64| Offer { available: true, price: Some(price), currency: Some("HUF".into()), stock: Some(stock) };
+        test_snapshot_of(vec![
+            (coded, offer(100.0, 50.0)),
+            (boxed, offer(250.0, 4.0)),
+            (test_product("W-1", "Withheld", "Pax", ""), Offer { available: false, ..offer(999.0, 9.0) }),
+            (test_product("E-1", "Empty", "Pax", ""), offer(80.0, 0.0))
+        ])
+    }
+
+    #[test]
+    fn each_line_is_resolved_priced_and_held_against_stock() {
+        let checked = check(&snapshot(), &[
+            test_item("5999000000011", Some(20)),
+            test_item("mfg-77", None),
+            test_item("B-10", Some(12)),
+            test_item("E-1", Some(3)),
+            test_item("W-1", Some(1)),
+            test_item("B-100", Some(1))
+        ]).expect("checks");
+
+        let statuses: Vec<Status> = checked.lines.iter().map(|line| line.status).collect();
+        assert_eq!(statuses, [Status::InStock, Status::InStock, Status::Partial, Status::OutOfStock, Status::NotOffered, Status::NotFound]);
+
+        let by_barcode = &checked.lines[0];
+        assert_eq!((by_barcode.no.as_deref(), by_barcode.line_total, by_barcode.shortfall), (Some("T-1"), Some(2000.0), Some(0.0)));
+        assert_eq!(checked.lines[1].quantity, 1);
+
+        let short = &checked.lines[2];
+        assert_eq!((short.available, short.shortfall, short.line_total), (Some(4.0), Some(8.0), Some(3000.0)));
+        assert_eq!(short.note.as_deref(), Some("Sold in multiples of 10."));
+
+        // A withheld product shows no figures; an unknown one offers near codes.
+        assert_eq!((checked.lines[4].price, checked.lines[4].line_total), (None, None));
+        assert_eq!(checked.lines[5].suggestions.first().map(|s| s.no.as_str()), Some("B-10"));
+
+        let summary = &checked.summary;
+        assert_eq!((summary.in_stock, summary.partial, summary.out_of_stock, summary.not_offered, summary.not_found), (2, 1, 1, 1, 1));
+        assert_eq!(summary.totals, [Total { currency: Some("HUF".into()), amount: 5340.0, available_amount: 3100.0 }]);
+        assert_eq!(rows(&checked)[0].len(), COLUMNS.len());
+    }
+
+    #[test]
+    fn bad_lists_are_refused_naming_the_line() {
+        let snapshot = snapshot();
+        assert!(check(&snapshot, &[]).is_err());
+        assert_eq!(check(&snapshot, &[test_item("T-1", None), test_item(" ", None)]).err().as_deref(), Some("item 2 has no id"));
+        assert!(check(&snapshot, &[test_item("T-1", Some(0))]).is_err());
+        let too_many: Vec<CheckItem> = (0..=MAX_ITEMS).map(|_| test_item("T-1", None)).collect();
+        assert!(check(&snapshot, &too_many).is_err());
+    }
+
+    #[test]
+    fn a_line_is_orderable_only_when_found_published_priced_and_in_currency() {
+        let snapshot = snapshot();
+        let line = orderable(&snapshot, 0, &test_item("mfg-77", None), Purpose::Order, None).expect("orderable");
+        assert_eq!((line.view.product.no.as_str(), line.quantity, line.price), ("T-1", 1, 100.0));
+
+        let refusal = |id: &str, quantity: Option<u32>, purpose: Purpose, currency: Option<&Option<String>>| {
+            orderable(&snapshot, 0, &test_item(id, quantity), purpose, currency).err().unwrap_or_default()
+        };
+        assert_eq!(refusal(" ", None, Purpose::Quote, None), "line 1 has no id");
+        assert!(refusal("T-1", Some(0), Purpose::Quote, None).contains("cannot be quoted"));
+        assert!(refusal("W-1", None, Purpose::Order, None).contains("cannot be ordered"));
+        assert!(refusal("B-100", None, Purpose::Order, None).contains("did you mean B-10"));
+        assert!(refusal("T-1", None, Purpose::Quote, Some(&Some("EUR".into()))).contains("one quote takes one currency"));
+    }
+}
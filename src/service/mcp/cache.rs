@@ -8,20 +8,33 @@
 //!
 //! Deliberately scoped to MCP. Caching inside `soap.rs` would silently change
 //! all eight existing REST endpoints, whose consumers expect a live read.
-
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-
+//!
+//! ## Shared bases
+//!
+//! The entries are partner snapshots, weighed by their own overlay. The base
+//! catalog they are laid over (`service/mcp/index`) is shared by every snapshot
+//! of one url and held once, for as long as any of them is — so `max_bytes`
+//! budgets the overlays, and each url with a resident partner adds its base on
+//! top. Builds for one url take turns merging into its base, which keeps two
+//! partners refreshed at once from each publishing a base without the other's
+//! rows. A snapshot keeps the base it was built against until it is rebuilt, so
+//! a replaced base lingers until the next sweep has rebuilt them.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, Weak};
+
+use chrono::{DateTime, Utc};
 use moka::future::Cache;
 use moka::policy::EvictionPolicy;
 use once_cell::sync::Lazy;
 use sha2::{Digest, Sha256};
+use tokio::sync::Mutex as AsyncMutex;
 
 use crate::service::{
     config::get_mcp_settings,
     log::{elogger, logger},
     mcp::{
-        index::{CatalogSnapshot, SnapshotError, build_snapshot},
+        index::{BaseCatalog, CatalogSnapshot, SnapshotError, build_snapshot, fetch_parts, log_build, refresh_snapshot},
         store
     }
 };
@@ -31,14 +44,9 @@ use crate::service::{
 /// The authcode is present only as a hash — the code itself must never reach a
 /// key, a log line, a dashboard response or an error message.
 ///
-/// `pid` is part of the key because prices are partner-specific. Note the cost:
-/// of the three operations a snapshot merges, only `GetArlistaAuth` (prices)
-/// takes a pid — `GetCikkekAuth` and `GetCikkekKeszletValtozasAuth` take an
-/// authcode alone — so two pids under one authcode hold two copies of identical
-/// master data and differ only in the price columns. That duplication is an
-/// accepted trade for a key that is trivially correct; if the per-snapshot
-/// `bytes` logged at build time shows it dominating the budget, splitting master
-/// data into its own `(auth, url)` tier is the fix.
+/// `pid` is part of the key because prices are partner-specific. Only the
+/// overlay is keyed this way: the master data every key of one url has in
+/// common lives once, in the url's shared base.
 #[derive(Hash, Eq, PartialEq, Clone, Debug)]
 pub struct CacheKey {
     pub auth_hash: [u8; 32],
@@ -62,12 +70,23 @@ impl CacheKey {
 /// SHA-256 of an authcode. One-way by construction: nothing in this service ever
 /// needs to recover the code from a key.
 pub fn hash_authcode(authcode: &str) -> [u8; 32] {
+    digest(authcode)
+}
+
+
+fn digest(text: &str) -> [u8; 32] {
     let mut hasher = Sha256::new();
-    hasher.update(authcode.as_bytes());
+    hasher.update(text.as_bytes());
     hasher.finalize().into()
 }
 
 
+/// [`fingerprint`] of a url, naming its base file.
+pub fn url_fingerprint(url: &str) -> String {
+    fingerprint(&digest(url))
+}
+
+
 /// The first four bytes of an authcode hash, hex-encoded — a stable identifier
 /// that can safely appear in a URL, a log line or a dashboard row.
 pub fn fingerprint(auth_hash: &[u8; 32]) -> String {
@@ -84,7 +103,7 @@ pub fn fingerprint(auth_hash: &[u8; 32]) -> String {
 pub struct EntryStats {
     pub hits: u64,
     pub misses: u64,
-    /// Size of the snapshot as last inserted, in bytes.
+    /// Size of the snapshot's own overlay as last inserted, in bytes.
     pub bytes: u64,
     /// How long the last build took, in milliseconds.
     pub last_build_ms: u64,
@@ -100,10 +119,23 @@ impl EntryStats {
 }
 
 
+/// One url's base as the cache tracks it.
+#[derive(Default)]
+struct SharedBase {
+    /// The newest base, for as long as any snapshot holds it.
+    current: Weak<BaseCatalog>,
+    /// Held by a build from reading the base until its merged successor is
+    /// published.
+    merging: Arc<AsyncMutex<()>>
+}
+
+
 /// The snapshot cache plus its bookkeeping.
 pub struct SnapshotCache {
     entries: Cache<CacheKey, Arc<CatalogSnapshot>>,
     stats: Mutex<HashMap<CacheKey, EntryStats>>,
+    /// Per url.
+    bases: Mutex<HashMap<String, SharedBase>>,
     budget_bytes: u64,
     /// Whether inserts are mirrored to the disk tier. Off in unit tests, which
     /// otherwise would write snapshot files into the working directory.
@@ -119,9 +151,9 @@ impl SnapshotCache {
         let entries = Cache::builder()
             .max_capacity(budget_bytes)
             // Weigh entries by their measured size so the budget is in bytes,
-            // not entry count: one combo's catalog is tens of megabytes. `min`
-            // caps at u32 because moka's weigher is u32-wide; a single snapshot
-            // that large would already be a bug worth noticing.
+            // not entry count: one combo's overlay is a couple of megabytes.
+            // `min` caps at u32 because moka's weigher is u32-wide; a single
+            // snapshot that large would already be a bug worth noticing.
             .weigher(|_key, value: &Arc<CatalogSnapshot>| value.bytes.min(u32::MAX as u64) as u32)
             .time_to_live(std::time::Duration::from_secs(ttl_secs))
             // Explicitly LRU, not moka's default TinyLFU, and not oldest-by-
@@ -136,6 +168,7 @@ impl SnapshotCache {
         Self {
             entries,
             stats: Mutex::new(HashMap::new()),
+            bases: Mutex::new(HashMap::new()),
             budget_bytes,
             mirror_to_disk
         }
@@ -146,11 +179,21 @@ impl SnapshotCache {
         self.budget_bytes
     }
 
-    /// Bytes currently held. moka evicts asynchronously, so this can briefly
+    /// Bytes currently held: the overlays, plus each base a resident snapshot
+    /// holds, counted once. moka evicts asynchronously, so this can briefly
     /// exceed the budget after a burst of large inserts — which is why the
     /// configured budget should sit ~20% below the container memory limit.
     pub fn used_bytes(&self) -> u64 {
-        self.entries.weighted_size()
+        self.entries.weighted_size() + self.base_bytes()
+    }
+
+    /// Bytes of the distinct bases resident snapshots hold.
+    pub fn base_bytes(&self) -> u64 {
+        let mut seen = HashSet::new();
+        self.entries.iter()
+            .filter(|(_, snapshot)| seen.insert(Arc::as_ptr(&snapshot.base)))
+            .map(|(_, snapshot)| snapshot.base.bytes)
+            .sum()
     }
 
     pub fn entry_count(&self) -> u64 {
@@ -210,13 +253,10 @@ impl SnapshotCache {
         // up request coalescing — two simultaneous callers each load their own
         // copy, which is part of what the setting costs.
         if !self.memory_enabled() {
-            let for_disk = key.clone();
-            match actix_web::web::block(move || store::read(&for_disk)).await {
-                Ok(Some(snapshot)) => return Ok(Arc::new(snapshot)),
-                Ok(None) => (),
-                Err(error) => elogger(format!("MCP cache: disk read failed to run: {}", error))
+            if let Some(snapshot) = self.load(&key).await {
+                return Ok(Arc::new(snapshot))
             }
-            let snapshot = Arc::new(build_snapshot(authcode, pid, url, None).await?);
+            let snapshot = Arc::new(self.fetch(authcode, pid, url, None).await?);
             write_through(key, snapshot.clone()).await;
             return Ok(snapshot)
         }
@@ -225,18 +265,12 @@ impl SnapshotCache {
         let for_disk = key.clone();
         let snapshot = self.entries
             .try_get_with(key.clone(), async move {
-                // Reading a ~46 MB snapshot back is blocking, CPU-bound work.
-                // Run it off the async workers so a cold query cannot stall the
-                // REST endpoints sharing this runtime.
-                let from_disk = actix_web::web::block(move || store::read(&for_disk)).await;
-                match from_disk {
-                    Ok(Some(snapshot)) => return Ok(Arc::new(snapshot)),
-                    Ok(None) => (),
-                    Err(error) => elogger(format!("MCP cache: disk read failed to run: {}", error))
+                if let Some(snapshot) = self.load(&for_disk).await {
+                    return Ok(Arc::new(snapshot))
                 }
 
-                let snapshot = Arc::new(build_snapshot(authcode, pid, url, None).await?);
-                write_through(CacheKey::new(authcode, pid, url), snapshot.clone()).await;
+                let snapshot = Arc::new(self.fetch(authcode, pid, url, None).await?);
+                write_through(for_disk, snapshot.clone()).await;
                 Ok(snapshot)
             })
             .await?;
@@ -245,6 +279,114 @@ impl SnapshotCache {
         Ok(snapshot)
     }
 
+    /// A partner's snapshot from the disk tier: the stored overlay over the
+    /// url's base, which is shared with every snapshot of the url already in
+    /// memory. `None` when either is missing or unreadable.
+    ///
+    /// Reading tens of megabytes back is blocking, CPU-bound work, so it runs
+    /// off the async workers and a cold query cannot stall the REST endpoints
+    /// sharing this runtime.
+    pub async fn load(&self, key: &CacheKey) -> Option<CatalogSnapshot> {
+        let for_disk = key.clone();
+        let overlay = match actix_web::web::block(move || store::read(&for_disk)).await {
+            Ok(overlay) => overlay?,
+            Err(error) => {
+                elogger(format!("MCP cache: disk read failed to run: {}", error));
+                return None
+            }
+        };
+        let base = self.base(&key.url).await?;
+        Some(CatalogSnapshot::resolve(base, overlay.offers, overlay.fetched_at))
+    }
+
+    /// Builds a partner's snapshot from Octopus: a full pull, or — given the
+    /// previous snapshot and when it was fetched — only what changed since.
+    ///
+    /// The pull runs unlocked; merging into the url's base and publishing the
+    /// result happen one build at a time. A merge that changes nothing keeps
+    /// the base, and nothing is written.
+    pub async fn fetch(
+        &self,
+        authcode: &str,
+        pid: i64,
+        url: &str,
+        previous: Option<(&CatalogSnapshot, DateTime<Utc>)>
+    ) -> Result<CatalogSnapshot, SnapshotError> {
+        let started = std::time::Instant::now();
+        let parts = fetch_parts(authcode, pid, url, previous.map(|(_, since)| since)).await?;
+        let pulled = parts.product_count();
+
+        let merging = self.merge_lock(url);
+        let _merging = merging.lock().await;
+        let base = self.base(url).await;
+        let snapshot = match previous {
+            Some((previous, _)) => refresh_snapshot(base.as_ref(), previous, parts),
+            None => build_snapshot(base.as_ref(), parts)
+        };
+        if base.is_none_or(|base| !Arc::ptr_eq(&base, &snapshot.base)) {
+            self.publish_base(url, &snapshot.base).await;
+        }
+        drop(_merging);
+
+        let what = match previous {
+            Some(_) => format!("refreshed ({} changed)", pulled),
+            None => "built".to_string()
+        };
+        log_build(&what, authcode, pid, &snapshot, started);
+        Ok(snapshot)
+    }
+
+    /// The url's current base: the one resident snapshots share, else the one
+    /// on disk.
+    async fn base(&self, url: &str) -> Option<Arc<BaseCatalog>> {
+        if let Some(base) = self.bases.lock().ok()?.get(url).and_then(|shared| shared.current.upgrade()) {
+            return Some(base)
+        }
+        if !self.mirror_to_disk {
+            return None
+        }
+
+        let for_disk = url.to_string();
+        let base = match actix_web::web::block(move || store::read_base(&for_disk)).await {
+            Ok(base) => Arc::new(base?),
+            Err(error) => {
+                elogger(format!("MCP cache: disk read failed to run: {}", error));
+                return None
+            }
+        };
+        // Two partners loading at once both read the file; whichever registers
+        // first is the copy both keep.
+        let mut bases = self.bases.lock().ok()?;
+        let shared = bases.entry(url.to_string()).or_default();
+        match shared.current.upgrade() {
+            Some(current) => Some(current),
+            None => {
+                shared.current = Arc::downgrade(&base);
+                Some(base)
+            }
+        }
+    }
+
+    fn merge_lock(&self, url: &str) -> Arc<AsyncMutex<()>> {
+        match self.bases.lock() {
+            Ok(mut bases) => bases.entry(url.to_string()).or_default().merging.clone(),
+            // Unlocked rather than failed: the worst a lost merge costs is a
+            // partner missing a new row until its next full pull.
+            Err(_) => Arc::new(AsyncMutex::new(()))
+        }
+    }
+
+    /// Makes a freshly merged base the url's current one, in memory and on
+    /// disk.
+    async fn publish_base(&self, url: &str, base: &Arc<BaseCatalog>) {
+        if let Ok(mut bases) = self.bases.lock() {
+            bases.entry(url.to_string()).or_default().current = Arc::downgrade(base);
+        }
+        if self.mirror_to_disk {
+            write_base_through(url.to_string(), base.clone()).await;
+        }
+    }
+
     /// Publishes an already-built snapshot to both tiers, replacing any previous
     /// one.
     ///
@@ -309,7 +451,7 @@ impl SnapshotCache {
             entry.bytes = snapshot.bytes;
             entry.last_build_ms = build_ms;
             entry.last_built_at = Some(snapshot.fetched_at);
-            entry.products = snapshot.products.len();
+            entry.products = snapshot.product_count();
         }
     }
 
@@ -321,22 +463,23 @@ impl SnapshotCache {
 }
 
 
-/// Mirrors a snapshot to the disk tier.
+/// Mirrors a snapshot's overlay to the disk tier. Its base is written when it
+/// is published, by [`write_base_through`].
 ///
-/// Serializing and gzipping ~46 MB is seconds of CPU-bound work, so it runs on a
-/// blocking thread. Doing it inline would park an async worker for the whole
-/// write — and this runtime also serves the REST endpoints.
+/// Encoding is CPU-bound work, so it runs on a blocking thread.
+/// Doing it inline would park an async worker for the whole write — and this
+/// runtime also serves the REST endpoints.
 ///
 /// A disk failure is logged and swallowed: the snapshot is already in memory and
 /// the query it was built for can be answered. Losing the write only costs a
 /// rebuild later, which is not worth failing a live request over.
 async fn write_through(key: CacheKey, snapshot: Arc<CatalogSnapshot>) {
-    let products = snapshot.products.len();
+    let products = snapshot.product_count();
     let started = std::time::Instant::now();
 
     match actix_web::web::block(move || store::write(&key, &snapshot)).await {
         Ok(Ok(size)) => logger(format!(
-            "MCP cache: mirrored {} products to disk ({:.1} MB compressed, {:.1}s)",
+            "MCP cache: mirrored {} offers to disk ({:.1} MB, {:.1}s)",
             products,
             size as f64 / 1_048_576.0,
             started.elapsed().as_secs_f64()
@@ -347,6 +490,26 @@ async fn write_through(key: CacheKey, snapshot: Arc<CatalogSnapshot>) {
 }
 
 
+/// Mirrors a url's base to the disk tier — ~46 MB of master data, so seconds
+/// of work, paid only when a build changed it. Failures are swallowed like
+/// [`write_through`]'s.
+async fn write_base_through(url: String, base: Arc<BaseCatalog>) {
+    let products = base.len();
+    let started = std::time::Instant::now();
+
+    match actix_web::web::block(move || store::write_base(&url, &base)).await {
+        Ok(Ok(size)) => logger(format!(
+            "MCP cache: mirrored the shared base of {} products to disk ({:.1} MB, {:.1}s)",
+            products,
+            size as f64 / 1_048_576.0,
+            started.elapsed().as_secs_f64()
+        )),
+        Ok(Err(error)) => elogger(format!("MCP cache: could not write base to disk: {}", error)),
+        Err(error) => elogger(format!("MCP cache: disk write failed to run: {}", error))
+    }
+}
+
+
 /// Process-wide cache, built on first use from `[mcp]`.
 ///
 /// Because it is lazy, a server started with `[mcp] enabled = false` never
@@ -372,7 +535,7 @@ pub fn cache() -> &'static SnapshotCache {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::service::mcp::index::test_snapshot;
+    use crate::service::mcp::index::{test_snapshot, test_snapshot_with_products};
 
     const MB: u64 = 1_048_576;
 
@@ -533,4 +696,20 @@ mod tests {
         assert_eq!(entry.last_build_ms, 1234);
         assert!((entry.hit_rate() - 2.0 / 3.0).abs() < f64::EPSILON);
     }
+
+    #[actix_web::test]
+    async fn a_shared_base_is_counted_once() {
+        let cache = SnapshotCache::build(100 * MB, 3600, false);
+        let first = test_snapshot_with_products(10);
+        let base_bytes = first.base.bytes;
+        let second = CatalogSnapshot::resolve(first.base.clone(), HashMap::new(), chrono::Utc::now());
+        let overlays = first.bytes + second.bytes;
+
+        cache.insert(key("AAAA1111BBBB2222", 1), Arc::new(first), 0).await;
+        cache.insert(key("AAAA1111BBBB2222", 2), Arc::new(second), 0).await;
+        cache.settle().await;
+
+        assert_eq!(cache.base_bytes(), base_bytes);
+        assert_eq!(cache.used_bytes(), overlays + base_bytes);
+    }
 }
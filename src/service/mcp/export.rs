@@ -31,7 +31,7 @@ use crate::service::{
     config::get_mcp_settings,
     ipv4::log_ip,
     log::{elog_with_ip, elogger, log_with_ip, logger},
-    mcp::index::IndexedProduct,
+    mcp::index::ProductView,
     path::get_current_or_root_dir
 };
 
@@ -84,7 +84,7 @@ impl Format {
         }
     }
 
-    fn content_type(&self) -> &'static str {
+    pub fn content_type(&self) -> &'static str {
         match self {
             Format::Xlsx => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
             Format::Csv => "text/csv; charset=utf-8"
@@ -160,7 +160,8 @@ fn new_token() -> String {
 
 
 /// Renders one product field as text, for CSV.
-fn field_text(product: &IndexedProduct, key: &str) -> String {
+fn field_text(row: &ProductView, key: &str) -> String {
+    let (product, offer) = (&row.product, row.offer);
     match key {
         "no" => product.no.clone(),
         "name" => product.name.clone(),
@@ -177,11 +178,11 @@ fn field_text(product: &IndexedProduct, key: &str) -> String {
         "main_category_name" => product.main_category_name.clone().unwrap_or_default(),
         "unit" => product.unit.clone().unwrap_or_default(),
         "base_unit" => product.base_unit.clone().unwrap_or_default(),
-        "currency" => product.currency.clone().unwrap_or_default(),
+        "currency" => offer.currency.clone().unwrap_or_default(),
         "origin_country" => product.origin_country.clone().unwrap_or_default(),
         "base_unit_qty" => product.base_unit_qty.map(|v| v.to_string()).unwrap_or_default(),
-        "price" => product.price.map(|v| v.to_string()).unwrap_or_default(),
-        "stock" => product.stock.map(|v| v.to_string()).unwrap_or_default(),
+        "price" => offer.price.map(|v| v.to_string()).unwrap_or_default(),
+        "stock" => offer.stock.map(|v| v.to_string()).unwrap_or_default(),
         "weight" => product.weight.map(|v| v.to_string()).unwrap_or_default(),
         _ => String::new()
     }
@@ -189,22 +190,71 @@ fn field_text(product: &IndexedProduct, key: &str) -> String {
 
 /// The numeric fields, which go into a spreadsheet as numbers rather than text
 /// so they can be summed and sorted without the recipient retyping them.
-fn field_number(product: &IndexedProduct, key: &str) -> Option<f64> {
+fn field_number(row: &ProductView, key: &str) -> Option<f64> {
     match key {
-        "base_unit_qty" => product.base_unit_qty,
-        "price" => product.price,
-        "stock" => product.stock,
-        "weight" => product.weight,
+        "base_unit_qty" => row.product.base_unit_qty,
+        "price" => row.offer.price,
+        "stock" => row.offer.stock,
+        "weight" => row.product.weight,
         _ => None
     }
 }
 
 
+/// One cell of a table written by [`write_table`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cell {
+    Text(String),
+    /// Written as a number to a spreadsheet, so it can be summed.
+    Number(f64),
+    Empty
+}
+
+impl Cell {
+    fn text(&self) -> String {
+        match self {
+            Cell::Text(text) => text.clone(),
+            Cell::Number(number) => number.to_string(),
+            Cell::Empty => String::new()
+        }
+    }
+}
+
+
 /// Writes the rows to a file and registers a download token for it.
 ///
 /// Blocking and CPU-bound by nature — callers must run it through
 /// `web::block` rather than on an async worker.
-pub fn write(rows: Vec<&IndexedProduct>, format: Format) -> Result<Prepared, String> {
+pub fn write(rows: Vec<ProductView>, format: Format) -> Result<Prepared, String> {
+    deliver("orink-products", format.extension(), format.content_type(), |path| match format {
+        Format::Xlsx => write_xlsx(&rows, path),
+        Format::Csv => write_csv(&rows, path)
+    })
+}
+
+
+/// Writes a small table of its own columns — a price check, say, rather than
+/// catalog rows — and registers a download token for it, exactly like
+/// [`write`]. `stem` starts the file name the browser saves.
+pub fn write_table(stem: &str, headers: &[&str], rows: &[Vec<Cell>], format: Format) -> Result<Prepared, String> {
+    deliver(stem, format.extension(), format.content_type(), |path| match format {
+        Format::Xlsx => write_table_xlsx(headers, rows, path),
+        Format::Csv => write_table_csv(headers, rows, path)
+    })
+}
+
+
+/// Creates the file through `fill`, restricts it and hands out its link.
+///
+/// Public for files that are neither catalog rows nor a plain table — a
+/// finished quote's workbook and PDF — which lay themselves out but are
+/// guarded, named and expired exactly like an export.
+pub fn deliver(
+    stem: &str,
+    extension: &str,
+    content_type: &'static str,
+    fill: impl FnOnce(&PathBuf) -> Result<(), String>
+) -> Result<Prepared, String> {
     let dir = export_dir();
     if !dir.is_dir() {
         std::fs::create_dir_all(&dir).map_err(|error| format!("cannot create '{:?}': {}", dir, error))?;
@@ -212,13 +262,10 @@ pub fn write(rows: Vec<&IndexedProduct>, format: Format) -> Result<Prepared, Str
     }
 
     let token = new_token();
-    let file_name = format!("orink-products-{}.{}", chrono::Utc::now().format("%Y%m%d-%H%M"), format.extension());
-    let path = dir.join(format!("{}.{}", token, format.extension()));
+    let file_name = format!("{}-{}.{}", stem, chrono::Utc::now().format("%Y%m%d-%H%M"), extension);
+    let path = dir.join(format!("{}.{}", token, extension));
 
-    match format {
-        Format::Xlsx => write_xlsx(&rows, &path)?,
-        Format::Csv => write_csv(&rows, &path)?
-    }
+    fill(&path)?;
     restrict(&path, 0o600);
 
     let bytes = std::fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
@@ -227,7 +274,7 @@ pub fn write(rows: Vec<&IndexedProduct>, format: Format) -> Result<Prepared, Str
         tokens.insert(token.clone(), Entry {
             path,
             file_name: file_name.clone(),
-            content_type: format.content_type(),
+            content_type,
             created: Instant::now()
         });
     }
@@ -251,7 +298,7 @@ fn public_base_url() -> String {
 }
 
 
-fn write_xlsx(rows: &[&IndexedProduct], path: &PathBuf) -> Result<(), String> {
+fn write_xlsx(rows: &[ProductView], path: &PathBuf) -> Result<(), String> {
     use rust_xlsxwriter::{Format as XlsxFormat, Workbook};
 
     let mut workbook = Workbook::new();
@@ -282,7 +329,7 @@ fn write_xlsx(rows: &[&IndexedProduct], path: &PathBuf) -> Result<(), String> {
 }
 
 
-fn write_csv(rows: &[&IndexedProduct], path: &PathBuf) -> Result<(), String> {
+fn write_csv(rows: &[ProductView], path: &PathBuf) -> Result<(), String> {
     // Semicolon-delimited, matching what the REST endpoints produce — and what a
     // Hungarian Excel opens without an import dialog.
     let mut writer = csv::WriterBuilder::new()
@@ -302,6 +349,51 @@ fn write_csv(rows: &[&IndexedProduct], path: &PathBuf) -> Result<(), String> {
 }
 
 
+fn write_table_xlsx(headers: &[&str], rows: &[Vec<Cell>], path: &PathBuf) -> Result<(), String> {
+    use rust_xlsxwriter::{Format as XlsxFormat, Workbook};
+
+    let mut workbook = Workbook::new();
+    let bold = XlsxFormat::new().set_bold();
+    let worksheet = workbook.add_worksheet();
+
+    for (column, header) in headers.iter().enumerate() {
+        worksheet.write_string_with_format(0, column as u16, *header, &bold)
+            .map_err(|error| error.to_string())?;
+    }
+    worksheet.set_freeze_panes(1, 0).map_err(|error| error.to_string())?;
+
+    for (index, cells) in rows.iter().enumerate() {
+        let row = index as u32 + 1;
+        for (column, cell) in cells.iter().enumerate() {
+            let column = column as u16;
+            match cell {
+                Cell::Text(text) => worksheet.write_string(row, column, text).map_err(|e| e.to_string())?,
+                Cell::Number(number) => worksheet.write_number(row, column, *number).map_err(|e| e.to_string())?,
+                Cell::Empty => continue
+            };
+        }
+    }
+    worksheet.autofit();
+
+    workbook.save(path).map_err(|error| error.to_string())
+}
+
+
+fn write_table_csv(headers: &[&str], rows: &[Vec<Cell>], path: &PathBuf) -> Result<(), String> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(b';')
+        .from_path(path)
+        .map_err(|error| error.to_string())?;
+
+    writer.write_record(headers).map_err(|error| error.to_string())?;
+    for cells in rows {
+        writer.write_record(cells.iter().map(Cell::text)).map_err(|error| error.to_string())?;
+    }
+
+    writer.flush().map_err(|error| error.to_string())
+}
+
+
 /// Resolves a download token to the file it stands for, or `None` when it is
 /// unknown or expired.
 pub fn resolve(token: &str) -> Option<(PathBuf, String, &'static str)> {
@@ -419,7 +511,10 @@ pub fn purge_orphans() {
 
 #[cfg(test)]
 mod tests {
+    use std::borrow::Cow;
+
     use super::*;
+    use crate::service::mcp::index::{IndexedProduct, Offer, test_offer, test_product};
 
     #[test]
     fn format_defaults_to_excel() {
@@ -441,9 +536,15 @@ mod tests {
         assert!(one.chars().all(|c| c.is_ascii_hexdigit()));
     }
 
+    fn view<'a>(product: &'a IndexedProduct, offer: &'a Offer) -> ProductView<'a> {
+        ProductView { product: Cow::Borrowed(product), offer }
+    }
+
     #[test]
     fn every_column_renders_without_panicking() {
-        let product = crate::service::mcp::index::test_product("A-1", "Pen", "Orink", "MFG-1");
+        let product = test_product("A-1", "Pen", "Orink", "MFG-1");
+        let offer = test_offer();
+        let product = view(&product, &offer);
         for (key, _) in COLUMNS {
             let _ = field_text(&product, key);
             let _ = field_number(&product, key);
@@ -459,18 +560,19 @@ mod tests {
 
     #[test]
     fn the_barcode_column_carries_the_main_ean() {
-        let mut product = crate::service::mcp::index::test_product("A-1", "Pen", "Orink", "");
-        assert_eq!(field_text(&product, "barcode"), "", "no codes renders empty, not \"None\"");
+        let mut product = test_product("A-1", "Pen", "Orink", "");
+        let offer = test_offer();
+        assert_eq!(field_text(&view(&product, &offer), "barcode"), "", "no codes renders empty, not \"None\"");
 
         product.barcodes = vec!["5998765432109".into(), "15998765432106".into()];
-        assert_eq!(field_text(&product, "barcode"), "5998765432109");
+        assert_eq!(field_text(&view(&product, &offer), "barcode"), "5998765432109");
     }
 
     #[test]
     fn numeric_columns_are_written_as_numbers() {
-        let mut product = crate::service::mcp::index::test_product("A-1", "Pen", "Orink", "");
-        product.price = Some(110.5);
-        product.stock = Some(43.0);
+        let product = test_product("A-1", "Pen", "Orink", "");
+        let offer = Offer { price: Some(110.5), stock: Some(43.0), ..test_offer() };
+        let product = view(&product, &offer);
         assert_eq!(field_number(&product, "price"), Some(110.5));
         assert_eq!(field_number(&product, "stock"), Some(43.0));
         // Text columns never claim to be numeric.
@@ -488,8 +590,8 @@ mod tests {
             .collect();
         assert_eq!(price_columns, vec!["Price"], "the sheet must offer one price and no choice");
 
-        let mut product = crate::service::mcp::index::test_product("A-1", "Pen", "Orink", "");
-        product.price = Some(1495.0);
-        assert_eq!(field_number(&product, "price"), Some(1495.0));
+        let product = test_product("A-1", "Pen", "Orink", "");
+        let offer = Offer { price: Some(1495.0), ..test_offer() };
+        assert_eq!(field_number(&view(&product, &offer), "price"), Some(1495.0));
     }
 }
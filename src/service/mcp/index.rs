@@ -1,6 +1,27 @@
 //! Catalog snapshot: one in-memory, searchable view of the product catalog for
 //! a single `(authcode, pid, url)` combination.
 //!
+//! ## Base and overlay
+//!
+//! Almost all of a catalog is master data — names, codes, descriptions,
+//! barcodes — and it is the same for every partner of one Octopus. What differs
+//! per partner is small: the price, the stock figure and whether the product is
+//! published to them. So a snapshot is two parts:
+//!
+//! * a [`BaseCatalog`] per url, holding the master records and the search
+//!   haystacks, shared by every partner snapshot built against it;
+//! * the partner's own [`Offer`] per product, held parallel to the base.
+//!
+//! A product the partner's pull did not return has no offer and does not exist
+//! for them — not in search, not by article number. Builds merge the master rows
+//! they pulled into the base and keep the very same base when nothing changed,
+//! which is the usual case: dozens of partners refreshed in a sweep share one
+//! copy of the catalog, and each holds about two megabytes of its own.
+//!
+//! A base read back from the disk tier is not decoded into the heap: it stays in
+//! its mapped file (`service/mcp/layout`), and a lookup decodes only the records
+//! it returns.
+//!
 //! Nothing here parses SOAP. The snapshot is built by calling the **existing**
 //! dispatch in `service/get_data.rs` — the same `RequestGet` variants the REST
 //! routes use — and merging the English models it returns. When Octopus changes,
@@ -15,7 +36,9 @@
 //!   JSON handed to the model free of dozens of `""` fields per product, which
 //!   is the scarcer budget.
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 
 use chrono::{DateTime, Utc};
 use once_cell::sync::Lazy;
@@ -25,6 +48,7 @@ use crate::{
     forms::{
         r#in::xml::defaults::CallData,
         out::xml::{
+            mat::Attribute,
             prices::Price,
             products::{Product, Size},
             stocks::Product as StockProduct
@@ -34,13 +58,20 @@ use crate::{
     service::{
         get::{
             barcodes::{BarcodesData, BarcodesXML},
+            mat::{MatData, MatXML},
             prices::{PricesData, PricesXML},
             products::{ProductsData, ProductsXML},
             stocks::{StocksData, StocksXML}
         },
         get_data::{RequestGet, ResponseGet},
         log::{elogger, logger},
-        mcp::mask_authcode
+        mcp::{
+            layout::MappedBase,
+            mask_authcode,
+            rank::{self, Highlight, Query, Ranker},
+            synonyms,
+            syntax::{self, NumberField, Predicate, TextField}
+        }
     }
 };
 
@@ -50,7 +81,7 @@ use crate::{
 /// it: that model is `Serialize`-only by way of the shared `out` macro, and a
 /// snapshot has to round-trip through the disk store. Converting here keeps
 /// `forms/` untouched.
-#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Dimensions {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub x: Option<f64>,
@@ -67,9 +98,39 @@ impl From<Size> for Dimensions {
 }
 
 
-/// Serde-visible product record, already merged with the caller's price and
-/// stock. Every optional field is dropped from the JSON when absent.
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+/// One MAT attribute of a product (`GetMatmodellAuth`): "RAM: 16 GB".
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ProductAttribute {
+    /// The attribute's code, or its name when Octopus gave it no code.
+    pub code: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Text value (`string_value`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    /// Numeric value (`num_value`), for attributes kept as numbers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub number: Option<f64>,
+    /// Whether the ERP marks the attribute as a search filter (`szures`). Only
+    /// these are indexed as facets.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub filterable: bool,
+    /// The ERP's value list the value was picked from, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_set: Option<i64>
+}
+
+impl ProductAttribute {
+    /// The value as shown and filtered on: the text, else the number.
+    pub fn shown(&self) -> Option<String> {
+        self.value.clone().or_else(|| self.number.map(|number| number.to_string()))
+    }
+}
+
+
+/// Serde-visible master record of one product, shared by every partner of an
+/// Octopus url. Every optional field is dropped from the JSON when absent.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct IndexedProduct {
     /// Internal ERP record id (`cikkid`). Exposed for cross-checking against the
     /// ERP UI, but deliberately **not** part of the search haystack — see
@@ -96,6 +157,10 @@ pub struct IndexedProduct {
     /// forcing every partner's catalog to rebuild from scratch.
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub barcodes: Vec<String>,
+    /// MAT attributes, in the ERP's display order. Pulled in full on every
+    /// refresh, like barcodes.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attributes: Vec<ProductAttribute>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub unit: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -120,7 +185,13 @@ pub struct IndexedProduct {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sell_unit: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub origin_country: Option<String>,
+    pub origin_country: Option<String>
+}
+
+/// What one product means to one partner: whether it is on offer to them, what
+/// they pay and how many are in stock.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Offer {
     /// Whether Octopus publishes this product to the web (`webmegjel == 1`).
     ///
     /// Anything other than 1 means the product exists in the ERP but is not on
@@ -146,6 +217,19 @@ pub struct IndexedProduct {
     pub stock: Option<f64>
 }
 
+/// A product as one partner sees it: the shared record with their offer. It
+/// serializes as one flat object, master fields first.
+///
+/// The record is borrowed from a base held on the heap, or decoded on the spot
+/// from a mapped one.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ProductView<'a> {
+    #[serde(flatten)]
+    pub product: Cow<'a, IndexedProduct>,
+    #[serde(flatten)]
+    pub offer: &'a Offer
+}
+
 /// The lean shape `search_products` returns: enough to identify a product and
 /// act on it, without burning the context window on a full record.
 #[derive(Debug, Clone, serde::Serialize)]
@@ -170,17 +254,21 @@ pub struct ProductSummary {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub secondary_barcodes: Vec<String>,
     /// What this partner pays. Same figure and same meaning as
-    /// [`IndexedProduct::price`].
+    /// [`Offer::price`].
     #[serde(skip_serializing_if = "Option::is_none")]
     pub price: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub currency: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub stock: Option<f64>
+    pub stock: Option<f64>,
+    /// What the query matched in this row, set by
+    /// [`search_page`](CatalogSnapshot::search_page) only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub highlight: Option<Highlight>
 }
 
-impl From<&IndexedProduct> for ProductSummary {
-    fn from(product: &IndexedProduct) -> Self {
+impl From<ProductView<'_>> for ProductSummary {
+    fn from(ProductView { product, offer }: ProductView<'_>) -> Self {
         Self {
             no: product.no.clone(),
             name: product.name.clone(),
@@ -190,67 +278,200 @@ impl From<&IndexedProduct> for ProductSummary {
             oem_code: product.oem_code.clone(),
             primary_barcode: product.barcodes.first().cloned(),
             secondary_barcodes: product.barcodes.iter().skip(1).cloned().collect(),
-            price: product.price,
-            currency: product.currency.clone(),
-            stock: product.stock
+            price: offer.price,
+            currency: offer.currency.clone(),
+            stock: offer.stock,
+            highlight: None
         }
     }
 }
 
 
-/// Accent-folded haystacks for one product, held parallel to `products`.
+/// Accent-folded haystacks for one product, held parallel to the records.
 ///
-/// Kept as separate fields rather than one flat string because the ranking below
-/// weights an article-number hit far above a category hit, which a single
-/// haystack cannot express.
+/// Kept as separate fields rather than one flat string because the ranking in
+/// `service/mcp/rank` boosts an article-number hit far above a description hit,
+/// which a single haystack cannot express. The filter fields are folded here
+/// too, once, rather than per product on every query.
 #[derive(Debug, Clone)]
 struct FoldedEntry {
     /// Folded product name.
     name: String,
-    /// Folded brand + category names — matched, but weakly.
-    rest: String,
     /// Folded primary article number. An exact hit here outranks everything.
     sku: String,
-    /// Folded secondary codes: the manufacturer part number and every barcode.
-    /// All share one bucket because they are all exact-lookup codes — a caller
-    /// pasting either expects the same product back.
-    alt_codes: Vec<String>
+    /// Folded manufacturer part number.
+    oem: String,
+    /// Folded barcodes, joined by [`ALT_SEPARATOR`].
+    barcodes: String,
+    /// Folded brand, ranked and used for [`SearchFilters::brand`].
+    brand: String,
+    /// Folded category code and name, for [`SearchFilters::category`].
+    category: String,
+    /// Folded main category code and name, for [`SearchFilters::main_category`].
+    main_category: String,
+    /// The description's distinct folded words, space-separated. Each word
+    /// once: the text is the bulk of a record, and the ranking only needs to
+    /// know whether a word occurs.
+    description: String,
+    /// Filterable attributes, see [`Haystack::facets`]. Kept as written rather
+    /// than folded: facet listings show them, and a filter folds the few it
+    /// reaches.
+    facets: String
+}
+
+impl FoldedEntry {
+    fn haystack(&self) -> Haystack<'_> {
+        Haystack {
+            name: &self.name,
+            sku: &self.sku,
+            oem: &self.oem,
+            barcodes: &self.barcodes,
+            brand: &self.brand,
+            category: &self.category,
+            main_category: &self.main_category,
+            description: &self.description,
+            facets: &self.facets
+        }
+    }
+}
+
+/// Separates the codes in [`Haystack::barcodes`]: a control character, which
+/// no barcode contains.
+pub(super) const ALT_SEPARATOR: &str = "\u{1f}";
+
+/// Separates code, name and value within one facet; facets themselves are
+/// separated by [`ALT_SEPARATOR`].
+const FACET_SEPARATOR: char = '\u{1e}';
+
+/// One filterable attribute value of a product, borrowed from its haystack.
+#[derive(Debug, Clone, Copy)]
+pub struct Facet<'a> {
+    pub code: &'a str,
+    /// Empty when the ERP gave none.
+    pub name: &'a str,
+    pub value: &'a str
+}
+
+impl Facet<'_> {
+    /// Whether a folded code or name refers to this attribute.
+    fn is(&self, folded: &str) -> bool {
+        fold(self.code) == folded || (!self.name.is_empty() && fold(self.name) == folded)
+    }
+}
+
+/// One product's haystacks, borrowed from wherever its base keeps them.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Haystack<'a> {
+    pub name: &'a str,
+    pub sku: &'a str,
+    pub oem: &'a str,
+    pub barcodes: &'a str,
+    pub brand: &'a str,
+    pub category: &'a str,
+    pub main_category: &'a str,
+    pub description: &'a str,
+    pub facets: &'a str
+}
+
+impl<'a> Haystack<'a> {
+    /// The barcodes, one by one.
+    pub fn barcodes(&self) -> impl Iterator<Item = &'a str> {
+        self.barcodes.split(ALT_SEPARATOR).filter(|code| !code.is_empty())
+    }
+
+    /// The secondary codes — the manufacturer part number and every barcode.
+    /// All are exact-lookup codes: a caller pasting either expects the same
+    /// product back.
+    pub fn alt(&self) -> impl Iterator<Item = &'a str> {
+        Some(self.oem).filter(|oem| !oem.is_empty()).into_iter().chain(self.barcodes())
+    }
+
+    /// The filterable attribute values, one by one.
+    pub fn facets(&self) -> impl Iterator<Item = Facet<'a>> {
+        self.facets.split(ALT_SEPARATOR).filter_map(|facet| {
+            let mut parts = facet.splitn(3, FACET_SEPARATOR);
+            Some(Facet { code: parts.next()?, name: parts.next()?, value: parts.next()? })
+        })
+    }
+
+    /// Does this product pass the (already folded) filters? Numeric
+    /// conditions are not tested here: the haystacks do not hold numbers.
+    pub fn passes(&self, filters: &SearchFilters) -> bool {
+        filters.brand.as_ref().is_none_or(|brand| self.brand.contains(brand.as_str()))
+            && filters.category.as_ref().is_none_or(|category| self.category.contains(category.as_str()))
+            && filters.main_category.as_ref().is_none_or(|main| self.main_category.contains(main.as_str()))
+            && filters.predicates.iter().all(|predicate| match predicate {
+                Predicate::Text { field, value, negated } => {
+                    let text = match field {
+                        TextField::Brand => self.brand,
+                        TextField::Category => self.category,
+                        TextField::MainCategory => self.main_category
+                    };
+                    text.contains(value.as_str()) != *negated
+                }
+                Predicate::Attribute { name, test } =>
+                    self.facets().any(|facet| facet.is(name) && test.holds(facet.value)),
+                Predicate::Number { .. } => true
+            })
+    }
+}
+
+/// The master data of one Octopus url, shared by every partner snapshot built
+/// against it. Never changed in place: a merge that changes a row produces a new
+/// base, and snapshots move to it when they are next rebuilt.
+#[derive(Debug)]
+pub struct BaseCatalog {
+    rows: Rows,
+    /// Heap footprint, measured at build time. Next to nothing for a mapped
+    /// base, whose pages belong to the OS page cache.
+    pub bytes: u64
+}
+
+/// Where a base's rows live.
+#[derive(Debug)]
+enum Rows {
+    /// Built or merged here: every record and haystack on the heap.
+    Owned {
+        products: Vec<IndexedProduct>,
+        /// Folded article number -> position.
+        by_sku: HashMap<String, u32>,
+        folded: Vec<FoldedEntry>
+    },
+    /// Read back from the disk tier and left in the file.
+    Mapped(MappedBase)
 }
 
 /// One `(authcode, pid, url)` combination's catalog, ready to answer questions.
 #[derive(Debug)]
 pub struct CatalogSnapshot {
-    pub products: Vec<IndexedProduct>,
-    /// Folded article number -> index into `products`.
-    pub by_sku: HashMap<String, u32>,
-    /// Accent-folded haystacks, parallel to `products`.
-    folded: Vec<FoldedEntry>,
+    pub base: Arc<BaseCatalog>,
+    /// This partner's offer per base product, by base position.
+    /// `None` for a product their pull did not return.
+    offers: Vec<Option<Offer>>,
     pub fetched_at: DateTime<Utc>,
-    /// Measured at build time; feeds the cache weigher.
+    /// The overlay's own footprint, measured at build time; feeds the cache
+    /// weigher. The base is accounted for once, by the cache.
     pub bytes: u64
 }
 
 
-/// What actually goes to disk.
-///
-/// Only the two things that cannot be recomputed. `folded`, `by_sku` and `bytes`
-/// are all derived from `products`, so storing them would roughly double the
-/// file for data [`assemble`] regenerates in a single pass on load.
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-pub struct PersistedSnapshot {
+/// A partner overlay as it goes to disk (`service/mcp/layout`), keyed by article
+/// number rather than by position so it survives the base growing underneath
+/// it.
+#[derive(Debug)]
+pub struct PersistedOverlay {
     /// Layout marker, checked on load. Bumped whenever a stored field changes
     /// **meaning** rather than merely appearing or disappearing: serde happily
     /// ignores fields it no longer knows, so without this a file written when
     /// `price` held Octopus's `ar` would load into a build where `price` means
     /// `akcios_ar`, and the caller would be quoted a retail price as their own.
     /// A mismatch discards the file and rebuilds, which costs minutes once.
-    #[serde(default)]
     pub version: u32,
-    pub products: Vec<IndexedProduct>,
+    pub offers: HashMap<String, Offer>,
     pub fetched_at: DateTime<Utc>
 }
 
-/// Current [`PersistedSnapshot`] layout.
+/// Current layout of the files in the disk tier.
 ///
 /// 0 — implicit, pre-versioning: `price` carried Octopus's `ar`, with
 ///     `list_price` and `sale_price` stored beside it.
@@ -260,28 +481,25 @@ pub struct PersistedSnapshot {
 ///     applied to every row, and an incremental refresh only corrects the rows
 ///     that happen to change — so a wrong guess would stay visible to partners
 ///     until the next full pull.
-pub const SNAPSHOT_VERSION: u32 = 2;
-
-impl From<&CatalogSnapshot> for PersistedSnapshot {
+/// 3 — split into one shared base per url and a small overlay per partner,
+///     which a whole v2 snapshot cannot be read as.
+/// 4 — binary files (`service/mcp/layout`) in place of gzipped JSON, so a base
+///     is mapped rather than parsed.
+/// 5 — haystacks split per ranked field, with the description's words indexed
+///     (`service/mcp/rank`).
+/// 6 — filterable MAT attributes indexed as a facet haystack.
+pub const SNAPSHOT_VERSION: u32 = 6;
+
+impl From<&CatalogSnapshot> for PersistedOverlay {
     fn from(snapshot: &CatalogSnapshot) -> Self {
         Self {
             version: SNAPSHOT_VERSION,
-            products: snapshot.products.clone(),
+            offers: snapshot.offer_map(),
             fetched_at: snapshot.fetched_at
         }
     }
 }
 
-impl From<PersistedSnapshot> for CatalogSnapshot {
-    fn from(persisted: PersistedSnapshot) -> Self {
-        let mut snapshot = assemble(persisted.products);
-        // `assemble` stamps "now"; a reloaded snapshot must keep the age it had
-        // when it was fetched, or every restart would look like fresh data.
-        snapshot.fetched_at = persisted.fetched_at;
-        snapshot
-    }
-}
-
 
 /// Folds one character to its unaccented lowercase form.
 ///
@@ -527,92 +745,247 @@ fn non_empty(value: String) -> Option<String> {
 }
 
 
-/// Search filters, all accent-folded substring matches.
+/// Search filters, all accent-folded substring matches, plus whatever
+/// conditions the query itself spelled out (`service/mcp/syntax`).
 #[derive(Debug, Default, Clone)]
 pub struct SearchFilters {
     pub brand: Option<String>,
     pub category: Option<String>,
-    pub main_category: Option<String>
+    pub main_category: Option<String>,
+    pub predicates: Vec<Predicate>
+}
+
+impl SearchFilters {
+    /// These filters and a query's own conditions, all of which must hold.
+    fn with(&self, predicates: Vec<Predicate>) -> Self {
+        let mut filters = self.clone();
+        filters.predicates.extend(predicates);
+        filters
+    }
+
+    /// Whether a condition reads the master record. Decoding one per product
+    /// is what a mapped base exists to avoid, so only then is it done.
+    fn needs_record(&self) -> bool {
+        self.predicates.iter().any(Predicate::needs_record)
+    }
+
+    /// The price and stock conditions against a partner's offer.
+    fn passes_offer(&self, offer: &Offer) -> bool {
+        self.predicates.iter().all(|predicate| match predicate {
+            Predicate::Number { field: NumberField::Price, comparison, bound } =>
+                offer.price.is_some_and(|price| comparison.holds(price, *bound)),
+            Predicate::Number { field: NumberField::Stock, comparison, bound } =>
+                offer.stock.is_some_and(|stock| comparison.holds(stock, *bound)),
+            _ => true
+        })
+    }
+
+    /// The weight and size conditions against a master record.
+    fn passes_record(&self, product: &IndexedProduct) -> bool {
+        let size = |axis: fn(&Dimensions) -> Option<f64>| product.size.as_ref().and_then(axis);
+        self.predicates.iter().all(|predicate| {
+            let Predicate::Number { field, comparison, bound } = predicate else {
+                return true
+            };
+            let value = match field {
+                NumberField::Weight => product.weight,
+                NumberField::Width => size(|size| size.x),
+                NumberField::Height => size(|size| size.y),
+                NumberField::Depth => size(|size| size.z),
+                NumberField::Price | NumberField::Stock => return true
+            };
+            value.is_some_and(|value| comparison.holds(value, *bound))
+        })
+    }
 }
 
 /// Outcome of a search: the page of results plus how many matched in total, so
-/// the caller can say when a result set was truncated.
+/// the caller can say when a result set was truncated, and the breakdown of
+/// every match, so the caller can narrow it.
 pub struct SearchOutcome {
     pub results: Vec<ProductSummary>,
-    pub matched: usize
+    pub matched: usize,
+    pub facets: Facets
+}
+
+
+impl BaseCatalog {
+    /// A base left in its mapped file.
+    pub fn mapped(rows: MappedBase) -> Self {
+        let bytes = rows.heap_bytes();
+        Self { rows: Rows::Mapped(rows), bytes }
+    }
+
+    /// Products held, whoever they are on offer to.
+    pub fn len(&self) -> usize {
+        match &self.rows {
+            Rows::Owned { products, .. } => products.len(),
+            Rows::Mapped(rows) => rows.len()
+        }
+    }
+
+    /// Article number at a position.
+    pub fn no(&self, position: usize) -> Option<&str> {
+        match &self.rows {
+            Rows::Owned { products, .. } => products.get(position).map(|product| product.no.as_str()),
+            Rows::Mapped(rows) => rows.no(position)
+        }
+    }
+
+    /// The record at a position: borrowed, or decoded from the mapped file.
+    pub fn record(&self, position: usize) -> Option<Cow<'_, IndexedProduct>> {
+        match &self.rows {
+            Rows::Owned { products, .. } => products.get(position).map(Cow::Borrowed),
+            Rows::Mapped(rows) => rows.record(position).map(Cow::Owned)
+        }
+    }
+
+    pub(super) fn haystack(&self, position: usize) -> Option<Haystack<'_>> {
+        match &self.rows {
+            Rows::Owned { folded, .. } => folded.get(position).map(FoldedEntry::haystack),
+            Rows::Mapped(rows) => rows.haystack(position)
+        }
+    }
+
+    /// Every record in position order. Decodes a mapped base whole, which only
+    /// a merge has reason to.
+    fn records(&self) -> Cow<'_, [IndexedProduct]> {
+        match &self.rows {
+            Rows::Owned { products, .. } => Cow::Borrowed(products),
+            Rows::Mapped(rows) => Cow::Owned((0..rows.len()).filter_map(|position| rows.record(position)).collect())
+        }
+    }
+
+    /// Positions whose article number folds to `folded`.
+    fn positions_of_sku(&self, folded: &str) -> Vec<usize> {
+        match &self.rows {
+            Rows::Owned { by_sku, .. } => by_sku.get(folded).map(|&position| position as usize).into_iter().collect(),
+            Rows::Mapped(rows) => rows.positions_of_code(folded, false)
+        }
+    }
+
+    /// Positions holding `folded` among their secondary codes.
+    fn positions_of_alt(&self, folded: &str) -> Vec<usize> {
+        match &self.rows {
+            Rows::Owned { folded: entries, .. } => entries.iter()
+                .enumerate()
+                .filter(|(_, entry)| entry.haystack().alt().any(|code| code == folded))
+                .map(|(position, _)| position)
+                .collect(),
+            Rows::Mapped(rows) => rows.positions_of_code(folded, true)
+        }
+    }
+
+    /// Positions holding an internal record id.
+    fn positions_of_id(&self, id: u64) -> Vec<usize> {
+        match &self.rows {
+            Rows::Owned { products, .. } => products.iter()
+                .enumerate()
+                .filter(|(_, product)| product.id == id)
+                .map(|(position, _)| position)
+                .collect(),
+            Rows::Mapped(rows) => rows.positions_of_id(id)
+        }
+    }
+
+    /// Position of an article number, matched exactly.
+    fn position(&self, no: &str) -> Option<usize> {
+        if let Some(position) = self.positions_of_sku(&fold(no)).into_iter().find(|&position| self.no(position) == Some(no)) {
+            return Some(position)
+        }
+        // Two article numbers that fold alike share one slot.
+        (0..self.len()).find(|&position| self.no(position) == Some(no))
+    }
+
+    pub fn product(&self, no: &str) -> Option<Cow<'_, IndexedProduct>> {
+        self.position(no).and_then(|position| self.record(position))
+    }
 }
 
 
 impl CatalogSnapshot {
+    /// Lays a partner's offers, keyed by article number, over a base. An offer
+    /// whose product the base does not hold has nothing to show and is dropped.
+    pub fn resolve(base: Arc<BaseCatalog>, mut offers: HashMap<String, Offer>, fetched_at: DateTime<Utc>) -> Self {
+        let resolved: Vec<Option<Offer>> = (0..base.len())
+            .map(|position| base.no(position).and_then(|no| offers.remove(no)))
+            .collect();
+        if !offers.is_empty() {
+            elogger(format!("MCP snapshot: dropped {} offers whose products the base catalog lacks", offers.len()));
+        }
+        let bytes = measure_offers(&resolved);
+        Self { base, offers: resolved, fetched_at, bytes }
+    }
+
+    /// The offers keyed by article number, as they are stored.
+    fn offer_map(&self) -> HashMap<String, Offer> {
+        self.offered()
+            .filter_map(|(position, offer)| Some((self.base.no(position)?.to_string(), offer.clone())))
+            .collect()
+    }
+
+    /// The product at a base position, if this partner has it.
+    fn view(&self, position: usize) -> Option<ProductView<'_>> {
+        let offer = self.offers.get(position)?.as_ref()?;
+        Some(ProductView { product: self.base.record(position)?, offer })
+    }
+
+    /// Every product this partner has, with its base position.
+    fn views(&self) -> impl Iterator<Item = (usize, ProductView<'_>)> {
+        (0..self.offers.len()).filter_map(|position| Some((position, self.view(position)?)))
+    }
+
+    /// This partner's offers with their base positions, decoding no record.
+    fn offered(&self) -> impl Iterator<Item = (usize, &Offer)> {
+        self.offers.iter()
+            .enumerate()
+            .filter_map(|(position, offer)| Some((position, offer.as_ref()?)))
+    }
+
     /// How old this snapshot is, in seconds.
     pub fn age_secs(&self) -> i64 {
         (Utc::now() - self.fetched_at).num_seconds().max(0)
     }
 
+    /// Products held for this partner, on offer or not.
+    pub fn product_count(&self) -> usize {
+        self.offers.iter().flatten().count()
+    }
+
     /// Products a caller can actually reach through search or export.
     pub fn available_count(&self) -> usize {
-        self.products.iter().filter(|product| product.available).count()
+        self.offers.iter().flatten().filter(|offer| offer.available).count()
     }
 
     /// Products held but hidden, i.e. not published to the web by the ERP.
     pub fn hidden_count(&self) -> usize {
-        self.products.len() - self.available_count()
+        self.product_count() - self.available_count()
     }
 
-    /// Exact lookup by article number, then by manufacturer part number, then by
-    /// internal record id. The id is accepted here but never fed to the ranking
-    /// (see [`FoldedEntry`]).
-    pub fn get_by_no(&self, needle: &str) -> Option<&IndexedProduct> {
-        let trimmed = needle.trim();
-        let folded = fold(trimmed);
-        if let Some(index) = self.by_sku.get(&folded)
-            && let Some(product) = self.products.get(*index as usize) {
-                return Some(product)
-        }
-        self.products.iter().enumerate().find_map(|(position, product)| {
-            let matches_alt = self.folded.get(position)
-                .is_some_and(|entry| entry.alt_codes.contains(&folded));
-            let matches_id = product.id.to_string() == trimmed;
-            (matches_alt || matches_id).then_some(product)
-        })
+    /// Products on offer with a price for this partner.
+    pub fn priced_count(&self) -> usize {
+        self.offers.iter().flatten().filter(|offer| offer.available && offer.price.is_some()).count()
     }
 
-    /// Does this product pass the (already folded) filters?
-    fn passes(&self, product: &IndexedProduct, filters: &SearchFilters) -> bool {
-        if let Some(brand) = &filters.brand
-            && !fold(product.brand.as_deref().unwrap_or_default()).contains(brand) {
-                return false
-        }
-        if let Some(category) = &filters.category {
-            let haystack = fold(&format!(
-                "{} {}",
-                product.category_code.as_deref().unwrap_or_default(),
-                product.category_name.as_deref().unwrap_or_default()
-            ));
-            if !haystack.contains(category) {
-                return false
-            }
+    /// Exact lookup by article number, then by manufacturer part number or
+    /// barcode, then by internal record id. The id is accepted here but never
+    /// fed to the ranking (see [`FoldedEntry`]).
+    pub fn get_by_no(&self, needle: &str) -> Option<ProductView<'_>> {
+        let trimmed = needle.trim();
+        let folded = fold(trimmed);
+        if let Some(view) = self.base.positions_of_sku(&folded).into_iter().find_map(|position| self.view(position)) {
+            return Some(view)
         }
-        if let Some(main_category) = &filters.main_category {
-            let haystack = fold(&format!(
-                "{} {}",
-                product.main_category_code.as_deref().unwrap_or_default(),
-                product.main_category_name.as_deref().unwrap_or_default()
-            ));
-            if !haystack.contains(main_category) {
-                return false
-            }
+        if let Some(view) = self.base.positions_of_alt(&folded).into_iter().find_map(|position| self.view(position)) {
+            return Some(view)
         }
-        true
+        let id = trimmed.parse::<u64>().ok().filter(|id| id.to_string() == trimmed)?;
+        self.base.positions_of_id(id).into_iter().find_map(|position| self.view(position))
     }
 
-    /// Ranked search. Every query term must match somewhere, and the best match
-    /// kind per term decides its weight.
-    ///
-    /// The weights are ported verbatim from the TypeScript prototype, where they
-    /// were tuned against real colleague queries: an exact article number beats
-    /// an exact manufacturer code, which beats a name hit, which beats a brand or
-    /// category hit. Changing them changes answers people already trust.
+    /// Ranked search. Every query term must match somewhere — exactly, as a
+    /// prefix, inside a word or, for words, as a near spelling — and the
+    /// relevance score is `service/mcp/rank`'s.
     pub fn search(&self, query: &str, filters: &SearchFilters, limit: usize) -> SearchOutcome {
         self.search_page(query, filters, 0, limit)
     }
@@ -631,98 +1004,113 @@ impl CatalogSnapshot {
         offset: usize,
         limit: usize
     ) -> SearchOutcome {
-        let scored = self.ranked(query, filters);
+        let (query, filters) = understand(query, filters);
+        let scored = self.ranked(&query, &filters);
         let matched = scored.len();
 
         let results = scored.iter()
             .skip(offset)
             .take(limit)
-            .filter_map(|(_, position)| self.products.get(*position))
-            .map(ProductSummary::from)
+            .filter_map(|(_, position)| self.view(*position))
+            .map(|view| {
+                let highlight = query.highlight(&view.product);
+                ProductSummary { highlight, ..ProductSummary::from(view) }
+            })
             .collect();
+        let facets = self.facets(scored.iter().map(|(_, position)| *position));
 
-        SearchOutcome { results, matched }
+        SearchOutcome { results, matched, facets }
     }
 
     /// How many products a query and filter combination matches, without
     /// building any output. Used to answer "is there anything to export?"
     /// before committing to writing a file.
     pub fn count_matching(&self, query: &str, filters: &SearchFilters) -> usize {
-        self.ranked(query, filters).len()
+        let (query, filters) = understand(query, filters);
+        self.ranked(&query, &filters).len()
     }
 
     /// Every matching product in rank order, borrowed rather than copied.
     ///
     /// The export path uses this: 24,000 owned records would be a second copy of
     /// the whole catalog in memory, on a host that has ~1–1.5 GB.
-    pub fn select(&self, query: &str, filters: &SearchFilters) -> Vec<&IndexedProduct> {
-        self.ranked(query, filters).iter()
-            .filter_map(|(_, position)| self.products.get(*position))
+    pub fn select(&self, query: &str, filters: &SearchFilters) -> Vec<ProductView<'_>> {
+        let (query, filters) = understand(query, filters);
+        self.ranked(&query, &filters).iter()
+            .filter_map(|(_, position)| self.view(*position))
             .collect()
     }
 
     /// The shared ranking pass: every product that passes the filters and
     /// matches every query term, sorted best first.
-    fn ranked(&self, query: &str, filters: &SearchFilters) -> Vec<(f64, usize)> {
-        let folded_query = fold(query);
-        let terms: Vec<&str> = folded_query.split_whitespace().collect();
-
-        let mut scored: Vec<(f64, usize)> = Vec::new();
-
-        for (position, product) in self.products.iter().enumerate() {
-            if !product.available || !self.passes(product, filters) {
-                continue
-            }
-            let Some(entry) = self.folded.get(position) else {
+    fn ranked(&self, query: &Query, filters: &SearchFilters) -> Vec<(f64, usize)> {
+        let mut ranker = Ranker::new(query);
+        let needs_record = filters.needs_record();
+
+        // Haystacks only: a mapped base decodes no record until the page is cut,
+        // unless a weight or size condition has to read one.
+        for (position, _) in self.offered().filter(|(_, offer)| offer.available && filters.passes_offer(offer)) {
+            let Some(entry) = self.base.haystack(position).filter(|entry| entry.passes(filters)) else {
                 continue
             };
+            if needs_record && !self.base.record(position).is_some_and(|product| filters.passes_record(&product)) {
+                continue
+            }
+            ranker.scan(position, &entry);
+        }
 
-            let mut score = 0.0_f64;
-            let mut all_matched = true;
-            for term in &terms {
-                let best = if entry.sku == *term {
-                    2000.0
-                } else if entry.alt_codes.iter().any(|code| code == term) {
-                    800.0
-                } else if entry.sku.starts_with(term) {
-                    300.0
-                } else if entry.alt_codes.iter().any(|code| code.starts_with(term)) {
-                    200.0
-                } else if entry.name.contains(term) {
-                    if entry.name.starts_with(term) { 120.0 } else { 60.0 }
-                } else if entry.rest.contains(term) {
-                    20.0
-                } else if entry.sku.contains(term) || entry.alt_codes.iter().any(|code| code.contains(term)) {
-                    15.0
-                } else {
-                    0.0
-                };
+        ranker.finish(|position| self.base.no(position))
+    }
 
-                if best == 0.0 {
-                    all_matched = false;
-                    break
-                }
-                score += best;
-            }
+    /// The breakdown of a result set. Counted on the haystacks and offers; a
+    /// record is decoded only once per distinct brand or group, for the name
+    /// to show.
+    fn facets(&self, positions: impl Iterator<Item = usize>) -> Facets {
+        // Folded key -> (products, a position holding it).
+        type Counted<'a> = HashMap<&'a str, (u32, usize)>;
+        let mut brands: Counted = HashMap::new();
+        let mut main_groups: Counted = HashMap::new();
+        let mut groups: Counted = HashMap::new();
+        let mut bands = [0_u32; PRICE_BANDS.len()];
+        let mut facets = Facets::default();
+        let mut haystacks = Vec::new();
 
-            if !all_matched && !terms.is_empty() {
+        for position in positions {
+            let (Some(Some(offer)), Some(haystack)) = (self.offers.get(position), self.base.haystack(position)) else {
                 continue
+            };
+            for (counted, key) in [(&mut brands, haystack.brand), (&mut main_groups, haystack.main_category), (&mut groups, haystack.category)] {
+                if !key.trim().is_empty() {
+                    counted.entry(key).or_insert((0, position)).0 += 1;
+                }
+            }
+            if offer.stock.is_some_and(|stock| stock > 0.0) {
+                facets.in_stock += 1;
+            } else {
+                facets.out_of_stock += 1;
             }
-            // Shorter names are usually the more canonical match at equal score.
-            scored.push((score - entry.name.len() as f64 / 1000.0, position));
+            if let Some(price) = offer.price {
+                bands[PRICE_BANDS.iter().rposition(|&low| price >= low).unwrap_or(0)] += 1;
+            }
+            haystacks.push(haystack);
         }
 
-        scored.sort_by(|a, b| {
-            b.0.partial_cmp(&a.0)
-                .unwrap_or(std::cmp::Ordering::Equal)
-                .then_with(|| {
-                    let left = self.products.get(a.1).map(|p| p.no.as_str()).unwrap_or_default();
-                    let right = self.products.get(b.1).map(|p| p.no.as_str()).unwrap_or_default();
-                    left.cmp(right)
-                })
-        });
-
-        scored
+        let named = |counted: Counted, describe: fn(&IndexedProduct) -> (Option<String>, Option<String>)| {
+            sorted_counts(counted.into_values().filter_map(|(count, position)| {
+                let (code, name) = describe(self.base.record(position)?.as_ref());
+                Some(CategoryCount { code, name: name.unwrap_or_default(), count })
+            }))
+        };
+        facets.brands = named(brands, |product| (None, product.brand.clone()));
+        facets.main_categories = named(main_groups, |product| (product.main_category_code.clone(), product.main_category_name.clone()));
+        facets.categories = named(groups, |product| (product.category_code.clone(), product.category_name.clone()));
+        facets.price_bands = bands.iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(band, &count)| PriceBand::new(band, count))
+            .collect();
+        facets.attributes = count_facets(haystacks.into_iter());
+        facets
     }
 
     /// The `limit` closest article numbers to a miss, so a failed `get_product`
@@ -739,10 +1127,12 @@ impl CatalogSnapshot {
         if prefix.is_empty() {
             return Vec::new()
         }
-        self.folded.iter().enumerate()
-            .filter(|(_, entry)| entry.sku.starts_with(&prefix))
-            .filter_map(|(position, _)| self.products.get(position))
-            .filter(|product| product.available)
+        self.offered()
+            .filter(|(position, offer)| {
+                offer.available
+                    && self.base.haystack(*position).is_some_and(|entry| entry.sku.starts_with(&prefix))
+            })
+            .filter_map(|(position, _)| self.view(position))
             .take(limit)
             .map(ProductSummary::from)
             .collect()
@@ -754,7 +1144,7 @@ impl CatalogSnapshot {
         let mut main_groups: HashMap<(String, String), u32> = HashMap::new();
         let mut groups: HashMap<(String, String), u32> = HashMap::new();
 
-        for product in self.products.iter().filter(|product| product.available) {
+        for (_, ProductView { product, .. }) in self.views().filter(|(_, view)| view.offer.available) {
             if let Some(brand) = &product.brand {
                 *brands.entry(brand.clone()).or_default() += 1;
             }
@@ -792,6 +1182,59 @@ impl CatalogSnapshot {
             }))
         }
     }
+
+    /// The filterable attributes of the products on offer, each with its
+    /// values. Read from the facet haystacks, so no record is decoded.
+    pub fn attributes(&self) -> Vec<AttributeFacet> {
+        count_facets(
+            self.offered()
+                .filter(|(_, offer)| offer.available)
+                .filter_map(|(position, _)| self.base.haystack(position))
+        )
+    }
+}
+
+
+/// Counts attribute values over some products' haystacks: per attribute the
+/// products carrying it, per value the products holding it. Most common first.
+fn count_facets<'a>(haystacks: impl Iterator<Item = Haystack<'a>>) -> Vec<AttributeFacet> {
+    // code -> (name, products, value -> products)
+    let mut counted: HashMap<&str, (&str, u32, HashMap<&str, u32>)> = HashMap::new();
+    for haystack in haystacks {
+        let mut seen: Vec<&str> = Vec::new();
+        for facet in haystack.facets() {
+            let (name, products, values) = counted.entry(facet.code).or_default();
+            if name.is_empty() {
+                *name = facet.name;
+            }
+            if !seen.contains(&facet.code) {
+                seen.push(facet.code);
+                *products += 1;
+            }
+            *values.entry(facet.value).or_default() += 1;
+        }
+    }
+
+    let mut facets: Vec<AttributeFacet> = counted.into_iter()
+        .map(|(code, (name, count, values))| {
+            let mut values: Vec<FacetValue> = values.into_iter()
+                .map(|(value, count)| FacetValue { value: value.to_string(), count })
+                .collect();
+            values.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+            AttributeFacet { code: code.to_string(), name: non_empty(name.to_string()), count, values }
+        })
+        .collect();
+    facets.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.code.cmp(&b.code)));
+    facets
+}
+
+
+/// A query taken apart: the words to rank by, and the filters with the query's
+/// own conditions added.
+fn understand(query: &str, filters: &SearchFilters) -> (Query, SearchFilters) {
+    let mut parsed = syntax::parse(query);
+    let predicates = std::mem::take(&mut parsed.predicates);
+    (Query::read(&parsed, &synonyms::current()), filters.with(predicates))
 }
 
 
@@ -819,6 +1262,99 @@ pub struct Categories {
 }
 
 
+/// Lower bounds of the price bands a result set is broken down into. Round
+/// forint figures: the catalog is priced in HUF.
+const PRICE_BANDS: [f64; 10] = [0.0, 1_000.0, 2_500.0, 5_000.0, 10_000.0, 25_000.0, 50_000.0, 100_000.0, 250_000.0, 500_000.0];
+
+/// What a result set holds, for narrowing it: brands, groups, stock, price
+/// bands and attribute values, each with its product count.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct Facets {
+    pub brands: Vec<CategoryCount>,
+    pub main_categories: Vec<CategoryCount>,
+    pub categories: Vec<CategoryCount>,
+    /// Products with stock above zero.
+    pub in_stock: u32,
+    pub out_of_stock: u32,
+    /// Bands holding at least one priced product, cheapest first.
+    pub price_bands: Vec<PriceBand>,
+    pub attributes: Vec<AttributeFacet>
+}
+
+impl Facets {
+    /// The same breakdown cut to the `limit` largest entries per list, values
+    /// included. Counts are unaffected.
+    pub fn top(&self, limit: usize) -> Self {
+        Self {
+            brands: self.brands.iter().take(limit).cloned().collect(),
+            main_categories: self.main_categories.iter().take(limit).cloned().collect(),
+            categories: self.categories.iter().take(limit).cloned().collect(),
+            in_stock: self.in_stock,
+            out_of_stock: self.out_of_stock,
+            price_bands: self.price_bands.clone(),
+            attributes: self.attributes.iter()
+                .take(limit)
+                .map(|attribute| AttributeFacet {
+                    values: attribute.values.iter().take(limit).cloned().collect(),
+                    ..attribute.clone()
+                })
+                .collect()
+        }
+    }
+}
+
+/// One price band of a result set.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PriceBand {
+    pub min: f64,
+    /// Exclusive; absent for the top band.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    pub count: u32,
+    /// The query operators that keep this band, to append to the query.
+    pub query: String
+}
+
+impl PriceBand {
+    fn new(band: usize, count: u32) -> Self {
+        let min = PRICE_BANDS[band];
+        let max = PRICE_BANDS.get(band + 1).copied();
+        let query = match (min > 0.0, max) {
+            (true, Some(max)) => format!("price>={} price<{}", min, max),
+            (false, Some(max)) => format!("price<{}", max),
+            (_, None) => format!("price>={}", min)
+        };
+        Self { min, max, count, query }
+    }
+}
+
+
+/// One filterable attribute and the values it takes.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AttributeFacet {
+    pub code: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Products carrying the attribute.
+    pub count: u32,
+    pub values: Vec<FacetValue>
+}
+
+impl AttributeFacet {
+    /// Whether a code or name, as a caller typed it, refers to this attribute.
+    pub fn is(&self, code_or_name: &str) -> bool {
+        let folded = fold(code_or_name.trim());
+        fold(&self.code) == folded || self.name.as_deref().is_some_and(|name| fold(name) == folded)
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct FacetValue {
+    pub value: String,
+    pub count: u32
+}
+
+
 /// Why a snapshot could not be built. Each variant maps to an actionable MCP
 /// error, so the model can tell "wrong credentials" from "ERP unreachable".
 #[derive(Debug)]
@@ -854,8 +1390,8 @@ fn call_data(authcode: &str, pid: i64, url: &str, from_date: Option<DateTime<Utc
 }
 
 
-/// The three raw pieces a snapshot is assembled from.
-struct CatalogParts {
+/// The raw pieces a snapshot is assembled from.
+pub struct CatalogParts {
     products: Vec<Product>,
     /// Always the full price list: `GetArlistaAuth` takes no date parameter, so
     /// there is no such thing as an incremental price pull.
@@ -867,17 +1403,33 @@ struct CatalogParts {
     /// the same reason prices are: an incremental pull cannot report a barcode
     /// that was *removed*, and the list is small enough that a full one costs
     /// little beside the product pull.
-    barcodes: HashMap<String, Vec<String>>
+    barcodes: HashMap<String, Vec<String>>,
+    /// MAT attributes per article number, in display order. Always the full
+    /// list, for the reason barcodes are.
+    attributes: HashMap<String, Vec<ProductAttribute>>
 }
 
 
-/// Fetches products, prices and stock for one combination.
+impl CatalogParts {
+    /// How many product records the pull returned — every one of them, or only
+    /// the changed ones for an incremental pull.
+    pub fn product_count(&self) -> usize {
+        self.products.len()
+    }
+}
+
+
+/// Fetches products, prices, stock, barcodes and attributes for one
+/// combination.
 ///
 /// `from_date` makes the product and stock pulls incremental (`web_update`);
 /// prices come back in full either way. Only prices vary by `pid` —
 /// `GetCikkekAuth` and `GetCikkekKeszletValtozasAuth` take an authcode alone —
 /// so two pids under one authcode differ solely in the price columns.
-async fn fetch_parts(
+///
+/// Nothing is merged here: the caller holds the url's base while it merges, and
+/// a fetch takes far too long to hold it for.
+pub async fn fetch_parts(
     authcode: &str,
     pid: i64,
     url: &str,
@@ -895,16 +1447,18 @@ async fn fetch_parts(
     }
 
     // Prices and stock are independent of each other, so they overlap. Both go
-    // through the existing SOAP_GATE, so this cannot exceed the configured
-    // outbound concurrency.
-    let (prices_response, stocks_response, barcodes_response) = futures::join!(
+    // through the SOAP gate, so this cannot exceed the configured outbound
+    // concurrency.
+    let (prices_response, stocks_response, barcodes_response, attributes_response) = futures::join!(
         // Prices carry no date: `call_data`'s `from_date` is ignored by
         // `GetArlistaAuth`, so this is a full list on every refresh.
         RequestGet::Prices(call_data(authcode, pid, url, None)).into_data(),
         RequestGet::Stocks(call_data(authcode, pid, url, from_date)).into_data(),
         // `GetVonalkodokAuth` *does* take a date, but it is deliberately not
         // given one: see `CatalogParts::barcodes`.
-        RequestGet::Barcodes(call_data(authcode, pid, url, None)).into_data()
+        RequestGet::Barcodes(call_data(authcode, pid, url, None)).into_data(),
+        // Full for the same reason: see `CatalogParts::attributes`.
+        RequestGet::Mat(call_data(authcode, pid, url, None)).into_data()
     );
 
     let prices: HashMap<String, Price> = match prices_response {
@@ -970,15 +1524,68 @@ async fn fetch_parts(
         }
     };
 
+    let attributes: HashMap<String, Vec<ProductAttribute>> = match attributes_response {
+        ResponseGet::Mat(MatData::Xml(MatXML::En(envelope)))
+            if envelope.body.response.result.answer.error.is_none() =>
+        {
+            group_attributes(envelope.body.response.result.answer.attributes.attribute)
+        }
+        _ => {
+            elogger(format!("MCP snapshot: attributes unavailable for {} pid={}", mask_authcode(authcode), pid));
+            HashMap::new()
+        }
+    };
+
     Ok(CatalogParts {
         products: products.body.response.result.answer.products.product,
         prices,
         stocks,
-        barcodes
+        barcodes,
+        attributes
     })
 }
 
 
+/// MAT rows grouped per article number, in the ERP's display order. Deleted
+/// rows, rows naming no product and rows without a value are dropped.
+fn group_attributes(rows: Vec<Attribute>) -> HashMap<String, Vec<ProductAttribute>> {
+    let mut grouped: HashMap<String, Vec<(i64, ProductAttribute)>> = HashMap::new();
+    for row in rows {
+        if row.delstatus.is_some() {
+            continue
+        }
+        let Some(no) = row.product_no.and_then(non_empty) else {
+            continue
+        };
+        let name = row.name.and_then(non_empty);
+        let Some(code) = row.code.and_then(non_empty).or_else(|| name.clone()) else {
+            continue
+        };
+        let value = row.string_value.and_then(non_empty);
+        let number = row.num_value.filter(|number| number.is_finite());
+        if value.is_none() && number.is_none() {
+            continue
+        }
+        let attribute = ProductAttribute {
+            code: code.trim().to_string(),
+            name: name.map(|name| name.trim().to_string()),
+            value: value.map(|value| value.trim().to_string()),
+            number,
+            filterable: row.filter.is_some_and(|filter| filter.get() == 1),
+            value_set: row.value_set
+        };
+        grouped.entry(no).or_default().push((row.order.unwrap_or(i64::MAX), attribute));
+    }
+
+    grouped.into_iter()
+        .map(|(no, mut list)| {
+            list.sort_by_key(|(order, _)| *order);
+            (no, list.into_iter().map(|(_, attribute)| attribute).collect())
+        })
+        .collect()
+}
+
+
 /// Builds the folded haystacks and the article-number map over a finished
 /// product list, and measures the result.
 ///
@@ -986,25 +1593,32 @@ async fn fetch_parts(
 /// key whose values collide with other products' article numbers, so indexing it
 /// corrupts the ranking. Exact lookup by id still works through
 /// [`CatalogSnapshot::get_by_no`].
-fn assemble(products: Vec<IndexedProduct>) -> CatalogSnapshot {
+fn assemble(products: Vec<IndexedProduct>) -> BaseCatalog {
     let mut folded: Vec<FoldedEntry> = Vec::with_capacity(products.len());
     let mut by_sku: HashMap<String, u32> = HashMap::with_capacity(products.len());
 
     for (position, product) in products.iter().enumerate() {
         let entry = FoldedEntry {
             name: fold(&product.name),
-            rest: fold(&format!(
-                "{} {} {}",
-                product.brand.as_deref().unwrap_or_default(),
-                product.category_name.as_deref().unwrap_or_default(),
+            sku: fold(&product.no),
+            oem: product.oem_code.as_deref().map(fold).unwrap_or_default(),
+            barcodes: product.barcodes.iter()
+                .map(|ean| fold(ean))
+                .collect::<Vec<_>>()
+                .join(ALT_SEPARATOR),
+            brand: fold(product.brand.as_deref().unwrap_or_default()),
+            category: fold(&format!(
+                "{} {}",
+                product.category_code.as_deref().unwrap_or_default(),
+                product.category_name.as_deref().unwrap_or_default()
+            )),
+            main_category: fold(&format!(
+                "{} {}",
+                product.main_category_code.as_deref().unwrap_or_default(),
                 product.main_category_name.as_deref().unwrap_or_default()
             )),
-            sku: fold(&product.no),
-            alt_codes: product.oem_code.as_deref()
-                .map(fold)
-                .into_iter()
-                .chain(product.barcodes.iter().map(|ean| fold(ean)))
-                .collect()
+            description: rank::distinct_words(&fold(product.description.as_deref().unwrap_or_default())),
+            facets: facets(&product.attributes)
         };
         if !entry.sku.is_empty() {
             // `u32` indices keep the map small; a catalog past four billion rows
@@ -1015,7 +1629,67 @@ fn assemble(products: Vec<IndexedProduct>) -> CatalogSnapshot {
     }
 
     let bytes = measure_bytes(&products, &folded, &by_sku);
-    CatalogSnapshot { products, by_sku, folded, fetched_at: Utc::now(), bytes }
+    BaseCatalog { rows: Rows::Owned { products, by_sku, folded }, bytes }
+}
+
+
+/// The facet haystack of a product: its filterable attributes as
+/// `code␞name␞value`, joined by [`ALT_SEPARATOR`]. Separator characters inside
+/// the ERP's text are dropped rather than allowed to split a facet.
+fn facets(attributes: &[ProductAttribute]) -> String {
+    let clean = |text: &str| text.replace([FACET_SEPARATOR, '\u{1f}'], "");
+    attributes.iter()
+        .filter(|attribute| attribute.filterable)
+        .filter_map(|attribute| Some(format!(
+            "{}{}{}{}{}",
+            clean(&attribute.code),
+            FACET_SEPARATOR,
+            clean(attribute.name.as_deref().unwrap_or_default()),
+            FACET_SEPARATOR,
+            clean(&attribute.shown()?)
+        )))
+        .collect::<Vec<_>>()
+        .join(ALT_SEPARATOR)
+}
+
+
+/// Writes master rows into a base, replacing rows with the same article number
+/// and appending new ones.
+///
+/// Rows are never removed: a product missing from one partner's pull may still
+/// be in another's, and the partner who no longer has it simply holds no offer
+/// for it. When every row matches what the base already holds, the base itself
+/// is handed back, so partners refreshed one after another keep sharing it.
+fn merge_base(base: Option<&Arc<BaseCatalog>>, rows: impl IntoIterator<Item = IndexedProduct>) -> Arc<BaseCatalog> {
+    let current = base.map(|base| base.records()).unwrap_or_default();
+    let current: &[IndexedProduct] = &current;
+    let mut positions: HashMap<String, usize> = current.iter()
+        .enumerate()
+        .map(|(position, product)| (product.no.clone(), position))
+        .collect();
+    // Copied on the first change only.
+    let mut merged: Option<Vec<IndexedProduct>> = None;
+
+    for row in rows {
+        match positions.get(&row.no).copied() {
+            Some(position) => {
+                let held = merged.as_deref().unwrap_or(current);
+                if held.get(position) != Some(&row) {
+                    merged.get_or_insert_with(|| current.to_vec())[position] = row;
+                }
+            }
+            None => {
+                let products = merged.get_or_insert_with(|| current.to_vec());
+                positions.insert(row.no.clone(), products.len());
+                products.push(row);
+            }
+        }
+    }
+
+    match (merged, base) {
+        (None, Some(base)) => Arc::clone(base),
+        (merged, _) => Arc::new(assemble(merged.unwrap_or_default()))
+    }
 }
 
 
@@ -1043,32 +1717,40 @@ pub async fn verify_authcode(authcode: &str, pid: i64, url: &str) -> Result<(),
 }
 
 
-/// Builds a complete snapshot from scratch.
-pub async fn build_snapshot(
-    authcode: &str,
-    pid: i64,
-    url: &str,
-    from_date: Option<DateTime<Utc>>
-) -> Result<CatalogSnapshot, SnapshotError> {
-    let started = std::time::Instant::now();
-    let parts = fetch_parts(authcode, pid, url, from_date).await?;
-
-    let products = parts.products.into_iter()
-        .map(|product| {
-            let price = parts.prices.get(&product.no);
-            let stock = parts.stocks.get(&product.no).copied();
-            let barcodes = parts.barcodes.get(&product.no).cloned().unwrap_or_default();
-            to_indexed(product, price, stock, barcodes)
-        })
-        .collect();
+/// A partner's snapshot from a full pull, over the url's current base (`None`
+/// when there is none yet).
+///
+/// The base is shared by every partner on the url, so a failed sub-call must
+/// not blank it: an empty barcode or attribute map keeps what the base already
+/// holds, as [`refresh_snapshot`] does, rather than stripping codes, facets and
+/// attribute filters from everyone.
+pub fn build_snapshot(base: Option<&Arc<BaseCatalog>>, parts: CatalogParts) -> CatalogSnapshot {
+    let mut rows = Vec::with_capacity(parts.products.len());
+    let mut offers = HashMap::with_capacity(parts.products.len());
+    for product in parts.products {
+        let price = parts.prices.get(&product.no);
+        let stock = parts.stocks.get(&product.no).copied();
+        let held = base.and_then(|base| base.product(&product.no));
+        let barcodes = if parts.barcodes.is_empty() {
+            held.as_ref().map(|row| row.barcodes.clone()).unwrap_or_default()
+        } else {
+            parts.barcodes.get(&product.no).cloned().unwrap_or_default()
+        };
+        let attributes = if parts.attributes.is_empty() {
+            held.map(|row| row.attributes.clone()).unwrap_or_default()
+        } else {
+            parts.attributes.get(&product.no).cloned().unwrap_or_default()
+        };
+        let (indexed, offer) = to_indexed(product, price, stock, barcodes, attributes);
+        offers.insert(indexed.no.clone(), offer);
+        rows.push(indexed);
+    }
 
-    let snapshot = assemble(products);
-    log_build("built", authcode, pid, &snapshot, started);
-    Ok(snapshot)
+    CatalogSnapshot::resolve(merge_base(base, rows), offers, Utc::now())
 }
 
 
-/// Refreshes an existing snapshot with only what changed since `since`.
+/// Refreshes an existing snapshot with only what changed since it was fetched.
 ///
 /// Necessary because an incremental product pull returns *only* changed records:
 /// publishing that as-is would replace a 24,000-product catalog with a handful of
@@ -1076,80 +1758,114 @@ pub async fn build_snapshot(
 /// replace their predecessors, new rows are appended, and everything else is
 /// carried forward with refreshed prices.
 ///
+/// The master rows go into `base`, the url's current base, rather than the one
+/// `previous` was built against: another partner's refresh may have moved it on
+/// since, and merging into the older one would undo that.
+///
 /// Incremental responses do not report products **deleted** in the ERP, which is
 /// why the precache job still schedules a full pull weekly.
-pub async fn refresh_snapshot(
+pub fn refresh_snapshot(
+    base: Option<&Arc<BaseCatalog>>,
     previous: &CatalogSnapshot,
-    authcode: &str,
-    pid: i64,
-    url: &str,
-    since: DateTime<Utc>
-) -> Result<CatalogSnapshot, SnapshotError> {
-    let started = std::time::Instant::now();
-    let parts = fetch_parts(authcode, pid, url, Some(since)).await?;
-
-    let mut products = previous.products.clone();
-    let mut positions: HashMap<String, usize> = HashMap::with_capacity(products.len());
-    for (position, product) in products.iter().enumerate() {
-        positions.insert(product.no.clone(), position);
+    parts: CatalogParts
+) -> CatalogSnapshot {
+    let base = base.unwrap_or(&previous.base);
+    let mut offers = previous.offer_map();
+    // The partner's master rows that differ from `base`, in a stable order.
+    let mut changed: BTreeMap<String, IndexedProduct> = BTreeMap::new();
+
+    // A row `base` lacks — only when it is not the base `previous` was built
+    // against and was lost in between — is carried over rather than dropped.
+    for no in offers.keys() {
+        if base.product(no).is_none()
+            && let Some(row) = previous.base.product(no) {
+                changed.insert(no.clone(), row.into_owned());
+        }
     }
 
     // Prices arrived in full, so they are authoritative for every row. A failed
     // price fetch yields an empty map — carry the previous figures rather than
     // blanking the catalog.
     if !parts.prices.is_empty() {
-        for product in products.iter_mut() {
-            let price = parts.prices.get(&product.no);
-            product.price = price.and_then(|entry| entry.sale_price);
-            product.currency = price.and_then(|entry| non_empty(entry.currency.clone()));
+        for (no, offer) in offers.iter_mut() {
+            let price = parts.prices.get(no);
+            offer.price = price.and_then(|entry| entry.sale_price);
+            offer.currency = price.and_then(|entry| non_empty(entry.currency.clone()));
         }
     }
 
     // Barcodes arrived in full, exactly like prices, so they are authoritative
     // for every row — including the removals an incremental pull cannot report.
-    // An empty map means the sub-call failed; keep what the snapshot already has
+    // An empty map means the sub-call failed; keep what the base already has
     // rather than blanking every product's codes.
     if !parts.barcodes.is_empty() {
-        for product in products.iter_mut() {
-            product.barcodes = parts.barcodes.get(&product.no).cloned().unwrap_or_default();
+        for no in offers.keys() {
+            let barcodes = parts.barcodes.get(no).cloned().unwrap_or_default();
+            let Some(row) = changed.get(no).map(Cow::Borrowed).or_else(|| base.product(no)) else {
+                continue
+            };
+            if row.barcodes != barcodes {
+                let mut row = row.into_owned();
+                row.barcodes = barcodes;
+                changed.insert(no.clone(), row);
+            }
+        }
+    }
+
+    // Attributes arrived in full as well, and are carried the same way.
+    if !parts.attributes.is_empty() {
+        for no in offers.keys() {
+            let attributes = parts.attributes.get(no).cloned().unwrap_or_default();
+            let Some(row) = changed.get(no).map(Cow::Borrowed).or_else(|| base.product(no)) else {
+                continue
+            };
+            if row.attributes != attributes {
+                let mut row = row.into_owned();
+                row.attributes = attributes;
+                changed.insert(no.clone(), row);
+            }
         }
     }
 
     // Stock deltas: only the rows that moved.
     for (no, level) in &parts.stocks {
-        if let Some(position) = positions.get(no)
-            && let Some(product) = products.get_mut(*position) {
-                product.stock = Some(*level);
+        if let Some(offer) = offers.get_mut(no) {
+            offer.stock = Some(*level);
         }
     }
 
     // Product deltas replace or append.
-    let changed = parts.products.len();
     for product in parts.products {
         let price = parts.prices.get(&product.no);
-        let existing = positions.get(&product.no).copied();
         let stock = parts.stocks.get(&product.no).copied()
-            .or_else(|| existing.and_then(|position| products.get(position)).and_then(|p| p.stock));
+            .or_else(|| offers.get(&product.no).and_then(|offer| offer.stock));
+        let held = changed.get(&product.no).map(Cow::Borrowed).or_else(|| base.product(&product.no));
         let barcodes = parts.barcodes.get(&product.no).cloned()
-            .or_else(|| existing.and_then(|position| products.get(position)).map(|p| p.barcodes.clone()))
+            .or_else(|| held.as_ref().map(|row| row.barcodes.clone()))
             .unwrap_or_default();
-        let indexed = to_indexed(product, price, stock, barcodes);
-
-        match existing {
-            Some(position) => products[position] = indexed,
-            None => products.push(indexed)
+        let attributes = if parts.attributes.is_empty() {
+            held.map(|row| row.attributes.clone()).unwrap_or_default()
+        } else {
+            parts.attributes.get(&product.no).cloned().unwrap_or_default()
+        };
+        let (indexed, mut offer) = to_indexed(product, price, stock, barcodes, attributes);
+        // A failed price pull keeps the previous figure here too.
+        if parts.prices.is_empty()
+            && let Some(held) = offers.get(&indexed.no) {
+                offer.price = held.price;
+                offer.currency = held.currency.clone();
         }
+        offers.insert(indexed.no.clone(), offer);
+        changed.insert(indexed.no.clone(), indexed);
     }
 
-    let snapshot = assemble(products);
-    log_build(&format!("refreshed ({} changed)", changed), authcode, pid, &snapshot, started);
-    Ok(snapshot)
+    CatalogSnapshot::resolve(merge_base(Some(base), changed.into_values()), offers, Utc::now())
 }
 
 
 /// One line per build, with the authcode masked and the measured size included
 /// so the configured budget can be tuned against reality.
-fn log_build(
+pub fn log_build(
     what: &str,
     authcode: &str,
     pid: i64,
@@ -1157,32 +1873,39 @@ fn log_build(
     started: std::time::Instant
 ) {
     logger(format!(
-        "MCP snapshot {} for {} pid={}: {} products, {:.1} MB, {:.1}s",
+        "MCP snapshot {} for {} pid={}: {} products, {:.1} MB own + {:.1} MB shared base, {:.1}s",
         what,
         mask_authcode(authcode),
         pid,
-        snapshot.products.len(),
+        snapshot.product_count(),
         snapshot.bytes as f64 / 1_048_576.0,
+        snapshot.base.bytes as f64 / 1_048_576.0,
         started.elapsed().as_secs_f64()
     ));
 }
 
 
 /// Projects an English `Product` plus the caller's price and stock into the
-/// indexed record, dropping empty fields and flattening the description.
+/// shared record and the partner's offer, dropping empty fields and flattening
+/// the description.
 fn to_indexed(
     product: Product,
     price: Option<&Price>,
     stock: Option<f64>,
-    barcodes: Vec<String>
-) -> IndexedProduct {
-    IndexedProduct {
+    barcodes: Vec<String>,
+    attributes: Vec<ProductAttribute>
+) -> (IndexedProduct, Offer) {
+    // Octopus uses `webmegjel` as a small enum, not a flag: 1 is published,
+    // every other value is one of the ways a product can be withheld.
+    let available = product.web_available.get() == 1;
+    let indexed = IndexedProduct {
         id: product.id,
         no: product.no,
         name: product.name,
         brand: non_empty(product.brand),
         oem_code: non_empty(product.oem_code),
         barcodes,
+        attributes,
         unit: non_empty(product.unit),
         base_unit: non_empty(product.base_unit),
         base_unit_qty: product.base_unit_qty,
@@ -1194,21 +1917,22 @@ fn to_indexed(
         weight: product.weight,
         size: product.size.map(Dimensions::from),
         sell_unit: product.sell_unit,
-        origin_country: non_empty(product.origin_country),
-        // Octopus uses `webmegjel` as a small enum, not a flag: 1 is published,
-        // every other value is one of the ways a product can be withheld.
-        available: product.web_available.get() == 1,
+        origin_country: non_empty(product.origin_country)
+    };
+    let offer = Offer {
+        available,
         // `akcios_ar` only, with no fallback to `ar` or `listaar`: those two mean
         // different things for different partners, so falling back would quote a
         // retail price as if it were the caller's own.
         price: price.and_then(|p| p.sale_price),
         currency: price.and_then(|p| non_empty(p.currency.clone())),
         stock
-    }
+    };
+    (indexed, offer)
 }
 
 
-/// Approximate heap footprint of a snapshot, in bytes.
+/// Approximate heap footprint of a base, in bytes.
 ///
 /// Deliberately an estimate: it counts the struct arrays plus every owned
 /// string's capacity, which is where a catalog's memory actually goes. It feeds
@@ -1227,20 +1951,29 @@ fn measure_bytes(
         bytes += product.name.capacity() as u64;
         bytes += (product.barcodes.capacity() * size_of::<String>()) as u64;
         bytes += product.barcodes.iter().map(|ean| ean.capacity() as u64).sum::<u64>();
+        bytes += (product.attributes.capacity() * size_of::<ProductAttribute>()) as u64;
+        for attribute in &product.attributes {
+            bytes += attribute.code.capacity() as u64;
+            bytes += [&attribute.name, &attribute.value].into_iter()
+                .map(|field| field.as_ref().map_or(0, |value| value.capacity()) as u64)
+                .sum::<u64>();
+        }
         for field in [
             &product.brand, &product.oem_code, &product.unit, &product.base_unit,
             &product.category_code, &product.category_name, &product.main_category_code,
-            &product.main_category_name, &product.description, &product.origin_country,
-            &product.currency
+            &product.main_category_name, &product.description, &product.origin_country
         ] {
             bytes += field.as_ref().map_or(0, |value| value.capacity()) as u64;
         }
     }
 
     for entry in folded {
-        bytes += (entry.name.capacity() + entry.rest.capacity() + entry.sku.capacity()) as u64;
-        bytes += (entry.alt_codes.capacity() * size_of::<String>()) as u64;
-        bytes += entry.alt_codes.iter().map(|code| code.capacity() as u64).sum::<u64>();
+        for field in [
+            &entry.name, &entry.sku, &entry.oem, &entry.barcodes,
+            &entry.brand, &entry.category, &entry.main_category, &entry.description, &entry.facets
+        ] {
+            bytes += field.capacity() as u64;
+        }
     }
 
     // HashMap overhead: roughly the key plus one bucket entry per row.
@@ -1252,31 +1985,63 @@ fn measure_bytes(
 }
 
 
+/// Approximate heap footprint of one partner's offers, in bytes: the array and
+/// the currency strings, which is all an overlay owns.
+fn measure_offers(offers: &[Option<Offer>]) -> u64 {
+    let mut bytes = size_of_val(offers) as u64;
+    for offer in offers.iter().flatten() {
+        bytes += offer.currency.as_ref().map_or(0, |currency| currency.capacity()) as u64;
+    }
+    bytes
+}
+
+
 /// A synthetic snapshot of a declared size, for exercising the cache's budget
 /// and eviction behaviour without a live ERP.
 #[cfg(test)]
 pub fn test_snapshot(bytes: u64) -> CatalogSnapshot {
     CatalogSnapshot {
-        products: Vec::new(),
-        by_sku: HashMap::new(),
-        folded: Vec::new(),
+        base: Arc::new(assemble(Vec::new())),
+        offers: Vec::new(),
         fetched_at: Utc::now(),
         bytes
     }
 }
 
 
-/// A synthetic snapshot holding real products, for exercising serialization and
-/// the disk store.
+/// A base built on the heap from the given rows, for the layout tests.
 #[cfg(test)]
-pub fn test_snapshot_with_products(count: usize) -> CatalogSnapshot {
-    let products = (0..count)
-        .map(|index| test_product(&format!("A-{}", index), "Szövegkiemelő", "Orink", ""))
-        .collect();
+pub fn test_base(products: Vec<IndexedProduct>) -> BaseCatalog {
     assemble(products)
 }
 
 
+/// A snapshot of the given rows, each with its offer, for the tools' tests.
+#[cfg(test)]
+pub fn test_snapshot_of(rows: Vec<(IndexedProduct, Offer)>) -> CatalogSnapshot {
+    let offers = rows.iter().map(|(product, offer)| (product.no.clone(), offer.clone())).collect();
+    let products = rows.into_iter().map(|(product, _)| product).collect();
+    CatalogSnapshot::resolve(Arc::new(assemble(products)), offers, Utc::now())
+}
+
+
+/// A synthetic snapshot holding real products, every one on offer, for
+/// exercising serialization and the disk store.
+#[cfg(test)]
+pub fn test_snapshot_with_products(count: usize) -> CatalogSnapshot {
+    test_snapshot_of((0..count)
+        .map(|index| (test_product(&format!("A-{}", index), "Szövegkiemelő", "Orink", ""), test_offer()))
+        .collect())
+}
+
+
+/// An offer for a product on sale, with no price or stock.
+#[cfg(test)]
+pub fn test_offer() -> Offer {
+    Offer { available: true, ..Default::default() }
+}
+
+
 /// A synthetic product, shared by this module's tests and the export module's.
 #[cfg(test)]
 pub fn test_product(no: &str, name: &str, brand: &str, oem: &str) -> IndexedProduct {
@@ -1287,6 +2052,7 @@ pub fn test_product(no: &str, name: &str, brand: &str, oem: &str) -> IndexedProd
         brand: non_empty(brand.into()),
         oem_code: non_empty(oem.into()),
         barcodes: Vec::new(),
+        attributes: Vec::new(),
         unit: None,
         base_unit: None,
         base_unit_qty: None,
@@ -1298,11 +2064,7 @@ pub fn test_product(no: &str, name: &str, brand: &str, oem: &str) -> IndexedProd
         weight: None,
         size: None,
         sell_unit: None,
-        origin_country: None,
-        available: true,
-        price: None,
-        currency: None,
-        stock: None
+        origin_country: None
     }
 }
 
@@ -1321,6 +2083,7 @@ mod tests {
             brand: non_empty(brand.into()),
             oem_code: non_empty(oem.into()),
             barcodes: Vec::new(),
+            attributes: Vec::new(),
             unit: None,
             base_unit: None,
             base_unit_qty: None,
@@ -1332,11 +2095,7 @@ mod tests {
             weight: None,
             size: None,
             sell_unit: None,
-            origin_country: None,
-            available: true,
-            price: None,
-            currency: None,
-            stock: None
+            origin_country: None
         }
     }
 
@@ -1377,9 +2136,9 @@ mod tests {
         // Octopus's `ar` (1882) is the wrong figure to publish — it is the retail
         // price for some partners and the net one for others. Only `akcios_ar`
         // means the same thing for everyone, so that is the one `price` carries.
-        let indexed = to_indexed(product, Some(&source), None, Vec::new());
-        assert_eq!(indexed.price, Some(1495.0));
-        assert_eq!(ProductSummary::from(&indexed).price, Some(1495.0));
+        let (indexed, offer) = to_indexed(product, Some(&source), None, Vec::new(), Vec::new());
+        assert_eq!(offer.price, Some(1495.0));
+        assert_eq!(ProductSummary::from(ProductView { product: Cow::Borrowed(&indexed), offer: &offer }).price, Some(1495.0));
     }
 
     #[test]
@@ -1420,13 +2179,21 @@ mod tests {
 
         // Falling back to `ar` here would quote 999 as if the partner had agreed
         // to it. Better to show no price than the wrong one.
-        assert_eq!(to_indexed(full, Some(&source), None, Vec::new()).price, None);
+        assert_eq!(to_indexed(full, Some(&source), None, Vec::new(), Vec::new()).1.price, None);
     }
 
     /// Straight through `assemble`, so the tests exercise the real folding and
     /// the real haystack composition rather than a copy that can drift from it.
     fn snapshot(rows: Vec<IndexedProduct>) -> CatalogSnapshot {
-        assemble(rows)
+        snapshot_withholding(rows, &[])
+    }
+
+    /// [`snapshot`], with the named article numbers held but not on offer.
+    fn snapshot_withholding(rows: Vec<IndexedProduct>, withheld: &[&str]) -> CatalogSnapshot {
+        let offers = rows.iter()
+            .map(|row| (row.no.clone(), Offer { available: !withheld.contains(&row.no.as_str()), ..Default::default() }))
+            .collect();
+        CatalogSnapshot::resolve(Arc::new(assemble(rows)), offers, Utc::now())
     }
 
     /// An English out-model product with a chosen `webmegjel`, for exercising
@@ -1461,19 +2228,18 @@ mod tests {
     fn only_webmegjel_one_counts_as_available() {
         // `webmegjel` is a small enum, not a boolean: 2 and 3 are distinct ways
         // of being withheld, and neither may read as "on offer".
-        assert!(to_indexed(out_product("A-1", 1), None, None, Vec::new()).available);
-        assert!(!to_indexed(out_product("A-2", 2), None, None, Vec::new()).available);
-        assert!(!to_indexed(out_product("A-3", 3), None, None, Vec::new()).available);
+        assert!(to_indexed(out_product("A-1", 1), None, None, Vec::new(), Vec::new()).1.available);
+        assert!(!to_indexed(out_product("A-2", 2), None, None, Vec::new(), Vec::new()).1.available);
+        assert!(!to_indexed(out_product("A-3", 3), None, None, Vec::new(), Vec::new()).1.available);
     }
 
     #[test]
     fn withheld_products_are_absent_from_search_and_export() {
-        let mut rows = vec![
+        let rows = vec![
             product("A1", "Highlighter", "Orink", ""),
             product("A2", "Highlighter", "Orink", "")
         ];
-        rows[1].available = false;
-        let snapshot = snapshot(rows);
+        let snapshot = snapshot_withholding(rows, &["A2"]);
 
         let outcome = snapshot.search("highlighter", &SearchFilters::default(), 10);
         assert_eq!(outcome.matched, 1);
@@ -1487,12 +2253,11 @@ mod tests {
     fn a_withheld_product_is_still_reachable_by_article_number() {
         // The point of keeping it in the snapshot: a partner quoting a number
         // off an old order gets "not on offer", not "no such product".
-        let mut rows = vec![product("A1", "Pen", "Orink", "MFG-9")];
-        rows[0].available = false;
-        let snapshot = snapshot(rows);
+        let rows = vec![product("A1", "Pen", "Orink", "MFG-9")];
+        let snapshot = snapshot_withholding(rows, &["A1"]);
 
-        assert_eq!(snapshot.get_by_no("A1").map(|p| p.available), Some(false));
-        assert_eq!(snapshot.get_by_no("MFG-9").map(|p| p.no.as_str()), Some("A1"));
+        assert_eq!(snapshot.get_by_no("A1").map(|p| p.offer.available), Some(false));
+        assert_eq!(snapshot.get_by_no("MFG-9").as_ref().map(|p| p.product.no.as_str()), Some("A1"));
     }
 
     #[test]
@@ -1501,10 +2266,9 @@ mod tests {
             product("ABCD-1", "Pen", "Orink", ""),
             product("ABCD-2", "Pen", "Orink", "")
         ];
-        rows[1].available = false;
         rows[0].category_name = Some("Pens".into());
         rows[1].category_name = Some("Pens".into());
-        let snapshot = snapshot(rows);
+        let snapshot = snapshot_withholding(rows, &["ABCD-2"]);
 
         assert_eq!(snapshot.available_count(), 1);
         assert_eq!(snapshot.hidden_count(), 1);
@@ -1534,11 +2298,11 @@ mod tests {
             let has_code = result.primary_barcode.as_deref() == Some(ean)
                 || result.secondary_barcodes.iter().any(|code| code == ean);
             assert!(has_code, "row must echo back {}", ean);
-            assert_eq!(snapshot.get_by_no(ean).map(|p| p.no.as_str()), Some("A1"), "lookup must find {}", ean);
+            assert_eq!(snapshot.get_by_no(ean).as_ref().map(|p| p.product.no.as_str()), Some("A1"), "lookup must find {}", ean);
         }
         // The manufacturer code still works — barcodes joined that bucket, they
         // did not replace it.
-        assert_eq!(snapshot.get_by_no("MFG-1").map(|p| p.no.as_str()), Some("A1"));
+        assert_eq!(snapshot.get_by_no("MFG-1").as_ref().map(|p| p.product.no.as_str()), Some("A1"));
     }
 
     #[test]
@@ -1584,6 +2348,72 @@ mod tests {
         assert_eq!(snapshot.search("blue stapler", &SearchFilters::default(), 10).matched, 0);
     }
 
+    #[test]
+    fn typos_and_word_starts_still_find_the_product() {
+        let snapshot = snapshot(vec![
+            product("SM-27", "Samsung Odyssey monitor 27\"", "Samsung", ""),
+            product("LG-27", "LG UltraGear monitor 27\"", "LG", ""),
+            product("SM-TV", "Samsung televízió 55\"", "Samsung", "")
+        ]);
+
+        let outcome = snapshot.search("samsnug monitr", &SearchFilters::default(), 10);
+        assert_eq!(outcome.matched, 1);
+        assert_eq!(outcome.results[0].no, "SM-27");
+        let highlight = outcome.results[0].highlight.as_ref().expect("a search row carries its highlight");
+        assert_eq!(highlight.name.as_deref(), Some("**Samsung** Odyssey **monitor** 27\""));
+
+        assert_eq!(snapshot.search("telev", &SearchFilters::default(), 10).results[0].no, "SM-TV");
+    }
+
+    #[test]
+    fn synonyms_find_the_other_language_and_units_either_spelling() {
+        let snapshot = snapshot(vec![
+            product("M185", "Logitech M185 wireless mouse", "Logitech", ""),
+            product("T7", "Samsung T7 hordozható SSD 2 TB", "Samsung", ""),
+            product("M28", "HP LaserJet multifunkciós készülék", "HP", ""),
+            product("LJ", "HP LaserJet nyomtató", "HP", "")
+        ]);
+        let filters = SearchFilters::default();
+
+        let outcome = snapshot.search("vezeték nélküli egér", &filters, 10);
+        assert_eq!(outcome.matched, 1);
+        let highlight = outcome.results[0].highlight.as_ref().expect("a synonym hit is highlighted");
+        assert_eq!(highlight.name.as_deref(), Some("Logitech M185 **wireless** **mouse**"));
+
+        assert_eq!(snapshot.search("2tb ssd", &filters, 10).results[0].no, "T7");
+        assert_eq!(snapshot.search("2 terabyte", &filters, 10).results[0].no, "T7");
+
+        // One way: a printer search shows the multifunction device, not the reverse.
+        let printers: Vec<String> = snapshot.search("printer", &filters, 10).results.into_iter().map(|row| row.no).collect();
+        assert_eq!(printers, ["LJ", "M28"]);
+        assert_eq!(snapshot.search("multifunkciós", &filters, 10).matched, 1);
+    }
+
+    #[test]
+    fn fields_rank_in_boost_order() {
+        let mut rows = vec![
+            product("D1", "Stapler", "Orink", ""),
+            product("B1", "Stapler", "Kangaro", ""),
+            product("N1", "Kangaro stapler", "Orink", ""),
+            product("O1", "Stapler", "Orink", "KANGARO")
+        ];
+        rows[0].description = Some("Fits every Kangaro staple.".into());
+        let snapshot = snapshot(rows);
+
+        let order: Vec<String> = snapshot.search("kangaro", &SearchFilters::default(), 10)
+            .results.into_iter().map(|row| row.no).collect();
+        assert_eq!(order, ["O1", "N1", "B1", "D1"]);
+    }
+
+    #[test]
+    fn a_short_name_outranks_a_long_one_for_the_same_word() {
+        let snapshot = snapshot(vec![
+            product("A1", "Stapler with a long descriptive name for the box", "Orink", ""),
+            product("A2", "Stapler", "Orink", "")
+        ]);
+        assert_eq!(snapshot.search("stapler", &SearchFilters::default(), 10).results[0].no, "A2");
+    }
+
     #[test]
     fn accent_folded_query_finds_accented_name() {
         let snapshot = snapshot(vec![product("A1", "Szövegkiemelő sárga", "Orink", "")]);
@@ -1609,9 +2439,9 @@ mod tests {
         rows[0].id = 4242;
         let snapshot = snapshot(rows);
 
-        assert_eq!(snapshot.get_by_no("a1").map(|p| p.no.as_str()), Some("A1"));
-        assert_eq!(snapshot.get_by_no("MFG-77").map(|p| p.no.as_str()), Some("A1"));
-        assert_eq!(snapshot.get_by_no("4242").map(|p| p.no.as_str()), Some("A1"));
+        assert_eq!(snapshot.get_by_no("a1").as_ref().map(|p| p.product.no.as_str()), Some("A1"));
+        assert_eq!(snapshot.get_by_no("MFG-77").as_ref().map(|p| p.product.no.as_str()), Some("A1"));
+        assert_eq!(snapshot.get_by_no("4242").as_ref().map(|p| p.product.no.as_str()), Some("A1"));
         assert!(snapshot.get_by_no("nope").is_none());
     }
 
@@ -1627,6 +2457,149 @@ mod tests {
         assert_eq!(outcome.results[0].no, "A1");
     }
 
+    #[test]
+    fn query_operators_filter_on_price_stock_size_and_brand() {
+        let mut rows = vec![
+            product("S27", "Samsung monitor 27 col", "Samsung", ""),
+            product("S32", "Samsung monitor 32 col", "Samsung", ""),
+            product("L27", "LG monitor 27 col", "LG", ""),
+            product("R27", "Samsung monitor 27 col refurbished", "Samsung", "")
+        ];
+        rows[0].weight = Some(4.2);
+        rows[1].weight = Some(7.5);
+        let offers = [("S27", 69_990.0, 3.0), ("S32", 99_990.0, 8.0), ("L27", 59_990.0, 0.0), ("R27", 39_990.0, 1.0)]
+            .into_iter()
+            .map(|(no, price, stock)| (no.to_string(), Offer { available: true, price: Some(price), stock: Some(stock), ..Default::default() }))
+            .collect();
+        let snapshot = CatalogSnapshot::resolve(Arc::new(assemble(rows)), offers, Utc::now());
+        let found = |query: &str| -> Vec<String> {
+            let mut found: Vec<String> = snapshot.search(query, &SearchFilters::default(), 10).results.into_iter().map(|row| row.no).collect();
+            found.sort();
+            found
+        };
+
+        assert_eq!(found("27 inch monitor price<80000 stock>0"), ["R27", "S27"]);
+        assert_eq!(found("monitor brand:samsung -refurbished"), ["S27", "S32"]);
+        assert_eq!(found("monitor ár:60000..100000"), ["S27", "S32"]);
+        assert_eq!(found("monitor -márka:samsung"), ["L27"]);
+        // A product with no weight never passes a weight condition.
+        assert_eq!(found("monitor weight<5"), ["S27"]);
+        assert_eq!(found("\"monitor 32\""), ["S32"]);
+        assert_eq!(found("\"32 monitor\""), Vec::<String>::new());
+        // Operators alone are a query too, and export counts them alike.
+        assert_eq!(found("stock=0"), ["L27"]);
+        assert_eq!(snapshot.count_matching("price>=90000", &SearchFilters::default()), 1);
+    }
+
+    fn mat(no: &str, code: &str, value: Option<&str>, number: Option<f64>, filter: bool, order: i64) -> Attribute {
+        Attribute {
+            id: 1,
+            code: Some(code.into()),
+            name: Some(format!("{} name", code)),
+            product_id: None,
+            product_no: Some(no.into()),
+            string_value: value.map(Into::into),
+            num_value: number,
+            order: Some(order),
+            delstatus: None,
+            filter: filter.then_some(NonZeroU8::MIN),
+            data_type: None,
+            value_set: None
+        }
+    }
+
+    #[test]
+    fn filterable_attributes_are_facets_to_list_and_filter_on() {
+        let mut deleted = mat("L8", "RAM", Some("32 GB"), None, true, 0);
+        deleted.delstatus = Some(NonZeroU8::MIN);
+        let mut grouped = group_attributes(vec![
+            mat("L16", "OS", Some("Windows 11 Pro"), None, true, 2),
+            mat("L16", "RAM", Some("16 GB"), None, true, 1),
+            mat("L16", "COLOR", Some("Black"), None, false, 3),
+            mat("L8", "RAM", None, Some(8.0), true, 1),
+            deleted,
+            mat("L32", "RAM", Some("32 GB"), None, true, 1),
+            mat("L32", "NOTE", None, None, true, 1)
+        ]);
+        assert_eq!(
+            grouped["L16"].iter().map(|attribute| attribute.code.as_str()).collect::<Vec<_>>(),
+            ["RAM", "OS", "COLOR"]
+        );
+        assert_eq!(grouped["L8"].len(), 1, "deleted rows are dropped");
+        assert_eq!(grouped["L32"].len(), 1, "rows without a value are dropped");
+
+        let rows = ["L8", "L16", "L32"].map(|no| {
+            let mut row = product(no, "Laptop", "Lenovo", "");
+            row.attributes = grouped.remove(no).unwrap_or_default();
+            row
+        });
+        let snapshot = snapshot(rows.to_vec());
+        let found = |attributes: &[(&str, &str)]| -> Vec<String> {
+            let mut filters = SearchFilters::default();
+            for (name, value) in attributes {
+                filters.predicates.extend(syntax::attribute(name, value).expect("parses"));
+            }
+            let mut found: Vec<String> = snapshot.search("laptop", &filters, 10).results.into_iter().map(|row| row.no).collect();
+            found.sort();
+            found
+        };
+
+        assert_eq!(found(&[("ram", "16")]), ["L16"]);
+        assert_eq!(found(&[("RAM name", ">=16")]), ["L16", "L32"]);
+        assert_eq!(found(&[("ram", "..8")]), ["L8"]);
+        assert_eq!(found(&[("ram", "8..16"), ("os", "windows 11 pro")]), ["L16"]);
+        // Only attributes the ERP marks as filters are indexed.
+        assert_eq!(found(&[("color", "black")]), Vec::<String>::new());
+
+        let listed = snapshot.attributes();
+        assert_eq!(listed.iter().map(|facet| (facet.code.as_str(), facet.count)).collect::<Vec<_>>(), [("RAM", 3), ("OS", 1)]);
+        assert_eq!(listed[0].name.as_deref(), Some("RAM name"));
+        assert!(listed[0].is("ram name"));
+        let mut values: Vec<(&str, u32)> = listed[0].values.iter().map(|value| (value.value.as_str(), value.count)).collect();
+        values.sort();
+        assert_eq!(values, [("16 GB", 1), ("32 GB", 1), ("8", 1)]);
+    }
+
+    #[test]
+    fn a_search_breaks_its_matches_down_into_facets() {
+        let mut rows = vec![
+            product("P1", "Toll kék", "Pax", ""),
+            product("P2", "Toll piros", "Pax", ""),
+            product("O1", "Toll fekete", "Orink", ""),
+            product("F1", "Füzet", "Pax", "")
+        ];
+        for row in &mut rows[..3] {
+            row.category_code = Some("T".into());
+            row.category_name = Some("Tollak".into());
+        }
+        rows[0].attributes = vec![ProductAttribute {
+            code: "SZIN".into(), name: Some("Szín".into()), value: Some("Kék".into()),
+            number: None, filterable: true, value_set: None
+        }];
+        let offers = [("P1", Some(450.0), 5.0), ("P2", Some(1_200.0), 0.0), ("O1", None, 2.0), ("F1", Some(300.0), 1.0)]
+            .into_iter()
+            .map(|(no, price, stock)| (no.to_string(), Offer { available: true, price, stock: Some(stock), ..Default::default() }))
+            .collect();
+        let snapshot = CatalogSnapshot::resolve(Arc::new(assemble(rows)), offers, Utc::now());
+
+        // Counted over every match, not just the page.
+        let facets = snapshot.search("toll", &SearchFilters::default(), 1).facets;
+        assert_eq!(facets.brands.iter().map(|brand| (brand.name.as_str(), brand.count)).collect::<Vec<_>>(), [("Pax", 2), ("Orink", 1)]);
+        assert_eq!(facets.categories.iter().map(|group| (group.code.as_deref(), group.count)).collect::<Vec<_>>(), [(Some("T"), 3)]);
+        assert!(facets.main_categories.is_empty());
+        assert_eq!((facets.in_stock, facets.out_of_stock), (2, 1));
+        assert_eq!(
+            facets.price_bands.iter().map(|band| (band.query.as_str(), band.count)).collect::<Vec<_>>(),
+            [("price<1000", 1), ("price>=1000 price<2500", 1)]
+        );
+        assert_eq!(facets.attributes.len(), 1);
+        assert_eq!(facets.attributes[0].values[0].value, "Kék");
+
+        // A band's query narrows to exactly that band.
+        assert_eq!(snapshot.search("toll price>=1000 price<2500", &SearchFilters::default(), 10).matched, 1);
+        assert_eq!(facets.top(1).brands.len(), 1);
+    }
+
     #[test]
     fn search_reports_total_matches_beyond_the_limit() {
         let rows = (0..10).map(|i| product(&format!("A{}", i), "Pen", "Orink", "")).collect();
@@ -1735,4 +2708,97 @@ mod tests {
         assert_eq!(categories.brands[0].count, 2);
         assert_eq!(categories.categories[0].count, 3);
     }
+
+    /// One partner's pull: the given products, each at the given price.
+    fn parts(products: Vec<Product>, price: f64) -> CatalogParts {
+        let prices = products.iter()
+            .map(|product| (product.no.clone(), Price {
+                id: product.id,
+                no: product.no.clone(),
+                list_price: None,
+                price: None,
+                sale_price: Some(price),
+                currency: "HUF".into()
+            }))
+            .collect();
+        CatalogParts { products, prices, stocks: HashMap::new(), barcodes: HashMap::new(), attributes: HashMap::new() }
+    }
+
+    #[test]
+    fn partners_of_one_url_share_one_base_under_their_own_prices() {
+        let first = build_snapshot(None, parts(vec![out_product("A-1", 1), out_product("A-2", 1)], 100.0));
+        let second = build_snapshot(Some(&first.base), parts(vec![out_product("A-1", 1), out_product("A-2", 1)], 90.0));
+
+        assert!(Arc::ptr_eq(&first.base, &second.base), "identical master data was copied");
+        assert_eq!(first.get_by_no("A-1").and_then(|p| p.offer.price), Some(100.0));
+        assert_eq!(second.get_by_no("A-1").and_then(|p| p.offer.price), Some(90.0));
+    }
+
+    #[test]
+    fn a_partner_sees_only_the_products_its_pull_returned() {
+        let first = build_snapshot(None, parts(vec![out_product("A-1", 1), out_product("A-2", 1)], 100.0));
+        let second = build_snapshot(Some(&first.base), parts(vec![out_product("A-1", 1)], 90.0));
+
+        assert!(Arc::ptr_eq(&first.base, &second.base));
+        assert!(second.get_by_no("A-2").is_none());
+        assert_eq!(second.product_count(), 1);
+    }
+
+    #[test]
+    fn a_changed_master_row_yields_a_new_base() {
+        let first = build_snapshot(None, parts(vec![out_product("A-1", 1)], 100.0));
+        let mut renamed = out_product("A-1", 1);
+        renamed.name = "Pen, blue".into();
+        let second = build_snapshot(Some(&first.base), parts(vec![renamed, out_product("A-3", 1)], 100.0));
+
+        assert!(!Arc::ptr_eq(&first.base, &second.base));
+        // The base in use stays as it was; only snapshots built later move on.
+        assert_eq!(first.get_by_no("A-1").as_ref().map(|p| p.product.name.as_str()), Some("Pen"));
+        assert_eq!(second.get_by_no("A-1").as_ref().map(|p| p.product.name.as_str()), Some("Pen, blue"));
+        assert_eq!(second.base.len(), 2);
+    }
+
+    #[test]
+    fn a_full_pull_without_barcodes_keeps_the_bases_codes() {
+        let mut pull = parts(vec![out_product("A-1", 1)], 100.0);
+        pull.barcodes.insert("A-1".into(), vec!["5999000000011".into()]);
+        let first = build_snapshot(None, pull);
+
+        // The barcode sub-call failed for another partner on the same url.
+        let second = build_snapshot(Some(&first.base), parts(vec![out_product("A-1", 1)], 90.0));
+        assert!(Arc::ptr_eq(&first.base, &second.base), "a failed sub-call rewrote the shared base");
+        assert_eq!(second.get_by_no("A-1").map(|p| p.product.barcodes.clone()), Some(vec!["5999000000011".to_string()]));
+    }
+
+    #[test]
+    fn a_full_pull_without_attributes_keeps_the_bases_facets() {
+        let ram = ProductAttribute {
+            code: "RAM".into(),
+            name: Some("RAM".into()),
+            value: Some("16 GB".into()),
+            number: None,
+            filterable: true,
+            value_set: None
+        };
+        let mut pull = parts(vec![out_product("A-1", 1)], 100.0);
+        pull.attributes.insert("A-1".into(), vec![ram.clone()]);
+        let first = build_snapshot(None, pull);
+
+        // The MAT sub-call failed for another partner on the same url.
+        let second = build_snapshot(Some(&first.base), parts(vec![out_product("A-1", 1)], 90.0));
+        assert!(Arc::ptr_eq(&first.base, &second.base), "a failed sub-call rewrote the shared base");
+        assert_eq!(second.get_by_no("A-1").map(|p| p.product.attributes.clone()), Some(vec![ram]));
+    }
+
+    #[test]
+    fn a_refresh_merges_into_the_current_base() {
+        let first = build_snapshot(None, parts(vec![out_product("A-1", 1)], 100.0));
+        // Another partner's build added a product since.
+        let other = build_snapshot(Some(&first.base), parts(vec![out_product("A-1", 1), out_product("A-2", 1)], 90.0));
+
+        let refreshed = refresh_snapshot(Some(&other.base), &first, parts(Vec::new(), 0.0));
+        assert!(Arc::ptr_eq(&refreshed.base, &other.base), "the refresh left the shared base behind");
+        assert_eq!(refreshed.product_count(), 1);
+        assert!(refreshed.get_by_no("A-2").is_none());
+    }
 }
@@ -0,0 +1,592 @@
+//! Binary layout of the disk tier's files.
+//!
+//! The tier used to hold gzipped JSON, and a snapshot read back from it had to
+//! be inflated and parsed whole — about 90 ms per tool call with `max_bytes = 0`,
+//! where nothing stays resident. A base file is now laid out so it can be
+//! mapped and queried where it lies:
+//!
+//! ```text
+//! header      64 bytes: magic, SNAPSHOT_VERSION, kind, counts, blob lengths
+//! ids         u64 per product, for lookup by internal record id
+//! record ends u32 per product, cumulative into the record blob
+//! string ends u32 per product and string field, cumulative into the string blob
+//! codes       (offset u32, length u32, position u32) per code, sorted by code
+//! records     each product's JSON, decoded only when it is returned
+//! strings     article numbers, the folded haystacks and the facets, read in place
+//! ```
+//!
+//! The haystacks and the sorted code table are the search index, built once
+//! when the base is written. A lookup by article number or barcode is a binary
+//! search over the code table, and a search reads the haystacks and decodes
+//! only the records on the page it returns, so a call touches the pages it
+//! needs and the OS page cache decides what stays warm.
+//!
+//! Records stay JSON inside the file on purpose: [`IndexedProduct`] is already
+//! `serde`, and a record format of its own would be one more place to update
+//! each time a field is added.
+//!
+//! An overlay is small and always wanted whole, so it is a plain sequential
+//! encoding, decoded in one pass.
+//!
+//! Everything is little-endian and read through `from_le_bytes`, never cast in
+//! place, so nothing depends on alignment.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use memmap2::Mmap;
+
+use crate::service::{
+    mcp::index::{
+        ALT_SEPARATOR, BaseCatalog, Haystack, IndexedProduct, Offer, PersistedOverlay, SNAPSHOT_VERSION
+    },
+    sealed
+};
+
+/// First bytes of every file in this layout.
+const MAGIC: &[u8; 8] = b"RTPSNAP\0";
+
+const HEADER_BYTES: usize = 64;
+
+/// What a file holds, recorded in its header so one can never be read as the
+/// other.
+const KIND_BASE: u32 = 1;
+const KIND_OVERLAY: u32 = 2;
+
+/// String fields stored per product, in this order.
+const NO: usize = 0;
+const NAME: usize = 1;
+const SKU: usize = 2;
+const OEM: usize = 3;
+const BARCODES: usize = 4;
+const BRAND: usize = 5;
+const CATEGORY: usize = 6;
+const MAIN_CATEGORY: usize = 7;
+const DESCRIPTION: usize = 8;
+const FACETS: usize = 9;
+const FIELDS: usize = 10;
+
+/// Bytes per entry in the code table.
+const CODE_BYTES: usize = 12;
+
+/// Marks a code-table position as a secondary code rather than an article
+/// number. Positions stay far below it.
+const ALT_FLAG: u32 = 1 << 31;
+
+
+/// The bytes of a base file: mapped, or — for a sealed file, which has to be
+/// decrypted before it can be read — opened into memory.
+#[derive(Debug)]
+enum Backing {
+    Mapped(Mmap),
+    Opened(Vec<u8>)
+}
+
+impl std::ops::Deref for Backing {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Backing::Mapped(map) => map,
+            Backing::Opened(bytes) => bytes
+        }
+    }
+}
+
+
+/// Where each section of a base file starts, worked out from the header.
+#[derive(Debug, Clone, Copy)]
+struct Sections {
+    count: usize,
+    codes: usize,
+    ids: usize,
+    record_ends: usize,
+    string_ends: usize,
+    code_table: usize,
+    records: usize,
+    strings: usize,
+    end: usize
+}
+
+impl Sections {
+    fn new(count: usize, codes: usize, records_len: usize, strings_len: usize) -> Option<Self> {
+        let ids = HEADER_BYTES;
+        let record_ends = ids.checked_add(count.checked_mul(8)?)?;
+        let string_ends = record_ends.checked_add(count.checked_mul(4)?)?;
+        let code_table = string_ends.checked_add(count.checked_mul(FIELDS * 4)?)?;
+        let records = code_table.checked_add(codes.checked_mul(CODE_BYTES)?)?;
+        let strings = records.checked_add(records_len)?;
+        let end = strings.checked_add(strings_len)?;
+        Some(Self { count, codes, ids, record_ends, string_ends, code_table, records, strings, end })
+    }
+}
+
+
+/// A base read back from the disk tier and queried where it lies.
+#[derive(Debug)]
+pub struct MappedBase {
+    bytes: Backing,
+    sections: Sections
+}
+
+impl MappedBase {
+    /// Maps a base file, or opens it into memory when it is sealed. `context`
+    /// is the name it was sealed under.
+    ///
+    /// Only the header is checked here; every read after it is bounds-checked,
+    /// so a damaged body answers with nothing rather than with a panic.
+    pub fn open(path: &Path, context: &str) -> Result<Self, String> {
+        let file = std::fs::File::open(path).map_err(|error| error.to_string())?;
+        // SAFETY: the store only ever replaces these files by renaming a new
+        // one over them, so the inode mapped here is never written again. A
+        // process truncating it by hand is the one way this read can fault.
+        let map = unsafe { Mmap::map(&file) }.map_err(|error| error.to_string())?;
+        let bytes = if sealed::is_sealed(&map) {
+            Backing::Opened(sealed::open(map.to_vec(), context)?)
+        } else {
+            Backing::Mapped(map)
+        };
+        Self::from_backing(bytes)
+    }
+
+    /// A base from bytes already in memory.
+    #[cfg(test)]
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, String> {
+        Self::from_backing(Backing::Opened(bytes))
+    }
+
+    fn from_backing(bytes: Backing) -> Result<Self, String> {
+        let header = check_header(&bytes, KIND_BASE)?;
+        let sections = Sections::new(
+            header.u32_at(16) as usize,
+            header.u32_at(20) as usize,
+            header.u64_at(24) as usize,
+            header.u64_at(32) as usize
+        ).ok_or("section lengths overflow")?;
+        if sections.end != bytes.len() {
+            return Err(format!("{} bytes long, its header describes {}", bytes.len(), sections.end))
+        }
+        Ok(Self { bytes, sections })
+    }
+
+    /// What this base holds on the heap: a sealed file's decrypted bytes, or
+    /// next to nothing for a mapping.
+    pub fn heap_bytes(&self) -> u64 {
+        let held = match &self.bytes {
+            Backing::Mapped(_) => 0,
+            Backing::Opened(bytes) => bytes.capacity()
+        };
+        (size_of::<Self>() + held) as u64
+    }
+
+    pub fn len(&self) -> usize {
+        self.sections.count
+    }
+
+    fn reader(&self) -> Reader<'_> {
+        Reader(&self.bytes)
+    }
+
+    /// One product's span in a blob whose cumulative ends start at `ends`.
+    fn span(&self, ends: usize, index: usize) -> Option<Range<usize>> {
+        let reader = self.reader();
+        let start = match index {
+            0 => 0,
+            _ => reader.u32_at(ends + (index - 1) * 4) as usize
+        };
+        let end = reader.u32_at(ends + index * 4) as usize;
+        (start <= end).then_some(start..end)
+    }
+
+    fn string(&self, position: usize, field: usize) -> Option<&str> {
+        if position >= self.len() {
+            return None
+        }
+        let span = self.span(self.sections.string_ends, position * FIELDS + field)?;
+        let bytes = self.bytes.get(self.sections.strings..self.sections.end)?.get(span)?;
+        std::str::from_utf8(bytes).ok()
+    }
+
+    /// Article number at a position, unfolded.
+    pub fn no(&self, position: usize) -> Option<&str> {
+        self.string(position, NO)
+    }
+
+    /// The record at a position, decoded from the file.
+    pub fn record(&self, position: usize) -> Option<IndexedProduct> {
+        if position >= self.len() {
+            return None
+        }
+        let span = self.span(self.sections.record_ends, position)?;
+        let bytes = self.bytes.get(self.sections.records..self.sections.strings)?.get(span)?;
+        serde_json::from_slice(bytes).ok()
+    }
+
+    pub(super) fn haystack(&self, position: usize) -> Option<Haystack<'_>> {
+        Some(Haystack {
+            name: self.string(position, NAME)?,
+            sku: self.string(position, SKU)?,
+            oem: self.string(position, OEM)?,
+            barcodes: self.string(position, BARCODES)?,
+            brand: self.string(position, BRAND)?,
+            category: self.string(position, CATEGORY)?,
+            main_category: self.string(position, MAIN_CATEGORY)?,
+            description: self.string(position, DESCRIPTION)?,
+            facets: self.string(position, FACETS)?
+        })
+    }
+
+    /// Entry `index` of the code table: the folded code and its tagged position.
+    fn code(&self, index: usize) -> Option<(&str, u32)> {
+        let reader = self.reader();
+        let at = self.sections.code_table + index * CODE_BYTES;
+        let offset = reader.u32_at(at) as usize;
+        let len = reader.u32_at(at + 4) as usize;
+        let strings = self.bytes.get(self.sections.strings..self.sections.end)?;
+        let code = std::str::from_utf8(strings.get(offset..offset.checked_add(len)?)?).ok()?;
+        Some((code, reader.u32_at(at + 8)))
+    }
+
+    /// Positions whose article number (`alt == false`) or one of whose
+    /// secondary codes (`alt == true`) folds to exactly `folded`, ascending.
+    pub fn positions_of_code(&self, folded: &str, alt: bool) -> Vec<usize> {
+        // Binary search by hand: the table is a byte section, not a slice of
+        // entries.
+        let (mut low, mut high) = (0, self.sections.codes);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match self.code(middle) {
+                Some((code, _)) if code < folded => low = middle + 1,
+                _ => high = middle
+            }
+        }
+
+        (low..self.sections.codes)
+            .map_while(|index| self.code(index).filter(|(code, _)| *code == folded))
+            .filter(|(_, tagged)| (tagged & ALT_FLAG != 0) == alt)
+            .map(|(_, tagged)| (tagged & !ALT_FLAG) as usize)
+            .collect()
+    }
+
+    /// Positions holding an internal record id. A scan, but over one packed
+    /// column rather than the records.
+    pub fn positions_of_id(&self, id: u64) -> Vec<usize> {
+        let reader = self.reader();
+        (0..self.len())
+            .filter(|position| reader.u64_at(self.sections.ids + position * 8) == id)
+            .collect()
+    }
+}
+
+
+/// Encodes a base for the disk tier, haystacks and code table included.
+///
+/// Works from the base's accessors rather than its fields, so a mapped base
+/// writes back out the same as one built on the heap.
+pub fn encode_base(base: &BaseCatalog) -> Result<Vec<u8>, String> {
+    let count = base.len();
+    let mut ids: Vec<u8> = Vec::with_capacity(count * 8);
+    let mut record_ends: Vec<u8> = Vec::with_capacity(count * 4);
+    let mut string_ends: Vec<u8> = Vec::with_capacity(count * FIELDS * 4);
+    let mut records: Vec<u8> = Vec::new();
+    let mut strings: Vec<u8> = Vec::new();
+    // (folded code, tagged position, offset into `strings`)
+    let mut codes: Vec<(String, u32, u32)> = Vec::new();
+
+    for position in 0..count {
+        let (Some(record), Some(haystack)) = (base.record(position), base.haystack(position)) else {
+            return Err(format!("base has no row at position {}", position))
+        };
+        let tag = u32::try_from(position).ok().filter(|tag| tag & ALT_FLAG == 0)
+            .ok_or("too many products for the code table")?;
+
+        ids.extend_from_slice(&record.id.to_le_bytes());
+        serde_json::to_writer(&mut records, record.as_ref())
+            .map_err(|error| format!("cannot serialize {}: {}", record.no, error))?;
+        record_ends.extend_from_slice(&blob_offset(records.len())?.to_le_bytes());
+
+        let fields = [
+            record.no.as_str(), haystack.name, haystack.sku, haystack.oem, haystack.barcodes,
+            haystack.brand, haystack.category, haystack.main_category, haystack.description,
+            haystack.facets
+        ];
+        for (field, value) in fields.into_iter().enumerate() {
+            let start = blob_offset(strings.len())?;
+            match field {
+                SKU if !value.is_empty() => codes.push((value.to_string(), tag, start)),
+                OEM if !value.is_empty() => codes.push((value.to_string(), tag | ALT_FLAG, start)),
+                BARCODES => {
+                    let mut offset = start;
+                    for code in value.split(ALT_SEPARATOR) {
+                        if !code.is_empty() {
+                            codes.push((code.to_string(), tag | ALT_FLAG, offset));
+                        }
+                        offset += blob_offset(code.len() + ALT_SEPARATOR.len())?;
+                    }
+                }
+                _ => {}
+            }
+            strings.extend_from_slice(value.as_bytes());
+            string_ends.extend_from_slice(&blob_offset(strings.len())?.to_le_bytes());
+        }
+    }
+
+    codes.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)));
+    let mut code_table: Vec<u8> = Vec::with_capacity(codes.len() * CODE_BYTES);
+    for (code, tag, offset) in &codes {
+        code_table.extend_from_slice(&offset.to_le_bytes());
+        code_table.extend_from_slice(&blob_offset(code.len())?.to_le_bytes());
+        code_table.extend_from_slice(&tag.to_le_bytes());
+    }
+
+    let mut out = header(KIND_BASE, SNAPSHOT_VERSION);
+    out[16..20].copy_from_slice(&blob_offset(count)?.to_le_bytes());
+    out[20..24].copy_from_slice(&blob_offset(codes.len())?.to_le_bytes());
+    out[24..32].copy_from_slice(&(records.len() as u64).to_le_bytes());
+    out[32..40].copy_from_slice(&(strings.len() as u64).to_le_bytes());
+    out.reserve(ids.len() + record_ends.len() + string_ends.len() + code_table.len() + records.len() + strings.len());
+    for section in [ids, record_ends, string_ends, code_table, records, strings] {
+        out.extend_from_slice(&section);
+    }
+    Ok(out)
+}
+
+
+/// Encodes an overlay for the disk tier, at the version it carries.
+pub fn encode_overlay(overlay: &PersistedOverlay) -> Vec<u8> {
+    let mut out = header(KIND_OVERLAY, overlay.version);
+    out.extend_from_slice(&overlay.fetched_at.timestamp().to_le_bytes());
+    out.extend_from_slice(&overlay.fetched_at.timestamp_subsec_nanos().to_le_bytes());
+    out.extend_from_slice(&(overlay.offers.len() as u64).to_le_bytes());
+
+    for (no, offer) in &overlay.offers {
+        put_str(&mut out, no);
+        let flags = u8::from(offer.available)
+            | (u8::from(offer.price.is_some()) << 1)
+            | (u8::from(offer.currency.is_some()) << 2)
+            | (u8::from(offer.stock.is_some()) << 3);
+        out.push(flags);
+        if let Some(price) = offer.price {
+            out.extend_from_slice(&price.to_le_bytes());
+        }
+        if let Some(currency) = &offer.currency {
+            put_str(&mut out, currency);
+        }
+        if let Some(stock) = offer.stock {
+            out.extend_from_slice(&stock.to_le_bytes());
+        }
+    }
+    out
+}
+
+
+/// Decodes an overlay written by [`encode_overlay`].
+pub fn decode_overlay(bytes: &[u8]) -> Result<PersistedOverlay, String> {
+    check_header(bytes, KIND_OVERLAY)?;
+    let mut cursor = Cursor { bytes, at: HEADER_BYTES };
+
+    let seconds = i64::from_le_bytes(cursor.array()?);
+    let nanos = u32::from_le_bytes(cursor.array()?);
+    let fetched_at = DateTime::<Utc>::from_timestamp(seconds, nanos).ok_or("fetched_at out of range")?;
+    let count = u64::from_le_bytes(cursor.array()?) as usize;
+
+    // Capped by what the bytes left could hold, so a damaged count cannot
+    // reserve gigabytes.
+    let mut offers = HashMap::with_capacity(count.min(bytes.len() / 5));
+    for _ in 0..count {
+        let no = cursor.string()?;
+        let [flags] = cursor.array()?;
+        let offer = Offer {
+            available: flags & 1 != 0,
+            price: if flags & 2 != 0 { Some(f64::from_le_bytes(cursor.array()?)) } else { None },
+            currency: if flags & 4 != 0 { Some(cursor.string()?) } else { None },
+            stock: if flags & 8 != 0 { Some(f64::from_le_bytes(cursor.array()?)) } else { None }
+        };
+        offers.insert(no, offer);
+    }
+    if cursor.at != bytes.len() {
+        return Err(format!("{} trailing bytes", bytes.len() - cursor.at))
+    }
+    Ok(PersistedOverlay { version: SNAPSHOT_VERSION, offers, fetched_at })
+}
+
+
+/// A fresh header with the fields every file shares filled in.
+fn header(kind: u32, version: u32) -> Vec<u8> {
+    let mut out = vec![0u8; HEADER_BYTES];
+    out[..8].copy_from_slice(MAGIC);
+    out[8..12].copy_from_slice(&version.to_le_bytes());
+    out[12..16].copy_from_slice(&kind.to_le_bytes());
+    out
+}
+
+
+/// Checks a file's magic, version and kind, and hands back a reader over it.
+///
+/// A version mismatch is an error like any other: the store discards the file
+/// and the caller rebuilds, as for corruption.
+fn check_header(bytes: &[u8], kind: u32) -> Result<Reader<'_>, String> {
+    if bytes.len() < HEADER_BYTES || !bytes.starts_with(MAGIC) {
+        return Err("not a snapshot file".to_string())
+    }
+    let reader = Reader(bytes);
+    let version = reader.u32_at(8);
+    if version != SNAPSHOT_VERSION {
+        return Err(format!("written by snapshot layout v{}, this build reads v{}", version, SNAPSHOT_VERSION))
+    }
+    if reader.u32_at(12) != kind {
+        return Err("holds the other kind of snapshot file".to_string())
+    }
+    Ok(reader)
+}
+
+
+/// A length or offset as stored: `u32`, which a base would need four gigabytes
+/// of strings to outgrow.
+fn blob_offset(value: usize) -> Result<u32, String> {
+    u32::try_from(value).map_err(|_| "snapshot too large for its layout".to_string())
+}
+
+
+fn put_str(out: &mut Vec<u8>, value: &str) {
+    out.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    out.extend_from_slice(value.as_bytes());
+}
+
+
+/// Fixed-width reads at known offsets. Out of range reads as zero, which every
+/// caller then fails to find anything at.
+#[derive(Clone, Copy)]
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn u32_at(&self, at: usize) -> u32 {
+        self.0.get(at..at + 4).and_then(|bytes| bytes.try_into().ok()).map_or(0, u32::from_le_bytes)
+    }
+
+    fn u64_at(&self, at: usize) -> u64 {
+        self.0.get(at..at + 8).and_then(|bytes| bytes.try_into().ok()).map_or(0, u64::from_le_bytes)
+    }
+}
+
+
+/// Sequential reads through an overlay.
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    at: usize
+}
+
+impl Cursor<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], String> {
+        let taken = self.at.checked_add(len)
+            .and_then(|end| self.bytes.get(self.at..end))
+            .ok_or("truncated")?;
+        self.at += len;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        self.take(N).map(|bytes| bytes.try_into().unwrap_or([0; N]))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = u32::from_le_bytes(self.array()?) as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| "string is not valid UTF-8".to_string())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::service::mcp::index::{CatalogSnapshot, ProductAttribute, test_base, test_offer, test_product};
+
+    fn rows() -> Vec<IndexedProduct> {
+        let mut pen = test_product("A-1", "Golyóstoll", "Pax", "MFG-1");
+        pen.id = 4242;
+        pen.barcodes = vec!["5999000000011".into(), "5999000000028".into()];
+        pen.attributes = vec![ProductAttribute {
+            code: "INK".into(),
+            name: Some("Tinta színe".into()),
+            value: Some("Kék".into()),
+            number: None,
+            filterable: true,
+            value_set: None
+        }];
+        vec![pen, test_product("B-2", "Szövegkiemelő", "Orink", ""), test_product("C-3", "Füzet", "Pax", "")]
+    }
+
+    /// The same rows, as a base built on the heap and as that base written out
+    /// and read back.
+    fn both() -> (BaseCatalog, BaseCatalog) {
+        let owned = test_base(rows());
+        let mapped = MappedBase::from_bytes(encode_base(&owned).expect("encodes")).expect("opens");
+        (owned, BaseCatalog::mapped(mapped))
+    }
+
+    #[test]
+    fn a_mapped_base_holds_what_was_written() {
+        let (owned, mapped) = both();
+        assert_eq!(mapped.len(), owned.len());
+        for position in 0..owned.len() {
+            assert_eq!(mapped.no(position), owned.no(position));
+            assert_eq!(mapped.record(position), owned.record(position));
+            assert_eq!(
+                mapped.haystack(position).map(|entry| (entry.oem, entry.barcodes, entry.description, entry.facets)),
+                owned.haystack(position).map(|entry| (entry.oem, entry.barcodes, entry.description, entry.facets))
+            );
+        }
+        assert!(mapped.record(owned.len()).is_none());
+    }
+
+    #[test]
+    fn every_lookup_answers_through_the_code_table() {
+        let (_, mapped) = both();
+        let offers = rows().into_iter().map(|product| (product.no, test_offer())).collect();
+        let snapshot = CatalogSnapshot::resolve(Arc::new(mapped), offers, Utc::now());
+
+        for needle in ["A-1", "a-1", "MFG-1", "5999000000028", "4242"] {
+            assert_eq!(snapshot.get_by_no(needle).as_ref().map(|p| p.product.no.as_str()), Some("A-1"), "{}", needle);
+        }
+        assert!(snapshot.get_by_no("5999000000035").is_none());
+        assert_eq!(snapshot.base.product("C-3").map(|p| p.name.clone()), Some("Füzet".to_string()));
+    }
+
+    #[test]
+    fn an_overlay_round_trips() {
+        let snapshot = CatalogSnapshot::resolve(
+            Arc::new(test_base(rows())),
+            HashMap::from([
+                ("A-1".to_string(), Offer { available: true, price: Some(12.5), currency: Some("HUF".into()), stock: None }),
+                ("B-2".to_string(), Offer { available: false, price: None, currency: None, stock: Some(3.0) })
+            ]),
+            Utc::now()
+        );
+        let overlay = PersistedOverlay::from(&snapshot);
+
+        let decoded = decode_overlay(&encode_overlay(&overlay)).expect("decodes");
+        assert_eq!(decoded.offers, overlay.offers);
+        assert_eq!(decoded.fetched_at, overlay.fetched_at);
+    }
+
+    #[test]
+    fn a_damaged_file_is_refused_rather_than_half_read() {
+        let bytes = encode_base(&test_base(rows())).expect("encodes");
+        assert!(MappedBase::from_bytes(bytes[..bytes.len() - 1].to_vec()).is_err(), "a truncated base opened");
+
+        let mut overlay = encode_overlay(&PersistedOverlay::from(&CatalogSnapshot::resolve(
+            Arc::new(test_base(rows())),
+            HashMap::from([("A-1".to_string(), test_offer())]),
+            Utc::now()
+        )));
+        overlay.push(0);
+        assert!(decode_overlay(&overlay).is_err(), "trailing bytes went unnoticed");
+        // An overlay is not a base, even with a valid header.
+        assert!(MappedBase::from_bytes(overlay).is_err());
+    }
+}
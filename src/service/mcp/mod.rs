@@ -10,14 +10,26 @@
 //! consumers expect a live read on every call.
 
 pub mod admin;
+pub mod availability;
 pub mod cache;
 pub mod export;
 pub mod index;
+pub mod layout;
 pub mod oauth;
+pub mod order;
+pub mod pdf;
 pub mod precache;
+pub mod quote;
+pub mod rank;
 pub mod store;
+pub mod synonyms;
+pub mod syntax;
 pub mod tools;
 
+use oauth::TokenScope;
+
+use crate::service::config::get_mcp_settings;
+
 /// Header carrying the caller's Octopus authentication code.
 ///
 /// Deliberately **not** `Authorization`: `rmcp-actix-web` forwards
@@ -72,10 +84,35 @@ pub fn secrets_match(provided: &str, expected: &str) -> bool {
 #[derive(Clone, Debug)]
 pub struct McpAuth {
     pub authcode: String,
-    pub pid: i64
+    pub pid: i64,
+    /// What the caller's OAuth token was granted, or `None` for a caller that
+    /// presented the authcode itself in the headers.
+    pub scopes: Option<Vec<TokenScope>>
 }
 
 impl McpAuth {
+    /// Whether the caller may do what `scope` covers.
+    pub fn allows(&self, scope: TokenScope) -> bool {
+        self.permits(scope, get_mcp_settings().header_orders())
+    }
+
+    /// [`allows`](Self::allows), with `[mcp] header_orders` passed in.
+    ///
+    /// A header caller may read whatever its authcode can, since it holds the
+    /// authcode. Ordering is the exception: it takes an explicit `orders.write`,
+    /// which a header caller only has when the operator switched it on.
+    fn permits(&self, scope: TokenScope, header_orders: bool) -> bool {
+        match &self.scopes {
+            Some(granted) => granted.contains(&scope),
+            None => scope != TokenScope::OrdersWrite || header_orders
+        }
+    }
+
+    /// Whether the caller presented the authcode itself rather than a token.
+    pub fn is_header_caller(&self) -> bool {
+        self.scopes.is_none()
+    }
+
     /// The caller's masked identity, safe to log.
     pub fn masked(&self) -> String {
         format!("{} pid={}", mask_authcode(&self.authcode), self.pid)
@@ -85,7 +122,7 @@ impl McpAuth {
 
 #[cfg(test)]
 mod tests {
-    use super::{mask_authcode, secrets_match};
+    use super::{McpAuth, TokenScope, mask_authcode, secrets_match};
 
     #[test]
     fn secret_comparison_accepts_only_an_exact_match() {
@@ -96,6 +133,20 @@ mod tests {
         assert!(!secrets_match("s3cret-token", ""));
     }
 
+    #[test]
+    fn a_header_caller_is_refused_submit_order_unless_switched_on() {
+        let header = McpAuth { authcode: "FFD3ABCDEF120E37".into(), pid: 1, scopes: None };
+        // `submit_order`, like `draft_order` and `show_order`, asks for `orders.write`.
+        assert!(!header.permits(TokenScope::OrdersWrite, false));
+        assert!(header.permits(TokenScope::CatalogRead, false));
+        assert!(header.permits(TokenScope::OrdersWrite, true));
+
+        let token = McpAuth { scopes: Some(vec![TokenScope::CatalogRead, TokenScope::OrdersWrite]), ..header.clone() };
+        assert!(token.permits(TokenScope::OrdersWrite, false));
+        let reader = McpAuth { scopes: Some(vec![TokenScope::CatalogRead]), ..header };
+        assert!(!reader.permits(TokenScope::OrdersWrite, true));
+    }
+
     #[test]
     fn masks_all_but_first_and_last_four() {
         assert_eq!(mask_authcode("FFD3ABCDEF120E37"), "FFD3…0E37");
@@ -33,6 +33,7 @@ use serde_json::json;
 use sha2::{Digest, Sha256};
 
 use crate::service::{
+    abuse::{self, Trigger},
     config::get_mcp_settings,
     ipv4::log_ip,
     log::{elog_with_ip, elogger, log_with_ip, logger},
@@ -41,7 +42,7 @@ use crate::service::{
         index::verify_authcode,
         mask_authcode,
         oauth::{
-            self, Grant, OauthClient, SCOPE,
+            self, Grant, OauthClient, TokenScope,
             store::{self, IssuedCode, PendingRequest}
         },
         secrets_match
@@ -109,13 +110,20 @@ fn metadata_response(body: serde_json::Value) -> HttpResponse {
 }
 
 
+/// Every scope this server issues. Published in full even though a given client
+/// may be allowed fewer — the authorize step narrows the request to those.
+fn scopes_supported() -> Vec<&'static str> {
+    TokenScope::ALL.iter().map(TokenScope::as_str).collect()
+}
+
+
 /// RFC 9728 protected-resource metadata.
 pub fn protected_resource_document() -> serde_json::Value {
     json!({
         "resource": oauth::resource_uri(),
         "authorization_servers": [oauth::issuer()],
         "bearer_methods_supported": ["header"],
-        "scopes_supported": [SCOPE],
+        "scopes_supported": scopes_supported(),
         "resource_documentation": format!("{}/docs/", oauth::issuer())
     })
 }
@@ -123,23 +131,31 @@ pub fn protected_resource_document() -> serde_json::Value {
 
 /// RFC 8414 authorization-server metadata.
 ///
-/// No `registration_endpoint` is published: clients are created by hand in
-/// `/admin`. A client that can only register dynamically therefore fails at
-/// discovery rather than half-working, which is the intended outcome.
+/// `registration_endpoint` is published only with dynamic registration on.
+/// Without it clients are created by hand in `/admin`, and a client that can
+/// only register dynamically fails at discovery rather than half-working.
 pub fn authorization_server_document() -> serde_json::Value {
     let issuer = oauth::issuer();
-    json!({
+    let mut document = json!({
         "issuer": issuer,
         "authorization_endpoint": format!("{}/oauth/authorize", issuer),
         "token_endpoint": format!("{}/oauth/token", issuer),
         "revocation_endpoint": format!("{}/oauth/revoke", issuer),
+        "revocation_endpoint_auth_methods_supported": ["client_secret_basic", "client_secret_post"],
+        "introspection_endpoint": format!("{}/oauth/introspect", issuer),
+        "introspection_endpoint_auth_methods_supported": ["client_secret_basic", "client_secret_post"],
         "response_types_supported": ["code"],
         "grant_types_supported": ["authorization_code", "refresh_token"],
         "code_challenge_methods_supported": ["S256"],
         "token_endpoint_auth_methods_supported": ["client_secret_basic", "client_secret_post"],
-        "scopes_supported": [SCOPE],
+        "scopes_supported": scopes_supported(),
         "service_documentation": format!("{}/docs/", issuer)
-    })
+    });
+    if oauth::registration_enabled()
+        && let Some(object) = document.as_object_mut() {
+            object.insert("registration_endpoint".into(), json!(format!("{}/oauth/register", issuer)));
+    }
+    document
 }
 
 
@@ -211,12 +227,26 @@ fn redirect_error(redirect_uri: &str, state: Option<&str>, error: &str, descript
 }
 
 
+/// The list of what is being granted, for the sign-in page. Fixed text from
+/// [`TokenScope::describe`], escaped all the same.
+fn render_scopes(scope: &str) -> String {
+    let items: String = oauth::parse_scopes(scope).unwrap_or_default().iter()
+        .map(|scope| format!(
+            "<li>{} <code>{}</code></li>",
+            oauth::escape_html(scope.describe()),
+            oauth::escape_html(scope.as_str())
+        ))
+        .collect();
+    format!("<ul class=\"scopes\">{}</ul>", items)
+}
+
+
 /// Renders the sign-in page.
 ///
 /// A template rather than a static file: the page has to carry the opaque
-/// request id, and the client's name beside it is operator input, so both go
-/// through [`oauth::escape_html`].
-fn render_login(request_id: &str, client_name: &str, error: Option<&str>, status: StatusCode) -> HttpResponse {
+/// request id and the scopes being granted, and the client's name beside them
+/// is operator input, so all of it goes through [`oauth::escape_html`].
+fn render_login(request_id: &str, client_name: &str, scope: &str, error: Option<&str>, status: StatusCode) -> HttpResponse {
     let path = static_dir().join("login.html");
     let template = match std::fs::read_to_string(&path) {
         Ok(template) => template,
@@ -237,6 +267,7 @@ fn render_login(request_id: &str, client_name: &str, error: Option<&str>, status
     let body = template
         .replace("{{REQUEST_ID}}", &oauth::escape_html(request_id))
         .replace("{{CLIENT_NAME}}", &oauth::escape_html(client_name))
+        .replace("{{SCOPES}}", &render_scopes(scope))
         .replace("{{ERROR}}", &banner);
 
     HttpResponse::build(status)
@@ -260,6 +291,9 @@ async fn authorize(query: web::Query<AuthorizeQuery>, request: HttpRequest) -> i
     let Some(client_id) = query.client_id.as_deref().map(str::trim).filter(|value| !value.is_empty()) else {
         return plain_error(StatusCode::BAD_REQUEST, "Unknown connector", "This sign-in link carries no client id.")
     };
+    if oauth::registration_enabled() {
+        store::sweep_registrations(get_mcp_settings().oauth_registration_ttl_secs());
+    }
     let Some(client) = store::find_client(client_id).filter(OauthClient::is_enabled) else {
         elog_with_ip(&ip_address, format!("OAUTH: authorize refused — unknown or disabled client '{}'", client_id));
         return plain_error(
@@ -297,11 +331,13 @@ async fn authorize(query: web::Query<AuthorizeQuery>, request: HttpRequest) -> i
         return redirect_error(redirect_uri, state, "invalid_request", "only code_challenge_method=S256 is supported")
     }
 
-    // An empty `scope` means "whatever you issue", which here is one scope.
-    let requested_scope = query.scope.as_deref().map(str::trim).unwrap_or_default();
-    if !requested_scope.is_empty() && requested_scope != SCOPE {
-        return redirect_error(redirect_uri, state, "invalid_scope", "the only scope this server issues is catalog.read")
-    }
+    let scope = match oauth::negotiate_scopes(query.scope.as_deref(), &client.allowed_scopes()) {
+        Ok(scopes) => oauth::format_scopes(&scopes),
+        Err(error) => {
+            elog_with_ip(&ip_address, format!("OAUTH: authorize refused for client '{}' — {}", client.name, error));
+            return redirect_error(redirect_uri, state, "invalid_scope", &error)
+        }
+    };
 
     // RFC 8707: a token is bound to one resource. A client asking for a different
     // audience is asking the wrong server.
@@ -316,12 +352,12 @@ async fn authorize(query: web::Query<AuthorizeQuery>, request: HttpRequest) -> i
         redirect_uri.to_string(),
         state.map(str::to_string),
         code_challenge.to_string(),
-        SCOPE.to_string(),
+        scope.clone(),
         oauth::resource_uri().to_string()
     ));
 
-    log_with_ip(&ip_address, format!("OAUTH: sign-in page served for client '{}'", client.name));
-    render_login(&request_id, &client.name, None, StatusCode::OK)
+    log_with_ip(&ip_address, format!("OAUTH: sign-in page served for client '{}' ({})", client.name, scope));
+    render_login(&request_id, &client.name, &scope, None, StatusCode::OK)
 }
 
 
@@ -361,6 +397,7 @@ async fn login(form: web::Form<LoginForm>, request: HttpRequest) -> impl Respond
         return render_login(
             &form.request_id,
             &pending.client_name,
+            &pending.scope,
             Some("Too many failed attempts. Wait ten minutes and try again."),
             StatusCode::TOO_MANY_REQUESTS
         )
@@ -368,7 +405,7 @@ async fn login(form: web::Form<LoginForm>, request: HttpRequest) -> impl Respond
 
     let authcode = form.authcode.trim();
     if authcode.is_empty() {
-        return render_login(&form.request_id, &pending.client_name, Some("Enter your authcode."), StatusCode::OK)
+        return render_login(&form.request_id, &pending.client_name, &pending.scope, Some("Enter your authcode."), StatusCode::OK)
     }
     // Refused before `verify_authcode` below, which costs a real SOAP call to
     // Octopus: a value that is not shaped like an authcode cannot be one, so
@@ -376,16 +413,18 @@ async fn login(form: web::Form<LoginForm>, request: HttpRequest) -> impl Respond
     // load against the ERP. Counted as a failed attempt for the same reason.
     if !crate::service::authcode::is_well_formed(authcode) {
         store::note_failure(&ip_address);
+        abuse::report(Trigger::MalformedAuthcode, &ip_address);
         elog_with_ip(&ip_address, "OAUTH: sign-in refused — the authcode is not shaped like one");
         return render_login(
             &form.request_id,
             &pending.client_name,
+            &pending.scope,
             Some("That is not a valid authcode. It contains only letters, numbers and hyphens."),
             StatusCode::OK
         )
     }
     let Ok(pid) = form.pid.trim().parse::<i64>() else {
-        return render_login(&form.request_id, &pending.client_name, Some("The partner ID has to be a number."), StatusCode::OK)
+        return render_login(&form.request_id, &pending.client_name, &pending.scope, Some("The partner ID has to be a number."), StatusCode::OK)
     };
 
     let Some(url) = get_default_url() else {
@@ -401,12 +440,14 @@ async fn login(form: web::Form<LoginForm>, request: HttpRequest) -> impl Respond
     // any token exists.
     if let Err(error) = verify_authcode(authcode, pid, &url).await {
         store::note_failure(&ip_address);
+        abuse::report(Trigger::OctopusAuth, &ip_address);
         elog_with_ip(&ip_address, format!(
             "OAUTH: sign-in refused for {} pid={} — {}", mask_authcode(authcode), pid, error
         ));
         return render_login(
             &form.request_id,
             &pending.client_name,
+            &pending.scope,
             Some("Octopus did not accept that authcode. Check it and try again."),
             StatusCode::OK
         )
@@ -453,7 +494,9 @@ async fn login(form: web::Form<LoginForm>, request: HttpRequest) -> impl Respond
         refresh_token
     ));
 
-    log_with_ip(&ip_address, format!("OAUTH: signed in [{}] for client '{}'", masked, pending.client_name));
+    log_with_ip(&ip_address, format!(
+        "OAUTH: signed in [{}] for client '{}' ({})", masked, pending.client_name, pending.scope
+    ));
 
     // Warm the catalog in the background. This is what proves the pid — only
     // prices vary by it, so a synchronous check would mean a full price pull —
@@ -463,7 +506,7 @@ async fn login(form: web::Form<LoginForm>, request: HttpRequest) -> impl Respond
         match cache().get_or_build(&warm_authcode, pid, &url).await {
             Ok(snapshot) => logger(format!(
                 "OAuth: catalog warmed after sign-in for {} pid={} — {} products",
-                mask_authcode(&warm_authcode), pid, snapshot.products.len()
+                mask_authcode(&warm_authcode), pid, snapshot.product_count()
             )),
             Err(error) => elogger(format!(
                 "OAuth: first catalog build failed for {} pid={} — {}. A wrong partner ID looks like this.",
@@ -711,6 +754,247 @@ async fn revoke(form: web::Form<RevokeForm>, request: HttpRequest) -> impl Respo
 }
 
 
+// ------------------------------------------------------------- introspect ---
+
+#[derive(Debug, Deserialize)]
+pub struct IntrospectForm {
+    token: Option<String>,
+    token_type_hint: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>
+}
+
+
+/// The RFC 7662 §2.2 body for a live token. Nothing that could be replayed is
+/// in it — `sub` is the grant id, which is derived from a hash of the authcode.
+fn active_token(grant: &Grant, token_type: &str, issued_at: i64, expires_at: i64) -> serde_json::Value {
+    json!({
+        "active": true,
+        "scope": oauth::format_scopes(&store::effective_scopes(grant)),
+        "client_id": grant.client_id,
+        "token_type": token_type,
+        "iat": issued_at,
+        "exp": expires_at,
+        "sub": grant.id,
+        "aud": grant.resource,
+        "iss": oauth::issuer()
+    })
+}
+
+
+/// RFC 7662 introspection, for a resource server that cannot share this
+/// server's files — and for a client checking its own token.
+///
+/// The caller authenticates as a client, and only that client's tokens are
+/// reported active: one connector must not be able to probe another's. Every
+/// other answer is `{"active": false}`, as §2.2 requires, so an unknown, expired,
+/// revoked and foreign token are indistinguishable.
+async fn introspect(form: web::Form<IntrospectForm>, request: HttpRequest) -> impl Responder {
+    let form = form.into_inner();
+    let ip_address = log_ip(request.clone()).await.to_string();
+
+    let client = match authenticate_client(&request, form.client_id.as_deref(), form.client_secret.as_deref()) {
+        Ok(client) => client,
+        Err(response) => {
+            elog_with_ip(&ip_address, "OAUTH: introspection refused — client authentication failed");
+            return response
+        }
+    };
+
+    let inactive = || HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .json(json!({ "active": false }));
+
+    let Some(token) = form.token.as_deref().map(str::trim).filter(|value| !value.is_empty()) else {
+        return inactive()
+    };
+    let hash = oauth::hash_secret(token);
+    // The hint only decides which table is looked at first (§2.1).
+    let refresh_first = form.token_type_hint.as_deref().map(str::trim) == Some("refresh_token");
+
+    let as_access = || store::resolve_access(&hash).and_then(|record| {
+        let grant = store::find_grant(&record.grant_id)?;
+        Some(active_token(&grant, "Bearer", record.issued_at.timestamp(), record.expires_at.timestamp()))
+    });
+    let as_refresh = || store::grant_by_refresh(&hash)
+        .map(|grant| active_token(&grant, "refresh_token", grant.created_at.timestamp(), grant.expires_at.timestamp()));
+
+    let found = if refresh_first {
+        as_refresh().or_else(as_access)
+    } else {
+        as_access().or_else(as_refresh)
+    };
+
+    match found.filter(|body| body["client_id"] == client.client_id.as_str()) {
+        Some(body) => HttpResponse::Ok()
+            .insert_header((header::CACHE_CONTROL, "no-store"))
+            .json(body),
+        None => inactive()
+    }
+}
+
+
+// --------------------------------------------------------------- register ---
+
+/// An RFC 7591 registration request. Only the fields this server acts on; the
+/// rest of the metadata a client may send is ignored, as §2 allows.
+#[derive(Debug, Default, Deserialize)]
+pub struct RegistrationRequest {
+    #[serde(default)]
+    redirect_uris: Vec<String>,
+    client_name: Option<String>,
+    token_endpoint_auth_method: Option<String>,
+    grant_types: Option<Vec<String>>,
+    response_types: Option<Vec<String>>,
+    scope: Option<String>
+}
+
+/// What a registration is granted once it has been checked.
+#[derive(Debug, PartialEq)]
+struct Registration {
+    name: String,
+    redirect_uris: Vec<String>,
+    scopes: Vec<TokenScope>,
+    auth_method: String
+}
+
+/// Longest client name kept. It is shown on the sign-in page and in `/admin`,
+/// and an unauthenticated caller chose it.
+const MAX_CLIENT_NAME_CHARS: usize = 80;
+
+
+/// Checks a registration against the allowlist and what this server supports.
+/// The error is an RFC 7591 §3.2.2 `(error, description)` pair.
+fn check_registration(request: RegistrationRequest, allowed_uris: &[String]) -> Result<Registration, (&'static str, String)> {
+    let redirect_uris: Vec<String> = request.redirect_uris.iter()
+        .map(|uri| uri.trim().to_string())
+        .filter(|uri| !uri.is_empty())
+        .collect();
+    if redirect_uris.is_empty() {
+        return Err(("invalid_redirect_uri", "at least one redirect_uri is required".into()))
+    }
+    if let Some(refused) = redirect_uris.iter()
+        .find(|uri| !allowed_uris.iter().any(|allowed| oauth::redirect_matches(allowed, uri))) {
+            return Err(("invalid_redirect_uri", format!("'{}' is not a redirect URI this server registers", refused)))
+    }
+
+    // Confidential clients only: refresh tokens are not rotated, which OAuth 2.1
+    // permits only because the client authenticates (see `oauth::store`).
+    let auth_method = request.token_endpoint_auth_method.as_deref().map(str::trim).unwrap_or("client_secret_basic");
+    if !matches!(auth_method, "client_secret_basic" | "client_secret_post") {
+        return Err((
+            "invalid_client_metadata",
+            format!("token_endpoint_auth_method '{}' is not supported — use client_secret_basic or client_secret_post", auth_method)
+        ))
+    }
+    if let Some(grant_types) = &request.grant_types
+        && let Some(other) = grant_types.iter().find(|grant| !matches!(grant.as_str(), "authorization_code" | "refresh_token")) {
+            return Err(("invalid_client_metadata", format!("grant_type '{}' is not supported", other)))
+    }
+    if let Some(response_types) = &request.response_types
+        && let Some(other) = response_types.iter().find(|response| response.as_str() != "code") {
+            return Err(("invalid_client_metadata", format!("response_type '{}' is not supported", other)))
+    }
+
+    // A client that registered itself gets the default scopes; anything more is
+    // an operator's decision in /admin.
+    let scopes = oauth::negotiate_scopes(request.scope.as_deref(), &oauth::DEFAULT_SCOPES)
+        .map_err(|error| ("invalid_client_metadata", error))?;
+
+    let name: String = request.client_name.as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unwrap_or("Self-registered client")
+        .chars()
+        .filter(|character| !character.is_control())
+        .take(MAX_CLIENT_NAME_CHARS)
+        .collect();
+
+    Ok(Registration {
+        name,
+        redirect_uris,
+        scopes,
+        auth_method: auth_method.to_string()
+    })
+}
+
+
+/// RFC 7591 dynamic client registration. Registered only with
+/// `[mcp] oauth_registration_enabled = true`.
+///
+/// The body is parsed here rather than by `web::Json`, whose refusal is a
+/// plain-text `400` an OAuth client cannot read.
+async fn register(body: web::Bytes, request: HttpRequest) -> impl Responder {
+    let ip_address = log_ip(request.clone()).await.to_string();
+    let config = get_mcp_settings();
+
+    store::sweep_registrations(config.oauth_registration_ttl_secs());
+
+    // Counted before anything is checked, so a refused attempt costs the same
+    // as an accepted one and the endpoint cannot be probed for free.
+    if store::is_registration_limited(&ip_address, config.oauth_registration_rate_limit()) {
+        elog_with_ip(&ip_address, "OAUTH: registration refused — too many registrations from this address");
+        return token_error(StatusCode::TOO_MANY_REQUESTS, "temporarily_unavailable", "too many registrations, try again later")
+    }
+    store::note_registration(&ip_address);
+
+    if store::pending_registrations() >= oauth::MAX_PENDING_REGISTRATIONS {
+        elog_with_ip(&ip_address, "OAUTH: registration refused — too many registrations awaiting review");
+        return token_error(StatusCode::SERVICE_UNAVAILABLE, "temporarily_unavailable", "registration is paused, try again later")
+    }
+
+    let parsed = match serde_json::from_slice::<RegistrationRequest>(&body) {
+        Ok(parsed) => parsed,
+        Err(error) => return token_error(StatusCode::BAD_REQUEST, "invalid_client_metadata", &format!("the body is not a registration request: {}", error))
+    };
+    let registration = match check_registration(parsed, oauth::registration_redirect_uris()) {
+        Ok(registration) => registration,
+        Err((error, description)) => {
+            elog_with_ip(&ip_address, format!("OAUTH: registration refused — {}", description));
+            return token_error(StatusCode::BAD_REQUEST, error, &description)
+        }
+    };
+
+    let secret = oauth::new_secret();
+    let issued_at = Utc::now();
+    let client = OauthClient {
+        client_id: uuid::Uuid::new_v4().simple().to_string(),
+        name: registration.name.clone(),
+        secret_hash: oauth::hash_secret(&secret),
+        redirect_uris: registration.redirect_uris.clone(),
+        scopes: registration.scopes.iter().map(|scope| scope.as_str().to_string()).collect(),
+        created_at: Some(issued_at),
+        enabled: Some(true),
+        registered_by: Some(ip_address.clone()),
+        reviewed: None
+    };
+    let client_id = client.client_id.clone();
+
+    if let Err(error) = store::upsert_client(client) {
+        elogger(format!("OAuth: cannot record the registration of '{}': {}", registration.name, error));
+        return token_error(StatusCode::INTERNAL_SERVER_ERROR, "server_error", "the registration could not be recorded")
+    }
+    log_with_ip(&ip_address, format!("OAUTH: client '{}' registered itself — awaiting review in /admin", registration.name));
+
+    let scope = oauth::format_scopes(&registration.scopes);
+    HttpResponse::Created()
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .json(json!({
+            "client_id": client_id,
+            "client_secret": secret,
+            "client_id_issued_at": issued_at.timestamp(),
+            // Secrets do not expire; an unused registration does, see the sweep.
+            "client_secret_expires_at": 0,
+            "client_name": registration.name,
+            "redirect_uris": registration.redirect_uris,
+            "grant_types": ["authorization_code", "refresh_token"],
+            "response_types": ["code"],
+            "token_endpoint_auth_method": registration.auth_method,
+            "scope": scope
+        }))
+}
+
+
 // ----------------------------------------------------------------- assets ---
 
 /// Serves one of the sign-in page's own files. Public by nature — the page they
@@ -731,15 +1015,21 @@ async fn script(request: HttpRequest) -> impl Responder {
 }
 
 
-/// The `/oauth` scope. Registered only when MCP **and** OAuth are both enabled.
+/// The `/oauth` scope. Registered only when MCP **and** OAuth are both enabled;
+/// `/oauth/register` only when dynamic registration is on as well.
 pub fn scope() -> Scope {
-    web::scope("/oauth")
+    let scope = web::scope("/oauth")
         .route("/authorize", web::get().to(authorize))
         .route("/login", web::post().to(login))
         .route("/token", web::post().to(token))
         .route("/revoke", web::post().to(revoke))
+        .route("/introspect", web::post().to(introspect))
         .route("/login.css", web::get().to(style))
-        .route("/login.js", web::get().to(script))
+        .route("/login.js", web::get().to(script));
+    if oauth::registration_enabled() {
+        return scope.route("/register", web::post().to(register))
+    }
+    scope
 }
 
 
@@ -778,6 +1068,87 @@ mod tests {
         assert_eq!(query_escape("x&error=nope"), "x%26error%3Dnope");
     }
 
+    #[test]
+    fn the_sign_in_page_lists_each_granted_scope() {
+        let list = render_scopes("catalog.read orders.write");
+        assert!(list.contains("<code>catalog.read</code>"));
+        assert!(list.contains("<code>orders.write</code>"));
+        assert!(!list.contains("catalog.export"));
+    }
+
+    fn registration(redirect_uris: &[&str]) -> RegistrationRequest {
+        RegistrationRequest {
+            redirect_uris: redirect_uris.iter().map(|uri| uri.to_string()).collect(),
+            client_name: Some("Claude".into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn a_registration_may_only_use_allowlisted_redirect_uris() {
+        let allowed = vec![
+            "https://claude.ai/api/mcp/auth_callback".to_string(),
+            "http://127.0.0.1:3334/oauth/callback".to_string()
+        ];
+        let accepted = check_registration(registration(&["https://claude.ai/api/mcp/auth_callback"]), &allowed)
+            .expect("allowlisted");
+        assert_eq!(accepted.name, "Claude");
+        assert_eq!(accepted.scopes, oauth::DEFAULT_SCOPES.to_vec());
+        assert_eq!(accepted.auth_method, "client_secret_basic");
+        // Loopback on another port is the same URI, as at the authorize step.
+        assert!(check_registration(registration(&["http://127.0.0.1:5555/oauth/callback"]), &allowed).is_ok());
+
+        let refused = check_registration(registration(&["https://claude.ai/api/mcp/auth_callback", "https://evil.test/cb"]), &allowed);
+        assert_eq!(refused.map_err(|(error, _)| error), Err("invalid_redirect_uri"));
+        assert!(check_registration(registration(&[]), &allowed).is_err());
+        // An empty allowlist refuses everything.
+        assert!(check_registration(registration(&["https://claude.ai/api/mcp/auth_callback"]), &[]).is_err());
+    }
+
+    #[test]
+    fn a_registration_is_confidential_and_limited_to_the_default_scopes() {
+        let allowed = vec!["https://claude.ai/api/mcp/auth_callback".to_string()];
+        let uri = ["https://claude.ai/api/mcp/auth_callback"];
+
+        let public = RegistrationRequest { token_endpoint_auth_method: Some("none".into()), ..registration(&uri) };
+        assert_eq!(check_registration(public, &allowed).map_err(|(error, _)| error), Err("invalid_client_metadata"));
+
+        let implicit = RegistrationRequest { response_types: Some(vec!["token".into()]), ..registration(&uri) };
+        assert!(check_registration(implicit, &allowed).is_err());
+
+        // Asking for more is narrowed, not refused; asking for only more is.
+        let greedy = RegistrationRequest { scope: Some("catalog.read orders.write".into()), ..registration(&uri) };
+        assert_eq!(check_registration(greedy, &allowed).map(|accepted| accepted.scopes), Ok(vec![TokenScope::CatalogRead]));
+        let writer = RegistrationRequest { scope: Some("orders.write".into()), ..registration(&uri) };
+        assert!(check_registration(writer, &allowed).is_err());
+
+        let unnamed = RegistrationRequest { client_name: Some(format!("  {}\n", "x".repeat(200))), ..registration(&uri) };
+        assert_eq!(check_registration(unnamed, &allowed).map(|accepted| accepted.name.len()), Ok(MAX_CLIENT_NAME_CHARS));
+    }
+
+    #[test]
+    fn an_introspected_token_carries_no_credential() {
+        let grant = Grant {
+            id: "abc-7".into(),
+            client_id: "client-1".into(),
+            label: "FFD3…0E37 pid=7".into(),
+            authcode: "FFD3ABCDEF120E37".into(),
+            pid: 7,
+            resource: "https://example.test/mcp".into(),
+            scope: "catalog.read".into(),
+            refresh_hash: oauth::hash_secret("refresh"),
+            created_at: Utc::now(),
+            expires_at: Utc::now()
+        };
+        let body = active_token(&grant, "Bearer", 10, 20);
+        assert_eq!(body["active"], true);
+        assert_eq!(body["client_id"], "client-1");
+        assert_eq!(body["sub"], "abc-7");
+        assert_eq!(body["exp"], 20);
+        assert!(!body.to_string().contains("FFD3ABCDEF120E37"));
+        assert!(!body.to_string().contains(&grant.refresh_hash));
+    }
+
     #[test]
     fn a_redirect_uri_that_already_has_a_query_keeps_it() {
         assert_eq!(with_query("https://x.test/cb", "code=1"), "https://x.test/cb?code=1");
@@ -790,7 +1161,8 @@ mod tests {
         assert_eq!(document["resource"], oauth::resource_uri());
         assert_eq!(document["authorization_servers"][0], oauth::issuer());
         assert_eq!(document["bearer_methods_supported"][0], "header");
-        assert_eq!(document["scopes_supported"][0], SCOPE);
+        assert_eq!(document["scopes_supported"][0], "catalog.read");
+        assert_eq!(document["scopes_supported"].as_array().map(Vec::len), Some(TokenScope::ALL.len()));
     }
 
     #[test]
@@ -801,12 +1173,13 @@ mod tests {
         assert_eq!(document["code_challenge_methods_supported"][0], "S256");
         assert_eq!(document["grant_types_supported"][0], "authorization_code");
         assert_eq!(document["grant_types_supported"][1], "refresh_token");
-        // Deliberately absent: clients are registered by hand in /admin, and a
-        // client that can only register dynamically should fail at discovery
-        // rather than half-work.
-        assert!(document.get("registration_endpoint").is_none());
+        // Absent unless dynamic registration is on: a client that can only
+        // register dynamically should fail at discovery rather than half-work.
+        assert_eq!(document.get("registration_endpoint").is_some(), oauth::registration_enabled());
         assert!(document["authorization_endpoint"].as_str().is_some_and(|url| url.ends_with("/oauth/authorize")));
         assert!(document["token_endpoint"].as_str().is_some_and(|url| url.ends_with("/oauth/token")));
         assert!(document["revocation_endpoint"].as_str().is_some_and(|url| url.ends_with("/oauth/revoke")));
+        assert!(document["introspection_endpoint"].as_str().is_some_and(|url| url.ends_with("/oauth/introspect")));
+        assert_eq!(document["introspection_endpoint_auth_methods_supported"][0], "client_secret_basic");
     }
 }
@@ -24,6 +24,7 @@ use actix_web::{Error, HttpMessage, HttpResponse};
 
 use crate::service::{
     blocklist::{self, Surface},
+    ipv4::client_address,
     log::{elog_with_ip, logger},
     mcp::{
         AUTHCODE_HEADER, McpAuth,
@@ -90,8 +91,8 @@ pub async fn guard(
     }
 
     if let Some(token) = presented_bearer(&request) {
-        let address = request.connection_info().realip_remote_addr().map(str::to_string);
-        let address = address.unwrap_or_else(|| "unknown IP address".into());
+        let address = client_address(request.peer_addr(), request.headers())
+            .map_or_else(|| "unknown IP address".into(), |address| address.to_string());
 
         let Some(record) = store::resolve_access(&oauth::hash_secret(&token)) else {
             elog_with_ip(&address, "OAUTH: /mcp refused — unknown or expired bearer token");
@@ -116,9 +117,19 @@ pub async fn guard(
             return Ok(request.into_response(denied))
         }
 
+        let scopes = store::effective_scopes(&grant);
+
         store::touch(&grant.id);
-        logger(format!("MCP request: bearer token accepted ({})", grant.masked()));
-        request.extensions_mut().insert(McpAuth { authcode: grant.authcode.clone(), pid: grant.pid });
+        logger(format!(
+            "MCP request: bearer token accepted ({}; {})",
+            grant.masked(),
+            oauth::format_scopes(&scopes)
+        ));
+        request.extensions_mut().insert(McpAuth {
+            authcode: grant.authcode.clone(),
+            pid: grant.pid,
+            scopes: Some(scopes)
+        });
         return next.call(request).await.map(ServiceResponse::map_into_boxed_body)
     }
 
@@ -136,7 +147,8 @@ pub async fn guard(
         .filter(|value| !value.is_empty()) {
             // Headers present but switched off: say so, rather than letting the
             // caller read the 401 as "my code is wrong".
-            let address = request.connection_info().realip_remote_addr().unwrap_or("unknown IP address").to_string();
+            let address = client_address(request.peer_addr(), request.headers())
+                .map_or_else(|| "unknown IP address".into(), |address| address.to_string());
             elog_with_ip(&address, format!(
                 "OAUTH: /mcp refused — header authentication is disabled ({} presented)", mask_authcode(authcode)
             ));
@@ -11,24 +11,50 @@
 //! credentials — and then fails every tool call.
 //!
 //! So Rustopus becomes both the **authorization server** and the **resource
-//! server** for its own MCP endpoint. There is no third party, no new user
-//! directory (the sign-in page asks for the Octopus credentials the partner
-//! already holds) and no dynamic client registration (RFC 7591): clients are
-//! created by hand in `/admin`, which is what the dialog's Client ID / Client
-//! Secret fields exist for.
+//! server** for its own MCP endpoint. There is no third party and no new user
+//! directory: the sign-in page asks for the Octopus credentials the partner
+//! already holds. Clients are created by hand in `/admin`, which is what the
+//! dialog's Client ID / Client Secret fields exist for.
+//!
+//! ## Dynamic registration
+//!
+//! With `[mcp] oauth_registration_enabled = true` a client may also register
+//! itself at `/oauth/register` (RFC 7591). That is an unauthenticated endpoint
+//! that writes a file, so it is fenced in four ways:
+//!
+//! - its redirect URIs must be on `oauth_registration_redirect_uris`, which
+//!   keeps the authorize step from redirecting anywhere the operator did not
+//!   list;
+//! - registrations are counted per address per hour;
+//! - the client is limited to [`DEFAULT_SCOPES`] and marked for review in
+//!   `/admin`, where the operator approves or widens it;
+//! - one nobody signs in through and nobody approves is dropped after
+//!   `oauth_registration_ttl_secs`, with at most [`MAX_PENDING_REGISTRATIONS`]
+//!   waiting at a time.
 //!
 //! ## Layout
 //!
-//! - [`store`] — the two files and the in-memory tables: clients, grants,
-//!   pending sign-ins, authorization codes, access tokens.
+//! - [`store`] — the files and the in-memory tables: clients, grants, pending
+//!   sign-ins, authorization codes, access tokens.
 //! - [`endpoints`] — the metadata documents, `/oauth/authorize`, `/oauth/login`,
-//!   `/oauth/token`, `/oauth/revoke`.
+//!   `/oauth/token`, `/oauth/revoke`, `/oauth/introspect`, `/oauth/register`.
 //! - [`guard`] — the middleware that turns `/mcp` into a protected resource.
 //!
 //! Everything here is inert unless `[mcp] enabled` **and** `[mcp] oauth_enabled`
 //! are both on: with either off no route is registered, no file is read, and the
 //! guard returns on its first line.
 //!
+//! ## Scopes
+//!
+//! A token carries a subset of [`TokenScope`]: `catalog.read`, `catalog.export`,
+//! `invoices.read`, `orders.write`. Each client is registered with the scopes it
+//! may be granted, the sign-in page lists what is being granted, and the MCP
+//! tools check the calling token before they run. A caller identified by
+//! `X-Authcode` rather than a token may read anything — it holds the authcode,
+//! and with it everything the REST endpoints would give it anyway — but gets
+//! `orders.write` only with `[mcp] header_orders = true`, so placing an order
+//! always rests on a grant somebody made on purpose.
+//!
 //! ## What is secret and what is not
 //!
 //! `oauth_sessions.toml` holds live authcodes in plain text, for the same reason
@@ -54,9 +80,110 @@ use crate::service::{
     }
 };
 
-/// The only scope this server issues. One scope, because there is one thing a
-/// token can do: read the catalog the caller's own authcode already reaches.
-pub const SCOPE: &str = "catalog.read";
+/// What a token may be used for.
+///
+/// Every scope is bounded by the partner's own authcode — none of them reaches
+/// data the partner could not already fetch from the REST endpoints. What they
+/// separate is *what a connector may do with it*: reading a catalog is not the
+/// same request as walking off with all of it in a spreadsheet, and neither is
+/// placing an order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenScope {
+    CatalogRead,
+    CatalogExport,
+    InvoicesRead,
+    OrdersWrite
+}
+
+impl TokenScope {
+    pub const ALL: [TokenScope; 4] = [
+        TokenScope::CatalogRead,
+        TokenScope::CatalogExport,
+        TokenScope::InvoicesRead,
+        TokenScope::OrdersWrite
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenScope::CatalogRead => "catalog.read",
+            TokenScope::CatalogExport => "catalog.export",
+            TokenScope::InvoicesRead => "invoices.read",
+            TokenScope::OrdersWrite => "orders.write"
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|scope| scope.as_str() == name.trim())
+    }
+
+    /// What the sign-in page tells the partner they are granting.
+    pub fn describe(&self) -> &'static str {
+        match self {
+            TokenScope::CatalogRead => "Search and read the product catalog, with your own prices and stock",
+            TokenScope::CatalogExport => "Export the catalog to Excel or CSV files",
+            TokenScope::InvoicesRead => "Read your invoices",
+            TokenScope::OrdersWrite => "Place orders in your name"
+        }
+    }
+}
+
+/// What a client may be granted when its registration names no scopes — every
+/// client registered before scopes existed. Reading is what those tokens could
+/// always do, so they keep exactly that.
+pub const DEFAULT_SCOPES: [TokenScope; 1] = [TokenScope::CatalogRead];
+
+
+/// Parses a scope list, space- or comma-separated as OAuth and the dashboard
+/// write them. An unknown name is an error rather than skipped: a typo in a
+/// registration must not quietly grant less than was meant.
+pub fn parse_scopes(text: &str) -> Result<Vec<TokenScope>, String> {
+    let mut scopes = Vec::new();
+    for name in text.split([' ', ',']).map(str::trim).filter(|name| !name.is_empty()) {
+        let Some(scope) = TokenScope::parse(name) else {
+            return Err(format!(
+                "'{}' is not a scope this server issues — expected one of {}",
+                name,
+                format_scopes(&TokenScope::ALL)
+            ))
+        };
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    // Canonical order, so the same set always renders the same way.
+    scopes.sort_by_key(|scope| TokenScope::ALL.iter().position(|known| known == scope));
+    Ok(scopes)
+}
+
+
+/// A scope list as OAuth writes it: space-separated.
+pub fn format_scopes(scopes: &[TokenScope]) -> String {
+    scopes.iter().map(TokenScope::as_str).collect::<Vec<_>>().join(" ")
+}
+
+
+/// The scopes an authorization request ends up with.
+///
+/// An empty request means "whatever this client may have". A request naming
+/// scopes the client may not have is **narrowed** rather than refused — RFC 6749
+/// §3.3 lets a server grant less than asked, and the token response says what
+/// was granted — because a client that asks for every scope in the metadata
+/// document should still work where it is only allowed to read. Only a request
+/// left with nothing, or naming a scope that does not exist, is refused.
+pub fn negotiate_scopes(requested: Option<&str>, allowed: &[TokenScope]) -> Result<Vec<TokenScope>, String> {
+    let requested = requested.map(str::trim).unwrap_or_default();
+    if requested.is_empty() {
+        return Ok(allowed.to_vec())
+    }
+    let granted: Vec<TokenScope> = parse_scopes(requested)?
+        .into_iter()
+        .filter(|scope| allowed.contains(scope))
+        .collect();
+    if granted.is_empty() {
+        return Err(format!("this connector may only be granted {}", format_scopes(allowed)))
+    }
+    Ok(granted)
+}
 
 /// How long a validated authorization request waits on its sign-in page.
 pub const REQUEST_TTL_SECS: u64 = 600;
@@ -68,6 +195,14 @@ pub const CODE_TTL_SECS: u64 = 60;
 /// Window the sign-in rate limit counts failures over.
 pub const RATE_WINDOW_SECS: u64 = 600;
 
+/// Window the registration rate limit counts registrations over.
+pub const REGISTRATION_WINDOW_SECS: u64 = 3_600;
+
+/// Self-registered clients that may await review at once. Past this,
+/// `/oauth/register` refuses until one is approved, removed or expires — the
+/// per-address limit alone does not stop many addresses filling the file.
+pub const MAX_PENDING_REGISTRATIONS: usize = 50;
+
 
 /// Whether `/mcp` is an OAuth-protected resource on this instance.
 ///
@@ -83,6 +218,10 @@ static ENABLED: Lazy<bool> = Lazy::new(|| {
 
 static ALLOW_HEADERS: Lazy<bool> = Lazy::new(|| get_mcp_settings().oauth_allow_headers());
 
+static REGISTRATION: Lazy<bool> = Lazy::new(|| *ENABLED && get_mcp_settings().oauth_registration_enabled());
+
+static REGISTRATION_REDIRECT_URIS: Lazy<Vec<String>> = Lazy::new(|| get_mcp_settings().oauth_registration_redirect_uris());
+
 /// Issuer identifier. Deliberately the same value as the export links' base URL:
 /// two sources of truth for one hostname is how a metadata document ends up
 /// pointing at a server nobody can reach.
@@ -101,6 +240,16 @@ pub fn allow_headers() -> bool {
     *ALLOW_HEADERS
 }
 
+/// Whether `/oauth/register` is served and advertised.
+pub fn registration_enabled() -> bool {
+    *REGISTRATION
+}
+
+/// Redirect URIs a self-registered client may use.
+pub fn registration_redirect_uris() -> &'static [String] {
+    REGISTRATION_REDIRECT_URIS.as_slice()
+}
+
 pub fn issuer() -> &'static str {
     ISSUER.as_str()
 }
@@ -136,7 +285,7 @@ pub fn hash_secret(secret: &str) -> String {
 
 
 /// A registered OAuth client — one per organization's connector, created in
-/// `/admin`.
+/// `/admin` or through `/oauth/register`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OauthClient {
     pub client_id: String,
@@ -147,11 +296,21 @@ pub struct OauthClient {
     pub secret_hash: String,
     #[serde(default)]
     pub redirect_uris: Vec<String>,
+    /// The scopes this client may be granted. Empty means [`DEFAULT_SCOPES`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub created_at: Option<DateTime<Utc>>,
     /// Set `false` to keep a client on file but refuse its sign-ins.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub enabled: Option<bool>
+    pub enabled: Option<bool>,
+    /// The address a self-registered client came from. Absent for a client
+    /// created in `/admin`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registered_by: Option<String>,
+    /// Set once an operator has approved a self-registered client in `/admin`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reviewed: Option<bool>
 }
 
 impl OauthClient {
@@ -159,6 +318,40 @@ impl OauthClient {
         self.enabled.unwrap_or(true)
     }
 
+    /// The scopes this client may be granted. A name this build does not know —
+    /// a hand edit, or a file from a newer version — is dropped, never widened.
+    pub fn allowed_scopes(&self) -> Vec<TokenScope> {
+        let allowed: Vec<TokenScope> = self.scopes.iter().filter_map(|name| TokenScope::parse(name)).collect();
+        if allowed.is_empty() {
+            return DEFAULT_SCOPES.to_vec()
+        }
+        allowed
+    }
+
+    /// Whether this client registered itself through `/oauth/register`.
+    pub fn is_dynamic(&self) -> bool {
+        self.registered_by.is_some()
+    }
+
+    /// Whether the dashboard should flag this client for an operator's look.
+    pub fn needs_review(&self) -> bool {
+        self.is_dynamic() && !self.reviewed.unwrap_or(false)
+    }
+
+    /// Whether this is a self-registered client that has gone unused for `ttl`:
+    /// nobody approved it and nobody holds a sign-in through it.
+    pub fn is_abandoned(&self, has_grants: bool, ttl: chrono::Duration, now: DateTime<Utc>) -> bool {
+        if !self.needs_review() || has_grants {
+            return false
+        }
+        match self.created_at {
+            Some(created_at) => created_at + ttl <= now,
+            // A registration always records when; without it, the hand edit
+            // that removed it decides, not the sweep.
+            None => false
+        }
+    }
+
     /// Whether this client may be redirected to `presented`.
     pub fn allows_redirect(&self, presented: &str) -> bool {
         self.redirect_uris.iter().any(|registered| redirect_matches(registered, presented))
@@ -263,6 +456,12 @@ impl Grant {
     pub fn is_expired(&self) -> bool {
         self.expires_at <= Utc::now()
     }
+
+    /// The scopes this grant was issued with, leniently: the grant was checked
+    /// when it was issued, and an unknown name only ever narrows it.
+    pub fn scopes(&self) -> Vec<TokenScope> {
+        self.scope.split(' ').filter_map(TokenScope::parse).collect()
+    }
 }
 
 
@@ -341,6 +540,82 @@ mod tests {
         assert_ne!(one, new_secret());
     }
 
+    #[test]
+    fn an_empty_request_gets_what_the_client_may_have() {
+        let allowed = [TokenScope::CatalogRead, TokenScope::CatalogExport];
+        assert_eq!(negotiate_scopes(None, &allowed), Ok(allowed.to_vec()));
+        assert_eq!(negotiate_scopes(Some("  "), &allowed), Ok(allowed.to_vec()));
+    }
+
+    #[test]
+    fn a_request_is_narrowed_to_what_the_client_may_have() {
+        let allowed = [TokenScope::CatalogRead];
+        assert_eq!(
+            negotiate_scopes(Some("orders.write catalog.read"), &allowed),
+            Ok(vec![TokenScope::CatalogRead])
+        );
+        assert!(negotiate_scopes(Some("orders.write"), &allowed).is_err());
+        assert!(negotiate_scopes(Some("catalog.read admin"), &allowed).is_err());
+    }
+
+    #[test]
+    fn scopes_parse_in_canonical_order_without_repeats() {
+        assert_eq!(
+            parse_scopes("orders.write, catalog.read orders.write"),
+            Ok(vec![TokenScope::CatalogRead, TokenScope::OrdersWrite])
+        );
+        assert_eq!(format_scopes(&[TokenScope::CatalogRead, TokenScope::InvoicesRead]), "catalog.read invoices.read");
+    }
+
+    #[test]
+    fn a_client_without_scopes_may_only_read() {
+        let mut client = OauthClient {
+            client_id: "c".into(),
+            name: "n".into(),
+            secret_hash: String::new(),
+            redirect_uris: Vec::new(),
+            scopes: Vec::new(),
+            created_at: None,
+            enabled: None,
+            registered_by: None,
+            reviewed: None
+        };
+        assert_eq!(client.allowed_scopes(), DEFAULT_SCOPES.to_vec());
+        client.scopes = vec!["catalog.export".into(), "nonsense".into()];
+        assert_eq!(client.allowed_scopes(), vec![TokenScope::CatalogExport]);
+    }
+
+    #[test]
+    fn only_an_unreviewed_unused_registration_is_abandoned() {
+        let now = Utc::now();
+        let day = chrono::Duration::days(1);
+        let mut client = OauthClient {
+            client_id: "c".into(),
+            name: "n".into(),
+            secret_hash: String::new(),
+            redirect_uris: Vec::new(),
+            scopes: Vec::new(),
+            created_at: Some(now - chrono::Duration::days(2)),
+            enabled: None,
+            registered_by: Some("203.0.113.7".into()),
+            reviewed: None
+        };
+        assert!(client.needs_review());
+        assert!(client.is_abandoned(false, day, now));
+        // Somebody signed in through it, or it is still young.
+        assert!(!client.is_abandoned(true, day, now));
+        assert!(!client.is_abandoned(false, chrono::Duration::days(3), now));
+
+        client.reviewed = Some(true);
+        assert!(!client.needs_review());
+        assert!(!client.is_abandoned(false, day, now));
+
+        // A client made in /admin is never swept.
+        client.reviewed = None;
+        client.registered_by = None;
+        assert!(!client.is_abandoned(false, day, now));
+    }
+
     #[test]
     fn interpolated_text_cannot_close_a_tag() {
         assert_eq!(escape_html("<script>x</script>"), "&lt;script&gt;x&lt;/script&gt;");
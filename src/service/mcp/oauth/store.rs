@@ -1,4 +1,4 @@
-//! Where OAuth state lives: two files on disk, four tables in memory.
+//! Where OAuth state lives: two or three files on disk, four tables in memory.
 //!
 //! ## On disk
 //!
@@ -7,9 +7,17 @@
 //!   server needs to read it.
 //! - `oauth_sessions.toml` — issued grants. **Secret-grade**: each grant holds a
 //!   live Octopus authcode in plain text, for the same reason `mcp_precache.toml`
-//!   does — the tools present it to Octopus with no user in the loop.
+//!   does — the tools present it to Octopus with no user in the loop. Sealed
+//!   when a storage key is configured (`service/sealed.rs`); the other two are
+//!   hashes only and stay readable.
+//! - `oauth_tokens.toml` — live access tokens, **only** when
+//!   `[mcp] oauth_tokens_path` is set. Hashes only: a token is not recoverable
+//!   from this file, so it is not a credential file either.
 //!
-//! Both are written through a temporary file and a rename, so a crash mid-write
+//! With `[storage] backend = "sqlite"` clients and grants are rows in the
+//! database instead, and only the token file remains a file.
+//!
+//! All are written through a temporary file and a rename, so a crash mid-write
 //! cannot leave half a file behind, and both treat a missing file as "nothing
 //! configured" rather than an error. The structure is `service/blocklist.rs`'s.
 //!
@@ -18,15 +26,45 @@
 //! `service/mcp/precache.rs` already argues that a file holding live credentials
 //! should not be rewritten on a timer. Two choices here follow from it:
 //!
-//! - **Access tokens are never persisted.** They live in [`ACCESS`], so an hourly
-//!   refresh touches no file. A restart drops them; a client sees a `401`,
-//!   refreshes, and carries on with nobody involved.
+//! - **Access tokens are not persisted by default.** They live in [`ACCESS`], so
+//!   an hourly refresh touches no file. A restart drops them; a client sees a
+//!   `401`, refreshes, and carries on with nobody involved. Setting
+//!   `oauth_tokens_path` trades that for a write per refresh — to a file of
+//!   hashes, not of credentials.
 //! - **Refresh tokens are not rotated.** Rotation would mean writing the
 //!   credential file every time an access token expired. OAuth 2.1 requires
 //!   rotation *or* a confidential client, and claude.ai supplies a client secret.
 //!
 //! So the file is written on sign-in, on revocation and on the expiry sweep —
 //! when a person actually did something.
+//!
+//! ## Shared between instances
+//!
+//! With `oauth_tokens_path` set, the store assumes the three files may be shared
+//! with other instances (a common volume behind a load balancer) and re-reads
+//! each one whenever it changed on disk since this process last looked. A
+//! token issued by one instance is then accepted by the next, and a revocation
+//! on one — which removes the grant and its tokens from the files — is honoured
+//! by all of them on their next lookup. The check is a `stat` per file per
+//! lookup — or, with `[storage] backend = "sqlite"`, where clients and grants
+//! live in the database (`service/state`), a read of their revision counters.
+//!
+//! What is *not* shared is the sign-in itself: a pending request and its
+//! authorization code live for minutes in the memory of the instance that
+//! issued them, so `/oauth/authorize` → `/oauth/login` → `/oauth/token` has to
+//! land on one instance (session affinity on `/oauth`).
+//!
+//! Every change is a read-modify-write of a whole file, so writers take turns:
+//! each holds an exclusive lock on a `.lock` file beside the document for the
+//! whole change, and re-reads the document once it holds the lock. Without
+//! that, two instances writing in the same instant could each write what it
+//! read before the other's write — and a revocation lost that way brings the
+//! revoked token back. The lock is `flock`, so the shared volume has to honour
+//! it; NFS does from v4 on.
+//!
+//! RFC 8414 advertises `revocation_endpoint` and `introspection_endpoint`,
+//! both served. There is no published list of revoked tokens: a revoked token
+//! is deleted, not remembered, and instances learn of it through the files.
 
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
@@ -40,8 +78,13 @@ use serde::{Deserialize, Serialize};
 use crate::service::{
     config::get_mcp_settings,
     log::{elogger, logger},
-    mcp::oauth::{CODE_TTL_SECS, Grant, OauthClient, RATE_WINDOW_SECS, REQUEST_TTL_SECS, hash_secret, new_secret},
-    path::get_current_or_root_dir
+    mcp::oauth::{
+        CODE_TTL_SECS, Grant, OauthClient, RATE_WINDOW_SECS, REGISTRATION_WINDOW_SECS, REQUEST_TTL_SECS,
+        TokenScope, hash_secret, new_secret, registration_enabled, registration_redirect_uris
+    },
+    path::get_current_or_root_dir,
+    sealed,
+    state::{self, Document, Revision}
 };
 
 
@@ -59,6 +102,22 @@ pub struct SessionConfig {
     pub grants: Vec<Grant>
 }
 
+/// On-disk shape of `oauth_tokens.toml`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TokenConfig {
+    #[serde(default, rename = "token")]
+    pub tokens: Vec<StoredToken>
+}
+
+/// One access token as persisted: its hash and what it was issued for.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredToken {
+    /// SHA-256 hex of the token, never the token.
+    pub hash: String,
+    #[serde(flatten)]
+    pub record: AccessRecord
+}
+
 
 static CLIENTS: Lazy<RwLock<Vec<OauthClient>>> = Lazy::new(|| RwLock::new(load_clients().clients));
 static GRANTS: Lazy<RwLock<Vec<Grant>>> = Lazy::new(|| RwLock::new(load_sessions().grants));
@@ -98,16 +157,39 @@ pub struct IssuedCode {
     created: Instant
 }
 
-/// A live access token. Memory only — see the module note.
-#[derive(Debug, Clone)]
+/// A live access token. In memory, and in `oauth_tokens.toml` when that is
+/// configured — see the module note.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AccessRecord {
     pub grant_id: String,
-    expires: Instant
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>
+}
+
+impl AccessRecord {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at <= now
+    }
 }
 
 static REQUESTS: Lazy<Mutex<HashMap<String, PendingRequest>>> = Lazy::new(|| Mutex::new(HashMap::new()));
 static CODES: Lazy<Mutex<HashMap<String, IssuedCode>>> = Lazy::new(|| Mutex::new(HashMap::new()));
-static ACCESS: Lazy<Mutex<HashMap<String, AccessRecord>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static ACCESS: Lazy<Mutex<HashMap<String, AccessRecord>>> = Lazy::new(|| Mutex::new(
+    load_tokens().tokens.into_iter().map(|token| (token.hash, token.record)).collect()
+));
+
+/// What another instance can change under this one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Shared {
+    Clients,
+    Sessions,
+    Tokens
+}
+
+/// Each shared document's revision when this process last read or wrote it, so
+/// a change made by another instance is noticed. Only consulted with
+/// `oauth_tokens_path` set.
+static SEEN: Lazy<Mutex<HashMap<Shared, Revision>>> = Lazy::new(|| Mutex::new(HashMap::new()));
 
 /// When each grant was last used, for the dashboard. In memory like the precache
 /// run log: a timestamp is not worth rewriting a credential file for.
@@ -117,6 +199,9 @@ static LAST_USED: Lazy<Mutex<HashMap<String, DateTime<Utc>>>> = Lazy::new(|| Mut
 /// authcodes against the ERP.
 static FAILURES: Lazy<Mutex<HashMap<String, Vec<Instant>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
 
+/// Dynamic registrations per address, successful or not.
+static REGISTRATIONS: Lazy<Mutex<HashMap<String, Vec<Instant>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
 
 /// Resolves a configured path against the working directory, like every other
 /// runtime path in this service.
@@ -138,34 +223,47 @@ pub fn sessions_path() -> PathBuf {
     resolve(get_mcp_settings().oauth_sessions_path())
 }
 
+/// What `oauth_sessions.toml` is sealed as — see `service/sealed`. Its logical
+/// name rather than the configured path, so moving the file does not lock it.
+pub const SESSIONS_SEAL_CONTEXT: &str = "oauth_sessions.toml";
+
+/// Where access tokens are persisted, or `None` when they live in memory only.
+pub fn tokens_path() -> Option<PathBuf> {
+    get_mcp_settings().oauth_tokens_path().map(resolve)
+}
+
+/// Whether tokens are persisted, and with them the files treated as shared.
+pub fn is_shared() -> bool {
+    static SHARED: Lazy<bool> = Lazy::new(|| tokens_path().is_some());
+    *SHARED
+}
 
-/// Reads a TOML file, or its empty shape when it is absent or unreadable. A
-/// missing file is the normal case: nobody has signed in yet.
-fn read_file<T: Default + serde::de::DeserializeOwned>(path: &Path, what: &str) -> T {
+
+/// Reads a TOML file, or its empty shape when it is absent. A missing file is
+/// the normal case: nobody has signed in yet. A sealed file is opened under
+/// `what`, which is therefore also its seal context.
+fn read_file<T: Default + serde::de::DeserializeOwned>(path: &Path, what: &str) -> Result<T, String> {
     if !path.is_file() {
-        return T::default()
-    }
-    match std::fs::read_to_string(path) {
-        Ok(content) => match toml::from_str::<T>(&content) {
-            Ok(parsed) => parsed,
-            Err(error) => {
-                elogger(format!("OAuth: cannot parse {} '{:?}': {}", what, path, error));
-                T::default()
-            }
-        },
-        Err(error) => {
-            elogger(format!("OAuth: cannot read {} '{:?}': {}", what, path, error));
-            T::default()
-        }
+        return Ok(T::default())
     }
+    let content = sealed::read_to_string(path, what)
+        .map_err(|error| format!("cannot read {} '{:?}': {}", what, path, error))?;
+    toml::from_str::<T>(&content)
+        .map_err(|error| format!("cannot parse {} '{:?}': {}", what, path, error))
 }
 
 
-/// Writes a file owner-only, through a temporary path and a rename.
-fn write_file<T: Serialize>(path: &Path, banner: &str, value: &T) -> Result<(), String> {
+/// Writes a file owner-only, through a temporary path and a rename. `seal_as`
+/// names the context a secret-grade file is sealed under; the other files are
+/// hashes only and stay readable.
+fn write_file<T: Serialize>(path: &Path, banner: &str, value: &T, seal_as: Option<&str>) -> Result<(), String> {
     let body = toml::to_string_pretty(value).map_err(|error| error.to_string())?;
+    let mut content = format!("{}\n{}", banner, body).into_bytes();
+    if let Some(context) = seal_as {
+        content = sealed::seal(content, context)?;
+    }
     let temporary = path.with_extension("toml.tmp");
-    std::fs::write(&temporary, format!("{}\n{}", banner, body)).map_err(|error| error.to_string())?;
+    std::fs::write(&temporary, content).map_err(|error| error.to_string())?;
     restrict_permissions(&temporary);
     std::fs::rename(&temporary, path).map_err(|error| error.to_string())?;
     restrict_permissions(path);
@@ -190,16 +288,171 @@ fn restrict_permissions(path: &Path) {
 }
 
 
+/// The registered clients, from `oauth_clients.toml` or the database —
+/// whichever `[storage] backend` names (`service/state`).
 pub fn load_clients() -> ClientConfig {
-    read_file(&clients_path(), "oauth_clients.toml")
+    state::store().load_clients().unwrap_or_else(|error| {
+        elogger(format!("OAuth: {}", error));
+        ClientConfig::default()
+    })
 }
 
+/// The issued grants, from `oauth_sessions.toml` or the database.
 pub fn load_sessions() -> SessionConfig {
-    read_file(&sessions_path(), "oauth_sessions.toml")
+    state::store().load_sessions().unwrap_or_else(|error| {
+        elogger(format!("OAuth: {}", error));
+        SessionConfig::default()
+    })
+}
+
+pub fn load_tokens() -> TokenConfig {
+    match tokens_path() {
+        Some(path) => read_file(&path, "oauth_tokens.toml").unwrap_or_else(|error| {
+            elogger(format!("OAuth: {}", error));
+            TokenConfig::default()
+        }),
+        None => TokenConfig::default()
+    }
+}
+
+/// `oauth_clients.toml`, for the TOML backend.
+pub fn read_clients_file() -> Result<ClientConfig, String> {
+    read_file(&clients_path(), "oauth_clients.toml")
+}
+
+/// `oauth_sessions.toml`, for the TOML backend.
+pub fn read_sessions_file() -> Result<SessionConfig, String> {
+    read_file(&sessions_path(), SESSIONS_SEAL_CONTEXT)
+}
+
+
+/// Each shared document's current revision.
+fn revision(document: Shared) -> Revision {
+    match document {
+        Shared::Clients => state::store().revision(Document::OauthClients),
+        Shared::Sessions => state::store().revision(Document::OauthSessions),
+        Shared::Tokens => tokens_path().map(|path| state::file_revision(&path)).unwrap_or(Revision::Missing)
+    }
+}
+
+/// Whether `document` changed since this process last read or wrote it,
+/// recording its current revision either way.
+fn changed_elsewhere(document: Shared) -> bool {
+    let current = revision(document);
+    let Ok(mut seen) = SEEN.lock() else {
+        return false
+    };
+    match seen.insert(document, current) {
+        Some(previous) => previous != current,
+        // First look: whatever was loaded at startup may already be stale.
+        None => true
+    }
+}
+
+/// Notes that this process holds `document` as it now stands on disk — just
+/// written or just re-read — so its own write is not mistaken for another
+/// instance's.
+fn note_seen(document: Shared) {
+    if is_shared() {
+        let current = revision(document);
+        if let Ok(mut seen) = SEEN.lock() {
+            seen.insert(document, current);
+        }
+    }
+}
+
+/// Re-reads whichever shared document another instance has changed. A no-op
+/// unless `oauth_tokens_path` is set.
+fn sync_shared() {
+    if !is_shared() {
+        return
+    }
+    for document in [Shared::Clients, Shared::Sessions, Shared::Tokens] {
+        if changed_elsewhere(document) {
+            reload(document);
+        }
+    }
+}
+
+/// Replaces the in-memory copy of `document` with what is on disk.
+fn reload(document: Shared) {
+    match document {
+        Shared::Clients => if let Ok(mut held) = CLIENTS.write() {
+            *held = load_clients().clients;
+        },
+        Shared::Sessions => if let Ok(mut held) = GRANTS.write() {
+            *held = load_sessions().grants;
+        },
+        Shared::Tokens => if let Ok(mut access) = ACCESS.lock() {
+            *access = load_tokens().tokens.into_iter().map(|token| (token.hash, token.record)).collect();
+        }
+    }
+}
+
+
+/// Takes an exclusive lock on the `.lock` file beside `document`, held until
+/// the returned file is dropped. Beside the document rather than on it: every
+/// write replaces the document by a rename, and a lock on the replaced file
+/// would hold nobody off.
+fn lock_shared(document: Shared) -> Result<std::fs::File, String> {
+    let path = match document {
+        Shared::Clients => clients_path(),
+        Shared::Sessions => sessions_path(),
+        Shared::Tokens => tokens_path().ok_or("access tokens are not persisted")?
+    };
+    lock_beside(&path)
 }
 
+fn lock_beside(document: &Path) -> Result<std::fs::File, String> {
+    let path = document.with_extension("toml.lock");
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .map_err(|error| format!("cannot open '{:?}': {}", path, error))?;
+    file.lock().map_err(|error| format!("cannot lock '{:?}': {}", path, error))?;
+    Ok(file)
+}
+
+/// Runs a read-modify-write of `document` with other instances held off: under
+/// [`lock_shared`], on a copy re-read after the lock was taken. A no-op wrapper
+/// unless `oauth_tokens_path` is set.
+///
+/// Documents are always taken in the order clients, sessions, tokens, and a
+/// change never takes its own document twice, so two changes cannot deadlock.
+fn exclusively<T>(document: Shared, change: impl FnOnce() -> T) -> T {
+    if !is_shared() {
+        return change()
+    }
+    // A volume that cannot lock still gets the write: refusing would turn a
+    // revocation into a no-op, which is worse than the race the lock closes.
+    let _lock = lock_shared(document)
+        .inspect_err(|error| elogger(format!("OAuth: writing without a lock: {}", error)))
+        .ok();
+    note_seen(document);
+    reload(document);
+    change()
+}
+
+
+// Unlike the token file, a saved client or grant document is not noted as
+// seen: the SQLite backend merges a save with rows other instances wrote, so
+// what is stored can hold more than what was saved, and the next lookup
+// re-reads it. Clients and grants change at sign-in and in `/admin`, so the
+// extra read costs nothing that matters.
 
 fn save_clients(config: &ClientConfig) -> Result<(), String> {
+    state::store().save_clients(config)
+}
+
+fn save_sessions(config: &SessionConfig) -> Result<(), String> {
+    state::store().save_sessions(config)
+}
+
+
+/// Writes `oauth_clients.toml`, for the TOML backend.
+pub fn write_clients_file(config: &ClientConfig) -> Result<(), String> {
     write_file(
         &clients_path(),
         "# Rustopus OAuth clients.\n\
@@ -209,12 +462,14 @@ fn save_clients(config: &ClientConfig) -> Result<(), String> {
          # nothing except this server needs to read it, so it is written 0600.\n\
          #\n\
          # Managed by the /admin dashboard; hand edits are picked up on restart.\n",
-        config
+        config,
+        None
     )
 }
 
 
-fn save_sessions(config: &SessionConfig) -> Result<(), String> {
+/// Writes `oauth_sessions.toml`, for the TOML backend.
+pub fn write_sessions_file(config: &SessionConfig) -> Result<(), String> {
     write_file(
         &sessions_path(),
         "# Rustopus OAuth grants.\n\
@@ -226,11 +481,39 @@ fn save_sessions(config: &SessionConfig) -> Result<(), String> {
          #\n\
          # Written on sign-in, on revocation and on the expiry sweep — never on a\n\
          # token refresh. Hand edits are picked up on restart.\n",
-        config
+        config,
+        Some(SESSIONS_SEAL_CONTEXT)
     )
 }
 
 
+/// Writes the live access tokens, when they are persisted at all.
+fn save_tokens(access: &HashMap<String, AccessRecord>) -> Result<(), String> {
+    let Some(path) = tokens_path() else {
+        return Ok(())
+    };
+    let mut tokens: Vec<StoredToken> = access.iter()
+        .map(|(hash, record)| StoredToken { hash: hash.clone(), record: record.clone() })
+        .collect();
+    // Stable order, so two instances holding the same tokens write the same file.
+    tokens.sort_by(|left, right| left.hash.cmp(&right.hash));
+    write_file(
+        &path,
+        "# Rustopus OAuth access tokens.\n\
+         #\n\
+         # Only the SHA-256 hash of each token is stored, so a token cannot be\n\
+         # recovered from this file. Written on every issue and revocation so a\n\
+         # restart, or another instance sharing this path, keeps accepting them.\n\
+         #\n\
+         # Expired entries are dropped on the next write.\n",
+        &TokenConfig { tokens },
+        None
+    )?;
+    note_seen(Shared::Tokens);
+    Ok(())
+}
+
+
 /// Loads both files and reports them. Called from `main.rs` at startup so a
 /// broken file is visible in the log before the first connector tries to sign in.
 pub fn init() {
@@ -246,12 +529,30 @@ pub fn init() {
     if clients.is_empty() {
         logger("OAuth: no clients registered yet — add one in /admin, then paste its id and secret into the connector");
     }
+    match tokens_path() {
+        Some(path) => logger(format!(
+            "OAuth: access tokens persisted to '{:?}' ({} live) — instances sharing the OAuth files share sign-ins",
+            path,
+            ACCESS.lock().map(|access| access.len()).unwrap_or(0)
+        )),
+        None => logger("OAuth: access tokens held in memory — a restart asks every connector to refresh")
+    }
+    if registration_enabled() {
+        let allowed = registration_redirect_uris();
+        if allowed.is_empty() {
+            elogger("OAuth: dynamic registration is on but [mcp] oauth_registration_redirect_uris is empty — every registration will be refused");
+        } else {
+            logger(format!("OAuth: dynamic registration enabled at /oauth/register for {} redirect URI(s)", allowed.len()));
+        }
+        sweep_registrations(get_mcp_settings().oauth_registration_ttl_secs());
+    }
 }
 
 
 // ---------------------------------------------------------------- clients ---
 
 pub fn clients() -> Vec<OauthClient> {
+    sync_shared();
     CLIENTS.read().map(|clients| clients.clone()).unwrap_or_default()
 }
 
@@ -261,32 +562,26 @@ pub fn find_client(client_id: &str) -> Option<OauthClient> {
 
 /// Adds a client, or replaces the one with the same id, and persists the result.
 pub fn upsert_client(client: OauthClient) -> Result<(), String> {
-    let mut current = clients();
-    match current.iter().position(|existing| existing.client_id == client.client_id) {
-        Some(position) => current[position] = client,
-        None => current.push(client)
-    }
-    save_clients(&ClientConfig { clients: current.clone() })?;
-    match CLIENTS.write() {
-        Ok(mut held) => *held = current,
-        Err(_) => return Err("oauth client lock poisoned".into())
-    }
-    Ok(())
+    exclusively(Shared::Clients, || {
+        let mut current = clients();
+        match current.iter().position(|existing| existing.client_id == client.client_id) {
+            Some(position) => current[position] = client,
+            None => current.push(client)
+        }
+        commit_clients(current)
+    })
 }
 
 /// Removes a client and every grant it issued: a connector that is gone should
 /// not leave working tokens behind.
 pub fn remove_client(client_id: &str) -> Result<bool, String> {
-    let mut current = clients();
-    let before = current.len();
-    current.retain(|client| client.client_id != client_id);
-    let removed = current.len() != before;
-
-    save_clients(&ClientConfig { clients: current.clone() })?;
-    match CLIENTS.write() {
-        Ok(mut held) => *held = current,
-        Err(_) => return Err("oauth client lock poisoned".into())
-    }
+    let removed = exclusively(Shared::Clients, || {
+        let mut current = clients();
+        let before = current.len();
+        current.retain(|client| client.client_id != client_id);
+        let removed = current.len() != before;
+        commit_clients(current).map(|_| removed)
+    })?;
 
     if removed {
         for grant in grants().into_iter().filter(|grant| grant.client_id == client_id) {
@@ -297,9 +592,44 @@ pub fn remove_client(client_id: &str) -> Result<bool, String> {
 }
 
 
+fn commit_clients(clients: Vec<OauthClient>) -> Result<(), String> {
+    save_clients(&ClientConfig { clients: clients.clone() })?;
+    match CLIENTS.write() {
+        Ok(mut held) => *held = clients,
+        Err(_) => return Err("oauth client lock poisoned".into())
+    }
+    Ok(())
+}
+
+
+/// Self-registered clients still waiting for an operator's review.
+pub fn pending_registrations() -> usize {
+    clients().iter().filter(|client| client.needs_review()).count()
+}
+
+/// Drops self-registered clients nobody approved and nobody signed in through
+/// within `ttl_secs`. Run on registration and authorization rather than on a
+/// timer, like [`sweep_grants`].
+pub fn sweep_registrations(ttl_secs: u64) {
+    let ttl = chrono::Duration::seconds(ttl_secs.min(i64::MAX as u64) as i64);
+    let now = Utc::now();
+    let held = grants();
+    let abandoned: Vec<OauthClient> = clients().into_iter()
+        .filter(|client| client.is_abandoned(held.iter().any(|grant| grant.client_id == client.client_id), ttl, now))
+        .collect();
+    for client in abandoned {
+        match remove_client(&client.client_id) {
+            Ok(_) => logger(format!("OAuth: unused registration '{}' expired and was dropped", client.name)),
+            Err(error) => elogger(format!("OAuth: cannot drop the unused registration '{}': {}", client.name, error))
+        }
+    }
+}
+
+
 // ----------------------------------------------------------------- grants ---
 
 pub fn grants() -> Vec<Grant> {
+    sync_shared();
     GRANTS.read().map(|grants| grants.clone()).unwrap_or_default()
 }
 
@@ -319,12 +649,14 @@ pub fn grant_by_refresh(refresh_hash: &str) -> Option<Grant> {
 /// should mean.
 pub fn upsert_grant(grant: Grant) -> Result<(), String> {
     let id = grant.id.clone();
-    let mut current = grants();
-    match current.iter().position(|existing| existing.id == id) {
-        Some(position) => current[position] = grant,
-        None => current.push(grant)
-    }
-    commit_grants(current)?;
+    exclusively(Shared::Sessions, || {
+        let mut current = grants();
+        match current.iter().position(|existing| existing.id == id) {
+            Some(position) => current[position] = grant,
+            None => current.push(grant)
+        }
+        commit_grants(current)
+    })?;
     drop_access_for_grant(&id);
     Ok(())
 }
@@ -332,11 +664,13 @@ pub fn upsert_grant(grant: Grant) -> Result<(), String> {
 /// Revokes a grant: it leaves the file, and its live access tokens stop working
 /// in the same call.
 pub fn remove_grant(id: &str) -> Result<bool, String> {
-    let mut current = grants();
-    let before = current.len();
-    current.retain(|grant| grant.id != id);
-    let removed = current.len() != before;
-    commit_grants(current)?;
+    let removed = exclusively(Shared::Sessions, || {
+        let mut current = grants();
+        let before = current.len();
+        current.retain(|grant| grant.id != id);
+        let removed = current.len() != before;
+        commit_grants(current).map(|_| removed)
+    })?;
     drop_access_for_grant(id);
     if let Ok(mut used) = LAST_USED.lock() {
         used.remove(id);
@@ -358,18 +692,18 @@ fn commit_grants(grants: Vec<Grant>) -> Result<(), String> {
 /// Drops grants whose refresh token has expired. Run at sign-in and at token
 /// exchange rather than on a timer, for the reason in the module note.
 pub fn sweep_grants() {
-    let current = grants();
-    let live: Vec<Grant> = current.iter().filter(|grant| !grant.is_expired()).cloned().collect();
-    if live.len() == current.len() {
-        return
-    }
-    for grant in current.iter().filter(|grant| grant.is_expired()) {
+    let expired = exclusively(Shared::Sessions, || {
+        let (expired, live): (Vec<Grant>, Vec<Grant>) = grants().into_iter().partition(|grant| grant.is_expired());
+        if !expired.is_empty()
+            && let Err(error) = commit_grants(live) {
+                elogger(format!("OAuth: cannot write the session file during the expiry sweep: {}", error));
+        }
+        expired
+    });
+    for grant in expired {
         logger(format!("OAuth: grant expired and was dropped [{}]", grant.masked()));
         drop_access_for_grant(&grant.id);
     }
-    if let Err(error) = commit_grants(live) {
-        elogger(format!("OAuth: cannot write the session file during the expiry sweep: {}", error));
-    }
 }
 
 
@@ -501,22 +835,27 @@ impl IssuedCode {
 /// Mints an access token for a grant and returns it. Only the hash is kept.
 pub fn issue_access(grant_id: &str, ttl_secs: u64) -> String {
     let token = new_secret();
-    sweep_access();
-    if let Ok(mut access) = ACCESS.lock() {
-        access.insert(hash_secret(&token), AccessRecord {
-            grant_id: grant_id.to_string(),
-            expires: Instant::now() + std::time::Duration::from_secs(ttl_secs)
-        });
-    }
+    let now = Utc::now();
+    let record = AccessRecord {
+        grant_id: grant_id.to_string(),
+        issued_at: now,
+        expires_at: now + chrono::Duration::seconds(ttl_secs.min(i64::MAX as u64) as i64)
+    };
+    let hash = hash_secret(&token);
+    update_access(|access| {
+        access.insert(hash, record);
+        true
+    });
     token
 }
 
 /// The grant a presented access token belongs to, or `None` when the token is
 /// unknown or has expired.
 pub fn resolve_access(token_hash: &str) -> Option<AccessRecord> {
+    sync_shared();
     let access = ACCESS.lock().ok()?;
     let record = access.get(token_hash)?;
-    if record.expires <= Instant::now() {
+    if record.is_expired(Utc::now()) {
         return None
     }
     Some(record.clone())
@@ -524,21 +863,59 @@ pub fn resolve_access(token_hash: &str) -> Option<AccessRecord> {
 
 /// Drops one access token (RFC 7009 revocation of an access token).
 pub fn drop_access(token_hash: &str) -> bool {
-    ACCESS.lock().map(|mut access| access.remove(token_hash).is_some()).unwrap_or(false)
+    update_access(|access| access.remove(token_hash).is_some())
 }
 
 /// Drops every access token issued against a grant.
 pub fn drop_access_for_grant(grant_id: &str) {
-    if let Ok(mut access) = ACCESS.lock() {
+    update_access(|access| {
+        let before = access.len();
         access.retain(|_, record| record.grant_id != grant_id);
-    }
+        access.len() != before
+    });
 }
 
-fn sweep_access() {
-    if let Ok(mut access) = ACCESS.lock() {
-        let now = Instant::now();
-        access.retain(|_, record| record.expires > now);
-    }
+/// Applies `change` to the live tokens, sweeping expired ones first, and writes
+/// the result when tokens are persisted and `change` reports a difference.
+/// Returns what `change` returned.
+///
+/// Runs [`exclusively`], so a write merges with another instance's tokens
+/// rather than overwriting them.
+fn update_access(change: impl FnOnce(&mut HashMap<String, AccessRecord>) -> bool) -> bool {
+    exclusively(Shared::Tokens, || {
+        let Ok(mut access) = ACCESS.lock() else {
+            return false
+        };
+        let now = Utc::now();
+        let before = access.len();
+        access.retain(|_, record| !record.is_expired(now));
+        let swept = access.len() != before;
+        let changed = change(&mut access);
+        if is_shared()
+            && (changed || swept)
+            && let Err(error) = save_tokens(&access) {
+                elogger(format!("OAuth: cannot write the token file: {}", error));
+        }
+        changed
+    })
+}
+
+/// Live access tokens, for the dashboard's count.
+pub fn access_count() -> usize {
+    sync_shared();
+    let now = Utc::now();
+    ACCESS.lock().map(|access| access.values().filter(|record| !record.is_expired(now)).count()).unwrap_or(0)
+}
+
+
+/// What a grant's tokens may do right now: what it was granted, narrowed by
+/// what its client may still have. Taking a scope away from a connector in
+/// `/admin` therefore takes effect on the next call, not at the next sign-in.
+pub fn effective_scopes(grant: &Grant) -> Vec<TokenScope> {
+    let allowed = find_client(&grant.client_id)
+        .map(|client| client.allowed_scopes())
+        .unwrap_or_default();
+    grant.scopes().into_iter().filter(|scope| allowed.contains(scope)).collect()
 }
 
 
@@ -567,6 +944,29 @@ pub fn is_rate_limited(address: &str, limit: u32) -> bool {
     window.len() as u32 >= limit
 }
 
+/// Counts a registration attempt from an address.
+pub fn note_registration(address: &str) {
+    if let Ok(mut registrations) = REGISTRATIONS.lock() {
+        let window = registrations.entry(address.to_string()).or_default();
+        window.retain(|at| at.elapsed().as_secs() < REGISTRATION_WINDOW_SECS);
+        window.push(Instant::now());
+    }
+}
+
+/// Whether an address has spent its registrations for the hour.
+pub fn is_registration_limited(address: &str, limit: u32) -> bool {
+    let Ok(mut registrations) = REGISTRATIONS.lock() else {
+        // Unlike the sign-in limit nothing stands behind this one, so a
+        // poisoned lock refuses.
+        return true
+    };
+    let Some(window) = registrations.get_mut(address) else {
+        return false
+    };
+    window.retain(|at| at.elapsed().as_secs() < REGISTRATION_WINDOW_SECS);
+    window.len() as u32 >= limit
+}
+
 /// Forgets an address's failures after a successful sign-in.
 pub fn clear_failures(address: &str) {
     if let Ok(mut failures) = FAILURES.lock() {
@@ -609,8 +1009,11 @@ mod tests {
                 name: "Orink Hungary".into(),
                 secret_hash: hash_secret("s3cret"),
                 redirect_uris: vec!["https://claude.ai/api/mcp/auth_callback".into()],
+                scopes: Vec::new(),
                 created_at: Some(Utc::now()),
-                enabled: Some(true)
+                enabled: Some(true),
+                registered_by: None,
+                reviewed: None
             }]
         };
         let text = toml::to_string_pretty(&config).expect("serializes");
@@ -622,6 +1025,30 @@ mod tests {
         assert!(parsed.clients[0].is_enabled());
     }
 
+    #[test]
+    fn persisted_tokens_round_trip_as_hashes() {
+        let token = new_secret();
+        let now = Utc::now();
+        let config = TokenConfig {
+            tokens: vec![StoredToken {
+                hash: hash_secret(&token),
+                record: AccessRecord {
+                    grant_id: "abc-1".into(),
+                    issued_at: now,
+                    expires_at: now + chrono::Duration::hours(1)
+                }
+            }]
+        };
+        let text = toml::to_string_pretty(&config).expect("serializes");
+        assert!(!text.contains(&token));
+
+        let parsed: TokenConfig = toml::from_str(&text).expect("parses");
+        assert_eq!(parsed.tokens[0].hash, hash_secret(&token));
+        assert_eq!(parsed.tokens[0].record.grant_id, "abc-1");
+        assert!(!parsed.tokens[0].record.is_expired(now));
+        assert!(parsed.tokens[0].record.is_expired(now + chrono::Duration::hours(2)));
+    }
+
     #[test]
     fn serialized_grants_round_trip() {
         let config = SessionConfig { grants: vec![grant("a", 30)] };
@@ -671,6 +1098,18 @@ mod tests {
         assert!(resolve_access(&hash_secret(&token)).is_none());
     }
 
+    #[test]
+    fn a_shared_file_is_changed_by_one_writer_at_a_time() {
+        let document = std::env::temp_dir().join(format!("rustopus-lock-{}.toml", uuid::Uuid::new_v4()));
+        let lock_path = document.with_extension("toml.lock");
+        let held = lock_beside(&document).unwrap();
+        let other = std::fs::OpenOptions::new().write(true).open(&lock_path).unwrap();
+        assert!(other.try_lock().is_err());
+        drop(held);
+        assert!(other.try_lock().is_ok());
+        let _ = std::fs::remove_file(lock_path);
+    }
+
     #[test]
     fn revoking_a_grant_drops_its_access_tokens() {
         let token = issue_access("grant-4", 60);
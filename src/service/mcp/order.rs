@@ -0,0 +1,678 @@
+//! Orders from a conversation: drafted against the catalog, read back to the
+//! user, and sent to Octopus only once they have said yes.
+//!
+//! An assistant that has just found the products should not leave the user to
+//! write `/post-order` XML by hand, but nor should it place an order on its own
+//! reading of "sounds good". So ordering takes two steps. Drafting checks every
+//! line against the snapshot — found, published for sale, priced — and hands
+//! back the draft with a **confirmation token**. Submitting needs that token,
+//! which is single-use, expires after [`CONFIRMATION_SECS`], and is replaced
+//! whenever the draft is; an order is sent exactly as it was last shown.
+//!
+//! A submitted order goes out through [`routes::order::submit`], the same
+//! `Order` to `Rendeles` conversion and response translation `/post-order`
+//! uses. Drafts live in memory like quotes, owned by the authcode and partner
+//! id that made them, and are forgotten after [`DRAFT_TTL_SECS`] untouched. One
+//! still sending after [`SENDING_TIMEOUT_SECS`] is settled as unconfirmed.
+//!
+//! [`routes::order::submit`]: crate::routes::order::submit
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+
+use crate::{
+    forms::{
+        r#in::xml::orders::{Address, Header, Item, Items, Order},
+        out::xml::orders_response::Answer
+    },
+    macros::mcp::McpToolArgs,
+    service::mcp::{
+        availability::{CheckItem, MAX_ITEMS, Orderable, Purpose, orderable, round},
+        index::CatalogSnapshot,
+        quote::Owner,
+        secrets_match
+    }
+};
+
+/// How long a confirmation token stands. Long enough to read a draft out and
+/// get an answer; short enough that a yes is about the order just shown.
+pub const CONFIRMATION_SECS: u64 = 15 * 60;
+
+/// Drafts nobody touches for a day are forgotten.
+const DRAFT_TTL_SECS: u64 = 24 * 60 * 60;
+
+/// How long an order may stay `sending`. Far past any upstream timeout, so
+/// reaching it means the call that sent it never came back — its client went
+/// away mid-call — and the draft is settled as unconfirmed instead of stuck.
+const SENDING_TIMEOUT_SECS: u64 = 60 * 60;
+
+/// Drafts one caller may hold at once.
+const MAX_DRAFTS_PER_OWNER: usize = 20;
+
+/// Delivery mode when the caller names none, as in the `/post-order` example.
+const DEFAULT_DELIVERY_MODE: u8 = 1;
+
+/// The `version` Octopus expects on an order document.
+const ORDER_VERSION: &str = "1.0";
+
+
+McpToolArgs! {
+    #[derive(Clone)]
+    pub struct DeliveryAddress {
+        /// Company or person the goods go to.
+        pub name: Option<String>,
+        /// Country name or code, in English or Hungarian.
+        pub country: Option<String>,
+        pub zip: Option<String>,
+        pub city: Option<String>,
+        pub street: Option<String>
+    }
+}
+
+
+/// The particulars of an order, as a tool sets them. Drafting replaces all of
+/// them, so a field left out is left off the order.
+#[derive(Debug, Clone, Default)]
+pub struct Particulars {
+    pub delivery_mode: Option<u8>,
+    /// The partner's own order number.
+    pub reference: Option<String>,
+    pub delivery_note: Option<String>,
+    pub note: Option<String>,
+    pub note_warehouse: Option<String>,
+    pub contact_phone: Option<String>,
+    pub contact_email: Option<String>,
+    pub delivery_address: Option<DeliveryAddress>
+}
+
+
+/// One product on a draft, as it was when drafted.
+#[derive(Debug, Clone)]
+struct Line {
+    no: String,
+    name: String,
+    unit: Option<String>,
+    quantity: u32,
+    price: f64,
+    stock: Option<f64>
+}
+
+
+/// Where a draft stands.
+#[derive(Debug, Clone)]
+enum Status {
+    /// Open, with the token that sends it if it is still current.
+    Draft(Option<Confirmation>),
+    /// Handed to Octopus, answer pending.
+    Sending,
+    Submitted(Receipt),
+    /// Sent, but Octopus's answer could not be read.
+    Unconfirmed(String)
+}
+
+#[derive(Debug, Clone)]
+struct Confirmation {
+    token: String,
+    expires: Instant
+}
+
+
+/// An order being drafted, or one already sent.
+#[derive(Debug, Clone)]
+struct Draft {
+    id: String,
+    owner: Owner,
+    particulars: Particulars,
+    currency: Option<String>,
+    lines: Vec<Line>,
+    created: DateTime<Utc>,
+    touched: Instant,
+    status: Status
+}
+
+
+/// One line as the user is shown it before saying yes.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DraftLine {
+    pub position: usize,
+    pub no: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    pub quantity: u32,
+    /// This partner's price per unit, net, when drafted.
+    pub unit_price: f64,
+    pub net: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stock: Option<f64>
+}
+
+
+/// A whole draft: what the tools return.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DraftView {
+    pub order_id: String,
+    /// `draft`, `sending`, `submitted` or `unconfirmed`.
+    pub status: &'static str,
+    pub delivery_mode: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivery_note: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note_warehouse: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contact_phone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contact_email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivery_address: Option<AddressView>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    pub lines: Vec<DraftLine>,
+    /// The lines' total at this partner's prices, net of VAT.
+    pub net: f64,
+    /// Lines stock does not cover, which Octopus may split or backorder.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    /// Whether a confirmation token from the last draft still stands.
+    pub awaiting_confirmation: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receipt: Option<Receipt>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub problem: Option<String>
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AddressView {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub street: Option<String>
+}
+
+
+/// A freshly drafted order and the token that sends it.
+#[derive(Debug, Clone)]
+pub struct Drafted {
+    pub order: DraftView,
+    pub confirmation_token: String
+}
+
+
+/// What Octopus recorded, from its translated answer.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Receipt {
+    pub submitted_at: DateTime<Utc>,
+    pub document_number: String,
+    pub identifier: String,
+    pub web_identifier: String,
+    pub delivery_date: String,
+    pub lines: Vec<ReceiptLine>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shipping_cost: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cash_on_delivery: Option<String>
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ReceiptLine {
+    pub position: String,
+    pub no: String,
+    pub quantity: String,
+    /// Octopus's own coverage indicator and expected date for the quantity.
+    pub coverage: String,
+    pub date: String,
+    pub unit_price_net: String,
+    pub value_net: String,
+    pub value_gross: String,
+    pub currency: String
+}
+
+impl Receipt {
+    fn from_answer(answer: &Answer) -> Self {
+        Self {
+            submitted_at: Utc::now(),
+            document_number: answer.header.document_number.clone(),
+            identifier: answer.header.identifier.clone(),
+            web_identifier: answer.header.web_identifier.clone(),
+            delivery_date: answer.header.delivery_date.clone(),
+            lines: answer.items.item.iter()
+                .map(|item| ReceiptLine {
+                    position: item.item_number.clone(),
+                    no: item.product_number.clone(),
+                    quantity: item.quantity.value.clone(),
+                    coverage: item.quantity.coverage.clone(),
+                    date: item.quantity.date.clone(),
+                    unit_price_net: item.unit_price_net.clone(),
+                    value_net: item.value_net.clone(),
+                    value_gross: item.value_gross.clone(),
+                    currency: item.currency.clone()
+                })
+                .collect(),
+            shipping_cost: answer.shipping_cost.clone(),
+            cash_on_delivery: answer.cash_on_delivery.clone()
+        }
+    }
+}
+
+
+/// Drafts, by id.
+static DRAFTS: Lazy<Mutex<HashMap<String, Draft>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+
+/// Drafts an order, or redrafts one of the owner's that has not been sent, and
+/// returns it with a new confirmation token. Every line is checked against the
+/// snapshot, and nothing is kept if any is refused.
+pub fn draft(owner: &Owner, snapshot: &CatalogSnapshot, id: Option<&str>, items: &[CheckItem], particulars: Particulars) -> Result<Drafted, String> {
+    sweep_expired();
+    let mut draft = Draft {
+        id: new_id(),
+        owner: owner.clone(),
+        particulars,
+        currency: None,
+        lines: Vec::new(),
+        created: Utc::now(),
+        touched: Instant::now(),
+        status: Status::Draft(None)
+    };
+    draft.fill(snapshot, items)?;
+
+    let mut drafts = DRAFTS.lock().map_err(|_| "the order store is unavailable".to_string())?;
+    match id.map(str::trim) {
+        Some(id) => {
+            let earlier = drafts.get(id)
+                .filter(|earlier| earlier.owner == *owner)
+                .ok_or_else(|| missing(id))?;
+            if !matches!(earlier.status, Status::Draft(_)) {
+                return Err(format!("order '{}' has already been sent; draft a new one instead", id))
+            }
+            draft.id = earlier.id.clone();
+            draft.created = earlier.created;
+        }
+        None => {
+            if drafts.values().filter(|held| held.owner == *owner && matches!(held.status, Status::Draft(_))).count() >= MAX_DRAFTS_PER_OWNER {
+                return Err(format!(
+                    "this connector already holds {} open drafts; submit or redraft one of them instead",
+                    MAX_DRAFTS_PER_OWNER
+                ))
+            }
+        }
+    }
+
+    let token = uuid::Uuid::new_v4().simple().to_string();
+    draft.status = Status::Draft(Some(Confirmation {
+        token: token.clone(),
+        expires: Instant::now() + Duration::from_secs(CONFIRMATION_SECS)
+    }));
+    let view = draft.view();
+    drafts.insert(draft.id.clone(), draft);
+    Ok(Drafted { order: view, confirmation_token: token })
+}
+
+
+/// One of the owner's drafts or sent orders, as it stands.
+pub fn show(owner: &Owner, id: &str) -> Result<DraftView, String> {
+    sweep_expired();
+    let drafts = DRAFTS.lock().map_err(|_| "the order store is unavailable".to_string())?;
+    drafts.get(id.trim())
+        .filter(|draft| draft.owner == *owner)
+        .map(Draft::view)
+        .ok_or_else(|| missing(id.trim()))
+}
+
+
+/// Takes the confirmation token and, if it is the draft's current one, spends
+/// it and returns the `Order` to send for partner `pid`. The draft is marked as
+/// sending, so the same yes cannot send it twice; [`record`] settles it.
+pub fn claim(owner: &Owner, id: &str, token: &str, pid: i64) -> Result<Order, String> {
+    let pid = u64::try_from(pid).map_err(|_| format!("partner id {} cannot place orders", pid))?;
+    let mut drafts = DRAFTS.lock().map_err(|_| "the order store is unavailable".to_string())?;
+    let draft = drafts.get_mut(id.trim())
+        .filter(|draft| draft.owner == *owner)
+        .ok_or_else(|| missing(id.trim()))?;
+
+    let current = match &draft.status {
+        Status::Draft(Some(confirmation)) if secrets_match(token.trim(), &confirmation.token) => Instant::now() < confirmation.expires,
+        Status::Draft(_) => return Err(
+            "that is not the confirmation token of this draft's latest version; draft_order returns the current one".into()
+        ),
+        _ => return Err(format!("order '{}' has already been sent", draft.id))
+    };
+    if !current {
+        draft.status = Status::Draft(None);
+        return Err("the confirmation has expired; show the user the order again with draft_order and ask once more".into())
+    }
+
+    draft.status = Status::Sending;
+    draft.touched = Instant::now();
+    Ok(draft.order(pid))
+}
+
+
+/// Settles a claimed draft with Octopus's translated answer, or with why it
+/// could not be read.
+pub fn record(owner: &Owner, id: &str, outcome: Result<&Answer, &str>) -> Result<DraftView, String> {
+    let mut drafts = DRAFTS.lock().map_err(|_| "the order store is unavailable".to_string())?;
+    let draft = drafts.get_mut(id.trim())
+        .filter(|draft| draft.owner == *owner)
+        .ok_or_else(|| missing(id.trim()))?;
+    draft.status = match outcome {
+        Ok(answer) => Status::Submitted(Receipt::from_answer(answer)),
+        Err(reason) => Status::Unconfirmed(reason.to_string())
+    };
+    draft.touched = Instant::now();
+    Ok(draft.view())
+}
+
+
+fn new_id() -> String {
+    let random = uuid::Uuid::new_v4().simple().to_string().to_uppercase();
+    format!("O-{}", &random[..8])
+}
+
+
+/// Another owner's draft reads as missing, so an id reveals nothing.
+fn missing(id: &str) -> String {
+    format!("no order '{}' — it may have expired; draft it again with draft_order", id)
+}
+
+
+/// Forgets drafts nobody has touched within [`DRAFT_TTL_SECS`]. One still
+/// being sent is kept until [`SENDING_TIMEOUT_SECS`], then settled as
+/// unconfirmed — it may have reached Octopus — and forgotten a day later.
+fn sweep_expired() {
+    if let Ok(mut drafts) = DRAFTS.lock() {
+        sweep(&mut drafts, Instant::now());
+    }
+}
+
+fn sweep(drafts: &mut HashMap<String, Draft>, now: Instant) {
+    let ttl = Duration::from_secs(DRAFT_TTL_SECS);
+    let sending = Duration::from_secs(SENDING_TIMEOUT_SECS);
+    for draft in drafts.values_mut() {
+        if matches!(draft.status, Status::Sending) && now.duration_since(draft.touched) >= sending {
+            draft.status = Status::Unconfirmed("no answer was recorded for it".into());
+            draft.touched = now;
+        }
+    }
+    drafts.retain(|_, draft| matches!(draft.status, Status::Sending) || now.duration_since(draft.touched) < ttl);
+}
+
+
+/// Trimmed, and `None` when empty.
+fn text(value: &Option<String>) -> Option<String> {
+    value.as_deref().map(str::trim).filter(|value| !value.is_empty()).map(str::to_string)
+}
+
+
+impl Draft {
+    fn fill(&mut self, snapshot: &CatalogSnapshot, items: &[CheckItem]) -> Result<(), String> {
+        if items.is_empty() {
+            return Err("an order needs at least one line".into())
+        }
+        if items.len() > MAX_ITEMS {
+            return Err(format!("an order holds at most {} lines", MAX_ITEMS))
+        }
+        for (index, item) in items.iter().enumerate() {
+            self.add(snapshot, index, item)?;
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, snapshot: &CatalogSnapshot, index: usize, item: &CheckItem) -> Result<(), String> {
+        let held = (!self.lines.is_empty()).then_some(&self.currency);
+        let Orderable { view, quantity, price } = orderable(snapshot, index, item, Purpose::Order, held)?;
+        let (product, offer) = (&view.product, view.offer);
+        if self.lines.iter().any(|line| line.no == product.no) {
+            return Err(format!("'{}' is on the order twice; give it once with the whole quantity", product.no))
+        }
+        self.currency = offer.currency.clone();
+        self.lines.push(Line {
+            no: product.no.clone(),
+            name: product.name.clone(),
+            unit: product.unit.clone(),
+            quantity,
+            price,
+            stock: offer.stock
+        });
+        Ok(())
+    }
+
+    /// The `Order` `/post-order` would have parsed for the same draft.
+    fn order(&self, pid: u64) -> Order {
+        let particulars = &self.particulars;
+        let address = particulars.delivery_address.as_ref().map(|address| Address {
+            name: text(&address.name),
+            country: text(&address.country),
+            zip: text(&address.zip),
+            city: text(&address.city),
+            street: text(&address.street)
+        });
+        Order {
+            version: ORDER_VERSION.to_string(),
+            header: Header {
+                pid,
+                foreign_order_number: text(&particulars.reference),
+                delivery_mode: particulars.delivery_mode.unwrap_or(DEFAULT_DELIVERY_MODE),
+                delivery_note: text(&particulars.delivery_note),
+                enduser_id: None,
+                enduser_name: None,
+                enduser_contact_id: None,
+                enduser_contact: None,
+                contact_phone: text(&particulars.contact_phone),
+                contact_email: text(&particulars.contact_email),
+                note: text(&particulars.note),
+                note_warehouse: text(&particulars.note_warehouse),
+                note_hidden: None,
+                invoice_address: None,
+                delivery_address: address,
+                enduser_delivery_mode: None,
+                enduser_currency: None,
+                enduser_payment_method: None,
+                enduser_payment_deadline: None,
+                enduser_vat_no: None,
+                enduser_type: None,
+                enduser_invoice: None
+            },
+            items: Items {
+                items: self.lines.iter().enumerate()
+                    .map(|(index, line)| Item {
+                        lot_no: index as u64 + 1,
+                        no: line.no.clone(),
+                        qty: f64::from(line.quantity),
+                        enduser_price: None,
+                        note: None
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    fn view(&self) -> DraftView {
+        let lines: Vec<DraftLine> = self.lines.iter().enumerate()
+            .map(|(index, line)| DraftLine {
+                position: index + 1,
+                no: line.no.clone(),
+                name: line.name.clone(),
+                unit: line.unit.clone(),
+                quantity: line.quantity,
+                unit_price: line.price,
+                net: round(line.price * f64::from(line.quantity)),
+                stock: line.stock
+            })
+            .collect();
+        let warnings = self.lines.iter()
+            .filter(|line| line.stock.unwrap_or(0.0) < f64::from(line.quantity))
+            .map(|line| format!(
+                "{}: {} ordered, {} in stock",
+                line.no, line.quantity, line.stock.unwrap_or(0.0)
+            ))
+            .collect();
+
+        let particulars = &self.particulars;
+        let (status, awaiting_confirmation, receipt, problem) = match &self.status {
+            Status::Draft(confirmation) => (
+                "draft",
+                confirmation.as_ref().is_some_and(|confirmation| Instant::now() < confirmation.expires),
+                None,
+                None
+            ),
+            Status::Sending => ("sending", false, None, None),
+            Status::Submitted(receipt) => ("submitted", false, Some(receipt.clone()), None),
+            Status::Unconfirmed(reason) => ("unconfirmed", false, None, Some(format!(
+                "the order was sent but Octopus's answer could not be read ({}); whether it was recorded \
+                 is unknown, so check with Orink before ordering the same again",
+                reason
+            )))
+        };
+
+        DraftView {
+            order_id: self.id.clone(),
+            status,
+            delivery_mode: particulars.delivery_mode.unwrap_or(DEFAULT_DELIVERY_MODE),
+            reference: text(&particulars.reference),
+            delivery_note: text(&particulars.delivery_note),
+            note: text(&particulars.note),
+            note_warehouse: text(&particulars.note_warehouse),
+            contact_phone: text(&particulars.contact_phone),
+            contact_email: text(&particulars.contact_email),
+            delivery_address: particulars.delivery_address.as_ref().map(|address| AddressView {
+                name: text(&address.name),
+                country: text(&address.country),
+                zip: text(&address.zip),
+                city: text(&address.city),
+                street: text(&address.street)
+            }),
+            currency: self.currency.clone(),
+            net: round(lines.iter().map(|line| line.net).sum()),
+            lines,
+            warnings,
+            created_at: self.created,
+            awaiting_confirmation,
+            receipt,
+            problem
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::mcp::availability::test_item;
+    use crate::service::mcp::index::{Offer, test_product, test_snapshot_of};
+
+    fn snapshot() -> CatalogSnapshot {
+        let offer = |available: bool, price: f64, stock: f64| Offer { available, price: Some(price), currency: Some("HUF".into()), stock: Some(stock) };
+        test_snapshot_of(vec![
+            (test_product("T-1", "Toll", "Pax", ""), offer(true, 100.0, 50.0)),
+            (test_product("P-1", "Papír", "Pax", ""), offer(true, 1000.0, 2.0)),
+            (test_product("H-1", "Rejtett", "Pax", ""), offer(false, 10.0, 9.0))
+        ])
+    }
+
+    #[test]
+    fn a_draft_is_checked_against_the_catalog_and_built_as_an_order() {
+        let snapshot = snapshot();
+        let owner = Owner::new("FFD3ABCDEF120E37", 7);
+
+        assert!(draft(&owner, &snapshot, None, &[test_item("X-9", 1)], Particulars::default()).is_err());
+        assert!(draft(&owner, &snapshot, None, &[test_item("H-1", 1)], Particulars::default()).is_err(), "not published");
+        assert!(draft(&owner, &snapshot, None, &[test_item("T-1", 0)], Particulars::default()).is_err());
+        assert!(draft(&owner, &snapshot, None, &[test_item("T-1", 1), test_item("t-1", 2)], Particulars::default()).is_err());
+        assert!(draft(&owner, &snapshot, None, &[], Particulars::default()).is_err());
+
+        let particulars = Particulars {
+            reference: Some(" PO-77 ".into()),
+            delivery_address: Some(DeliveryAddress {
+                name: Some("Kovács Kft.".into()), country: Some("Hungary".into()), zip: Some("1111".into()),
+                city: Some("Budapest".into()), street: Some("Fő utca 1.".into())
+            }),
+            ..Particulars::default()
+        };
+        let drafted = draft(&owner, &snapshot, None, &[test_item("T-1", 10), test_item("P-1", 3)], particulars).expect("drafted");
+        assert_eq!((drafted.order.net, drafted.order.status), (4000.0, "draft"));
+        assert_eq!(drafted.order.warnings, ["P-1: 3 ordered, 2 in stock"]);
+        assert!(drafted.order.awaiting_confirmation);
+
+        let order = claim(&owner, &drafted.order.order_id, &drafted.confirmation_token, 7).expect("claimed");
+        assert_eq!((order.version.as_str(), order.header.pid, order.header.delivery_mode), ("1.0", 7, DEFAULT_DELIVERY_MODE));
+        assert_eq!(order.header.foreign_order_number.as_deref(), Some("PO-77"));
+        let lines: Vec<(u64, &str, f64)> = order.items.items.iter().map(|item| (item.lot_no, item.no.as_str(), item.qty)).collect();
+        assert_eq!(lines, [(1, "T-1", 10.0), (2, "P-1", 3.0)]);
+    }
+
+    #[test]
+    fn only_the_latest_unspent_token_sends_an_order_once() {
+        let snapshot = snapshot();
+        let owner = Owner::new("FFD3ABCDEF120E37", 7);
+        let first = draft(&owner, &snapshot, None, &[test_item("T-1", 1)], Particulars::default()).expect("drafted");
+        let id = first.order.order_id.clone();
+
+        // Another partner sees nothing; a redraft keeps the id and retires the old token.
+        assert!(show(&Owner::new("FFD3ABCDEF120E37", 8), &id).is_err());
+        assert!(claim(&Owner::new("FFD3ABCDEF120E37", 8), &id, &first.confirmation_token, 8).is_err());
+        let second = draft(&owner, &snapshot, Some(&id), &[test_item("T-1", 2)], Particulars::default()).expect("redrafted");
+        assert_eq!(second.order.order_id, id);
+        assert!(claim(&owner, &id, &first.confirmation_token, 7).is_err());
+        assert!(claim(&owner, &id, "", 7).is_err());
+
+        // An expired token is refused, and spent with it.
+        if let Some(Draft { status: Status::Draft(Some(confirmation)), .. }) = DRAFTS.lock().expect("store").get_mut(&id) {
+            confirmation.expires = Instant::now();
+        }
+        assert!(claim(&owner, &id, &second.confirmation_token, 7).is_err());
+        assert!(!show(&owner, &id).expect("shown").awaiting_confirmation);
+
+        let third = draft(&owner, &snapshot, Some(&id), &[test_item("T-1", 2)], Particulars::default()).expect("redrafted");
+        assert!(claim(&owner, &id, &third.confirmation_token, 7).is_ok());
+        assert!(claim(&owner, &id, &third.confirmation_token, 7).is_err(), "a token sends once");
+        assert_eq!(show(&owner, &id).expect("shown").status, "sending");
+        assert!(draft(&owner, &snapshot, Some(&id), &[test_item("T-1", 1)], Particulars::default()).is_err());
+
+        let settled = record(&owner, &id, Err("unexpected end of input")).expect("recorded");
+        assert_eq!(settled.status, "unconfirmed");
+        assert!(settled.problem.is_some_and(|problem| problem.contains("check with Orink")));
+    }
+
+    #[test]
+    fn an_order_left_sending_is_settled_as_unconfirmed_then_forgotten() {
+        let snapshot = snapshot();
+        let owner = Owner::new("FFD3ABCDEF120E37", 9);
+        let drafted = draft(&owner, &snapshot, None, &[test_item("T-1", 1)], Particulars::default()).expect("drafted");
+        let id = drafted.order.order_id.clone();
+        claim(&owner, &id, &drafted.confirmation_token, 9).expect("claimed");
+
+        // The call that sent it never recorded an answer.
+        let mut drafts: HashMap<String, Draft> = DRAFTS.lock().expect("store").iter()
+            .filter(|(held, _)| **held == id)
+            .map(|(held, draft)| (held.clone(), draft.clone()))
+            .collect();
+        let start = Instant::now();
+        sweep(&mut drafts, start + Duration::from_secs(SENDING_TIMEOUT_SECS - 1));
+        assert_eq!(drafts[&id].view().status, "sending");
+
+        let timed_out = start + Duration::from_secs(SENDING_TIMEOUT_SECS);
+        sweep(&mut drafts, timed_out);
+        let view = drafts[&id].view();
+        assert_eq!(view.status, "unconfirmed");
+        assert!(view.problem.is_some_and(|problem| problem.contains("check with Orink")));
+
+        sweep(&mut drafts, timed_out + Duration::from_secs(DRAFT_TTL_SECS));
+        assert!(drafts.is_empty());
+    }
+}
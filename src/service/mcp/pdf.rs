@@ -0,0 +1,324 @@
+//! A minimal PDF writer: text in the built-in Helvetica faces, rules and shaded
+//! boxes on A4 pages. Enough for a quote, and nothing more.
+//!
+//! Hand-rolled rather than pulled in as a crate for the same reason the
+//! dashboard's base64 decoder is: the whole need is a few hundred bytes of
+//! well-specified syntax, and a PDF library would bring font embedding, image
+//! codecs and a layout engine into a server that prints one table.
+//!
+//! The standard 14 fonts need no embedding, so a file stays a few kilobytes.
+//! Their WinAnsi encoding covers Western European text but not Hungarian's
+//! `ő` and `ű`; those four letters are mapped onto code points WinAnsi leaves
+//! unused through a `/Differences` array, so a customer's name prints as
+//! written rather than with a question mark.
+
+/// A4, in points.
+pub const PAGE_WIDTH: f64 = 595.28;
+pub const PAGE_HEIGHT: f64 = 841.89;
+
+/// Code points WinAnsi leaves undefined, borrowed for Hungarian's double acute
+/// letters, with the glyph names every PDF reader knows them by.
+const DOUBLE_ACUTES: [(char, u8, &str); 4] = [
+    ('Ő', 0x81, "Ohungarumlaut"),
+    ('ő', 0x8D, "ohungarumlaut"),
+    ('Ű', 0x8F, "Uhungarumlaut"),
+    ('ű', 0x90, "uhungarumlaut")
+];
+
+/// Helvetica advance widths for ASCII 32–126, in thousandths of the font size.
+const REGULAR_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556,
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556,
+    333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556,
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584
+];
+
+/// Helvetica-Bold advance widths for ASCII 32–126.
+const BOLD_WIDTHS: [u16; 95] = [
+    278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 333, 333, 584, 584, 584, 611,
+    975, 722, 722, 722, 722, 667, 611, 778, 722, 278, 556, 722, 611, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 333, 278, 333, 584, 556,
+    333, 556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556, 278, 889, 611, 611,
+    611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500, 389, 280, 389, 584
+];
+
+/// Width assumed for a letter outside ASCII — an accented vowel, mostly.
+const OTHER_WIDTH: u16 = 556;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Font {
+    Regular,
+    Bold
+}
+
+impl Font {
+    fn resource(self) -> &'static str {
+        match self {
+            Font::Regular => "F1",
+            Font::Bold => "F2"
+        }
+    }
+}
+
+
+/// A document being drawn, one content stream per page. Positions are in
+/// points, `x` from the left edge and `y` from the **top**, which is how a
+/// layout is thought about; the flip to PDF's bottom-up space happens here.
+pub struct Pdf {
+    pages: Vec<Vec<u8>>
+}
+
+impl Default for Pdf {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pdf {
+    /// A document with one empty page.
+    pub fn new() -> Self {
+        Self { pages: vec![Vec::new()] }
+    }
+
+    /// Starts a new page; everything drawn after goes on it.
+    pub fn new_page(&mut self) {
+        self.pages.push(Vec::new());
+    }
+
+    fn page(&mut self) -> &mut Vec<u8> {
+        if self.pages.is_empty() {
+            self.pages.push(Vec::new());
+        }
+        let last = self.pages.len() - 1;
+        &mut self.pages[last]
+    }
+
+    /// Text with its baseline at `y`, starting at `x`.
+    pub fn text(&mut self, x: f64, y: f64, size: f64, font: Font, text: &str) {
+        let mut operation = format!("BT /{} {} Tf {} {} Td (", font.resource(), number(size), number(x), number(PAGE_HEIGHT - y)).into_bytes();
+        operation.extend(escape(&encode(text)));
+        operation.extend_from_slice(b") Tj ET\n");
+        self.page().extend(operation);
+    }
+
+    /// Text ending at `right`, for figures in a column.
+    pub fn text_right(&mut self, right: f64, y: f64, size: f64, font: Font, text: &str) {
+        self.text(right - width(text, size, font), y, size, font, text);
+    }
+
+    /// A horizontal rule from `left` to `right`.
+    pub fn rule(&mut self, left: f64, right: f64, y: f64, thickness: f64) {
+        let operation = format!(
+            "{} w {} {} m {} {} l S\n",
+            number(thickness), number(left), number(PAGE_HEIGHT - y), number(right), number(PAGE_HEIGHT - y)
+        );
+        self.page().extend(operation.into_bytes());
+    }
+
+    /// A light grey box with its top-left corner at `x`, `y`, drawn under
+    /// whatever comes after it.
+    pub fn shade(&mut self, x: f64, y: f64, width: f64, height: f64) {
+        let operation = format!(
+            "0.92 g {} {} {} {} re f 0 g\n",
+            number(x), number(PAGE_HEIGHT - y - height), number(width), number(height)
+        );
+        self.page().extend(operation.into_bytes());
+    }
+
+    /// The finished file.
+    pub fn finish(self) -> Vec<u8> {
+        let pages = if self.pages.is_empty() { vec![Vec::new()] } else { self.pages };
+        let mut objects: Vec<Vec<u8>> = Vec::new();
+
+        // 1 catalog, 2 page tree, 3 and 4 the fonts, 5 their encoding, then a
+        // page object and a content stream per page.
+        let kids: Vec<String> = (0..pages.len()).map(|index| format!("{} 0 R", 6 + index * 2)).collect();
+        objects.push(b"<< /Type /Catalog /Pages 2 0 R >>".to_vec());
+        objects.push(format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), pages.len()).into_bytes());
+        objects.push(b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding 5 0 R >>".to_vec());
+        objects.push(b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding 5 0 R >>".to_vec());
+        let differences: Vec<String> = DOUBLE_ACUTES.iter().map(|(_, code, name)| format!("{} /{}", code, name)).collect();
+        objects.push(format!(
+            "<< /Type /Encoding /BaseEncoding /WinAnsiEncoding /Differences [{}] >>",
+            differences.join(" ")
+        ).into_bytes());
+
+        for (index, content) in pages.into_iter().enumerate() {
+            objects.push(format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+                number(PAGE_WIDTH), number(PAGE_HEIGHT), 7 + index * 2
+            ).into_bytes());
+            let mut stream = format!("<< /Length {} >>\nstream\n", content.len()).into_bytes();
+            stream.extend(content);
+            stream.extend_from_slice(b"\nendstream");
+            objects.push(stream);
+        }
+
+        let mut file = b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec();
+        let mut offsets = Vec::with_capacity(objects.len());
+        for (index, object) in objects.iter().enumerate() {
+            offsets.push(file.len());
+            file.extend(format!("{} 0 obj\n", index + 1).into_bytes());
+            file.extend(object);
+            file.extend_from_slice(b"\nendobj\n");
+        }
+
+        let xref = file.len();
+        file.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).into_bytes());
+        for offset in offsets {
+            file.extend(format!("{:010} 00000 n \n", offset).into_bytes());
+        }
+        file.extend(format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1, xref
+        ).into_bytes());
+        file
+    }
+}
+
+
+/// How wide `text` sets, in points.
+pub fn width(text: &str, size: f64, font: Font) -> f64 {
+    let widths = match font {
+        Font::Regular => &REGULAR_WIDTHS,
+        Font::Bold => &BOLD_WIDTHS
+    };
+    let thousandths: u32 = text.chars()
+        .map(|letter| match letter as u32 {
+            code @ 32..=126 => u32::from(widths[(code - 32) as usize]),
+            _ => u32::from(OTHER_WIDTH)
+        })
+        .sum();
+    f64::from(thousandths) * size / 1000.0
+}
+
+
+/// `text`, cut short with an ellipsis where it would run past `max` points.
+pub fn fit(text: &str, size: f64, font: Font, max: f64) -> String {
+    if width(text, size, font) <= max {
+        return text.to_string()
+    }
+    let mut kept = String::new();
+    for letter in text.chars() {
+        kept.push(letter);
+        if width(&kept, size, font) + width("…", size, font) > max {
+            kept.pop();
+            break
+        }
+    }
+    format!("{}…", kept.trim_end())
+}
+
+
+/// `text` broken into lines no wider than `max` points, at spaces.
+pub fn wrap(text: &str, size: f64, font: Font, max: f64) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+            if width(&candidate, size, font) > max && !line.is_empty() {
+                lines.push(std::mem::replace(&mut line, word.to_string()));
+            } else {
+                line = candidate;
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+
+/// Text in the fonts' encoding. A letter it cannot show becomes `?`.
+fn encode(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|letter| match letter {
+            ' '..='~' => letter as u8,
+            '\u{a0}'..='\u{ff}' => letter as u32 as u8,
+            '€' => 0x80,
+            '…' => 0x85,
+            '‘' => 0x91,
+            '’' => 0x92,
+            '“' => 0x93,
+            '”' => 0x94,
+            '•' => 0x95,
+            '–' => 0x96,
+            '—' => 0x97,
+            _ => DOUBLE_ACUTES.iter()
+                .find(|(double, _, _)| *double == letter)
+                .map_or(b'?', |(_, code, _)| *code)
+        })
+        .collect()
+}
+
+
+/// A string literal's body: delimiters escaped, and anything outside printable
+/// ASCII written as an octal escape so the content stream stays plain text.
+fn escape(bytes: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(bytes.len());
+    for &byte in bytes {
+        match byte {
+            b'(' | b')' | b'\\' => escaped.extend_from_slice(&[b'\\', byte]),
+            32..=126 => escaped.push(byte),
+            _ => escaped.extend(format!("\\{:03o}", byte).into_bytes())
+        }
+    }
+    escaped
+}
+
+
+/// A coordinate as PDF writes it: at most two decimals, no trailing zeros.
+fn number(value: f64) -> String {
+    let text = format!("{:.2}", value);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_document_has_a_consistent_cross_reference_table() {
+        let mut pdf = Pdf::new();
+        pdf.text(40.0, 60.0, 12.0, Font::Bold, "Árajánlat (Győr)");
+        pdf.new_page();
+        pdf.rule(40.0, 555.0, 100.0, 0.5);
+        let file = pdf.finish();
+        let text = String::from_utf8_lossy(&file);
+
+        assert!(text.starts_with("%PDF-1.4"));
+        assert!(text.contains("/Count 2"));
+        // Delimiters escaped, letters outside ASCII as octal: Á is 301, ő 215.
+        assert!(text.contains("(\\301raj\\341nlat \\(Gy\\215r\\)) Tj"));
+
+        // `startxref` points at the table, and every entry at its object.
+        let start: usize = text.rsplit("startxref\n").next().and_then(|tail| tail.lines().next()).and_then(|n| n.parse().ok()).expect("startxref");
+        assert!(file[start..].starts_with(b"xref\n"));
+        // Offsets are in bytes, so the table is read from the bytes, not from
+        // the lossy text with its widened binary marker.
+        let table = String::from_utf8_lossy(&file[start..]).to_string();
+        let entries: Vec<usize> = table.lines().skip(3).take_while(|line| line.ends_with(" n ")).map(|line| line[..10].parse().expect("offset")).collect();
+        assert_eq!(entries.len(), 9);
+        for (index, offset) in entries.into_iter().enumerate() {
+            assert!(file[offset..].starts_with(format!("{} 0 obj", index + 1).as_bytes()));
+        }
+    }
+
+    #[test]
+    fn text_is_measured_cut_and_wrapped_to_a_width() {
+        assert_eq!(width("100", 10.0, Font::Regular), 16.68);
+        assert!(width("Monitor", 10.0, Font::Bold) > width("Monitor", 10.0, Font::Regular));
+
+        let cut = fit("Samsung S27C390EAU 27 inch Full HD monitor", 9.0, Font::Regular, 80.0);
+        assert!(cut.ends_with('…') && width(&cut, 9.0, Font::Regular) <= 80.0);
+        assert_eq!(fit("Toll", 9.0, Font::Regular, 80.0), "Toll");
+
+        let lines = wrap("Prices are net of VAT and valid while stock lasts.", 9.0, Font::Regular, 100.0);
+        assert!(lines.len() > 1 && lines.iter().all(|line| width(line, 9.0, Font::Regular) <= 100.0));
+    }
+}
@@ -8,9 +8,10 @@
 //!
 //! The job runs with no user present, so it needs real authcodes at rest —
 //! hashing is not an option. `mcp_precache.toml` is therefore a secret-grade
-//! file: gitignored, written `0600`, and mounted like `soap.json` rather than
-//! baked into the image. Nothing in this module ever logs, returns or renders a
-//! full authcode; [`mask_authcode`] guards every path out.
+//! file: gitignored, written `0600`, sealed when a storage key is configured
+//! (`service/sealed.rs`), and mounted like `soap.json` rather than baked into
+//! the image. Nothing in this module ever logs, returns or renders a full
+//! authcode; [`mask_authcode`] guards every path out.
 //!
 //! ## Why runtime state is not persisted here
 //!
@@ -34,11 +35,13 @@ use crate::service::{
     log::{elogger, logger},
     mcp::{
         cache::{CacheKey, cache, fingerprint, hash_authcode},
-        index::{build_snapshot, refresh_snapshot},
         mask_authcode, store
     },
     path::get_current_or_root_dir,
-    soap_config::get_default_url
+    sealed,
+    soap_config::get_default_url,
+    soap_gate::{self, Lane},
+    state::{self, RunRecord}
 };
 
 /// Gap between entries in one sweep, so a refresh cycle does not fire every
@@ -107,7 +110,8 @@ pub struct PrecacheConfig {
 }
 
 
-/// What happened to one entry on its last run. Held in memory only.
+/// What happened to one entry on its last run. Held in memory only; with the
+/// SQLite backend every run is also appended to a history (`service/state`).
 #[derive(Debug, Clone, Default)]
 pub struct EntryRun {
     pub last_run: Option<DateTime<Utc>>,
@@ -117,6 +121,9 @@ pub struct EntryRun {
     pub running: bool
 }
 
+/// What `mcp_precache.toml` is sealed as — see `service/sealed`.
+pub const SEAL_CONTEXT: &str = "mcp_precache.toml";
+
 /// Runtime state per entry id.
 static RUNS: Lazy<Mutex<HashMap<String, EntryRun>>> = Lazy::new(|| Mutex::new(HashMap::new()));
 
@@ -133,34 +140,43 @@ pub fn get_precache_path() -> PathBuf {
 }
 
 
-/// Reads `mcp_precache.toml`, or an empty configuration when it is absent or
-/// unreadable. A missing file is normal — it means nothing is precached yet.
+/// The configured entries, or none when they cannot be read. From
+/// `mcp_precache.toml` or the database, whichever `[storage] backend` names —
+/// see `service/state`.
 pub fn load() -> PrecacheConfig {
+    state::store().load_precache().unwrap_or_else(|error| {
+        elogger(format!("MCP precache: {}", error));
+        PrecacheConfig::default()
+    })
+}
+
+
+/// Persists the entries to the configured backend.
+pub fn save(config: &PrecacheConfig) -> Result<(), String> {
+    state::store().save_precache(config)
+}
+
+
+/// Reads `mcp_precache.toml`. A missing file is normal — it means nothing is
+/// precached yet — and reads as no entries.
+pub fn read_file() -> Result<PrecacheConfig, String> {
     let path = get_precache_path();
     if !path.is_file() {
-        return PrecacheConfig::default()
-    }
-    match std::fs::read_to_string(&path) {
-        Ok(content) => match toml::from_str::<PrecacheConfig>(&content) {
-            Ok(config) => config,
-            Err(error) => {
-                elogger(format!("MCP precache: cannot parse '{:?}': {}", path, error));
-                PrecacheConfig::default()
-            }
-        },
-        Err(error) => {
-            elogger(format!("MCP precache: cannot read '{:?}': {}", path, error));
-            PrecacheConfig::default()
-        }
+        return Ok(PrecacheConfig::default())
     }
+    let content = sealed::read_to_string(&path, SEAL_CONTEXT)
+        .map_err(|error| format!("cannot read '{:?}': {}", path, error))?;
+    toml::from_str::<PrecacheConfig>(&content)
+        .map_err(|error| format!("cannot parse '{:?}': {}", path, error))
 }
 
 
-/// Writes `mcp_precache.toml` with owner-only permissions.
+/// Writes `mcp_precache.toml` with owner-only permissions, sealed when a
+/// storage key is configured.
 ///
 /// Written to a temporary file and renamed, so a crash mid-write cannot leave a
 /// half-written credential file behind.
-pub fn save(config: &PrecacheConfig) -> Result<(), String> {
+pub fn write_file(config: &PrecacheConfig) -> Result<(), String> {
     let path = get_precache_path();
     let body = toml::to_string_pretty(config).map_err(|error| error.to_string())?;
     let content = format!(
@@ -176,7 +192,7 @@ pub fn save(config: &PrecacheConfig) -> Result<(), String> {
     );
 
     let temporary = path.with_extension("toml.tmp");
-    std::fs::write(&temporary, content).map_err(|error| error.to_string())?;
+    std::fs::write(&temporary, sealed::seal(content.into_bytes(), SEAL_CONTEXT)?).map_err(|error| error.to_string())?;
     restrict_permissions(&temporary);
     std::fs::rename(&temporary, &path).map_err(|error| error.to_string())?;
     restrict_permissions(&path);
@@ -240,6 +256,16 @@ pub fn find(id: &str) -> Option<PrecacheEntry> {
 }
 
 
+/// The most recent runs of one entry, newest first. Empty with the TOML
+/// backend, which keeps no history.
+pub fn history(id: &str, limit: usize) -> Vec<RunRecord> {
+    state::store().recent_runs(id, limit).unwrap_or_else(|error| {
+        elogger(format!("MCP precache: cannot read the run history: {}", error));
+        Vec::new()
+    })
+}
+
+
 /// Runtime state per entry id, for the dashboard.
 pub fn runs() -> HashMap<String, EntryRun> {
     RUNS.lock()
@@ -248,13 +274,19 @@ pub fn runs() -> HashMap<String, EntryRun> {
 }
 
 
-/// Refreshes one entry now.
+/// Refreshes one entry now, in the background lane of the SOAP gate
+/// (`service/soap_gate`) so it never takes a slot reserved for live traffic.
 ///
 /// The replacement snapshot is built **before** anything is replaced, so the
 /// previous one stays queryable for the whole build: a colleague asking a
 /// question mid-refresh gets the slightly stale answer instantly rather than
 /// waiting out a cold fetch.
 pub async fn refresh(entry: &PrecacheEntry, force_full: bool) -> Result<(), String> {
+    soap_gate::in_lane(Lane::Background, refresh_entry(entry, force_full)).await
+}
+
+
+async fn refresh_entry(entry: &PrecacheEntry, force_full: bool) -> Result<(), String> {
     let Some(url) = entry.url() else {
         return Err("no Octopus url configured for this entry or in soap.json".into())
     };
@@ -293,16 +325,7 @@ pub async fn refresh(entry: &PrecacheEntry, force_full: bool) -> Result<(), Stri
     } else {
         match cache().peek(&key).await {
             Some(snapshot) => Some(snapshot),
-            None => {
-                let for_disk = key.clone();
-                match actix_web::web::block(move || store::read(&for_disk)).await {
-                    Ok(snapshot) => snapshot.map(Arc::new),
-                    Err(error) => {
-                        elogger(format!("MCP precache: disk read failed to run: {}", error));
-                        None
-                    }
-                }
-            }
+            None => cache().load(&key).await.map(Arc::new)
         }
     };
 
@@ -327,7 +350,7 @@ pub async fn refresh(entry: &PrecacheEntry, force_full: bool) -> Result<(), Stri
         && !needs_full
         && previous.age_secs() < interval {
             let age = previous.age_secs();
-            let products = previous.products.len();
+            let products = previous.product_count();
             // `promote`, not `insert`: this snapshot came off disk, so mirroring
             // it back would rewrite an identical file at real CPU cost.
             cache().promote(key, previous.clone()).await;
@@ -342,9 +365,9 @@ pub async fn refresh(entry: &PrecacheEntry, force_full: bool) -> Result<(), Stri
     let result = match (needs_full, existing) {
         (false, Some(previous)) => {
             let since = previous_run.last_run.unwrap_or(previous.fetched_at);
-            refresh_snapshot(&previous, &entry.authcode, entry.pid, &url, since).await
+            cache().fetch(&entry.authcode, entry.pid, &url, Some((&previous, since))).await
         }
-        _ => build_snapshot(&entry.authcode, entry.pid, &url, None).await
+        _ => cache().fetch(&entry.authcode, entry.pid, &url, None).await
     };
 
     let elapsed_ms = started.elapsed().as_millis() as u64;
@@ -352,7 +375,7 @@ pub async fn refresh(entry: &PrecacheEntry, force_full: bool) -> Result<(), Stri
     match result {
         Ok(snapshot) => {
             let bytes = snapshot.bytes;
-            let products = snapshot.products.len();
+            let products = snapshot.product_count();
             cache().insert(key, Arc::new(snapshot), elapsed_ms).await;
             record(&id, elapsed_ms, needs_full, Ok(()));
             logger(format!(
@@ -390,6 +413,20 @@ fn mark_running(id: &str, running: bool) {
 
 
 fn record(id: &str, elapsed_ms: u64, was_full: bool, outcome: Result<(), String>) {
+    let history = RunRecord {
+        entry_id: id.to_string(),
+        finished_at: Utc::now(),
+        duration_ms: elapsed_ms,
+        full_pull: was_full,
+        outcome: match &outcome {
+            Ok(()) => "ok".to_string(),
+            Err(message) => message.clone()
+        }
+    };
+    if let Err(error) = state::store().record_run(&history) {
+        elogger(format!("MCP precache: cannot record the run in the history: {}", error));
+    }
+
     if let Ok(mut runs) = RUNS.lock() {
         let run = runs.entry(id.to_string()).or_default();
         run.running = false;
@@ -413,9 +450,9 @@ fn record(id: &str, elapsed_ms: u64, was_full: bool, outcome: Result<(), String>
 /// outbound SOAP budget.
 ///
 /// Entries run one at a time. Combined with the [`STAGGER_SECS`] gap, a sweep
-/// uses at most the two concurrent calls one snapshot build issues, well inside
-/// the `soap_concurrency` gate that live API traffic shares — a sweep slows
-/// itself down rather than starving `/get-product`.
+/// uses at most the two concurrent calls one snapshot build issues, and those
+/// wait in the background lane — a sweep slows itself down rather than starving
+/// `/get-product` or an order.
 async fn sweep() {
     let entries: Vec<PrecacheEntry> = entries().into_iter().filter(|entry| entry.is_enabled()).collect();
     if entries.is_empty() {
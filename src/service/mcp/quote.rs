@@ -0,0 +1,878 @@
+//! Quotes: a partner's priced offer to their own customer, built from the
+//! catalog a line at a time and finished as a workbook and a PDF.
+//!
+//! A quote starts from what the partner pays — the snapshot's `price`, frozen
+//! when a line is added, so a price refresh mid-conversation does not quietly
+//! change a figure already read out — and adds the partner's own markup, per
+//! brand, per product group or across the board. The documents show only the
+//! customer's prices: the partner's cost and margin are in the tool's answer,
+//! for the salesperson, and never in a file meant to be forwarded.
+//!
+//! Quotes in progress live in memory, keyed by an id the tools hand back and
+//! owned by the authcode and partner id that created them, so one partner can
+//! never read another's. Nothing is persisted: a restart drops unfinished
+//! quotes, and a quote nobody touches for `quote_ttl_secs` is forgotten. The
+//! finished documents are exports and share their directory, guard and expiry.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use once_cell::sync::Lazy;
+
+use crate::{
+    macros::mcp::McpToolArgs,
+    service::{
+        blocklist::hash_hex,
+        config::get_mcp_settings,
+        mcp::{
+            availability::{CheckItem, MAX_ITEMS, Orderable, Purpose, orderable, round},
+            export::{self, Format, Prepared},
+            index::{CatalogSnapshot, fold},
+            pdf::{self, Font, Pdf}
+        }
+    }
+};
+
+/// Quotes one caller may hold at once. A salesperson juggles a handful; past
+/// this, something is creating them in a loop.
+const MAX_QUOTES_PER_OWNER: usize = 50;
+
+/// Longest validity a caller may ask for.
+const MAX_VALID_DAYS: u32 = 365;
+
+/// Markups below -100% would price a product below nothing.
+const MIN_MARKUP_PERCENT: f64 = -100.0;
+const MAX_MARKUP_PERCENT: f64 = 1000.0;
+
+
+McpToolArgs! {
+    #[derive(Clone)]
+    pub struct MarkupRule {
+        /// The brand this markup applies to.
+        pub brand: Option<String>,
+        /// The product group or main group it applies to, by code or name.
+        /// With neither `brand` nor `category` the markup applies to every
+        /// line no other rule covers.
+        pub category: Option<String>,
+        /// Percent added to this partner's price: `25` sells at 1.25 times
+        /// cost. Negative for a discount, `0` to sell at cost.
+        pub percent: f64
+    }
+}
+
+
+/// Who a quote belongs to: the authcode, by hash, and the partner id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Owner {
+    authcode: String,
+    pid: i64
+}
+
+impl Owner {
+    pub fn new(authcode: &str, pid: i64) -> Self {
+        Self { authcode: hash_hex(authcode), pid }
+    }
+}
+
+
+/// What a markup applies to, by folded brand or group.
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    Everything,
+    Brand(String),
+    Category(String)
+}
+
+#[derive(Debug, Clone)]
+struct Markup {
+    target: Target,
+    /// The brand or group as the caller named it, for display.
+    label: String,
+    percent: f64
+}
+
+impl Markup {
+    fn describe(&self) -> String {
+        match self.target {
+            Target::Everything => "everything else".to_string(),
+            Target::Brand(_) => format!("brand {}", self.label),
+            Target::Category(_) => format!("category {}", self.label)
+        }
+    }
+}
+
+
+/// One product on a quote, as it was when added.
+#[derive(Debug, Clone)]
+struct Line {
+    no: String,
+    name: String,
+    brand: Option<String>,
+    /// Folded group and main group codes and names, for markups.
+    categories: Vec<String>,
+    unit: Option<String>,
+    quantity: u32,
+    /// What the partner pays per unit.
+    cost: f64,
+    stock: Option<f64>
+}
+
+
+/// When, and until when, a quote was issued.
+#[derive(Debug, Clone, Copy)]
+struct Issued {
+    at: DateTime<Utc>,
+    valid_until: NaiveDate
+}
+
+
+/// A quote in progress, or finished and still open to changes.
+#[derive(Debug, Clone)]
+struct Quote {
+    id: String,
+    owner: Owner,
+    customer: Option<String>,
+    seller: Option<String>,
+    reference: Option<String>,
+    note: Option<String>,
+    /// Set by the first line; every line after must be priced in it.
+    currency: Option<String>,
+    lines: Vec<Line>,
+    markups: Vec<Markup>,
+    created: DateTime<Utc>,
+    touched: Instant,
+    issued: Option<Issued>
+}
+
+
+/// The free-text parts of a quote, as a tool sets them. `None` leaves a part
+/// as it is; an empty string clears it.
+#[derive(Debug, Clone, Default)]
+pub struct Details {
+    pub customer: Option<String>,
+    pub seller: Option<String>,
+    pub reference: Option<String>,
+    pub note: Option<String>
+}
+
+
+/// A change to a quote. Applied whole or not at all.
+#[derive(Debug, Clone, Default)]
+pub struct Changes {
+    pub details: Details,
+    /// Products to add; one already on the quote takes the new quantity.
+    pub add: Vec<CheckItem>,
+    /// Article numbers to take off.
+    pub remove: Vec<String>,
+    pub markups: Vec<MarkupRule>
+}
+
+
+/// One line as priced for the customer.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PricedLine {
+    pub position: usize,
+    pub no: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brand: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    pub quantity: u32,
+    /// What the partner pays per unit. Not on the documents.
+    pub cost: f64,
+    pub markup_percent: f64,
+    /// What the customer pays per unit, net of VAT.
+    pub unit_price: f64,
+    pub net: f64,
+    /// Stock when the line was added, as the customer is told it.
+    pub availability: String
+}
+
+
+/// The markups in force, most specific first.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MarkupView {
+    pub applies_to: String,
+    pub percent: f64
+}
+
+
+/// A whole quote, priced: what the tools return and what the documents print.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Priced {
+    pub quote_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub customer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seller: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    pub lines: Vec<PricedLine>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub markups: Vec<MarkupView>,
+    /// The customer's total, net of VAT.
+    pub net: f64,
+    pub vat_percent: f64,
+    pub vat: f64,
+    pub gross: f64,
+    /// What the lines cost the partner, and what the quote earns them.
+    pub cost: f64,
+    pub margin: f64,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issued_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<NaiveDate>
+}
+
+
+/// Quotes in progress, by id.
+static QUOTES: Lazy<Mutex<HashMap<String, Quote>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+
+/// Starts a quote and returns it priced. Lines and markups in `changes` are
+/// applied as by [`update`], and the quote is not created if any is refused.
+pub fn create(owner: &Owner, snapshot: &CatalogSnapshot, changes: &Changes) -> Result<Priced, String> {
+    sweep_expired();
+    let mut quote = Quote {
+        id: new_id(),
+        owner: owner.clone(),
+        customer: None,
+        seller: None,
+        reference: None,
+        note: None,
+        currency: None,
+        lines: Vec::new(),
+        markups: Vec::new(),
+        created: Utc::now(),
+        touched: Instant::now(),
+        issued: None
+    };
+    quote.apply(snapshot, changes)?;
+
+    let mut quotes = QUOTES.lock().map_err(|_| "the quote store is unavailable".to_string())?;
+    if quotes.values().filter(|held| held.owner == *owner).count() >= MAX_QUOTES_PER_OWNER {
+        return Err(format!(
+            "this connector already holds {} quotes; finish or let older ones expire first",
+            MAX_QUOTES_PER_OWNER
+        ))
+    }
+    let priced = quote.priced(vat_percent());
+    quotes.insert(quote.id.clone(), quote);
+    Ok(priced)
+}
+
+
+/// Applies `changes` to one of the owner's quotes and returns it priced. No
+/// changes at all just shows it.
+pub fn update(owner: &Owner, id: &str, snapshot: Option<&CatalogSnapshot>, changes: &Changes) -> Result<Priced, String> {
+    with_quote(owner, id, |quote| {
+        if !changes.add.is_empty() {
+            let Some(snapshot) = snapshot else {
+                return Err("adding lines needs the catalog".to_string())
+            };
+            quote.apply(snapshot, changes)?;
+        } else {
+            quote.apply_without_lines(changes)?;
+        }
+        Ok(quote.priced(vat_percent()))
+    })
+}
+
+
+/// Issues a quote: stamps it with today's date and a validity, and returns it
+/// priced, ready to be written out by [`write`].
+pub fn finalize(owner: &Owner, id: &str, valid_days: Option<u32>) -> Result<Priced, String> {
+    let days = match valid_days {
+        Some(0) => return Err("a quote must be valid for at least one day".into()),
+        Some(days) if days > MAX_VALID_DAYS => return Err(format!("a quote can be valid for at most {} days", MAX_VALID_DAYS)),
+        Some(days) => days,
+        None => get_mcp_settings().quote_valid_days()
+    };
+    with_quote(owner, id, |quote| {
+        if quote.lines.is_empty() {
+            return Err("the quote has no lines yet".into())
+        }
+        let at = Utc::now();
+        quote.issued = Some(Issued {
+            at,
+            valid_until: (at + chrono::Duration::days(i64::from(days))).date_naive()
+        });
+        Ok(quote.priced(vat_percent()))
+    })
+}
+
+
+/// Writes an issued quote as a workbook and a PDF, each behind its own
+/// download link. Blocking: run it through `web::block`.
+pub fn write(priced: &Priced) -> Result<(Prepared, Prepared), String> {
+    let stem = format!("quote-{}", priced.quote_id);
+    let workbook = export::deliver(&stem, Format::Xlsx.extension(), Format::Xlsx.content_type(), |path| write_xlsx(priced, path))?;
+    let document = render_pdf(priced);
+    let pdf = export::deliver(&stem, "pdf", "application/pdf", |path| {
+        std::fs::write(path, &document).map_err(|error| error.to_string())
+    })?;
+    Ok((workbook, pdf))
+}
+
+
+fn vat_percent() -> f64 {
+    get_mcp_settings().quote_vat_percent()
+}
+
+
+fn new_id() -> String {
+    let random = uuid::Uuid::new_v4().simple().to_string().to_uppercase();
+    format!("Q-{}", &random[..8])
+}
+
+
+/// Runs `change` on one of the owner's quotes. Another owner's quote reads as
+/// missing, so an id reveals nothing about whose it is.
+fn with_quote<T>(owner: &Owner, id: &str, change: impl FnOnce(&mut Quote) -> Result<T, String>) -> Result<T, String> {
+    sweep_expired();
+    let mut quotes = QUOTES.lock().map_err(|_| "the quote store is unavailable".to_string())?;
+    let quote = quotes.get_mut(id.trim())
+        .filter(|quote| quote.owner == *owner)
+        .ok_or_else(|| format!("no quote '{}' — it may have expired; start a new one with create_quote", id.trim()))?;
+    // Work on a copy, so a refused change leaves the quote exactly as it was.
+    let mut draft = quote.clone();
+    let result = change(&mut draft)?;
+    draft.touched = Instant::now();
+    *quote = draft;
+    Ok(result)
+}
+
+
+/// Forgets quotes nobody has touched within `quote_ttl_secs`.
+fn sweep_expired() {
+    let ttl = Duration::from_secs(get_mcp_settings().quote_ttl_secs());
+    if let Ok(mut quotes) = QUOTES.lock() {
+        quotes.retain(|_, quote| quote.touched.elapsed() < ttl);
+    }
+}
+
+
+impl Quote {
+    fn apply(&mut self, snapshot: &CatalogSnapshot, changes: &Changes) -> Result<(), String> {
+        self.apply_without_lines(changes)?;
+        for (index, item) in changes.add.iter().enumerate() {
+            self.add(snapshot, index, item)?;
+        }
+        if self.lines.len() > MAX_ITEMS {
+            return Err(format!("a quote holds at most {} lines", MAX_ITEMS))
+        }
+        Ok(())
+    }
+
+    fn apply_without_lines(&mut self, changes: &Changes) -> Result<(), String> {
+        let Details { customer, seller, reference, note } = &changes.details;
+        for (field, value) in [(&mut self.customer, customer), (&mut self.seller, seller), (&mut self.reference, reference), (&mut self.note, note)] {
+            if let Some(value) = value {
+                *field = Some(value.trim().to_string()).filter(|value| !value.is_empty());
+            }
+        }
+        for no in &changes.remove {
+            let before = self.lines.len();
+            self.lines.retain(|line| !line.no.eq_ignore_ascii_case(no.trim()));
+            if self.lines.len() == before {
+                return Err(format!("'{}' is not on the quote", no.trim()))
+            }
+        }
+        if self.lines.is_empty() {
+            self.currency = None;
+        }
+        for rule in &changes.markups {
+            self.set_markup(rule)?;
+        }
+        Ok(())
+    }
+
+    /// Adds one product, or sets the quantity of one already on the quote.
+    fn add(&mut self, snapshot: &CatalogSnapshot, index: usize, item: &CheckItem) -> Result<(), String> {
+        let held = (!self.lines.is_empty()).then_some(&self.currency);
+        let Orderable { view, quantity, price: cost } = orderable(snapshot, index, item, Purpose::Quote, held)?;
+        let (product, offer) = (&view.product, view.offer);
+        self.currency = offer.currency.clone();
+
+        if let Some(line) = self.lines.iter_mut().find(|line| line.no == product.no) {
+            line.quantity = quantity;
+            return Ok(())
+        }
+        let categories = [&product.category_code, &product.category_name, &product.main_category_code, &product.main_category_name]
+            .into_iter()
+            .flatten()
+            .map(|value| fold(value))
+            .collect();
+        self.lines.push(Line {
+            no: product.no.clone(),
+            name: product.name.clone(),
+            brand: product.brand.clone(),
+            categories,
+            unit: product.unit.clone(),
+            quantity,
+            cost,
+            stock: offer.stock
+        });
+        Ok(())
+    }
+
+    /// Sets a markup, replacing any earlier one for the same brand or group.
+    fn set_markup(&mut self, rule: &MarkupRule) -> Result<(), String> {
+        if !rule.percent.is_finite() || !(MIN_MARKUP_PERCENT..=MAX_MARKUP_PERCENT).contains(&rule.percent) {
+            return Err(format!("a markup must be between {}% and {}%", MIN_MARKUP_PERCENT, MAX_MARKUP_PERCENT))
+        }
+        let named = |value: &Option<String>| value.as_deref().map(str::trim).filter(|value| !value.is_empty()).map(str::to_string);
+        let (target, label) = match (named(&rule.brand), named(&rule.category)) {
+            (Some(_), Some(_)) => return Err("a markup names a brand or a category, not both".into()),
+            (Some(brand), None) => (Target::Brand(fold(&brand)), brand),
+            (None, Some(category)) => (Target::Category(fold(&category)), category),
+            (None, None) => (Target::Everything, String::new())
+        };
+        self.markups.retain(|markup| markup.target != target);
+        self.markups.push(Markup { target, label, percent: rule.percent });
+        Ok(())
+    }
+
+    /// The markup a line takes: its brand's, else its group's, else the
+    /// quote-wide one, else none.
+    fn markup_for(&self, line: &Line) -> f64 {
+        let brand = line.brand.as_deref().map(fold);
+        let by_brand = self.markups.iter().find(|markup| matches!(&markup.target, Target::Brand(name) if Some(name) == brand.as_ref()));
+        let by_category = || self.markups.iter().find(|markup| matches!(&markup.target, Target::Category(name) if line.categories.contains(name)));
+        let everything = || self.markups.iter().find(|markup| markup.target == Target::Everything);
+        by_brand.or_else(by_category).or_else(everything).map_or(0.0, |markup| markup.percent)
+    }
+
+    fn priced(&self, vat_percent: f64) -> Priced {
+        let lines: Vec<PricedLine> = self.lines.iter().enumerate()
+            .map(|(index, line)| {
+                let markup_percent = self.markup_for(line);
+                let unit_price = round(line.cost * (1.0 + markup_percent / 100.0));
+                PricedLine {
+                    position: index + 1,
+                    no: line.no.clone(),
+                    name: line.name.clone(),
+                    brand: line.brand.clone(),
+                    unit: line.unit.clone(),
+                    quantity: line.quantity,
+                    cost: line.cost,
+                    markup_percent,
+                    unit_price,
+                    net: round(unit_price * f64::from(line.quantity)),
+                    availability: availability(line.stock, line.quantity)
+                }
+            })
+            .collect();
+
+        let net = round(lines.iter().map(|line| line.net).sum());
+        let cost = round(self.lines.iter().map(|line| line.cost * f64::from(line.quantity)).sum());
+        let vat = round(net * vat_percent / 100.0);
+
+        // Most specific first, the order they are applied in.
+        let mut markups = self.markups.clone();
+        markups.sort_by_key(|markup| match markup.target {
+            Target::Brand(_) => 0,
+            Target::Category(_) => 1,
+            Target::Everything => 2
+        });
+
+        Priced {
+            quote_id: self.id.clone(),
+            customer: self.customer.clone(),
+            seller: self.seller.clone(),
+            reference: self.reference.clone(),
+            note: self.note.clone(),
+            currency: self.currency.clone(),
+            lines,
+            markups: markups.iter().map(|markup| MarkupView { applies_to: markup.describe(), percent: markup.percent }).collect(),
+            net,
+            vat_percent,
+            vat,
+            gross: round(net + vat),
+            cost,
+            margin: round(net - cost),
+            created_at: self.created,
+            issued_at: self.issued.map(|issued| issued.at),
+            valid_until: self.issued.map(|issued| issued.valid_until)
+        }
+    }
+}
+
+
+/// Stock as the customer is told it: enough, some, or to be ordered in.
+fn availability(stock: Option<f64>, quantity: u32) -> String {
+    match stock.unwrap_or(0.0) {
+        stock if stock >= f64::from(quantity) => "In stock".to_string(),
+        stock if stock > 0.0 => format!("{} in stock", stock),
+        _ => "On order".to_string()
+    }
+}
+
+
+/// `1234567.5` as `1,234,567.50`.
+fn money(value: f64) -> String {
+    let cents = (value.abs() * 100.0).round() as u64;
+    let whole = (cents / 100).to_string();
+    let mut grouped = String::new();
+    for (index, digit) in whole.chars().enumerate() {
+        if index > 0 && (whole.len() - index).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    format!("{}{}.{:02}", if value < 0.0 && cents > 0 { "-" } else { "" }, grouped, cents % 100)
+}
+
+
+/// The labelled particulars at the top of both documents.
+fn particulars(priced: &Priced) -> Vec<(&'static str, String)> {
+    let mut rows = vec![("Quote no.", priced.quote_id.clone())];
+    if let Some(issued) = priced.issued_at {
+        rows.push(("Date", issued.date_naive().to_string()));
+    }
+    if let Some(valid_until) = priced.valid_until {
+        rows.push(("Valid until", valid_until.to_string()));
+    }
+    if let Some(customer) = &priced.customer {
+        rows.push(("Customer", customer.clone()));
+    }
+    if let Some(reference) = &priced.reference {
+        rows.push(("Reference", reference.clone()));
+    }
+    rows
+}
+
+
+fn currency_suffix(priced: &Priced) -> String {
+    priced.currency.as_ref().map(|currency| format!(" ({})", currency)).unwrap_or_default()
+}
+
+
+fn write_xlsx(priced: &Priced, path: &PathBuf) -> Result<(), String> {
+    use rust_xlsxwriter::{Format as XlsxFormat, Workbook};
+
+    let title = XlsxFormat::new().set_bold().set_font_size(16);
+    let bold = XlsxFormat::new().set_bold();
+    let header = XlsxFormat::new().set_bold().set_background_color("#EBEBEB").set_border_bottom(rust_xlsxwriter::FormatBorder::Thin);
+    let amount = XlsxFormat::new().set_num_format("#,##0.00");
+    let total = XlsxFormat::new().set_bold().set_num_format("#,##0.00");
+
+    let mut workbook = Workbook::new();
+    let sheet = workbook.add_worksheet();
+    sheet.set_name("Quote").map_err(|error| error.to_string())?;
+    let fail = |error: rust_xlsxwriter::XlsxError| error.to_string();
+
+    sheet.write_string_with_format(0, 0, "Quote", &title).map_err(fail)?;
+    if let Some(seller) = &priced.seller {
+        sheet.write_string_with_format(0, 2, seller, &bold).map_err(fail)?;
+    }
+    let mut row = 2;
+    for (label, value) in particulars(priced) {
+        sheet.write_string_with_format(row, 0, label, &bold).map_err(fail)?;
+        sheet.write_string(row, 2, &value).map_err(fail)?;
+        row += 1;
+    }
+
+    row += 1;
+    let suffix = currency_suffix(priced);
+    let headers = [
+        "#".to_string(), "Article number".into(), "Name".into(), "Brand".into(), "Quantity".into(), "Unit".into(),
+        format!("Unit price{}", suffix), format!("Net{}", suffix), "Availability".into()
+    ];
+    for (column, text) in headers.iter().enumerate() {
+        sheet.write_string_with_format(row, column as u16, text, &header).map_err(fail)?;
+    }
+    sheet.set_freeze_panes(row + 1, 0).map_err(fail)?;
+
+    for line in &priced.lines {
+        row += 1;
+        sheet.write_number(row, 0, line.position as f64).map_err(fail)?;
+        sheet.write_string(row, 1, &line.no).map_err(fail)?;
+        sheet.write_string(row, 2, &line.name).map_err(fail)?;
+        sheet.write_string(row, 3, line.brand.as_deref().unwrap_or_default()).map_err(fail)?;
+        sheet.write_number(row, 4, f64::from(line.quantity)).map_err(fail)?;
+        sheet.write_string(row, 5, line.unit.as_deref().unwrap_or_default()).map_err(fail)?;
+        sheet.write_number_with_format(row, 6, line.unit_price, &amount).map_err(fail)?;
+        sheet.write_number_with_format(row, 7, line.net, &amount).map_err(fail)?;
+        sheet.write_string(row, 8, &line.availability).map_err(fail)?;
+    }
+
+    row += 2;
+    let vat_label = format!("VAT {}%", priced.vat_percent);
+    for (label, value) in [("Net total", priced.net), (vat_label.as_str(), priced.vat), ("Total incl. VAT", priced.gross)] {
+        sheet.write_string_with_format(row, 6, label, &bold).map_err(fail)?;
+        sheet.write_number_with_format(row, 7, value, &total).map_err(fail)?;
+        row += 1;
+    }
+    if let Some(note) = &priced.note {
+        sheet.write_string(row + 1, 0, note).map_err(fail)?;
+    }
+
+    for (column, width) in [(0, 5.0), (1, 16.0), (2, 48.0), (3, 14.0), (4, 9.0), (5, 8.0), (6, 16.0), (7, 16.0), (8, 14.0)] {
+        sheet.set_column_width(column, width).map_err(fail)?;
+    }
+    workbook.save(path).map_err(fail)
+}
+
+
+/// Page geometry for the PDF, in points from the top left.
+const MARGIN: f64 = 40.0;
+const ROW_HEIGHT: f64 = 15.0;
+const BODY_SIZE: f64 = 9.0;
+const TABLE_BOTTOM: f64 = 780.0;
+/// Where the table starts on the first page, below the particulars, and on
+/// every page after.
+const FIRST_TABLE_TOP: f64 = 200.0;
+const NEXT_TABLE_TOP: f64 = 60.0;
+
+/// Right edges of the figure columns, left edges of the rest.
+const COLUMN_POSITION: f64 = MARGIN;
+const COLUMN_NO: f64 = 62.0;
+const COLUMN_NAME: f64 = 150.0;
+const COLUMN_QUANTITY: f64 = 392.0;
+const COLUMN_UNIT: f64 = 398.0;
+const COLUMN_UNIT_PRICE: f64 = 482.0;
+const COLUMN_NET: f64 = PAGE_RIGHT;
+const PAGE_RIGHT: f64 = pdf::PAGE_WIDTH - MARGIN;
+
+
+fn render_pdf(priced: &Priced) -> Vec<u8> {
+    let first_rows = ((TABLE_BOTTOM - FIRST_TABLE_TOP) / ROW_HEIGHT) as usize - 1;
+    let next_rows = ((TABLE_BOTTOM - NEXT_TABLE_TOP) / ROW_HEIGHT) as usize - 1;
+    let notes = priced.note.as_deref()
+        .map(|note| pdf::wrap(note, BODY_SIZE, Font::Regular, PAGE_RIGHT - MARGIN))
+        .unwrap_or_default();
+    let totals_height = ROW_HEIGHT * (4.0 + notes.len() as f64);
+
+    // Lines per page, with one more page when the totals do not fit under
+    // the last of them.
+    let mut pages: Vec<&[PricedLine]> = Vec::new();
+    let mut rest = priced.lines.as_slice();
+    loop {
+        let room = if pages.is_empty() { first_rows } else { next_rows };
+        let (page, after) = rest.split_at(room.min(rest.len()));
+        pages.push(page);
+        rest = after;
+        if rest.is_empty() {
+            break
+        }
+    }
+    let last_top = if pages.len() == 1 { FIRST_TABLE_TOP } else { NEXT_TABLE_TOP };
+    let totals_fit = last_top + ROW_HEIGHT * (pages.last().map_or(0, |page| page.len()) + 2) as f64 + totals_height <= TABLE_BOTTOM;
+    let page_count = pages.len() + usize::from(!totals_fit);
+
+    let mut document = Pdf::new();
+    let mut y = 0.0;
+    for (index, lines) in pages.iter().enumerate() {
+        if index == 0 {
+            heading(&mut document, priced);
+            y = FIRST_TABLE_TOP;
+        } else {
+            document.new_page();
+            y = NEXT_TABLE_TOP;
+        }
+        footer(&mut document, index + 1, page_count);
+        y = table(&mut document, priced, lines, y);
+    }
+    if !totals_fit {
+        document.new_page();
+        footer(&mut document, page_count, page_count);
+        y = NEXT_TABLE_TOP;
+    }
+    totals(&mut document, priced, &notes, y + ROW_HEIGHT);
+    document.finish()
+}
+
+
+fn heading(document: &mut Pdf, priced: &Priced) {
+    document.text(MARGIN, 64.0, 22.0, Font::Bold, "Quote");
+    if let Some(seller) = &priced.seller {
+        document.text_right(PAGE_RIGHT, 64.0, 12.0, Font::Bold, &pdf::fit(seller, 12.0, Font::Bold, 300.0));
+    }
+    document.rule(MARGIN, PAGE_RIGHT, 76.0, 0.8);
+    let mut y = 100.0;
+    for (label, value) in particulars(priced) {
+        document.text(MARGIN, y, 10.0, Font::Bold, label);
+        document.text(130.0, y, 10.0, Font::Regular, &pdf::fit(&value, 10.0, Font::Regular, PAGE_RIGHT - 130.0));
+        y += 15.0;
+    }
+}
+
+
+fn footer(document: &mut Pdf, page: usize, pages: usize) {
+    document.rule(MARGIN, PAGE_RIGHT, 800.0, 0.4);
+    document.text(MARGIN, 814.0, 8.0, Font::Regular, "Prices are net of VAT unless stated. Availability as at the date of the quote.");
+    document.text_right(PAGE_RIGHT, 814.0, 8.0, Font::Regular, &format!("Page {} of {}", page, pages));
+}
+
+
+/// Draws the table header and `lines` from `top`, and returns where it ended.
+fn table(document: &mut Pdf, priced: &Priced, lines: &[PricedLine], top: f64) -> f64 {
+    let baseline = |y: f64| y + ROW_HEIGHT - 4.0;
+    document.shade(MARGIN, top, PAGE_RIGHT - MARGIN, ROW_HEIGHT);
+    let suffix = currency_suffix(priced);
+    document.text(COLUMN_POSITION + 2.0, baseline(top), BODY_SIZE, Font::Bold, "#");
+    document.text(COLUMN_NO, baseline(top), BODY_SIZE, Font::Bold, "Article no.");
+    document.text(COLUMN_NAME, baseline(top), BODY_SIZE, Font::Bold, "Name");
+    document.text_right(COLUMN_QUANTITY, baseline(top), BODY_SIZE, Font::Bold, "Qty");
+    document.text(COLUMN_UNIT, baseline(top), BODY_SIZE, Font::Bold, "Unit");
+    document.text_right(COLUMN_UNIT_PRICE, baseline(top), BODY_SIZE, Font::Bold, &format!("Unit price{}", suffix));
+    document.text_right(COLUMN_NET, baseline(top), BODY_SIZE, Font::Bold, &format!("Net{}", suffix));
+
+    let mut y = top + ROW_HEIGHT;
+    for line in lines {
+        document.text(COLUMN_POSITION + 2.0, baseline(y), BODY_SIZE, Font::Regular, &line.position.to_string());
+        document.text(COLUMN_NO, baseline(y), BODY_SIZE, Font::Regular, &pdf::fit(&line.no, BODY_SIZE, Font::Regular, COLUMN_NAME - COLUMN_NO - 6.0));
+        document.text(COLUMN_NAME, baseline(y), BODY_SIZE, Font::Regular, &pdf::fit(&line.name, BODY_SIZE, Font::Regular, COLUMN_QUANTITY - COLUMN_NAME - 30.0));
+        document.text_right(COLUMN_QUANTITY, baseline(y), BODY_SIZE, Font::Regular, &line.quantity.to_string());
+        document.text(COLUMN_UNIT, baseline(y), BODY_SIZE, Font::Regular, &pdf::fit(line.unit.as_deref().unwrap_or_default(), BODY_SIZE, Font::Regular, 28.0));
+        document.text_right(COLUMN_UNIT_PRICE, baseline(y), BODY_SIZE, Font::Regular, &money(line.unit_price));
+        document.text_right(COLUMN_NET, baseline(y), BODY_SIZE, Font::Regular, &money(line.net));
+        document.rule(MARGIN, PAGE_RIGHT, y + ROW_HEIGHT, 0.2);
+        y += ROW_HEIGHT;
+    }
+    y
+}
+
+
+fn totals(document: &mut Pdf, priced: &Priced, notes: &[String], top: f64) {
+    let currency = priced.currency.as_deref().map(|currency| format!(" {}", currency)).unwrap_or_default();
+    let vat_label = format!("VAT {}%", priced.vat_percent);
+    let rows = [("Net total", priced.net, Font::Regular), (vat_label.as_str(), priced.vat, Font::Regular), ("Total incl. VAT", priced.gross, Font::Bold)];
+    let mut y = top;
+    for (label, value, font) in rows {
+        document.text_right(COLUMN_UNIT_PRICE, y, 10.0, font, label);
+        document.text_right(COLUMN_NET, y, 10.0, font, &format!("{}{}", money(value), currency));
+        y += ROW_HEIGHT;
+    }
+    y += ROW_HEIGHT / 2.0;
+    for line in notes {
+        document.text(MARGIN, y, BODY_SIZE, Font::Regular, line);
+        y += ROW_HEIGHT * 0.8;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::mcp::availability::test_item;
+    use crate::service::mcp::index::{Offer, test_product, test_snapshot_of};
+
+    fn rule(brand: Option<&str>, category: Option<&str>, percent: f64) -> MarkupRule {
+        MarkupRule { brand: brand.map(str::to_string), category: category.map(str::to_string), percent }
+    }
+
+    fn snapshot() -> CatalogSnapshot {
+        let mut pen = test_product("T-1", "Toll", "Pax", "");
+        pen.category_name = Some("Írószer".into());
+        let mut ink = test_product("I-1", "Tinta", "Canon", "");
+        ink.category_name = Some("Írószer".into());
+        let offer = |price: f64, currency: &str| Offer { available: true, price: Some(price), currency: Some(currency.into()), stock: Some(5.0) };
+        test_snapshot_of(vec![
+            (pen, offer(100.0, "HUF")),
+            (ink, offer(2000.0, "HUF")),
+            (test_product("P-1", "Papír", "Pax", ""), offer(1000.0, "HUF")),
+            (test_product("E-1", "Euro", "Pax", ""), offer(3.0, "EUR"))
+        ])
+    }
+
+    #[test]
+    fn markups_go_brand_then_category_then_everything() {
+        let snapshot = snapshot();
+        let owner = Owner::new("FFD3ABCDEF120E37", 7);
+        let quote = create(&owner, &snapshot, &Changes {
+            details: Details { customer: Some("Kovács Kft.".into()), ..Details::default() },
+            add: vec![test_item("T-1", 10), test_item("I-1", 1), test_item("P-1", 2)],
+            ..Changes::default()
+        }).expect("created");
+
+        let changes = Changes {
+            markups: vec![rule(None, None, 10.0), rule(None, Some("irószer"), 20.0), rule(Some("canon"), None, 50.0)],
+            ..Changes::default()
+        };
+        let priced = update(&owner, &quote.quote_id, None, &changes).expect("marked up");
+        let unit_prices: Vec<f64> = priced.lines.iter().map(|line| line.unit_price).collect();
+        assert_eq!(unit_prices, [120.0, 3000.0, 1100.0]);
+        assert_eq!((priced.net, priced.cost, priced.margin), (6400.0, 5000.0, 1400.0));
+        assert_eq!(priced.vat, round(6400.0 * priced.vat_percent / 100.0));
+        assert_eq!(priced.markups.first().map(|markup| markup.applies_to.as_str()), Some("brand canon"));
+
+        // Adding a product already on the quote sets its quantity; removing
+        // takes it off; a refused change leaves the quote as it was.
+        let priced = update(&owner, &quote.quote_id, Some(&snapshot), &Changes { add: vec![test_item("T-1", 1)], remove: vec!["P-1".into()], ..Changes::default() }).expect("changed");
+        assert_eq!(priced.lines.iter().map(|line| (line.no.as_str(), line.quantity)).collect::<Vec<_>>(), [("T-1", 1), ("I-1", 1)]);
+        assert!(update(&owner, &quote.quote_id, Some(&snapshot), &Changes { add: vec![test_item("E-1", 1)], ..Changes::default() }).is_err());
+        assert!(update(&owner, &quote.quote_id, Some(&snapshot), &Changes { add: vec![test_item("X-9", 1)], remove: vec!["T-1".into()], ..Changes::default() }).is_err());
+        assert_eq!(update(&owner, &quote.quote_id, None, &Changes::default()).expect("shown").lines.len(), 2);
+    }
+
+    #[test]
+    fn a_quote_is_only_its_owners_and_is_issued_with_a_validity() {
+        let snapshot = snapshot();
+        let owner = Owner::new("FFD3ABCDEF120E37", 7);
+        let quote = create(&owner, &snapshot, &Changes::default()).expect("created");
+
+        assert!(update(&Owner::new("FFD3ABCDEF120E37", 8), &quote.quote_id, None, &Changes::default()).is_err());
+        assert!(finalize(&owner, &quote.quote_id, None).is_err(), "an empty quote is not issued");
+
+        update(&owner, &quote.quote_id, Some(&snapshot), &Changes { add: vec![test_item("T-1", 3)], ..Changes::default() }).expect("added");
+        let issued = finalize(&owner, &quote.quote_id, Some(14)).expect("issued");
+        let at = issued.issued_at.expect("dated");
+        assert_eq!(issued.valid_until, Some((at + chrono::Duration::days(14)).date_naive()));
+        assert!(finalize(&owner, &quote.quote_id, Some(0)).is_err());
+
+        let document = render_pdf(&issued);
+        assert!(document.starts_with(b"%PDF-1.4"));
+        assert!(String::from_utf8_lossy(&document).contains("(Page 1 of 1) Tj"));
+    }
+
+    #[test]
+    fn a_long_quote_runs_over_several_pages() {
+        let line = |position: usize| PricedLine {
+            position,
+            no: format!("A-{}", position),
+            name: "Szövegkiemelő".into(),
+            brand: None,
+            unit: Some("db".into()),
+            quantity: 1,
+            cost: 10.0,
+            markup_percent: 0.0,
+            unit_price: 10.0,
+            net: 10.0,
+            availability: "In stock".into()
+        };
+        let priced = Priced {
+            quote_id: "Q-TEST".into(),
+            customer: None,
+            seller: None,
+            reference: None,
+            note: Some("Delivery within three working days.".into()),
+            currency: Some("HUF".into()),
+            lines: (1..=80).map(line).collect(),
+            markups: Vec::new(),
+            net: 800.0,
+            vat_percent: 27.0,
+            vat: 216.0,
+            gross: 1016.0,
+            cost: 800.0,
+            margin: 0.0,
+            created_at: Utc::now(),
+            issued_at: None,
+            valid_until: None
+        };
+        let text = String::from_utf8_lossy(&render_pdf(&priced)).to_string();
+        assert!(text.contains("/Count 3") && text.contains("(Page 3 of 3) Tj"));
+        assert_eq!(money(1234567.5), "1,234,567.50");
+        assert_eq!(money(-0.5), "-0.50");
+    }
+}
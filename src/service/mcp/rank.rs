@@ -0,0 +1,725 @@
+//! Relevance ranking and highlighting for catalog search.
+//!
+//! The first ranking was a tier list ported from the TypeScript prototype: each
+//! query term had to occur verbatim somewhere, and the kind of hit decided a
+//! fixed weight. It answered article-number lookups well and everything typed
+//! by a person badly — `samsnug monitr` found nothing, and a product naming the
+//! term once in a long description ranked like one named after it.
+//!
+//! This is a BM25F-style score instead:
+//!
+//! * every query term is matched per field, as an exact word, a word prefix,
+//!   an infix or — for words, not codes — a near spelling;
+//! * a field's hit is weighted by its boost (article number above barcode above
+//!   manufacturer code above name above brand above category above
+//!   description) and normalized by how long that field is against the
+//!   average, so a hit in a short name counts for more than one in a long name;
+//! * the per-term weight saturates (`K1`) and is scaled by how rare the term is
+//!   among the products searched (`idf`), so `samsung` in a Samsung-heavy
+//!   category decides less than the model number next to it;
+//! * an exact hit on a code earns a bonus outside the saturation, which keeps
+//!   the old guarantee that pasting an article number brings that product back
+//!   first.
+//!
+//! Every term still has to match somewhere. Typo tolerance and the operator's
+//! synonyms (`service/mcp/synonyms`) widen what counts as a match — `egér`
+//! also finds `mouse` — but they do not turn search into "any of these words".
+//! A quoted phrase (`service/mcp/syntax`) is matched as written, with neither,
+//! and a negated word drops every product it starts a word of.
+//!
+//! Near spellings are judged by the overlap of padded character bigrams, only
+//! for alphabetic terms of four letters or more and only against words that
+//! start with the same letter: codes are never fuzzy, because a code one digit
+//! off is a different product, and `did_you_mean` already covers that case.
+
+use std::ops::Range;
+
+use crate::service::mcp::{
+    index::{Haystack, IndexedProduct, fold},
+    synonyms::Dictionary,
+    syntax::Parsed
+};
+
+/// Characters that join a word rather than end it, so `ABC-1`, `2.5` and
+/// `A4/5` stay one word — article numbers are written that way. Trimmed where
+/// they lead or trail.
+const JOINERS: &[char] = &['-', '.', '/'];
+
+/// Match quality per kind of hit, before field boosts.
+const EXACT: f64 = 1.0;
+const PREFIX: f64 = 0.75;
+const INFIX: f64 = 0.5;
+/// Scaled by the similarity, so a near spelling stays below an infix.
+const FUZZY: f64 = 0.45;
+/// Scales a synonym's hits, so among equals the product written the way the
+/// caller wrote it comes first.
+const SYNONYM: f64 = 0.9;
+
+/// Shortest term matched inside a word. Below this an infix matches nearly
+/// everything: `a` is in half the catalog.
+const MIN_INFIX: usize = 3;
+/// Shortest term, and word, compared by spelling.
+const MIN_FUZZY: usize = 4;
+/// Bigram overlap (Dice) a word needs to count as a near spelling.
+const MIN_RESEMBLANCE: f64 = 0.6;
+/// How many characters a near spelling may be longer or shorter.
+const MAX_LENGTH_DRIFT: usize = 2;
+
+/// BM25 saturation and length normalization, at their textbook defaults.
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+/// Wraps a matched fragment in [`Highlight`].
+const MARK: &str = "**";
+/// Characters of description kept around the first hit in a snippet.
+const SNIPPET_BEFORE: usize = 60;
+const SNIPPET_AFTER: usize = 100;
+
+
+/// A ranked field of a [`Haystack`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    No,
+    Barcode,
+    Oem,
+    Name,
+    Brand,
+    Category,
+    MainCategory,
+    Description
+}
+
+impl Field {
+    const ALL: [Field; 8] = [
+        Field::No, Field::Barcode, Field::Oem, Field::Name,
+        Field::Brand, Field::Category, Field::MainCategory, Field::Description
+    ];
+
+    fn boost(self) -> f64 {
+        match self {
+            Field::No => 10.0,
+            Field::Barcode => 8.0,
+            Field::Oem => 6.0,
+            Field::Name => 4.0,
+            Field::Brand => 2.0,
+            Field::Category | Field::MainCategory => 1.5,
+            Field::Description => 1.0
+        }
+    }
+
+    /// Added outside the saturation for an exact hit, so a whole code typed
+    /// back always wins over the same string in someone's name.
+    fn exact_bonus(self) -> f64 {
+        match self {
+            Field::No => 3.0,
+            Field::Barcode => 2.5,
+            Field::Oem => 2.0,
+            _ => 0.0
+        }
+    }
+
+    /// Codes are matched whole and not length-normalized.
+    fn is_code(self) -> bool {
+        matches!(self, Field::No | Field::Barcode | Field::Oem)
+    }
+
+    /// Fields short enough to compare word by word for near spellings. The
+    /// description is not one of them: it is long, and a loose hit there is
+    /// noise more often than intent.
+    fn tolerates_typos(self) -> bool {
+        matches!(self, Field::Name | Field::Brand | Field::Category | Field::MainCategory)
+    }
+
+    fn text<'a>(self, haystack: &Haystack<'a>) -> &'a str {
+        match self {
+            Field::No => haystack.sku,
+            Field::Barcode => haystack.barcodes,
+            Field::Oem => haystack.oem,
+            Field::Name => haystack.name,
+            Field::Brand => haystack.brand,
+            Field::Category => haystack.category,
+            Field::MainCategory => haystack.main_category,
+            Field::Description => haystack.description
+        }
+    }
+}
+
+
+/// The words of a text, with the byte offset each starts at.
+pub fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.split(|c: char| !(c.is_alphanumeric() || JOINERS.contains(&c)))
+        .map(|piece| piece.trim_matches(JOINERS))
+        .filter(|word| !word.is_empty())
+        // Every word is a slice of `text`, so its offset is the pointer gap.
+        .map(move |word| (word.as_ptr() as usize - text.as_ptr() as usize, word))
+}
+
+/// A folded text's distinct words, space-separated, in first-seen order.
+pub fn distinct_words(folded: &str) -> String {
+    let mut seen: Vec<&str> = Vec::new();
+    for (_, word) in words(folded) {
+        if !seen.contains(&word) {
+            seen.push(word);
+        }
+    }
+    seen.join(" ")
+}
+
+
+/// One folded query word.
+#[derive(Debug, Clone, PartialEq)]
+struct Term {
+    text: String,
+    /// Long and alphabetic enough to be compared by spelling.
+    fuzzy: bool
+}
+
+impl Term {
+    fn new(text: &str) -> Self {
+        let fuzzy = text.chars().count() >= MIN_FUZZY && text.chars().all(char::is_alphabetic);
+        Self { text: text.to_string(), fuzzy }
+    }
+
+    /// A term matched as written, never by spelling.
+    fn exact(text: &str) -> Self {
+        Self { text: text.to_string(), fuzzy: false }
+    }
+
+    /// How, and where in `word`, this term hits it.
+    fn matches(&self, word: &str) -> Option<(f64, Range<usize>)> {
+        let term = self.text.as_str();
+        if word == term {
+            Some((EXACT, 0..word.len()))
+        } else if word.starts_with(term) {
+            Some((PREFIX, 0..term.len()))
+        } else if term.chars().count() >= MIN_INFIX {
+            word.find(term).map(|at| (INFIX, at..at + term.len()))
+        } else {
+            None
+        }
+    }
+
+    /// The quality of `word` as a near spelling of this term.
+    fn resembles(&self, word: &str) -> Option<f64> {
+        if !self.fuzzy {
+            return None
+        }
+        let (term_len, word_len) = (self.text.chars().count(), word.chars().count());
+        if word_len < MIN_FUZZY
+            || term_len.abs_diff(word_len) > MAX_LENGTH_DRIFT
+            || self.text.chars().next() != word.chars().next()
+        {
+            return None
+        }
+        let similarity = dice(&self.text, word);
+        (similarity >= MIN_RESEMBLANCE).then_some(FUZZY * similarity)
+    }
+
+    /// The best hit of this term on a code.
+    fn on_code(&self, code: &str) -> f64 {
+        self.matches(code).map_or(0.0, |(quality, _)| quality)
+    }
+
+    /// The best hit of this term on any word of a text field.
+    fn on_text(&self, text: &str, typos: bool) -> f64 {
+        // `contains` first: most products miss most terms, and it is far
+        // cheaper than splitting the field into words.
+        if text.contains(self.text.as_str()) {
+            let best = words(text)
+                .filter_map(|(_, word)| self.matches(word))
+                .map(|(quality, _)| quality)
+                .fold(0.0, f64::max);
+            if best > 0.0 {
+                return best
+            }
+        }
+        if typos && self.fuzzy {
+            return words(text).filter_map(|(_, word)| self.resembles(word)).fold(0.0, f64::max)
+        }
+        0.0
+    }
+
+    fn on(&self, field: Field, haystack: &Haystack<'_>) -> f64 {
+        match field {
+            Field::Barcode => haystack.barcodes().map(|code| self.on_code(code)).fold(0.0, f64::max),
+            Field::No | Field::Oem => self.on_code(field.text(haystack)),
+            _ => self.on_text(field.text(haystack), field.tolerates_typos())
+        }
+    }
+}
+
+/// Padded character bigrams, so the first and last letters count too.
+fn bigrams(text: &str) -> Vec<(char, char)> {
+    let padded: Vec<char> = std::iter::once(' ').chain(text.chars()).chain(std::iter::once(' ')).collect();
+    padded.windows(2).map(|pair| (pair[0], pair[1])).collect()
+}
+
+/// Dice coefficient of two words' bigram multisets: 1.0 for the same word.
+fn dice(left: &str, right: &str) -> f64 {
+    let left = bigrams(left);
+    let mut right = bigrams(right);
+    let total = left.len() + right.len();
+    let mut shared = 0;
+    for gram in &left {
+        if let Some(at) = right.iter().position(|other| other == gram) {
+            right.swap_remove(at);
+            shared += 1;
+        }
+    }
+    2.0 * shared as f64 / total as f64
+}
+
+
+/// One word or phrase of a query, as typed and as each synonym reads it. A
+/// product matches the group when every word of any one reading matches.
+#[derive(Debug, Clone, PartialEq)]
+struct Group {
+    /// The typed reading first, then the synonyms.
+    readings: Vec<Reading>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Reading {
+    words: Vec<Term>,
+    weight: f64,
+    /// Set for a quoted phrase: its words joined by a space, which a field has
+    /// to hold as written.
+    quoted: Option<String>
+}
+
+impl Group {
+    fn new(phrases: Vec<Vec<String>>) -> Self {
+        let readings = phrases.into_iter()
+            .filter(|phrase| !phrase.is_empty())
+            .enumerate()
+            .map(|(at, phrase)| Reading {
+                words: phrase.iter().map(|word| Term::new(word)).collect(),
+                weight: if at == 0 { 1.0 } else { SYNONYM },
+                quoted: None
+            })
+            .collect();
+        Self { readings }
+    }
+
+    /// A quoted phrase: one reading, no synonyms, no near spellings.
+    fn quoted(words: Vec<String>) -> Self {
+        let quoted = (words.len() > 1).then(|| words.join(" "));
+        let reading = Reading {
+            words: words.iter().map(|word| Term::exact(word)).collect(),
+            weight: 1.0,
+            quoted
+        };
+        Self { readings: vec![reading] }
+    }
+
+    /// The same readings with near spellings off, for a negation: dropping a
+    /// product over a word it only resembles would be a surprise.
+    fn exact(mut self) -> Self {
+        for term in self.readings.iter_mut().flat_map(|reading| reading.words.iter_mut()) {
+            term.fuzzy = false;
+        }
+        self
+    }
+
+    /// The best hit quality per field over the readings that match, all zero
+    /// when none does. A phrase scores the mean of its words in each field.
+    ///
+    /// A quoted phrase counts only in a field holding it as written. The
+    /// description is the exception: its haystack keeps each word once, out of
+    /// order, so there the words being present is all that can be checked.
+    fn on(&self, haystack: &Haystack<'_>) -> [f64; Field::ALL.len()] {
+        let mut best = [0.0_f64; Field::ALL.len()];
+        for reading in &self.readings {
+            let mut per_word: Vec<[f64; Field::ALL.len()]> = reading.words.iter()
+                .map(|term| Field::ALL.map(|field| term.on(field, haystack)))
+                .collect();
+            if let Some(phrase) = &reading.quoted {
+                for (at, field) in Field::ALL.into_iter().enumerate() {
+                    let holds = field == Field::Description
+                        || (!field.is_code() && field.text(haystack).contains(phrase.as_str()));
+                    if !holds {
+                        per_word.iter_mut().for_each(|fields| fields[at] = 0.0);
+                    }
+                }
+            }
+            if per_word.iter().any(|fields| fields.iter().all(|quality| *quality == 0.0)) {
+                continue
+            }
+            for (at, slot) in best.iter_mut().enumerate() {
+                let mean = per_word.iter().map(|fields| fields[at]).sum::<f64>() / per_word.len() as f64;
+                *slot = slot.max(reading.weight * mean);
+            }
+        }
+        best
+    }
+
+    fn terms(&self) -> impl Iterator<Item = &Term> {
+        self.readings.iter().flat_map(|reading| reading.words.iter())
+    }
+}
+
+
+/// A search query, folded, split into words and widened by the synonym
+/// dictionary.
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    groups: Vec<Group>,
+    /// A product any of these hits is dropped.
+    excluded: Vec<Group>
+}
+
+impl Query {
+    /// The ranked part of a parsed query (`service/mcp/syntax`), read through
+    /// the given synonyms. The query's conditions are the filters' business.
+    pub fn read(parsed: &Parsed, dictionary: &Dictionary) -> Self {
+        let mut query = Self::default();
+        for group in expand(&parsed.text, dictionary) {
+            push_new(&mut query.groups, group);
+        }
+        for phrase in &parsed.phrases {
+            push_new(&mut query.groups, Group::quoted(folded_words(phrase)));
+        }
+        for word in &parsed.excluded {
+            for group in expand(word, dictionary) {
+                push_new(&mut query.excluded, group.exact());
+            }
+        }
+        for phrase in &parsed.excluded_phrases {
+            push_new(&mut query.excluded, Group::quoted(folded_words(phrase)));
+        }
+        query
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Every word of every reading, for marking hits.
+    fn terms(&self) -> impl Iterator<Item = &Term> {
+        self.groups.iter().flat_map(Group::terms)
+    }
+
+    /// Marks what this query matched in a product's own text.
+    ///
+    /// Works on the original text, not the folded haystacks, so the caller
+    /// sees the accents and casing Octopus holds. `None` when nothing shown
+    /// matched — a hit can sit in a field this does not echo, such as the
+    /// category code.
+    pub fn highlight(&self, product: &IndexedProduct) -> Option<Highlight> {
+        if self.is_empty() {
+            return None
+        }
+        let highlight = Highlight {
+            no: self.mark_code(&product.no),
+            name: self.mark_text(&product.name),
+            brand: product.brand.as_deref().and_then(|brand| self.mark_text(brand)),
+            category_name: product.category_name.as_deref().and_then(|name| self.mark_text(name)),
+            oem_code: product.oem_code.as_deref().and_then(|code| self.mark_code(code)),
+            barcode: product.barcodes.iter().find_map(|code| self.mark_code(code)),
+            description: product.description.as_deref().and_then(|text| self.snippet(text))
+        };
+        (highlight != Highlight::default()).then_some(highlight)
+    }
+
+    fn mark_code(&self, code: &str) -> Option<String> {
+        let (folded, origin) = fold_mapped(code);
+        let ranges: Vec<Range<usize>> = self.terms()
+            .filter_map(|term| term.matches(&folded))
+            .map(|(_, range)| original(&origin, range))
+            .collect();
+        (!ranges.is_empty()).then(|| render(code, ranges, 0..code.len()))
+    }
+
+    fn mark_text(&self, text: &str) -> Option<String> {
+        let ranges = self.ranges_in(text, true);
+        (!ranges.is_empty()).then(|| render(text, ranges, 0..text.len()))
+    }
+
+    /// The part of a description around its first hit.
+    fn snippet(&self, text: &str) -> Option<String> {
+        let ranges = self.ranges_in(text, false);
+        let first = ranges.iter().min_by_key(|range| range.start)?.clone();
+        let start = text[..first.start].char_indices().rev()
+            .nth(SNIPPET_BEFORE.saturating_sub(1))
+            .map_or(0, |(at, _)| at);
+        let end = text[first.end..].char_indices()
+            .nth(SNIPPET_AFTER)
+            .map_or(text.len(), |(at, _)| first.end + at);
+        let body = render(text, ranges, start..end).replace(['\n', '\r'], " ");
+        Some(format!(
+            "{}{}{}",
+            if start > 0 { "…" } else { "" },
+            body,
+            if end < text.len() { "…" } else { "" }
+        ))
+    }
+
+    /// Byte ranges of `text` this query's words hit, synonyms included.
+    fn ranges_in(&self, text: &str, typos: bool) -> Vec<Range<usize>> {
+        let (folded, origin) = fold_mapped(text);
+        let mut ranges = Vec::new();
+        for (at, word) in words(&folded) {
+            for term in self.terms() {
+                let hit = term.matches(word).map(|(_, range)| range)
+                    .or_else(|| typos.then(|| term.resembles(word)).flatten().map(|_| 0..word.len()));
+                if let Some(range) = hit {
+                    ranges.push(original(&origin, at + range.start..at + range.end));
+                }
+            }
+        }
+        ranges
+    }
+}
+
+/// A text's folded words.
+fn folded_words(text: &str) -> Vec<String> {
+    words(&fold(text)).map(|(_, word)| word.to_string()).collect()
+}
+
+/// A text's words, grouped with their synonyms.
+fn expand(text: &str, dictionary: &Dictionary) -> Vec<Group> {
+    let folded = fold(text);
+    let typed: Vec<&str> = words(&folded).map(|(_, word)| word).collect();
+    dictionary.expand(&typed).into_iter().map(Group::new).collect()
+}
+
+/// Adds a group unless it is empty or already there.
+fn push_new(groups: &mut Vec<Group>, group: Group) {
+    if group.readings.iter().any(|reading| !reading.words.is_empty()) && !groups.contains(&group) {
+        groups.push(group);
+    }
+}
+
+/// `text` folded, with the byte range of `text` each folded byte came from.
+/// Folding is per character, so this is exactly [`fold`] with a trail back.
+fn fold_mapped(text: &str) -> (String, Vec<Range<usize>>) {
+    let mut folded = String::with_capacity(text.len());
+    let mut origin = Vec::with_capacity(text.len());
+    let mut buffer = [0; 4];
+    for (at, c) in text.char_indices() {
+        let before = folded.len();
+        folded.push_str(&fold(c.encode_utf8(&mut buffer)));
+        origin.extend(std::iter::repeat_n(at..at + c.len_utf8(), folded.len() - before));
+    }
+    (folded, origin)
+}
+
+/// A range of folded bytes, as the range of original text it came from.
+fn original(origin: &[Range<usize>], folded: Range<usize>) -> Range<usize> {
+    let start = origin.get(folded.start).map_or(0, |range| range.start);
+    let end = folded.end.checked_sub(1)
+        .and_then(|last| origin.get(last))
+        .map_or(start, |range| range.end);
+    start..end
+}
+
+/// `text[window]` with every range inside the window marked, overlaps merged.
+fn render(text: &str, mut ranges: Vec<Range<usize>>, window: Range<usize>) -> String {
+    ranges.retain(|range| range.start >= window.start && range.end <= window.end && range.start < range.end);
+    ranges.sort_by_key(|range| range.start);
+
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range)
+        }
+    }
+
+    let mut out = String::with_capacity(window.len() + merged.len() * MARK.len() * 2);
+    let mut at = window.start;
+    for range in merged {
+        out.push_str(&text[at..range.start]);
+        out.push_str(MARK);
+        out.push_str(&text[range.clone()]);
+        out.push_str(MARK);
+        at = range.end;
+    }
+    out.push_str(&text[at..window.end]);
+    out
+}
+
+
+/// What a search matched, per field, with the hits wrapped in `**`. Only the
+/// fields with a hit are present; `description` is a snippet around its first
+/// hit rather than the whole text.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct Highlight {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brand: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oem_code: Option<String>,
+    /// The first barcode hit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub barcode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>
+}
+
+
+/// One scanned product that matched every term.
+struct Matched {
+    position: usize,
+    /// Per query group, the hit quality in each field (`0.0` for none).
+    qualities: Vec<[f64; Field::ALL.len()]>,
+    /// Each field's length in bytes, for normalization.
+    lengths: [usize; Field::ALL.len()]
+}
+
+/// Scores a query over a stream of products in two passes: [`scan`](Self::scan)
+/// gathers hits and the statistics BM25 needs — how many products each term
+/// hits, how long each field runs on average — and [`finish`](Self::finish)
+/// turns the survivors' hits into scores.
+pub struct Ranker<'q> {
+    query: &'q Query,
+    scanned: usize,
+    /// Per query group, how many scanned products it hit.
+    hits: Vec<usize>,
+    length_sums: [f64; Field::ALL.len()],
+    matched: Vec<Matched>
+}
+
+impl<'q> Ranker<'q> {
+    pub fn new(query: &'q Query) -> Self {
+        Self {
+            query,
+            scanned: 0,
+            hits: vec![0; query.groups.len()],
+            length_sums: [0.0; Field::ALL.len()],
+            matched: Vec::new()
+        }
+    }
+
+    pub(super) fn scan(&mut self, position: usize, haystack: &Haystack<'_>) {
+        self.scanned += 1;
+        let lengths = Field::ALL.map(|field| field.text(haystack).len());
+        for (sum, length) in self.length_sums.iter_mut().zip(lengths) {
+            *sum += length as f64;
+        }
+
+        let mut qualities = Vec::with_capacity(self.query.groups.len());
+        let mut every_term = true;
+        // All groups, even after one misses: the hit counts feed `idf`.
+        for (group, hits) in self.query.groups.iter().zip(self.hits.iter_mut()) {
+            let per_field = group.on(haystack);
+            if per_field.iter().any(|quality| *quality > 0.0) {
+                *hits += 1;
+            } else {
+                every_term = false;
+            }
+            qualities.push(per_field);
+        }
+
+        // A word prefix or better, a synonym's included: a negated `box` should
+        // not drop an `xbox`.
+        let excluded = self.query.excluded.iter()
+            .any(|group| group.on(haystack).iter().any(|quality| *quality >= PREFIX * SYNONYM));
+
+        if every_term && !excluded {
+            self.matched.push(Matched { position, qualities, lengths });
+        }
+    }
+
+    /// The matched positions, best first. Ties go to the shorter name — usually
+    /// the more canonical product — then to the article number `no` reports.
+    pub fn finish<'b>(self, no: impl Fn(usize) -> Option<&'b str>) -> Vec<(f64, usize)> {
+        let scanned = self.scanned.max(1) as f64;
+        let average = self.length_sums.map(|sum| sum / scanned);
+        let idf: Vec<f64> = self.hits.iter()
+            .map(|&hits| (1.0 + (scanned - hits as f64 + 0.5) / (hits as f64 + 0.5)).ln())
+            .collect();
+
+        let mut scored: Vec<(f64, usize, usize)> = self.matched.into_iter()
+            .map(|Matched { position, qualities, lengths }| {
+                let score = qualities.iter().zip(&idf)
+                    .map(|(per_field, idf)| {
+                        let mut weight = 0.0;
+                        let mut bonus = 0.0;
+                        for (at, field) in Field::ALL.into_iter().enumerate() {
+                            let quality = per_field[at];
+                            if quality == 0.0 {
+                                continue
+                            }
+                            let norm = if field.is_code() || average[at] == 0.0 {
+                                1.0
+                            } else {
+                                1.0 - B + B * lengths[at] as f64 / average[at]
+                            };
+                            weight += field.boost() * quality / norm;
+                            if quality == EXACT {
+                                bonus += field.exact_bonus();
+                            }
+                        }
+                        idf * (weight * (K1 + 1.0) / (weight + K1) + bonus)
+                    })
+                    .sum();
+                (score, lengths[Field::Name as usize], position)
+            })
+            .collect();
+
+        scored.sort_by(|a, b| {
+            b.0.partial_cmp(&a.0)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.1.cmp(&b.1))
+                .then_with(|| no(a.2).unwrap_or_default().cmp(no(b.2).unwrap_or_default()))
+        });
+        scored.into_iter().map(|(score, _, position)| (score, position)).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::mcp::{index::test_product, synonyms, syntax};
+
+    fn query(text: &str) -> Query {
+        Query::read(&syntax::parse(text), &synonyms::current())
+    }
+
+    #[test]
+    fn words_keep_joined_codes_whole() {
+        let found: Vec<&str> = words("hp-305xl, (2.5 mm) a4/5 -x-").map(|(_, word)| word).collect();
+        assert_eq!(found, ["hp-305xl", "2.5", "mm", "a4/5", "x"]);
+        let (at, word) = words("  monitor").next().unwrap();
+        assert_eq!((at, word), (2, "monitor"));
+    }
+
+    #[test]
+    fn near_spellings_resemble_but_other_words_do_not() {
+        let term = Term::new("samsnug");
+        assert!(term.resembles("samsung").is_some());
+        assert!(term.resembles("samsonite").is_none());
+        assert!(Term::new("monitr").resembles("monitor").is_some());
+        // Codes are never fuzzy.
+        assert!(Term::new("abcd-7").resembles("abcd-1").is_none());
+    }
+
+    #[test]
+    fn highlights_mark_the_original_text() {
+        let mut product = test_product("SM-27", "Samsung Monitor 27\"", "Samsung", "");
+        product.description = Some(format!("{} ívelt képernyős monitor, fekete.", "Nagy felbontású ".repeat(8)));
+        let highlight = query("samsnug MONIT ivelt").highlight(&product).unwrap();
+
+        assert_eq!(highlight.name.as_deref(), Some("**Samsung** **Monit**or 27\""));
+        assert_eq!(highlight.brand.as_deref(), Some("**Samsung**"));
+        let snippet = highlight.description.unwrap();
+        assert!(snippet.starts_with('…'), "a long lead-in is cut: {}", snippet);
+        assert!(snippet.contains("**ívelt**"), "the accented original is marked: {}", snippet);
+        assert!(highlight.no.is_none());
+    }
+
+    #[test]
+    fn a_code_hit_is_marked_where_it_matched() {
+        let product = test_product("ABC-123", "Pen", "", "");
+        let highlight = query("abc").highlight(&product).unwrap();
+        assert_eq!(highlight.no.as_deref(), Some("**ABC**-123"));
+        assert!(query("zzz").highlight(&product).is_none());
+    }
+}
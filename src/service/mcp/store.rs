@@ -5,40 +5,53 @@
 //! a snapshot evicted from RAM, or lost to a restart, is reloaded from here in
 //! well under a second instead of being rebuilt from Octopus in tens of seconds.
 //!
+//! A snapshot is stored the way it is held (see `service/mcp/index`): one base
+//! file per Octopus url, `base-<url fingerprint>.snap`, and one small overlay
+//! file per partner beside it. Dozens of partners cost one catalog plus their
+//! overlays, and a partner's refresh rewrites the base only when the master data
+//! actually changed.
+//!
+//! Both are in the binary layout of `service/mcp/layout`. A base is mapped
+//! rather than read, so a lookup against it touches only the pages it needs.
+//!
 //! ## These files are commercially sensitive
 //!
-//! A snapshot holds a partner's **own negotiated prices** and stock. Files are
+//! An overlay holds a partner's **own negotiated prices** and stock. Files are
 //! therefore written `0600` and named by the authcode's hash fingerprint, never
 //! by the code itself, so a directory listing reveals neither credentials nor
 //! who the entry belongs to. Provision the directory like `mcp_precache.toml`,
 //! not like a scratch volume.
+//!
+//! With a storage key configured each file is also sealed (`service/sealed.rs`),
+//! under its own file name: a snapshot copied over another partner's name fails
+//! to open instead of being served as theirs. A sealed base cannot be mapped:
+//! it is decrypted into memory whole on load, so a storage key costs the disk
+//! tier the idle footprint the mapping saves.
 
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::io::Write;
 use std::path::{Path, PathBuf};
 
-use flate2::Compression;
-use flate2::read::GzDecoder;
-use flate2::write::GzEncoder;
-
 use crate::service::{
     config::get_mcp_settings,
     log::{elogger, logger},
     mcp::{
-        cache::{CacheKey, fingerprint},
-        index::{CatalogSnapshot, PersistedSnapshot, SNAPSHOT_VERSION}
+        cache::{CacheKey, fingerprint, url_fingerprint},
+        index::{BaseCatalog, CatalogSnapshot, PersistedOverlay},
+        layout::{self, MappedBase}
     },
-    path::get_current_or_root_dir
+    path::get_current_or_root_dir,
+    sealed
 };
 
-/// Extension marking a stored snapshot: gzipped JSON.
-const SNAPSHOT_EXTENSION: &str = "json.gz";
+/// Extension marking a stored snapshot.
+const SNAPSHOT_EXTENSION: &str = "snap";
 
-/// Buffer size for the compressed streams, both ways.
-///
-/// A snapshot is tens of megabytes written a JSON token at a time, so the cost
-/// here is entirely in the number of calls, not in the size of any one of them.
-const BUFFER_BYTES: usize = 256 * 1024;
+/// Extension of the gzipped JSON files earlier builds wrote. This build cannot
+/// read them, so they are pruned first, budget or not.
+const LEGACY_EXTENSION: &str = "json.gz";
 
+/// Prefix of a base file, which no overlay's name can start with.
+const BASE_PREFIX: &str = "base-";
 
 /// The cache directory, resolved against the working directory like every other
 /// runtime path in this service (`Config.toml`, `soap.json`, `log/`).
@@ -54,7 +67,7 @@ pub fn cache_dir() -> PathBuf {
 }
 
 
-/// File name for one cache key: `<auth fingerprint>-<pid>.json.gz`.
+/// Overlay file name for one cache key: `<auth fingerprint>-<pid>.snap`.
 ///
 /// Derived from the authcode's hash, so neither the code nor the partner it
 /// belongs to can be read off a directory listing. The url is not part of the
@@ -65,6 +78,13 @@ fn file_name(key: &CacheKey) -> String {
 }
 
 
+/// Base file name for one url. The url is hashed rather than sanitized into a
+/// path component, like the authcode.
+fn base_file_name(url: &str) -> String {
+    format!("{}{}.{}", BASE_PREFIX, url_fingerprint(url), SNAPSHOT_EXTENSION)
+}
+
+
 /// Full path for one cache key.
 fn path_for(key: &CacheKey) -> PathBuf {
     cache_dir().join(file_name(key))
@@ -110,48 +130,44 @@ fn restrict_dir(path: &Path) {
 }
 
 
-/// Writes a snapshot to disk, replacing any previous one for the same key.
+/// Writes a partner's overlay to disk, replacing any previous one for the same
+/// key. The base it is laid over is written by [`write_base`].
 ///
 /// Written to a temporary file and renamed, so a crash mid-write cannot leave a
 /// truncated snapshot that would later deserialize into a partial catalog.
 pub fn write(key: &CacheKey, snapshot: &CatalogSnapshot) -> Result<u64, String> {
-    let size = write_to(&cache_dir(), key, snapshot)?;
+    let size = write_to(&cache_dir(), &file_name(key), layout::encode_overlay(&PersistedOverlay::from(snapshot)))?;
     prune();
     Ok(size)
 }
 
 
-/// [`write`], against an explicit directory. Split out so tests can round-trip
-/// through a temporary directory instead of the configured one.
-fn write_to(dir: &Path, key: &CacheKey, snapshot: &CatalogSnapshot) -> Result<u64, String> {
+/// Writes a url's base to disk, replacing the previous one.
+pub fn write_base(url: &str, base: &BaseCatalog) -> Result<u64, String> {
+    let size = write_to(&cache_dir(), &base_file_name(url), layout::encode_base(base)?)?;
+    prune();
+    Ok(size)
+}
+
+
+/// Writes one file into an explicit directory. Split out so tests can
+/// round-trip through a temporary directory instead of the configured one.
+fn write_to(dir: &Path, name: &str, body: Vec<u8>) -> Result<u64, String> {
     ensure_dir(dir)?;
-    let path = dir.join(file_name(key));
+    let path = dir.join(name);
     let temporary = path.with_extension("tmp");
 
-    let persisted = PersistedSnapshot::from(snapshot);
-    let file = std::fs::File::create(&temporary)
+    let mut file = std::fs::File::create(&temporary)
         .map_err(|error| format!("cannot create '{:?}': {}", temporary, error))?;
     restrict_file(&temporary);
 
-    // Fast compression rather than best: this runs on every refresh, and the
-    // difference in size is small next to the difference in CPU time.
-    //
-    // Both buffers matter. `serde_json` emits one write per token — per field
-    // name, per string, per comma — so writing straight into the encoder means
-    // tens of millions of deflate calls for a 45 MB catalog; the outer
-    // `BufWriter` batches those into 256 KB blocks. The inner one does the same
-    // for the encoder's output against the file, turning a syscall per
-    // compressed chunk into one per block.
-    let encoder = GzEncoder::new(BufWriter::with_capacity(BUFFER_BYTES, file), Compression::fast());
-    let mut writer = BufWriter::with_capacity(BUFFER_BYTES, encoder);
-    serde_json::to_writer(&mut writer, &persisted)
-        .map_err(|error| format!("cannot serialize snapshot: {}", error))?;
-    let encoder = writer.into_inner()
-        .map_err(|error| format!("cannot flush '{:?}': {}", temporary, error))?;
-    let mut file = encoder.finish().map_err(|error| format!("cannot finish '{:?}': {}", temporary, error))?;
+    let body = sealed::seal(body, name)?;
+    file.write_all(&body).map_err(|error| format!("cannot write '{:?}': {}", temporary, error))?;
     file.flush().map_err(|error| format!("cannot flush '{:?}': {}", temporary, error))?;
     drop(file);
 
+    // A rename rather than a rewrite in place also keeps any mapping of the
+    // previous file valid: it holds the old inode until it is dropped.
     std::fs::rename(&temporary, &path)
         .map_err(|error| format!("cannot rename into '{:?}': {}", path, error))?;
     restrict_file(&path);
@@ -160,61 +176,62 @@ fn write_to(dir: &Path, key: &CacheKey, snapshot: &CatalogSnapshot) -> Result<u6
 }
 
 
-/// Reads a snapshot back, or `None` when nothing is stored for this key.
+/// Reads a partner's overlay back, or `None` when nothing is stored for this
+/// key.
 ///
 /// A corrupt or unreadable file is logged and removed rather than surfaced: the
 /// caller can always rebuild from Octopus, and leaving a poison file in place
 /// would make every future lookup fail the same way.
-pub fn read(key: &CacheKey) -> Option<CatalogSnapshot> {
-    read_from(&cache_dir(), key)
+pub fn read(key: &CacheKey) -> Option<PersistedOverlay> {
+    read_overlay_from(&cache_dir(), &file_name(key))
+}
+
+
+/// Maps a url's base, or `None` when nothing is stored for it.
+pub fn read_base(url: &str) -> Option<BaseCatalog> {
+    let started = std::time::Instant::now();
+    let name = base_file_name(url);
+    let base = read_base_from(&cache_dir(), &name)?;
+    logger(format!(
+        "MCP store: opened {} — {} products, {:.1} MB on the heap, {:.1} ms",
+        name,
+        base.len(),
+        base.bytes as f64 / 1_048_576.0,
+        started.elapsed().as_secs_f64() * 1000.0
+    ));
+    Some(base)
+}
+
+
+fn read_overlay_from(dir: &Path, name: &str) -> Option<PersistedOverlay> {
+    read_from(dir, name, |path| {
+        // Read whole rather than streamed: a sealed file has to be opened, tag
+        // and all, before a byte of it can be trusted. Plain files pass
+        // straight through.
+        let bytes = std::fs::read(path).map_err(|error| error.to_string())?;
+        layout::decode_overlay(&sealed::open(bytes, name)?)
+    })
+}
+
+
+fn read_base_from(dir: &Path, name: &str) -> Option<BaseCatalog> {
+    read_from(dir, name, |path| MappedBase::open(path, name).map(BaseCatalog::mapped))
 }
 
 
-/// [`read`], against an explicit directory.
-fn read_from(dir: &Path, key: &CacheKey) -> Option<CatalogSnapshot> {
-    let path = dir.join(file_name(key));
+/// Reads one file from an explicit directory through `decode`.
+///
+/// A file from an older layout is refused by `decode` like a corrupt one — see
+/// `SNAPSHOT_VERSION` — so either way it is removed here and the caller
+/// rebuilds from Octopus.
+fn read_from<T>(dir: &Path, name: &str, decode: impl FnOnce(&Path) -> Result<T, String>) -> Option<T> {
+    let path = dir.join(name);
     if !path.is_file() {
         return None
     }
 
-    let started = std::time::Instant::now();
-    let outcome = std::fs::File::open(&path)
-        .map_err(|error| error.to_string())
-        .and_then(|file| {
-            let mut decoder = GzDecoder::new(BufReader::with_capacity(BUFFER_BYTES, file));
-            let mut buffer = String::new();
-            decoder.read_to_string(&mut buffer)
-                .map_err(|error| error.to_string())
-                .map(|_| buffer)
-        })
-        .and_then(|buffer| {
-            serde_json::from_str::<PersistedSnapshot>(&buffer).map_err(|error| error.to_string())
-        })
-        .and_then(|persisted| {
-            // A file from an older layout parses cleanly but means something
-            // different — see `SNAPSHOT_VERSION`. Treat it like corruption: the
-            // error arm below removes it and the caller rebuilds from Octopus.
-            if persisted.version != SNAPSHOT_VERSION {
-                return Err(format!(
-                    "written by snapshot layout v{}, this build reads v{}",
-                    persisted.version, SNAPSHOT_VERSION
-                ))
-            }
-            Ok(persisted)
-        });
-
-    match outcome {
-        Ok(persisted) => {
-            let snapshot = CatalogSnapshot::from(persisted);
-            logger(format!(
-                "MCP store: loaded {} from disk — {} products, {:.1} MB, {:.2}s",
-                file_name(key),
-                snapshot.products.len(),
-                snapshot.bytes as f64 / 1_048_576.0,
-                started.elapsed().as_secs_f64()
-            ));
-            Some(snapshot)
-        }
+    match decode(&path) {
+        Ok(persisted) => Some(persisted),
         Err(error) => {
             elogger(format!("MCP store: discarding unreadable '{:?}': {}", path, error));
             if let Err(error) = std::fs::remove_file(&path) {
@@ -226,7 +243,8 @@ fn read_from(dir: &Path, key: &CacheKey) -> Option<CatalogSnapshot> {
 }
 
 
-/// Deletes one stored snapshot. Missing files are not an error.
+/// Deletes one partner's stored overlay; the shared base stays. Missing files
+/// are not an error.
 pub fn remove(key: &CacheKey) {
     let path = path_for(key);
     if path.is_file()
@@ -236,14 +254,14 @@ pub fn remove(key: &CacheKey) {
 }
 
 
-/// Whether a snapshot is stored for this key, without reading it.
+/// Whether an overlay is stored for this key, without reading it.
 pub fn contains(key: &CacheKey) -> bool {
     path_for(key).is_file()
 }
 
 
 /// How old the stored snapshot is, in seconds, from the file's modification
-/// time — without decompressing tens of megabytes to find out.
+/// time — without reading the file to find out.
 ///
 /// The file is written once when the snapshot is built, so its mtime tracks the
 /// snapshot's `fetched_at` closely enough to decide whether a refresh is due.
@@ -262,7 +280,10 @@ fn stored_files() -> Vec<(PathBuf, u64, std::time::SystemTime)> {
 
     let mut files: Vec<(PathBuf, u64, std::time::SystemTime)> = entries
         .filter_map(|entry| entry.ok())
-        .filter(|entry| entry.file_name().to_string_lossy().ends_with(SNAPSHOT_EXTENSION))
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            name.ends_with(SNAPSHOT_EXTENSION) || name.ends_with(LEGACY_EXTENSION)
+        })
         .filter_map(|entry| {
             let metadata = entry.metadata().ok()?;
             let modified = metadata.modified().ok()?;
@@ -275,6 +296,12 @@ fn stored_files() -> Vec<(PathBuf, u64, std::time::SystemTime)> {
 }
 
 
+/// Every stored snapshot's path, for `service/sealed`'s migration.
+pub fn stored_paths() -> Vec<PathBuf> {
+    stored_files().into_iter().map(|(path, _, _)| path).collect()
+}
+
+
 /// Total bytes currently stored, and how many snapshots that is.
 pub fn usage() -> (u64, usize) {
     let files = stored_files();
@@ -288,17 +315,29 @@ pub fn usage() -> (u64, usize) {
 /// recency because it is answering live queries, while disk is a fallback whose
 /// only job is to be cheaper than a rebuild. An old snapshot is the one closest
 /// to being stale anyway.
+///
+/// Overlays go before any base: a base is what every overlay of its url needs,
+/// so dropping one costs all of those partners a rebuild.
 fn prune() {
     let budget = get_mcp_settings().disk_max_bytes();
-    let files = stored_files();
+    let is_legacy = |path: &PathBuf| path.to_string_lossy().ends_with(LEGACY_EXTENSION);
+    let (legacy, files): (Vec<_>, Vec<_>) = stored_files().into_iter().partition(|(path, _, _)| is_legacy(path));
+    for (path, _, _) in legacy {
+        match std::fs::remove_file(&path) {
+            Ok(()) => logger(format!("MCP store: removed '{:?}', written in a layout this build does not read", path)),
+            Err(error) => elogger(format!("MCP store: cannot remove '{:?}': {}", path, error))
+        }
+    }
     let mut total: u64 = files.iter().map(|(_, size, _)| size).sum();
 
     if total <= budget {
         return
     }
 
+    let is_base = |path: &PathBuf| path.file_name().is_some_and(|name| name.to_string_lossy().starts_with(BASE_PREFIX));
     // `stored_files` is newest-first, so walk it backwards.
-    for (path, size, _) in files.iter().rev() {
+    let oldest_first = files.iter().rev();
+    for (path, size, _) in oldest_first.clone().filter(|(path, _, _)| !is_base(path)).chain(oldest_first.filter(|(path, _, _)| is_base(path))) {
         if total <= budget {
             break
         }
@@ -315,18 +354,36 @@ fn prune() {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use super::*;
-    use crate::service::mcp::index::test_snapshot_with_products;
+    use crate::service::mcp::index::{SNAPSHOT_VERSION, SearchFilters, test_snapshot_with_products};
+
+    const URL: &str = "https://example.test/services/vision.asmx";
 
     fn key(authcode: &str, pid: i64) -> CacheKey {
-        CacheKey::new(authcode, pid, "https://example.test/services/vision.asmx")
+        CacheKey::new(authcode, pid, URL)
+    }
+
+    /// Writes a snapshot the way the cache does: its base, then its overlay.
+    fn write_snapshot(dir: &Path, key: &CacheKey, snapshot: &CatalogSnapshot) -> u64 {
+        let base = layout::encode_base(&snapshot.base).expect("encodes the base");
+        write_to(dir, &base_file_name(&key.url), base).expect("writes the base")
+            + write_to(dir, &file_name(key), layout::encode_overlay(&PersistedOverlay::from(snapshot))).expect("writes the overlay")
+    }
+
+    /// Reads a snapshot back the way the cache does.
+    fn read_snapshot(dir: &Path, key: &CacheKey) -> Option<CatalogSnapshot> {
+        let overlay = read_overlay_from(dir, &file_name(key))?;
+        let base = read_base_from(dir, &base_file_name(&key.url))?;
+        Some(CatalogSnapshot::resolve(Arc::new(base), overlay.offers, overlay.fetched_at))
     }
 
     #[test]
     fn file_names_reveal_neither_the_code_nor_the_partner_name() {
         let name = file_name(&key("SUPERSECRETAUTHCODE", 7824));
         assert!(!name.contains("SUPERSECRET"));
-        assert!(name.ends_with("-7824.json.gz"));
+        assert!(name.ends_with("-7824.snap"));
         // Stable, so the same combination always maps to the same file.
         assert_eq!(name, file_name(&key("SUPERSECRETAUTHCODE", 7824)));
     }
@@ -337,6 +394,16 @@ mod tests {
         assert_ne!(file_name(&key("AAAA1111BBBB2222", 1)), file_name(&key("CCCC3333DDDD4444", 1)));
     }
 
+    #[test]
+    fn every_partner_of_a_url_shares_one_base_file() {
+        let name = base_file_name(URL);
+        assert!(name.starts_with(BASE_PREFIX) && name.ends_with(SNAPSHOT_EXTENSION));
+        assert!(!name.contains("example"), "the url is readable off the name");
+        assert_ne!(name, base_file_name("https://other.test/services/vision.asmx"));
+        // No overlay can be mistaken for a base when pruning.
+        assert!(!file_name(&key("AAAA1111BBBB2222", 1)).starts_with(BASE_PREFIX));
+    }
+
     /// A scratch directory that cleans itself up, so tests never write into the
     /// configured cache directory.
     struct TempDir(PathBuf);
@@ -361,19 +428,21 @@ mod tests {
         let key = key("AAAA1111BBBB2222", 42);
         let snapshot = test_snapshot_with_products(50);
 
-        let size = write_to(&dir.0, &key, &snapshot).expect("writes");
+        let size = write_snapshot(&dir.0, &key, &snapshot);
         assert!(size > 0, "wrote an empty file");
 
-        let restored = read_from(&dir.0, &key).expect("reads back");
-        assert_eq!(restored.products.len(), 50);
+        let restored = read_snapshot(&dir.0, &key).expect("reads back");
+        assert_eq!(restored.product_count(), 50);
         // Age is preserved rather than reset to "now" on load — otherwise every
         // restart would make stale data look fresh.
         assert_eq!(restored.fetched_at, snapshot.fetched_at);
-        // Derived data is rebuilt on load rather than stored.
-        assert_eq!(restored.by_sku.len(), 50);
-        assert!(restored.bytes > 0);
+        // The base stays in its file rather than being decoded onto the heap.
+        assert!(restored.base.bytes < snapshot.base.bytes);
+        assert_eq!(restored.base.len(), 50);
+        assert!(restored.bytes > 0 && restored.base.bytes > 0);
         // And the reloaded snapshot is actually searchable.
-        assert_eq!(restored.get_by_no("A-7").map(|p| p.no.as_str()), Some("A-7"));
+        assert_eq!(restored.get_by_no("A-7").as_ref().map(|p| p.product.no.as_str()), Some("A-7"));
+        assert_eq!(restored.search("A-7", &SearchFilters::default(), 5).results.first().map(|p| p.no.as_str()), Some("A-7"));
     }
 
     #[test]
@@ -382,28 +451,23 @@ mod tests {
         let key = key("AAAA1111BBBB2222", 1);
         ensure_dir(&dir.0).expect("creates dir");
 
-        // Hand-written at the previous layout: parses fine, but its `price` held
-        // Octopus's `ar` rather than `akcios_ar`, so serving it would quote a
-        // retail figure as the partner's own.
-        let stale = PersistedSnapshot {
-            version: SNAPSHOT_VERSION - 1,
-            products: test_snapshot_with_products(3).products,
-            fetched_at: chrono::Utc::now()
-        };
+        // Written at the previous layout: reads fine, but an older layout may
+        // mean something else by the same field — `price` once held Octopus's
+        // `ar` rather than `akcios_ar` — and serving it could quote a retail
+        // figure as the partner's own.
+        let mut stale = PersistedOverlay::from(&test_snapshot_with_products(3));
+        stale.version = SNAPSHOT_VERSION - 1;
         let path = dir.0.join(file_name(&key));
-        let file = std::fs::File::create(&path).expect("creates file");
-        let mut encoder = flate2::write::GzEncoder::new(file, Compression::fast());
-        serde_json::to_writer(&mut encoder, &stale).expect("writes");
-        encoder.finish().expect("finishes");
+        std::fs::write(&path, layout::encode_overlay(&stale)).expect("writes");
 
-        assert!(read_from(&dir.0, &key).is_none(), "a stale layout was served");
+        assert!(read_overlay_from(&dir.0, &file_name(&key)).is_none(), "a stale layout was served");
         assert!(!path.exists(), "the stale file was left to fail every future load");
     }
 
     #[test]
     fn a_missing_snapshot_reads_as_none() {
         let dir = TempDir::new("missing");
-        assert!(read_from(&dir.0, &key("AAAA1111BBBB2222", 1)).is_none());
+        assert!(read_snapshot(&dir.0, &key("AAAA1111BBBB2222", 1)).is_none());
     }
 
     #[test]
@@ -413,10 +477,10 @@ mod tests {
         ensure_dir(&dir.0).expect("creates dir");
 
         let path = dir.0.join(file_name(&key));
-        std::fs::write(&path, b"this is not gzipped json").expect("writes garbage");
+        std::fs::write(&path, b"this is not a snapshot").expect("writes garbage");
 
         // A poison file must not make every future lookup fail the same way.
-        assert!(read_from(&dir.0, &key).is_none());
+        assert!(read_overlay_from(&dir.0, &file_name(&key)).is_none());
         assert!(!path.exists(), "the unreadable file was left in place");
     }
 
@@ -424,7 +488,7 @@ mod tests {
     fn stored_files_are_owner_only() {
         let dir = TempDir::new("perms");
         let key = key("AAAA1111BBBB2222", 1);
-        write_to(&dir.0, &key, &test_snapshot_with_products(2)).expect("writes");
+        write_snapshot(&dir.0, &key, &test_snapshot_with_products(2));
 
         #[cfg(unix)]
         {
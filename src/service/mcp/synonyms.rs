@@ -0,0 +1,408 @@
+//! Search synonyms: the operator's vocabulary, applied at query time.
+//!
+//! Catalog names mix Hungarian and English — one supplier writes `nyomtató`,
+//! the next `printer` — and [`fold`] only strips accents. `search_synonyms.toml`
+//! says which words mean the same thing, and `rank::Query` reads every query
+//! through it, so `egér` also finds `mouse` and `2tb` also finds `2 TB`.
+//!
+//! Three kinds of entry:
+//!
+//! * `synonyms` — groups whose members all find one another;
+//! * `[one_way]` — a word that also finds others, but not the reverse, so a
+//!   printer search shows multifunction devices without every multifunction
+//!   search showing printers;
+//! * `units` — groups of unit aliases (`tb`, `terabyte`), which also match a
+//!   number written against the unit or apart from it.
+//!
+//! It is applied to the query rather than baked into the snapshots, so an edit
+//! from `/admin` takes effect on the next search without a rebuild. With no
+//! file on disk the starter vocabulary shipped in
+//! `src/static/search/synonyms.toml` is used, and "Reset to starter" on the
+//! dashboard goes back to it by deleting the file.
+//!
+//! This is a plain file whatever `[storage] backend` says: it holds no partner
+//! data and is meant to be read and diffed by a person.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::service::{
+    log::{elogger, logger},
+    mcp::{index::fold, rank::words},
+    path::get_current_or_root_dir
+};
+
+/// Used whenever the operator has not saved a file of their own.
+const STARTER: &str = include_str!("../../static/search/synonyms.toml");
+
+
+/// On-disk shape of `search_synonyms.toml`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SynonymFile {
+    #[serde(default)]
+    synonyms: Vec<Vec<String>>,
+    #[serde(default)]
+    units: Vec<Vec<String>>,
+    #[serde(default)]
+    one_way: BTreeMap<String, Vec<String>>
+}
+
+
+/// How many entries of each kind a dictionary holds, for the log and `/admin`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Counts {
+    pub synonyms: usize,
+    pub one_way: usize,
+    pub units: usize
+}
+
+
+/// A compiled synonym file. Every key and alternative is folded and split into
+/// words the way a query is, so lookups are plain string comparisons.
+#[derive(Debug, Clone, Default)]
+pub struct Dictionary {
+    /// A phrase's words joined by one space → the phrases it also finds.
+    expansions: HashMap<String, Vec<Vec<String>>>,
+    /// A unit alias → every alias of its unit, itself first.
+    units: HashMap<String, Vec<String>>,
+    /// Most words in any key, which bounds the lookup at each query position.
+    longest: usize,
+    counts: Counts
+}
+
+impl Dictionary {
+    /// Compiles a synonym file's text, refusing it whole on the first bad entry.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let file: SynonymFile = toml::from_str(text).map_err(|error| error.to_string())?;
+        let mut dictionary = Self::default();
+
+        for (at, group) in file.synonyms.iter().enumerate() {
+            let phrases = phrases(group, &format!("synonyms group {}", at + 1))?;
+            dictionary.link_all(&phrases);
+        }
+
+        for (at, group) in file.units.iter().enumerate() {
+            let context = format!("units group {}", at + 1);
+            let phrases = phrases(group, &context)?;
+            let mut aliases: Vec<String> = Vec::with_capacity(phrases.len());
+            for phrase in &phrases {
+                match phrase.as_slice() {
+                    [alias] if alias.chars().all(char::is_alphabetic) => aliases.push(alias.clone()),
+                    _ => return Err(format!("{}: unit alias '{}' must be a single word of letters", context, phrase.join(" ")))
+                }
+            }
+            for alias in &aliases {
+                let mut ordered = vec![alias.clone()];
+                ordered.extend(aliases.iter().filter(|other| *other != alias).cloned());
+                dictionary.units.insert(alias.clone(), ordered);
+            }
+            // A unit written on its own is a plain synonym of its aliases.
+            dictionary.link_all(&phrases);
+        }
+
+        for (key, targets) in &file.one_way {
+            let context = format!("one_way '{}'", key);
+            let from = phrase(key).ok_or_else(|| format!("{}: the word is empty", context))?;
+            if targets.is_empty() {
+                return Err(format!("{}: lists nothing to find", context))
+            }
+            for target in targets {
+                let to = phrase(target).ok_or_else(|| format!("{}: has an empty entry", context))?;
+                dictionary.link(&from, to);
+            }
+        }
+
+        dictionary.counts = Counts {
+            synonyms: file.synonyms.len(),
+            one_way: file.one_way.len(),
+            units: file.units.len()
+        };
+        Ok(dictionary)
+    }
+
+    pub fn counts(&self) -> Counts {
+        self.counts
+    }
+
+    /// Makes every phrase of a group find every other.
+    fn link_all(&mut self, phrases: &[Vec<String>]) {
+        for from in phrases {
+            for to in phrases.iter().filter(|to| *to != from) {
+                self.link(from, to.clone());
+            }
+        }
+    }
+
+    fn link(&mut self, from: &[String], to: Vec<String>) {
+        if from == to.as_slice() {
+            return
+        }
+        self.longest = self.longest.max(from.len());
+        let alternatives = self.expansions.entry(from.join(" ")).or_default();
+        if !alternatives.contains(&to) {
+            alternatives.push(to);
+        }
+    }
+
+    /// A query's folded words, grouped: one entry per word or phrase, holding
+    /// the phrase as typed first and then every phrase it also finds.
+    ///
+    /// At each position a quantity (`2tb`, `2 tb`) is tried first, then the
+    /// longest phrase with synonyms, then the word alone.
+    pub fn expand(&self, words: &[&str]) -> Vec<Vec<Vec<String>>> {
+        let mut groups = Vec::new();
+        let mut at = 0;
+        while at < words.len() {
+            if let Some((taken, group)) = self.quantity(&words[at..]) {
+                groups.push(group);
+                at += taken;
+                continue
+            }
+
+            let found = (1..=self.longest.min(words.len() - at)).rev().find_map(|length| {
+                self.expansions.get(&words[at..at + length].join(" ")).map(|alternatives| (length, alternatives))
+            });
+            let length = found.map_or(1, |(length, _)| length);
+            let mut group = vec![owned(&words[at..at + length])];
+            if let Some((_, alternatives)) = found {
+                group.extend(alternatives.iter().cloned());
+            }
+            groups.push(group);
+            at += length;
+        }
+        groups
+    }
+
+    /// A number and a unit at the start of `words`, as the count of words they
+    /// take and every way of writing them.
+    fn quantity(&self, words: &[&str]) -> Option<(usize, Vec<Vec<String>>)> {
+        let first = *words.first()?;
+        let digits = first.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(first.len());
+        let (number, unit, typed) = if digits == first.len() {
+            // `2 tb`: the unit is the next word.
+            let unit = *words.get(1)?;
+            (first, unit, owned(&words[..2]))
+        } else {
+            // `2tb`: the unit is glued on.
+            (&first[..digits], &first[digits..], owned(&words[..1]))
+        };
+        if number.is_empty() || !number.starts_with(|c: char| c.is_ascii_digit()) {
+            return None
+        }
+        let aliases = self.units.get(unit)?;
+
+        let mut group = vec![typed.clone()];
+        for alias in aliases {
+            for spelling in [vec![format!("{}{}", number, alias)], vec![number.to_string(), alias.clone()]] {
+                if !group.contains(&spelling) {
+                    group.push(spelling);
+                }
+            }
+        }
+        Some((typed.len(), group))
+    }
+}
+
+/// An entry folded and split into words, `None` when nothing is left.
+fn phrase(entry: &str) -> Option<Vec<String>> {
+    let folded = fold(entry);
+    let words: Vec<String> = words(&folded).map(|(_, word)| word.to_string()).collect();
+    (!words.is_empty()).then_some(words)
+}
+
+/// A group's entries as phrases, refusing groups that could not mean anything.
+fn phrases(group: &[String], context: &str) -> Result<Vec<Vec<String>>, String> {
+    if group.len() < 2 {
+        return Err(format!("{}: needs at least two entries", context))
+    }
+    group.iter()
+        .map(|entry| phrase(entry).ok_or_else(|| format!("{}: has an empty entry", context)))
+        .collect()
+}
+
+fn owned(words: &[&str]) -> Vec<String> {
+    words.iter().map(|word| word.to_string()).collect()
+}
+
+
+/// Where the synonyms in force came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    /// The operator's `search_synonyms.toml`.
+    File,
+    /// The vocabulary shipped with the server.
+    Starter
+}
+
+
+/// The dictionary every query is read through, swapped whole on an edit.
+static DICTIONARY: Lazy<RwLock<Arc<Dictionary>>> = Lazy::new(|| RwLock::new(Arc::new(load())));
+
+
+/// Path to `search_synonyms.toml`, resolved against the working directory like
+/// `blocklist.toml`.
+pub fn get_synonyms_path() -> PathBuf {
+    let mut path = get_current_or_root_dir();
+    path.push("search_synonyms.toml");
+    path
+}
+
+
+/// The synonyms in force.
+pub fn current() -> Arc<Dictionary> {
+    DICTIONARY.read()
+        .map(|dictionary| Arc::clone(&dictionary))
+        .unwrap_or_default()
+}
+
+
+/// The text in force and where it came from: the operator's file, verbatim with
+/// its comments, or the starter vocabulary.
+pub fn text() -> Result<(String, Source), String> {
+    let path = get_synonyms_path();
+    if !path.is_file() {
+        return Ok((STARTER.to_string(), Source::Starter))
+    }
+    std::fs::read_to_string(&path)
+        .map(|text| (text, Source::File))
+        .map_err(|error| format!("cannot read '{:?}': {}", path, error))
+}
+
+
+/// The operator's file, or the starter vocabulary when there is none or it
+/// cannot be used. A broken file is logged rather than fatal: search without
+/// the operator's synonyms beats no search.
+fn load() -> Dictionary {
+    let compiled = text().and_then(|(text, _)| {
+        Dictionary::parse(&text).map_err(|error| format!("{:?}: {}", get_synonyms_path(), error))
+    });
+    compiled.unwrap_or_else(|error| {
+        elogger(format!("Search synonyms: {} — using the starter vocabulary", error));
+        Dictionary::parse(STARTER).unwrap_or_default()
+    })
+}
+
+
+/// Validates and stores a new synonym file, then puts it in force. Nothing is
+/// written unless the whole file compiles.
+pub fn save(text: &str) -> Result<Counts, String> {
+    let dictionary = Dictionary::parse(text)?;
+    let counts = dictionary.counts();
+    let path = get_synonyms_path();
+
+    // Held across the write, so two saves cannot interleave.
+    let mut slot = DICTIONARY.write().map_err(|_| "synonym dictionary lock poisoned".to_string())?;
+    let temporary = path.with_extension("toml.tmp");
+    std::fs::write(&temporary, text).map_err(|error| error.to_string())?;
+    std::fs::rename(&temporary, &path).map_err(|error| error.to_string())?;
+    *slot = Arc::new(dictionary);
+    Ok(counts)
+}
+
+
+/// Deletes the operator's file and goes back to the starter vocabulary.
+pub fn reset() -> Result<Counts, String> {
+    let dictionary = Dictionary::parse(STARTER)?;
+    let counts = dictionary.counts();
+    let path = get_synonyms_path();
+
+    let mut slot = DICTIONARY.write().map_err(|_| "synonym dictionary lock poisoned".to_string())?;
+    if path.is_file() {
+        std::fs::remove_file(&path).map_err(|error| error.to_string())?;
+    }
+    *slot = Arc::new(dictionary);
+    Ok(counts)
+}
+
+
+/// Loads the dictionary at startup, so the log says which vocabulary is in
+/// force and a broken file is reported before the first search.
+pub fn init() {
+    let counts = current().counts();
+    let source = match text() {
+        Ok((_, Source::File)) => format!("{:?}", get_synonyms_path()),
+        _ => "the starter vocabulary".to_string()
+    };
+    logger(format!(
+        "Search synonyms: {} group{}, {} one-way, {} unit{} from {}",
+        counts.synonyms,
+        if counts.synonyms == 1 { "" } else { "s" },
+        counts.one_way,
+        counts.units,
+        if counts.units == 1 { "" } else { "s" },
+        source
+    ));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expanded(dictionary: &Dictionary, query: &str) -> Vec<Vec<Vec<String>>> {
+        let folded = fold(query);
+        let typed: Vec<&str> = words(&folded).map(|(_, word)| word).collect();
+        dictionary.expand(&typed)
+    }
+
+    fn phrases_of(group: &[Vec<String>]) -> Vec<String> {
+        group.iter().map(|phrase| phrase.join(" ")).collect()
+    }
+
+    #[test]
+    fn the_starter_vocabulary_compiles() {
+        let counts = Dictionary::parse(STARTER).expect("starter parses").counts();
+        assert!(counts.synonyms > 20 && counts.units > 5 && counts.one_way > 0, "{:?}", counts);
+    }
+
+    #[test]
+    fn synonyms_go_both_ways_and_one_way_entries_do_not() {
+        let dictionary = Dictionary::parse(r#"
+            synonyms = [["Egér", "mouse"], ["tápegység", "power supply"]]
+            [one_way]
+            "nyomtató" = ["mfp"]
+        "#).unwrap();
+
+        assert_eq!(phrases_of(&expanded(&dictionary, "egér")[0]), ["eger", "mouse"]);
+        assert_eq!(phrases_of(&expanded(&dictionary, "MOUSE")[0]), ["mouse", "eger"]);
+        assert_eq!(phrases_of(&expanded(&dictionary, "nyomtató")[0]), ["nyomtato", "mfp"]);
+        assert_eq!(phrases_of(&expanded(&dictionary, "mfp")[0]), ["mfp"]);
+
+        // A phrase is taken whole, ahead of its words.
+        let groups = expanded(&dictionary, "500w power supply");
+        assert_eq!(groups.len(), 2);
+        assert_eq!(phrases_of(&groups[1]), ["power supply", "tapegyseg"]);
+    }
+
+    #[test]
+    fn a_quantity_is_found_glued_or_apart_under_every_alias() {
+        let dictionary = Dictionary::parse(r#"units = [["tb", "terabyte"]]"#).unwrap();
+
+        let glued = phrases_of(&expanded(&dictionary, "2TB")[0]);
+        assert_eq!(glued, ["2tb", "2 tb", "2terabyte", "2 terabyte"]);
+
+        let apart = expanded(&dictionary, "2.5 terabyte ssd");
+        assert_eq!(apart.len(), 2);
+        assert_eq!(phrases_of(&apart[0]), ["2.5 terabyte", "2.5terabyte", "2.5tb", "2.5 tb"]);
+
+        // A unit on its own is a plain synonym.
+        assert_eq!(phrases_of(&expanded(&dictionary, "terabyte")[0]), ["terabyte", "tb"]);
+        // A code that merely ends in letters is not a quantity.
+        assert_eq!(phrases_of(&expanded(&dictionary, "x2tb")[0]), ["x2tb"]);
+    }
+
+    #[test]
+    fn a_bad_entry_refuses_the_whole_file() {
+        assert!(Dictionary::parse(r#"synonyms = [["egér"]]"#).unwrap_err().contains("at least two"));
+        assert!(Dictionary::parse(r#"synonyms = [["egér", " , "]]"#).unwrap_err().contains("empty entry"));
+        assert!(Dictionary::parse(r#"units = [["tb", "tera byte"]]"#).unwrap_err().contains("single word"));
+        assert!(Dictionary::parse("[one_way]\nprinter = []").unwrap_err().contains("nothing"));
+        assert!(Dictionary::parse("synonym = []").is_err(), "unknown keys are typos");
+    }
+}
@@ -0,0 +1,453 @@
+//! Search syntax: operators inside a query string.
+//!
+//! People ask for "27 inch monitor under 80000 in stock", and an assistant can
+//! say that precisely — `27 inch monitor price<80000 stock>0` — if the query
+//! has words for it. This reads a query into:
+//!
+//! * plain words, ranked as before (`service/mcp/rank`), synonyms and all;
+//! * `"quoted phrases"`, whose words must stand together, as written;
+//! * negations — `-refurbished`, `-"open box"` — which drop every product
+//!   they match;
+//! * field filters — `brand:Samsung`, `category:"Tinta patron"` — which narrow
+//!   like [`SearchFilters`](crate::service::mcp::index::SearchFilters) does;
+//! * numeric comparisons on price, stock, weight and the three dimensions —
+//!   `price<80000`, `stock>0`, `weight:1..2.5`.
+//!
+//! Field names are accepted in English and Hungarian (`ár<80000`,
+//! `márka:hp`). Anything that does not parse as an operator is searched for as
+//! text, so a query written before this existed reads exactly as it did — an
+//! article number with a colon in it still finds its product.
+//!
+//! Product attributes (`RAM: 16 GB`) are not part of the query: their names are
+//! the ERP's own and collide with ordinary words, so they arrive as a separate
+//! name → value map and are read by [`attribute`].
+
+use crate::service::mcp::index::fold;
+
+/// Opens or closes a phrase. Hungarian text quotes as „this”, and a phone
+/// keyboard may produce “this”, so those count too.
+const QUOTES: &[char] = &['"', '„', '“', '”'];
+
+
+/// A field a text filter applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextField {
+    Brand,
+    /// Product group, by code or name.
+    Category,
+    /// Main group, by code or name.
+    MainCategory
+}
+
+/// A field a numeric comparison applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberField {
+    /// What the partner pays.
+    Price,
+    Stock,
+    Weight,
+    /// Octopus's size `x`.
+    Width,
+    /// Octopus's size `y`.
+    Height,
+    /// Octopus's size `z`.
+    Depth
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual
+}
+
+impl Comparison {
+    pub fn holds(self, value: f64, bound: f64) -> bool {
+        match self {
+            Comparison::Less => value < bound,
+            Comparison::LessOrEqual => value <= bound,
+            Comparison::Greater => value > bound,
+            Comparison::GreaterOrEqual => value >= bound,
+            Comparison::Equal => value == bound,
+            Comparison::NotEqual => value != bound
+        }
+    }
+
+    /// The comparison a leading `-` turns this into.
+    fn negated(self) -> Self {
+        match self {
+            Comparison::Less => Comparison::GreaterOrEqual,
+            Comparison::LessOrEqual => Comparison::Greater,
+            Comparison::Greater => Comparison::LessOrEqual,
+            Comparison::GreaterOrEqual => Comparison::Less,
+            Comparison::Equal => Comparison::NotEqual,
+            Comparison::NotEqual => Comparison::Equal
+        }
+    }
+}
+
+
+/// What an attribute's value must be.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeTest {
+    /// The whole value, folded: `Windows 11 Pro`.
+    Is(String),
+    /// The number the value starts with: `16 GB` reads as 16.
+    Compare { comparison: Comparison, bound: f64 }
+}
+
+impl AttributeTest {
+    pub fn holds(&self, value: &str) -> bool {
+        match self {
+            AttributeTest::Is(expected) => fold(value.trim()) == *expected,
+            AttributeTest::Compare { comparison, bound } =>
+                leading_number(value).is_some_and(|number| comparison.holds(number, *bound))
+        }
+    }
+}
+
+
+/// One condition a product must meet, read from a query.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
+    /// The field holds the (folded) value, or with `negated` does not.
+    Text { field: TextField, value: String, negated: bool },
+    /// The field has a value and it compares as asked. A product without one
+    /// never passes: "under 80000" does not mean "or unpriced".
+    Number { field: NumberField, comparison: Comparison, bound: f64 },
+    /// The product has an attribute whose code or name folds to `name`, with
+    /// a value passing `test`. Several values of one attribute pass if any
+    /// does.
+    Attribute { name: String, test: AttributeTest }
+}
+
+impl Predicate {
+    /// Whether the product's master record is needed to test this, rather
+    /// than the partner's offer or the search haystacks.
+    pub fn needs_record(&self) -> bool {
+        matches!(self, Predicate::Number { field, .. } if !matches!(field, NumberField::Price | NumberField::Stock))
+    }
+}
+
+
+/// A query, taken apart.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Parsed {
+    /// The plain words, in the order typed, for ranking.
+    pub text: String,
+    /// Quoted phrases that must match.
+    pub phrases: Vec<String>,
+    /// Words after a `-`: a product matching any of them is dropped.
+    pub excluded: Vec<String>,
+    /// Quoted phrases after a `-`.
+    pub excluded_phrases: Vec<String>,
+    pub predicates: Vec<Predicate>
+}
+
+
+/// Reads a query. Never fails: what is not an operator is text.
+pub fn parse(query: &str) -> Parsed {
+    let chars: Vec<char> = query.chars().collect();
+    let mut parsed = Parsed::default();
+    let mut words: Vec<String> = Vec::new();
+    let mut at = 0;
+
+    while at < chars.len() {
+        if chars[at].is_whitespace() {
+            at += 1;
+            continue
+        }
+
+        // A `-` negates only at the start of a word, and only a word or a
+        // phrase: `-5` is a number, `ABC-5` an article number.
+        let negated = chars[at] == '-'
+            && chars.get(at + 1).is_some_and(|next| next.is_alphabetic() || QUOTES.contains(next));
+        if negated {
+            at += 1;
+        }
+
+        if QUOTES.contains(&chars[at]) {
+            let (phrase, next) = quoted(&chars, at);
+            at = next;
+            if phrase.trim().is_empty() {
+                continue
+            }
+            if negated {
+                parsed.excluded_phrases.push(phrase);
+            } else {
+                parsed.phrases.push(phrase);
+            }
+            continue
+        }
+
+        let (token, next) = token(&chars, at);
+        at = next;
+        if let Some(predicates) = predicates(&token, negated) {
+            parsed.predicates.extend(predicates);
+        } else if negated {
+            parsed.excluded.push(token);
+        } else {
+            words.push(token);
+        }
+    }
+
+    parsed.text = words.join(" ");
+    parsed
+}
+
+/// The text of a phrase opening at `at`, and where reading resumes. An
+/// unclosed quote runs to the end.
+fn quoted(chars: &[char], at: usize) -> (String, usize) {
+    let start = at + 1;
+    let end = chars[start..].iter().position(|c| QUOTES.contains(c)).map_or(chars.len(), |length| start + length);
+    (chars[start..end].iter().collect(), (end + 1).min(chars.len()))
+}
+
+/// One whitespace-separated token. A quoted value right after an operator
+/// (`brand:"Hewlett Packard"`) belongs to the token, spaces included.
+fn token(chars: &[char], at: usize) -> (String, usize) {
+    let mut token = String::new();
+    let mut position = at;
+    while position < chars.len() && !chars[position].is_whitespace() {
+        let c = chars[position];
+        if QUOTES.contains(&c) && position > at && matches!(chars[position - 1], ':' | '=') {
+            let (value, next) = quoted(chars, position);
+            token.push('"');
+            token.push_str(&value);
+            token.push('"');
+            position = next;
+            continue
+        }
+        token.push(c);
+        position += 1;
+    }
+    (token, position)
+}
+
+/// The conditions a `field<op>value` token stands for, or `None` when it is
+/// not one.
+fn predicates(token: &str, negated: bool) -> Option<Vec<Predicate>> {
+    let name_end = token.find(|c: char| !(c.is_alphabetic() || c == '_'))?;
+    let (name, rest) = token.split_at(name_end);
+    let (operator, value) = [("<=", Comparison::LessOrEqual), (">=", Comparison::GreaterOrEqual),
+        ("<", Comparison::Less), (">", Comparison::Greater), ("=", Comparison::Equal), (":", Comparison::Equal)]
+        .into_iter()
+        .find_map(|(symbol, comparison)| rest.strip_prefix(symbol).map(|value| ((symbol, comparison), value)))?;
+    let value = value.trim_matches('"');
+    if value.is_empty() {
+        return None
+    }
+
+    if let Some(field) = text_field(name) {
+        // Text is matched, not ordered: only `:` and `=` mean anything.
+        return matches!(operator.0, ":" | "=").then(|| vec![Predicate::Text {
+            field,
+            value: fold(value),
+            negated
+        }])
+    }
+
+    let field = number_field(name)?;
+    let compare = |comparison: Comparison, bound: f64| Predicate::Number {
+        field,
+        comparison: if negated { comparison.negated() } else { comparison },
+        bound
+    };
+
+    // `weight:1..2.5`, either end optional. Negating a range would need an
+    // "or", which a list of conditions cannot say, so it is left as text.
+    if operator.0 == ":" && let Some((low, high)) = value.split_once("..") {
+        if negated {
+            return None
+        }
+        let mut range = Vec::new();
+        if !low.is_empty() {
+            range.push(compare(Comparison::GreaterOrEqual, number(low)?));
+        }
+        if !high.is_empty() {
+            range.push(compare(Comparison::LessOrEqual, number(high)?));
+        }
+        return (!range.is_empty()).then_some(range)
+    }
+
+    Some(vec![compare(operator.1, number(value)?)])
+}
+
+fn text_field(name: &str) -> Option<TextField> {
+    match fold(name).as_str() {
+        "brand" | "marka" | "gyarto" => Some(TextField::Brand),
+        "category" | "cat" | "kategoria" | "csoport" => Some(TextField::Category),
+        "main_category" | "maincategory" | "main" | "fokategoria" | "focsoport" => Some(TextField::MainCategory),
+        _ => None
+    }
+}
+
+fn number_field(name: &str) -> Option<NumberField> {
+    match fold(name).as_str() {
+        "price" | "ar" => Some(NumberField::Price),
+        "stock" | "keszlet" => Some(NumberField::Stock),
+        "weight" | "suly" => Some(NumberField::Weight),
+        "width" | "x" | "szelesseg" => Some(NumberField::Width),
+        "height" | "y" | "magassag" => Some(NumberField::Height),
+        "depth" | "z" | "melyseg" => Some(NumberField::Depth),
+        _ => None
+    }
+}
+
+/// A number as people type it: `79.99` or `79,99`.
+fn number(text: &str) -> Option<f64> {
+    text.replace(',', ".").parse::<f64>().ok().filter(|value| value.is_finite())
+}
+
+/// The number an attribute value starts with: `16 GB` is 16, `2,5"` is 2.5.
+fn leading_number(value: &str) -> Option<f64> {
+    let value = value.trim_start();
+    let end = value.char_indices()
+        .find(|&(at, c)| !(c.is_ascii_digit() || c == '.' || c == ',' || (at == 0 && c == '-')))
+        .map_or(value.len(), |(at, _)| at);
+    number(value[..end].trim_end_matches(['.', ',']))
+}
+
+
+/// The conditions one attribute filter stands for. `value` is a comparison
+/// (`>=16`, `<8`), a range (`8..16`), a number (`16`, which matches `16 GB`
+/// too) or text matched whole (`Windows 11 Pro`).
+pub fn attribute(name: &str, value: &str) -> Result<Vec<Predicate>, String> {
+    let label = name.trim();
+    let name = fold(label);
+    let value = value.trim();
+    if name.is_empty() {
+        return Err("an attribute filter needs the attribute's code or name".into())
+    }
+    if value.is_empty() {
+        return Err(format!("attribute '{}' needs a value to filter on", label))
+    }
+    let compare = |comparison: Comparison, bound: f64| Predicate::Attribute {
+        name: name.clone(),
+        test: AttributeTest::Compare { comparison, bound }
+    };
+    let bound = |text: &str| number(text.trim())
+        .ok_or_else(|| format!("'{}' in the filter on attribute '{}' is not a number", text.trim(), label));
+
+    if let Some((low, high)) = value.split_once("..") {
+        let mut range = Vec::new();
+        if !low.trim().is_empty() {
+            range.push(compare(Comparison::GreaterOrEqual, bound(low)?));
+        }
+        if !high.trim().is_empty() {
+            range.push(compare(Comparison::LessOrEqual, bound(high)?));
+        }
+        if range.is_empty() {
+            return Err(format!("the range on attribute '{}' has neither end", label))
+        }
+        return Ok(range)
+    }
+
+    for (symbol, comparison) in [("<=", Comparison::LessOrEqual), (">=", Comparison::GreaterOrEqual),
+        ("<", Comparison::Less), (">", Comparison::Greater)] {
+        if let Some(rest) = value.strip_prefix(symbol) {
+            return Ok(vec![compare(comparison, bound(rest)?)])
+        }
+    }
+
+    let value = value.strip_prefix('=').map_or(value, str::trim);
+    Ok(vec![match number(value) {
+        Some(number) => compare(Comparison::Equal, number),
+        None => Predicate::Attribute { name, test: AttributeTest::Is(fold(value)) }
+    }])
+}
+
+/// Attribute filters written on one line, as a query string carries them:
+/// `RAM:>=16;OS:Windows 11 Pro`.
+pub fn attribute_list(text: &str) -> Result<Vec<Predicate>, String> {
+    let mut predicates = Vec::new();
+    for entry in text.split(';').filter(|entry| !entry.trim().is_empty()) {
+        let Some((name, value)) = entry.split_once(':') else {
+            return Err(format!("'{}' is not an attribute:value pair", entry.trim()))
+        };
+        predicates.extend(attribute(name, value)?);
+    }
+    Ok(predicates)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(comparison: Comparison, bound: f64) -> Predicate {
+        Predicate::Number { field: NumberField::Price, comparison, bound }
+    }
+
+    #[test]
+    fn operators_are_lifted_out_of_the_words() {
+        let parsed = parse("27 inch monitor price<80000 stock>0 brand:Samsung");
+        assert_eq!(parsed.text, "27 inch monitor");
+        assert_eq!(parsed.predicates, [
+            price(Comparison::Less, 80000.0),
+            Predicate::Number { field: NumberField::Stock, comparison: Comparison::Greater, bound: 0.0 },
+            Predicate::Text { field: TextField::Brand, value: "samsung".into(), negated: false }
+        ]);
+    }
+
+    #[test]
+    fn phrases_and_negations_are_kept_apart() {
+        let parsed = parse("„open box” laptop -refurbished -\"b grade\" -brand:acer");
+        assert_eq!(parsed.text, "laptop");
+        assert_eq!(parsed.phrases, ["open box"]);
+        assert_eq!(parsed.excluded, ["refurbished"]);
+        assert_eq!(parsed.excluded_phrases, ["b grade"]);
+        assert_eq!(parsed.predicates, [Predicate::Text { field: TextField::Brand, value: "acer".into(), negated: true }]);
+    }
+
+    #[test]
+    fn hungarian_names_ranges_and_quoted_values_parse() {
+        let parsed = parse("ár<=12,5 súly:1..2 márka:\"Hewlett Packard\" -ár>100");
+        assert_eq!(parsed.text, "");
+        assert_eq!(parsed.predicates, [
+            price(Comparison::LessOrEqual, 12.5),
+            Predicate::Number { field: NumberField::Weight, comparison: Comparison::GreaterOrEqual, bound: 1.0 },
+            Predicate::Number { field: NumberField::Weight, comparison: Comparison::LessOrEqual, bound: 2.0 },
+            Predicate::Text { field: TextField::Brand, value: "hewlett packard".into(), negated: false },
+            price(Comparison::LessOrEqual, 100.0)
+        ]);
+    }
+
+    #[test]
+    fn attribute_filters_read_numbers_ranges_and_text() {
+        let ram = |comparison, bound| Predicate::Attribute {
+            name: "ram".into(),
+            test: AttributeTest::Compare { comparison, bound }
+        };
+        assert_eq!(attribute("RAM", "16"), Ok(vec![ram(Comparison::Equal, 16.0)]));
+        assert_eq!(attribute(" ram ", ">= 8"), Ok(vec![ram(Comparison::GreaterOrEqual, 8.0)]));
+        assert_eq!(attribute("ram", "8..16"), Ok(vec![
+            ram(Comparison::GreaterOrEqual, 8.0),
+            ram(Comparison::LessOrEqual, 16.0)
+        ]));
+        assert_eq!(attribute("Operációs rendszer", "Windows 11 Pro"), Ok(vec![Predicate::Attribute {
+            name: "operacios rendszer".into(),
+            test: AttributeTest::Is("windows 11 pro".into())
+        }]));
+        assert!(attribute("ram", "..").is_err());
+        assert!(attribute("ram", ">lots").is_err());
+        assert!(attribute("", "16").is_err());
+
+        let test = AttributeTest::Compare { comparison: Comparison::Equal, bound: 16.0 };
+        assert!(test.holds("16 GB") && test.holds("16") && !test.holds("160 GB") && !test.holds("DDR4"));
+        assert!(AttributeTest::Is("ddr4".into()).holds(" DDR4"));
+
+        assert_eq!(attribute_list("RAM:>=8; ram:..16;").map(|list| list.len()), Ok(2));
+        assert!(attribute_list("RAM 16").is_err());
+    }
+
+    #[test]
+    fn what_is_not_an_operator_stays_text() {
+        let parsed = parse("HP-305XL 27\" price<cheap colour:red -5 a4:5");
+        assert_eq!(parsed.text, "HP-305XL 27\" price<cheap colour:red -5 a4:5");
+        assert!(parsed.predicates.is_empty());
+    }
+}
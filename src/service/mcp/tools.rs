@@ -7,12 +7,20 @@
 //! partner argument** — the pid is fixed per user by their connector config, so
 //! a model cannot ask for another partner's prices.
 //!
-//! Four tools, shaped by what people ask rather than by SOAP operation. Every
+//! Thirteen tools, shaped by what people ask rather than by SOAP operation. Every
 //! tool definition costs context on every request, so there is deliberately no
 //! tool-per-endpoint mapping, and deliberately **no sync tool**: refresh is the
 //! precache job's business, and a model-triggered 28-second sync is exactly what
 //! this design exists to prevent.
-
+//!
+//! A caller signed in through OAuth is limited to its token's scopes: the
+//! reading tools need `catalog.read`, `export_products` needs `catalog.export`,
+//! and so do `check_availability` when it is asked for a spreadsheet and
+//! `finalize_quote`, which writes the quote's documents. The order tools need
+//! `orders.write`, and `draft_order`, which reads prices, `catalog.read` too.
+//! Each tool names its scope when it asks for the snapshot.
+
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -30,16 +38,20 @@ use serde_json::json;
 
 use crate::{
     macros::mcp::McpToolArgs,
+    routes::{self, default::xmlns_for_url},
     service::{
         log::{elogger, logger},
         mcp::{
             AUTHCODE_HEADER, McpAuth, PID_HEADER,
             cache::cache,
-            export,
+            availability, export,
             index::{CatalogSnapshot, SearchFilters, fold},
-            mask_authcode
+            mask_authcode,
+            oauth::TokenScope,
+            order, quote, syntax
         },
-        soap_config::get_default_url
+        soap_config::get_default_url,
+        soap_gate::{self, Lane}
     }
 };
 
@@ -58,6 +70,14 @@ const MAX_SEARCH_LIMIT: usize = 100;
 /// Categories listed per kind before the list is truncated.
 const DEFAULT_CATEGORY_LIMIT: usize = 40;
 
+/// Attributes listed before the list is truncated, and values per attribute.
+const DEFAULT_ATTRIBUTE_LIMIT: usize = 40;
+const DEFAULT_VALUE_LIMIT: usize = 20;
+
+/// Entries per facet list in a search result, values per attribute included.
+/// The full breakdown is `list_categories`' and `list_attributes`' job.
+const SEARCH_FACET_LIMIT: usize = 8;
+
 /// Near matches offered when an article number is not found.
 const DID_YOU_MEAN_LIMIT: usize = 5;
 
@@ -82,7 +102,7 @@ fn caller_identity(context: &RequestContext<RoleServer>) -> String {
 /// `invalid type: string "20", expected u32` — a message that reads like a server
 /// bug and blocks the call entirely. Accepting both costs nothing and is the
 /// difference between a working tool and a dead end for the person asking.
-fn lenient_count<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+pub(super) fn lenient_count<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
 where
     D: serde::Deserializer<'de>
 {
@@ -114,8 +134,18 @@ where
 McpToolArgs! {
     pub struct SearchProductsArgs {
         /// Words to search for. Accent- and case-insensitive: `szovegkiemelo`
-        /// matches `Szövegkiemelő`. Every word must match somewhere. A barcode
-        /// (EAN) or a manufacturer part number can be pasted here directly.
+        /// matches `Szövegkiemelő`. Every word must match somewhere, but the
+        /// start of a word is enough (`monit`) and small typos are forgiven
+        /// (`samsnug`). Hungarian and English names find each other (`egér`,
+        /// `mouse`), as do unit spellings (`2tb`, `2 TB`). A barcode (EAN) or
+        /// a manufacturer part number can be pasted here directly.
+        ///
+        /// Operators narrow the search: `"exact phrase"`, `-word` to exclude,
+        /// `brand:Samsung`, `category:…`, `main_category:…`, and comparisons on
+        /// `price` (what this partner pays), `stock`, `weight`, `width`,
+        /// `height` and `depth` — `price<80000`, `stock>0`, `weight:1..2.5`.
+        /// "27 inch monitor under 80000 in stock" is
+        /// `27 inch monitor price<80000 stock>0`.
         pub query: String,
         /// Restrict to a brand / manufacturer, e.g. "Orink".
         pub brand: Option<String>,
@@ -123,6 +153,12 @@ McpToolArgs! {
         pub category: Option<String>,
         /// Restrict to a main group, by code or name.
         pub main_category: Option<String>,
+        /// Filter on product attributes, keyed by attribute code or name as
+        /// `list_attributes` shows them. A value is matched whole
+        /// (`"DDR4"`), as a number (`"16"` also matches `16 GB`), as a
+        /// comparison (`">=16"`) or as a range (`"8..16"`). Every entry must
+        /// hold.
+        pub attributes: Option<BTreeMap<String, String>>,
         /// How many results to return. Clamped to 100.
         #[serde(default, deserialize_with = "lenient_count")]
         pub limit: Option<u32>,
@@ -140,6 +176,100 @@ McpToolArgs! {
         pub no: String
     }
 
+    pub struct CheckAvailabilityArgs {
+        /// The lines to check, each a code and a quantity, in the order the
+        /// customer listed them. Up to 500.
+        pub items: Vec<availability::CheckItem>,
+        /// `xlsx` or `csv` to also get the result as a spreadsheet quote.
+        /// Omit for the answer inline only.
+        pub format: Option<String>
+    }
+
+    pub struct CreateQuoteArgs {
+        /// Who the quote is for, as it should be printed.
+        pub customer: Option<String>,
+        /// The partner's own company name, printed at the top of the quote.
+        pub seller: Option<String>,
+        /// The customer's reference — an RFQ or project number.
+        pub reference: Option<String>,
+        /// Free text printed under the totals: delivery terms, conditions.
+        pub note: Option<String>,
+        /// Lines to start with: article number, barcode (EAN) or manufacturer
+        /// part number, each with a quantity.
+        pub items: Option<Vec<availability::CheckItem>>,
+        /// Markups to start with.
+        pub markups: Option<Vec<quote::MarkupRule>>
+    }
+
+    pub struct UpdateQuoteArgs {
+        /// The `quote_id` `create_quote` returned.
+        pub quote_id: String,
+        /// Products to add. One already on the quote takes the new quantity.
+        pub add: Option<Vec<availability::CheckItem>>,
+        /// Article numbers to take off the quote.
+        pub remove: Option<Vec<String>>,
+        /// End-user markups, per `brand`, per `category`, or — naming
+        /// neither — for everything else. Replaces an earlier markup for the
+        /// same brand or category.
+        pub markups: Option<Vec<quote::MarkupRule>>,
+        /// Replace the customer; an empty string clears it.
+        pub customer: Option<String>,
+        /// Replace the seller; an empty string clears it.
+        pub seller: Option<String>,
+        /// Replace the reference; an empty string clears it.
+        pub reference: Option<String>,
+        /// Replace the note; an empty string clears it.
+        pub note: Option<String>
+    }
+
+    pub struct FinalizeQuoteArgs {
+        /// The `quote_id` `create_quote` returned.
+        pub quote_id: String,
+        /// How many days the offer stands. The server's default when omitted.
+        #[serde(default, deserialize_with = "lenient_count")]
+        pub valid_days: Option<u32>
+    }
+
+    pub struct DraftOrderArgs {
+        /// The lines to order: article number, barcode (EAN) or manufacturer
+        /// part number, each with a quantity. Each product once. Up to 500.
+        pub items: Vec<availability::CheckItem>,
+        /// Redraft this order instead of starting a new one. Its lines and
+        /// particulars are replaced whole, and its earlier confirmation token
+        /// stops working.
+        pub order_id: Option<String>,
+        /// The partner's own order number, printed on Orink's paperwork.
+        pub reference: Option<String>,
+        /// Octopus delivery mode code. `1` when omitted.
+        #[serde(default, deserialize_with = "lenient_count")]
+        pub delivery_mode: Option<u32>,
+        /// A note for the delivery.
+        pub delivery_note: Option<String>,
+        /// A note on the order.
+        pub note: Option<String>,
+        /// A note for the warehouse.
+        pub note_warehouse: Option<String>,
+        /// Contact phone for this order.
+        pub contact_phone: Option<String>,
+        /// Contact email for this order.
+        pub contact_email: Option<String>,
+        /// Where to deliver, when not to the partner's usual address.
+        pub delivery_address: Option<order::DeliveryAddress>
+    }
+
+    pub struct ShowOrderArgs {
+        /// The `order_id` `draft_order` returned.
+        pub order_id: String
+    }
+
+    pub struct SubmitOrderArgs {
+        /// The `order_id` `draft_order` returned.
+        pub order_id: String,
+        /// The `confirmation_token` `draft_order` returned with the version of
+        /// the order the user agreed to.
+        pub confirmation_token: String
+    }
+
     pub struct ListCategoriesArgs {
         /// Which grouping to list: `brands`, `main_categories`, `categories`, or
         /// `all` (the default).
@@ -149,11 +279,25 @@ McpToolArgs! {
         pub limit: Option<u32>
     }
 
+    pub struct ListAttributesArgs {
+        /// Show one attribute only, by code or name, with all its values.
+        pub attribute: Option<String>,
+        /// How many attributes to list. Clamped to 200.
+        #[serde(default, deserialize_with = "lenient_count")]
+        pub limit: Option<u32>,
+        /// How many values to show per attribute, most common first. Clamped
+        /// to 200.
+        #[serde(default, deserialize_with = "lenient_count")]
+        pub values: Option<u32>
+    }
+
     pub struct ExportProductsArgs {
         /// `xlsx` (default, for Excel) or `csv` (semicolon-delimited, as the
         /// REST endpoints produce).
         pub format: Option<String>,
-        /// Optional words to narrow the export. Omit to export everything.
+        /// Optional words to narrow the export, with the same operators as
+        /// `search_products` (`price<80000`, `stock>0`, `-refurbished`, …).
+        /// Omit to export everything.
         pub query: Option<String>,
         /// Restrict to a brand / manufacturer.
         pub brand: Option<String>,
@@ -191,19 +335,24 @@ impl RustopusMcp {
         Self { tool_router: Self::tool_router() }
     }
 
-    /// Search the catalog. The workhorse: name, article number, barcode, brand
-    /// and manufacturer part number in one ranked pass, with the caller's own
-    /// price and stock inline so a follow-up call is not needed to answer
-    /// "how much".
-    #[tool(description = "Search Orink products by name, article number, barcode (EAN), brand or \
-        manufacturer part number. Accent-insensitive. Returns this partner's own price and current \
-        stock inline.")]
+    /// Search the catalog. The workhorse: name, article number, barcode, brand,
+    /// manufacturer part number and description in one relevance-ranked pass,
+    /// with the caller's own price and stock inline so a follow-up call is not
+    /// needed to answer "how much".
+    #[tool(description = "Search Orink products by name, article number, barcode (EAN), brand, \
+        manufacturer part number or description. Accent-insensitive, tolerant of word prefixes and \
+        small typos, with Hungarian/English synonyms, ranked by relevance. The query also takes operators: \
+        \"phrase\", -exclude, brand:X, category:X, and price/stock/weight/width/height/depth comparisons \
+        (price<80000 stock>0). `attributes` filters on technical attributes such as {\"RAM\": \">=16\"}; \
+        list_attributes shows which exist. Each row's `highlight` marks what matched with **. `facets` breaks \
+        down every match by brand, group, stock, price band and attribute, to narrow a large result. \
+        Returns this partner's own price and current stock inline.")]
     async fn search_products(
         &self,
         context: RequestContext<RoleServer>,
         Parameters(args): Parameters<SearchProductsArgs>
     ) -> Result<CallToolResult, McpError> {
-        let snapshot = match self.snapshot(&context).await {
+        let snapshot = match self.snapshot(&context, TokenScope::CatalogRead).await {
             Ok(snapshot) => snapshot,
             Err(result) => return Ok(result)
         };
@@ -216,26 +365,36 @@ impl RustopusMcp {
 
         let limit = clamp(args.limit, DEFAULT_SEARCH_LIMIT, MAX_SEARCH_LIMIT);
         let offset = args.offset.unwrap_or(0) as usize;
-        let filters = SearchFilters {
+        let mut filters = SearchFilters {
             brand: args.brand.as_deref().map(fold),
             category: args.category.as_deref().map(fold),
-            main_category: args.main_category.as_deref().map(fold)
+            main_category: args.main_category.as_deref().map(fold),
+            ..SearchFilters::default()
         };
+        for (name, value) in args.attributes.iter().flatten() {
+            match syntax::attribute(name, value) {
+                Ok(predicates) => filters.predicates.extend(predicates),
+                Err(message) => return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "{}. Use list_attributes to see the attributes and their values.", message
+                ))]))
+            }
+        }
 
         let outcome = snapshot.search_page(&args.query, &filters, offset, limit);
 
         logger(format!(
-            "MCP tool 'search_products' by {}: query='{}' brand={:?} category={:?} main_category={:?} offset={} limit={} -> matched={} returned={}",
+            "MCP tool 'search_products' by {}: query='{}' brand={:?} category={:?} main_category={:?} attributes={:?} offset={} limit={} -> matched={} returned={}",
             caller_identity(&context), args.query, args.brand, args.category, args.main_category,
-            offset, limit, outcome.matched, outcome.results.len()
+            args.attributes, offset, limit, outcome.matched, outcome.results.len()
         ));
 
         if outcome.matched == 0 {
             // An empty result set is actionable, not exceptional: the model can
             // retry with fewer words or a different spelling.
             return Ok(CallToolResult::error(vec![Content::text(format!(
-                "No product matched '{}'{}. Every word must match; try fewer words, \
-                 or use list_categories to see the available brands and groups.",
+                "No product matched '{}'{}. Every word and condition must match; try fewer \
+                 words or looser conditions, or use list_categories and list_attributes to see the \
+                 available brands, groups and attribute values.",
                 args.query,
                 describe_filters(&args)
             ))]))
@@ -250,7 +409,8 @@ impl RustopusMcp {
             "offset": offset,
             "has_more": has_more,
             "catalog_age_seconds": snapshot.age_secs(),
-            "results": outcome.results
+            "results": outcome.results,
+            "facets": outcome.facets.top(SEARCH_FACET_LIMIT)
         });
 
         if has_more && let Some(object) = payload.as_object_mut() {
@@ -279,7 +439,7 @@ impl RustopusMcp {
         context: RequestContext<RoleServer>,
         Parameters(args): Parameters<ExportProductsArgs>
     ) -> Result<CallToolResult, McpError> {
-        let snapshot = match self.snapshot(&context).await {
+        let snapshot = match self.snapshot(&context, TokenScope::CatalogExport).await {
             Ok(snapshot) => snapshot,
             Err(result) => return Ok(result)
         };
@@ -294,7 +454,8 @@ impl RustopusMcp {
         let filters = SearchFilters {
             brand: args.brand.as_deref().map(fold),
             category: args.category.as_deref().map(fold),
-            main_category: args.main_category.as_deref().map(fold)
+            main_category: args.main_category.as_deref().map(fold),
+            ..SearchFilters::default()
         };
 
         // An empty query means "everything that passes the filters", which is the
@@ -365,7 +526,7 @@ impl RustopusMcp {
         context: RequestContext<RoleServer>,
         Parameters(args): Parameters<GetProductArgs>
     ) -> Result<CallToolResult, McpError> {
-        let snapshot = match self.snapshot(&context).await {
+        let snapshot = match self.snapshot(&context, TokenScope::CatalogRead).await {
             Ok(snapshot) => snapshot,
             Err(result) => return Ok(result)
         };
@@ -374,7 +535,7 @@ impl RustopusMcp {
             Some(product) => {
                 logger(format!(
                     "MCP tool 'get_product' by {}: no='{}' -> found (available={})",
-                    caller_identity(&context), args.no, product.available
+                    caller_identity(&context), args.no, product.offer.available
                 ));
                 let mut payload = json!({
                     "catalog_age_seconds": snapshot.age_secs(),
@@ -385,10 +546,10 @@ impl RustopusMcp {
                 // caller should never have to infer "primary" from array order.
                 if let Some(product_object) = payload["product"].as_object_mut() {
                     product_object.remove("barcodes");
-                    if let Some(primary) = product.barcodes.first() {
+                    if let Some(primary) = product.product.barcodes.first() {
                         product_object.insert("primary_barcode".into(), json!(primary));
                     }
-                    let secondary: Vec<&String> = product.barcodes.iter().skip(1).collect();
+                    let secondary: Vec<&String> = product.product.barcodes.iter().skip(1).collect();
                     if !secondary.is_empty() {
                         product_object.insert("secondary_barcodes".into(), json!(secondary));
                     }
@@ -396,13 +557,13 @@ impl RustopusMcp {
                 // A withheld product is served rather than hidden — the caller
                 // asked for it by article number — but the record alone reads
                 // like any other, so say plainly that it is not on offer.
-                if !product.available && let Some(object) = payload.as_object_mut() {
+                if !product.offer.available && let Some(object) = payload.as_object_mut() {
                     object.insert(
                         "note".into(),
                         json!(format!(
                             "'{}' exists in the catalog but is not currently published for sale, \
                             so it does not appear in search or export results.",
-                            product.no
+                            product.product.no
                         ))
                     );
                 }
@@ -433,6 +594,398 @@ impl RustopusMcp {
         }
     }
 
+    /// A whole RFQ in one call: every line resolved like `get_product`, priced
+    /// and held against stock, with a spreadsheet on request.
+    #[tool(description = "Check a list of Orink products at once — typically a customer's request for \
+        quotation. Each item is an article number, barcode (EAN) or manufacturer part number with a quantity. \
+        Returns per line the product found, this partner's price, stock, how much is available now, the \
+        shortfall and the line total, with near matches for codes not found, plus totals. Pass `format` \
+        (xlsx or csv) to also get the result as a downloadable spreadsheet.")]
+    async fn check_availability(
+        &self,
+        context: RequestContext<RoleServer>,
+        Parameters(args): Parameters<CheckAvailabilityArgs>
+    ) -> Result<CallToolResult, McpError> {
+        // A spreadsheet is a file handed out by link, so it needs the same
+        // grant as an export; the inline answer is an ordinary read.
+        let scope = if args.format.is_some() { TokenScope::CatalogExport } else { TokenScope::CatalogRead };
+        let snapshot = match self.snapshot(&context, scope).await {
+            Ok(snapshot) => snapshot,
+            Err(result) => return Ok(result)
+        };
+
+        let format = match args.format.as_deref().map(|format| export::Format::parse(Some(format))) {
+            None => None,
+            Some(Some(format)) => Some(format),
+            Some(None) => return Ok(CallToolResult::error(vec![Content::text(
+                "format must be 'xlsx' or 'csv'."
+            )]))
+        };
+
+        let checked = match availability::check(&snapshot, &args.items) {
+            Ok(checked) => checked,
+            Err(error) => return Ok(CallToolResult::error(vec![Content::text(format!(
+                "The list could not be checked: {}.", error
+            ))]))
+        };
+
+        logger(format!(
+            "MCP tool 'check_availability' by {}: lines={} in_stock={} not_found={} format={}",
+            caller_identity(&context), checked.summary.lines, checked.summary.in_stock,
+            checked.summary.not_found, format.map_or("none", |format| format.extension())
+        ));
+
+        let mut payload = json!({
+            "catalog_age_seconds": snapshot.age_secs(),
+            "summary": checked.summary,
+            "lines": checked.lines
+        });
+
+        let Some(format) = format else {
+            return Ok(json_result(payload))
+        };
+
+        let rows = availability::rows(&checked);
+        let built = actix_web::web::block(move || {
+            export::write_table("orink-availability", &availability::COLUMNS, &rows, format)
+        }).await;
+
+        let prepared = match built {
+            Ok(Ok(prepared)) => prepared,
+            Ok(Err(error)) => {
+                elogger(format!("MCP availability sheet failed for {}: {}", caller_identity(&context), error));
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "The spreadsheet could not be written: {}", error
+                ))]))
+            }
+            Err(error) => {
+                elogger(format!("MCP availability sheet failed to run for {}: {}", caller_identity(&context), error));
+                return Ok(CallToolResult::error(vec![Content::text(
+                    "The spreadsheet could not be started. This is a server-side problem."
+                )]))
+            }
+        };
+
+        if let Some(object) = payload.as_object_mut() {
+            object.insert("file_name".into(), json!(prepared.file_name));
+            object.insert("download_url".into(), json!(prepared.url));
+            object.insert("expires_in_seconds".into(), json!(export::ttl_secs()));
+            object.insert("note".into(), json!(
+                "Give the user this download_url. The link expires, needs no login, and the file \
+                 holds this partner's own prices — do not post it anywhere public."
+            ));
+        }
+        Ok(json_result(payload))
+    }
+
+    /// Starts a quote: the partner's offer to their own customer, priced from
+    /// the catalog with a markup of their choosing.
+    #[tool(description = "Start a quote for one of this partner's customers. Optionally give the customer, \
+        the partner's own company name as `seller`, a reference, a note, starting `items` (article number, \
+        barcode or part number with quantity) and `markups`. Returns a `quote_id` for update_quote and \
+        finalize_quote, and the quote priced: `cost` is what this partner pays, `unit_price` what the \
+        customer pays net of VAT. The partner's cost and margin never appear on the finished documents.")]
+    async fn create_quote(
+        &self,
+        context: RequestContext<RoleServer>,
+        Parameters(args): Parameters<CreateQuoteArgs>
+    ) -> Result<CallToolResult, McpError> {
+        let snapshot = match self.snapshot(&context, TokenScope::CatalogRead).await {
+            Ok(snapshot) => snapshot,
+            Err(result) => return Ok(result)
+        };
+        let auth = match self.caller(&context, TokenScope::CatalogRead) {
+            Ok(auth) => auth,
+            Err(result) => return Ok(result)
+        };
+
+        let changes = quote::Changes {
+            details: quote::Details { customer: args.customer, seller: args.seller, reference: args.reference, note: args.note },
+            add: args.items.unwrap_or_default(),
+            markups: args.markups.unwrap_or_default(),
+            ..quote::Changes::default()
+        };
+        match quote::create(&quote::Owner::new(&auth.authcode, auth.pid), &snapshot, &changes) {
+            Ok(priced) => {
+                logger(format!(
+                    "MCP tool 'create_quote' by {}: {} lines={}",
+                    caller_identity(&context), priced.quote_id, priced.lines.len()
+                ));
+                Ok(json_result(json!({ "quote": priced })))
+            }
+            Err(error) => Ok(CallToolResult::error(vec![Content::text(format!(
+                "The quote was not created: {}.", error
+            ))]))
+        }
+    }
+
+    /// Every change to a quote in one tool: lines, markups and particulars.
+    #[tool(description = "Change a quote: `add` products (or set the quantity of one already on it), `remove` \
+        article numbers, set `markups` per brand, per category or for everything else (percent over this \
+        partner's price; a brand's markup beats a category's, which beats the general one), and replace the \
+        customer, seller, reference or note. Applied whole or not at all. With only `quote_id`, shows the \
+        quote.")]
+    async fn update_quote(
+        &self,
+        context: RequestContext<RoleServer>,
+        Parameters(args): Parameters<UpdateQuoteArgs>
+    ) -> Result<CallToolResult, McpError> {
+        let adding = args.add.as_ref().is_some_and(|add| !add.is_empty());
+        // Only adding a line reads the catalog; anything else is the quote's own.
+        let snapshot = if adding {
+            match self.snapshot(&context, TokenScope::CatalogRead).await {
+                Ok(snapshot) => Some(snapshot),
+                Err(result) => return Ok(result)
+            }
+        } else {
+            None
+        };
+        let auth = match self.caller(&context, TokenScope::CatalogRead) {
+            Ok(auth) => auth,
+            Err(result) => return Ok(result)
+        };
+
+        let changes = quote::Changes {
+            details: quote::Details { customer: args.customer, seller: args.seller, reference: args.reference, note: args.note },
+            add: args.add.unwrap_or_default(),
+            remove: args.remove.unwrap_or_default(),
+            markups: args.markups.unwrap_or_default()
+        };
+        match quote::update(&quote::Owner::new(&auth.authcode, auth.pid), &args.quote_id, snapshot.as_deref(), &changes) {
+            Ok(priced) => {
+                logger(format!(
+                    "MCP tool 'update_quote' by {}: {} added={} removed={} markups={} -> lines={}",
+                    caller_identity(&context), priced.quote_id, changes.add.len(), changes.remove.len(),
+                    changes.markups.len(), priced.lines.len()
+                ));
+                Ok(json_result(json!({ "quote": priced })))
+            }
+            Err(error) => Ok(CallToolResult::error(vec![Content::text(format!(
+                "The quote was not changed: {}.", error
+            ))]))
+        }
+    }
+
+    /// Issues a quote as the two documents a customer is sent.
+    #[tool(description = "Finish a quote: date it, set how many days it is valid (`valid_days`, the server's \
+        default when omitted), and write it as an Excel workbook and a PDF with the customer's prices, VAT \
+        and totals. Returns a download link for each. The quote stays open: change it and finalize again for \
+        a revised version.")]
+    async fn finalize_quote(
+        &self,
+        context: RequestContext<RoleServer>,
+        Parameters(args): Parameters<FinalizeQuoteArgs>
+    ) -> Result<CallToolResult, McpError> {
+        // The documents are files handed out by link, like an export.
+        let auth = match self.caller(&context, TokenScope::CatalogExport) {
+            Ok(auth) => auth,
+            Err(result) => return Ok(result)
+        };
+
+        let priced = match quote::finalize(&quote::Owner::new(&auth.authcode, auth.pid), &args.quote_id, args.valid_days) {
+            Ok(priced) => priced,
+            Err(error) => return Ok(CallToolResult::error(vec![Content::text(format!(
+                "The quote was not finalized: {}.", error
+            ))]))
+        };
+
+        let for_files = priced.clone();
+        let built = actix_web::web::block(move || quote::write(&for_files)).await;
+        let (workbook, pdf) = match built {
+            Ok(Ok(files)) => files,
+            Ok(Err(error)) => {
+                elogger(format!("MCP quote documents failed for {}: {}", caller_identity(&context), error));
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "The quote documents could not be written: {}", error
+                ))]))
+            }
+            Err(error) => {
+                elogger(format!("MCP quote documents failed to run for {}: {}", caller_identity(&context), error));
+                return Ok(CallToolResult::error(vec![Content::text(
+                    "The quote documents could not be started. This is a server-side problem."
+                )]))
+            }
+        };
+
+        logger(format!(
+            "MCP tool 'finalize_quote' by {}: {} lines={} -> {}, {}",
+            caller_identity(&context), priced.quote_id, priced.lines.len(), workbook.file_name, pdf.file_name
+        ));
+
+        Ok(json_result(json!({
+            "quote": priced,
+            "xlsx_url": workbook.url,
+            "pdf_url": pdf.url,
+            "expires_in_seconds": export::ttl_secs(),
+            "note": "Give the user both links. They expire and need no login. The documents show the \
+                     customer's prices only, so they can be forwarded to the customer as they are."
+        })))
+    }
+
+    /// Orders, step one: the order as it would be sent, checked against the
+    /// catalog, with the token that sends it.
+    #[tool(description = "Draft an Orink order for this partner from `items` (article number, barcode or part \
+        number with quantity), with an optional `reference` (the partner's own order number), delivery mode, \
+        notes, contact and `delivery_address`. Every line is checked against the catalog. Returns the draft \
+        priced at this partner's prices, stock warnings, an `order_id` and a `confirmation_token`. Nothing is \
+        ordered yet: show the user the draft, and only when they explicitly confirm it call submit_order with \
+        the token. Pass `order_id` to redraft; that replaces the draft and its token.")]
+    async fn draft_order(
+        &self,
+        context: RequestContext<RoleServer>,
+        Parameters(args): Parameters<DraftOrderArgs>
+    ) -> Result<CallToolResult, McpError> {
+        // A draft shows this partner's prices, so it takes the catalog grant as
+        // well as the right to order.
+        if let Err(result) = self.caller(&context, TokenScope::CatalogRead) {
+            return Ok(result)
+        }
+        let snapshot = match self.snapshot(&context, TokenScope::OrdersWrite).await {
+            Ok(snapshot) => snapshot,
+            Err(result) => return Ok(result)
+        };
+        let auth = match self.caller(&context, TokenScope::OrdersWrite) {
+            Ok(auth) => auth,
+            Err(result) => return Ok(result)
+        };
+
+        let delivery_mode = match args.delivery_mode.map(u8::try_from) {
+            None => None,
+            Some(Ok(mode)) => Some(mode),
+            Some(Err(_)) => return Ok(CallToolResult::error(vec![Content::text(
+                "delivery_mode must be between 0 and 255."
+            )]))
+        };
+        let particulars = order::Particulars {
+            delivery_mode,
+            reference: args.reference,
+            delivery_note: args.delivery_note,
+            note: args.note,
+            note_warehouse: args.note_warehouse,
+            contact_phone: args.contact_phone,
+            contact_email: args.contact_email,
+            delivery_address: args.delivery_address
+        };
+        let owner = quote::Owner::new(&auth.authcode, auth.pid);
+        match order::draft(&owner, &snapshot, args.order_id.as_deref(), &args.items, particulars) {
+            Ok(drafted) => {
+                logger(format!(
+                    "MCP tool 'draft_order' by {}: {} lines={} redraft={}",
+                    caller_identity(&context), drafted.order.order_id, drafted.order.lines.len(), args.order_id.is_some()
+                ));
+                Ok(json_result(json!({
+                    "catalog_age_seconds": snapshot.age_secs(),
+                    "order": drafted.order,
+                    "confirmation_token": drafted.confirmation_token,
+                    "confirmation_expires_in_seconds": order::CONFIRMATION_SECS,
+                    "note": "Nothing has been ordered. Show the user every line, the total and any warnings, \
+                             and call submit_order with this confirmation_token only after they say to place \
+                             this order. Any change means drafting again."
+                })))
+            }
+            Err(error) => Ok(CallToolResult::error(vec![Content::text(format!(
+                "The order was not drafted: {}.", error
+            ))]))
+        }
+    }
+
+    /// A draft or sent order as it stands, for reading back.
+    #[tool(description = "Show an order drafted with draft_order: its lines, total, warnings and whether it is \
+        still a draft, awaiting confirmation, or already submitted with Orink's document number.")]
+    async fn show_order(
+        &self,
+        context: RequestContext<RoleServer>,
+        Parameters(args): Parameters<ShowOrderArgs>
+    ) -> Result<CallToolResult, McpError> {
+        let auth = match self.caller(&context, TokenScope::OrdersWrite) {
+            Ok(auth) => auth,
+            Err(result) => return Ok(result)
+        };
+
+        match order::show(&quote::Owner::new(&auth.authcode, auth.pid), &args.order_id) {
+            Ok(view) => {
+                logger(format!(
+                    "MCP tool 'show_order' by {}: {} status={}",
+                    caller_identity(&context), view.order_id, view.status
+                ));
+                Ok(json_result(json!({ "order": view })))
+            }
+            Err(error) => Ok(CallToolResult::error(vec![Content::text(format!(
+                "The order cannot be shown: {}.", error
+            ))]))
+        }
+    }
+
+    /// Orders, step two: sends a confirmed draft to Octopus.
+    #[tool(description = "Place an order drafted with draft_order, in this partner's name. Needs the \
+        `confirmation_token` of the draft's latest version, and must only be called after the user has \
+        explicitly confirmed that order. The token works once. Returns Orink's document number, delivery \
+        date and the lines as recorded.")]
+    async fn submit_order(
+        &self,
+        context: RequestContext<RoleServer>,
+        Parameters(args): Parameters<SubmitOrderArgs>
+    ) -> Result<CallToolResult, McpError> {
+        let auth = match self.caller(&context, TokenScope::OrdersWrite) {
+            Ok(auth) => auth,
+            Err(result) => return Ok(result)
+        };
+        let Some(url) = get_default_url() else {
+            elogger("MCP: no default SOAP url configured (soap.json missing or empty)");
+            return Ok(CallToolResult::error(vec![Content::text(
+                "The Rustopus server has no Octopus url configured, so no order can be placed. \
+                 This is a server-side configuration problem."
+            )]))
+        };
+
+        let owner = quote::Owner::new(&auth.authcode, auth.pid);
+        let claimed = match order::claim(&owner, &args.order_id, &args.confirmation_token, auth.pid) {
+            Ok(claimed) => claimed,
+            Err(error) => {
+                logger(format!("MCP tool 'submit_order' by {}: {} refused: {}", caller_identity(&context), args.order_id.trim(), error));
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "The order was not placed: {}.", error
+                ))]))
+            }
+        };
+
+        let identity = caller_identity(&context);
+        let log = |line: String| logger(format!("MCP tool 'submit_order' by {}: {}", identity, line));
+        let answer = routes::order::submit(&url, &xmlns_for_url(&url), &auth.authcode, claimed, log).await;
+        let recorded = match &answer {
+            Ok(response) => order::record(&owner, &args.order_id, Ok(&response.body.response.result.answer)),
+            Err(error) => {
+                elogger(format!("MCP order {} for {}: Octopus's answer could not be read: {}", args.order_id.trim(), identity, error));
+                order::record(&owner, &args.order_id, Err(error))
+            }
+        };
+
+        match recorded {
+            Ok(view) => {
+                logger(format!(
+                    "MCP tool 'submit_order' by {}: {} status={} document={}",
+                    identity, view.order_id, view.status,
+                    view.receipt.as_ref().map_or("none", |receipt| receipt.document_number.as_str())
+                ));
+                if answer.is_err() {
+                    return Ok(CallToolResult::error(vec![Content::text(format!(
+                        "The order was sent, but Octopus's answer could not be read, so whether it was recorded \
+                         is unknown. Do not submit it again; ask the user to check with Orink. Order {}.",
+                        view.order_id
+                    ))]))
+                }
+                Ok(json_result(json!({ "order": view })))
+            }
+            Err(error) => {
+                elogger(format!("MCP order {} for {} could not be recorded: {}", args.order_id.trim(), identity, error));
+                Ok(CallToolResult::error(vec![Content::text(format!(
+                    "The order was sent, but its outcome could not be recorded here: {}. Do not submit it again.",
+                    error
+                ))]))
+            }
+        }
+    }
+
     /// The vocabulary of the catalog, so filters can be chosen from real values
     /// rather than guessed.
     #[tool(description = "List the brands, main groups and product groups in the Orink catalog, with product counts.")]
@@ -441,7 +994,7 @@ impl RustopusMcp {
         context: RequestContext<RoleServer>,
         Parameters(args): Parameters<ListCategoriesArgs>
     ) -> Result<CallToolResult, McpError> {
-        let snapshot = match self.snapshot(&context).await {
+        let snapshot = match self.snapshot(&context, TokenScope::CatalogRead).await {
             Ok(snapshot) => snapshot,
             Err(result) => return Ok(result)
         };
@@ -481,10 +1034,69 @@ impl RustopusMcp {
         Ok(json_result(payload))
     }
 
+    /// The technical attributes the catalog can be filtered on, so
+    /// `search_products`' `attributes` can be filled from real values.
+    #[tool(description = "List the filterable technical attributes of Orink products (e.g. RAM, screen size, \
+        colour) with the values they take and product counts. Use the codes or names and values shown here \
+        in search_products' `attributes` filter.")]
+    async fn list_attributes(
+        &self,
+        context: RequestContext<RoleServer>,
+        Parameters(args): Parameters<ListAttributesArgs>
+    ) -> Result<CallToolResult, McpError> {
+        let snapshot = match self.snapshot(&context, TokenScope::CatalogRead).await {
+            Ok(snapshot) => snapshot,
+            Err(result) => return Ok(result)
+        };
+
+        let mut attributes = snapshot.attributes();
+        let total = attributes.len();
+        if let Some(wanted) = args.attribute.as_deref().filter(|wanted| !wanted.trim().is_empty()) {
+            attributes.retain(|attribute| attribute.is(wanted));
+        }
+        let limit = clamp(args.limit, DEFAULT_ATTRIBUTE_LIMIT, 200);
+        // One attribute asked for by name is shown whole, up to the ceiling.
+        let default_values = if args.attribute.is_some() { 200 } else { DEFAULT_VALUE_LIMIT };
+        let values = clamp(args.values, default_values, 200);
+
+        logger(format!(
+            "MCP tool 'list_attributes' by {}: attribute={:?} limit={} values={} -> attributes={} of {}",
+            caller_identity(&context), args.attribute, limit, values, attributes.len(), total
+        ));
+
+        if attributes.is_empty() {
+            return Ok(CallToolResult::error(vec![Content::text(match &args.attribute {
+                Some(wanted) if total > 0 => format!(
+                    "No filterable attribute is called '{}'. Call list_attributes without `attribute` \
+                     to see the {} there are.", wanted, total
+                ),
+                _ => "The catalog holds no filterable attributes.".to_string()
+            })]))
+        }
+
+        let items: Vec<serde_json::Value> = attributes.iter()
+            .take(limit)
+            .map(|attribute| json!({
+                "code": attribute.code,
+                "name": attribute.name,
+                "count": attribute.count,
+                "distinct_values": attribute.values.len(),
+                "values": attribute.values.iter().take(values).collect::<Vec<_>>()
+            }))
+            .collect();
+
+        Ok(json_result(json!({
+            "catalog_age_seconds": snapshot.age_secs(),
+            "total": attributes.len(),
+            "truncated": attributes.len() > limit,
+            "attributes": items
+        })))
+    }
+
     /// Freshness, so an answer can be qualified instead of implied to be live.
     #[tool(description = "How many products the cached Orink catalog holds and how old the data is.")]
     async fn catalog_status(&self, context: RequestContext<RoleServer>) -> Result<CallToolResult, McpError> {
-        let snapshot = match self.snapshot(&context).await {
+        let snapshot = match self.snapshot(&context, TokenScope::CatalogRead).await {
             Ok(snapshot) => snapshot,
             Err(result) => return Ok(result)
         };
@@ -502,25 +1114,29 @@ impl RustopusMcp {
             "hidden_products": snapshot.hidden_count(),
             "fetched_at": snapshot.fetched_at.to_rfc3339(),
             "age_seconds": snapshot.age_secs(),
-            "priced_products": snapshot.products.iter()
-                .filter(|p| p.available && p.price.is_some())
-                .count(),
+            "priced_products": snapshot.priced_count(),
             "note": "Prices and stock are specific to the partner id configured on this connector. \
                 `hidden_products` are held in the catalog but not published for sale, so they do not \
                 appear in search or export results."
         })))
     }
 
-    /// The caller's catalog snapshot, or a ready-made error result explaining
-    /// what is missing.
+    /// The caller's identity, or a ready-made error result explaining what is
+    /// missing — credentials, or the `scope` the tool needs.
     ///
     /// Returns `Err(CallToolResult)` rather than `Err(McpError)` on purpose: a
     /// missing header or a bad authcode is the caller's to fix, and a tool-level
     /// error reaches the user, where a protocol error would be rendered opaquely.
-    async fn snapshot(
+    #[expect(
+        clippy::result_large_err,
+        reason = "Called once per tool call, and the error is returned to the caller as it is; boxing it \
+                  would only be unboxed again at every call site."
+    )]
+    fn caller<'a>(
         &self,
-        context: &RequestContext<RoleServer>
-    ) -> Result<Arc<CatalogSnapshot>, CallToolResult> {
+        context: &'a RequestContext<RoleServer>,
+        scope: TokenScope
+    ) -> Result<&'a McpAuth, CallToolResult> {
         let Some(auth) = context.extensions.get::<McpAuth>() else {
             return Err(CallToolResult::error(vec![Content::text(format!(
                 "This connector is not sending credentials. Both the {} and {} request headers \
@@ -529,6 +1145,37 @@ impl RustopusMcp {
             ))]))
         };
 
+        // Checked before the catalog is read, so a tool a token may not use
+        // costs nothing upstream.
+        if !auth.allows(scope) && auth.is_header_caller() {
+            elogger(format!("MCP: {} refused — header callers are not allowed {} ([mcp] header_orders)", auth.masked(), scope.as_str()));
+            return Err(CallToolResult::error(vec![Content::text(format!(
+                "This connector sends its authcode in a header, and the Rustopus server does not accept \
+                 '{}' ({}) from header-based connectors. Connect through OAuth with a sign-in that grants \
+                 it, or ask whoever runs the server to allow it.",
+                scope.as_str(), scope.describe().to_lowercase()
+            ))]))
+        }
+        if !auth.allows(scope) {
+            elogger(format!("MCP: {} refused — the token was not granted {}", auth.masked(), scope.as_str()));
+            return Err(CallToolResult::error(vec![Content::text(format!(
+                "This connector's sign-in was not granted '{}' ({}). Ask whoever runs the Rustopus \
+                 server to allow it for this connector, then sign in again.",
+                scope.as_str(), scope.describe().to_lowercase()
+            ))]))
+        }
+        Ok(auth)
+    }
+
+    /// The caller's catalog snapshot, or a ready-made error result — see
+    /// [`caller`](Self::caller), which checks the credentials first.
+    async fn snapshot(
+        &self,
+        context: &RequestContext<RoleServer>,
+        scope: TokenScope
+    ) -> Result<Arc<CatalogSnapshot>, CallToolResult> {
+        let auth = self.caller(context, scope)?;
+
         let Some(url) = get_default_url() else {
             // Server-side misconfiguration, not the caller's problem — but they
             // still need to be told why nothing works.
@@ -539,7 +1186,9 @@ impl RustopusMcp {
             )]))
         };
 
-        match cache().get_or_build(&auth.authcode, auth.pid, &url).await {
+        // A model is waiting on the answer, so a cold build takes the
+        // interactive lane rather than queueing behind a bulk sync.
+        match soap_gate::in_lane(Lane::Interactive, cache().get_or_build(&auth.authcode, auth.pid, &url)).await {
             Ok(snapshot) => Ok(snapshot),
             Err(error) => {
                 elogger(format!("MCP snapshot failed for {}: {}", auth.masked(), error));
@@ -563,7 +1212,8 @@ impl ServerHandler for RustopusMcp {
                  partner id configured on this connector: `price` is what this partner actually \
                  pays, not a list or retail price, so it can be quoted as-is. Data comes from a \
                  periodically refreshed snapshot — call catalog_status when the freshness of an \
-                 answer matters."
+                 answer matters. Orders are placed in the partner's name: draft_order first, and \
+                 submit_order only after the user has confirmed that draft."
             )
     }
 }
@@ -597,7 +1247,16 @@ fn clamp(requested: Option<u32>, default: usize, max: usize) -> usize {
 /// Renders the active filters for a "nothing matched" message, so the model can
 /// see whether a filter, rather than the query, was the problem.
 fn describe_filters(args: &SearchProductsArgs) -> String {
-    render_filters(args.brand.as_deref(), args.category.as_deref(), args.main_category.as_deref())
+    let mut described = render_filters(args.brand.as_deref(), args.category.as_deref(), args.main_category.as_deref());
+    let attributes: Vec<String> = args.attributes.iter()
+        .flatten()
+        .map(|(name, value)| format!("{} '{}'", name, value))
+        .collect();
+    if !attributes.is_empty() {
+        described.push_str(if described.is_empty() { " with " } else { " and " });
+        described.push_str(&attributes.join(" and "));
+    }
+    described
 }
 
 /// The same, for the export tool's own argument shape.
@@ -657,7 +1316,7 @@ fn extract_auth(request: &HttpRequest, extensions: &mut rmcp::model::Extensions)
     match (authcode, pid) {
         (Some(authcode), Some(pid)) => {
             logger(format!("MCP request: credentials received ({} pid={})", mask_authcode(authcode), pid));
-            extensions.insert(McpAuth { authcode: authcode.to_string(), pid });
+            extensions.insert(McpAuth { authcode: authcode.to_string(), pid, scopes: None });
         }
         (Some(authcode), None) => elogger(format!(
             "MCP request: {} received ({}) but {} missing or not an integer",
@@ -711,6 +1370,7 @@ mod tests {
             brand: Some("Orink".into()),
             category: None,
             main_category: None,
+            attributes: None,
             limit: None,
             offset: None
         };
@@ -721,9 +1381,16 @@ mod tests {
             brand: None,
             category: None,
             main_category: None,
+            attributes: None,
             limit: None,
             offset: None
         };
         assert_eq!(describe_filters(&bare), "");
+
+        let attributes = SearchProductsArgs {
+            attributes: Some(BTreeMap::from([("RAM".to_string(), ">=16".to_string())])),
+            ..args
+        };
+        assert_eq!(describe_filters(&attributes), " with brand 'Orink' and RAM '>=16'");
     }
 }
@@ -2,13 +2,22 @@ pub mod get;
 pub mod authcode;
 pub mod log;
 pub mod blocklist;
+pub mod apikey;
+pub mod ratelimit;
+pub mod abuse;
+pub mod sealed;
+pub mod state;
 pub mod config;
 pub mod ipv4;
 pub mod soap;
+pub mod soap_gate;
+pub mod tenant;
 pub mod errors;
 pub mod path;
 pub mod soap_config;
 pub mod slave;
 pub mod get_data;
+pub mod page;
+pub mod select;
 pub mod dates;
 pub mod mcp;
\ No newline at end of file
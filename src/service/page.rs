@@ -0,0 +1,233 @@
+//! `limit`/`offset` and cursor pagination for the REST fetchers.
+//!
+//! A page is cut from the translated list after the filters in
+//! [`select`](crate::service::select) have run, so `total` counts what the
+//! caller asked for rather than the whole catalog. The same metadata goes out
+//! twice: in the `X-Total-Count` / `X-Next-Cursor` headers, which every output
+//! format carries, and in a `page` element of the English envelope.
+//!
+//! Octopus cannot page, so every page needs the full list. A paged request
+//! therefore reads through the short-lived page cache in `soap.rs`
+//! ([`get_response_paged`](crate::service::soap::get_response_paged)): the first
+//! page pays for the upstream call and the pages after it are cut from the
+//! same response. Unpaged requests never touch that cache and still read live.
+
+use actix_web::HttpResponse;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use base64::Engine;
+
+use crate::forms::out::xml::defaults::Page;
+
+
+/// Header carrying the number of records before paging.
+pub const TOTAL_HEADER: &str = "x-total-count";
+
+/// Header carrying the cursor of the next page. Absent on the last page.
+pub const NEXT_CURSOR_HEADER: &str = "x-next-cursor";
+
+
+/// Where a page starts and how long it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Paging {
+    offset: usize,
+    /// `None` runs to the end of the list.
+    limit: Option<usize>
+}
+
+
+impl Paging {
+    /// Builds the paging a request asked for, `None` when it asked for none.
+    ///
+    /// A cursor carries its own offset and limit; an explicit `limit` next to
+    /// it wins, so a client can change its page size mid-walk. `offset` next to
+    /// a cursor is refused rather than guessed at.
+    pub fn new(offset: Option<usize>, limit: Option<usize>, cursor: Option<&str>) -> Result<Option<Self>, &'static str> {
+        if limit == Some(0) {
+            return Err("limit must be at least 1")
+        }
+
+        if let Some(cursor) = cursor.map(str::trim).filter(|cursor| !cursor.is_empty()) {
+            if offset.is_some() {
+                return Err("offset cannot be combined with cursor")
+            }
+            let paging = Self::decode(cursor).ok_or("cursor is not one this server issued")?;
+            return Ok(Some(Self { limit: limit.or(paging.limit), ..paging }))
+        }
+
+        if offset.is_none() && limit.is_none() {
+            return Ok(None)
+        }
+        Ok(Some(Self { offset: offset.unwrap_or(0), limit }))
+    }
+
+    /// The page a request asked for, or the first `default` records, with the
+    /// length held to `max`. For lists that are never returned whole, such as
+    /// search results.
+    pub fn bounded(paging: Option<Self>, default: usize, max: usize) -> Self {
+        let paging = paging.unwrap_or(Self { offset: 0, limit: None });
+        Self { limit: Some(paging.limit.unwrap_or(default).min(max)), ..paging }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Cuts the page out of `records` in place and describes it.
+    pub fn cut<T>(&self, records: &mut Vec<T>) -> Page {
+        let total = records.len();
+        records.drain(..self.offset.min(total));
+        if let Some(limit) = self.limit {
+            records.truncate(limit);
+        }
+        self.describe(total)
+    }
+
+    /// Describes this page of a list `total` records long that was cut
+    /// elsewhere.
+    pub fn describe(&self, total: usize) -> Page {
+        let next_cursor = self.limit
+            .map(|limit| self.offset.saturating_add(limit))
+            .filter(|next| *next < total)
+            .map(|next| Self { offset: next, limit: self.limit }.encode());
+
+        Page {
+            total,
+            offset: self.offset,
+            limit: self.limit,
+            next_cursor
+        }
+    }
+
+    /// Opaque to the client; in fact `offset:limit`, base64url without padding.
+    fn encode(&self) -> String {
+        let limit = self.limit.map(|limit| limit.to_string()).unwrap_or_default();
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(format!("{}:{}", self.offset, limit))
+    }
+
+    fn decode(cursor: &str) -> Option<Self> {
+        let decoded = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(cursor).ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        let (offset, limit) = decoded.split_once(':')?;
+        let limit = match limit {
+            "" => None,
+            limit => Some(limit.parse::<usize>().ok().filter(|limit| *limit > 0)?)
+        };
+        Some(Self { offset: offset.parse().ok()?, limit })
+    }
+}
+
+
+/// Cuts a page when one was asked for.
+pub fn cut<T>(paging: Option<&Paging>, records: &mut Vec<T>) -> Option<Page> {
+    paging.map(|paging| paging.cut(records))
+}
+
+
+/// Adds the page headers to a response. Unpaged responses are left as they are.
+pub fn paged(mut response: HttpResponse, page: Option<&Page>) -> HttpResponse {
+    if let Some(page) = page {
+        let headers = response.headers_mut();
+        headers.insert(HeaderName::from_static(TOTAL_HEADER), HeaderValue::from(page.total));
+        if let Some(cursor) = page.next_cursor.as_deref()
+            && let Ok(cursor) = HeaderValue::from_str(cursor) {
+                headers.insert(HeaderName::from_static(NEXT_CURSOR_HEADER), cursor);
+        }
+    }
+    response
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn walk(limit: usize, total: usize) -> Vec<Vec<usize>> {
+        let mut pages = Vec::new();
+        let mut paging = Paging::new(None, Some(limit), None).unwrap();
+        while let Some(current) = paging {
+            let mut records: Vec<usize> = (0..total).collect();
+            let page = current.cut(&mut records);
+            assert_eq!(page.total, total);
+            pages.push(records);
+            paging = page.next_cursor.map(|cursor| Paging::new(None, None, Some(&cursor)).unwrap().unwrap());
+        }
+        pages
+    }
+
+    #[test]
+    fn no_parameters_mean_no_paging() {
+        assert_eq!(Paging::new(None, None, None), Ok(None));
+        assert_eq!(Paging::new(None, None, Some("  ")), Ok(None));
+    }
+
+    #[test]
+    fn following_the_cursor_visits_every_record_once() {
+        let pages = walk(3, 8);
+        assert_eq!(pages, vec![vec![0, 1, 2], vec![3, 4, 5], vec![6, 7]]);
+
+        // An exact multiple ends without pointing at an empty page.
+        assert_eq!(walk(4, 8).len(), 2);
+    }
+
+    #[test]
+    fn an_offset_past_the_end_is_an_empty_last_page() {
+        let mut records = vec![1, 2, 3];
+        let page = Paging::new(Some(10), Some(5), None).unwrap().unwrap().cut(&mut records);
+        assert!(records.is_empty());
+        assert_eq!(page.total, 3);
+        assert_eq!(page.next_cursor, None);
+    }
+
+    #[test]
+    fn an_offset_alone_runs_to_the_end() {
+        let mut records = vec![1, 2, 3, 4];
+        let page = Paging::new(Some(1), None, None).unwrap().unwrap().cut(&mut records);
+        assert_eq!(records, vec![2, 3, 4]);
+        assert_eq!(page.next_cursor, None);
+    }
+
+    #[test]
+    fn a_limit_next_to_a_cursor_changes_the_page_size() {
+        let cursor = Paging { offset: 4, limit: Some(2) }.encode();
+        let paging = Paging::new(None, Some(10), Some(&cursor)).unwrap().unwrap();
+        assert_eq!(paging, Paging { offset: 4, limit: Some(10) });
+    }
+
+    #[test]
+    fn a_bounded_page_defaults_and_caps_its_length() {
+        assert_eq!(Paging::bounded(None, 20, 100), Paging { offset: 0, limit: Some(20) });
+        let asked = Paging::new(Some(40), Some(500), None).unwrap();
+        assert_eq!(Paging::bounded(asked, 20, 100), Paging { offset: 40, limit: Some(100) });
+        // Cut elsewhere, described the same way.
+        assert_eq!(Paging::bounded(None, 20, 100).describe(45).next_cursor, Some(Paging { offset: 20, limit: Some(20) }.encode()));
+    }
+
+    #[test]
+    fn malformed_requests_are_refused() {
+        assert!(Paging::new(None, Some(0), None).is_err());
+        assert!(Paging::new(Some(1), None, Some("MDoy")).is_err(), "offset and cursor together");
+        assert!(Paging::new(None, None, Some("not a cursor")).is_err());
+        // Well-formed base64 of something that is not `offset:limit`.
+        let forged = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode("-1:5");
+        assert!(Paging::new(None, None, Some(&forged)).is_err());
+    }
+
+    #[test]
+    fn the_headers_carry_the_total_and_only_a_real_next_cursor() {
+        let page = Page { total: 12, offset: 0, limit: Some(5), next_cursor: Some("NTo1".into()) };
+        let response = paged(HttpResponse::Ok().finish(), Some(&page));
+        assert_eq!(response.headers().get(TOTAL_HEADER).unwrap(), "12");
+        assert_eq!(response.headers().get(NEXT_CURSOR_HEADER).unwrap(), "NTo1");
+
+        let last = Page { next_cursor: None, ..page };
+        let response = paged(HttpResponse::Ok().finish(), Some(&last));
+        assert!(response.headers().get(NEXT_CURSOR_HEADER).is_none());
+
+        let response = paged(HttpResponse::Ok().finish(), None);
+        assert!(response.headers().get(TOTAL_HEADER).is_none());
+    }
+}
@@ -0,0 +1,791 @@
+//! Rate limits and daily quotas for the REST endpoints.
+//!
+//! Every call to a fetcher or `/post-order` costs one token from two buckets:
+//! one keyed by the caller's authcode, one by its address. A bucket refills at
+//! `per_minute` and holds at most `burst`, so a partner syncing once an hour is
+//! never noticed while a script looping on `/get-bulk` is slowed to the refill
+//! rate. A daily quota, when set, caps what a key may spend per UTC day on top
+//! of that. Both keys are checked before either is charged, so a call refused
+//! on one does not also drain the other.
+//!
+//! ## Why both keys
+//!
+//! An authcode alone misses the caller that rotates through codes it is trying
+//! out; an address alone punishes every partner behind one office NAT for the
+//! one that loops. Charging both means each limit can be set for what it is
+//! good at: the authcode limit for fair use per partner, the IP limit as a
+//! ceiling for anyone.
+//!
+//! ## What this does not cover
+//!
+//! `/mcp` answers from the snapshot cache and has its own per-partner costs;
+//! `/export`, `/admin`, the docs and `/get-test` reach no ERP at all. Only the
+//! paths in [`ENDPOINTS`] are metered. Like the blocklist counters, usage lives
+//! in memory: a restart forgives everyone, which is the cheaper mistake.
+//!
+//! An authcode is keyed by its SHA-256 (the same hash as a blocklist rule) and
+//! shown by its mask, so the usage table on `/admin` holds no credential.
+//!
+//! ## API keys
+//!
+//! A call made with a `service/apikey` key is charged to the authcode the key
+//! maps to, like any other, and — when the key carries a limit of its own — to
+//! a third bucket for the key. That bucket is shared across endpoints: a key's
+//! limit is what its partner agreed to, not a per-route tuning. It applies
+//! whether or not `[rate_limit]` is enabled.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpMessage, HttpResponse};
+use chrono::{DateTime, NaiveDate, Utc};
+use once_cell::sync::Lazy;
+
+use crate::{
+    global::errors::{GLOBAL_QUOTA_ERROR, GLOBAL_RATE_LIMIT_ERROR, RustopusError},
+    service::{
+        abuse::{self, Trigger},
+        apikey::KeyUse,
+        blocklist::{hash_hex, request_addresses, request_authcode},
+        config::{EndpointLimitConfig, LimitConfig, RateLimitConfig, get_settings},
+        log::{elog_with_ip, logger},
+        mcp::mask_authcode
+    }
+};
+
+/// The metered endpoints, by singular name. The plural alias of each is
+/// metered as the same endpoint — they are one route under two names.
+pub const ENDPOINTS: [&str; 11] = [
+    "get-product",
+    "get-stock",
+    "get-price",
+    "get-image",
+    "get-barcode",
+    "get-bulk",
+    "get-invoice",
+    "get-mat",
+    "post-order",
+    "search-product",
+    "check-availability"
+];
+
+/// Calls per minute per authcode when `[rate_limit.authcode] per_minute` is
+/// unset. A full catalog sync is a handful of calls; sixty a minute is a loop.
+const DEFAULT_AUTHCODE_PER_MINUTE: f64 = 60.0;
+
+/// Calls per minute per address when `[rate_limit.ip] per_minute` is unset.
+/// Twice the authcode limit, so a few partners behind one NAT do not trip it.
+const DEFAULT_IP_PER_MINUTE: f64 = 120.0;
+
+/// The endpoint an API key's bucket is filed under: all of them at once.
+const ALL_ENDPOINTS: &str = "all";
+
+/// How often idle buckets are swept out of memory.
+const PRUNE_EVERY: Duration = Duration::from_secs(60);
+
+/// How long a bucket from a previous UTC day may sit idle before it is dropped.
+/// By then it has refilled, and its quota counter is for a day that is over.
+const PRUNE_IDLE: Duration = Duration::from_secs(3_600);
+
+
+/// What a bucket is keyed by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LimitKind {
+    Authcode,
+    Ip,
+    Key
+}
+
+impl LimitKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LimitKind::Authcode => "authcode",
+            LimitKind::Ip => "ip",
+            LimitKind::Key => "key"
+        }
+    }
+}
+
+
+/// One limit with its defaults applied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limit {
+    /// Tokens per second. `None` when `per_minute = 0`: no rate limit, only the
+    /// quota (if any) applies.
+    rate: Option<f64>,
+    burst: f64,
+    daily_quota: Option<u64>
+}
+
+impl Limit {
+    /// Merges an endpoint's override over the table-wide limit, then the code
+    /// default under both. Each field falls back on its own, so an endpoint
+    /// that only sets `daily_quota` keeps the shared rate.
+    fn resolve(base: Option<&LimitConfig>, over: Option<&LimitConfig>, default_per_minute: f64) -> Self {
+        let per_minute = over.and_then(|limit| limit.per_minute)
+            .or(base.and_then(|limit| limit.per_minute))
+            .unwrap_or(default_per_minute)
+            .max(0.0);
+        let burst = over.and_then(|limit| limit.burst)
+            .or(base.and_then(|limit| limit.burst))
+            .unwrap_or(per_minute)
+            .max(1.0);
+        let daily_quota = over.and_then(|limit| limit.daily_quota)
+            .or(base.and_then(|limit| limit.daily_quota))
+            .filter(|quota| *quota > 0);
+
+        Self {
+            rate: (per_minute > 0.0).then_some(per_minute / 60.0),
+            burst,
+            daily_quota
+        }
+    }
+
+    /// An API key's own limit, or `None` when the key sets neither field. The
+    /// burst is a minute's worth, as for the tables.
+    pub fn for_key(per_minute: Option<f64>, daily_quota: Option<u64>) -> Option<Self> {
+        let limited = per_minute.is_some_and(|rate| rate > 0.0) || daily_quota.is_some_and(|quota| quota > 0);
+        limited.then(|| Self::resolve(
+            Some(&LimitConfig { per_minute: Some(per_minute.unwrap_or(0.0)), burst: None, daily_quota }),
+            None,
+            0.0
+        ))
+    }
+}
+
+
+/// The `[rate_limit]` table resolved for every endpoint, once, at startup.
+#[derive(Debug, Clone)]
+struct Limits {
+    by_endpoint: HashMap<&'static str, (Limit, Limit)>
+}
+
+impl Limits {
+    fn from_config(config: &RateLimitConfig) -> Self {
+        let endpoints = config.endpoints.clone().unwrap_or_default();
+        let by_endpoint = ENDPOINTS.iter().map(|endpoint| {
+            let over: Option<&EndpointLimitConfig> = endpoints.get(*endpoint);
+            let authcode = Limit::resolve(
+                config.authcode.as_ref(),
+                over.and_then(|over| over.authcode.as_ref()),
+                DEFAULT_AUTHCODE_PER_MINUTE
+            );
+            let ip = Limit::resolve(
+                config.ip.as_ref(),
+                over.and_then(|over| over.ip.as_ref()),
+                DEFAULT_IP_PER_MINUTE
+            );
+            (*endpoint, (authcode, ip))
+        }).collect();
+        Self { by_endpoint }
+    }
+
+    fn get(&self, endpoint: &str, kind: LimitKind) -> Option<Limit> {
+        self.by_endpoint.get(endpoint).and_then(|(authcode, ip)| match kind {
+            LimitKind::Authcode => Some(*authcode),
+            LimitKind::Ip => Some(*ip),
+            LimitKind::Key => None
+        })
+    }
+}
+
+
+/// The resolved limits, or `None` when `[rate_limit]` is absent or disabled —
+/// in which case the middleware returns on its first line.
+static LIMITS: Lazy<Option<Limits>> = Lazy::new(|| {
+    get_settings().rate_limit
+        .filter(RateLimitConfig::is_enabled)
+        .map(|config| Limits::from_config(&config))
+});
+
+
+/// Why a call was refused, and for how many seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    Rate(u64),
+    Quota(u64)
+}
+
+impl Refusal {
+    fn retry_after(&self) -> u64 {
+        match self {
+            Refusal::Rate(seconds) | Refusal::Quota(seconds) => *seconds
+        }
+    }
+
+    fn error(&self) -> RustopusError {
+        match self {
+            Refusal::Rate(_) => GLOBAL_RATE_LIMIT_ERROR,
+            Refusal::Quota(_) => GLOBAL_QUOTA_ERROR
+        }
+    }
+
+    /// What the refusal counts as toward an automatic block. Only a rate
+    /// refusal does: a partner past its daily quota that keeps syncing is over
+    /// its allowance, not flooding, and the quota already stops it.
+    fn strike(&self) -> Option<Trigger> {
+        match self {
+            Refusal::Rate(_) => Some(Trigger::Flood),
+            Refusal::Quota(_) => None
+        }
+    }
+}
+
+
+/// One key's bucket on one endpoint, plus its usage for the day.
+#[derive(Debug, Clone)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+    day: NaiveDate,
+    used_today: u64,
+    refused_today: u64,
+    last_seen: DateTime<Utc>,
+    /// The address, the authcode's mask, or the key's label. Never the code.
+    label: String,
+    /// The limit last applied, kept for the dashboard.
+    limit: Limit
+}
+
+impl Bucket {
+    fn new(limit: &Limit, now: Instant, at: DateTime<Utc>, label: String) -> Self {
+        Self {
+            tokens: limit.burst,
+            refilled: now,
+            day: at.date_naive(),
+            used_today: 0,
+            refused_today: 0,
+            last_seen: at,
+            label,
+            limit: *limit
+        }
+    }
+
+    /// Adds what has dripped in since the last call, and starts a new day's
+    /// count when the UTC date has moved on.
+    fn refill(&mut self, limit: &Limit, now: Instant, at: DateTime<Utc>) {
+        if let Some(rate) = limit.rate {
+            let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * rate).min(limit.burst);
+        }
+        self.refilled = now;
+        self.limit = *limit;
+        if at.date_naive() != self.day {
+            self.day = at.date_naive();
+            self.used_today = 0;
+            self.refused_today = 0;
+        }
+    }
+
+    /// Whether one more call fits. The quota is checked first: when both are
+    /// spent, "come back tomorrow" is the answer that is true.
+    fn verdict(&self, limit: &Limit, at: DateTime<Utc>) -> Result<(), Refusal> {
+        if let Some(quota) = limit.daily_quota
+            && self.used_today >= quota {
+                return Err(Refusal::Quota(until_midnight(at)))
+        }
+        if let Some(rate) = limit.rate
+            && self.tokens < 1.0 {
+                return Err(Refusal::Rate(((1.0 - self.tokens) / rate).ceil().max(1.0) as u64))
+        }
+        Ok(())
+    }
+
+    fn take(&mut self, limit: &Limit) {
+        if limit.rate.is_some() {
+            self.tokens -= 1.0;
+        }
+        self.used_today += 1;
+    }
+}
+
+
+/// Seconds from `at` to the next UTC midnight, at least one.
+fn until_midnight(at: DateTime<Utc>) -> u64 {
+    let tomorrow = at.date_naive().succ_opt()
+        .and_then(|day| day.and_hms_opt(0, 0, 0))
+        .map(|midnight| midnight.and_utc());
+    tomorrow
+        .map(|midnight| (midnight - at).num_seconds().max(1) as u64)
+        .unwrap_or(86_400)
+}
+
+
+/// `(kind, key, endpoint)`. The key is an address, an authcode's SHA-256 hex or
+/// an API key's id; an API key's endpoint is always [`ALL_ENDPOINTS`].
+type BucketKey = (LimitKind, String, &'static str);
+
+struct Meter {
+    buckets: HashMap<BucketKey, Bucket>,
+    pruned: Instant
+}
+
+static METER: Lazy<Mutex<Meter>> = Lazy::new(|| Mutex::new(Meter {
+    buckets: HashMap::new(),
+    pruned: Instant::now()
+}));
+
+
+/// One side of a call to be metered: which bucket, and how to show it.
+struct Caller {
+    kind: LimitKind,
+    key: String,
+    label: String,
+    /// A limit of the caller's own, in place of the `[rate_limit]` table.
+    limit: Option<Limit>
+}
+
+impl Caller {
+    fn limit(&self, limits: Option<&Limits>, endpoint: &str) -> Option<Limit> {
+        self.limit.or_else(|| limits?.get(endpoint, self.kind))
+    }
+
+    fn bucket(&self, endpoint: &'static str) -> BucketKey {
+        let endpoint = match self.kind {
+            LimitKind::Key => ALL_ENDPOINTS,
+            _ => endpoint
+        };
+        (self.kind, self.key.clone(), endpoint)
+    }
+}
+
+
+impl Meter {
+    /// Charges every caller one token, or none of them and says why not.
+    fn admit(&mut self, limits: Option<&Limits>, endpoint: &'static str, callers: &[Caller], now: Instant, at: DateTime<Utc>) -> Result<(), (LimitKind, Refusal)> {
+        self.prune(now, at);
+
+        let mut refusal: Option<(LimitKind, Refusal)> = None;
+        for caller in callers {
+            let Some(limit) = caller.limit(limits, endpoint) else {
+                continue
+            };
+            // A caller not yet in the table is judged on a fresh bucket that is
+            // stored only once the call is admitted, so a flood of refused
+            // calls under made-up authcodes adds nothing to the table.
+            let verdict = match self.buckets.get_mut(&caller.bucket(endpoint)) {
+                Some(bucket) => {
+                    bucket.refill(&limit, now, at);
+                    bucket.last_seen = at;
+                    let verdict = bucket.verdict(&limit, at);
+                    if verdict.is_err() {
+                        bucket.refused_today += 1;
+                    }
+                    verdict
+                }
+                None => Bucket::new(&limit, now, at, caller.label.clone()).verdict(&limit, at)
+            };
+
+            if let Err(reason) = verdict {
+                // The longest wait is the one that is true for the whole call.
+                if refusal.is_none_or(|(_, current)| reason.retry_after() > current.retry_after()) {
+                    refusal = Some((caller.kind, reason));
+                }
+            }
+        }
+        if let Some(refusal) = refusal {
+            return Err(refusal)
+        }
+
+        for caller in callers {
+            if let Some(limit) = caller.limit(limits, endpoint) {
+                self.buckets
+                    .entry(caller.bucket(endpoint))
+                    .or_insert_with(|| Bucket::new(&limit, now, at, caller.label.clone()))
+                    .take(&limit);
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops buckets nobody has used since a previous UTC day. Runs at most
+    /// once a minute, so the sweep is not paid per request.
+    fn prune(&mut self, now: Instant, at: DateTime<Utc>) {
+        if now.saturating_duration_since(self.pruned) < PRUNE_EVERY {
+            return
+        }
+        self.pruned = now;
+        let today = at.date_naive();
+        self.buckets.retain(|_, bucket| {
+            bucket.day == today || now.saturating_duration_since(bucket.refilled) < PRUNE_IDLE
+        });
+    }
+}
+
+
+/// The metered endpoint a path belongs to, or `None` for anything unmetered.
+/// `/get-products` is `get-product`: the alias is the same route.
+pub fn endpoint_of(path: &str) -> Option<&'static str> {
+    let name = path.strip_prefix('/')?;
+    ENDPOINTS.iter()
+        .find(|endpoint| name == **endpoint || name.strip_suffix('s') == Some(**endpoint))
+        .copied()
+}
+
+
+/// One row of the usage table on `/admin`.
+#[derive(Debug, Clone)]
+pub struct Usage {
+    pub kind: LimitKind,
+    pub label: String,
+    /// `all` for an API key's bucket.
+    pub endpoint: &'static str,
+    /// Whole tokens left in the bucket, `None` when the endpoint has no rate.
+    pub remaining: Option<u64>,
+    pub burst: Option<u64>,
+    pub used_today: u64,
+    pub daily_quota: Option<u64>,
+    pub refused_today: u64,
+    pub last_seen: DateTime<Utc>
+}
+
+
+/// Current usage of every key seen today, busiest first. Empty when rate
+/// limiting is off and no API key carries a limit.
+pub fn usage() -> Vec<Usage> {
+    let Ok(meter) = METER.lock() else {
+        return Vec::new()
+    };
+    let now = Instant::now();
+    let today = Utc::now().date_naive();
+
+    let mut rows: Vec<Usage> = meter.buckets.iter()
+        .filter(|(_, bucket)| bucket.day == today)
+        .map(|((kind, _, endpoint), bucket)| {
+            let limit = bucket.limit;
+            // Shown as it would be after a refill now, without touching the
+            // bucket: reading the dashboard must not change what it reads.
+            let remaining = limit.rate.map(|rate| {
+                let elapsed = now.saturating_duration_since(bucket.refilled).as_secs_f64();
+                (bucket.tokens + elapsed * rate).min(limit.burst).floor().max(0.0) as u64
+            });
+            Usage {
+                kind: *kind,
+                label: bucket.label.clone(),
+                endpoint,
+                remaining,
+                burst: limit.rate.map(|_| limit.burst as u64),
+                used_today: bucket.used_today,
+                daily_quota: limit.daily_quota,
+                refused_today: bucket.refused_today,
+                last_seen: bucket.last_seen
+            }
+        })
+        .collect();
+    rows.sort_by(|a, b| b.used_today.cmp(&a.used_today).then_with(|| b.last_seen.cmp(&a.last_seen)));
+    rows
+}
+
+
+/// Whether limits are enforced on this instance, for the dashboard.
+pub fn is_enabled() -> bool {
+    LIMITS.is_some()
+}
+
+
+/// Resolves the limits and reports them. Called from `main.rs` at startup, so a
+/// malformed table shows in the log before the first call rather than on it.
+pub fn init() {
+    let Some(limits) = LIMITS.as_ref() else {
+        logger("Rate limits: off (no [rate_limit] table, or enabled = false)");
+        return
+    };
+    let describe = |limit: &Limit| match (limit.rate, limit.daily_quota) {
+        (Some(rate), Some(quota)) => format!("{}/min, burst {}, {}/day", rate * 60.0, limit.burst, quota),
+        (Some(rate), None) => format!("{}/min, burst {}", rate * 60.0, limit.burst),
+        (None, Some(quota)) => format!("{}/day", quota),
+        (None, None) => "unlimited".into()
+    };
+    for endpoint in ENDPOINTS {
+        if let (Some(authcode), Some(ip)) = (limits.get(endpoint, LimitKind::Authcode), limits.get(endpoint, LimitKind::Ip)) {
+            logger(format!("Rate limits: {} — authcode {}; ip {}", endpoint, describe(&authcode), describe(&ip)));
+        }
+    }
+}
+
+
+/// `429` with the house error shape and a `Retry-After` the client can obey.
+fn too_many_requests(refusal: Refusal) -> HttpResponse {
+    let error = refusal.error();
+    HttpResponse::TooManyRequests()
+        .insert_header(("Retry-After", refusal.retry_after().to_string()))
+        .content_type("application/xml")
+        .body(format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?><error><code>{}</code><description>{}</description></error>",
+            error.code, error.description
+        ))
+}
+
+
+/// The middleware. Wraps the whole app like the blocklist, inside it and inside
+/// `service/apikey`, so a blocked caller is refused before it costs a token and
+/// a key has already been swapped for its authcode.
+pub async fn guard(
+    request: ServiceRequest,
+    next: Next<impl MessageBody + 'static>
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let Some(endpoint) = endpoint_of(request.path()) else {
+        return next.call(request).await.map(ServiceResponse::map_into_boxed_body)
+    };
+    let key = request.extensions().get::<KeyUse>()
+        .and_then(|key| Some((key.clone(), Limit::for_key(key.per_minute, key.daily_quota)?)));
+    let limits = LIMITS.as_ref();
+    if limits.is_none() && key.is_none() {
+        return next.call(request).await.map(ServiceResponse::map_into_boxed_body)
+    }
+
+    let (_, display) = request_addresses(&request);
+    let address = display.unwrap_or_else(|| "unknown IP address".into());
+    let mut callers = vec![Caller {
+        kind: LimitKind::Ip,
+        key: address.clone(),
+        label: address.clone(),
+        limit: None
+    }];
+    if let Some(authcode) = request_authcode(&request) {
+        callers.push(Caller {
+            kind: LimitKind::Authcode,
+            key: hash_hex(&authcode),
+            label: mask_authcode(&authcode),
+            limit: None
+        });
+    }
+    if let Some((key, limit)) = key {
+        callers.push(Caller {
+            kind: LimitKind::Key,
+            key: key.id,
+            label: key.label,
+            limit: Some(limit)
+        });
+    }
+
+    let verdict = match METER.lock() {
+        Ok(mut meter) => meter.admit(limits, endpoint, &callers, Instant::now(), Utc::now()),
+        // A poisoned meter fails open: refusing every partner because one
+        // thread panicked is worse than not counting for a while.
+        Err(_) => Ok(())
+    };
+
+    if let Err((kind, refusal)) = verdict {
+        let error = refusal.error();
+        elog_with_ip(&address, format!(
+            "{}: {} — {} limit on {} {}, retry after {}s",
+            error.code,
+            error.description,
+            kind.as_str(),
+            request.method(),
+            request.path(),
+            refusal.retry_after()
+        ));
+        if let Some(trigger) = refusal.strike() {
+            abuse::report(trigger, &address);
+        }
+        return Ok(request.into_response(too_many_requests(refusal)))
+    }
+
+    next.call(request).await.map(ServiceResponse::map_into_boxed_body)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn limit(per_minute: f64, burst: f64, daily_quota: Option<u64>) -> LimitConfig {
+        LimitConfig { per_minute: Some(per_minute), burst: Some(burst), daily_quota }
+    }
+
+    fn config(authcode: Option<LimitConfig>, endpoints: Vec<(&str, EndpointLimitConfig)>) -> RateLimitConfig {
+        RateLimitConfig {
+            enabled: Some(true),
+            authcode,
+            ip: None,
+            endpoints: Some(endpoints.into_iter().map(|(name, limits)| (name.to_string(), limits)).collect())
+        }
+    }
+
+    fn caller(kind: LimitKind, key: &str) -> Caller {
+        Caller { kind, key: key.into(), label: key.into(), limit: None }
+    }
+
+    fn noon() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 3, 2, 12, 0, 0).unwrap()
+    }
+
+    fn meter() -> Meter {
+        Meter { buckets: HashMap::new(), pruned: Instant::now() }
+    }
+
+    #[test]
+    fn aliases_meter_as_their_endpoint_and_nothing_else_is_metered() {
+        assert_eq!(endpoint_of("/get-product"), Some("get-product"));
+        assert_eq!(endpoint_of("/get-products"), Some("get-product"));
+        assert_eq!(endpoint_of("/post-orders"), Some("post-order"));
+        assert_eq!(endpoint_of("/get-test"), None);
+        assert_eq!(endpoint_of("/mcp"), None);
+        assert_eq!(endpoint_of("/admin/api/state"), None);
+        assert_eq!(endpoint_of("/get-productss"), None);
+    }
+
+    #[test]
+    fn an_endpoint_override_replaces_only_the_fields_it_sets() {
+        let limits = Limits::from_config(&config(
+            Some(limit(30.0, 10.0, None)),
+            vec![("get-bulk", EndpointLimitConfig {
+                authcode: Some(LimitConfig { daily_quota: Some(24), ..Default::default() }),
+                ip: None
+            })]
+        ));
+
+        let bulk = limits.get("get-bulk", LimitKind::Authcode).unwrap();
+        assert_eq!(bulk, Limit { rate: Some(0.5), burst: 10.0, daily_quota: Some(24) });
+        let product = limits.get("get-product", LimitKind::Authcode).unwrap();
+        assert_eq!(product.daily_quota, None);
+        // No `[rate_limit.ip]` at all: the code default, burst equal to the rate.
+        let ip = limits.get("get-product", LimitKind::Ip).unwrap();
+        assert_eq!(ip, Limit { rate: Some(2.0), burst: 120.0, daily_quota: None });
+    }
+
+    #[test]
+    fn a_spent_bucket_refills_at_its_rate() {
+        let limits = Limits::from_config(&config(Some(limit(60.0, 2.0, None)), vec![]));
+        let callers = [caller(LimitKind::Authcode, "a")];
+        let mut meter = meter();
+        let start = Instant::now();
+
+        assert!(meter.admit(Some(&limits), "get-price", &callers, start, noon()).is_ok());
+        assert!(meter.admit(Some(&limits), "get-price", &callers, start, noon()).is_ok());
+        assert_eq!(
+            meter.admit(Some(&limits), "get-price", &callers, start, noon()),
+            Err((LimitKind::Authcode, Refusal::Rate(1)))
+        );
+        // One a second: a second later exactly one more call fits.
+        let later = start + Duration::from_secs(1);
+        assert!(meter.admit(Some(&limits), "get-price", &callers, later, noon()).is_ok());
+        assert!(meter.admit(Some(&limits), "get-price", &callers, later, noon()).is_err());
+    }
+
+    #[test]
+    fn endpoints_have_separate_buckets() {
+        let limits = Limits::from_config(&config(Some(limit(60.0, 1.0, None)), vec![]));
+        let callers = [caller(LimitKind::Authcode, "a")];
+        let mut meter = meter();
+        let now = Instant::now();
+
+        assert!(meter.admit(Some(&limits), "get-price", &callers, now, noon()).is_ok());
+        assert!(meter.admit(Some(&limits), "get-stock", &callers, now, noon()).is_ok());
+        assert!(meter.admit(Some(&limits), "get-price", &callers, now, noon()).is_err());
+    }
+
+    #[test]
+    fn a_quota_holds_until_utc_midnight_then_resets() {
+        let limits = Limits::from_config(&config(Some(limit(0.0, 1.0, Some(2))), vec![]));
+        let callers = [caller(LimitKind::Authcode, "a")];
+        let mut meter = meter();
+        let now = Instant::now();
+
+        assert!(meter.admit(Some(&limits), "get-bulk", &callers, now, noon()).is_ok());
+        assert!(meter.admit(Some(&limits), "get-bulk", &callers, now, noon()).is_ok());
+        assert_eq!(
+            meter.admit(Some(&limits), "get-bulk", &callers, now, noon()),
+            Err((LimitKind::Authcode, Refusal::Quota(12 * 3_600)))
+        );
+
+        let tomorrow = noon() + chrono::Duration::hours(12);
+        assert!(meter.admit(Some(&limits), "get-bulk", &callers, now, tomorrow).is_ok());
+    }
+
+    #[test]
+    fn quota_refusals_are_not_strikes() {
+        let limits = Limits::from_config(&config(Some(limit(0.0, 1.0, Some(1))), vec![]));
+        let callers = [caller(LimitKind::Authcode, "a")];
+        let mut meter = meter();
+        let now = Instant::now();
+
+        assert!(meter.admit(Some(&limits), "get-bulk", &callers, now, noon()).is_ok());
+        // A sync past its quota keeps retrying: every refusal is the quota's.
+        for _ in 0..100 {
+            let Err((_, refusal)) = meter.admit(Some(&limits), "get-bulk", &callers, now, noon()) else {
+                panic!("a spent quota admitted a call")
+            };
+            assert_eq!(refusal.strike(), None);
+        }
+        assert_eq!(Refusal::Rate(1).strike(), Some(Trigger::Flood));
+    }
+
+    #[test]
+    fn a_refused_call_charges_neither_key() {
+        let limits = Limits::from_config(&config(Some(limit(60.0, 1.0, None)), vec![]));
+        let mut meter = meter();
+        let now = Instant::now();
+
+        // Spend the authcode's single token from one address…
+        let first = [caller(LimitKind::Ip, "203.0.113.7"), caller(LimitKind::Authcode, "a")];
+        assert!(meter.admit(Some(&limits), "get-price", &first, now, noon()).is_ok());
+        // …then a refused call from the same address leaves its IP bucket as is.
+        assert!(meter.admit(Some(&limits), "get-price", &first, now, noon()).is_err());
+        let ip = &meter.buckets[&(LimitKind::Ip, "203.0.113.7".to_string(), "get-price")];
+        assert_eq!(ip.used_today, 1);
+        assert_eq!(ip.refused_today, 0);
+        let authcode = &meter.buckets[&(LimitKind::Authcode, "a".to_string(), "get-price")];
+        assert_eq!(authcode.refused_today, 1);
+    }
+
+    #[test]
+    fn refused_calls_add_no_buckets() {
+        let limits = Limits::from_config(&RateLimitConfig {
+            ip: Some(limit(60.0, 1.0, None)),
+            ..config(Some(limit(60.0, 5.0, None)), vec![])
+        });
+        let mut meter = meter();
+        let now = Instant::now();
+
+        let first = [caller(LimitKind::Ip, "203.0.113.7"), caller(LimitKind::Authcode, "a")];
+        assert!(meter.admit(Some(&limits), "get-price", &first, now, noon()).is_ok());
+        assert_eq!(meter.buckets.len(), 2);
+
+        // The address is spent: a stream of made-up authcodes from it is
+        // refused and leaves no bucket behind.
+        for n in 0..100 {
+            let callers = [caller(LimitKind::Ip, "203.0.113.7"), caller(LimitKind::Authcode, &format!("random-{}", n))];
+            assert!(meter.admit(Some(&limits), "get-price", &callers, now, noon()).is_err());
+        }
+        assert_eq!(meter.buckets.len(), 2);
+    }
+
+    #[test]
+    fn a_key_limit_is_shared_across_endpoints_and_needs_no_table() {
+        assert_eq!(Limit::for_key(None, Some(0)), None);
+        let key = Caller {
+            limit: Limit::for_key(Some(2.0), None),
+            ..caller(LimitKind::Key, "k1")
+        };
+        let mut meter = meter();
+        let now = Instant::now();
+
+        assert!(meter.admit(None, "get-price", std::slice::from_ref(&key), now, noon()).is_ok());
+        assert!(meter.admit(None, "get-stock", std::slice::from_ref(&key), now, noon()).is_ok());
+        assert!(matches!(
+            meter.admit(None, "get-bulk", std::slice::from_ref(&key), now, noon()),
+            Err((LimitKind::Key, Refusal::Rate(_)))
+        ));
+        assert_eq!(meter.buckets.len(), 1);
+    }
+
+    #[test]
+    fn midnight_is_counted_in_utc() {
+        let at = Utc.with_ymd_and_hms(2026, 3, 2, 23, 59, 30).unwrap();
+        assert_eq!(until_midnight(at), 30);
+    }
+
+    #[test]
+    fn a_table_without_enabled_is_off() {
+        let config = RateLimitConfig { enabled: None, authcode: None, ip: None, endpoints: None };
+        assert!(!config.is_enabled());
+    }
+}
@@ -0,0 +1,535 @@
+//! Encryption at rest for the secret-grade files.
+//!
+//! Four things on disk hold what must not leak: the snapshots in `mcp_cache/`
+//! (a partner's own negotiated prices), and `mcp_precache.toml`,
+//! `oauth_sessions.toml` and `api_keys.toml` (live Octopus authcodes). `0600`
+//! keeps other local users out; it does nothing for a backup, a copied volume
+//! or a stolen disk image. With a key configured, each of those files is sealed
+//! with ChaCha20-Poly1305 before it is written and opened after it is read. A
+//! file that was altered, truncated or copied over another file's name fails to
+//! open instead of parsing into something else.
+//!
+//! Off unless a key is configured, in which case nothing here touches a byte.
+//!
+//! ## Keys
+//!
+//! A key is 32 random bytes in base64; `rustopus new-storage-key` prints one.
+//! `RUSTOPUS_STORAGE_KEY` wins over `[storage] key_file`, like the admin token
+//! over `admin_token`. The key file holds one key per line, current first.
+//!
+//! **Retired** keys — `RUSTOPUS_STORAGE_OLD_KEYS` (comma-separated) or the key
+//! file's later lines — still open a file but never seal one. Rotation is
+//! therefore: put the new key first, keep the old one behind it, run
+//! `rustopus reseal`, then drop the old one. With retired keys and no current
+//! one, `reseal` writes every file back in plain text, which is how encryption
+//! is switched off again.
+//!
+//! ## Format
+//!
+//! `MAGIC | key id (4 bytes) | nonce (12) | ciphertext and tag`. The key id is the
+//! head of the key's SHA-256, so the right key is picked without trial
+//! decryption and a missing key is reported as such. The associated data is
+//! the header plus the file's *context* — its logical name — which is what stops
+//! one partner's sealed snapshot being served under another's file name.
+//!
+//! A file without the magic is plain text and is read as before. That is what
+//! lets a deployment switch encryption on without a flag day: every file is
+//! sealed the next time it is written, and `rustopus reseal` does the rest in
+//! one pass.
+
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+
+use crate::service::{
+    apikey,
+    config::{STORAGE_KEY_ENV, STORAGE_OLD_KEYS_ENV, get_storage_settings},
+    log::{elogger, logger},
+    mcp::{oauth, precache, store},
+    path::get_current_or_root_dir,
+    state
+};
+
+/// What every sealed file starts with. Versioned, so a later format can be told
+/// apart from this one rather than failing as corruption.
+const MAGIC: &[u8; 8] = b"RSTPSEAL";
+
+const KEY_ID_BYTES: usize = 4;
+const NONCE_BYTES: usize = 12;
+const HEADER_BYTES: usize = MAGIC.len() + KEY_ID_BYTES;
+
+/// Bytes of a raw key.
+const KEY_BYTES: usize = 32;
+
+
+/// One usable key and the id sealed files refer to it by.
+struct SealKey {
+    id: [u8; KEY_ID_BYTES],
+    cipher: ChaCha20Poly1305
+}
+
+/// The current key, which seals, and the retired ones, which only open.
+#[derive(Default)]
+struct Keyring {
+    current: Option<SealKey>,
+    retired: Vec<SealKey>
+}
+
+impl Keyring {
+    fn find(&self, id: &[u8]) -> Option<&SealKey> {
+        self.current.iter().chain(self.retired.iter()).find(|key| key.id == id)
+    }
+}
+
+/// Read once: the environment and the key file do not change under a running
+/// process, and a rotation is a restart anyway.
+static KEYRING: Lazy<Keyring> = Lazy::new(load_keyring);
+
+
+/// Decodes one base64 key. The error names what was wrong, never the value.
+fn parse_key(encoded: &str) -> Result<SealKey, String> {
+    let bytes = base64::engine::general_purpose::STANDARD.decode(encoded.trim())
+        .map_err(|_| "not valid base64".to_string())?;
+    if bytes.len() != KEY_BYTES {
+        return Err(format!("{} bytes, expected {}", bytes.len(), KEY_BYTES))
+    }
+    let digest = Sha256::digest(&bytes);
+    let mut id = [0u8; KEY_ID_BYTES];
+    id.copy_from_slice(&digest[..KEY_ID_BYTES]);
+    Ok(SealKey {
+        id,
+        cipher: ChaCha20Poly1305::new(Key::from_slice(&bytes))
+    })
+}
+
+
+/// Builds a keyring from a current key and retired ones, skipping (and
+/// logging) any that do not decode. A bad current key leaves sealing off rather
+/// than falling back to a retired one: writing under a key the operator meant
+/// to retire would be worse than writing plain text they can see in the log.
+fn keyring_from(current: Option<&str>, retired: &[&str], source: &str) -> Keyring {
+    let current = current
+        .filter(|value| !value.trim().is_empty())
+        .and_then(|value| match parse_key(value) {
+            Ok(key) => Some(key),
+            Err(error) => {
+                elogger(format!("Storage: the current key from {} is unusable ({}) — files will be written unsealed", source, error));
+                None
+            }
+        });
+    let retired = retired.iter()
+        .filter(|value| !value.trim().is_empty())
+        .enumerate()
+        .filter_map(|(index, value)| match parse_key(value) {
+            Ok(key) => Some(key),
+            Err(error) => {
+                elogger(format!("Storage: retired key #{} from {} is unusable ({})", index + 1, source, error));
+                None
+            }
+        })
+        .collect();
+    Keyring { current, retired }
+}
+
+
+/// The environment first, then `[storage] key_file`, then no keys at all.
+fn load_keyring() -> Keyring {
+    let from_env = std::env::var(STORAGE_KEY_ENV).ok().filter(|value| !value.trim().is_empty());
+    let retired_env = std::env::var(STORAGE_OLD_KEYS_ENV).unwrap_or_default();
+    if from_env.is_some() || !retired_env.trim().is_empty() {
+        let retired: Vec<&str> = retired_env.split(',').collect();
+        return keyring_from(from_env.as_deref(), &retired, "the environment")
+    }
+
+    let Some(key_file) = get_storage_settings().key_file() else {
+        return Keyring::default()
+    };
+    let path = resolve(&key_file);
+    match std::fs::read_to_string(&path) {
+        Ok(content) => {
+            let lines: Vec<&str> = content.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .collect();
+            keyring_from(lines.first().copied(), lines.get(1..).unwrap_or_default(), "the key file")
+        }
+        Err(error) => {
+            elogger(format!("Storage: cannot read the key file '{:?}': {} — files will be written unsealed", path, error));
+            Keyring::default()
+        }
+    }
+}
+
+
+/// Resolves a configured path against the working directory, like every other
+/// runtime path in this service.
+fn resolve(configured: &str) -> PathBuf {
+    let path = PathBuf::from(configured);
+    if path.is_absolute() {
+        return path
+    }
+    get_current_or_root_dir().join(path)
+}
+
+
+/// Whether files are sealed when written.
+pub fn is_enabled() -> bool {
+    KEYRING.current.is_some()
+}
+
+
+/// Whether `bytes` are a sealed file rather than plain text.
+pub fn is_sealed(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+
+/// The associated data: the header, then the context. Binding the context means
+/// a file only opens under the name it was sealed for.
+fn associated_data(header: &[u8], context: &str) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(header.len() + context.len());
+    aad.extend_from_slice(header);
+    aad.extend_from_slice(context.as_bytes());
+    aad
+}
+
+
+/// Seals `plain` under `key` for `context`.
+fn seal_with(key: &SealKey, plain: &[u8], context: &str) -> Result<Vec<u8>, String> {
+    let mut header = Vec::with_capacity(HEADER_BYTES);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&key.id);
+
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let aad = associated_data(&header, context);
+    let ciphertext = key.cipher.encrypt(&nonce, Payload { msg: plain, aad: &aad })
+        .map_err(|_| format!("cannot seal {}", context))?;
+
+    let mut sealed = header;
+    sealed.reserve(NONCE_BYTES + ciphertext.len());
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+
+/// Seals `plain` for writing, or returns it unchanged when no key is
+/// configured. `context` is the file's logical name, and the same value has to
+/// be given to [`open`].
+pub fn seal(plain: Vec<u8>, context: &str) -> Result<Vec<u8>, String> {
+    match &KEYRING.current {
+        Some(key) => seal_with(key, &plain, context),
+        None => Ok(plain)
+    }
+}
+
+
+/// Opens `bytes` read from disk against `keyring`.
+fn open_with(keyring: &Keyring, bytes: Vec<u8>, context: &str) -> Result<Vec<u8>, String> {
+    if !is_sealed(&bytes) {
+        return Ok(bytes)
+    }
+    if bytes.len() < HEADER_BYTES + NONCE_BYTES {
+        return Err(format!("{} is sealed but truncated", context))
+    }
+    let (header, rest) = bytes.split_at(HEADER_BYTES);
+    let (nonce, ciphertext) = rest.split_at(NONCE_BYTES);
+    let Some(key) = keyring.find(&header[MAGIC.len()..]) else {
+        return Err(format!("{} is sealed with a key this server does not hold", context))
+    };
+    let aad = associated_data(header, context);
+    key.cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: &aad })
+        .map_err(|_| format!("{} does not open: altered, or sealed for another file", context))
+}
+
+
+/// Opens `bytes` read from disk: decrypts a sealed file, passes plain text
+/// through unchanged.
+pub fn open(bytes: Vec<u8>, context: &str) -> Result<Vec<u8>, String> {
+    open_with(&KEYRING, bytes, context)
+}
+
+
+/// Reads a text file that may be sealed. The error is a message for the log,
+/// in the shape the callers already print for an unreadable file.
+pub fn read_to_string(path: &Path, context: &str) -> Result<String, String> {
+    let bytes = std::fs::read(path).map_err(|error| error.to_string())?;
+    let plain = open(bytes, context)?;
+    String::from_utf8(plain).map_err(|_| format!("{} is not valid UTF-8", context))
+}
+
+
+/// Reports whether sealing is on. Called from `main.rs` at startup, so the log
+/// says how the secret-grade files are held before anything writes one.
+pub fn init() {
+    let retired = KEYRING.retired.len();
+    match &KEYRING.current {
+        Some(_) => logger(format!(
+            "Storage: secret-grade files are sealed (ChaCha20-Poly1305){}",
+            if retired > 0 { format!(", {} retired key{} still accepted", retired, if retired == 1 { "" } else { "s" }) } else { String::new() }
+        )),
+        None if retired > 0 => logger("Storage: only retired keys configured — sealed files are read, new ones are written in plain text"),
+        None => return
+    }
+
+    let unsealed = targets().iter()
+        .filter(|(path, _)| std::fs::read(path).is_ok_and(|bytes| !bytes.is_empty() && !is_sealed(&bytes)))
+        .count();
+    if is_enabled() && unsealed > 0 {
+        logger(format!(
+            "Storage: {} file{} still in plain text — each is sealed on its next write, or run `rustopus reseal`",
+            unsealed,
+            if unsealed == 1 { "" } else { "s" }
+        ));
+    }
+}
+
+
+/// Every file this module is responsible for, with the context it is sealed
+/// under. Missing files are left out.
+fn targets() -> Vec<(PathBuf, String)> {
+    let mut targets: Vec<(PathBuf, String)> = vec![
+        (precache::get_precache_path(), precache::SEAL_CONTEXT.to_string()),
+        (oauth::store::sessions_path(), oauth::store::SESSIONS_SEAL_CONTEXT.to_string()),
+        (apikey::get_api_keys_path(), apikey::SEAL_CONTEXT.to_string())
+    ];
+    targets.extend(store::stored_paths().into_iter().filter_map(|path| {
+        let context = path.file_name()?.to_string_lossy().to_string();
+        Some((path, context))
+    }));
+    targets.retain(|(path, _)| path.is_file());
+    targets
+}
+
+
+/// What [`reseal_file`] did with one file.
+#[derive(Debug, PartialEq)]
+enum Resealed {
+    /// Already in the shape the keyring asks for.
+    Unchanged,
+    Rewritten
+}
+
+
+/// Rewrites one file under `keyring`'s current key, or in plain text when it
+/// has none. Through a temporary file and a rename, like every writer of these
+/// files, so an interrupted migration leaves each file either old or new.
+fn reseal_file(keyring: &Keyring, path: &Path, context: &str) -> Result<Resealed, String> {
+    let bytes = std::fs::read(path).map_err(|error| error.to_string())?;
+    let already = match &keyring.current {
+        Some(key) => is_sealed(&bytes) && bytes.get(MAGIC.len()..HEADER_BYTES) == Some(&key.id[..]),
+        None => !is_sealed(&bytes)
+    };
+    if already {
+        return Ok(Resealed::Unchanged)
+    }
+
+    let plain = open_with(keyring, bytes, context)?;
+    let content = match &keyring.current {
+        Some(key) => seal_with(key, &plain, context)?,
+        None => plain
+    };
+
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".reseal");
+    let temporary = PathBuf::from(temporary);
+    std::fs::write(&temporary, content).map_err(|error| error.to_string())?;
+    restrict_permissions(&temporary);
+    std::fs::rename(&temporary, path).map_err(|error| error.to_string())?;
+    restrict_permissions(path);
+    Ok(Resealed::Rewritten)
+}
+
+
+/// Narrows a file to owner read/write. Logged rather than fatal, like the
+/// writers whose files this rewrites.
+fn restrict_permissions(path: &Path) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Err(error) = std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)) {
+            elogger(format!("Storage: cannot restrict permissions on '{:?}': {}", path, error));
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+    }
+}
+
+
+/// `rustopus reseal`: the one-shot migration. Seals every plain-text file,
+/// moves every file sealed under a retired key to the current one — or, with
+/// no current key, writes them all back in plain text.
+///
+/// Run it with the server stopped: a running instance would keep its own view
+/// of the files and could write over a file this just rewrote.
+pub fn reseal_command() -> std::io::Result<()> {
+    if KEYRING.current.is_none() && KEYRING.retired.is_empty() {
+        elogger(format!("Storage: no key configured — set {} or [storage] key_file first", STORAGE_KEY_ENV));
+        return Err(std::io::Error::other("no storage key configured"))
+    }
+
+    let (mut rewritten, mut unchanged, mut failed) = (0usize, 0usize, 0usize);
+    for (path, context) in targets() {
+        match reseal_file(&KEYRING, &path, &context) {
+            Ok(Resealed::Rewritten) => rewritten += 1,
+            Ok(Resealed::Unchanged) => unchanged += 1,
+            Err(error) => {
+                failed += 1;
+                elogger(format!("Storage: cannot reseal '{:?}': {}", path, error));
+            }
+        }
+    }
+
+    // With the SQLite backend the secret rows are in the database rather than
+    // in files: loading opens them under any known key, saving seals them
+    // under the current one.
+    state::init()?;
+    match state::store().reseal() {
+        Ok(0) => {}
+        Ok(rows) => logger(format!("Storage: {} database rows rewritten", rows)),
+        Err(error) => {
+            failed += 1;
+            elogger(format!("Storage: cannot reseal the state database: {}", error));
+        }
+    }
+
+    logger(format!(
+        "Storage: reseal done — {} rewritten {}, {} already so, {} failed",
+        rewritten,
+        if is_enabled() { "sealed" } else { "in plain text" },
+        unchanged,
+        failed
+    ));
+    if failed > 0 {
+        return Err(std::io::Error::other(format!("{} file(s) or database could not be resealed", failed)))
+    }
+    Ok(())
+}
+
+
+/// `rustopus new-storage-key`: a fresh random key, base64, for the environment
+/// or the key file.
+pub fn new_key() -> String {
+    let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+    base64::engine::general_purpose::STANDARD.encode(key)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyring(current: Option<&str>, retired: &[&str]) -> Keyring {
+        keyring_from(current, retired, "the test")
+    }
+
+    /// A scratch directory that cleans itself up.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("rustopus-sealed-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).expect("creates dir");
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn a_sealed_file_opens_and_hides_its_content() {
+        let key = new_key();
+        let ring = keyring(Some(&key), &[]);
+        let sealed = seal_with(ring.current.as_ref().unwrap(), b"authcode = \"FFD3ABCDEF120E37\"", "mcp_precache.toml").expect("seals");
+
+        assert!(is_sealed(&sealed));
+        assert!(!String::from_utf8_lossy(&sealed).contains("FFD3ABCDEF120E37"));
+        assert_eq!(open_with(&ring, sealed, "mcp_precache.toml").expect("opens"), b"authcode = \"FFD3ABCDEF120E37\"");
+    }
+
+    #[test]
+    fn plain_text_passes_through() {
+        let ring = keyring(Some(&new_key()), &[]);
+        assert_eq!(open_with(&ring, b"[[entry]]".to_vec(), "mcp_precache.toml").expect("opens"), b"[[entry]]");
+    }
+
+    #[test]
+    fn a_file_does_not_open_under_another_name() {
+        // One partner's snapshot copied over another's file name must not be
+        // served as theirs.
+        let ring = keyring(Some(&new_key()), &[]);
+        let sealed = seal_with(ring.current.as_ref().unwrap(), b"prices", "aaaa-1.json.gz").expect("seals");
+        assert!(open_with(&ring, sealed, "bbbb-2.json.gz").is_err());
+    }
+
+    #[test]
+    fn an_altered_file_does_not_open() {
+        let ring = keyring(Some(&new_key()), &[]);
+        let mut sealed = seal_with(ring.current.as_ref().unwrap(), b"prices", "a").expect("seals");
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(open_with(&ring, sealed.clone(), "a").is_err());
+        assert!(open_with(&ring, sealed[..HEADER_BYTES + 3].to_vec(), "a").is_err());
+    }
+
+    #[test]
+    fn a_retired_key_opens_but_an_unknown_one_does_not() {
+        let (old, new) = (new_key(), new_key());
+        let sealed = seal_with(keyring(Some(&old), &[]).current.as_ref().unwrap(), b"x", "a").expect("seals");
+
+        assert_eq!(open_with(&keyring(Some(&new), &[&old]), sealed.clone(), "a").expect("opens"), b"x");
+        let error = open_with(&keyring(Some(&new), &[]), sealed, "a").expect_err("refused");
+        assert!(error.contains("does not hold"));
+    }
+
+    #[test]
+    fn malformed_keys_are_refused_without_echoing_them() {
+        assert!(parse_key("not base64 at all!").is_err());
+        let short = base64::engine::general_purpose::STANDARD.encode([7u8; 16]);
+        let error = parse_key(&short).err().expect("refused");
+        assert!(!error.contains(&short));
+        assert!(keyring(Some(&short), &[]).current.is_none());
+    }
+
+    #[test]
+    fn reseal_migrates_rotates_and_switches_off() {
+        let dir = TempDir::new("reseal");
+        let path = dir.0.join("oauth_sessions.toml");
+        std::fs::write(&path, "[[grant]]\n").expect("writes");
+        let (old, new) = (new_key(), new_key());
+
+        // Plain text → sealed under the old key, and a second pass is a no-op.
+        let first = keyring(Some(&old), &[]);
+        assert_eq!(reseal_file(&first, &path, "oauth_sessions.toml"), Ok(Resealed::Rewritten));
+        assert_eq!(reseal_file(&first, &path, "oauth_sessions.toml"), Ok(Resealed::Unchanged));
+
+        // Rotation: the new key first, the old one retired.
+        let rotated = keyring(Some(&new), &[&old]);
+        assert_eq!(reseal_file(&rotated, &path, "oauth_sessions.toml"), Ok(Resealed::Rewritten));
+        let bytes = std::fs::read(&path).expect("reads");
+        assert!(open_with(&keyring(Some(&new), &[]), bytes, "oauth_sessions.toml").is_ok());
+
+        // Retired keys only: back to plain text.
+        let off = keyring(None, &[&new]);
+        assert_eq!(reseal_file(&off, &path, "oauth_sessions.toml"), Ok(Resealed::Rewritten));
+        assert_eq!(std::fs::read_to_string(&path).expect("reads"), "[[grant]]\n");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).expect("stat").permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
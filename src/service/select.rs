@@ -0,0 +1,586 @@
+//! Column projection (`fields=`) and simple filters (`brand=`, `category=`,
+//! `in_stock=`, `no=`, `published_since=`) for the product, price, stock and bulk
+//! fetchers.
+//!
+//! Both run **after** translation, on the records the caller is about to
+//! receive, so one set of rules covers XML, CSV and XLSX. Octopus has no
+//! server-side equivalent: the full list is still fetched, but a partner that
+//! only needs `no`, `price` and `stock` no longer downloads the whole product
+//! master to get them.
+//!
+//! Filters match on whatever the record carries. A filter the record type has
+//! no field for (a `brand=` on `/get-stock`) is logged and ignored rather than
+//! turned into an empty answer, which would look like a catalog with nothing
+//! in it.
+
+use std::collections::HashSet;
+
+use actix_web::HttpResponse;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    forms::{
+        r#in::xml::{
+            products as o8_products,
+            prices as o8_prices,
+            stocks as o8_stocks
+        },
+        out::{
+            csv,
+            xml::{bulk, bulk_hu, prices, products, stocks}
+        }
+    },
+    routes::default::{RequestParameters, return_internal_server_error, send_csv, send_xlsx},
+    service::{
+        get_data::to_xml_string,
+        log::{elog_with_ip_uuid, log_with_ip_uuid}
+    }
+};
+
+
+/// A record the filters can be applied to.
+///
+/// The `HAS_*` constants say which filters make sense for the type; the
+/// accessors behind a `false` constant are never called.
+pub trait Filterable {
+    const HAS_BRAND: bool = false;
+    const HAS_CATEGORY: bool = false;
+    const HAS_STOCK: bool = false;
+    const HAS_DATE: bool = false;
+
+    /// Article number.
+    fn no(&self) -> &str;
+
+    fn brand(&self) -> &str {
+        ""
+    }
+
+    /// Every category code and name the record belongs to, main group included.
+    fn categories(&self) -> [&str; 4] {
+        [""; 4]
+    }
+
+    fn stock(&self) -> Option<f64> {
+        None
+    }
+
+    /// The date the record carries for `published_since`: the day the product
+    /// went on the web (`web_available_from`). It says nothing about later
+    /// changes — those are what the upstream `from_date=` pull is for.
+    fn published(&self) -> Option<NaiveDate> {
+        None
+    }
+}
+
+
+/// Implements [`Filterable`] for the product-shaped records, English and
+/// Hungarian, which all carry brand and both category levels.
+macro_rules! filterable_product {
+    ($type:ty, $has_stock:expr, $no:ident, $brand:ident, [$($category:ident),*], $stock:expr, $has_date:expr, $published:expr) => {
+        impl Filterable for $type {
+            const HAS_BRAND: bool = true;
+            const HAS_CATEGORY: bool = true;
+            const HAS_STOCK: bool = $has_stock;
+            const HAS_DATE: bool = $has_date;
+
+            fn no(&self) -> &str {
+                &self.$no
+            }
+
+            fn brand(&self) -> &str {
+                &self.$brand
+            }
+
+            fn categories(&self) -> [&str; 4] {
+                [$(&self.$category),*]
+            }
+
+            fn stock(&self) -> Option<f64> {
+                let stock: fn(&Self) -> Option<f64> = $stock;
+                stock(self)
+            }
+
+            fn published(&self) -> Option<NaiveDate> {
+                let published: fn(&Self) -> Option<NaiveDate> = $published;
+                published(self)
+            }
+        }
+    };
+}
+
+filterable_product!(products::Product, false, no, brand, [category_code, category_name, main_category_code, main_category_name], |_| None, true, |p| p.web_available_from);
+filterable_product!(csv::products::Product, false, no, brand, [category_code, category_name, main_category_code, main_category_name], |_| None, true, |p| p.web_available_from);
+filterable_product!(o8_products::Cikk, false, cikkszam, gyarto, [cikkcsoportkod, cikkcsoportnev, focsoportkod, focsoportnev], |_| None, true, |c| c.webigendatum);
+filterable_product!(bulk::Product, true, no, brand, [category_code, category_name, main_category_code, main_category_name], |p| p.stock, true, |p| p.web_available_from);
+filterable_product!(bulk_hu::Cikk, true, cikkszam, gyarto, [cikkcsoportkod, cikkcsoportnev, focsoportkod, focsoportnev], |c| c.keszlet, false, |_| None);
+filterable_product!(csv::bulk::Product, true, no, brand, [category_code, category_name, main_category_code, main_category_name], |p| p.stock, true, |p| p.web_available_from);
+
+
+impl Filterable for prices::Price {
+    fn no(&self) -> &str {
+        &self.no
+    }
+}
+
+impl Filterable for csv::prices::Price {
+    fn no(&self) -> &str {
+        &self.no
+    }
+}
+
+impl Filterable for o8_prices::Ar {
+    fn no(&self) -> &str {
+        &self.cikkszam
+    }
+}
+
+
+/// Implements [`Filterable`] for the stock records, which carry only the
+/// article number and the free stock.
+macro_rules! filterable_stock {
+    ($type:ty, $no:ident, $stock:ident) => {
+        impl Filterable for $type {
+            const HAS_STOCK: bool = true;
+
+            fn no(&self) -> &str {
+                &self.$no
+            }
+
+            fn stock(&self) -> Option<f64> {
+                self.$stock
+            }
+        }
+    };
+}
+
+filterable_stock!(stocks::Product, no, stock);
+filterable_stock!(csv::stocks::Product, no, stock);
+filterable_stock!(o8_stocks::Cikk, cikkszam, szabad);
+
+
+/// The projection and filters one request asked for.
+#[derive(Debug, Default, Clone)]
+pub struct Selection {
+    /// Requested columns, in the order asked for. `None` keeps every column.
+    fields: Option<Vec<String>>,
+    /// Accepted brands, folded to lowercase. Empty means no brand filter.
+    brands: Vec<String>,
+    /// Accepted category codes or names, folded to lowercase.
+    categories: Vec<String>,
+    /// `Some(true)` keeps records with stock above zero, `Some(false)` the rest.
+    in_stock: Option<bool>,
+    /// Accepted article numbers, matched exactly.
+    numbers: HashSet<String>,
+    /// Keeps records published on or after this day; unpublished ones are
+    /// dropped.
+    published_since: Option<NaiveDate>
+}
+
+
+/// Splits a comma-separated parameter into its trimmed, non-empty parts.
+fn split_list(value: Option<&String>) -> Vec<String> {
+    value
+        .map(|value| value.split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .map(str::to_string)
+            .collect())
+        .unwrap_or_default()
+}
+
+
+impl Selection {
+    /// Reads the projection and filter parameters. Absent or empty parameters
+    /// select nothing away.
+    pub fn from_params(params: &RequestParameters) -> Self {
+        let fields = split_list(params.fields.as_ref());
+        Self {
+            fields: (!fields.is_empty()).then_some(fields),
+            brands: split_list(params.brand.as_ref()).iter().map(|brand| brand.to_lowercase()).collect(),
+            categories: split_list(params.category.as_ref()).iter().map(|category| category.to_lowercase()).collect(),
+            in_stock: params.in_stock.map(|in_stock| in_stock != 0),
+            numbers: split_list(params.no.as_ref()).into_iter().collect(),
+            published_since: params.published_since.as_ref().map(DateTime::<Utc>::date_naive)
+        }
+    }
+
+    /// Whether a column projection was asked for.
+    pub fn projects(&self) -> bool {
+        self.fields.is_some()
+    }
+
+    /// Whether any filter was asked for.
+    pub fn filters(&self) -> bool {
+        !self.brands.is_empty() || !self.categories.is_empty() || self.in_stock.is_some() || !self.numbers.is_empty()
+            || self.published_since.is_some()
+    }
+
+    /// Drops the records the filters reject. Filters the record type cannot
+    /// answer are logged once and skipped.
+    pub fn retain<T: Filterable>(&self, records: &mut Vec<T>, ip_address: &str, uuid: &str) {
+        if !self.filters() {
+            return
+        }
+
+        let mut ignored = Vec::new();
+        let brands = !self.brands.is_empty() && T::HAS_BRAND;
+        if !self.brands.is_empty() && !T::HAS_BRAND {
+            ignored.push("brand");
+        }
+        let categories = !self.categories.is_empty() && T::HAS_CATEGORY;
+        if !self.categories.is_empty() && !T::HAS_CATEGORY {
+            ignored.push("category");
+        }
+        let in_stock = self.in_stock.filter(|_| T::HAS_STOCK);
+        if self.in_stock.is_some() && !T::HAS_STOCK {
+            ignored.push("in_stock");
+        }
+        let published_since = self.published_since.filter(|_| T::HAS_DATE);
+        if self.published_since.is_some() && !T::HAS_DATE {
+            ignored.push("published_since");
+        }
+        if !ignored.is_empty() {
+            log_with_ip_uuid(ip_address, uuid, format!("Ignoring filter(s) this endpoint has no field for: {}", ignored.join(", ")));
+        }
+
+        let before = records.len();
+        records.retain(|record| {
+            (self.numbers.is_empty() || self.numbers.contains(record.no().trim()))
+                && (!brands || self.brands.iter().any(|brand| record.brand().trim().to_lowercase() == *brand))
+                && (!categories || record.categories().iter().any(|category| {
+                    let category = category.trim().to_lowercase();
+                    self.categories.contains(&category)
+                }))
+                && in_stock.is_none_or(|wanted| (record.stock().unwrap_or(0.0) > 0.0) == wanted)
+                && published_since.is_none_or(|since| record.published().is_some_and(|published| published >= since))
+        });
+        log_with_ip_uuid(ip_address, uuid, format!("Filters kept {} of {} records", records.len(), before));
+    }
+
+    /// The English XML for an envelope, projected to the requested fields.
+    ///
+    /// `list` is the path from the envelope's `answer` element to the record
+    /// array, e.g. `["products", "product"]`. Without `fields=` this is the
+    /// ordinary serializer output.
+    pub fn to_xml<T: Serialize>(&self, envelope: &T, list: &[&str], ip_address: &str, uuid: &str) -> String {
+        let Some(fields) = &self.fields else {
+            return to_xml_string(envelope)
+        };
+
+        let mut value = match serde_json::to_value(envelope) {
+            Ok(value) => value,
+            Err(error) => {
+                elog_with_ip_uuid(ip_address, uuid, format!("Cannot project the response, sending every field: {}", error));
+                return to_xml_string(envelope)
+            }
+        };
+
+        let records = ["body", "response", "result", "answer"].iter()
+            .chain(list)
+            .try_fold(&mut value, |value, key| value.get_mut(*key));
+        if let Some(Value::Array(records)) = records {
+            for record in records.iter_mut() {
+                if let Value::Object(map) = record {
+                    *map = project(map, fields);
+                }
+            }
+        }
+
+        quick_xml::se::to_string_with_root("Envelope", &value).unwrap_or_else(|error| {
+            elog_with_ip_uuid(ip_address, uuid, format!("Cannot serialize the projected response: {}", error));
+            to_xml_string(envelope)
+        })
+    }
+
+    /// CSV of the records, projected to the requested fields.
+    pub fn send_csv<T: Serialize>(&self, records: &[T], filename: &str, hu_headers: Option<&[&str]>) -> HttpResponse {
+        let Some(fields) = &self.fields else {
+            return send_csv(records, filename, hu_headers)
+        };
+        let Some((headers, rows)) = self.table(records, fields, hu_headers) else {
+            return return_internal_server_error()
+        };
+
+        let mut wtr = ::csv::WriterBuilder::new()
+            .delimiter(b';')
+            .from_writer(vec![]);
+        let written = wtr.write_record(&headers).is_ok()
+            && rows.iter().all(|row| wtr.write_record(row.iter().map(cell_text)).is_ok());
+        match wtr.into_inner() {
+            Ok(data) if written => HttpResponse::Ok()
+                .content_type("text/csv")
+                .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\"", filename)))
+                .body(data),
+            _ => return_internal_server_error()
+        }
+    }
+
+    /// XLSX of the records, projected to the requested fields. Numbers stay
+    /// numbers, as in the unprojected sheet.
+    pub fn send_xlsx<T: Serialize>(&self, records: &[T], filename: &str, hu_headers: Option<&[&str]>) -> HttpResponse {
+        let Some(fields) = &self.fields else {
+            return send_xlsx(records, filename, hu_headers)
+        };
+        let Some((headers, rows)) = self.table(records, fields, hu_headers) else {
+            return return_internal_server_error()
+        };
+
+        let build = || -> Result<Vec<u8>, rust_xlsxwriter::XlsxError> {
+            let mut workbook = rust_xlsxwriter::Workbook::new();
+            let worksheet = workbook.add_worksheet();
+            for (col, header) in headers.iter().enumerate() {
+                worksheet.write_string(0, col as u16, header)?;
+            }
+            for (index, row) in rows.iter().enumerate() {
+                let row_number = index as u32 + 1;
+                for (col, cell) in row.iter().enumerate() {
+                    match cell {
+                        Value::Null => continue,
+                        Value::Number(number) => worksheet.write_number(row_number, col as u16, number.as_f64().unwrap_or_default())?,
+                        other => worksheet.write_string(row_number, col as u16, cell_text(other))?
+                    };
+                }
+            }
+            workbook.save_to_buffer()
+        };
+
+        match build() {
+            Ok(data) => HttpResponse::Ok()
+                .content_type("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet")
+                .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\"", filename)))
+                .body(data),
+            Err(_) => return_internal_server_error()
+        }
+    }
+
+    /// Header row and projected cells for a flat record list.
+    ///
+    /// Unknown field names are dropped. Hungarian headers are picked by the
+    /// field's position in the full record, so they are used only when
+    /// `hu_headers` lists exactly one per column; otherwise a position would
+    /// name the wrong column, and the field names are used instead.
+    fn table<T: Serialize>(&self, records: &[T], fields: &[String], hu_headers: Option<&[&str]>) -> Option<(Vec<String>, Vec<Vec<Value>>)> {
+        // Drives the language-aware `serialize_with` helpers (e.g. bool -> Igaz/Hamis)
+        crate::tools::csv::set_csv_hu(hu_headers.is_some());
+        let rows: Result<Vec<Value>, _> = records.iter().map(serde_json::to_value).collect();
+        // Reset so the flag never leaks to a later (English) export on this thread
+        crate::tools::csv::set_csv_hu(false);
+        let rows = rows.ok()?;
+
+        let all: Vec<String> = match rows.first() {
+            Some(Value::Object(map)) => map.keys().cloned().collect(),
+            _ => fields.to_vec()
+        };
+        let kept: Vec<&String> = fields.iter().filter(|field| all.contains(field)).collect();
+        let hu_headers = hu_headers.filter(|hu| hu.len() == all.len());
+
+        let headers = kept.iter()
+            .map(|field| {
+                let position = all.iter().position(|name| name == *field);
+                match (hu_headers, position) {
+                    (Some(hu), Some(position)) => hu.get(position).map(|header| header.to_string()).unwrap_or_else(|| field.to_string()),
+                    _ => field.to_string()
+                }
+            })
+            .collect();
+
+        let rows = rows.into_iter()
+            .map(|row| kept.iter().map(|field| row.get(field.as_str()).cloned().unwrap_or(Value::Null)).collect())
+            .collect();
+
+        Some((headers, rows))
+    }
+}
+
+
+/// Keeps the requested keys of one record, in the order they were asked for.
+/// Unknown keys are skipped.
+fn project(record: &Map<String, Value>, fields: &[String]) -> Map<String, Value> {
+    fields.iter()
+        .filter_map(|field| record.get(field).map(|value| (field.clone(), value.clone())))
+        .collect()
+}
+
+
+/// One CSV cell. Matches what the `csv` serializer writes for the same value:
+/// empty for a missing one, the plain text otherwise.
+fn cell_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        Value::Bool(flag) => flag.to_string(),
+        Value::Number(number) => number.to_string(),
+        other => other.to_string()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroU8;
+
+    fn params(query: &str) -> RequestParameters {
+        actix_web::web::Query::<RequestParameters>::from_query(query)
+            .expect("the test query must parse")
+            .into_inner()
+    }
+
+    fn stock(no: &str, stock: Option<f64>) -> stocks::Product {
+        stocks::Product { id: 1, no: no.into(), stock }
+    }
+
+    fn product(no: &str, brand: &str, category: &str) -> products::Product {
+        let one = NonZeroU8::new(1).unwrap();
+        products::Product {
+            id: 7,
+            no: no.into(),
+            name: format!("{} name", no),
+            unit: "db".into(),
+            base_unit: "db".into(),
+            base_unit_qty: Some(1.0),
+            brand: brand.into(),
+            category_code: category.into(),
+            category_name: format!("{} group", category),
+            v_type: one,
+            supply_status: one,
+            web_available: one,
+            web_available_from: None,
+            description: String::new(),
+            weight: None,
+            size: None,
+            oem_code: String::new(),
+            main_category_code: "MAIN".into(),
+            main_category_name: "Main".into(),
+            sell_unit: None,
+            origin_country: "HU".into()
+        }
+    }
+
+    #[test]
+    fn no_parameters_select_nothing_away() {
+        let selection = Selection::from_params(&params(""));
+        assert!(!selection.filters());
+
+        let mut records = vec![stock("A", None), stock("B", Some(3.0))];
+        selection.retain(&mut records, "", "");
+        assert_eq!(records.len(), 2);
+    }
+
+    #[test]
+    fn an_article_number_list_matches_exactly_and_ignores_blanks() {
+        let selection = Selection::from_params(&params("no=A,%20C%20,,"));
+        let mut records = vec![stock("A", None), stock("AB", None), stock("C", None)];
+        selection.retain(&mut records, "", "");
+        assert_eq!(records.iter().map(|r| r.no.as_str()).collect::<Vec<_>>(), vec!["A", "C"]);
+    }
+
+    #[test]
+    fn in_stock_keeps_positive_stock_and_zero_keeps_the_rest() {
+        let mut records = vec![stock("A", None), stock("B", Some(0.0)), stock("C", Some(2.0))];
+        Selection::from_params(&params("in_stock=1")).retain(&mut records, "", "");
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].no, "C");
+
+        let mut records = vec![stock("A", None), stock("B", Some(0.0)), stock("C", Some(2.0))];
+        Selection::from_params(&params("in_stock=0")).retain(&mut records, "", "");
+        assert_eq!(records.len(), 2);
+    }
+
+    #[test]
+    fn brand_and_category_match_case_insensitively_on_code_or_name() {
+        let mut records = vec![
+            product("A", "Samsung", "MON"),
+            product("B", "LG", "MON"),
+            product("C", "samsung", "TV")
+        ];
+        Selection::from_params(&params("brand=SAMSUNG&category=mon%20group")).retain(&mut records, "", "");
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].no, "A");
+
+        let mut records = vec![product("A", "Samsung", "MON"), product("B", "LG", "TV")];
+        Selection::from_params(&params("category=main")).retain(&mut records, "", "");
+        assert_eq!(records.len(), 2, "the main group matches too");
+    }
+
+    #[test]
+    fn a_filter_the_record_has_no_field_for_is_ignored() {
+        // Stock records carry no brand: an empty answer would look like an
+        // empty catalog rather than a parameter that does not apply here.
+        let mut records = vec![stock("A", Some(1.0)), stock("B", Some(1.0))];
+        Selection::from_params(&params("brand=Samsung")).retain(&mut records, "", "");
+        assert_eq!(records.len(), 2);
+    }
+
+    #[test]
+    fn without_fields_the_xml_is_the_ordinary_serializer_output() {
+        let envelope = stocks::Envelope {
+            body: stocks::Body {
+                response: stocks::GetStockChangeAuthResponse {
+                    result: stocks::GetStockChangeAuthResult {
+                        answer: stocks::Answer {
+                            version: "1.0".into(),
+                            products: stocks::Products { product: vec![stock("A", Some(1.5)), stock("B", None)] },
+                            page: None,
+                            error: None
+                        }
+                    }
+                }
+            }
+        };
+        let selection = Selection::from_params(&params(""));
+        assert_eq!(selection.to_xml(&envelope, &["products", "product"], "", ""), to_xml_string(&envelope));
+
+        // Projecting onto every field in the original order must not change a
+        // byte either, or the projected output drifts from the documented shape.
+        let every = Selection::from_params(&params("fields=id,no,stock"));
+        assert_eq!(every.to_xml(&envelope, &["products", "product"], "", ""), to_xml_string(&envelope));
+
+        let projected = Selection::from_params(&params("fields=stock,no,nonsense"))
+            .to_xml(&envelope, &["products", "product"], "", "");
+        assert!(projected.contains("<product><stock>1.5</stock><no>A</no></product>"), "{}", projected);
+        assert!(!projected.contains("<id>"), "{}", projected);
+        assert!(projected.contains("<version>1.0</version>"), "the envelope around the records is untouched: {}", projected);
+    }
+
+    #[test]
+    fn a_header_list_that_misses_a_column_is_not_used_for_projection() {
+        // Picking by position from a short list would put each header after
+        // the gap over the wrong column.
+        let records = vec![csv::stocks::Product { id: 1, no: "A".into(), stock: Some(2.0) }];
+        let selection = Selection::from_params(&params("fields=stock,no"));
+        let (headers, _) = selection.table(&records, selection.fields.as_ref().unwrap(), Some(&["Cikkszám", "Szabad készlet"])).unwrap();
+        assert_eq!(headers, vec!["stock", "no"]);
+    }
+
+    #[test]
+    fn published_since_keeps_records_published_since_and_is_ignored_without_a_date() {
+        let date = |day: &str| NaiveDate::parse_from_str(day, "%Y-%m-%d").ok();
+        let mut old = product("A", "Pax", "MON");
+        old.web_available_from = date("2026-01-31");
+        let mut new = product("B", "Pax", "MON");
+        new.web_available_from = date("2026-02-01");
+        let undated = product("C", "Pax", "MON");
+
+        let selection = Selection::from_params(&params("published_since=2026-02-01T10:00:00Z"));
+        let mut records = vec![old, new, undated];
+        selection.retain(&mut records, "", "");
+        assert_eq!(records.iter().map(|r| r.no.as_str()).collect::<Vec<_>>(), vec!["B"]);
+
+        // Price and stock records carry no date, so the filter does not apply.
+        let mut records = vec![stock("A", Some(1.0)), stock("B", None)];
+        selection.retain(&mut records, "", "");
+        assert_eq!(records.len(), 2);
+    }
+
+    #[test]
+    fn a_projected_table_takes_hungarian_headers_by_field_position() {
+        let records = vec![csv::stocks::Product { id: 1, no: "A".into(), stock: Some(2.0) }];
+        let selection = Selection::from_params(&params("fields=stock,no"));
+        let (headers, rows) = selection.table(&records, selection.fields.as_ref().unwrap(), Some(csv::stocks::HU_HEADERS)).unwrap();
+        assert_eq!(headers, vec!["Szabad készlet", "Cikkszám"]);
+        assert_eq!(rows[0].iter().map(cell_text).collect::<Vec<_>>(), vec!["2.0", "A"]);
+    }
+}
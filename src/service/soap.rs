@@ -5,7 +5,8 @@ use std::time::Duration;
 use once_cell::sync::Lazy;
 use futures::FutureExt;
 use futures::future::{BoxFuture, Shared};
-use tokio::sync::Semaphore;
+use moka::future::Cache;
+use sha2::{Digest, Sha256};
 use reqwest::{
     Client,
     header::CONTENT_TYPE
@@ -13,12 +14,26 @@ use reqwest::{
 
 use crate::service::{
     config,
-    log::{logger, elogger}
+    log::{logger, elogger},
+    soap_gate::{self, Lane}
 };
 
-/// Default cap on concurrent outbound SOAP calls when `Config.toml` doesn't
-/// set `[server] soap_concurrency`.
-const DEFAULT_SOAP_CONCURRENCY: usize = 4;
+/// Default cap on concurrent outbound SOAP calls per backend when
+/// `Config.toml` doesn't set `[server] soap_concurrency` (see `soap_gate`).
+pub const DEFAULT_SOAP_CONCURRENCY: usize = 4;
+
+/// Lifetime of a paged request's cached response when `Config.toml` doesn't
+/// set `[server] page_cache_ttl_secs`: 5 minutes. Long enough to walk a
+/// catalog page by page, short enough that the last page is not an hour older
+/// than the first.
+const DEFAULT_PAGE_CACHE_TTL_SECS: u64 = 300;
+
+/// Page cache budget when `Config.toml` doesn't set
+/// `[server] page_cache_max_bytes`: 200 MB, room for four full product lists.
+const DEFAULT_PAGE_CACHE_MAX_BYTES: u64 = 200_000_000;
+
+/// What a fetcher gets back when the upstream call itself failed.
+const FALLBACK_RESPONSE: &str = "<Envelope></Envelope>";
 
 /// Process-wide reqwest client, built once so the connection pool (and TLS
 /// sessions) are reused across every outbound Octopus call.
@@ -34,14 +49,6 @@ static CLIENT: Lazy<Client> = Lazy::new(|| {
     }
 });
 
-/// Caps how many outbound SOAP calls run at once; excess requests wait here
-/// (an idle async task is nearly free) instead of stacking response buffers
-/// in memory. Coalesced waiters (see `get_response_shared`) never consume a
-/// permit — the gate sits inside the one real fetch.
-static SOAP_GATE: Lazy<Semaphore> = Lazy::new(|| {
-    Semaphore::new(config::get_settings().server.soap_concurrency.unwrap_or(DEFAULT_SOAP_CONCURRENCY))
-});
-
 /// One in-flight upstream fetch that identical concurrent requests attach to.
 /// The `id` guards cleanup: an entry is only removed by the caller that
 /// created it, so a newer future under the same key is never deleted early.
@@ -57,19 +64,44 @@ static IN_FLIGHT: Lazy<Mutex<HashMap<(String, String), InFlight>>> = Lazy::new(|
 
 static NEXT_IN_FLIGHT_ID: AtomicU64 = AtomicU64::new(0);
 
+/// Raw upstream responses of paged requests, so the pages after the first are
+/// cut from one Octopus call instead of each re-reading the whole list.
+///
+/// Only [`get_response_paged`] reads or fills it: an unpaged call still reads
+/// live, as every existing consumer of the fetchers expects. Keys are a hash of
+/// `(url, soap_request)` rather than the pair itself, so no authcode sits in the
+/// key set; since the authcode is part of what is hashed, one partner can never
+/// be handed another's response.
+static PAGE_CACHE: Lazy<Cache<String, Arc<String>>> = Lazy::new(|| {
+    let server = config::get_settings().server;
+    Cache::builder()
+        .max_capacity(server.page_cache_max_bytes.unwrap_or(DEFAULT_PAGE_CACHE_MAX_BYTES))
+        // Weighed by length so the budget is in bytes; one product list is
+        // tens of megabytes. moka's weigher is u32-wide.
+        .weigher(|_key, value: &Arc<String>| value.len().min(u32::MAX as usize) as u32)
+        .time_to_live(Duration::from_secs(server.page_cache_ttl_secs.unwrap_or(DEFAULT_PAGE_CACHE_TTL_SECS)))
+        .build()
+});
+
 /// This function handles the request to the given url with the given soap string, theoretically it can handle other requests too
+///
+/// The call waits for a slot in the current task's lane (`soap_gate`).
 pub async fn get_response(url: &str, soap_request: String) -> String {
+    fetch(url, soap_request, soap_gate::current_lane()).await
+}
+
+/// [`get_response`] in an explicit lane, for a future that may be polled from
+/// another task than the one that created it.
+async fn fetch(url: &str, soap_request: String, lane: Lane) -> String {
     // Wait for a free slot; the permit is held only for the duration of this
-    // one HTTP round-trip. `acquire` can only fail if the semaphore is closed
-    // (never done here) — on that impossible error, log and fetch ungated
-    // rather than fail the request.
-    let _permit = match SOAP_GATE.acquire().await {
-        Ok(permit) => Some(permit),
-        Err(error) => {
-            elogger(format!("SOAP gate error (continuing without permit): {}", error));
-            None
-        }
-    };
+    // one HTTP round-trip, and coalesced waiters (see `get_response_shared`)
+    // never take one — the gate sits inside the one real fetch. Acquiring can
+    // only fail if a semaphore is closed (never done here) — on that impossible
+    // error, log and fetch ungated rather than fail the request.
+    let permit = soap_gate::acquire(url, lane).await;
+    if permit.is_none() {
+        elogger("SOAP gate error (continuing without permit)");
+    }
 
     match CLIENT
         .post(url)
@@ -83,7 +115,7 @@ pub async fn get_response(url: &str, soap_request: String) -> String {
             },
             Err(error) => elogger(format!("Response error: {}", error))
     }
-    "<Envelope></Envelope>".into()
+    FALLBACK_RESPONSE.into()
 }
 
 /// Singleflight variant of [`get_response`] for the read-only GET fetchers:
@@ -107,7 +139,10 @@ pub async fn get_response_shared(url: &str, soap_request: String) -> Arc<String>
             }
             None => {
                 let owned_url = url.to_string();
-                let fut = async move { Arc::new(get_response(&owned_url, soap_request).await) }
+                // The lane of the caller that starts the fetch: a shared future
+                // runs in whichever task polls it, which may be a joiner's.
+                let lane = soap_gate::current_lane();
+                let fut = async move { Arc::new(fetch(&owned_url, soap_request, lane).await) }
                     .boxed()
                     .shared();
                 let id = NEXT_IN_FLIGHT_ID.fetch_add(1, Ordering::Relaxed);
@@ -131,3 +166,67 @@ pub async fn get_response_shared(url: &str, soap_request: String) -> Arc<String>
 
     response
 }
+
+
+/// [`PAGE_CACHE`] key of one upstream request.
+fn page_cache_key(url: &str, soap_request: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(url.as_bytes());
+    hasher.update([0]);
+    hasher.update(soap_request.as_bytes());
+    hasher.finalize().iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+
+/// Whether a response may be replayed to later pages. Neither the transport
+/// fallback nor an Octopus error answer is: either would be served to every
+/// page of the walk until the entry expired.
+fn is_cacheable(response: &str) -> bool {
+    response != FALLBACK_RESPONSE && !response.contains("<hiba")
+}
+
+
+/// [`get_response_shared`] for the fetchers that page. When `paged` is set the
+/// response comes from, or goes into, [`PAGE_CACHE`]; otherwise this is exactly
+/// `get_response_shared`.
+pub async fn get_response_paged(url: &str, soap_request: String, paged: bool) -> Arc<String> {
+    if !paged {
+        return get_response_shared(url, soap_request).await
+    }
+
+    let key = page_cache_key(url, &soap_request);
+    if let Some(response) = PAGE_CACHE.get(&key).await {
+        logger(format!("Serving a page from the cached response of '{}'", url));
+        return response
+    }
+
+    let response = get_response_shared(url, soap_request).await;
+    if is_cacheable(&response) {
+        PAGE_CACHE.insert(key, response.clone()).await;
+    }
+    response
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_cache_keys_separate_every_part_of_the_request() {
+        let key = page_cache_key("https://a.test/x", "<auth>ONE</auth>");
+        assert_eq!(key, page_cache_key("https://a.test/x", "<auth>ONE</auth>"));
+        assert_ne!(key, page_cache_key("https://a.test/x", "<auth>TWO</auth>"));
+        assert_ne!(key, page_cache_key("https://b.test/x", "<auth>ONE</auth>"));
+        assert!(!key.contains("ONE"), "the authcode must not be readable from the key");
+    }
+
+    #[test]
+    fn failures_are_not_kept_for_later_pages() {
+        assert!(!is_cacheable(FALLBACK_RESPONSE));
+        assert!(!is_cacheable("<Envelope><valasz><hiba><kod>1</kod></hiba></valasz></Envelope>"));
+        assert!(is_cacheable("<Envelope><valasz><cikk/></valasz></Envelope>"));
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    collections::BTreeMap,
     fs,
     path::PathBuf,
     sync::OnceLock
@@ -10,7 +11,8 @@ use url::Url;
 use crate::service::{
     config::get_settings,
     path::get_current_or_root_dir,
-    log::{elogger, logger}
+    log::{elogger, logger},
+    tenant::{self, TenantProfile}
 };
 
 /// Cached default SOAP url, loaded once at startup from `soap.json`.
@@ -33,14 +35,22 @@ pub fn check_soap_config() -> bool {
 /// `SoapConfig` struct
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SoapConfig {
-    pub url: Option<String>
+    pub url: Option<String>,
+    /// The profile whose url is the default when `url` is unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_tenant: Option<String>,
+    /// Named Octopus installations, picked per request (see `service/tenant`).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tenants: BTreeMap<String, TenantProfile>
 }
 
 impl Default for SoapConfig {
     /// Default for `SoapConfig`
     fn default() -> Self {
         Self {
-            url: None
+            url: None,
+            default_tenant: None,
+            tenants: BTreeMap::new()
         }
     }
 }
@@ -67,6 +77,25 @@ impl SoapConfig {
 }
 
 
+impl SoapConfig {
+    /// The default url: `url`, or else the url of `default_tenant`. Read after
+    /// `tenant::init`, which is what knows the profiles.
+    pub fn default_url(&self) -> Option<String> {
+        if let Some(url) = self.url.as_ref().filter(|url| !url.trim().is_empty()) {
+            return Some(url.clone())
+        }
+        let name = self.default_tenant.as_ref()?;
+        match tenant::get(name) {
+            Some(profile) => Some(profile.url.clone()),
+            None => {
+                elogger(format!("soap.json: default_tenant '{}' is not a configured tenant", name));
+                None
+            }
+        }
+    }
+}
+
+
 /// This function return default url if found (reads from cached `SOAP_URL`)
 pub fn get_default_url() -> Option<String> {
     SOAP_URL.get().and_then(|v| v.clone())
@@ -125,7 +154,9 @@ impl AllowedHost {
 /// Hosts an outbound SOAP call may be sent to, resolved once at startup.
 ///
 /// `[server] allowed_soap_hosts` when it is set, otherwise the single host of
-/// `soap.json`'s url. Empty means **nothing is allowed**: this list is what
+/// `soap.json`'s url — plus, either way, the url of every tenant profile
+/// (`service/tenant`), which is configuration just like this list. Empty means
+/// **nothing is allowed**: this list is what
 /// stops a caller-supplied `?url=` from turning the service into a request proxy
 /// for whatever the host can reach, so an unconfigured instance has to fail
 /// closed rather than open.
@@ -136,12 +167,13 @@ static ALLOWED_HOSTS: Lazy<Vec<AllowedHost>> = Lazy::new(|| {
         .filter(|entry| !entry.is_empty())
         .collect();
 
-    let source = if configured.is_empty() {
+    let mut source = if configured.is_empty() {
         // Nothing configured: the one Octopus this instance talks to anyway.
         get_default_url().into_iter().collect()
     } else {
         configured
     };
+    source.extend(tenant::urls());
 
     let hosts: Vec<AllowedHost> = source.iter()
         .filter_map(|entry| match AllowedHost::parse(entry) {
@@ -209,8 +241,8 @@ pub fn is_allowed_soap_url(url: &str) -> bool {
 
 
 /// Loads the allowlist so it is reported at startup rather than on the first
-/// request. Called from `main.rs` after `SOAP_URL` is set — the default url is
-/// the fallback source, so the order matters.
+/// request. Called from `main.rs` after `SOAP_URL` is set and the tenants are
+/// known — both are sources, so the order matters.
 pub fn init_allowlist() {
     Lazy::force(&ALLOWED_HOSTS);
 }
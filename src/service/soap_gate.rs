@@ -0,0 +1,298 @@
+//! How many outbound SOAP calls may run at once: one gate per Octopus backend,
+//! split into priority lanes.
+//!
+//! ## Lanes
+//!
+//! - **Interactive** — `/post-order` and the MCP tools: someone is waiting on
+//!   the answer.
+//! - **Bulk** — the REST fetchers, which pull whole catalogs for a sync.
+//! - **Background** — the precache job.
+//!
+//! A backend's capacity (`soap_concurrency`, or a tenant's own `concurrency`)
+//! is split: each lane has slots reserved for it (`[server.soap_lanes]`) and
+//! the rest is shared. A call takes a free reserved slot of its lane, else a
+//! free shared one, else waits for whichever of the two frees first. A precache
+//! sweep and a `/get-bulk` loop can fill the shared slots between them and a
+//! submitted order still finds its reserved one.
+//!
+//! Reservations are granted in priority order and always leave at least one
+//! shared slot, so no lane is ever without a way in: with `soap_concurrency = 2`
+//! only the interactive lane gets a reservation, and with `1` the gate is the
+//! single queue it always was.
+//!
+//! ## Backends
+//!
+//! A tenant (`service/tenant`) is one backend. Any other url is keyed by its
+//! host and port, so a caller-supplied url on a slow host queues behind that
+//! host alone rather than behind every Octopus this instance talks to.
+//!
+//! Those hosts are whatever callers send, so a gate is dropped as soon as it
+//! is idle — no call holding or waiting for a slot — once a new one is made.
+//! The map holds only backends with calls in flight, and a host seen again
+//! later just gets a fresh gate. A gate whose capacity or lane split no longer
+//! matches the configuration is rebuilt; calls already holding a slot of the
+//! old one finish on it.
+//!
+//! ## Choosing a lane
+//!
+//! Nothing between a route and `service/soap` carries a lane: the work is run
+//! inside [`in_lane`], and the gate reads it back with [`current_lane`]. Work
+//! outside any lane is bulk.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use futures::future::{Either, select};
+use once_cell::sync::Lazy;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use url::Url;
+
+use crate::service::{
+    config::get_settings,
+    log::logger,
+    soap::DEFAULT_SOAP_CONCURRENCY,
+    tenant
+};
+
+
+/// Who is waiting on an outbound call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    Interactive,
+    Bulk,
+    Background
+}
+
+impl Lane {
+    /// Priority order, which is also the order reservations are granted in.
+    const ALL: [Lane; 3] = [Lane::Interactive, Lane::Bulk, Lane::Background];
+
+    fn index(self) -> usize {
+        match self {
+            Lane::Interactive => 0,
+            Lane::Bulk => 1,
+            Lane::Background => 2
+        }
+    }
+}
+
+
+tokio::task_local! {
+    static LANE: Lane;
+}
+
+
+/// Runs `future` with its outbound calls in `lane`.
+pub async fn in_lane<F: Future>(lane: Lane, future: F) -> F::Output {
+    LANE.scope(lane, future).await
+}
+
+
+/// The lane the current task runs in; bulk outside any.
+pub fn current_lane() -> Lane {
+    LANE.try_with(|lane| *lane).unwrap_or(Lane::Bulk)
+}
+
+
+/// How one backend's slots are divided.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Split {
+    reserved: [usize; 3],
+    shared: usize
+}
+
+/// Grants each lane what it asks for, in priority order, out of everything but
+/// one slot; the remainder is shared.
+fn split(capacity: usize, wanted: [usize; 3]) -> Split {
+    let capacity = capacity.max(1);
+    let mut left = capacity - 1;
+    let mut reserved = [0; 3];
+    for lane in Lane::ALL {
+        let granted = wanted[lane.index()].min(left);
+        reserved[lane.index()] = granted;
+        left -= granted;
+    }
+    Split { reserved, shared: capacity - reserved.iter().sum::<usize>() }
+}
+
+
+/// One backend's gate.
+struct Gate {
+    split: Split,
+    reserved: [Arc<Semaphore>; 3],
+    shared: Arc<Semaphore>
+}
+
+impl Gate {
+    fn new(split: &Split) -> Self {
+        Self {
+            split: split.clone(),
+            reserved: split.reserved.map(|permits| Arc::new(Semaphore::new(permits))),
+            shared: Arc::new(Semaphore::new(split.shared))
+        }
+    }
+
+    /// Nobody holds a slot or waits for one: every permit and every pending
+    /// acquire keeps a clone of its semaphore.
+    fn is_idle(self: &Arc<Self>) -> bool {
+        Arc::strong_count(self) == 1
+            && Arc::strong_count(&self.shared) == 1
+            && self.reserved.iter().all(|semaphore| Arc::strong_count(semaphore) == 1)
+    }
+
+    /// A slot for `lane`: its own if one is free, a shared one if not, and
+    /// otherwise whichever frees first. `None` only if a semaphore was closed,
+    /// which nothing does.
+    async fn acquire(&self, lane: Lane) -> Option<OwnedSemaphorePermit> {
+        let own = &self.reserved[lane.index()];
+        if let Ok(permit) = own.clone().try_acquire_owned() {
+            return Some(permit)
+        }
+        if let Ok(permit) = self.shared.clone().try_acquire_owned() {
+            return Some(permit)
+        }
+        // Dropping the losing acquire gives up its place in that queue.
+        match select(Box::pin(own.clone().acquire_owned()), Box::pin(self.shared.clone().acquire_owned())).await {
+            Either::Left((Ok(permit), _)) | Either::Right((Ok(permit), _)) => Some(permit),
+            Either::Left((Err(_), other)) => other.await.ok(),
+            Either::Right((Err(_), other)) => other.await.ok()
+        }
+    }
+}
+
+
+/// The gates of the backends with calls in flight.
+static GATES: Lazy<Mutex<HashMap<String, Arc<Gate>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+
+/// The backend a url belongs to, its capacity, and whether it is a tenant: the
+/// tenant's own, or else the url's host and port with `soap_concurrency`.
+fn backend_of(url: &str) -> (String, usize, bool) {
+    let default = get_settings().server.soap_concurrency.unwrap_or(DEFAULT_SOAP_CONCURRENCY);
+    if let Some((name, capacity)) = tenant::capacity_for(url) {
+        return (format!("tenant {}", name), capacity, true)
+    }
+    let host = Url::parse(url.trim()).ok()
+        .and_then(|parsed| Some(format!("{}:{}", parsed.host_str()?.to_lowercase(), parsed.port_or_known_default()?)))
+        .unwrap_or_else(|| "unknown host".into());
+    (host, default, false)
+}
+
+
+/// The gate of `backend`, made with `split` if it has none or one split
+/// otherwise. Making one drops every idle gate, so the map stays as small as
+/// the set of backends in use. Says whether a gate was made.
+fn gate_in(gates: &mut HashMap<String, Arc<Gate>>, backend: &str, split: &Split) -> (Arc<Gate>, bool) {
+    if let Some(gate) = gates.get(backend).filter(|gate| gate.split == *split) {
+        return (gate.clone(), false)
+    }
+    gates.retain(|_, gate| !gate.is_idle());
+    let gate = Arc::new(Gate::new(split));
+    gates.insert(backend.to_string(), gate.clone());
+    (gate, true)
+}
+
+
+fn gate_for(url: &str) -> Arc<Gate> {
+    let (backend, capacity, tenant) = backend_of(url);
+    let lanes = get_settings().server.soap_lanes();
+    let split = split(capacity, [lanes.interactive(), lanes.bulk(), lanes.background()]);
+    let mut gates = GATES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let (gate, made) = gate_in(&mut gates, &backend, &split);
+    // A caller's host comes and goes with its calls; only a tenant's gate,
+    // sized by its own profile, is worth a line.
+    if made && tenant {
+        logger(format!(
+            "SOAP gate: {} — {} slot(s): {} interactive, {} bulk, {} background, {} shared",
+            backend, capacity.max(1), split.reserved[0], split.reserved[1], split.reserved[2], split.shared
+        ));
+    }
+    gate
+}
+
+
+/// Waits for a slot to call `url` in `lane`. The slot is held until the permit
+/// is dropped.
+pub async fn acquire(url: &str, lane: Lane) -> Option<OwnedSemaphorePermit> {
+    gate_for(url).acquire(lane).await
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reservations_always_leave_a_shared_slot() {
+        assert_eq!(split(4, [1, 1, 1]), Split { reserved: [1, 1, 1], shared: 1 });
+        assert_eq!(split(8, [2, 1, 1]), Split { reserved: [2, 1, 1], shared: 4 });
+        // Short of slots, the higher lanes are served first.
+        assert_eq!(split(2, [1, 1, 1]), Split { reserved: [1, 0, 0], shared: 1 });
+        assert_eq!(split(3, [2, 1, 1]), Split { reserved: [2, 0, 0], shared: 1 });
+        // One slot, or none configured, is a single queue.
+        assert_eq!(split(1, [1, 1, 1]), Split { reserved: [0, 0, 0], shared: 1 });
+        assert_eq!(split(0, [1, 1, 1]), Split { reserved: [0, 0, 0], shared: 1 });
+    }
+
+    #[actix_web::test]
+    async fn a_busy_bulk_lane_leaves_the_interactive_slot_free() {
+        let gate = Gate::new(&split(4, [1, 1, 1]));
+
+        // Bulk takes its own slot, then the shared one, then has to wait.
+        let _first = gate.acquire(Lane::Bulk).await.expect("reserved");
+        let _second = gate.acquire(Lane::Bulk).await.expect("shared");
+        assert!(gate.reserved[Lane::Bulk.index()].available_permits() == 0 && gate.shared.available_permits() == 0);
+
+        // An order still gets through, and so does the precache job.
+        assert!(gate.acquire(Lane::Interactive).await.is_some());
+        assert!(gate.acquire(Lane::Background).await.is_some());
+    }
+
+    #[actix_web::test]
+    async fn a_waiting_call_takes_whichever_slot_frees_first() {
+        let gate = Arc::new(Gate::new(&split(2, [1, 0, 0])));
+        let interactive = gate.acquire(Lane::Interactive).await.expect("reserved");
+        let shared = gate.acquire(Lane::Interactive).await.expect("shared");
+
+        let waiting = {
+            let gate = gate.clone();
+            tokio::spawn(async move { gate.acquire(Lane::Interactive).await.is_some() })
+        };
+        tokio::task::yield_now().await;
+        drop(shared);
+        assert!(waiting.await.expect("joined"));
+        drop(interactive);
+    }
+
+    #[actix_web::test]
+    async fn idle_gates_are_dropped_and_a_resized_one_is_rebuilt() {
+        let mut gates = HashMap::new();
+        let (busy, made) = gate_in(&mut gates, "busy:443", &split(2, [1, 0, 0]));
+        assert!(made);
+        let permit = busy.acquire(Lane::Interactive).await.expect("slot");
+        drop(busy);
+        let (idle, _) = gate_in(&mut gates, "idle:443", &split(2, [1, 0, 0]));
+        drop(idle);
+
+        // The same backend and split is the same gate.
+        let (again, made) = gate_in(&mut gates, "busy:443", &split(2, [1, 0, 0]));
+        assert!(!made && Arc::ptr_eq(&again, &gates["busy:443"]));
+        drop(again);
+
+        // A new backend drops the idle gate but not the one with a call out.
+        gate_in(&mut gates, "other:443", &split(2, [1, 0, 0]));
+        assert!(gates.contains_key("busy:443") && !gates.contains_key("idle:443"));
+
+        // A new capacity rebuilds the gate; the call out finishes on the old one.
+        let (resized, made) = gate_in(&mut gates, "busy:443", &split(4, [1, 1, 1]));
+        assert!(made && resized.split == split(4, [1, 1, 1]));
+        drop(permit);
+    }
+
+    #[actix_web::test]
+    async fn work_outside_a_lane_is_bulk() {
+        assert_eq!(current_lane(), Lane::Bulk);
+        assert_eq!(in_lane(Lane::Background, async { current_lane() }).await, Lane::Background);
+    }
+}
@@ -0,0 +1,73 @@
+//! The TOML backend: one file per document, as this server has always kept
+//! them. Every read and write is the owning module's own file code, so what
+//! lands on disk is byte for byte what it was before `service/state` existed.
+
+use crate::service::{
+    blocklist::{self, BlocklistConfig},
+    mcp::{
+        oauth::store::{self as oauth_store, ClientConfig, SessionConfig},
+        precache::{self, PrecacheConfig}
+    }
+};
+
+use super::{Document, Revision, RunRecord, StateStore, file_revision};
+
+
+pub struct TomlFiles;
+
+impl StateStore for TomlFiles {
+    fn describe(&self) -> String {
+        "TOML files".into()
+    }
+
+    fn load_blocklist(&self) -> Result<BlocklistConfig, String> {
+        blocklist::read_file()
+    }
+
+    fn save_blocklist(&self, config: &BlocklistConfig) -> Result<(), String> {
+        blocklist::write_file(config)
+    }
+
+    fn load_precache(&self) -> Result<PrecacheConfig, String> {
+        precache::read_file()
+    }
+
+    fn save_precache(&self, config: &PrecacheConfig) -> Result<(), String> {
+        precache::write_file(config)
+    }
+
+    fn load_clients(&self) -> Result<ClientConfig, String> {
+        oauth_store::read_clients_file()
+    }
+
+    fn save_clients(&self, config: &ClientConfig) -> Result<(), String> {
+        oauth_store::write_clients_file(config)
+    }
+
+    fn load_sessions(&self) -> Result<SessionConfig, String> {
+        oauth_store::read_sessions_file()
+    }
+
+    fn save_sessions(&self, config: &SessionConfig) -> Result<(), String> {
+        oauth_store::write_sessions_file(config)
+    }
+
+    fn revision(&self, document: Document) -> Revision {
+        file_revision(&match document {
+            Document::Blocklist => blocklist::get_blocklist_path(),
+            Document::Precache => precache::get_precache_path(),
+            Document::OauthClients => oauth_store::clients_path(),
+            Document::OauthSessions => oauth_store::sessions_path()
+        })
+    }
+
+    /// The run log stays in memory with this backend, as it always has: a run
+    /// is not worth rewriting a credential file for.
+    fn record_run(&self, _run: &RunRecord) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn recent_runs(&self, _entry_id: &str, _limit: usize) -> Result<Vec<RunRecord>, String> {
+        Ok(Vec::new())
+    }
+}
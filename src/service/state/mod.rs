@@ -0,0 +1,259 @@
+//! Where server state is kept: TOML files, or one embedded SQLite database.
+//!
+//! Four kinds of state outlive a restart: the blocklist, the precache entries,
+//! and the OAuth clients and grants. Each is its own TOML file by default,
+//! rewritten whole through a temp file and a rename under its module's lock —
+//! right for one instance and a handful of rows, and readable by anyone with a
+//! text editor. `[storage] backend = "sqlite"` moves all four into one database
+//! instead: a write is a transaction rather than a rename, several instances can
+//! share the file without losing each other's writes to different rows, and the
+//! precache job's run history, which the files never held, is kept.
+//!
+//! ## The boundary
+//!
+//! [`StateStore`] is document-shaped on purpose: load a whole document, save a
+//! whole document. The modules that own the state keep their in-memory tables
+//! and their locking exactly as they were and only change where `load` and
+//! `save` go. The SQLite backend turns a whole-document save back into the rows
+//! that changed since it last loaded or saved that document (`sqlite.rs`), so a
+//! save only touches what this instance changed.
+//!
+//! Still files whatever the backend: `api_keys.toml`, the token file
+//! (`oauth_tokens_path`), `search_synonyms.toml`, the snapshot cache and the
+//! exports.
+//!
+//! ## Migrating
+//!
+//! `rustopus migrate-state sqlite` copies the TOML files into the database;
+//! `rustopus migrate-state toml` copies the database back out. Neither deletes
+//! its source. Run it with the server stopped, then switch `backend` and start
+//! the server again.
+
+pub mod files;
+pub mod sqlite;
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::service::{
+    blocklist::BlocklistConfig,
+    config::get_storage_settings,
+    log::{elogger, logger},
+    mcp::{
+        oauth::store::{ClientConfig, SessionConfig},
+        precache::PrecacheConfig
+    },
+    path::get_current_or_root_dir
+};
+
+use files::TomlFiles;
+use sqlite::SqliteStore;
+
+
+/// One unit of state, saved and loaded whole.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Document {
+    Blocklist,
+    Precache,
+    OauthClients,
+    OauthSessions
+}
+
+impl Document {
+    pub fn name(self) -> &'static str {
+        match self {
+            Document::Blocklist => "blocklist",
+            Document::Precache => "precache",
+            Document::OauthClients => "oauth_clients",
+            Document::OauthSessions => "oauth_sessions"
+        }
+    }
+
+    /// Whether the document holds live authcodes, and is therefore sealed when
+    /// a storage key is configured (`service/sealed`).
+    pub fn is_secret(self) -> bool {
+        matches!(self, Document::Precache | Document::OauthSessions)
+    }
+}
+
+
+/// Something that changes whenever a document is saved, by this process or
+/// another one. Only ever compared for equality.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Revision {
+    Missing,
+    /// A file's modification time and length.
+    File(SystemTime, u64),
+    /// The database's per-document save counter.
+    Counter(i64)
+}
+
+
+/// One finished precache run, as kept in the history.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunRecord {
+    pub entry_id: String,
+    pub finished_at: DateTime<Utc>,
+    pub duration_ms: u64,
+    pub full_pull: bool,
+    /// `"ok"`, or the error the run ended with.
+    pub outcome: String
+}
+
+
+/// Where the documents live. Loads report a document that exists but cannot be
+/// read as an error; an absent one is empty, which is the normal first-run case.
+pub trait StateStore: Send + Sync {
+    /// What the log calls this backend.
+    fn describe(&self) -> String;
+
+    fn load_blocklist(&self) -> Result<BlocklistConfig, String>;
+    fn save_blocklist(&self, config: &BlocklistConfig) -> Result<(), String>;
+
+    fn load_precache(&self) -> Result<PrecacheConfig, String>;
+    fn save_precache(&self, config: &PrecacheConfig) -> Result<(), String>;
+
+    fn load_clients(&self) -> Result<ClientConfig, String>;
+    fn save_clients(&self, config: &ClientConfig) -> Result<(), String>;
+
+    fn load_sessions(&self) -> Result<SessionConfig, String>;
+    fn save_sessions(&self, config: &SessionConfig) -> Result<(), String>;
+
+    /// The document's current revision, so an instance can tell that another
+    /// one changed it.
+    fn revision(&self, document: Document) -> Revision;
+
+    /// Appends a finished precache run to the history.
+    fn record_run(&self, run: &RunRecord) -> Result<(), String>;
+
+    /// An entry's most recent runs, newest first.
+    fn recent_runs(&self, entry_id: &str, limit: usize) -> Result<Vec<RunRecord>, String>;
+
+    /// Rewrites the secret documents under the current storage key, for
+    /// `rustopus reseal`, and returns how many rows that was. Files are resealed
+    /// by `service/sealed` itself, so a backend that keeps none has nothing to do.
+    fn reseal(&self) -> Result<usize, String> {
+        Ok(0)
+    }
+}
+
+
+/// The configured backend, or why it could not be opened.
+static STORE: Lazy<Result<Box<dyn StateStore>, String>> = Lazy::new(open_configured);
+
+
+/// Resolves a configured path against the working directory, like every other
+/// runtime path in this service.
+fn resolve(configured: &str) -> PathBuf {
+    let path = PathBuf::from(configured);
+    if path.is_absolute() {
+        return path
+    }
+    get_current_or_root_dir().join(path)
+}
+
+
+pub fn sqlite_path() -> PathBuf {
+    resolve(&get_storage_settings().sqlite_path())
+}
+
+
+fn open_configured() -> Result<Box<dyn StateStore>, String> {
+    match get_storage_settings().backend().as_str() {
+        "toml" => Ok(Box::new(TomlFiles)),
+        "sqlite" => Ok(Box::new(SqliteStore::open(&sqlite_path())?)),
+        // Refused rather than read as "toml": a typo would otherwise start the
+        // server on an empty blocklist and no sign-ins, with nothing in the way.
+        other => Err(format!("unknown [storage] backend '{}' — expected \"toml\" or \"sqlite\"", other))
+    }
+}
+
+
+/// The configured backend.
+///
+/// [`init`] refuses to start the server when the backend cannot be opened, so
+/// the TOML fallback here is only ever reached by tests, which never call it.
+pub fn store() -> &'static dyn StateStore {
+    match &*STORE {
+        Ok(store) => store.as_ref(),
+        Err(_) => &TomlFiles
+    }
+}
+
+
+/// Opens the configured backend and reports it. Called from `main.rs` before
+/// anything loads state; an error stops the server, since running on the wrong
+/// state would be worse than not running.
+pub fn init() -> std::io::Result<()> {
+    match &*STORE {
+        Ok(store) => {
+            logger(format!("State: kept in {}", store.describe()));
+            Ok(())
+        }
+        Err(error) => {
+            elogger(format!("State: {}", error));
+            Err(std::io::Error::other(error.clone()))
+        }
+    }
+}
+
+
+/// A file's revision, from its metadata.
+pub fn file_revision(path: &Path) -> Revision {
+    std::fs::metadata(path).ok()
+        .and_then(|metadata| Some(Revision::File(metadata.modified().ok()?, metadata.len())))
+        .unwrap_or(Revision::Missing)
+}
+
+
+/// Copies every document from one backend to another and returns how many rows
+/// each held. Everything is read before anything is written, so a source that
+/// cannot be read leaves the destination untouched.
+fn copy(source: &dyn StateStore, destination: &dyn StateStore) -> Result<[usize; 4], String> {
+    let blocklist = source.load_blocklist()?;
+    let precache = source.load_precache()?;
+    let clients = source.load_clients()?;
+    let sessions = source.load_sessions()?;
+
+    destination.save_blocklist(&blocklist)?;
+    destination.save_precache(&precache)?;
+    destination.save_clients(&clients)?;
+    destination.save_sessions(&sessions)?;
+    Ok([blocklist.rules.len(), precache.entries.len(), clients.clients.len(), sessions.grants.len()])
+}
+
+
+/// `rustopus migrate-state sqlite|toml`: copies the state into the named
+/// backend from the other one.
+pub fn migrate_command(target: Option<&str>) -> std::io::Result<()> {
+    let open_sqlite = || SqliteStore::open(&sqlite_path()).map_err(std::io::Error::other);
+    let (source, destination): (Box<dyn StateStore>, Box<dyn StateStore>) = match target {
+        Some("sqlite") => (Box::new(TomlFiles), Box::new(open_sqlite()?)),
+        Some("toml") => (Box::new(open_sqlite()?), Box::new(TomlFiles)),
+        _ => {
+            elogger("State: usage is `rustopus migrate-state sqlite` or `rustopus migrate-state toml`");
+            return Err(std::io::Error::other("expected a target backend"))
+        }
+    };
+
+    let [rules, entries, clients, grants] = copy(source.as_ref(), destination.as_ref()).map_err(|error| {
+        elogger(format!("State: migration stopped: {}", error));
+        std::io::Error::other(error)
+    })?;
+    logger(format!(
+        "State: copied {} blocklist rules, {} precache entries, {} OAuth clients and {} grants from {} to {}{} — set [storage] backend = \"{}\" and restart",
+        rules,
+        entries,
+        clients,
+        grants,
+        source.describe(),
+        destination.describe(),
+        if target == Some("toml") { "; the precache run history has no TOML form and stays behind" } else { "" },
+        target.unwrap_or_default()
+    ));
+    Ok(())
+}
@@ -0,0 +1,547 @@
+//! The SQLite backend: every document in one database file.
+//!
+//! Each document is a table of rows, one per rule, entry, client or grant, in
+//! the order the owning module keeps them. A row is the item's JSON — the same
+//! fields as its TOML form, so nothing is lost either way across
+//! `migrate-state` — and, for the documents holding authcodes, that JSON is
+//! sealed exactly like a file would be (`service/sealed`), under a context
+//! naming its table and key so a row cannot be moved to another one.
+//!
+//! A save writes only the rows that changed since this process last loaded or
+//! saved the document — inserting, updating and deleting by key — and bumps
+//! the document's counter in `revisions`, in one transaction. A row another
+//! instance added or changed meanwhile is not one this process touched, so it
+//! survives; the owning module hands over the whole document, but the
+//! database never gets it whole. The counter is what another instance polls to
+//! notice the change (`oauth/store.rs`). The database runs in WAL mode with a
+//! busy timeout, so instances sharing it wait for each other instead of
+//! failing.
+//!
+//! Two instances changing the *same* row still race, and the later write wins
+//! — for a grant or a client, that is the later sign-in or the later edit.
+//! A document this process never loaded (`migrate-state`, `reseal`) has no
+//! rows to compare against and is replaced whole, as before.
+//!
+//! ## Schema changes
+//!
+//! [`MIGRATIONS`] is append-only: step *n* takes a database from
+//! `user_version` *n − 1* to *n*, each in its own transaction. Opening applies
+//! whatever steps a database has not seen yet, and refuses one written by a
+//! newer build rather than guess at a schema it does not know.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior, params};
+use serde::{Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+
+use crate::service::{
+    blocklist::BlocklistConfig,
+    log::elogger,
+    mcp::{
+        oauth::store::{ClientConfig, SessionConfig},
+        precache::PrecacheConfig
+    },
+    sealed
+};
+
+use super::{Document, Revision, RunRecord, StateStore};
+
+
+/// The schema, one step per entry. Never edit a step that has shipped; append
+/// a new one.
+pub const MIGRATIONS: &[&str] = &[
+    // 1: the four documents, their save counters and the precache run history.
+    "CREATE TABLE revisions (
+        document TEXT PRIMARY KEY,
+        revision INTEGER NOT NULL
+    );
+    CREATE TABLE blocklist_rules (position INTEGER PRIMARY KEY, key TEXT NOT NULL, data BLOB NOT NULL);
+    CREATE TABLE precache_entries (position INTEGER PRIMARY KEY, key TEXT NOT NULL, data BLOB NOT NULL);
+    CREATE TABLE oauth_clients (position INTEGER PRIMARY KEY, key TEXT NOT NULL, data BLOB NOT NULL);
+    CREATE TABLE oauth_grants (position INTEGER PRIMARY KEY, key TEXT NOT NULL, data BLOB NOT NULL);
+    CREATE TABLE precache_runs (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        entry_id TEXT NOT NULL,
+        finished_at TEXT NOT NULL,
+        duration_ms INTEGER NOT NULL,
+        full_pull INTEGER NOT NULL,
+        outcome TEXT NOT NULL
+    );
+    CREATE INDEX precache_runs_by_entry ON precache_runs (entry_id, id);"
+];
+
+/// How many runs are kept per precache entry. Enough for a dashboard to show a
+/// pattern; old runs are pruned as new ones are recorded.
+pub const RUN_HISTORY_PER_ENTRY: usize = 100;
+
+/// How long a write waits for another instance's transaction to finish.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+
+fn table(document: Document) -> &'static str {
+    match document {
+        Document::Blocklist => "blocklist_rules",
+        Document::Precache => "precache_entries",
+        Document::OauthClients => "oauth_clients",
+        Document::OauthSessions => "oauth_grants"
+    }
+}
+
+/// What a secret row is sealed as.
+fn seal_context(document: Document, key: &str) -> String {
+    format!("{}:{}", table(document), key)
+}
+
+
+/// A document's rows as this process last loaded or saved them: each key and a
+/// digest of its JSON. A digest rather than the JSON, so no second plain copy
+/// of the grants' authcodes sits in memory.
+type Rows = HashMap<String, [u8; 32]>;
+
+fn digest(json: &[u8]) -> [u8; 32] {
+    Sha256::digest(json).into()
+}
+
+
+pub struct SqliteStore {
+    path: PathBuf,
+    connection: Mutex<Connection>,
+    /// What each document's next save is compared against.
+    seen: Mutex<HashMap<&'static str, Rows>>
+}
+
+impl SqliteStore {
+    /// Opens, or creates, the database and brings its schema up to date.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let fail = |error: rusqlite::Error| format!("cannot open SQLite database '{:?}': {}", path, error);
+
+        let mut connection = Connection::open(path).map_err(fail)?;
+        restrict_permissions(path);
+        connection.busy_timeout(BUSY_TIMEOUT).map_err(fail)?;
+        connection.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0)).map_err(fail)?;
+        migrate(&mut connection).map_err(|error| format!("SQLite database '{:?}': {}", path, error))?;
+
+        Ok(Self { path: path.to_path_buf(), connection: Mutex::new(connection), seen: Mutex::new(HashMap::new()) })
+    }
+
+    fn connection(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn schema_version(&self) -> i64 {
+        self.connection().query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap_or_default()
+    }
+
+    fn seen(&self) -> std::sync::MutexGuard<'_, HashMap<&'static str, Rows>> {
+        self.seen.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Writes the rows of `items` that differ from what this process last saw
+    /// of the document, deletes the ones it saw that `items` no longer holds,
+    /// and bumps the revision, atomically. Without anything seen, the document
+    /// is replaced whole.
+    fn save_items<T: Serialize>(&self, document: Document, items: &[T], key: impl Fn(&T) -> String) -> Result<(), String> {
+        let table = table(document);
+        let base = self.seen().get(table).cloned();
+        let mut saved = Rows::with_capacity(items.len());
+        let mut changed = Vec::new();
+        for item in items {
+            let key = key(item);
+            let json = serde_json::to_vec(item).map_err(|e| e.to_string())?;
+            let hash = digest(&json);
+            if base.as_ref().is_none_or(|base| base.get(&key) != Some(&hash)) {
+                let data = if document.is_secret() { sealed::seal(json, &seal_context(document, &key))? } else { json };
+                changed.push((key.clone(), data));
+            }
+            saved.insert(key, hash);
+        }
+        let removed: Vec<&String> = base.iter().flat_map(|base| base.keys()).filter(|key| !saved.contains_key(*key)).collect();
+
+        let fail = |error: rusqlite::Error| format!("cannot save {}: {}", document.name(), error);
+        let mut connection = self.connection();
+        // Immediate, so the write lock is taken before the first read and two
+        // instances queue on the busy timeout instead of one failing mid-way.
+        let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate).map_err(fail)?;
+        if base.is_none() {
+            transaction.execute(&format!("DELETE FROM {}", table), []).map_err(fail)?;
+        }
+        {
+            let mut delete = transaction.prepare(&format!("DELETE FROM {} WHERE key = ?1", table)).map_err(fail)?;
+            for key in removed {
+                delete.execute(params![key]).map_err(fail)?;
+            }
+            let mut update = transaction.prepare(&format!("UPDATE {} SET data = ?2 WHERE key = ?1", table)).map_err(fail)?;
+            let mut insert = transaction
+                .prepare(&format!(
+                    "INSERT INTO {0} (position, key, data) VALUES ((SELECT COALESCE(MAX(position), -1) + 1 FROM {0}), ?1, ?2)",
+                    table
+                ))
+                .map_err(fail)?;
+            for (key, data) in &changed {
+                if update.execute(params![key, data]).map_err(fail)? == 0 {
+                    insert.execute(params![key, data]).map_err(fail)?;
+                }
+            }
+        }
+        transaction.execute(
+            "INSERT INTO revisions (document, revision) VALUES (?1, 1)
+             ON CONFLICT (document) DO UPDATE SET revision = revision + 1",
+            params![document.name()]
+        ).map_err(fail)?;
+        transaction.commit().map_err(fail)?;
+        self.seen().insert(table, saved);
+        Ok(())
+    }
+
+    /// A document's rows, in saved order.
+    fn load_items<T: DeserializeOwned>(&self, document: Document) -> Result<Vec<T>, String> {
+        let fail = |error: rusqlite::Error| format!("cannot read {}: {}", document.name(), error);
+        let rows: Vec<(String, Vec<u8>)> = {
+            let connection = self.connection();
+            let mut select = connection
+                .prepare(&format!("SELECT key, data FROM {} ORDER BY position", table(document)))
+                .map_err(fail)?;
+            select.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                .map_err(fail)?
+                .collect::<Result<_, _>>()
+                .map_err(fail)?
+        };
+
+        let mut seen = Rows::with_capacity(rows.len());
+        let items = rows.into_iter()
+            .map(|(key, data)| {
+                let json = sealed::open(data, &seal_context(document, &key))
+                    .map_err(|e| format!("{} row '{}': {}", document.name(), key, e))?;
+                let item = serde_json::from_slice(&json).map_err(|e| format!("{} row '{}': {}", document.name(), key, e))?;
+                seen.insert(key, digest(&json));
+                Ok(item)
+            })
+            .collect::<Result<_, String>>()?;
+        self.seen().insert(table(document), seen);
+        Ok(items)
+    }
+}
+
+
+/// Applies the migrations the database has not seen yet and returns its
+/// schema version.
+fn migrate(connection: &mut Connection) -> Result<usize, String> {
+    let current: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0)).map_err(|e| e.to_string())?;
+    let current = current.max(0) as usize;
+    if current > MIGRATIONS.len() {
+        return Err(format!("schema v{} was written by a newer build, this one knows up to v{}", current, MIGRATIONS.len()))
+    }
+
+    for (index, step) in MIGRATIONS.iter().enumerate().skip(current) {
+        let version = index + 1;
+        let fail = |error: rusqlite::Error| format!("migration to schema v{} failed: {}", version, error);
+        let transaction = connection.transaction().map_err(fail)?;
+        transaction.execute_batch(step).map_err(fail)?;
+        transaction.pragma_update(None, "user_version", version as i64).map_err(fail)?;
+        transaction.commit().map_err(fail)?;
+    }
+    Ok(MIGRATIONS.len())
+}
+
+
+/// The database holds the same authcodes as the files it replaces, so it gets
+/// the same owner-only permissions. The `-wal` and `-shm` files SQLite creates
+/// next to it inherit nothing, which is why the directory should be private too.
+fn restrict_permissions(path: &Path) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Err(error) = std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)) {
+            elogger(format!("State: cannot restrict permissions on '{:?}': {}", path, error));
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+    }
+}
+
+
+impl StateStore for SqliteStore {
+    fn describe(&self) -> String {
+        format!("SQLite database '{}' (schema v{})", self.path.display(), self.schema_version())
+    }
+
+    fn load_blocklist(&self) -> Result<BlocklistConfig, String> {
+        Ok(BlocklistConfig { rules: self.load_items(Document::Blocklist)? })
+    }
+
+    fn save_blocklist(&self, config: &BlocklistConfig) -> Result<(), String> {
+        self.save_items(Document::Blocklist, &config.rules, |rule| rule.value.clone())
+    }
+
+    fn load_precache(&self) -> Result<PrecacheConfig, String> {
+        Ok(PrecacheConfig { entries: self.load_items(Document::Precache)? })
+    }
+
+    fn save_precache(&self, config: &PrecacheConfig) -> Result<(), String> {
+        self.save_items(Document::Precache, &config.entries, |entry| entry.id())
+    }
+
+    fn load_clients(&self) -> Result<ClientConfig, String> {
+        Ok(ClientConfig { clients: self.load_items(Document::OauthClients)? })
+    }
+
+    fn save_clients(&self, config: &ClientConfig) -> Result<(), String> {
+        self.save_items(Document::OauthClients, &config.clients, |client| client.client_id.clone())
+    }
+
+    fn load_sessions(&self) -> Result<SessionConfig, String> {
+        Ok(SessionConfig { grants: self.load_items(Document::OauthSessions)? })
+    }
+
+    fn save_sessions(&self, config: &SessionConfig) -> Result<(), String> {
+        self.save_items(Document::OauthSessions, &config.grants, |grant| grant.id.clone())
+    }
+
+    fn reseal(&self) -> Result<usize, String> {
+        let precache = self.load_precache()?;
+        let sessions = self.load_sessions()?;
+        // Nothing changed, so every row has to be rewritten rather than compared.
+        self.seen().clear();
+        self.save_precache(&precache)?;
+        self.save_sessions(&sessions)?;
+        Ok(precache.entries.len() + sessions.grants.len())
+    }
+
+    fn revision(&self, document: Document) -> Revision {
+        let revision = self.connection()
+            .query_row("SELECT revision FROM revisions WHERE document = ?1", params![document.name()], |row| row.get(0))
+            .optional();
+        match revision {
+            Ok(Some(counter)) => Revision::Counter(counter),
+            Ok(None) => Revision::Missing,
+            Err(error) => {
+                elogger(format!("State: cannot read the {} revision: {}", document.name(), error));
+                Revision::Missing
+            }
+        }
+    }
+
+    fn record_run(&self, run: &RunRecord) -> Result<(), String> {
+        let fail = |error: rusqlite::Error| format!("cannot record the run of {}: {}", run.entry_id, error);
+        let mut connection = self.connection();
+        let transaction = connection.transaction().map_err(fail)?;
+        transaction.execute(
+            "INSERT INTO precache_runs (entry_id, finished_at, duration_ms, full_pull, outcome) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![run.entry_id, run.finished_at.to_rfc3339(), run.duration_ms as i64, run.full_pull, run.outcome]
+        ).map_err(fail)?;
+        transaction.execute(
+            "DELETE FROM precache_runs WHERE entry_id = ?1 AND id NOT IN
+             (SELECT id FROM precache_runs WHERE entry_id = ?1 ORDER BY id DESC LIMIT ?2)",
+            params![run.entry_id, RUN_HISTORY_PER_ENTRY as i64]
+        ).map_err(fail)?;
+        transaction.commit().map_err(fail)
+    }
+
+    fn recent_runs(&self, entry_id: &str, limit: usize) -> Result<Vec<RunRecord>, String> {
+        let fail = |error: rusqlite::Error| format!("cannot read the runs of {}: {}", entry_id, error);
+        let connection = self.connection();
+        let mut select = connection.prepare(
+            "SELECT finished_at, duration_ms, full_pull, outcome FROM precache_runs
+             WHERE entry_id = ?1 ORDER BY id DESC LIMIT ?2"
+        ).map_err(fail)?;
+        let rows = select.query_map(params![entry_id, limit as i64], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get::<_, bool>(2)?, row.get::<_, String>(3)?))
+        }).map_err(fail)?;
+
+        let mut runs = Vec::new();
+        for row in rows {
+            let (finished_at, duration_ms, full_pull, outcome) = row.map_err(fail)?;
+            let Ok(finished_at) = DateTime::parse_from_rfc3339(&finished_at) else {
+                continue
+            };
+            runs.push(RunRecord {
+                entry_id: entry_id.to_string(),
+                finished_at: finished_at.with_timezone(&Utc),
+                duration_ms: duration_ms.max(0) as u64,
+                full_pull,
+                outcome
+            });
+        }
+        Ok(runs)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::{
+        blocklist::BlockRule,
+        mcp::{oauth::{Grant, OauthClient}, precache::PrecacheEntry}
+    };
+
+    /// A fresh database in the temp directory, removed with its WAL files when
+    /// dropped.
+    struct TempDb(PathBuf);
+
+    impl TempDb {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("rustopus-state-{}-{}.db", name, std::process::id()));
+            let db = Self(path);
+            db.remove();
+            db
+        }
+
+        fn remove(&self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{}", self.0.display(), suffix));
+            }
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            self.remove();
+        }
+    }
+
+    fn grant(id: &str) -> Grant {
+        Grant {
+            id: id.into(),
+            client_id: "client".into(),
+            label: "FFD3…0E37 pid=1".into(),
+            authcode: "FFD3-SECRET-0E37".into(),
+            pid: 1,
+            resource: "https://mcp.example.com/mcp".into(),
+            scope: "catalog:read".into(),
+            refresh_hash: "00".into(),
+            created_at: Utc::now(),
+            expires_at: Utc::now()
+        }
+    }
+
+    #[test]
+    fn documents_round_trip_in_order() {
+        let db = TempDb::new("round-trip");
+        let store = SqliteStore::open(&db.0).unwrap();
+
+        let rules = vec![BlockRule::ip("10.0.0.0/8", Some("office".into()), None).unwrap(), BlockRule::ip("192.0.2.1", None, None).unwrap()];
+        store.save_blocklist(&BlocklistConfig { rules }).unwrap();
+        let loaded = store.load_blocklist().unwrap();
+        assert_eq!(loaded.rules.iter().map(|rule| rule.value.as_str()).collect::<Vec<_>>(), ["10.0.0.0/8", "192.0.2.1"]);
+        assert_eq!(loaded.rules[0].note.as_deref(), Some("office"));
+
+        let entry = PrecacheEntry { label: "Team".into(), authcode: "FFD3-SECRET-0E37".into(), pid: 7, url: None, enabled: Some(false) };
+        store.save_precache(&PrecacheConfig { entries: vec![entry] }).unwrap();
+        let loaded = store.load_precache().unwrap();
+        assert_eq!(loaded.entries[0].authcode, "FFD3-SECRET-0E37");
+        assert!(!loaded.entries[0].is_enabled());
+
+        let client: OauthClient = toml::from_str(
+            "client_id = \"abc\"\nname = \"Claude\"\nsecret_hash = \"00\"\nredirect_uris = [\"https://claude.ai/cb\"]"
+        ).unwrap();
+        store.save_clients(&ClientConfig { clients: vec![client] }).unwrap();
+        assert_eq!(store.load_clients().unwrap().clients[0].redirect_uris, ["https://claude.ai/cb"]);
+
+        store.save_sessions(&SessionConfig { grants: vec![grant("b"), grant("a")] }).unwrap();
+        let ids: Vec<_> = store.load_sessions().unwrap().grants.into_iter().map(|grant| grant.id).collect();
+        assert_eq!(ids, ["b", "a"]);
+
+        // Saving replaces rather than appends.
+        store.save_sessions(&SessionConfig { grants: vec![grant("a")] }).unwrap();
+        assert_eq!(store.load_sessions().unwrap().grants.len(), 1);
+    }
+
+    #[test]
+    fn instances_sharing_the_file_keep_each_others_rows() {
+        let db = TempDb::new("shared");
+        let first = SqliteStore::open(&db.0).unwrap();
+        let second = SqliteStore::open(&db.0).unwrap();
+        first.save_sessions(&SessionConfig { grants: vec![grant("a"), grant("b")] }).unwrap();
+
+        // Both load the same document, then each saves its own change to it.
+        let mut mine = first.load_sessions().unwrap();
+        let mut theirs = second.load_sessions().unwrap();
+        mine.grants.push(grant("c"));
+        first.save_sessions(&mine).unwrap();
+        theirs.grants.retain(|grant| grant.id != "a");
+        theirs.grants[0].label = "renamed".into();
+        second.save_sessions(&theirs).unwrap();
+
+        let merged = first.load_sessions().unwrap().grants;
+        assert_eq!(merged.iter().map(|grant| grant.id.as_str()).collect::<Vec<_>>(), ["b", "c"]);
+        assert_eq!(merged[0].label, "renamed");
+        assert_eq!(first.revision(Document::OauthSessions), Revision::Counter(3));
+    }
+
+    #[test]
+    fn an_empty_database_loads_empty_documents() {
+        let db = TempDb::new("empty");
+        let store = SqliteStore::open(&db.0).unwrap();
+        assert!(store.load_blocklist().unwrap().rules.is_empty());
+        assert!(store.load_precache().unwrap().entries.is_empty());
+        assert!(store.load_clients().unwrap().clients.is_empty());
+        assert!(store.load_sessions().unwrap().grants.is_empty());
+    }
+
+    #[test]
+    fn saves_bump_the_document_revision_only() {
+        let db = TempDb::new("revision");
+        let store = SqliteStore::open(&db.0).unwrap();
+        assert_eq!(store.revision(Document::OauthSessions), Revision::Missing);
+
+        store.save_sessions(&SessionConfig::default()).unwrap();
+        let first = store.revision(Document::OauthSessions);
+        store.save_sessions(&SessionConfig::default()).unwrap();
+        let second = store.revision(Document::OauthSessions);
+        assert_eq!(first, Revision::Counter(1));
+        assert_eq!(second, Revision::Counter(2));
+        assert_eq!(store.revision(Document::OauthClients), Revision::Missing);
+
+        // Another connection to the same file sees the same counter.
+        let other = SqliteStore::open(&db.0).unwrap();
+        assert_eq!(other.revision(Document::OauthSessions), second);
+    }
+
+    #[test]
+    fn reopening_applies_no_migration_twice_and_a_newer_schema_is_refused() {
+        let db = TempDb::new("migrations");
+        let store = SqliteStore::open(&db.0).unwrap();
+        store.save_blocklist(&BlocklistConfig { rules: vec![BlockRule::ip("192.0.2.1", None, None).unwrap()] }).unwrap();
+        assert_eq!(store.schema_version(), MIGRATIONS.len() as i64);
+        drop(store);
+
+        let store = SqliteStore::open(&db.0).unwrap();
+        assert_eq!(store.load_blocklist().unwrap().rules.len(), 1);
+        store.connection().pragma_update(None, "user_version", MIGRATIONS.len() as i64 + 1).unwrap();
+        drop(store);
+
+        let error = SqliteStore::open(&db.0).err().unwrap();
+        assert!(error.contains("newer build"), "{}", error);
+    }
+
+    #[test]
+    fn run_history_is_newest_first_and_pruned_per_entry() {
+        let db = TempDb::new("runs");
+        let store = SqliteStore::open(&db.0).unwrap();
+        let run = |entry_id: &str, duration_ms: u64| RunRecord {
+            entry_id: entry_id.into(),
+            finished_at: Utc::now(),
+            duration_ms,
+            full_pull: false,
+            outcome: "ok".into()
+        };
+
+        for duration_ms in 0..(RUN_HISTORY_PER_ENTRY as u64 + 5) {
+            store.record_run(&run("a", duration_ms)).unwrap();
+        }
+        store.record_run(&run("b", 1)).unwrap();
+
+        let recent = store.recent_runs("a", 3).unwrap();
+        assert_eq!(recent.iter().map(|run| run.duration_ms).collect::<Vec<_>>(), [104, 103, 102]);
+        assert_eq!(store.recent_runs("a", 1000).unwrap().len(), RUN_HISTORY_PER_ENTRY);
+        assert_eq!(store.recent_runs("b", 10).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,307 @@
+//! Named Octopus backends, for a deployment that fronts more than one
+//! installation.
+//!
+//! `soap.json` may define profiles next to — or instead of — its default `url`:
+//!
+//! ```json
+//! {
+//!   "default_tenant": "orink",
+//!   "tenants": {
+//!     "orink": { "url": "https://orink.hu/services/vision.asmx", "language": "HU", "concurrency": 4 },
+//!     "other": { "url": "https://erp.other.test/services/vision.asmx", "xmlns": "https://erp.other.test/services/" }
+//!   }
+//! }
+//! ```
+//!
+//! A caller picks one with `?tenant=other`, or with a path prefix:
+//! `/t/other/get-product`. The profile's url replaces the `url` parameter, its
+//! `xmlns` (when set) replaces the derived namespace, and its `language` is the
+//! default when the caller sends none. Every profile url is on the outbound
+//! allowlist without being listed in `allowed_soap_hosts`.
+//!
+//! ## The url is the tenant
+//!
+//! Everything below the routes — the SOAP gate, singleflight, the page cache,
+//! the MCP snapshot cache — already works per url, and the url is part of every
+//! cache key. So a tenant is looked up by its url rather than threaded through
+//! the fetchers as a name: [`capacity_for`] gives `service/soap_gate` the
+//! tenant's own gate size, and each tenant's cache entries are apart from every
+//! other's because their keys are. That only holds while no two profiles share a url,
+//! so a second profile with an existing url is refused at startup.
+//!
+//! ## The path prefix
+//!
+//! [`route`] rewrites `/t/{tenant}/get-product?…` to `/get-product?…&tenant=…`
+//! before any other middleware runs, so the blocklist, API keys and rate limits
+//! see the endpoint they already know, and the handlers read the tenant from the
+//! query like any other parameter. Only the REST endpoints take a prefix.
+
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use actix_web::Error;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Uri;
+use actix_web::middleware::Next;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::service::{
+    config::get_settings,
+    log::{elogger, logger},
+    ratelimit::endpoint_of,
+    soap::DEFAULT_SOAP_CONCURRENCY
+};
+
+/// The query parameter a tenant is picked with, and what the path prefix is
+/// rewritten into.
+pub const TENANT_PARAMETER: &str = "tenant";
+
+/// Longest accepted profile name. A name goes into urls and log lines.
+const MAX_NAME_LEN: usize = 64;
+
+
+/// One named Octopus installation, as written in `soap.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TenantProfile {
+    /// The endpoint with the `.asmx` file.
+    pub url: String,
+    /// Namespace override, for an installation whose url has no `/services/`
+    /// segment to derive one from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xmlns: Option<String>,
+    /// Language used when the caller sends none, e.g. `HU`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Concurrent outbound calls to this installation. Falls back to
+    /// `[server] soap_concurrency`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<usize>
+}
+
+
+/// A profile that passed validation, with its concurrency resolved.
+struct Tenant {
+    name: String,
+    profile: TenantProfile,
+    permits: usize
+}
+
+
+/// The profiles, set once at startup by [`init`].
+static TENANTS: OnceLock<Vec<Tenant>> = OnceLock::new();
+
+
+/// Whether a profile name is usable: letters, digits, `-` and `_`, so it can sit
+/// in a path segment and a query string without escaping.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+
+/// Two spellings of one endpoint compare equal: a trailing slash and the case
+/// of the host are not part of what is being called.
+fn same_url(a: &str, b: &str) -> bool {
+    match (Url::parse(a.trim()), Url::parse(b.trim())) {
+        (Ok(a), Ok(b)) => {
+            a.scheme() == b.scheme()
+                && a.host_str() == b.host_str()
+                && a.port_or_known_default() == b.port_or_known_default()
+                && a.path().trim_end_matches('/') == b.path().trim_end_matches('/')
+                && a.query() == b.query()
+        }
+        _ => a.trim() == b.trim()
+    }
+}
+
+
+/// Validates the configured profiles. A profile with a bad name, a url that is
+/// not http(s), or the url of a profile already accepted is logged and left
+/// out; the rest are served.
+fn build(profiles: BTreeMap<String, TenantProfile>, default_concurrency: usize) -> Vec<Tenant> {
+    let mut tenants: Vec<Tenant> = Vec::new();
+    for (name, profile) in profiles {
+        let name = name.trim().to_lowercase();
+        if !is_valid_name(&name) {
+            elogger(format!("Tenants: '{}' is not a usable name (letters, digits, '-' and '_') — ignored", name));
+            continue
+        }
+        if !Url::parse(profile.url.trim()).is_ok_and(|url| matches!(url.scheme(), "http" | "https")) {
+            elogger(format!("Tenants: '{}' has no http(s) url — ignored", name));
+            continue
+        }
+        if let Some(existing) = tenants.iter().find(|tenant| same_url(&tenant.profile.url, &profile.url)) {
+            elogger(format!("Tenants: '{}' has the same url as '{}', and a url is a tenant — ignored", name, existing.name));
+            continue
+        }
+        let permits = profile.concurrency.filter(|permits| *permits > 0).unwrap_or(default_concurrency);
+        tenants.push(Tenant {
+            name,
+            profile: TenantProfile { url: profile.url.trim().to_string(), ..profile },
+            permits
+        });
+    }
+    tenants
+}
+
+
+/// Takes the profiles from `soap.json`. Called from `main.rs` before the
+/// default url and the allowlist are resolved, since both read the profiles.
+pub fn init(profiles: BTreeMap<String, TenantProfile>) {
+    let server = get_settings().server;
+    let tenants = build(profiles, server.soap_concurrency.unwrap_or(DEFAULT_SOAP_CONCURRENCY));
+    if !tenants.is_empty() {
+        logger(format!(
+            "Tenants: {} profile(s) — {}",
+            tenants.len(),
+            tenants.iter()
+                .map(|tenant| format!("{} ({} concurrent)", tenant.name, tenant.permits))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ));
+    }
+    let _ = TENANTS.set(tenants);
+}
+
+
+fn tenants() -> &'static [Tenant] {
+    TENANTS.get().map(Vec::as_slice).unwrap_or(&[])
+}
+
+
+/// A profile by name. Names are not case-sensitive.
+pub fn get(name: &str) -> Option<&'static TenantProfile> {
+    let name = name.trim().to_lowercase();
+    tenants().iter()
+        .find(|tenant| tenant.name == name)
+        .map(|tenant| &tenant.profile)
+}
+
+
+/// Every profile url, for the outbound allowlist.
+pub fn urls() -> Vec<String> {
+    tenants().iter().map(|tenant| tenant.profile.url.clone()).collect()
+}
+
+
+/// The tenant a url belongs to, if any.
+pub fn for_url(url: &str) -> Option<(&'static str, &'static TenantProfile)> {
+    tenants().iter()
+        .find(|tenant| same_url(&tenant.profile.url, url))
+        .map(|tenant| (tenant.name.as_str(), &tenant.profile))
+}
+
+
+/// The name and concurrency of the tenant a url belongs to, or `None` for a
+/// url outside every profile, which is gated by its host.
+pub fn capacity_for(url: &str) -> Option<(&'static str, usize)> {
+    tenants().iter()
+        .find(|tenant| same_url(&tenant.profile.url, url))
+        .map(|tenant| (tenant.name.as_str(), tenant.permits))
+}
+
+
+/// The path and query a `/t/{tenant}/…` request is served as, or `None` when
+/// the path has no prefix or what follows it is not a REST endpoint. The
+/// caller's own `tenant` parameter gives way to the path.
+fn unprefixed(path: &str, query: &str) -> Option<String> {
+    let (name, rest) = path.strip_prefix("/t/")?.split_once('/')?;
+    let rest = format!("/{}", rest);
+    if !is_valid_name(name) || endpoint_of(&rest).is_none() {
+        return None
+    }
+
+    let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+    for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+        if key != TENANT_PARAMETER {
+            serializer.append_pair(&key, &value);
+        }
+    }
+    serializer.append_pair(TENANT_PARAMETER, name);
+    Some(format!("{}?{}", rest, serializer.finish()))
+}
+
+
+/// Middleware: serves `/t/{tenant}/{endpoint}` as `/{endpoint}?tenant=…`.
+/// Outermost of the request guards, so every one of them sees the plain path.
+pub async fn route(
+    mut request: ServiceRequest,
+    next: Next<impl MessageBody + 'static>
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if let Some(target) = unprefixed(request.path(), request.query_string()) {
+        match target.parse::<Uri>() {
+            Ok(uri) => {
+                request.match_info_mut().get_mut().update(&uri);
+                request.head_mut().uri = uri;
+            }
+            Err(error) => elogger(format!("Tenants: cannot rewrite '{}': {}", request.path(), error))
+        }
+    }
+    next.call(request).await.map(ServiceResponse::map_into_boxed_body)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(url: &str) -> TenantProfile {
+        TenantProfile { url: url.into(), xmlns: None, language: None, concurrency: None }
+    }
+
+    #[test]
+    fn names_are_safe_in_a_path_segment() {
+        assert!(is_valid_name("orink"));
+        assert!(is_valid_name("company_2-hu"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("a/b"));
+        assert!(!is_valid_name("a b"));
+        assert!(!is_valid_name("ö"));
+        assert!(!is_valid_name(&"a".repeat(MAX_NAME_LEN + 1)));
+    }
+
+    #[test]
+    fn bad_and_duplicate_profiles_are_left_out() {
+        let mut profiles = BTreeMap::new();
+        profiles.insert("Orink".to_string(), TenantProfile { concurrency: Some(2), ..profile("https://orink.hu/services/vision.asmx") });
+        profiles.insert("same".to_string(), profile("https://ORINK.hu/services/vision.asmx/"));
+        profiles.insert("bad name".to_string(), profile("https://other.test/"));
+        profiles.insert("ftp".to_string(), profile("ftp://other.test/"));
+        profiles.insert("other".to_string(), profile("https://other.test/services/vision.asmx"));
+
+        let tenants = build(profiles, 4);
+        let names: Vec<&str> = tenants.iter().map(|tenant| tenant.name.as_str()).collect();
+        // "orink" is accepted first (sorted), so "same" is the duplicate.
+        assert_eq!(names, ["orink", "other"]);
+        assert_eq!(tenants[0].permits, 2);
+        assert_eq!(tenants[1].permits, 4);
+    }
+
+    #[test]
+    fn urls_match_regardless_of_trailing_slash_and_host_case() {
+        assert!(same_url("https://orink.hu/services/vision.asmx", "https://ORINK.HU/services/vision.asmx/"));
+        assert!(same_url("https://orink.hu/services/vision.asmx", "https://orink.hu:443/services/vision.asmx"));
+        assert!(!same_url("https://orink.hu/services/vision.asmx", "http://orink.hu/services/vision.asmx"));
+        assert!(!same_url("https://orink.hu/services/vision.asmx", "https://orink.hu/services/other.asmx"));
+    }
+
+    #[test]
+    fn the_path_prefix_becomes_the_tenant_parameter() {
+        assert_eq!(unprefixed("/t/orink/get-product", "").as_deref(), Some("/get-product?tenant=orink"));
+        assert_eq!(
+            unprefixed("/t/orink/get-products", "fields=no%2Cprice&tenant=other").as_deref(),
+            Some("/get-products?fields=no%2Cprice&tenant=orink")
+        );
+        assert_eq!(unprefixed("/t/orink/post-order", "pid=1").as_deref(), Some("/post-order?pid=1&tenant=orink"));
+
+        // Not a REST endpoint, not a prefix, or not a name: left alone.
+        assert_eq!(unprefixed("/t/orink/mcp", ""), None);
+        assert_eq!(unprefixed("/t/orink/admin", ""), None);
+        assert_eq!(unprefixed("/get-product", ""), None);
+        assert_eq!(unprefixed("/t/or%20ink/get-product", ""), None);
+        assert_eq!(unprefixed("/t/orink", ""), None);
+    }
+}
@@ -19,9 +19,11 @@
     var formEl = document.getElementById('add-form');
     var reloadEl = document.getElementById('reload');
     var blocksBodyEl = document.getElementById('blocks-body');
+    var rateLimitsBodyEl = document.getElementById('ratelimits-body');
     var blockFormEl = document.getElementById('block-form');
     var blockKindEl = document.getElementById('block-kind');
     var blockValueEl = document.getElementById('block-value');
+    var importFormEl = document.getElementById('import-form');
     var clientsBodyEl = document.getElementById('clients-body');
     var sessionsBodyEl = document.getElementById('sessions-body');
     var clientFormEl = document.getElementById('client-form');
@@ -29,6 +31,14 @@
     var secretIdEl = document.getElementById('client-secret-id');
     var secretValueEl = document.getElementById('client-secret-value');
     var secretDismissEl = document.getElementById('client-secret-dismiss');
+    var keysBodyEl = document.getElementById('keys-body');
+    var keyFormEl = document.getElementById('key-form');
+    var keySecretEl = document.getElementById('key-secret');
+    var keySecretValueEl = document.getElementById('key-secret-value');
+    var keySecretDismissEl = document.getElementById('key-secret-dismiss');
+    var synonymsFormEl = document.getElementById('synonyms-form');
+    var synonymsSourceEl = document.getElementById('synonyms-source');
+    var synonymsResetEl = document.getElementById('synonyms-reset');
 
     function setStatus(message, kind) {
         statusEl.textContent = message || '';
@@ -87,7 +97,12 @@
 
     function request(method, url, body) {
         var options = { method: method, headers: {} };
-        if (body !== undefined) {
+        /* A string is a file being uploaded as it is (the blocklist import,
+         * the synonym file); anything else is JSON. */
+        if (typeof body === 'string') {
+            options.headers['Content-Type'] = 'text/plain';
+            options.body = body;
+        } else if (body !== undefined) {
             options.headers['Content-Type'] = 'application/json';
             options.body = JSON.stringify(body);
         }
@@ -159,7 +174,14 @@
             cell(row, entry.on_disk ? 'yes' : 'no', entry.on_disk ? 'state-ok' : 'state-idle');
 
             var state = statusOf(entry);
-            cell(row, state.text, state.className);
+            var stateCell = cell(row, state.text, state.className);
+            /* Recent runs, when the server keeps them (the SQLite backend). */
+            if (entry.history && entry.history.length) {
+                stateCell.title = entry.history.map(function (run) {
+                    return formatTime(run.finished_at) + ' — ' + run.outcome +
+                        ' (' + formatDuration(run.duration_ms) + (run.full_pull ? ', full pull' : '') + ')';
+                }).join('\n');
+            }
 
             var actions = document.createElement('td');
             var wrapper = document.createElement('div');
@@ -204,6 +226,25 @@
         mcp: 'MCP + exports'
     };
 
+    function formatUntil(iso) {
+        var date = new Date(iso);
+        if (isNaN(date.getTime())) { return iso; }
+        var left = Math.round((date.getTime() - Date.now()) / 1000);
+        if (left < 60) { return left + 's'; }
+        if (left < 3600) { return Math.round(left / 60) + 'm'; }
+        if (left < 86400) { return Math.round(left / 3600) + 'h'; }
+        return Math.round(left / 86400) + 'd';
+    }
+
+    function blockStatus(block) {
+        var origin = block.auto ? 'auto, ' : '';
+        if (!block.enabled) { return { text: origin + 'paused', className: 'state-idle' }; }
+        if (block.expired) { return { text: origin + 'expired', className: 'state-idle' }; }
+        var verb = block.action === 'allow' ? 'allowed' : 'enforced';
+        if (block.expires_at) { return { text: origin + verb + ', expires in ' + formatUntil(block.expires_at), className: 'state-warn' }; }
+        return { text: origin + verb, className: block.action === 'allow' ? 'state-ok' : 'state-bad' };
+    }
+
     function renderBlocks(blocks) {
         blocksBodyEl.textContent = '';
 
@@ -220,7 +261,8 @@
 
         blocks.forEach(function (block) {
             var row = document.createElement('tr');
-            cell(row, block.kind === 'ip' ? 'IP' : 'Authcode');
+            var kind = block.kind === 'ip' ? 'IP' : 'Authcode';
+            cell(row, block.action === 'allow' ? kind + ' (allow)' : kind, block.action === 'allow' ? 'state-ok' : null);
 
             /* An authcode value is already the FFD3…0E37 mask server-side; the
              * code itself never reaches this page. */
@@ -231,12 +273,12 @@
             row.appendChild(valueCell);
 
             cell(row, SCOPE_LABELS[block.scope] || block.scope);
+            /* An automatic block's note is the reason abuse detection wrote. */
             cell(row, block.note || '—', block.note ? null : 'state-idle');
             cell(row, block.hits ? String(block.hits) : '—', block.hits ? null : 'state-idle');
             cell(row, block.last_hit ? formatTime(block.last_hit) + (block.last_ip ? ' — ' + block.last_ip : '') : '—');
-            cell(row,
-                block.enabled ? 'enforced' : 'paused',
-                block.enabled ? 'state-bad' : 'state-idle');
+            var status = blockStatus(block);
+            cell(row, status.text, status.className);
 
             var actions = document.createElement('td');
             var wrapper = document.createElement('div');
@@ -248,7 +290,15 @@
                     .catch(function (error) { setStatus(error.message, 'error'); });
             }));
 
-            wrapper.appendChild(actionButton('Unblock', 'danger', function () {
+            if (block.expires_at && !block.expired) {
+                wrapper.appendChild(actionButton('Make permanent', null, function () {
+                    request('PATCH', '/admin/api/blocks/' + encodeURIComponent(block.id), { permanent: true })
+                        .then(function () { setStatus('"' + block.label + '" is now blocked until removed.', 'success'); load(); })
+                        .catch(function (error) { setStatus(error.message, 'error'); });
+                }));
+            }
+
+            wrapper.appendChild(actionButton(block.auto ? 'Lift' : 'Unblock', 'danger', function () {
                 if (!window.confirm('Unblock "' + block.label + '"?')) { return; }
                 request('DELETE', '/admin/api/blocks/' + encodeURIComponent(block.id))
                     .then(function () { setStatus('Unblocked "' + block.label + '".', 'success'); load(); })
@@ -282,17 +332,123 @@
         return td;
     }
 
+    var RATE_KIND_LABELS = {
+        ip: 'IP',
+        authcode: 'Authcode',
+        key: 'API key'
+    };
+
+    function renderRateLimits(usage) {
+        rateLimitsBodyEl.textContent = '';
+
+        if (!usage || !usage.length) {
+            emptyRow(rateLimitsBodyEl, 7, 'Nobody has called a metered endpoint today.');
+            return;
+        }
+
+        usage.forEach(function (entry) {
+            var row = document.createElement('tr');
+            cell(row, RATE_KIND_LABELS[entry.kind] || entry.kind);
+            /* Already the mask for an authcode; the code never reaches this page. */
+            codeCell(row, entry.label);
+            cell(row, entry.endpoint);
+            cell(row,
+                entry.remaining === null ? '—' : entry.remaining + ' / ' + entry.burst,
+                entry.remaining === 0 ? 'state-bad' : null);
+            var quotaSpent = entry.daily_quota !== null && entry.used_today >= entry.daily_quota;
+            cell(row,
+                entry.daily_quota === null ? String(entry.used_today) : entry.used_today + ' / ' + entry.daily_quota,
+                quotaSpent ? 'state-bad' : null);
+            cell(row,
+                entry.refused_today ? String(entry.refused_today) : '—',
+                entry.refused_today ? 'state-warn' : 'state-idle');
+            cell(row, formatTime(entry.last_seen));
+            rateLimitsBodyEl.appendChild(row);
+        });
+    }
+
+    function keyLimit(key) {
+        var parts = [];
+        if (key.per_minute) { parts.push(key.per_minute + '/min'); }
+        if (key.daily_quota) { parts.push(key.daily_quota + '/day'); }
+        return parts.join(', ');
+    }
+
+    function keyStatus(key) {
+        if (!key.enabled) { return { text: 'disabled', className: 'state-idle' }; }
+        if (key.expired) { return { text: 'expired', className: 'state-bad' }; }
+        if (key.expires_at) { return { text: 'active, expires in ' + formatUntil(key.expires_at), className: 'state-warn' }; }
+        return { text: 'active', className: 'state-ok' };
+    }
+
+    function renderKeys(keys) {
+        keysBodyEl.textContent = '';
+
+        if (!keys || !keys.length) {
+            emptyRow(keysBodyEl, 8, 'No keys issued. Issue one below to let a partner call the REST endpoints without their authcode.');
+            return;
+        }
+
+        keys.forEach(function (key) {
+            var row = document.createElement('tr');
+            cell(row, key.label);
+            /* The hint (rk-3f9a1c…) and the mask are all this page ever gets. */
+            codeCell(row, key.hint);
+            codeCell(row, key.authcode + (key.pid !== null ? ' / ' + key.pid : ''));
+            cell(row, key.scopes && key.scopes.length ? key.scopes.join(', ') : 'all',
+                key.scopes && key.scopes.length ? null : 'state-idle');
+            var limit = keyLimit(key);
+            cell(row, limit || '—', limit ? null : 'state-idle');
+            cell(row, key.last_used ? formatTime(key.last_used) : 'never', key.last_used ? null : 'state-idle');
+            var status = keyStatus(key);
+            cell(row, status.text, status.className);
+
+            var actions = document.createElement('td');
+            var wrapper = document.createElement('div');
+            wrapper.className = 'actions';
+
+            wrapper.appendChild(actionButton(key.enabled ? 'Disable' : 'Enable', null, function () {
+                request('PATCH', '/admin/api/keys/' + encodeURIComponent(key.id), { enabled: !key.enabled })
+                    .then(function () { load(); })
+                    .catch(function (error) { setStatus(error.message, 'error'); });
+            }));
+
+            if (key.expires_at) {
+                wrapper.appendChild(actionButton('Never expire', null, function () {
+                    request('PATCH', '/admin/api/keys/' + encodeURIComponent(key.id), { permanent: true })
+                        .then(function () { load(); })
+                        .catch(function (error) { setStatus(error.message, 'error'); });
+                }));
+            }
+
+            wrapper.appendChild(actionButton('Revoke', 'danger', function () {
+                if (!window.confirm('Revoke "' + key.label + '"? The partner\'s next call with it is refused.')) { return; }
+                request('DELETE', '/admin/api/keys/' + encodeURIComponent(key.id))
+                    .then(function () { setStatus('Revoked "' + key.label + '".', 'success'); load(); })
+                    .catch(function (error) { setStatus(error.message, 'error'); });
+            }));
+
+            actions.appendChild(wrapper);
+            row.appendChild(actions);
+            keysBodyEl.appendChild(row);
+        });
+    }
+
     function renderClients(clients) {
         clientsBodyEl.textContent = '';
 
         if (!clients || !clients.length) {
-            emptyRow(clientsBodyEl, 6, 'No connectors registered. Register one below, then paste its id and secret into the claude.ai connector.');
+            emptyRow(clientsBodyEl, 7, 'No connectors registered. Register one below, then paste its id and secret into the claude.ai connector.');
             return;
         }
 
         clients.forEach(function (client) {
             var row = document.createElement('tr');
-            cell(row, client.name);
+            /* Self-registered clients named themselves, so the address they came
+             * from is shown beside the name to judge them by. */
+            cell(row, client.registered_by
+                ? client.name + ' (self-registered from ' + client.registered_by + ')'
+                : client.name);
             codeCell(row, client.client_id);
 
             /* Redirect URIs are operator input and there may be several, so they
@@ -305,15 +461,30 @@
             });
             row.appendChild(uris);
 
+            cell(row, (client.scopes || []).join(' '));
             cell(row, formatTime(client.created_at));
-            cell(row,
-                client.enabled ? 'enabled' : 'disabled',
-                client.enabled ? 'state-ok' : 'state-idle');
+            if (client.needs_review) {
+                cell(row,
+                    client.expires_at ? 'needs review, dropped in ' + formatUntil(client.expires_at) + ' if unused' : 'needs review',
+                    'state-warn');
+            } else {
+                cell(row,
+                    client.enabled ? 'enabled' : 'disabled',
+                    client.enabled ? 'state-ok' : 'state-idle');
+            }
 
             var actions = document.createElement('td');
             var wrapper = document.createElement('div');
             wrapper.className = 'actions';
 
+            if (client.needs_review) {
+                wrapper.appendChild(actionButton('Approve', null, function () {
+                    request('PATCH', '/admin/api/oauth/clients/' + encodeURIComponent(client.client_id), { reviewed: true })
+                        .then(function () { setStatus('Approved "' + client.name + '".', 'success'); load(); })
+                        .catch(function (error) { setStatus(error.message, 'error'); });
+                }));
+            }
+
             wrapper.appendChild(actionButton(client.enabled ? 'Disable' : 'Enable', null, function () {
                 request('PATCH', '/admin/api/oauth/clients/' + encodeURIComponent(client.client_id), { enabled: !client.enabled })
                     .then(function () { load(); })
@@ -337,7 +508,7 @@
         sessionsBodyEl.textContent = '';
 
         if (!sessions || !sessions.length) {
-            emptyRow(sessionsBodyEl, 8, 'Nobody has signed in yet.');
+            emptyRow(sessionsBodyEl, 9, 'Nobody has signed in yet.');
             return;
         }
 
@@ -348,6 +519,7 @@
              * reaches this page. */
             codeCell(row, session.authcode);
             cell(row, String(session.pid));
+            cell(row, (session.scopes || []).join(' '));
             cell(row, formatTime(session.created_at));
             cell(row, session.last_used ? formatTime(session.last_used) : 'never', session.last_used ? null : 'state-idle');
             cell(row, formatTime(session.expires_at));
@@ -393,12 +565,27 @@
         }
     }
 
+    /* Rate limits are off unless [rate_limit] enabled = true; the server sends
+     * `rate_limits: null` then. */
+    function applyRateLimitVisibility(enabled) {
+        var panels = document.querySelectorAll('.ratelimit-only');
+        var index;
+        for (index = 0; index < panels.length; index += 1) {
+            panels[index].hidden = !enabled;
+        }
+    }
+
     function load() {
         return request('GET', '/admin/api/state')
             .then(function (payload) {
                 applyMcpVisibility(payload.mcp_enabled !== false);
                 applyOauthVisibility(!!payload.oauth);
+                applyRateLimitVisibility(!!payload.rate_limits);
                 renderBlocks(payload.blocks);
+                renderKeys(payload.api_keys);
+                if (payload.rate_limits) {
+                    renderRateLimits(payload.rate_limits.usage);
+                }
                 if (payload.oauth) {
                     renderClients(payload.oauth.clients);
                     renderSessions(payload.oauth.sessions);
@@ -413,6 +600,23 @@
             });
     }
 
+    function renderSynonyms(payload) {
+        synonymsFormEl.elements.text.value = payload.text;
+        synonymsSourceEl.textContent = (payload.source === 'file'
+            ? 'In force: ' + payload.path
+            : 'In force: the starter vocabulary (no ' + payload.path + ' yet)') +
+            ' — ' + payload.counts.synonyms + ' group(s), ' + payload.counts.one_way +
+            ' one-way, ' + payload.counts.units + ' unit(s).';
+    }
+
+    /* Loaded once rather than on every poll: the poll would overwrite an edit
+     * in progress. */
+    function loadSynonyms() {
+        return request('GET', '/admin/api/synonyms')
+            .then(renderSynonyms)
+            .catch(function (error) { setStatus(error.message, 'error'); });
+    }
+
     formEl.addEventListener('submit', function (event) {
         event.preventDefault();
         var data = new FormData(formEl);
@@ -452,7 +656,9 @@
             kind: data.get('kind'),
             value: (data.get('value') || '').trim(),
             scope: data.get('scope'),
-            note: (data.get('note') || '').trim() || null
+            action: data.get('action'),
+            note: (data.get('note') || '').trim() || null,
+            expires_in_secs: parseInt(data.get('expires'), 10) || null
         };
         if (!body.value) {
             setStatus('A value is required.', 'error');
@@ -464,7 +670,31 @@
                 blockFormEl.reset();
                 blockValueEl.type = 'text';
                 blockValueEl.placeholder = '203.0.113.7';
-                setStatus('Block added. It takes effect on the next request.', 'success');
+                setStatus((body.action === 'allow' ? 'Allow rule' : 'Block') + ' added. It takes effect on the next request.', 'success');
+                load();
+            })
+            .catch(function (error) { setStatus(error.message, 'error'); });
+    });
+
+    importFormEl.addEventListener('submit', function (event) {
+        event.preventDefault();
+        var file = importFormEl.elements.file.files[0];
+        if (!file) {
+            setStatus('Choose a CSV or TOML file to import.', 'error');
+            return;
+        }
+        var format = /\.csv$/i.test(file.name) ? 'csv' : 'toml';
+        var replace = importFormEl.elements.replace.checked;
+        if (replace && !window.confirm('Replace every rule on file with the ones in "' + file.name + '"?')) { return; }
+
+        file.text()
+            .then(function (text) {
+                return request('POST', '/admin/api/blocks/import?format=' + format + '&replace=' + replace, text);
+            })
+            .then(function (summary) {
+                importFormEl.reset();
+                setStatus('Imported: ' + summary.added + ' added, ' + summary.updated + ' updated, ' +
+                    summary.removed + ' removed.', 'success');
                 load();
             })
             .catch(function (error) { setStatus(error.message, 'error'); });
@@ -479,7 +709,8 @@
 
         request('POST', '/admin/api/oauth/clients', {
             name: (data.get('name') || '').trim(),
-            redirect_uris: uris
+            redirect_uris: uris,
+            scopes: data.getAll('scopes')
         })
             .then(function (payload) {
                 clientFormEl.reset();
@@ -493,18 +724,74 @@
             .catch(function (error) { setStatus(error.message, 'error'); });
     });
 
+    keyFormEl.addEventListener('submit', function (event) {
+        event.preventDefault();
+        var data = new FormData(keyFormEl);
+        var pid = parseInt(data.get('pid'), 10);
+        var body = {
+            label: (data.get('label') || '').trim(),
+            authcode: (data.get('authcode') || '').trim(),
+            pid: isNaN(pid) ? null : pid,
+            url: (data.get('url') || '').trim() || null,
+            scopes: (data.get('scopes') || '').split(',')
+                .map(function (scope) { return scope.trim(); })
+                .filter(function (scope) { return scope.length > 0; }),
+            per_minute: parseFloat(data.get('per_minute')) || null,
+            daily_quota: parseInt(data.get('daily_quota'), 10) || null,
+            expires_in_secs: parseInt(data.get('expires'), 10) || null
+        };
+
+        request('POST', '/admin/api/keys', body)
+            .then(function (payload) {
+                keyFormEl.reset();
+                /* Shown once and never again — the server keeps only the hash. */
+                keySecretValueEl.textContent = payload.key;
+                keySecretEl.hidden = false;
+                setStatus('Key issued. Copy it below now — it is not shown again.', 'success');
+                load();
+            })
+            .catch(function (error) { setStatus(error.message, 'error'); });
+    });
+
+    keySecretDismissEl.addEventListener('click', function () {
+        keySecretValueEl.textContent = '';
+        keySecretEl.hidden = true;
+    });
+
     secretDismissEl.addEventListener('click', function () {
         secretIdEl.textContent = '';
         secretValueEl.textContent = '';
         secretEl.hidden = true;
     });
 
+    synonymsFormEl.addEventListener('submit', function (event) {
+        event.preventDefault();
+        request('PUT', '/admin/api/synonyms', synonymsFormEl.elements.text.value)
+            .then(function (payload) {
+                renderSynonyms(payload);
+                setStatus('Synonyms saved; the next search uses them.', 'success');
+            })
+            .catch(function (error) { setStatus(error.message, 'error'); });
+    });
+
+    synonymsResetEl.addEventListener('click', function () {
+        if (!window.confirm('Delete the synonym file and go back to the starter vocabulary?')) { return; }
+        request('DELETE', '/admin/api/synonyms')
+            .then(function (payload) {
+                renderSynonyms(payload);
+                setStatus('Synonyms reset to the starter vocabulary.', 'success');
+            })
+            .catch(function (error) { setStatus(error.message, 'error'); });
+    });
+
     reloadEl.addEventListener('click', function () {
         setStatus('');
         load();
     });
 
-    load();
+    load().then(function () {
+        if (!synonymsFormEl.closest('section').hidden) { loadSynonyms(); }
+    });
     /* A refresh in progress finishes without the page being touched; poll so its
      * outcome and the new cache usage appear on their own. */
     window.setInterval(load, 15000);